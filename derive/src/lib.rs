@@ -0,0 +1,156 @@
+// Derives morpheus::graph::model::VertexModel and EdgeModel for structs with named fields,
+// see graph::model in morpheus for the attributes and how fields map to schema fields.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+extern crate syn;
+#[macro_use]
+extern crate quote;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use syn::{Attribute, Data, DeriveInput, Fields, Ident, Lit, Meta, NestedMeta, Type};
+
+struct Model {
+    ident: Ident,
+    name: String,
+    fields: Vec<(Ident, Type)>,
+    key: Option<String>,
+    edge_type: Option<Ident>
+}
+
+// the items inside every #[morpheus(...)]
+fn morpheus_items(attrs: &[Attribute]) -> Vec<Meta> {
+    attrs.iter()
+        .filter_map(|attr| attr.parse_meta().ok())
+        .filter_map(|meta| match meta {
+            Meta::List(list) => if list.ident == "morpheus" { Some(list.nested) } else { None },
+            _ => None
+        })
+        .flat_map(|nested| nested.into_iter())
+        .filter_map(|item| match item { NestedMeta::Meta(meta) => Some(meta), _ => None })
+        .collect()
+}
+
+fn parse(input: &DeriveInput) -> Result<Model, String> {
+    let named = match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref named) => named,
+            _ => return Err("models need named fields".to_string())
+        },
+        _ => return Err("models are structs".to_string())
+    };
+    let mut model = Model {
+        ident: input.ident.clone(),
+        name: input.ident.to_string(),
+        fields: Vec::new(),
+        key: None,
+        edge_type: None
+    };
+    for item in morpheus_items(&input.attrs) {
+        match item {
+            Meta::NameValue(ref pair) if pair.ident == "name" => match pair.lit {
+                Lit::Str(ref name) => model.name = name.value(),
+                _ => return Err("name takes a string".to_string())
+            },
+            Meta::Word(ref word) if word == "directed" => model.edge_type = Some(Ident::new("Directed", Span::call_site())),
+            Meta::Word(ref word) if word == "undirected" => model.edge_type = Some(Ident::new("Undirected", Span::call_site())),
+            _ => return Err("unknown morpheus attribute".to_string())
+        }
+    }
+    for field in named.named.iter() {
+        let ident = field.ident.clone().unwrap();
+        for item in morpheus_items(&field.attrs) {
+            match item {
+                Meta::Word(ref word) if word == "key" => {
+                    if model.key.is_some() { return Err("models have one key".to_string()); }
+                    model.key = Some(ident.to_string());
+                },
+                _ => return Err(format!("unknown morpheus attribute on {}", ident))
+            }
+        }
+        model.fields.push((ident, field.ty.clone()));
+    }
+    Ok(model)
+}
+
+// schema_name, fields and the conversions shared by vertex and edge models
+fn common(model: &Model) -> TokenStream2 {
+    let ident = &model.ident;
+    let name = &model.name;
+    let field_names: Vec<String> = model.fields.iter().map(|&(ref ident, _)| ident.to_string()).collect();
+    let field_idents: Vec<&Ident> = model.fields.iter().map(|&(ref ident, _)| ident).collect();
+    let field_types: Vec<&Type> = model.fields.iter().map(|&(_, ref ty)| ty).collect();
+    let (names_a, names_b, names_c) = (&field_names, &field_names, &field_names);
+    let (idents_a, idents_b) = (&field_idents, &field_idents);
+    quote! {
+        fn schema_name() -> &'static str { #name }
+        fn fields() -> Vec<_morpheus::graph::model::Field> {
+            vec![#(_morpheus::graph::model::model_field::<#field_types>(#names_a)),*]
+        }
+        fn to_map(&self) -> _morpheus::graph::model::Map {
+            let mut map = _morpheus::graph::model::Map::new();
+            #(_morpheus::graph::model::write_field(&mut map, #names_b, &self.#idents_a);)*
+            map
+        }
+        fn from_map(map: &_morpheus::graph::model::Map) -> Result<#ident, _morpheus::graph::model::ModelError> {
+            Ok(#ident {
+                #(#idents_b: _morpheus::graph::model::read_field(map, #names_c)?),*
+            })
+        }
+    }
+}
+
+// the impl sits in a constant so the extern crate inside does not leak into the user's module
+fn wrap(model: &Model, kind: &str, body: TokenStream2) -> TokenStream {
+    let dummy = Ident::new(&format!("_IMPL_{}_MODEL_FOR_{}", kind, model.ident), Span::call_site());
+    let expanded = quote! {
+        #[allow(non_upper_case_globals, unused_attributes, unused_qualifications)]
+        const #dummy: () = {
+            extern crate morpheus as _morpheus;
+            #body
+        };
+    };
+    expanded.into()
+}
+
+fn fail(message: String) -> TokenStream {
+    let message = format!("morpheus: {}", message);
+    let expanded = quote! { compile_error!(#message); };
+    expanded.into()
+}
+
+#[proc_macro_derive(MorpheusVertex, attributes(morpheus))]
+pub fn derive_vertex(input: TokenStream) -> TokenStream {
+    let input: DeriveInput = syn::parse(input).unwrap();
+    let model = match parse(&input) { Ok(model) => model, Err(e) => return fail(e) };
+    if model.edge_type.is_some() { return fail("vertex models have no edge type".to_string()); }
+    let ident = &model.ident;
+    let common = common(&model);
+    let key = match model.key { Some(ref key) => quote!(Some(#key)), None => quote!(None) };
+    wrap(&model, "VERTEX", quote! {
+        impl _morpheus::graph::model::VertexModel for #ident {
+            fn key_field() -> Option<&'static str> { #key }
+            #common
+        }
+    })
+}
+
+#[proc_macro_derive(MorpheusEdge, attributes(morpheus))]
+pub fn derive_edge(input: TokenStream) -> TokenStream {
+    let input: DeriveInput = syn::parse(input).unwrap();
+    let model = match parse(&input) { Ok(model) => model, Err(e) => return fail(e) };
+    if model.key.is_some() { return fail("edge models have no key".to_string()); }
+    let edge_type = match model.edge_type {
+        Some(ref edge_type) => edge_type.clone(),
+        None => return fail("edge models are #[morpheus(directed)] or #[morpheus(undirected)]".to_string())
+    };
+    let ident = &model.ident;
+    let common = common(&model);
+    wrap(&model, "EDGE", quote! {
+        impl _morpheus::graph::model::EdgeModel for #ident {
+            fn edge_type() -> _morpheus::graph::edge::EdgeType { _morpheus::graph::edge::EdgeType::#edge_type }
+            #common
+        }
+    })
+}
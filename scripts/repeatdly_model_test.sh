@@ -2,5 +2,5 @@
 
 while true
 do
-cargo test --color=always --package morpheus --bin morpheus relationship -- --nocapture
+cargo test --color=always --package morpheus --lib relationship -- --nocapture
 done
\ No newline at end of file
@@ -0,0 +1,174 @@
+// Betweenness (Brandes) and closeness over unweighted edges. Both run one BFS per source vertex;
+// with sampling only a random subset of sources is searched and the totals are scaled up to
+// estimate the exact values, which keeps the cost linear in the sample size on large graphs.
+
+use futures::prelude::*;
+use rand;
+use neb::ram::types::Id;
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use analytics::{self, AnalyticsError};
+use graph::Graph;
+use graph::mem::MemGraph;
+use server::schema::ToSchemaId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Centrality {
+    Betweenness,
+    Closeness
+}
+
+#[derive(Debug, Clone)]
+pub struct CentralityOptions {
+    // follow directed edges only from their from end
+    pub directed: bool,
+    // number of BFS sources to sample, None searches from every vertex for exact results
+    pub samples: Option<usize>,
+    // betweenness over the number of vertex pairs, closeness is always in 0..1
+    pub normalized: bool
+}
+
+impl Default for CentralityOptions {
+    fn default() -> CentralityOptions {
+        CentralityOptions {
+            directed: true,
+            samples: None,
+            normalized: true
+        }
+    }
+}
+
+fn sources(n: usize, samples: Option<usize>) -> Vec<usize> {
+    match samples {
+        Some(k) if k < n => rand::sample(&mut rand::thread_rng(), 0..n, k),
+        _ => (0..n).collect()
+    }
+}
+
+// hop counts from source, None for vertices it cannot reach
+fn bfs(adjacency: &[Vec<usize>], source: usize) -> Vec<Option<usize>> {
+    let mut dist = vec![None; adjacency.len()];
+    let mut queue = VecDeque::new();
+    dist[source] = Some(0);
+    queue.push_back(source);
+    while let Some(v) = queue.pop_front() {
+        let next = dist[v].unwrap() + 1;
+        for &w in &adjacency[v] {
+            if dist[w].is_none() {
+                dist[w] = Some(next);
+                queue.push_back(w);
+            }
+        }
+    }
+    dist
+}
+
+pub fn betweenness(mem: &MemGraph, options: &CentralityOptions) -> Vec<f64> {
+    let adjacency = analytics::adjacency(mem, options.directed);
+    let n = adjacency.len();
+    let mut centrality = vec![0f64; n];
+    let sources = sources(n, options.samples);
+    for &s in &sources {
+        let mut stack = Vec::with_capacity(n);
+        let mut predecessors: Vec<Vec<usize>> = vec![Vec::new(); n];
+        let mut sigma = vec![0f64; n];
+        let mut dist: Vec<i64> = vec![-1; n];
+        sigma[s] = 1.0;
+        dist[s] = 0;
+        let mut queue = VecDeque::new();
+        queue.push_back(s);
+        while let Some(v) = queue.pop_front() {
+            stack.push(v);
+            for &w in &adjacency[v] {
+                if dist[w] < 0 {
+                    dist[w] = dist[v] + 1;
+                    queue.push_back(w);
+                }
+                if dist[w] == dist[v] + 1 {
+                    sigma[w] += sigma[v];
+                    predecessors[w].push(v);
+                }
+            }
+        }
+        let mut delta = vec![0f64; n];
+        while let Some(w) = stack.pop() {
+            for &v in &predecessors[w] {
+                delta[v] += sigma[v] / sigma[w] * (1.0 + delta[w]);
+            }
+            if w != s {
+                centrality[w] += delta[w];
+            }
+        }
+    }
+    let mut scale = if sources.is_empty() { 0.0 } else { n as f64 / sources.len() as f64 };
+    // every undirected path is counted once from each end
+    if !options.directed { scale /= 2.0; }
+    if options.normalized && n > 2 {
+        let pairs = ((n - 1) * (n - 2)) as f64;
+        scale /= if options.directed { pairs } else { pairs / 2.0 };
+    }
+    for value in &mut centrality {
+        *value *= scale;
+    }
+    centrality
+}
+
+// Reachable vertices over the sum of distances to them, scaled by the share of the graph reached
+// (Wasserman and Faust) so vertices in small components do not score high. Sampled closeness
+// searches backwards from the sampled targets to estimate every vertex's distances.
+pub fn closeness(mem: &MemGraph, options: &CentralityOptions) -> Vec<f64> {
+    let adjacency = analytics::adjacency(mem, options.directed);
+    let n = adjacency.len();
+    if n < 2 { return vec![0f64; n]; }
+    let mut total = vec![0f64; n];
+    let mut reached = vec![0f64; n];
+    let sampled = match options.samples { Some(k) => k < n, None => false };
+    if sampled {
+        let reverse = analytics::transpose(&adjacency);
+        let targets = sources(n, options.samples);
+        let scale = (n - 1) as f64 / targets.len() as f64;
+        for &t in &targets {
+            for (v, d) in bfs(&reverse, t).into_iter().enumerate() {
+                if let Some(d) = d {
+                    if v != t {
+                        total[v] += d as f64 * scale;
+                        reached[v] += scale;
+                    }
+                }
+            }
+        }
+    } else {
+        for v in 0..n {
+            for (w, d) in bfs(&adjacency, v).into_iter().enumerate() {
+                if let Some(d) = d {
+                    if v != w {
+                        total[v] += d as f64;
+                        reached[v] += 1.0;
+                    }
+                }
+            }
+        }
+    }
+    (0..n).map(|v| if total[v] > 0.0 {
+        (reached[v] / total[v]) * (reached[v].min((n - 1) as f64) / (n - 1) as f64)
+    } else { 0.0 }).collect()
+}
+
+// Loads the schemas and streams (vertex id, score) pairs, pass the stream to analytics::write_scores to persist it
+pub fn run<S>(graph: &Arc<Graph>, vertex_schemas: Vec<S>, edge_schemas: Vec<S>, centrality: Centrality, options: CentralityOptions)
+    -> impl Stream<Item = (Id, f64), Error = AnalyticsError>
+    where S: ToSchemaId
+{
+    MemGraph::from_scans(graph, vertex_schemas, edge_schemas)
+        .map_err(AnalyticsError::MemGraphError)
+        .map(move |mem| {
+            let scores = match centrality {
+                Centrality::Betweenness => betweenness(&mem, &options),
+                Centrality::Closeness => closeness(&mem, &options)
+            };
+            analytics::scores_stream(&mem, scores)
+        })
+        .flatten_stream()
+}
@@ -0,0 +1,181 @@
+// Community detection, edge directions are ignored. Label propagation is cheap and good enough for
+// most social graphs, Louvain maximises modularity and is steadier between runs. A community is
+// named by the id of its member that was loaded first, so ids can be written to vertices and joined on.
+
+use futures::prelude::*;
+use rand::{self, Rng};
+use neb::ram::types::Id;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use analytics::{self, AnalyticsError};
+use graph::Graph;
+use graph::mem::MemGraph;
+use server::schema::ToSchemaId;
+
+// gains below this are rounding noise, moves need to beat it
+static MIN_GAIN: f64 = 1e-12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommunityAlgorithm {
+    LabelPropagation,
+    Louvain
+}
+
+#[derive(Debug, Clone)]
+pub struct CommunityOptions {
+    pub algorithm: CommunityAlgorithm,
+    // label propagation rounds, or Louvain passes over every vertex per level
+    pub max_iterations: usize,
+    // Louvain only, how many times communities are merged into vertices and optimised again
+    pub max_levels: usize
+}
+
+impl Default for CommunityOptions {
+    fn default() -> CommunityOptions {
+        CommunityOptions {
+            algorithm: CommunityAlgorithm::LabelPropagation,
+            max_iterations: 20,
+            max_levels: 10
+        }
+    }
+}
+
+// Every vertex takes the label most of its neighbours have, in random order each round, until no
+// label changes. Ties keep the current label if it is among them, else the smallest one wins.
+pub fn label_propagation(mem: &MemGraph, max_iterations: usize) -> Vec<usize> {
+    let adjacency = analytics::adjacency(mem, false);
+    let n = adjacency.len();
+    let mut labels: Vec<usize> = (0..n).collect();
+    let mut order: Vec<usize> = (0..n).collect();
+    let mut rng = rand::thread_rng();
+    for _ in 0..max_iterations {
+        rng.shuffle(&mut order);
+        let mut changed = false;
+        for &v in &order {
+            if adjacency[v].is_empty() { continue; }
+            let mut counts: HashMap<usize, usize> = HashMap::new();
+            for &w in &adjacency[v] {
+                *counts.entry(labels[w]).or_insert(0) += 1;
+            }
+            let max = counts.values().cloned().max().unwrap_or(0);
+            if counts.get(&labels[v]).cloned() == Some(max) { continue; }
+            let best = counts.iter().filter(|&(_, c)| *c == max).map(|(l, _)| *l).min().unwrap();
+            labels[v] = best;
+            changed = true;
+        }
+        if !changed { break; }
+    }
+    labels
+}
+
+fn modularity_gain(links_in: f64, community_total: f64, degree: f64, two_m: f64) -> f64 {
+    links_in - community_total * degree / two_m
+}
+
+// one Louvain level, node -> community numbered from 0 in order of first appearance
+fn louvain_level(graph: &[Vec<(usize, f64)>], max_iterations: usize) -> Vec<usize> {
+    let n = graph.len();
+    let degree: Vec<f64> = graph.iter().map(|ns| ns.iter().map(|&(_, w)| w).sum()).collect();
+    let two_m: f64 = degree.iter().sum();
+    let mut community: Vec<usize> = (0..n).collect();
+    if two_m == 0.0 { return community; }
+    let mut total = degree.clone();
+    for _ in 0..max_iterations {
+        let mut moved = false;
+        for i in 0..n {
+            let current = community[i];
+            let mut links: HashMap<usize, f64> = HashMap::new();
+            for &(j, w) in &graph[i] {
+                if j != i { *links.entry(community[j]).or_insert(0.0) += w; }
+            }
+            total[current] -= degree[i];
+            let mut best = current;
+            let mut best_gain = modularity_gain(
+                links.get(&current).cloned().unwrap_or(0.0), total[current], degree[i], two_m);
+            for (&c, &links_in) in &links {
+                let gain = modularity_gain(links_in, total[c], degree[i], two_m);
+                let tie = (gain - best_gain).abs() <= MIN_GAIN && best != current && c < best;
+                if gain > best_gain + MIN_GAIN || tie {
+                    best = c;
+                    best_gain = gain;
+                }
+            }
+            total[best] += degree[i];
+            if best != current {
+                community[i] = best;
+                moved = true;
+            }
+        }
+        if !moved { break; }
+    }
+    let mut renumbered: HashMap<usize, usize> = HashMap::new();
+    community.iter().map(|c| {
+        let next = renumbered.len();
+        *renumbered.entry(*c).or_insert(next)
+    }).collect()
+}
+
+// communities become vertices, edge weights between them are summed, inner edges become self-loops
+fn aggregate(graph: &[Vec<(usize, f64)>], community: &[usize], count: usize) -> Vec<Vec<(usize, f64)>> {
+    let mut weights: Vec<HashMap<usize, f64>> = vec![HashMap::new(); count];
+    for (i, neighbours) in graph.iter().enumerate() {
+        for &(j, w) in neighbours {
+            *weights[community[i]].entry(community[j]).or_insert(0.0) += w;
+        }
+    }
+    weights.into_iter().map(|links| {
+        let mut links: Vec<(usize, f64)> = links.into_iter().collect();
+        links.sort_by_key(|&(c, _)| c);
+        links
+    }).collect()
+}
+
+pub fn louvain(mem: &MemGraph, max_iterations: usize, max_levels: usize) -> Vec<usize> {
+    let mut graph: Vec<Vec<(usize, f64)>> = analytics::adjacency(mem, false).into_iter()
+        .map(|neighbours| neighbours.into_iter().map(|w| (w, 1.0)).collect())
+        .collect();
+    let mut membership: Vec<usize> = (0..graph.len()).collect();
+    for _ in 0..max_levels {
+        let community = louvain_level(&graph, max_iterations);
+        let count = community.iter().cloned().max().map(|c| c + 1).unwrap_or(0);
+        if count == graph.len() { break; }
+        for node in &mut membership {
+            *node = community[*node];
+        }
+        graph = aggregate(&graph, &community, count);
+    }
+    membership
+}
+
+// names every label by the first loaded vertex carrying it
+pub fn community_ids(mem: &MemGraph, labels: &[usize]) -> Vec<Id> {
+    let mut representative: HashMap<usize, Id> = HashMap::new();
+    for (vertex, label) in mem.vertices().iter().zip(labels.iter()) {
+        representative.entry(*label).or_insert(vertex.cell.id());
+    }
+    labels.iter().map(|label| representative[label]).collect()
+}
+
+pub fn detect(mem: &MemGraph, options: &CommunityOptions) -> Vec<Id> {
+    let labels = match options.algorithm {
+        CommunityAlgorithm::LabelPropagation => label_propagation(mem, options.max_iterations),
+        CommunityAlgorithm::Louvain => louvain(mem, options.max_iterations, options.max_levels)
+    };
+    community_ids(mem, &labels)
+}
+
+// Loads the schemas and streams (vertex id, community id) pairs, see analytics::write_scores
+pub fn run<S>(graph: &Arc<Graph>, vertex_schemas: Vec<S>, edge_schemas: Vec<S>, options: CommunityOptions)
+    -> impl Stream<Item = (Id, Id), Error = AnalyticsError>
+    where S: ToSchemaId
+{
+    MemGraph::from_scans(graph, vertex_schemas, edge_schemas)
+        .map_err(AnalyticsError::MemGraphError)
+        .map(move |mem| {
+            let communities = detect(&mem, &options);
+            analytics::scores_stream(&mem, communities)
+        })
+        .flatten_stream()
+}
@@ -0,0 +1,84 @@
+// Whole-graph algorithms. Jobs load the vertices and edges of the chosen schemas into a MemGraph,
+// compute in memory and either stream (vertex id, score) pairs back or write the scores to a vertex field.
+
+use neb::ram::types::Id;
+use neb::dovahkiin::types::ToValue;
+use neb::client::transaction::TxnError;
+use futures::prelude::*;
+use futures::stream;
+
+use std::sync::Arc;
+
+use graph::Graph;
+use graph::edge::Edge;
+use graph::mem::{MemGraph, MemGraphError};
+
+pub mod centrality;
+pub mod community;
+pub mod pregel;
+pub mod programs;
+
+// vertices updated per transaction when writing scores back
+pub static WRITE_BATCH: usize = 256;
+
+#[derive(Debug)]
+pub enum AnalyticsError {
+    MemGraphError(MemGraphError),
+    TxnError(TxnError),
+    // a server computing part of a distributed job failed, see server::pregel
+    DistributedError(String)
+}
+
+// Neighbour lists by local index. Undirected edges are followed both ways, directed edges
+// only from their from end unless directed is false.
+pub fn adjacency(mem: &MemGraph, directed: bool) -> Vec<Vec<usize>> {
+    let mut adjacency = vec![Vec::new(); mem.vertices().len()];
+    for edge in mem.edges() {
+        adjacency[edge.from].push(edge.to);
+        let one_way = directed && match edge.edge { Edge::Directed(_) => true, Edge::Undirected(_) => false };
+        if !one_way && edge.from != edge.to {
+            adjacency[edge.to].push(edge.from);
+        }
+    }
+    adjacency
+}
+
+// reverses the lists built by adjacency, in-neighbours for every vertex
+pub fn transpose(adjacency: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let mut transposed = vec![Vec::new(); adjacency.len()];
+    for (from, neighbours) in adjacency.iter().enumerate() {
+        for to in neighbours {
+            transposed[*to].push(from);
+        }
+    }
+    transposed
+}
+
+// pairs scores with the vertex ids they belong to, scores are in local index order
+pub fn scores_stream<T>(mem: &MemGraph, scores: Vec<T>) -> impl Stream<Item = (Id, T), Error = AnalyticsError> {
+    let ids: Vec<Id> = mem.vertices().iter().map(|v| v.cell.id()).collect();
+    stream::iter_ok(ids.into_iter().zip(scores.into_iter()))
+}
+
+// Sets field to the score on every vertex, WRITE_BATCH vertices per transaction. Returns how many were written.
+pub fn write_scores<S, T>(graph: &Arc<Graph>, scores: S, field: &str) -> impl Future<Item = usize, Error = AnalyticsError>
+    where S: Stream<Item = (Id, T), Error = AnalyticsError>, T: ToValue + 'static
+{
+    let graph = graph.clone();
+    let field = field.to_string();
+    scores.chunks(WRITE_BATCH)
+        .and_then(move |batch| {
+            let field = field.clone();
+            graph.graph_transaction(move |txn| {
+                for &(id, ref score) in &batch {
+                    let field = field.as_str();
+                    txn.update_vertex(id, |mut vertex| {
+                        vertex[field] = score.value();
+                        Some(vertex)
+                    })?;
+                }
+                Ok(batch.len())
+            }).map_err(AnalyticsError::TxnError)
+        })
+        .fold(0, |written, count| Ok::<_, AnalyticsError>(written + count))
+}
@@ -0,0 +1,307 @@
+// Vertex-centric iterative computation. Every superstep runs the program's compute on each active
+// vertex with the messages sent to it in the previous superstep; messages are routed to the partition
+// holding their target and delivered before the next superstep starts. A vertex that votes to halt
+// sleeps until a message arrives, the job converges once every vertex sleeps and nothing is in flight.
+// run_program computes a MemGraph in this process, partitioned by the higher half of vertex ids
+// and run in parallel on a thread pool. Programs implementing DistributedProgram can also run spread
+// over the servers owning their vertices, each server computing a Shard, see server::pregel.
+
+use neb::ram::types::Id;
+use futures::prelude::*;
+use futures::future;
+use futures_cpupool::CpuPool;
+
+use std::collections::HashMap;
+use std::mem;
+use std::sync::Arc;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use analytics::{self, AnalyticsError};
+use graph::Graph;
+use graph::mem::MemGraph;
+use graph::vertex::Vertex;
+use server::schema::ToSchemaId;
+
+pub trait VertexProgram: Send + Sync + 'static {
+    type State: Send + 'static;
+    type Message: Clone + Send + 'static;
+
+    fn init(&self, vertex: &Vertex) -> Self::State;
+    fn compute(&self, ctx: &mut Context<Self::Message>, state: &mut Self::State, messages: Vec<Self::Message>);
+    // merges two messages for the same vertex into one, None keeps both
+    fn combine(&self, _a: &Self::Message, _b: &Self::Message) -> Option<Self::Message> { None }
+}
+
+// Programs every server knows by name. The coordinator sends the program as json and each server
+// rebuilds it to compute its shard, states and messages cross servers as json too.
+pub trait DistributedProgram: VertexProgram + Serialize + DeserializeOwned
+    where Self::State: Serialize + DeserializeOwned, Self::Message: Serialize + DeserializeOwned
+{
+    const NAME: &'static str;
+}
+
+#[derive(Debug, Clone)]
+pub struct PregelOptions {
+    pub max_supersteps: usize,
+    // follow directed edges only from their from end
+    pub directed: bool,
+    // partitions of a job run by run_program, distributed jobs have one per server
+    pub partitions: usize,
+    pub threads: usize
+}
+
+impl Default for PregelOptions {
+    fn default() -> PregelOptions {
+        PregelOptions {
+            max_supersteps: 30,
+            directed: true,
+            partitions: 8,
+            threads: 4
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PregelResult<S> {
+    // in MemGraph local index order
+    pub states: Vec<S>,
+    pub supersteps: usize,
+    // false when max_supersteps ran out first
+    pub converged: bool
+}
+
+// the vertices computed here with their neighbours, num_vertices counts the whole job
+struct Topology {
+    adjacency: Vec<Vec<Id>>,
+    ids: Vec<Id>,
+    num_vertices: usize
+}
+
+pub struct Context<'a, M: 'a> {
+    superstep: usize,
+    vertex: usize,
+    topology: &'a Topology,
+    outbox: &'a mut Vec<(Id, M)>,
+    halt: bool
+}
+
+impl <'a, M> Context<'a, M> where M: Clone {
+    pub fn superstep(&self) -> usize {
+        self.superstep
+    }
+    pub fn vertex_id(&self) -> Id {
+        self.topology.ids[self.vertex]
+    }
+    pub fn num_vertices(&self) -> usize {
+        self.topology.num_vertices
+    }
+    pub fn out_degree(&self) -> usize {
+        self.topology.adjacency[self.vertex].len()
+    }
+    pub fn neighbours(&self) -> Vec<Id> {
+        self.topology.adjacency[self.vertex].clone()
+    }
+    // messages to vertices outside the job are dropped on delivery
+    pub fn send(&mut self, to: &Id, message: M) {
+        self.outbox.push((*to, message));
+    }
+    pub fn send_to_neighbours(&mut self, message: M) {
+        for to in &self.topology.adjacency[self.vertex] {
+            self.outbox.push((*to, message.clone()));
+        }
+    }
+    pub fn vote_to_halt(&mut self) {
+        self.halt = true;
+    }
+}
+
+struct Partition<S, M> {
+    vertices: Vec<usize>,
+    states: Vec<S>,
+    halted: Vec<bool>,
+    inbox: Vec<Vec<M>>
+}
+
+impl <S, M> Partition<S, M> where M: Clone {
+    fn has_work(&self) -> bool {
+        self.is_active() || self.inbox.iter().any(|m| !m.is_empty())
+    }
+
+    fn is_active(&self) -> bool {
+        self.halted.iter().any(|h| !h)
+    }
+
+    fn compute<P>(&mut self, superstep: usize, program: &P, topology: &Topology) -> Vec<(Id, M)>
+        where P: VertexProgram<State = S, Message = M>
+    {
+        let mut outbox = Vec::new();
+        for pos in 0..self.vertices.len() {
+            let messages = mem::replace(&mut self.inbox[pos], Vec::new());
+            if self.halted[pos] && messages.is_empty() { continue; }
+            let halt = {
+                let mut ctx = Context {
+                    superstep, vertex: self.vertices[pos], topology, outbox: &mut outbox, halt: false
+                };
+                program.compute(&mut ctx, &mut self.states[pos], messages);
+                ctx.halt
+            };
+            self.halted[pos] = halt;
+        }
+        outbox
+    }
+}
+
+fn deliver<P>(program: &P, inbox: &mut Vec<P::Message>, message: P::Message) where P: VertexProgram {
+    let combined = match inbox.last() {
+        Some(last) => program.combine(last, &message),
+        None => None
+    };
+    match combined {
+        Some(combined) => *inbox.last_mut().unwrap() = combined,
+        None => inbox.push(message)
+    }
+}
+
+// neighbour lists by vertex id, in MemGraph local index order
+fn id_adjacency(mem: &MemGraph, directed: bool) -> Vec<Vec<Id>> {
+    analytics::adjacency(mem, directed).into_iter()
+        .map(|neighbours| neighbours.into_iter().map(|v| mem.vertices()[v].cell.id()).collect())
+        .collect()
+}
+
+pub fn run_program<P>(mem: &MemGraph, program: P, options: &PregelOptions) -> PregelResult<P::State>
+    where P: VertexProgram
+{
+    let program = Arc::new(program);
+    let ids: Vec<Id> = mem.vertices().iter().map(|v| v.cell.id()).collect();
+    let topology = Arc::new(Topology {
+        adjacency: id_adjacency(mem, options.directed),
+        num_vertices: ids.len(),
+        ids
+    });
+    let partition_count = options.partitions.max(1);
+    let mut partitions: Vec<Partition<P::State, P::Message>> = (0..partition_count)
+        .map(|_| Partition { vertices: Vec::new(), states: Vec::new(), halted: Vec::new(), inbox: Vec::new() })
+        .collect();
+    // vertex id -> (partition, position inside it)
+    let mut placement = HashMap::with_capacity(topology.ids.len());
+    for (v, vertex) in mem.vertices().iter().enumerate() {
+        let id = topology.ids[v];
+        let p = (id.higher % partition_count as u64) as usize;
+        let partition = &mut partitions[p];
+        placement.insert(id, (p, partition.vertices.len()));
+        partition.vertices.push(v);
+        partition.states.push(program.init(vertex));
+        partition.halted.push(false);
+        partition.inbox.push(Vec::new());
+    }
+    let pool = CpuPool::new(options.threads.max(1));
+    let mut supersteps = 0;
+    let mut converged = false;
+    while supersteps < options.max_supersteps {
+        if !partitions.iter().any(|p| p.has_work()) {
+            converged = true;
+            break;
+        }
+        let superstep = supersteps;
+        let jobs: Vec<_> = partitions.drain(..).map(|mut partition| {
+            let program = program.clone();
+            let topology = topology.clone();
+            pool.spawn_fn(move || {
+                let outbox = partition.compute(superstep, &*program, &topology);
+                Ok::<_, ()>((partition, outbox))
+            })
+        }).collect();
+        let mut outboxes = Vec::with_capacity(partition_count);
+        for (partition, outbox) in future::join_all(jobs).wait().unwrap() {
+            partitions.push(partition);
+            outboxes.push(outbox);
+        }
+        for outbox in outboxes {
+            for (to, message) in outbox {
+                if let Some(&(p, pos)) = placement.get(&to) {
+                    deliver(&*program, &mut partitions[p].inbox[pos], message);
+                }
+            }
+        }
+        supersteps += 1;
+    }
+    if !converged {
+        converged = !partitions.iter().any(|p| p.has_work());
+    }
+    let mut states: Vec<Option<P::State>> = (0..topology.ids.len()).map(|_| None).collect();
+    for partition in partitions {
+        for (v, state) in partition.vertices.into_iter().zip(partition.states.into_iter()) {
+            states[v] = Some(state);
+        }
+    }
+    PregelResult {
+        states: states.into_iter().map(|s| s.unwrap()).collect(),
+        supersteps,
+        converged
+    }
+}
+
+// The vertices of a distributed job one server owns, with their states and inboxes.
+// Messages for vertices the shard does not hold are dropped.
+pub struct Shard<P: VertexProgram> {
+    program: P,
+    topology: Topology,
+    partition: Partition<P::State, P::Message>,
+    positions: HashMap<Id, usize>
+}
+
+impl <P> Shard<P> where P: VertexProgram {
+    // vertices with their neighbour ids, num_vertices counts the vertices of every shard
+    pub fn new(program: P, vertices: Vec<(Vertex, Vec<Id>)>, num_vertices: usize) -> Shard<P> {
+        let mut topology = Topology { adjacency: Vec::new(), ids: Vec::new(), num_vertices };
+        let mut partition = Partition { vertices: Vec::new(), states: Vec::new(), halted: Vec::new(), inbox: Vec::new() };
+        let mut positions = HashMap::with_capacity(vertices.len());
+        for (pos, (vertex, neighbours)) in vertices.into_iter().enumerate() {
+            let id = vertex.cell.id();
+            positions.insert(id, pos);
+            topology.ids.push(id);
+            topology.adjacency.push(neighbours);
+            partition.vertices.push(pos);
+            partition.states.push(program.init(&vertex));
+            partition.halted.push(false);
+            partition.inbox.push(Vec::new());
+        }
+        Shard { program, topology, partition, positions }
+    }
+
+    pub fn len(&self) -> usize {
+        self.topology.ids.len()
+    }
+
+    pub fn deliver(&mut self, to: &Id, message: P::Message) {
+        if let Some(&pos) = self.positions.get(to) {
+            deliver(&self.program, &mut self.partition.inbox[pos], message);
+        }
+    }
+
+    // the messages sent, and whether a vertex of the shard is still awake
+    pub fn superstep(&mut self, superstep: usize) -> (Vec<(Id, P::Message)>, bool) {
+        let outbox = self.partition.compute(superstep, &self.program, &self.topology);
+        (outbox, self.partition.is_active())
+    }
+
+    pub fn states(&self) -> Vec<(Id, &P::State)> {
+        self.topology.ids.iter().cloned().zip(self.partition.states.iter()).collect()
+    }
+}
+
+// Loads the schemas into this server, runs the program and streams (vertex id, final state) pairs
+pub fn run<S, P>(graph: &Arc<Graph>, vertex_schemas: Vec<S>, edge_schemas: Vec<S>, program: P, options: PregelOptions)
+    -> impl Stream<Item = (Id, P::State), Error = AnalyticsError>
+    where S: ToSchemaId, P: VertexProgram
+{
+    MemGraph::from_scans(graph, vertex_schemas, edge_schemas)
+        .map_err(AnalyticsError::MemGraphError)
+        .map(move |mem| {
+            let result = run_program(&mem, program, &options);
+            analytics::scores_stream(&mem, result.states)
+        })
+        .flatten_stream()
+}
@@ -0,0 +1,127 @@
+// Common algorithms written as Pregel vertex programs, see analytics::pregel.
+
+use neb::ram::types::Id;
+
+use std::cmp;
+
+use analytics::pregel::{VertexProgram, DistributedProgram, Context};
+use graph::vertex::Vertex;
+
+// ids have no order of their own, compare them as (higher, lower)
+fn smaller(a: Id, b: Id) -> Id {
+    if (a.higher, a.lower) <= (b.higher, b.lower) { a } else { b }
+}
+
+// Ranks for a fixed number of iterations. Rank of vertices without out edges is not passed on,
+// so ranks sum to less than one on graphs with dead ends.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PageRank {
+    pub damping: f64,
+    pub iterations: usize
+}
+
+impl Default for PageRank {
+    fn default() -> PageRank {
+        PageRank { damping: 0.85, iterations: 20 }
+    }
+}
+
+impl VertexProgram for PageRank {
+    type State = f64;
+    type Message = f64;
+
+    fn init(&self, _vertex: &Vertex) -> f64 { 0.0 }
+
+    fn compute(&self, ctx: &mut Context<f64>, rank: &mut f64, messages: Vec<f64>) {
+        let n = ctx.num_vertices() as f64;
+        *rank = if ctx.superstep() == 0 {
+            1.0 / n
+        } else {
+            (1.0 - self.damping) / n + self.damping * messages.iter().sum::<f64>()
+        };
+        if ctx.superstep() >= self.iterations {
+            ctx.vote_to_halt();
+        } else if ctx.out_degree() > 0 {
+            let share = *rank / ctx.out_degree() as f64;
+            ctx.send_to_neighbours(share);
+        }
+    }
+
+    fn combine(&self, a: &f64, b: &f64) -> Option<f64> {
+        Some(a + b)
+    }
+}
+
+impl DistributedProgram for PageRank {
+    const NAME: &'static str = "page_rank";
+}
+
+// Every vertex ends up with the smallest id in its component, run undirected for weakly connected components
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConnectedComponents;
+
+impl VertexProgram for ConnectedComponents {
+    type State = Id;
+    type Message = Id;
+
+    fn init(&self, vertex: &Vertex) -> Id { vertex.cell.id() }
+
+    fn compute(&self, ctx: &mut Context<Id>, component: &mut Id, messages: Vec<Id>) {
+        let smallest = messages.into_iter().fold(None, |min: Option<Id>, id| {
+            Some(min.map(|min| smaller(min, id)).unwrap_or(id))
+        });
+        match smallest {
+            Some(id) if smaller(id, *component) != *component => {
+                *component = id;
+                ctx.send_to_neighbours(id);
+            },
+            _ if ctx.superstep() == 0 => ctx.send_to_neighbours(*component),
+            _ => {}
+        }
+        ctx.vote_to_halt();
+    }
+
+    fn combine(&self, a: &Id, b: &Id) -> Option<Id> {
+        Some(smaller(*a, *b))
+    }
+}
+
+impl DistributedProgram for ConnectedComponents {
+    const NAME: &'static str = "connected_components";
+}
+
+// Hop count from the source along edge directions, None for vertices it cannot reach
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ShortestPaths {
+    pub source: Id
+}
+
+impl VertexProgram for ShortestPaths {
+    type State = Option<u64>;
+    type Message = u64;
+
+    fn init(&self, _vertex: &Vertex) -> Option<u64> { None }
+
+    fn compute(&self, ctx: &mut Context<u64>, distance: &mut Option<u64>, messages: Vec<u64>) {
+        let candidate = if ctx.superstep() == 0 && ctx.vertex_id() == self.source {
+            Some(0)
+        } else {
+            messages.into_iter().min()
+        };
+        if let Some(candidate) = candidate {
+            if distance.map(|d| candidate < d).unwrap_or(true) {
+                *distance = Some(candidate);
+                ctx.send_to_neighbours(candidate + 1);
+            }
+        }
+        ctx.vote_to_halt();
+    }
+
+    fn combine(&self, a: &u64, b: &u64) -> Option<u64> {
+        Some(cmp::min(*a, *b))
+    }
+}
+
+impl DistributedProgram for ShortestPaths {
+    const NAME: &'static str = "shortest_paths";
+}
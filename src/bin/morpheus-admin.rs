@@ -0,0 +1,232 @@
+extern crate morpheus;
+extern crate bifrost;
+extern crate futures_await as futures;
+
+use bifrost::rpc::RPCClient;
+use futures::Future;
+use morpheus::graph::edge::{EdgeAttributes, EdgeType};
+use morpheus::server::admin::{AsyncServiceClient, ADMIN_SERVICE_ID};
+use morpheus::server::schema::SchemaType;
+use morpheus::server::auth::{Role, Grant, Permission, Scope};
+use morpheus::server::audit::AuditQuery;
+
+use std::env;
+use std::process;
+
+static USAGE: &'static str = "\
+usage: morpheus-admin <server address> <command>
+
+commands:
+    health                          check the server is up and list what it holds
+    schemas                         list vertex and edge schemas
+    new-schema <kind> <name>        create a dynamic schema, kind is one of
+                                    vertex, directed, undirected, directed-body, undirected-body
+    stats                           show the last collected statistics
+    collect-stats                   collect statistics now
+    degree-report [hubs]            show degree distributions, hub vertices and body sizes of edge schemas
+    snapshot <path>                 write the graph as N-Triples to the path, on the server
+    rebuild-index <schema> <field>  build the vector index of the field again, on the server
+    new-role <name> <permission> [namespace]
+                                    define a role, permission is one of read, write, schema-admin
+    new-user <name> <role,...>      create a user and print its token
+    remove-vertices <schema> [filter]
+                                    remove the vertices of the schema passing the filter, all without one
+    audit [user]                    list the latest administration done, by anyone or by the user
+    group-health                    show the raft group as the server sees it
+    join <member,...>               add the server to the raft group of the meta servers given
+    leave                           remove the server from its raft group
+    remove <address>                remove the member with the address from the raft group, removing
+                                    the leader makes the others elect a new one
+
+set MORPHEUS_TOKEN to authenticate when the server has authentication enabled";
+
+fn fail(msg: String) -> ! {
+    eprintln!("{}", msg);
+    process::exit(1)
+}
+
+fn schema_type_from_kind<'a>(kind: &'a str) -> Option<SchemaType> {
+    match kind {
+        "vertex" => Some(SchemaType::Vertex),
+        "directed" => Some(SchemaType::Edge(EdgeAttributes::new(EdgeType::Directed, false))),
+        "undirected" => Some(SchemaType::Edge(EdgeAttributes::new(EdgeType::Undirected, false))),
+        "directed-body" => Some(SchemaType::Edge(EdgeAttributes::new(EdgeType::Directed, true))),
+        "undirected-body" => Some(SchemaType::Edge(EdgeAttributes::new(EdgeType::Undirected, true))),
+        _ => None
+    }
+}
+
+fn permission_from_name<'a>(name: &'a str) -> Option<Permission> {
+    match name {
+        "read" => Some(Permission::Read),
+        "write" => Some(Permission::Write),
+        "schema-admin" => Some(Permission::SchemaAdmin),
+        _ => None
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() < 2 { fail(USAGE.to_string()); }
+    let rpc_client = RPCClient::new_async(&args[0])
+        .unwrap_or_else(|e| fail(format!("Cannot connect to {}: {:?}", args[0], e)));
+    let admin = AsyncServiceClient::new(ADMIN_SERVICE_ID, &rpc_client);
+    let token = env::var("MORPHEUS_TOKEN").unwrap_or(String::new());
+    match args[1].as_str() {
+        "health" => match admin.health().wait() {
+            Ok(Ok(report)) => println!(
+                "ok, group {}, {} schemas, statistics for {} schemas",
+                report.group, report.schemas, report.statistics
+            ),
+            Ok(Err(e)) => fail(format!("unhealthy: {}", e)),
+            Err(e) => fail(format!("unreachable: {:?}", e))
+        },
+        "schemas" => match admin.schemas(&token).wait() {
+            Ok(Ok(schemas)) => for schema in schemas {
+                println!("{}\t{}\t{:?}\t{}", schema.id, schema.name, schema.schema_type, schema.fields.join(","));
+            },
+            Ok(Err(e)) => fail(format!("{:?}", e)),
+            Err(e) => fail(format!("{:?}", e))
+        },
+        "new-schema" => {
+            if args.len() < 4 { fail(USAGE.to_string()); }
+            let schema_type = schema_type_from_kind(&args[2])
+                .unwrap_or_else(|| fail(format!("Unknown schema kind {}", args[2])));
+            match admin.new_schema(&token, &args[3].clone(), &schema_type, &None, &Vec::new(), &true).wait() {
+                Ok(Ok(id)) => println!("{}", id),
+                Ok(Err(e)) => fail(format!("{:?}", e)),
+                Err(e) => fail(format!("{:?}", e))
+            }
+        },
+        "stats" => match admin.statistics(&token).wait() {
+            Ok(Ok(all_stats)) => for stats in all_stats {
+                println!(
+                    "{}\tcount {}\tdegree histogram {:?}\thot {:?}",
+                    stats.schema_id, stats.count, stats.degree_histogram, stats.hot_vertices
+                );
+            },
+            Ok(Err(e)) => fail(format!("{:?}", e)),
+            Err(e) => fail(format!("{:?}", e))
+        },
+        "collect-stats" => match admin.collect_statistics(&token).wait() {
+            Ok(Ok(n)) => println!("collected statistics for {} schemas", n),
+            Ok(Err(e)) => fail(format!("{:?}", e)),
+            Err(e) => fail(format!("{:?}", e))
+        },
+        "degree-report" => {
+            let hubs = args.get(2).map(|hubs| hubs.parse::<usize>().unwrap_or_else(|_| fail(format!("Not a number {}", hubs))))
+                .unwrap_or(10);
+            match admin.degree_report(&token, &hubs).wait() {
+                Ok(Ok(reports)) => for report in reports {
+                    let body = report.avg_body_bytes.map(|bytes| format!("{:.1}", bytes)).unwrap_or("-".to_string());
+                    println!("{}\tedges {}\tavg body bytes {}", report.schema_id, report.edges, body);
+                    let directions = vec![("out", Some(report.out_degree)), ("in", report.in_degree)];
+                    for (name, distribution) in directions {
+                        if let Some(d) = distribution {
+                            println!(
+                                "\t{}-degree mean {:.2}\tmax {}\thistogram {:?}\thubs {:?}",
+                                name, d.mean, d.max, d.histogram, d.hubs
+                            );
+                        }
+                    }
+                },
+                Ok(Err(e)) => fail(format!("{:?}", e)),
+                Err(e) => fail(format!("{:?}", e))
+            }
+        },
+        "snapshot" => {
+            if args.len() < 3 { fail(USAGE.to_string()); }
+            match admin.snapshot(&token, &args[2].clone()).wait() {
+                Ok(Ok(triples)) => println!("wrote {} triples to {}", triples, args[2]),
+                Ok(Err(e)) => fail(format!("{:?}", e)),
+                Err(e) => fail(format!("{:?}", e))
+            }
+        },
+        "rebuild-index" => {
+            if args.len() < 4 { fail(USAGE.to_string()); }
+            match admin.rebuild_index(&token, &args[2].clone(), &args[3].clone()).wait() {
+                Ok(Ok(indexed)) => println!("indexed {} vectors", indexed),
+                Ok(Err(e)) => fail(format!("{:?}", e)),
+                Err(e) => fail(format!("{:?}", e))
+            }
+        },
+        "new-role" => {
+            if args.len() < 4 { fail(USAGE.to_string()); }
+            let permission = permission_from_name(&args[3])
+                .unwrap_or_else(|| fail(format!("Unknown permission {}", args[3])));
+            let scope = match args.get(4) {
+                Some(ns) => Scope::Namespace(ns.clone()),
+                None => Scope::Cluster
+            };
+            let role = Role { name: args[2].clone(), grants: vec![Grant { permission, scope }] };
+            match admin.define_role(&token, &role).wait() {
+                Ok(Ok(())) => println!("ok"),
+                Ok(Err(e)) => fail(format!("{:?}", e)),
+                Err(e) => fail(format!("{:?}", e))
+            }
+        },
+        "new-user" => {
+            if args.len() < 4 { fail(USAGE.to_string()); }
+            let roles: Vec<String> = args[3].split(',').map(|r| r.to_string()).collect();
+            match admin.create_user(&token, &args[2].clone(), &roles).wait() {
+                Ok(Ok(user_token)) => println!("{}", user_token),
+                Ok(Err(e)) => fail(format!("{:?}", e)),
+                Err(e) => fail(format!("{:?}", e))
+            }
+        },
+        "remove-vertices" => {
+            if args.len() < 3 { fail(USAGE.to_string()); }
+            match admin.remove_vertices(&token, &args[2].clone(), &args.get(3).cloned()).wait() {
+                Ok(Ok(removed)) => println!("removed {} vertices", removed),
+                Ok(Err(e)) => fail(format!("{:?}", e)),
+                Err(e) => fail(format!("{:?}", e))
+            }
+        },
+        "audit" => {
+            let query = AuditQuery { user: args.get(2).cloned(), limit: Some(100), ..AuditQuery::default() };
+            match admin.audit_log(&token, &query).wait() {
+                Ok(Ok(entries)) => for entry in entries {
+                    println!(
+                        "{}\t{}\t{:?}\t{}\t{}",
+                        entry.at_ms, entry.user, entry.action, entry.target,
+                        entry.error.unwrap_or_else(|| "ok".to_string())
+                    );
+                },
+                Ok(Err(e)) => fail(format!("{:?}", e)),
+                Err(e) => fail(format!("{:?}", e))
+            }
+        },
+        "group-health" => match admin.group_health().wait() {
+            Ok(Ok(health)) => println!(
+                "server {}{}, leader {}, {} members, {} logs up to {}",
+                health.server_id, if health.is_leader { " (leader)" } else { "" },
+                health.leader_id, health.members, health.logs, health.last_log_id
+            ),
+            Ok(Err(e)) => fail(format!("{:?}", e)),
+            Err(e) => fail(format!("unreachable: {:?}", e))
+        },
+        "join" => {
+            if args.len() < 3 { fail(USAGE.to_string()); }
+            let members: Vec<String> = args[2].split(',').map(|m| m.trim().to_string()).filter(|m| !m.is_empty()).collect();
+            match admin.join_group(&token, &members).wait() {
+                Ok(Ok(())) => println!("ok"),
+                Ok(Err(e)) => fail(format!("{:?}", e)),
+                Err(e) => fail(format!("{:?}", e))
+            }
+        },
+        "remove" => {
+            if args.len() < 3 { fail(USAGE.to_string()); }
+            match admin.remove_member(&token, &args[2]).wait() {
+                Ok(Ok(())) => println!("ok"),
+                Ok(Err(e)) => fail(format!("{:?}", e)),
+                Err(e) => fail(format!("{:?}", e))
+            }
+        },
+        "leave" => match admin.leave_group(&token).wait() {
+            Ok(Ok(())) => println!("ok"),
+            Ok(Err(e)) => fail(format!("{:?}", e)),
+            Err(e) => fail(format!("{:?}", e))
+        },
+        _ => fail(USAGE.to_string())
+    }
+}
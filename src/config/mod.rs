@@ -1,4 +1,4 @@
 use utils::file;
 use yaml_rust::{YamlLoader, Yaml};
 
-pub mod neb;
\ No newline at end of file
+pub mod morpheus;
@@ -0,0 +1,339 @@
+use neb::server::ServerOptions;
+use utils::file::slurp;
+use serde_yaml;
+use server::slow_log::SlowQueryOptions;
+use server::watchdog::WatchdogOptions;
+use server::namespace::GraphOptions;
+use server::auth::AuthOptions;
+use server::cdc::KafkaSinkOptions;
+use server::replication::{ReplicationOptions, ReplicaOptions};
+use server::graphql::GraphqlOptions;
+use server::stats::StatisticsOptions;
+use server::rate_limit::{RateLimitOptions, RateLimit};
+use graph::batch::LinkBatchOptions;
+use graph::gc::OrphanGcOptions;
+use graph::window_counter::CounterDecayOptions;
+use graph::retry::RetryPolicy;
+use graph::cache::QueryCacheOptions;
+use graph::vertex_cache::VertexCacheOptions;
+use graph::quota::QuotaOptions;
+use graph::result_limit::ResultLimits;
+use graph::memory_budget::MemoryBudgetOptions;
+use graph::id_list::ShardOptions;
+use graph::encryption::KeyProviderOptions;
+use graph::migrate::SchemaMigrationOptions;
+use import::stream::StreamIngestOptions;
+
+use std::env;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+
+pub static DEFAULT_CONFIG_FILE: &'static str = "config/morpheus.yaml";
+pub static CONFIG_FILE_ENV: &'static str = "MORPHEUS_CONFIG";
+
+fn default_log_config() -> String {
+    "config/log4rs.yaml".to_string()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MorpheusOptions {
+    #[serde(default = "default_log_config")]
+    pub log_config: String,
+    #[serde(default)]
+    pub metrics_port: Option<u16>,
+    #[serde(default)]
+    pub slow_query: SlowQueryOptions,
+    #[serde(default)]
+    pub graphs: Vec<GraphOptions>,
+    #[serde(default)]
+    pub auth: AuthOptions,
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default)]
+    pub link_batch: Option<LinkBatchOptions>,
+    #[serde(default)]
+    pub orphan_gc: Option<OrphanGcOptions>,
+    #[serde(default)]
+    pub counter_decay: Option<CounterDecayOptions>,
+    #[serde(default)]
+    pub cdc_kafka: Option<KafkaSinkOptions>,
+    #[serde(default)]
+    pub replication: Vec<ReplicationOptions>,
+    #[serde(default)]
+    pub replica: Option<ReplicaOptions>,
+    #[serde(default)]
+    pub stream_ingest: Option<StreamIngestOptions>,
+    #[serde(default)]
+    pub graphql: Option<GraphqlOptions>,
+    #[serde(default)]
+    pub query_cache: Option<QueryCacheOptions>,
+    #[serde(default)]
+    pub vertex_cache: Option<VertexCacheOptions>,
+    #[serde(default)]
+    pub quotas: Option<QuotaOptions>,
+    #[serde(default)]
+    pub result_limits: ResultLimits,
+    #[serde(default)]
+    pub memory_budget: Option<MemoryBudgetOptions>,
+    #[serde(default)]
+    pub encryption: Option<KeyProviderOptions>,
+    #[serde(default)]
+    pub schema_migration: Option<SchemaMigrationOptions>,
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitOptions>,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    #[serde(default)]
+    pub watchdog: WatchdogOptions,
+    #[serde(default)]
+    pub supernode_shards: ShardOptions,
+    #[serde(default)]
+    pub statistics: StatisticsOptions,
+    pub neb: ServerOptions
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    ReadFile(String, io::Error),
+    Parse(String, serde_yaml::Error),
+    MissingFlagValue(String),
+    UnknownFlag(String),
+    InvalidOverride(String, String),
+    Invalid(Vec<String>)
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ConfigError::ReadFile(ref file, ref e) =>
+                write!(f, "cannot read config file {}: {}, pass --config <file> or set {}", file, e, CONFIG_FILE_ENV),
+            &ConfigError::Parse(ref file, ref e) =>
+                write!(f, "config file {} is malformed: {}", file, e),
+            &ConfigError::MissingFlagValue(ref flag) =>
+                write!(f, "flag {} needs a value", flag),
+            &ConfigError::UnknownFlag(ref flag) =>
+                write!(f, "unknown flag {}", flag),
+            &ConfigError::InvalidOverride(ref name, ref value) =>
+                write!(f, "cannot use '{}' for {}", value, name),
+            &ConfigError::Invalid(ref problems) => {
+                write!(f, "invalid configuration:")?;
+                for problem in problems {
+                    write!(f, "\n  - {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// memory_size is written in megabytes, neb takes bytes
+pub fn options_from_file<'a>(file: &'a str) -> Result<MorpheusOptions, ConfigError> {
+    let file_text = slurp(file).map_err(|e| ConfigError::ReadFile(file.to_string(), e))?;
+    let mut options: MorpheusOptions = serde_yaml::from_str(&file_text)
+        .map_err(|e| ConfigError::Parse(file.to_string(), e))?;
+    options.neb.memory_size *= 1024 * 1024;
+    Ok(options)
+}
+
+fn parse_value<T: ::std::str::FromStr>(name: &str, value: &str) -> Result<T, ConfigError> {
+    value.parse().map_err(|_| ConfigError::InvalidOverride(name.to_string(), value.to_string()))
+}
+
+// one setting by its flag name, shared by environment variables and command line flags
+fn apply_override(options: &mut MorpheusOptions, name: &str, value: &str) -> Result<bool, ConfigError> {
+    match name {
+        "address" => options.neb.address = value.to_string(),
+        "group" => options.neb.group_name = value.to_string(),
+        "meta-members" => options.neb.meta_members =
+            value.split(',').map(|m| m.trim().to_string()).filter(|m| !m.is_empty()).collect(),
+        "standalone" => options.neb.standalone = parse_value(name, value)?,
+        "is-meta" => options.neb.is_meta = parse_value(name, value)?,
+        "chunk-count" => options.neb.chunk_count = parse_value(name, value)?,
+        "memory-size" => options.neb.memory_size = parse_value::<u64>(name, value)? * 1024 * 1024,
+        "metrics-port" => options.metrics_port = Some(parse_value(name, value)?),
+        "log-config" => options.log_config = value.to_string(),
+        "read-only" => options.read_only = parse_value(name, value)?,
+        _ => return Ok(false)
+    }
+    Ok(true)
+}
+
+fn env_name(name: &str) -> String {
+    format!("MORPHEUS_{}", name.replace('-', "_").to_uppercase())
+}
+
+static OVERRIDES: &'static [&'static str] = &[
+    "address", "group", "meta-members", "standalone", "is-meta",
+    "chunk-count", "memory-size", "metrics-port", "log-config", "read-only"
+];
+
+fn config_file_from_args(args: &[String]) -> Result<Option<String>, ConfigError> {
+    match args.iter().position(|a| a == "--config") {
+        Some(pos) => args.get(pos + 1)
+            .map(|f| Some(f.clone()))
+            .ok_or(ConfigError::MissingFlagValue("--config".to_string())),
+        None => Ok(None)
+    }
+}
+
+// Layers settings from the config file, then MORPHEUS_* environment variables,
+// then --flag value pairs on the command line, and validates the result.
+pub fn load(args: &[String]) -> Result<MorpheusOptions, ConfigError> {
+    let file = match config_file_from_args(args)? {
+        Some(file) => file,
+        None => env::var(CONFIG_FILE_ENV).unwrap_or(DEFAULT_CONFIG_FILE.to_string())
+    };
+    let mut options = options_from_file(&file)?;
+    for name in OVERRIDES {
+        if let Ok(value) = env::var(env_name(name)) {
+            apply_override(&mut options, name, &value)?;
+        }
+    }
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with("--") { return Err(ConfigError::UnknownFlag(arg.clone())); }
+        let value = iter.next().ok_or(ConfigError::MissingFlagValue(arg.clone()))?;
+        if arg == "--config" { continue; }
+        if !apply_override(&mut options, &arg[2..], value)? {
+            return Err(ConfigError::UnknownFlag(arg.clone()));
+        }
+    }
+    validate(&options)?;
+    Ok(options)
+}
+
+pub fn validate(options: &MorpheusOptions) -> Result<(), ConfigError> {
+    let mut problems = Vec::new();
+    let neb = &options.neb;
+    let address = neb.address.parse::<SocketAddr>();
+    if address.is_err() {
+        problems.push(format!("neb.address '{}' is not a host:port socket address", neb.address));
+    }
+    if neb.group_name.is_empty() {
+        problems.push("neb.group_name must not be empty, servers in one cluster share it".to_string());
+    }
+    if neb.meta_members.is_empty() {
+        problems.push("neb.meta_members is empty, list at least one meta server address".to_string());
+    }
+    for member in &neb.meta_members {
+        if member.parse::<SocketAddr>().is_err() {
+            problems.push(format!("meta member '{}' is not a host:port socket address", member));
+        }
+    }
+    if neb.standalone && !neb.is_meta {
+        problems.push("a standalone server must also be a meta server, set neb.is_meta to true".to_string());
+    }
+    if neb.chunk_count == 0 {
+        problems.push("neb.chunk_count must be at least 1".to_string());
+    }
+    if neb.memory_size == 0 {
+        problems.push("neb.memory_size must be at least 1 (megabytes)".to_string());
+    }
+    if let (Some(port), Ok(addr)) = (options.metrics_port, address.as_ref()) {
+        if port == addr.port() {
+            problems.push(format!("metrics_port {} clashes with the server port of neb.address", port));
+        }
+    }
+    for (i, graph) in options.graphs.iter().enumerate() {
+        if graph.name.is_empty() || graph.name.contains(':') || graph.name.contains('/') {
+            problems.push(format!("graph name '{}' must be non-empty without ':' or '/'", graph.name));
+        }
+        if options.graphs[..i].iter().any(|g| g.name == graph.name) {
+            problems.push(format!("graph '{}' is listed more than once", graph.name));
+        }
+    }
+    if options.watchdog.check_interval_ms == 0 {
+        problems.push("watchdog.check_interval_ms must be at least 1".to_string());
+    }
+    if options.supernode_shards.count == 0 {
+        problems.push("supernode_shards.count must be at least 1".to_string());
+    }
+    if options.orphan_gc.as_ref().map(|gc| gc.cells_per_second == 0).unwrap_or(false) {
+        problems.push("orphan_gc.cells_per_second must be at least 1".to_string());
+    }
+    if options.schema_migration.as_ref().map(|migration| migration.batch_size == 0).unwrap_or(false) {
+        problems.push("schema_migration.batch_size must be at least 1".to_string());
+    }
+    if options.counter_decay.as_ref().map(|decay| decay.interval_secs == 0).unwrap_or(false) {
+        problems.push("counter_decay.interval_secs must be at least 1".to_string());
+    }
+    if let Some(ref kafka) = options.cdc_kafka {
+        if kafka.brokers.is_empty() {
+            problems.push("cdc_kafka.brokers must list at least one broker".to_string());
+        }
+        if kafka.log_capacity < kafka.batch_size {
+            problems.push("cdc_kafka.log_capacity must be at least cdc_kafka.batch_size".to_string());
+        }
+    }
+    for (i, replication) in options.replication.iter().enumerate() {
+        if replication.servers.is_empty() {
+            problems.push(format!("replication '{}' must list at least one target server", replication.name));
+        }
+        if replication.group == neb.group_name {
+            problems.push(format!("replication '{}' targets the cluster of this server", replication.name));
+        }
+        if options.replication[..i].iter().any(|r| r.name == replication.name) {
+            problems.push(format!("replication '{}' is listed more than once", replication.name));
+        }
+    }
+    if options.replica.is_some() && options.read_only {
+        problems.push("replica cannot take replicated writes on a read_only server".to_string());
+    }
+    if let Some(ref ingest) = options.stream_ingest {
+        if ingest.brokers.is_empty() {
+            problems.push("stream_ingest.brokers must list at least one broker".to_string());
+        }
+        if ingest.topic.is_empty() {
+            problems.push("stream_ingest.topic must not be empty".to_string());
+        }
+        if options.read_only {
+            problems.push("stream_ingest cannot write to a read_only server".to_string());
+        }
+    }
+    if let Some(ref graphql) = options.graphql {
+        if options.metrics_port == Some(graphql.port) || address.as_ref().map(|a| a.port() == graphql.port).unwrap_or(false) {
+            problems.push(format!("graphql.port {} clashes with the metrics port or the server port", graphql.port));
+        }
+        if graphql.max_page_size == 0 || graphql.max_depth == 0 {
+            problems.push("graphql.max_page_size and graphql.max_depth must be at least 1".to_string());
+        }
+    }
+    if options.query_cache.as_ref().map(|cache| cache.capacity == 0).unwrap_or(false) {
+        problems.push("query_cache.capacity must be at least 1".to_string());
+    }
+    if let Some(ref cache) = options.vertex_cache {
+        if cache.capacity == 0 {
+            problems.push("vertex_cache.capacity must be at least 1".to_string());
+        }
+        for peer in &cache.peers {
+            if peer.parse::<SocketAddr>().is_err() {
+                problems.push(format!("vertex_cache peer '{}' is not a host:port socket address", peer));
+            }
+        }
+        if cache.peers.contains(&neb.address) {
+            problems.push("vertex_cache.peers must not list this server".to_string());
+        }
+    }
+    if options.result_limits.max_count == Some(0) || options.result_limits.max_bytes == Some(0) {
+        problems.push("result_limits.max_count and result_limits.max_bytes must be at least 1".to_string());
+    }
+    if options.memory_budget.as_ref().map_or(false, |budget| budget.max_bytes == 0) {
+        problems.push("memory_budget.max_bytes must be at least 1".to_string());
+    }
+    if let Some(ref rate_limit) = options.rate_limit {
+        let invalid = |limit: &Option<RateLimit>| limit.as_ref().map_or(false, |l| !(l.per_second > 0.0) || l.burst == 0);
+        if invalid(&rate_limit.writes) || invalid(&rate_limit.traversals)
+            || rate_limit.clients.iter().any(|c| invalid(&c.writes) || invalid(&c.traversals)) {
+            problems.push("rate_limit limits need a per_second above 0 and a burst of at least 1".to_string());
+        }
+    }
+    if options.auth.enabled && options.auth.root_token.as_ref().map(|t| t.len() < 16).unwrap_or(false) {
+        problems.push("auth.root_token is too short, use at least 16 characters".to_string());
+    }
+    if !Path::new(&options.log_config).exists() {
+        problems.push(format!("log_config file {} does not exist", options.log_config));
+    }
+    if problems.is_empty() { Ok(()) } else { Err(ConfigError::Invalid(problems)) }
+}
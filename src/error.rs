@@ -0,0 +1,186 @@
+// One error for the whole graph API. Operations still return their own enums, which become the kind
+// of a morpheus::Error through From, so callers can mix operations under ? and add context on the way:
+// the operation that failed, the vertex and the schema it was about. Results of graph transactions
+// carry two layers, the transaction error and the operation's, flatten folds them into one.
+
+use neb::ram::types::Id;
+use neb::client::transaction::TxnError;
+
+use std::error;
+use std::fmt;
+use std::result;
+
+use graph::{NewVertexError, ReadVertexError, LinkVerticesError, NeighbourhoodError, ScanVerticesError, ScanEdgesError};
+use graph::edge::EdgeError;
+use graph::id_list::IdListError;
+use graph::vertex::{UpdateError, RemoveError};
+use graph::model::ModelError;
+use graph::repository::RepositoryError;
+use server::schema::SchemaError;
+
+pub type Result<T> = result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    NewVertex(NewVertexError),
+    ReadVertex(ReadVertexError),
+    LinkVertices(LinkVerticesError),
+    Neighbourhood(NeighbourhoodError),
+    UpdateVertex(UpdateError),
+    RemoveVertex(RemoveError),
+    ScanVertices(ScanVerticesError),
+    ScanEdges(ScanEdgesError),
+    Edge(EdgeError),
+    IdList(IdListError),
+    Schema(SchemaError),
+    Model(ModelError),
+    Txn(TxnError),
+    // a vertex of another schema than the one asked for
+    WrongSchema(Id)
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub operation: Option<&'static str>,
+    pub vertex: Option<Id>,
+    pub schema: Option<String>
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind) -> Error {
+        Error { kind, operation: None, vertex: None, schema: None }
+    }
+
+    // context set closer to the caller does not overwrite what was set where the error happened
+    pub fn with_operation(mut self, operation: &'static str) -> Error {
+        if self.operation.is_none() { self.operation = Some(operation); }
+        self
+    }
+
+    pub fn with_vertex(mut self, vertex: Id) -> Error {
+        if self.vertex.is_none() { self.vertex = Some(vertex); }
+        self
+    }
+
+    pub fn with_schema(mut self, schema: &str) -> Error {
+        if self.schema.is_none() { self.schema = Some(schema.to_string()); }
+        self
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ErrorKind::NewVertex(ref e) => write!(f, "cannot create vertex: {:?}", e),
+            &ErrorKind::ReadVertex(ref e) => write!(f, "cannot read vertex: {:?}", e),
+            &ErrorKind::LinkVertices(ref e) => write!(f, "cannot link vertices: {:?}", e),
+            &ErrorKind::Neighbourhood(ref e) => write!(f, "cannot read neighbourhood: {:?}", e),
+            &ErrorKind::UpdateVertex(ref e) => write!(f, "cannot update vertex: {:?}", e),
+            &ErrorKind::RemoveVertex(ref e) => write!(f, "cannot remove vertex: {:?}", e),
+            &ErrorKind::ScanVertices(ref e) => write!(f, "cannot scan vertices: {:?}", e),
+            &ErrorKind::ScanEdges(ref e) => write!(f, "cannot scan edges: {:?}", e),
+            &ErrorKind::Edge(ref e) => write!(f, "edge error: {:?}", e),
+            &ErrorKind::IdList(ref e) => write!(f, "adjacency list error: {:?}", e),
+            &ErrorKind::Schema(ref e) => write!(f, "schema error: {:?}", e),
+            &ErrorKind::Model(ref e) => write!(f, "model error: {:?}", e),
+            &ErrorKind::Txn(ref e) => write!(f, "transaction failed: {:?}", e),
+            &ErrorKind::WrongSchema(ref id) => write!(f, "vertex {:?} is of another schema", id)
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(operation) = self.operation { write!(f, "{}: ", operation)?; }
+        write!(f, "{}", self.kind)?;
+        if let Some(ref vertex) = self.vertex { write!(f, ", vertex {:?}", vertex)?; }
+        if let Some(ref schema) = self.schema { write!(f, ", schema {}", schema)?; }
+        Ok(())
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match self.kind {
+            ErrorKind::NewVertex(_) => "cannot create vertex",
+            ErrorKind::ReadVertex(_) => "cannot read vertex",
+            ErrorKind::LinkVertices(_) => "cannot link vertices",
+            ErrorKind::Neighbourhood(_) => "cannot read neighbourhood",
+            ErrorKind::UpdateVertex(_) => "cannot update vertex",
+            ErrorKind::RemoveVertex(_) => "cannot remove vertex",
+            ErrorKind::ScanVertices(_) => "cannot scan vertices",
+            ErrorKind::ScanEdges(_) => "cannot scan edges",
+            ErrorKind::Edge(_) => "edge error",
+            ErrorKind::IdList(_) => "adjacency list error",
+            ErrorKind::Schema(_) => "schema error",
+            ErrorKind::Model(_) => "model error",
+            ErrorKind::Txn(_) => "transaction failed",
+            ErrorKind::WrongSchema(_) => "vertex of another schema"
+        }
+    }
+}
+
+macro_rules! from_kind {
+    ($t:ty, $variant:ident) => {
+        impl From<$t> for Error {
+            fn from(e: $t) -> Error { Error::new(ErrorKind::$variant(e)) }
+        }
+    };
+}
+
+from_kind!(NewVertexError, NewVertex);
+from_kind!(ReadVertexError, ReadVertex);
+from_kind!(LinkVerticesError, LinkVertices);
+from_kind!(NeighbourhoodError, Neighbourhood);
+from_kind!(UpdateError, UpdateVertex);
+from_kind!(RemoveError, RemoveVertex);
+from_kind!(ScanVerticesError, ScanVertices);
+from_kind!(ScanEdgesError, ScanEdges);
+from_kind!(EdgeError, Edge);
+from_kind!(IdListError, IdList);
+from_kind!(SchemaError, Schema);
+from_kind!(ModelError, Model);
+from_kind!(TxnError, Txn);
+
+impl From<RepositoryError> for Error {
+    fn from(e: RepositoryError) -> Error {
+        match e {
+            RepositoryError::NewVertexError(e) => e.into(),
+            RepositoryError::ReadVertexError(e) => e.into(),
+            RepositoryError::LinkVerticesError(e) => e.into(),
+            RepositoryError::NeighbourhoodError(e) => e.into(),
+            RepositoryError::ModelError(e) => e.into(),
+            RepositoryError::RemoveError(e) => e.into(),
+            RepositoryError::TxnError(e) => e.into(),
+            RepositoryError::WrongSchema(id) => Error::new(ErrorKind::WrongSchema(id)).with_vertex(id)
+        }
+    }
+}
+
+// the two layers of a graph transaction's result as one
+pub fn flatten<T, E>(result: result::Result<result::Result<T, E>, TxnError>) -> Result<T> where E: Into<Error> {
+    match result {
+        Ok(Ok(v)) => Ok(v),
+        Ok(Err(e)) => Err(e.into()),
+        Err(e) => Err(e.into())
+    }
+}
+
+pub trait ResultExt<T> {
+    fn operation(self, operation: &'static str) -> Result<T>;
+    fn vertex(self, vertex: Id) -> Result<T>;
+    fn schema(self, schema: &str) -> Result<T>;
+}
+
+impl <T, E> ResultExt<T> for result::Result<T, E> where E: Into<Error> {
+    fn operation(self, operation: &'static str) -> Result<T> {
+        self.map_err(|e| e.into().with_operation(operation))
+    }
+    fn vertex(self, vertex: Id) -> Result<T> {
+        self.map_err(|e| e.into().with_vertex(vertex))
+    }
+    fn schema(self, schema: &str) -> Result<T> {
+        self.map_err(|e| e.into().with_schema(schema))
+    }
+}
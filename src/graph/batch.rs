@@ -0,0 +1,193 @@
+// Group commit for link. While batching is on, link calls are queued and a flusher thread runs
+// everything queued since the last flush every few milliseconds. Links sharing a vertex go into
+// one transaction, so a high fan-in vertex gets one append to its id list per flush instead of a
+// stream of transactions all conflicting on the tail of that list.
+
+use neb::ram::types::Id;
+use neb::dovahkiin::types::Map;
+use neb::client::transaction::TxnError;
+use futures::prelude::*;
+use futures::future;
+use futures::sync::oneshot;
+use parking_lot::Mutex;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::HashMap;
+use std::mem;
+use std::thread;
+use std::time::Duration;
+
+use graph::{GraphInner, LinkVerticesError};
+use graph::edge::Edge;
+
+pub type LinkResult = Result<Result<Edge, LinkVerticesError>, TxnError>;
+
+fn default_flush_interval_ms() -> u64 { 5 }
+fn default_max_batch() -> usize { 256 }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LinkBatchOptions {
+    #[serde(default = "default_flush_interval_ms")]
+    pub flush_interval_ms: u64,
+    // upper bound of links in one transaction, larger groups are split
+    #[serde(default = "default_max_batch")]
+    pub max_batch: usize
+}
+
+impl Default for LinkBatchOptions {
+    fn default() -> LinkBatchOptions {
+        LinkBatchOptions {
+            flush_interval_ms: default_flush_interval_ms(),
+            max_batch: default_max_batch()
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PendingLink {
+    pub from: Id,
+    pub schema_id: u32,
+    pub to: Id,
+    pub body: Option<Map>
+}
+
+struct QueuedLink {
+    link: PendingLink,
+    sender: oneshot::Sender<LinkResult>
+}
+
+// None while batching is off
+pub struct LinkQueue {
+    pending: Mutex<Option<Vec<QueuedLink>>>
+}
+
+impl LinkQueue {
+    pub fn new() -> LinkQueue {
+        LinkQueue { pending: Mutex::new(None) }
+    }
+    fn open(&self) {
+        let mut pending = self.pending.lock();
+        if pending.is_none() { *pending = Some(Vec::new()); }
+    }
+    // hands the link back when batching is off so the caller can run it directly
+    pub fn push(&self, link: PendingLink) -> Result<oneshot::Receiver<LinkResult>, PendingLink> {
+        let mut pending = self.pending.lock();
+        match *pending {
+            Some(ref mut queue) => {
+                let (sender, receiver) = oneshot::channel();
+                queue.push(QueuedLink { link, sender });
+                Ok(receiver)
+            },
+            None => Err(link)
+        }
+    }
+    // closing under the same lock as push, nothing can be queued after the last drain
+    fn drain(&self, close: bool) -> Vec<QueuedLink> {
+        let mut pending = self.pending.lock();
+        let drained = match *pending {
+            Some(ref mut queue) => mem::replace(queue, Vec::new()),
+            None => Vec::new()
+        };
+        if close { *pending = None; }
+        drained
+    }
+}
+
+fn find(parent: &mut Vec<usize>, mut i: usize) -> usize {
+    while parent[i] != i {
+        let grand = parent[parent[i]];
+        parent[i] = grand;
+        i = grand;
+    }
+    i
+}
+
+// links touching a common vertex end up in the same group
+fn group_by_vertex(links: Vec<QueuedLink>) -> Vec<Vec<QueuedLink>> {
+    let mut parent: Vec<usize> = (0..links.len()).collect();
+    let mut first_seen: HashMap<Id, usize> = HashMap::new();
+    for (i, queued) in links.iter().enumerate() {
+        for id in &[queued.link.from, queued.link.to] {
+            match first_seen.get(id).cloned() {
+                Some(j) => {
+                    let a = find(&mut parent, i);
+                    let b = find(&mut parent, j);
+                    if a != b { parent[a] = b; }
+                },
+                None => { first_seen.insert(*id, i); }
+            }
+        }
+    }
+    let mut groups: HashMap<usize, Vec<QueuedLink>> = HashMap::new();
+    for (i, queued) in links.into_iter().enumerate() {
+        let root = find(&mut parent, i);
+        groups.entry(root).or_insert_with(Vec::new).push(queued);
+    }
+    groups.into_iter().map(|(_, group)| group).collect()
+}
+
+fn flush_group(graph: &Arc<GraphInner>, group: Vec<QueuedLink>) -> impl Future<Item = (), Error = ()> {
+    let (links, senders): (Vec<PendingLink>, Vec<_>) =
+        group.into_iter().map(|queued| (queued.link, queued.sender)).unzip();
+    let batch = links.clone();
+    let graph = graph.clone();
+    let txn_graph = graph.clone();
+    txn_graph.graph_transaction(move |txn| {
+        batch.iter().map(|link| txn.link(link.from, link.schema_id, link.to, link.body.clone()))
+            .collect::<Result<Vec<_>, TxnError>>()
+    }).then(move |result| match result {
+        Ok(results) => {
+            for (sender, result) in senders.into_iter().zip(results.into_iter()) {
+                let _ = sender.send(Ok(result));
+            }
+            future::Either::A(future::ok(()))
+        },
+        Err(e) => {
+            // the combined transaction gave up, run each link on its own so they fail independently
+            debug!("Link batch of {} failed with {:?}, retrying one by one", links.len(), e);
+            let retries: Vec<_> = links.into_iter().zip(senders.into_iter()).map(|(link, sender)| {
+                graph.link_now(link).then(move |result| {
+                    let _ = sender.send(result);
+                    Ok::<(), ()>(())
+                })
+            }).collect();
+            future::Either::B(future::join_all(retries).map(|_| ()))
+        }
+    })
+}
+
+fn flush(graph: &Arc<GraphInner>, max_batch: usize, close: bool) {
+    let queued = graph.link_queue.drain(close);
+    if queued.is_empty() { return; }
+    let mut flushes = Vec::new();
+    for mut group in group_by_vertex(queued) {
+        while !group.is_empty() {
+            let rest = if group.len() > max_batch { group.split_off(max_batch) } else { Vec::new() };
+            flushes.push(flush_group(graph, group));
+            group = rest;
+        }
+    }
+    let _ = future::join_all(flushes).wait();
+}
+
+// Queued links are flushed once more after `running` turns false, callers never see a link dropped
+pub fn start_flusher(graph: Arc<GraphInner>, options: LinkBatchOptions, running: Arc<AtomicBool>)
+    -> thread::JoinHandle<()>
+{
+    graph.link_queue.open();
+    let interval = Duration::from_millis(options.flush_interval_ms);
+    let max_batch = ::std::cmp::max(options.max_batch, 1);
+    thread::Builder::new()
+        .name("morpheus-link-batch".to_string())
+        .spawn(move || {
+            loop {
+                thread::sleep(interval);
+                let stopping = !running.load(Ordering::Relaxed);
+                flush(&graph, max_batch, stopping);
+                if stopping { break; }
+            }
+            debug!("Link batch flusher stopped");
+        })
+        .unwrap()
+}
@@ -0,0 +1,158 @@
+// Bulk writes. A list of declarative operations is split into chunks of at most max_ops operations
+// and about max_bytes of vertex and edge data, every chunk is one transaction and chunks run in list
+// order, so an operation may refer to vertices written by earlier chunks. Operations the graph
+// rejects, like a link to a missing vertex, are reported and the rest of their chunk still commits.
+// A chunk whose transaction fails is reported as a whole and the following chunks still run.
+
+use neb::ram::types::{Id, key_hash};
+use neb::dovahkiin::types::{Map, Value};
+use neb::client::transaction::TxnError;
+use futures::prelude::*;
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use graph::{GraphInner, GraphTransaction, UpdateOptions};
+
+fn default_max_ops() -> usize { 1000 }
+fn default_max_bytes() -> usize { 1024 * 1024 }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BulkOptions {
+    #[serde(default = "default_max_ops")]
+    pub max_ops: usize,
+    // an operation larger than this gets a chunk of its own
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: usize
+}
+
+impl Default for BulkOptions {
+    fn default() -> BulkOptions {
+        BulkOptions {
+            max_ops: default_max_ops(),
+            max_bytes: default_max_bytes()
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum BulkOp {
+    InsertVertex { schema: u32, data: Map },
+    // sets the fields given and keeps the rest
+    UpdateVertex { id: Id, fields: Vec<(String, Value)> },
+    RemoveVertex { id: Id },
+    Link { from: Id, schema: u32, to: Id, body: Option<Map> },
+    Unlink { from: Id, schema: u32, to: Id }
+}
+
+#[derive(Debug, Default)]
+pub struct BulkReport {
+    // operations committed, rejected ones excluded
+    pub applied: usize,
+    pub chunks: usize,
+    // position in the list and why the graph rejected it
+    pub rejected: Vec<(usize, String)>,
+    // positions of the operations in a chunk whose transaction failed
+    pub failed: Vec<(Range<usize>, TxnError)>
+}
+
+impl BulkReport {
+    pub fn is_complete(&self) -> bool {
+        self.rejected.is_empty() && self.failed.is_empty()
+    }
+}
+
+// rough size of the data an operation writes, only used to fill chunks
+fn value_bytes(value: &Value) -> usize {
+    match value {
+        &Value::String(ref s) => s.len() + 4,
+        &Value::Id(_) => 16,
+        &Value::Array(ref items) => items.iter().map(value_bytes).sum::<usize>() + 4,
+        &Value::Map(ref map) => map_bytes(map),
+        _ => 8
+    }
+}
+
+fn map_bytes(map: &Map) -> usize {
+    map.fields.iter().map(|name| name.len() + value_bytes(map.get_by_key_id(key_hash(name)))).sum()
+}
+
+pub fn op_bytes(op: &BulkOp) -> usize {
+    32 + match op {
+        &BulkOp::InsertVertex { ref data, .. } => map_bytes(data),
+        &BulkOp::UpdateVertex { ref fields, .. } => fields.iter().map(|&(ref name, ref value)| name.len() + value_bytes(value)).sum(),
+        &BulkOp::Link { body: Some(ref body), .. } => map_bytes(body),
+        _ => 0
+    }
+}
+
+// positions of the operations going into each transaction
+pub fn chunks(ops: &[BulkOp], options: &BulkOptions) -> Vec<Range<usize>> {
+    let mut chunks = Vec::new();
+    let (mut start, mut bytes) = (0, 0);
+    for (pos, op) in ops.iter().enumerate() {
+        let size = op_bytes(op);
+        if pos > start && (pos - start >= options.max_ops || bytes + size > options.max_bytes) {
+            chunks.push(start..pos);
+            start = pos;
+            bytes = 0;
+        }
+        bytes += size;
+    }
+    if start < ops.len() { chunks.push(start..ops.len()); }
+    chunks
+}
+
+// Applies one operation in the transaction, the inner error is why the graph rejected it
+pub fn apply_op(txn: &GraphTransaction, op: &BulkOp) -> Result<Result<(), String>, TxnError> {
+    Ok(match op {
+        &BulkOp::InsertVertex { schema, ref data } =>
+            txn.new_vertex(schema, data.clone())?.map(|_| ()).map_err(|e| format!("{:?}", e)),
+        &BulkOp::UpdateVertex { id, ref fields } =>
+            txn.update_vertex_with(id, UpdateOptions::default(), |mut vertex| {
+                if let Value::Map(ref mut data) = vertex.cell.data {
+                    for &(ref name, ref value) in fields {
+                        data.insert(name, value.clone());
+                    }
+                }
+                Some(vertex)
+            })?.map_err(|e| format!("{:?}", e)),
+        &BulkOp::RemoveVertex { id } =>
+            txn.remove_vertex(id)?.map_err(|e| format!("{:?}", e)),
+        &BulkOp::Link { from, schema, to, ref body } =>
+            txn.link(from, schema, to, body.clone())?.map(|_| ()).map_err(|e| format!("{:?}", e)),
+        &BulkOp::Unlink { from, schema, to } =>
+            txn.unlink(from, schema, to)?.map(|_| ()).map_err(|e| format!("{:?}", e))
+    })
+}
+
+impl GraphInner {
+    #[async]
+    pub fn bulk_transaction(this: Arc<Self>, ops: Vec<BulkOp>, options: BulkOptions) -> Result<BulkReport, ()> {
+        let ranges = chunks(&ops, &options);
+        let ops = Arc::new(ops);
+        let mut report = BulkReport::default();
+        for range in ranges {
+            let (txn_ops, txn_range) = (ops.clone(), range.clone());
+            let result = await!(this.graph_transaction(move |txn| {
+                let mut rejected = Vec::new();
+                for pos in txn_range.clone() {
+                    if let Err(reason) = apply_op(txn, &txn_ops[pos])? { rejected.push((pos, reason)); }
+                }
+                Ok(rejected)
+            }));
+            report.chunks += 1;
+            match result {
+                Ok(rejected) => {
+                    report.applied += range.len() - rejected.len();
+                    report.rejected.extend(rejected);
+                },
+                Err(e) => {
+                    warn!("Bulk transaction chunk {:?} failed: {:?}", range, e);
+                    report.failed.push((range, e));
+                }
+            }
+        }
+        Ok(report)
+    }
+}
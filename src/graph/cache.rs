@@ -0,0 +1,199 @@
+// Per-server cache of neighbourhood results, keyed by vertex, edge schema, direction and filter.
+// A write committed through this server drops the entries of every vertex it touches, found in the
+// change events its transaction recorded (see graph::changes): entries of the vertex itself, of the
+// ends of a link or unlink, and entries listing the vertex or edge body among their results.
+// Writes through other servers are not seen, entries also expire after max_age_ms to bound how stale
+// they get there. Edges removed with Edge::remove bypass the hooks just as they bypass the change log.
+
+use neb::ram::types::Id;
+use parking_lot::Mutex;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use graph::EdgeDirection;
+use graph::vertex::Vertex;
+use graph::edge::Edge;
+use graph::changes::ChangeEvent;
+use query::SExpr;
+use server::metrics;
+
+fn default_capacity() -> usize { 10000 }
+fn default_max_age_ms() -> u64 { 5000 }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QueryCacheOptions {
+    // entries kept at most, the oldest go first
+    #[serde(default = "default_capacity")]
+    pub capacity: usize,
+    #[serde(default = "default_max_age_ms")]
+    pub max_age_ms: u64
+}
+
+impl Default for QueryCacheOptions {
+    fn default() -> QueryCacheOptions {
+        QueryCacheOptions {
+            capacity: default_capacity(),
+            max_age_ms: default_max_age_ms()
+        }
+    }
+}
+
+pub type Neighbourhood = Vec<(Vertex, Edge)>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    vertex: Id,
+    schema: u32,
+    direction: u64,
+    // the parsed filter printed whole, two filters only share entries when they read the same
+    filter: Option<String>
+}
+
+impl CacheKey {
+    pub fn new(vertex: Id, schema: u32, direction: EdgeDirection, filter: &Option<Vec<SExpr>>) -> CacheKey {
+        // Both has no list of its own to name it
+        let direction = direction.as_field().unwrap_or(0);
+        let filter = filter.as_ref().map(|sexpr| format!("{:?}", sexpr));
+        CacheKey { vertex, schema, direction, filter }
+    }
+}
+
+struct Entry {
+    result: Neighbourhood,
+    cached_at: Instant,
+    stamp: u64,
+    // vertices and edge bodies whose writes drop the entry
+    deps: Vec<Id>
+}
+
+struct CacheState {
+    entries: HashMap<CacheKey, Entry>,
+    by_dep: HashMap<Id, HashSet<CacheKey>>,
+    // insertion order for eviction, stale when the stamp no longer matches the entry
+    order: VecDeque<(CacheKey, u64)>,
+    next_stamp: u64,
+    // bumped by every invalidation, results computed across one are not cached
+    generation: u64
+}
+
+pub struct QueryCache {
+    options: QueryCacheOptions,
+    state: Mutex<CacheState>
+}
+
+fn remove_entry(state: &mut CacheState, key: &CacheKey) {
+    if let Some(entry) = state.entries.remove(key) {
+        for dep in &entry.deps {
+            let emptied = match state.by_dep.get_mut(dep) {
+                Some(keys) => { keys.remove(key); keys.is_empty() },
+                None => false
+            };
+            if emptied { state.by_dep.remove(dep); }
+        }
+    }
+}
+
+impl QueryCache {
+    pub fn new(options: QueryCacheOptions) -> QueryCache {
+        QueryCache {
+            options,
+            state: Mutex::new(CacheState {
+                entries: HashMap::new(),
+                by_dep: HashMap::new(),
+                order: VecDeque::new(),
+                next_stamp: 0,
+                generation: 0
+            })
+        }
+    }
+
+    // taken before computing a result and handed back to insert
+    pub fn generation(&self) -> u64 {
+        self.state.lock().generation
+    }
+
+    pub fn get(&self, key: &CacheKey) -> Option<Neighbourhood> {
+        let mut state = self.state.lock();
+        let max_age = Duration::from_millis(self.options.max_age_ms);
+        let fresh = match state.entries.get(key) {
+            Some(entry) if entry.cached_at.elapsed() < max_age => Some(entry.result.clone()),
+            Some(_) => None,
+            None => { metrics::QUERY_CACHE_MISSES.inc(); return None; }
+        };
+        match fresh {
+            Some(_) => metrics::QUERY_CACHE_HITS.inc(),
+            None => {
+                metrics::QUERY_CACHE_MISSES.inc();
+                remove_entry(&mut state, key);
+            }
+        }
+        fresh
+    }
+
+    // Skipped when a write committed since generation was taken, the result may predate it
+    pub fn insert(&self, key: CacheKey, generation: u64, result: &Neighbourhood) {
+        let mut state = self.state.lock();
+        if state.generation != generation { return; }
+        remove_entry(&mut state, &key);
+        let mut deps = vec![key.vertex];
+        for &(ref vertex, ref edge) in result {
+            deps.push(vertex.cell.id());
+            if let &Some(ref body) = edge.get_data() { deps.push(body.id()); }
+        }
+        deps.sort_by_key(|id| (id.higher, id.lower));
+        deps.dedup();
+        for dep in &deps {
+            state.by_dep.entry(*dep).or_insert_with(HashSet::new).insert(key.clone());
+        }
+        let stamp = state.next_stamp;
+        state.next_stamp += 1;
+        state.order.push_back((key.clone(), stamp));
+        state.entries.insert(key, Entry { result: result.clone(), cached_at: Instant::now(), stamp, deps });
+        while state.entries.len() > self.options.capacity {
+            let (oldest, stamp) = match state.order.pop_front() { Some(oldest) => oldest, None => break };
+            if state.entries.get(&oldest).map(|e| e.stamp == stamp).unwrap_or(false) {
+                remove_entry(&mut state, &oldest);
+            }
+        }
+        // stale order slots of replaced or invalidated entries would otherwise pile up
+        if state.order.len() > self.options.capacity * 2 {
+            let state = &mut *state;
+            let entries = &state.entries;
+            state.order.retain(|&(ref key, stamp)| entries.get(key).map(|e| e.stamp == stamp).unwrap_or(false));
+        }
+    }
+
+    // Called with the events of every transaction committed through this server
+    pub fn invalidate(&self, events: &[ChangeEvent]) {
+        if events.is_empty() { return; }
+        let mut state = self.state.lock();
+        state.generation += 1;
+        for event in events {
+            let mut touched = vec![event.id];
+            if let Some((from, to)) = event.ends {
+                touched.push(from);
+                touched.push(to);
+            }
+            for id in touched {
+                let keys: Vec<CacheKey> = match state.by_dep.get(&id) {
+                    Some(keys) => keys.iter().cloned().collect(),
+                    None => continue
+                };
+                for key in keys { remove_entry(&mut state, &key); }
+            }
+        }
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.state.lock();
+        state.entries.clear();
+        state.by_dep.clear();
+        state.order.clear();
+        state.generation += 1;
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().entries.len()
+    }
+}
@@ -0,0 +1,154 @@
+// Change log of the vertex and edge writes committed through this server. A transaction records its
+// writes as it makes them and they are appended once it commits, each under a sequence number that
+// keeps growing across restarts of the server. Only the last capacity events are kept, in memory,
+// readers follow the log by sequence number and lose what they fall too far behind on.
+// Recording is off until the change log or one of the caches is enabled, the caches are invalidated
+// from the same events (see graph::cache and graph::vertex_cache). Edges removed with Edge::remove
+// bypass the graph transaction and are not logged, neither are the edges removed along with their vertex.
+
+use neb::ram::types::{Id, Value};
+use neb::ram::cell::Cell;
+use parking_lot::{Condvar, Mutex};
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use graph::GraphTransaction;
+use graph::edge::Edge;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    VertexInserted,
+    VertexUpdated,
+    VertexRemoved,
+    Linked,
+    // by unlink or by rolling a link back to a savepoint
+    Unlinked
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChangeEvent {
+    pub seq: u64,
+    pub kind: ChangeKind,
+    pub schema: u32,
+    // the vertex or the edge body, unit id for edges without a body
+    pub id: Id,
+    // edges only, in link order
+    pub ends: Option<(Id, Id)>,
+    // vertex or edge body after the write, None for removals
+    pub data: Option<Value>,
+    // milliseconds since the epoch when the transaction committed
+    #[serde(default)]
+    pub time: u64,
+    // group of the cluster a replicated write came from, None for writes made on this one,
+    // see server::replication
+    #[serde(default)]
+    pub origin: Option<String>
+}
+
+struct LogState {
+    events: VecDeque<ChangeEvent>,
+    next_seq: u64
+}
+
+pub struct ChangeLog {
+    capacity: usize,
+    state: Mutex<LogState>,
+    appended: Condvar
+}
+
+impl ChangeLog {
+    pub fn new(capacity: usize) -> ChangeLog {
+        // microseconds since the epoch, a restarted server does not reuse the numbers of its last run
+        let start = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() * 1_000_000 + d.subsec_nanos() as u64 / 1000)
+            .unwrap_or(1);
+        ChangeLog {
+            capacity: ::std::cmp::max(capacity, 1),
+            state: Mutex::new(LogState { events: VecDeque::new(), next_seq: start }),
+            appended: Condvar::new()
+        }
+    }
+
+    pub fn append(&self, events: Vec<ChangeEvent>) {
+        if events.is_empty() { return; }
+        let time = now_ms();
+        let mut state = self.state.lock();
+        for mut event in events {
+            event.seq = state.next_seq;
+            event.time = time;
+            state.next_seq += 1;
+            state.events.push_back(event);
+        }
+        while state.events.len() > self.capacity {
+            state.events.pop_front();
+        }
+        self.appended.notify_all();
+    }
+
+    // sequence number of the oldest event still kept
+    pub fn first_seq(&self) -> Option<u64> {
+        self.state.lock().events.front().map(|e| e.seq)
+    }
+
+    // Up to max events numbered after seq, waits for new ones up to timeout when there are none
+    pub fn read_after(&self, seq: u64, max: usize, timeout: Duration) -> Vec<ChangeEvent> {
+        let mut state = self.state.lock();
+        if state.next_seq <= seq + 1 {
+            self.appended.wait_for(&mut state, timeout);
+        }
+        let first = state.events.front().map(|e| e.seq).unwrap_or(state.next_seq);
+        let skip = if seq < first { 0 } else { (seq - first + 1) as usize };
+        state.events.iter().skip(skip).take(max).cloned().collect()
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() * 1000 + d.subsec_nanos() as u64 / 1_000_000)
+        .unwrap_or(0)
+}
+
+pub type PendingChanges = Arc<Mutex<Vec<ChangeEvent>>>;
+
+impl <'a>GraphTransaction<'a> {
+    pub(super) fn record_change(&self, before: Option<&Cell>, after: Option<&Cell>) {
+        let pending = match self.changes { Some(ref pending) => pending, None => return };
+        let (kind, cell) = match (before, after) {
+            (None, Some(cell)) => (ChangeKind::VertexInserted, cell),
+            (Some(_), Some(cell)) => (ChangeKind::VertexUpdated, cell),
+            (Some(cell), None) => (ChangeKind::VertexRemoved, cell),
+            (None, None) => return
+        };
+        pending.lock().push(ChangeEvent {
+            seq: 0, kind, schema: cell.header.schema, id: cell.id(), ends: None,
+            data: after.map(|cell| cell.data.clone()), time: 0, origin: None
+        });
+    }
+
+    pub(super) fn record_edge_change(&self, kind: ChangeKind, edge: &Edge) {
+        let pending = match self.changes { Some(ref pending) => pending, None => return };
+        let (from, to) = edge.ends();
+        let body = edge.get_data();
+        pending.lock().push(ChangeEvent {
+            seq: 0, kind, schema: edge.schema_id(),
+            id: body.as_ref().map(|cell| cell.id()).unwrap_or_else(Id::unit_id),
+            ends: Some((*from, *to)),
+            data: match kind {
+                ChangeKind::Linked => body.as_ref().map(|cell| cell.data.clone()),
+                _ => None
+            },
+            time: 0, origin: None
+        });
+    }
+
+    // marks the writes recorded so far in this transaction as replicated from another cluster
+    pub fn mark_change_origin(&self, origin: &str) {
+        if let Some(ref pending) = self.changes {
+            for event in pending.lock().iter_mut() {
+                event.origin = Some(origin.to_string());
+            }
+        }
+    }
+}
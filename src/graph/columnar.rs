@@ -0,0 +1,251 @@
+// Scan results in columns for analytics tools. The layout follows the declared fields of the schema:
+// vertex batches start with the vertex id, edge batches with the ends in link order and, for bodied
+// edges, the id of the edge cell. Ids take two UInt64 columns, <name>.higher and <name>.lower.
+// Integers are widened to 64 bits and floats to double. Maps, arrays and the fields of dynamic
+// schemas that are not declared are left out. Built with the arrow feature, batches convert to
+// Arrow record batches.
+
+use neb::ram::schema::{Field, Schema};
+use neb::ram::types::{Id, TypeId, Value, key_hash};
+use futures::prelude::*;
+
+use std::sync::Arc;
+
+use graph::{GraphInner, ScanVerticesError, ScanEdgesError};
+use graph::edge::Edge;
+use query::Expr;
+use server::schema::{SchemaType, ToSchemaId};
+
+#[cfg(feature = "arrow")]
+use arrow;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    Bool,
+    Int64,
+    UInt64,
+    Float64,
+    Utf8
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColumnData {
+    Bool(Vec<bool>),
+    Int64(Vec<i64>),
+    UInt64(Vec<u64>),
+    Float64(Vec<f64>),
+    Utf8(Vec<String>)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+    pub name: String,
+    pub data: ColumnData,
+    // false for nulls, their slot in data holds a default
+    pub valid: Vec<bool>
+}
+
+#[derive(Debug, Clone)]
+pub struct ColumnBatch {
+    pub schema_id: u32,
+    pub rows: usize,
+    pub columns: Vec<Column>
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ColumnLayout {
+    ids: Vec<String>,
+    // name, key and type of the declared fields that get a column
+    fields: Vec<(String, u64, ColumnType)>
+}
+
+fn column_type(field: &Field) -> Option<ColumnType> {
+    if field.is_array || field.sub_fields.is_some() || field.name.starts_with('_') { return None; }
+    let type_id = field.type_id;
+    let of = |types: &[u32]| types.contains(&type_id);
+    if of(&[TypeId::Bool as u32]) { Some(ColumnType::Bool) }
+    else if of(&[TypeId::I8 as u32, TypeId::I16 as u32, TypeId::I32 as u32, TypeId::I64 as u32]) { Some(ColumnType::Int64) }
+    else if of(&[TypeId::U8 as u32, TypeId::U16 as u32, TypeId::U32 as u32, TypeId::U64 as u32]) { Some(ColumnType::UInt64) }
+    else if of(&[TypeId::F32 as u32, TypeId::F64 as u32]) { Some(ColumnType::Float64) }
+    else if of(&[TypeId::String as u32]) { Some(ColumnType::Utf8) }
+    else { None }
+}
+
+impl ColumnLayout {
+    pub fn new(ids: &[&str], schema: &Schema) -> ColumnLayout {
+        let fields = match schema.fields.sub_fields {
+            Some(ref fields) => fields.iter()
+                .filter_map(|f| column_type(f).map(|t| (f.name.clone(), key_hash(&f.name), t)))
+                .collect(),
+            None => Vec::new()
+        };
+        ColumnLayout { ids: ids.iter().map(|id| id.to_string()).collect(), fields }
+    }
+}
+
+fn empty_column(name: String, column_type: ColumnType) -> Column {
+    let data = match column_type {
+        ColumnType::Bool => ColumnData::Bool(Vec::new()),
+        ColumnType::Int64 => ColumnData::Int64(Vec::new()),
+        ColumnType::UInt64 => ColumnData::UInt64(Vec::new()),
+        ColumnType::Float64 => ColumnData::Float64(Vec::new()),
+        ColumnType::Utf8 => ColumnData::Utf8(Vec::new())
+    };
+    Column { name, data, valid: Vec::new() }
+}
+
+impl Column {
+    pub fn column_type(&self) -> ColumnType {
+        match self.data {
+            ColumnData::Bool(_) => ColumnType::Bool,
+            ColumnData::Int64(_) => ColumnType::Int64,
+            ColumnData::UInt64(_) => ColumnType::UInt64,
+            ColumnData::Float64(_) => ColumnType::Float64,
+            ColumnData::Utf8(_) => ColumnType::Utf8
+        }
+    }
+
+    // values of other types than the column's go in as nulls
+    fn push(&mut self, value: &Value) {
+        let valid = match (&mut self.data, value) {
+            (&mut ColumnData::Bool(ref mut d), &Value::Bool(v)) => { d.push(v); true },
+            (&mut ColumnData::Int64(ref mut d), &Value::I8(v)) => { d.push(v as i64); true },
+            (&mut ColumnData::Int64(ref mut d), &Value::I16(v)) => { d.push(v as i64); true },
+            (&mut ColumnData::Int64(ref mut d), &Value::I32(v)) => { d.push(v as i64); true },
+            (&mut ColumnData::Int64(ref mut d), &Value::I64(v)) => { d.push(v); true },
+            (&mut ColumnData::UInt64(ref mut d), &Value::U8(v)) => { d.push(v as u64); true },
+            (&mut ColumnData::UInt64(ref mut d), &Value::U16(v)) => { d.push(v as u64); true },
+            (&mut ColumnData::UInt64(ref mut d), &Value::U32(v)) => { d.push(v as u64); true },
+            (&mut ColumnData::UInt64(ref mut d), &Value::U64(v)) => { d.push(v); true },
+            (&mut ColumnData::Float64(ref mut d), &Value::F32(v)) => { d.push(v as f64); true },
+            (&mut ColumnData::Float64(ref mut d), &Value::F64(v)) => { d.push(v); true },
+            (&mut ColumnData::Utf8(ref mut d), &Value::String(ref v)) => { d.push(v.clone()); true },
+            (&mut ColumnData::Bool(ref mut d), _) => { d.push(false); false },
+            (&mut ColumnData::Int64(ref mut d), _) => { d.push(0); false },
+            (&mut ColumnData::UInt64(ref mut d), _) => { d.push(0); false },
+            (&mut ColumnData::Float64(ref mut d), _) => { d.push(0.0); false },
+            (&mut ColumnData::Utf8(ref mut d), _) => { d.push(String::new()); false }
+        };
+        self.valid.push(valid);
+    }
+}
+
+impl ColumnBatch {
+    pub fn new(schema_id: u32, layout: &ColumnLayout) -> ColumnBatch {
+        let mut columns = Vec::new();
+        for id in &layout.ids {
+            columns.push(empty_column(format!("{}.higher", id), ColumnType::UInt64));
+            columns.push(empty_column(format!("{}.lower", id), ColumnType::UInt64));
+        }
+        for &(ref name, _, column_type) in &layout.fields {
+            columns.push(empty_column(name.clone(), column_type));
+        }
+        ColumnBatch { schema_id, rows: 0, columns }
+    }
+
+    pub fn column(&self, name: &str) -> Option<&Column> {
+        self.columns.iter().find(|c| c.name == name)
+    }
+
+    // one id per id column of the layout, data is the cell data the field columns are read from
+    fn push_row(&mut self, layout: &ColumnLayout, ids: &[Option<Id>], data: Option<&Value>) {
+        let mut columns = self.columns.iter_mut();
+        for id in ids {
+            let (higher, lower) = match id {
+                &Some(id) => (Value::U64(id.higher), Value::U64(id.lower)),
+                &None => (Value::Null, Value::Null)
+            };
+            columns.next().unwrap().push(&higher);
+            columns.next().unwrap().push(&lower);
+        }
+        for (column, &(_, key, _)) in columns.zip(layout.fields.iter()) {
+            match data {
+                Some(&Value::Map(ref map)) => column.push(map.get_by_key_id(key)),
+                _ => column.push(&Value::Null)
+            }
+        }
+        self.rows += 1;
+    }
+
+    #[cfg(feature = "arrow")]
+    pub fn to_record_batch(&self) -> arrow::record_batch::RecordBatch {
+        use arrow::array::{ArrayRef, BinaryBuilder, BooleanArray, Float64Array, Int64Array, UInt64Array};
+        use arrow::datatypes::{DataType, Field as ArrowField, Schema as ArrowSchema};
+        fn with_nulls<T: Copy>(values: &[T], valid: &[bool]) -> Vec<Option<T>> {
+            values.iter().zip(valid.iter()).map(|(v, ok)| if *ok { Some(*v) } else { None }).collect()
+        }
+        let mut fields = Vec::new();
+        let mut arrays: Vec<ArrayRef> = Vec::new();
+        for column in &self.columns {
+            let (data_type, array): (DataType, ArrayRef) = match column.data {
+                ColumnData::Bool(ref d) => (DataType::Boolean, Arc::new(BooleanArray::from(with_nulls(d, &column.valid)))),
+                ColumnData::Int64(ref d) => (DataType::Int64, Arc::new(Int64Array::from(with_nulls(d, &column.valid)))),
+                ColumnData::UInt64(ref d) => (DataType::UInt64, Arc::new(UInt64Array::from(with_nulls(d, &column.valid)))),
+                ColumnData::Float64(ref d) => (DataType::Float64, Arc::new(Float64Array::from(with_nulls(d, &column.valid)))),
+                ColumnData::Utf8(ref d) => {
+                    let mut builder = BinaryBuilder::new(d.len());
+                    for (value, ok) in d.iter().zip(column.valid.iter()) {
+                        if *ok { builder.append_string(value).unwrap(); } else { builder.append_null().unwrap(); }
+                    }
+                    (DataType::Utf8, Arc::new(builder.finish()))
+                }
+            };
+            fields.push(ArrowField::new(&column.name, data_type, true));
+            arrays.push(array);
+        }
+        arrow::record_batch::RecordBatch::new(Arc::new(ArrowSchema::new(fields)), arrays)
+    }
+}
+
+impl GraphInner {
+    fn layout(&self, schema_id: u32, ids: &[&str]) -> ColumnLayout {
+        self.schemas.get_neb_schema(schema_id)
+            .map(|schema| ColumnLayout::new(ids, &schema))
+            .unwrap_or_default()
+    }
+
+    pub fn scan_vertex_batches<S, F>(this: Arc<Self>, schema: S, filter: &Option<F>, rows: usize)
+        -> impl Stream<Item = ColumnBatch, Error = ScanVerticesError>
+        where S: ToSchemaId, F: Expr
+    {
+        let schema_id = schema.to_id(&this.schemas);
+        let layout = this.layout(schema_id, &["id"]);
+        Self::scan_vertices(this, schema_id, filter, None)
+            .chunks(::std::cmp::max(rows, 1))
+            .map(move |vertices| {
+                let mut batch = ColumnBatch::new(schema_id, &layout);
+                for vertex in &vertices {
+                    batch.push_row(&layout, &[Some(vertex.cell.id())], Some(&vertex.cell.data));
+                }
+                batch
+            })
+    }
+
+    pub fn scan_edge_batches<S, F>(this: Arc<Self>, schema: S, filter: &Option<F>, rows: usize)
+        -> impl Stream<Item = ColumnBatch, Error = ScanEdgesError>
+        where S: ToSchemaId, F: Expr
+    {
+        let schema_id = schema.to_id(&this.schemas);
+        let has_body = match this.schemas.schema_type(schema_id) {
+            Some(SchemaType::Edge(attrs)) => attrs.has_body, _ => false
+        };
+        let layout = if has_body { this.layout(schema_id, &["from", "to", "id"]) }
+            else { ColumnLayout { ids: vec!["from".to_string(), "to".to_string()], fields: Vec::new() } };
+        Self::scan_edges(this, schema_id, filter)
+            .chunks(::std::cmp::max(rows, 1))
+            .map(move |edges: Vec<Edge>| {
+                let mut batch = ColumnBatch::new(schema_id, &layout);
+                for edge in &edges {
+                    let (from, to) = edge.ends();
+                    let cell = edge.get_data();
+                    if has_body {
+                        let body_id = cell.as_ref().map(|c| c.id());
+                        batch.push_row(&layout, &[Some(*from), Some(*to), body_id], cell.as_ref().map(|c| &c.data));
+                    } else {
+                        batch.push_row(&layout, &[Some(*from), Some(*to)], None);
+                    }
+                }
+                batch
+            })
+    }
+}
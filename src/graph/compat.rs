@@ -0,0 +1,170 @@
+// The operations of graph::unified as std futures and streams, for async/await code and executors
+// that take std futures. Built with the std-future feature. Each call wraps the futures 0.1 future
+// of graph::unified, which still needs a task of futures 0.1 to run on; the wrapper provides one
+// when it is polled, so these can be awaited from any std executor. Neb's client threads drive
+// the RPCs underneath as before.
+
+use neb::ram::types::Id;
+use neb::dovahkiin::types::{Map, ToValue};
+use futures_util::compat::{Future01CompatExt, Stream01CompatExt};
+use futures_util::stream::Stream;
+
+use std::future::Future;
+
+use error;
+use graph::{self as graph_mod, EdgeDirection, CountMode, CountEstimate};
+use graph::unified;
+use graph::vertex::{Vertex, ToVertexId};
+use graph::edge::{Edge, EdgeAttributes};
+use graph::subgraph::Subgraph;
+use graph::traverse::{TraverseOptions, Neighbour};
+use graph::tree::Tree;
+use server::schema::{MorpheusSchema, ToSchemaId};
+use server::path_pattern::Path;
+use query::Expr;
+
+pub struct Graph {
+    graph: unified::Graph
+}
+
+impl Graph {
+    pub fn new(graph: &graph_mod::Graph) -> Graph {
+        Graph { graph: unified::Graph::new(graph) }
+    }
+    // the futures 0.1 graph, for what is not wrapped here
+    pub fn graph(&self) -> &graph_mod::Graph {
+        self.graph.graph()
+    }
+
+    pub fn new_vertex_group(&self, schema: MorpheusSchema) -> impl Future<Output = error::Result<u32>> {
+        self.graph.new_vertex_group(schema).compat()
+    }
+    pub fn new_edge_group(&self, schema: MorpheusSchema, edge_attrs: EdgeAttributes)
+        -> impl Future<Output = error::Result<u32>>
+    {
+        self.graph.new_edge_group(schema, edge_attrs).compat()
+    }
+
+    pub fn new_vertex<S>(&self, schema: S, data: Map) -> impl Future<Output = error::Result<Vertex>> where S: ToSchemaId {
+        self.graph.new_vertex(schema, data).compat()
+    }
+    pub fn vertex_by<V>(&self, vertex: V) -> impl Future<Output = error::Result<Option<Vertex>>> where V: ToVertexId {
+        self.graph.vertex_by(vertex).compat()
+    }
+    pub fn vertex_by_key<K, S>(&self, schema: S, key: K) -> impl Future<Output = error::Result<Option<Vertex>>>
+        where K: ToValue, S: ToSchemaId
+    {
+        self.graph.vertex_by_key(schema, key).compat()
+    }
+    pub fn vertices_by<V>(&self, vertices: Vec<V>) -> impl Future<Output = error::Result<Vec<Option<Vertex>>>>
+        where V: ToVertexId
+    {
+        self.graph.vertices_by(vertices).compat()
+    }
+    pub fn vertex_exists<V>(&self, vertex: V) -> impl Future<Output = error::Result<bool>> where V: ToVertexId {
+        self.graph.vertex_exists(vertex).compat()
+    }
+    pub fn update_vertex<V, U>(&self, vertex: V, update: U) -> impl Future<Output = error::Result<()>>
+        where V: ToVertexId, U: Fn(Vertex) -> Option<Vertex>, U: 'static
+    {
+        self.graph.update_vertex(vertex, update).compat()
+    }
+    pub fn update_vertex_by_key<K, U, S>(&self, schema: S, key: K, update: U) -> impl Future<Output = error::Result<()>>
+        where K: ToValue, S: ToSchemaId, U: Fn(Vertex) -> Option<Vertex>, U: 'static
+    {
+        self.graph.update_vertex_by_key(schema, key, update).compat()
+    }
+    pub fn remove_vertex<V>(&self, vertex: V) -> impl Future<Output = error::Result<()>> where V: ToVertexId {
+        self.graph.remove_vertex(vertex).compat()
+    }
+    pub fn remove_vertex_by_key<K, S>(&self, schema: S, key: K) -> impl Future<Output = error::Result<()>>
+        where K: ToValue, S: ToSchemaId
+    {
+        self.graph.remove_vertex_by_key(schema, key).compat()
+    }
+
+    pub fn link<V, S>(&self, from: V, schema: S, to: V, body: Option<Map>) -> impl Future<Output = error::Result<Edge>>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        self.graph.link(from, schema, to, body).compat()
+    }
+    pub fn has_edge<V, S>(&self, from: V, schema: S, to: V) -> impl Future<Output = error::Result<bool>>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        self.graph.has_edge(from, schema, to).compat()
+    }
+    pub fn degree<V, S>(&self, vertex: V, schema: S, direction: EdgeDirection) -> impl Future<Output = error::Result<usize>>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        self.graph.degree(vertex, schema, direction).compat()
+    }
+    pub fn edges<V, S, F>(&self, vertex: V, schema: S, direction: EdgeDirection, filter: &Option<F>)
+        -> impl Future<Output = error::Result<Vec<Edge>>>
+        where V: ToVertexId, S: ToSchemaId, F: Expr
+    {
+        self.graph.edges(vertex, schema, direction, filter).compat()
+    }
+    pub fn neighbourhoods<V, S, F>(&self, vertex: V, schema: S, direction: EdgeDirection, filter: &Option<F>)
+        -> impl Future<Output = error::Result<Vec<(Vertex, Edge)>>>
+        where V: ToVertexId, S: ToSchemaId, F: Expr
+    {
+        self.graph.neighbourhoods(vertex, schema, direction, filter).compat()
+    }
+    pub fn traverse_neighbours<V>(&self, vertex: V, options: TraverseOptions)
+        -> impl Future<Output = error::Result<Vec<Neighbour>>>
+        where V: ToVertexId
+    {
+        self.graph.traverse_neighbours(vertex, options).compat()
+    }
+    pub fn subgraph<V, S, F>(&self, start: Vec<V>, edge_schemas: Vec<S>, depth: usize, filter: &Option<F>)
+        -> impl Future<Output = error::Result<Subgraph>>
+        where V: ToVertexId, S: ToSchemaId, F: Expr
+    {
+        self.graph.subgraph(start, edge_schemas, depth, filter).compat()
+    }
+    pub fn reachable_path<V, S>(&self, from: V, to: V, edge_schemas: Vec<S>, max_depth: usize)
+        -> impl Future<Output = error::Result<Option<Vec<Id>>>>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        self.graph.reachable_path(from, to, edge_schemas, max_depth).compat()
+    }
+    pub fn k_shortest_paths<V, S>(&self, from: V, to: V, edge_schema: S, k: usize, weight_field: Option<&str>)
+        -> impl Future<Output = error::Result<Vec<Path>>>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        self.graph.k_shortest_paths(from, to, edge_schema, k, weight_field).compat()
+    }
+    pub fn descendants<V, S>(&self, root: V, edge_schema: S, max_depth: usize) -> impl Future<Output = error::Result<Tree>>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        self.graph.descendants(root, edge_schema, max_depth).compat()
+    }
+    pub fn ancestors<V, S>(&self, vertex: V, edge_schema: S, max_depth: usize) -> impl Future<Output = error::Result<Tree>>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        self.graph.ancestors(vertex, edge_schema, max_depth).compat()
+    }
+
+    pub fn scan_vertices<S, F>(&self, schema: S, filter: &Option<F>, projection: Option<Vec<String>>)
+        -> impl Stream<Item = error::Result<Vertex>>
+        where S: ToSchemaId, F: Expr
+    {
+        self.graph.scan_vertices(schema, filter, projection).compat()
+    }
+    pub fn scan_edges<S, F>(&self, schema: S, filter: &Option<F>) -> impl Stream<Item = error::Result<Edge>>
+        where S: ToSchemaId, F: Expr
+    {
+        self.graph.scan_edges(schema, filter).compat()
+    }
+    pub fn count_vertices<S, F>(&self, schema: S, filter: &Option<F>, mode: CountMode)
+        -> impl Future<Output = error::Result<CountEstimate>>
+        where S: ToSchemaId, F: Expr
+    {
+        self.graph.count_vertices(schema, filter, mode).compat()
+    }
+    pub fn count_edges<S>(&self, schema: S, mode: CountMode) -> impl Future<Output = error::Result<CountEstimate>>
+        where S: ToSchemaId
+    {
+        self.graph.count_edges(schema, mode).compat()
+    }
+}
@@ -0,0 +1,119 @@
+// Computed fields are lisp expressions over the vertex, declared on its schema. The expression sees
+// the vertex data as `vertex` and the metadata symbols filters have: `vertex_id`, and `in_degree` and
+// `out_degree` summed over every edge schema, undirected edges counting for both.
+// Virtual fields are evaluated when the vertex is read and never stored. A field that fails to
+// evaluate reads as null. Reads outside a transaction have no degrees, those bind to null.
+// Materialized fields are declared schema fields written with the vertex, refreshed by vertex
+// writes only. Linking does not rewrite the vertex, so degree based values belong in virtual fields.
+// New vertices have no id yet when theirs are computed, vertex_id is null and the degrees are 0.
+
+use neb::ram::types::Id;
+use neb::dovahkiin::types::{Map, Value};
+use neb::client::transaction::TxnError;
+
+use graph::{GraphTransaction, EdgeDirection};
+use graph::vertex::Vertex;
+use query::{Expr, Tester, FilterContext};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComputedMode {
+    Virtual,
+    Materialized
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ComputedField {
+    pub name: String,
+    pub expr: String,
+    pub mode: ComputedMode
+}
+
+impl ComputedField {
+    pub fn new(name: &str, expr: &str, mode: ComputedMode) -> ComputedField {
+        ComputedField { name: name.to_string(), expr: expr.to_string(), mode }
+    }
+    // counting degrees reads every edge list of the vertex, skip it when the expression cannot use them
+    fn uses_degrees(&self) -> bool {
+        self.expr.contains("in_degree") || self.expr.contains("out_degree")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ComputedFieldError {
+    pub field: String,
+    pub message: String
+}
+
+pub fn has_mode(fields: &[ComputedField], mode: ComputedMode) -> bool {
+    fields.iter().any(|f| f.mode == mode)
+}
+
+fn evaluate_field(field: &ComputedField, data: &Map, context: &FilterContext) -> Result<Value, ComputedFieldError> {
+    field.expr.to_sexpr()
+        .and_then(|sexpr| Tester::eval_value(&sexpr, Value::Map(data.clone()), context))
+        .map_err(|message| ComputedFieldError { field: field.name.clone(), message })
+}
+
+// Evaluates the fields of the mode in declaration order, so later ones can read earlier ones
+pub fn evaluate(fields: &[ComputedField], mode: ComputedMode, data: &mut Map, context: &FilterContext)
+    -> Result<(), ComputedFieldError>
+{
+    for field in fields.iter().filter(|f| f.mode == mode) {
+        let value = evaluate_field(field, data, context)?;
+        data.insert(&field.name, value);
+    }
+    Ok(())
+}
+
+fn evaluate_virtual_in_context(fields: &[ComputedField], vertex: &mut Vertex, context: &FilterContext) {
+    let id = vertex.cell.id();
+    if let Value::Map(ref mut data) = vertex.cell.data {
+        for field in fields.iter().filter(|f| f.mode == ComputedMode::Virtual) {
+            let value = evaluate_field(field, data, context).unwrap_or_else(|e| {
+                warn!("Computed field {} of {:?} failed: {}", e.field, id, e.message);
+                Value::Null
+            });
+            data.insert(&field.name, value);
+        }
+    }
+}
+
+// virtual fields of a vertex read outside a transaction
+pub fn evaluate_virtual(fields: &[ComputedField], vertex: &mut Vertex) {
+    let context = FilterContext { vertex_id: Some(vertex.cell.id()), ..FilterContext::default() };
+    evaluate_virtual_in_context(fields, vertex, &context)
+}
+
+impl <'a> GraphTransaction<'a> {
+    // Context for the computed fields of the vertex, degrees are left out unless one of them uses them
+    pub(super) fn computed_context(&self, id: Id, fields: &[ComputedField], mode: ComputedMode)
+        -> Result<FilterContext, TxnError>
+    {
+        let mut context = FilterContext { vertex_id: Some(id), ..FilterContext::default() };
+        if !fields.iter().any(|f| f.mode == mode && f.uses_degrees()) {
+            return Ok(context);
+        }
+        let (mut in_degree, mut out_degree) = (0, 0);
+        if let Ok(incident) = self.incident_edge_schemas(id)? {
+            for schema in incident {
+                match schema.direction {
+                    EdgeDirection::Inbound => in_degree += schema.count,
+                    EdgeDirection::Outbound => out_degree += schema.count,
+                    _ => { in_degree += schema.count; out_degree += schema.count; }
+                }
+            }
+        }
+        context.in_degree = Some(in_degree);
+        context.out_degree = Some(out_degree);
+        Ok(context)
+    }
+
+    pub(super) fn with_virtual_fields(&self, mut vertex: Vertex) -> Result<Vertex, TxnError> {
+        let fields = self.schemas.computed(vertex.schema());
+        if has_mode(&fields, ComputedMode::Virtual) {
+            let context = self.computed_context(vertex.cell.id(), &fields, ComputedMode::Virtual)?;
+            evaluate_virtual_in_context(&fields, &mut vertex, &context);
+        }
+        Ok(vertex)
+    }
+}
@@ -0,0 +1,127 @@
+// Graphviz DOT of a subgraph, for debugging and documentation. Vertices are labelled by the first
+// of the style's vertex fields they have, edges by the first of its edge fields their body has,
+// falling back to the schema name and, for vertices, the id. Everything goes in one digraph,
+// undirected edges are drawn without arrows.
+
+use neb::ram::types::Id;
+use neb::dovahkiin::types::Value;
+
+use std::fmt::Write;
+
+use graph::subgraph::Subgraph;
+use graph::edge::Edge;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DotStyle {
+    pub name: String,
+    // tried in order, the first field a vertex has labels it
+    #[serde(default)]
+    pub vertex_fields: Vec<String>,
+    #[serde(default)]
+    pub edge_fields: Vec<String>,
+    // prefixes labels with the schema name
+    #[serde(default)]
+    pub show_schema: bool,
+    // LR draws left to right, TB top to bottom as Graphviz does by default
+    #[serde(default)]
+    pub rank_dir: Option<String>
+}
+
+impl Default for DotStyle {
+    fn default() -> DotStyle {
+        DotStyle {
+            name: "morpheus".to_string(),
+            vertex_fields: Vec::new(),
+            edge_fields: Vec::new(),
+            show_schema: false,
+            rank_dir: None
+        }
+    }
+}
+
+impl DotStyle {
+    pub fn with_vertex_field(mut self, field: &str) -> DotStyle {
+        self.vertex_fields.push(field.to_string());
+        self
+    }
+    pub fn with_edge_field(mut self, field: &str) -> DotStyle {
+        self.edge_fields.push(field.to_string());
+        self
+    }
+    pub fn with_schema(mut self, show_schema: bool) -> DotStyle {
+        self.show_schema = show_schema;
+        self
+    }
+    pub fn with_rank_dir(mut self, rank_dir: &str) -> DotStyle {
+        self.rank_dir = Some(rank_dir.to_string());
+        self
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn value_text(value: &Value) -> Option<String> {
+    Some(match value {
+        &Value::Null => return None,
+        &Value::String(ref s) => s.clone(),
+        &Value::Bool(v) => v.to_string(),
+        &Value::U8(v) => v.to_string(),
+        &Value::U16(v) => v.to_string(),
+        &Value::U32(v) => v.to_string(),
+        &Value::U64(v) => v.to_string(),
+        &Value::I8(v) => v.to_string(),
+        &Value::I16(v) => v.to_string(),
+        &Value::I32(v) => v.to_string(),
+        &Value::I64(v) => v.to_string(),
+        &Value::F32(v) => v.to_string(),
+        &Value::F64(v) => v.to_string(),
+        other => format!("{:?}", other)
+    })
+}
+
+fn first_field(data: &Value, fields: &[String]) -> Option<String> {
+    fields.iter().filter_map(|field| value_text(&data[field.as_str()])).next()
+}
+
+fn label(schema: Option<String>, text: Option<String>, show_schema: bool) -> String {
+    match (schema, text) {
+        (Some(schema), Some(text)) => if show_schema { format!("{}: {}", schema, text) } else { text },
+        (None, Some(text)) => text,
+        (schema, None) => schema.unwrap_or_default()
+    }
+}
+
+fn id_text(id: &Id) -> String {
+    format!("{}:{}", id.higher, id.lower)
+}
+
+fn edge_label(edge: &Edge, schema_name: Option<String>, style: &DotStyle) -> String {
+    let text = edge.get_data().as_ref().and_then(|cell| first_field(&cell.data, &style.edge_fields));
+    label(schema_name, text, style.show_schema)
+}
+
+// schema_name resolves schema ids to names, unknown schemas leave labels without them
+pub fn to_dot<N>(subgraph: &Subgraph, style: &DotStyle, schema_name: N) -> String
+    where N: Fn(u32) -> Option<String>
+{
+    let mut dot = String::new();
+    let _ = writeln!(dot, "digraph \"{}\" {{", escape(&style.name));
+    if let Some(ref rank_dir) = style.rank_dir {
+        let _ = writeln!(dot, "  rankdir={};", rank_dir);
+    }
+    for (index, vertex) in subgraph.vertices.iter().enumerate() {
+        let id = vertex.cell.id();
+        let text = first_field(&vertex.cell.data, &style.vertex_fields).unwrap_or_else(|| id_text(&id));
+        let text = label(schema_name(vertex.schema()), Some(text), style.show_schema);
+        let _ = writeln!(dot, "  v{} [label=\"{}\", tooltip=\"{}\"];", index, escape(&text), id_text(&id));
+    }
+    for edge in &subgraph.edges {
+        let text = edge_label(&edge.edge, schema_name(edge.schema), style);
+        let arrow = match edge.edge { Edge::Undirected(_) => ", dir=none", _ => "" };
+        let _ = writeln!(dot, "  v{} -> v{} [label=\"{}\"{}];", edge.from, edge.to, escape(&text), arrow);
+    }
+    dot.push_str("}\n");
+    dot
+}
@@ -7,6 +7,7 @@ use std::sync::Arc;
 
 use super::{TEdge, EdgeError};
 use super::super::id_list::IdList;
+use graph::encryption;
 use server::schema::{SchemaContainer, SchemaType};
 
 
@@ -25,6 +26,17 @@ pub trait BilateralEdge : TEdge {
     fn edge_cell(&self) -> &Option<Cell>;
     fn schema_id(&self) -> u32;
 
+    fn from_cell(cell: Cell, schema_id: u32) -> Result<Self::Edge, EdgeError> {
+        let ends = match (&cell.data[Self::edge_a_field()], &cell.data[Self::edge_b_field()]) {
+            (&Value::Id(a_id), &Value::Id(b_id)) => Some((a_id, b_id)),
+            _ => None
+        };
+        match ends {
+            Some((a_id, b_id)) => Ok(Self::build_edge(a_id, b_id, schema_id, Some(cell))),
+            None => Err(EdgeError::WrongSchema)
+        }
+    }
+
     fn from_id(
         vertex_id: &Id, vertex_field: u64,
         schema_id: u32, schemas: &Arc<SchemaContainer>, txn: &Transaction, id: &Id
@@ -33,8 +45,15 @@ pub trait BilateralEdge : TEdge {
             Some(cell) => cell,
             None => return Ok(Err(EdgeError::CellNotFound))
         };
+        Ok(Self::from_trace_cell(vertex_id, vertex_field, schema_id, schemas, id, trace_cell))
+    }
+    // trace_cell is the cell behind an id list entry, the opposite vertex or the edge body
+    fn from_trace_cell(
+        vertex_id: &Id, vertex_field: u64,
+        schema_id: u32, schemas: &Arc<SchemaContainer>, id: &Id, mut trace_cell: Cell
+    ) -> Result<Self::Edge, EdgeError> {
         let cell_schema_type = match schemas.schema_type(trace_cell.header.schema) {
-            Some(t) => t, None => return Ok(Err(EdgeError::CannotFindSchema))
+            Some(t) => t, None => return Err(EdgeError::CannotFindSchema)
         };
         let mut a_id = Id::unit_id();
         let mut b_id = Id::unit_id();
@@ -47,7 +66,7 @@ pub trait BilateralEdge : TEdge {
                     b_id = *vertex_id;
                     a_id = *id;
                 } else {
-                    return Ok(Err(EdgeError::WrongVertexField));
+                    return Err(EdgeError::WrongVertexField);
                 }
                 None
             },
@@ -63,14 +82,15 @@ pub trait BilateralEdge : TEdge {
                         a_id = e_a_id;
                         b_id = e_b_id;
                     }
+                    encryption::open_cell(schemas, &mut trace_cell);
                     Some(trace_cell)
                 } else {
-                    return Ok(Err(EdgeError::WrongEdgeType))
+                    return Err(EdgeError::WrongEdgeType)
                 }
             },
-            _ => return Ok(Err(EdgeError::WrongSchema))
+            _ => return Err(EdgeError::WrongSchema)
         };
-        Ok(Ok(Self::build_edge(a_id, b_id, schema_id, edge_cell)))
+        Ok(Self::build_edge(a_id, b_id, schema_id, edge_cell))
     }
     fn link(
         vertex_a_id: &Id, vertex_b_id: &Id, body: Option<Map>,
@@ -79,51 +99,105 @@ pub trait BilateralEdge : TEdge {
     ) -> Result<Result<Self::Edge, EdgeError>, TxnError> {
         let mut vertex_a_pointer = Id::unit_id();
         let mut vertex_b_pointer = Id::unit_id();
+        let edge_attrs = match schemas.schema_type(schema_id) {
+            Some(SchemaType::Edge(ea)) => ea,
+            Some(_) => return Ok(Err(EdgeError::WrongSchema)),
+            None => return Ok(Err(EdgeError::CannotFindSchema))
+        };
+        if edge_attrs.edge_type != Self::edge_type() { return Ok(Err(EdgeError::WrongEdgeType)); }
         let edge_cell = {
-            match schemas.schema_type(schema_id) {
-                Some(SchemaType::Edge(ea)) => {
-                    if ea.edge_type != Self::edge_type() { return Ok(Err(EdgeError::WrongEdgeType)); }
-                    if ea.has_body {
-                        if let Some(body_map) = body {
-                            let mut edge_body_cell = Cell::new_with_id(
-                                schema_id,
-                                &Id::new(vertex_a_id.higher, rand::next()),
-                                Value::Map(body_map)
-                            );
-                            edge_body_cell.data[Self::edge_a_field()] = Value::Id(*vertex_a_id);
-                            edge_body_cell.data[Self::edge_b_field()] = Value::Id(*vertex_b_id);
-                            txn.write(&edge_body_cell)?;
-                            vertex_a_pointer = edge_body_cell.id();
-                            vertex_b_pointer = edge_body_cell.id();
-                            Some(edge_body_cell)
-                        } else {
-                            return Ok(Err(EdgeError::NormalEdgeShouldHaveBody));
-                        }
-                    } else {
-                        if body.is_none() {
-                            vertex_a_pointer = *vertex_b_id;
-                            vertex_b_pointer = *vertex_a_id;
-                            None
-                        } else {
-                            return Ok(Err(EdgeError::SimpleEdgeShouldNotHaveBody));
-                        }
+            if edge_attrs.has_body {
+                if let Some(mut body_map) = body {
+                    if let Err(e) = encryption::seal_fields(schemas, schema_id, &mut body_map) {
+                        return Ok(Err(EdgeError::EncryptionError(e)));
                     }
-                },
-                Some(_) => return Ok(Err(EdgeError::WrongSchema)),
-                None => return Ok(Err(EdgeError::CannotFindSchema))
+                    let mut edge_body_cell = Cell::new_with_id(
+                        schema_id,
+                        &Id::new(vertex_a_id.higher, rand::next()),
+                        Value::Map(body_map)
+                    );
+                    edge_body_cell.data[Self::edge_a_field()] = Value::Id(*vertex_a_id);
+                    edge_body_cell.data[Self::edge_b_field()] = Value::Id(*vertex_b_id);
+                    txn.write(&edge_body_cell)?;
+                    vertex_a_pointer = edge_body_cell.id();
+                    vertex_b_pointer = edge_body_cell.id();
+                    Some(edge_body_cell)
+                } else {
+                    return Ok(Err(EdgeError::NormalEdgeShouldHaveBody));
+                }
+            } else {
+                if body.is_none() {
+                    vertex_a_pointer = *vertex_b_id;
+                    vertex_b_pointer = *vertex_a_id;
+                    None
+                } else {
+                    return Ok(Err(EdgeError::SimpleEdgeShouldNotHaveBody));
+                }
             }
         };
+        let shared = Self::is_shared_entry(vertex_a_id, vertex_b_id);
+        if edge_cell.is_some() { // index before the lists change, a legacy list is indexed as it was
+            let mut a_index = match Self::opposite_index(txn, vertex_a_id, Self::vertex_a_field(), schema_id)? {
+                Ok(index) => index, Err(e) => return Ok(Err(e))
+            };
+            if !shared {
+                let mut b_index = match Self::opposite_index(txn, vertex_b_id, Self::vertex_b_field(), schema_id)? {
+                    Ok(index) => index, Err(e) => return Ok(Err(e))
+                };
+                if let Err(e) = b_index.add(vertex_a_id)? { return Ok(Err(EdgeError::IdListError(e))); }
+            }
+            if let Err(e) = a_index.add(vertex_b_id)? { return Ok(Err(EdgeError::IdListError(e))); }
+        }
         match IdList::from_txn_and_container(txn, vertex_a_id, Self::vertex_a_field(), schema_id)
             .add(&vertex_a_pointer)?.map_err(EdgeError::IdListError) {
             Err(e) => return Ok(Err(e)), _ => {}
         }
-        match IdList::from_txn_and_container(txn, vertex_b_id, Self::vertex_b_field(), schema_id)
-            .add(&vertex_b_pointer)?.map_err(EdgeError::IdListError) {
-            Err(e) => return Ok(Err(e)), _ => {}
+        let mut vertex_b_list = IdList::from_txn_and_container(txn, vertex_b_id, Self::vertex_b_field(), schema_id);
+        if shared {
+            // self-loop in a shared list, keep a single entry
+            if edge_attrs.self_loops_count_twice {
+                match vertex_b_list.adjust_count(1)?.map_err(EdgeError::IdListError) {
+                    Err(e) => return Ok(Err(e)), _ => {}
+                }
+            }
+        } else {
+            match vertex_b_list.add(&vertex_b_pointer)?.map_err(EdgeError::IdListError) {
+                Err(e) => return Ok(Err(e)), _ => {}
+            }
         }
         Ok(Ok(Self::build_edge(*vertex_a_id, *vertex_b_id, schema_id, edge_cell)))
     }
-    fn remove(&mut self, txn: &Transaction) -> Result<Result<(), EdgeError>, TxnError> {
+    // Opposite vertex index of a bodied edge list. A list written before the index existed is indexed
+    // from its edge bodies the first time one of its edges changes
+    fn opposite_index<'a>(txn: &'a Transaction, vertex_id: &Id, vertex_field: u64, schema_id: u32)
+        -> Result<Result<IdList<'a>, EdgeError>, TxnError>
+    {
+        let mut index = IdList::opposite_index(txn, vertex_id, vertex_field, schema_id);
+        match index.is_assigned()? {
+            Ok(true) => return Ok(Ok(index)), Ok(false) => {},
+            Err(e) => return Ok(Err(EdgeError::IdListError(e)))
+        }
+        let edge_ids = match IdList::from_txn_and_container(txn, vertex_id, vertex_field, schema_id).all()? {
+            Ok(ids) => ids, Err(e) => return Ok(Err(EdgeError::IdListError(e)))
+        };
+        let end_fields = vec![Self::edge_a_field(), Self::edge_b_field()];
+        for edge_id in edge_ids {
+            let ends = match txn.read_selected(&edge_id, &end_fields)? {
+                Some(ends) => ends, None => return Ok(Err(EdgeError::CellNotFound))
+            };
+            let opposite = match (ends.get(0), ends.get(1)) {
+                (Some(&Value::Id(a)), Some(&Value::Id(b))) =>
+                    if vertex_field == Self::vertex_a_field() && &a == vertex_id { b } else { a },
+                _ => return Ok(Err(EdgeError::WrongSchema))
+            };
+            if let Err(e) = index.add(&opposite)? { return Ok(Err(EdgeError::IdListError(e))); }
+        }
+        Ok(Ok(index))
+    }
+    fn is_shared_entry(vertex_a_id: &Id, vertex_b_id: &Id) -> bool {
+        vertex_a_id == vertex_b_id && Self::vertex_a_field() == Self::vertex_b_field()
+    }
+    fn remove(&mut self, txn: &Transaction, schemas: &Arc<SchemaContainer>) -> Result<Result<(), EdgeError>, TxnError> {
         let (v_a_removal, v_b_removal) = match self.edge_cell() {
             &Some(ref cell) => {
                 txn.remove(&cell.id())?;
@@ -133,16 +207,94 @@ pub trait BilateralEdge : TEdge {
                 (*self.vertex_b(), *self.vertex_a())
             }
         };
+        let shared = Self::is_shared_entry(self.vertex_a(), self.vertex_b());
+        if self.edge_cell().is_some() {
+            let mut a_index = match Self::opposite_index(txn, self.vertex_a(), Self::vertex_a_field(), self.schema_id())? {
+                Ok(index) => index, Err(e) => return Ok(Err(e))
+            };
+            if !shared {
+                let mut b_index = match Self::opposite_index(txn, self.vertex_b(), Self::vertex_b_field(), self.schema_id())? {
+                    Ok(index) => index, Err(e) => return Ok(Err(e))
+                };
+                if let Err(e) = b_index.remove(self.vertex_a(), false)? { return Ok(Err(EdgeError::IdListError(e))); }
+            }
+            if let Err(e) = a_index.remove(self.vertex_b(), false)? { return Ok(Err(EdgeError::IdListError(e))); }
+        }
         match IdList::from_txn_and_container(txn, self.vertex_a(), Self::vertex_a_field(), self.schema_id())
             .remove(&v_a_removal, false)?.map_err(EdgeError::IdListError) {
             Err(e) => return Ok(Err(e)), _ => {}
         }
-        match IdList::from_txn_and_container(txn, self.vertex_b(), Self::vertex_b_field(), self.schema_id())
-            .remove(&v_b_removal, false)?.map_err(EdgeError::IdListError) {
-            Err(e) => return Ok(Err(e)), _ => {}
+        let mut vertex_b_list = IdList::from_txn_and_container(txn, self.vertex_b(), Self::vertex_b_field(), self.schema_id());
+        if shared {
+            let count_twice = match schemas.schema_type(self.schema_id()) {
+                Some(SchemaType::Edge(ea)) => ea.self_loops_count_twice,
+                _ => return Ok(Err(EdgeError::CannotFindSchema))
+            };
+            if count_twice {
+                match vertex_b_list.adjust_count(-1)?.map_err(EdgeError::IdListError) {
+                    Err(e) => return Ok(Err(e)), _ => {}
+                }
+            }
+        } else {
+            match vertex_b_list.remove(&v_b_removal, false)?.map_err(EdgeError::IdListError) {
+                Err(e) => return Ok(Err(e)), _ => {}
+            }
         }
         Ok(Ok(()))
     }
+    // Moves the b end onto another vertex. Simple edges are relinked, bodied edges keep their body cell,
+    // its b end and the entries in the lists of the old and the new b vertex change, the a list stays.
+    fn rewire(&mut self, new_b_id: &Id, txn: &Transaction, schemas: &Arc<SchemaContainer>)
+        -> Result<Result<Self::Edge, EdgeError>, TxnError>
+    {
+        let (vertex_a_id, old_b_id) = (*self.vertex_a(), *self.vertex_b());
+        if self.edge_cell().is_none() {
+            if let Err(e) = self.remove(txn, schemas)? { return Ok(Err(e)); }
+            return Self::link(&vertex_a_id, new_b_id, None, txn, self.schema_id(), schemas);
+        }
+        let mut cell = self.edge_cell().clone().unwrap();
+        let count_twice = match schemas.schema_type(self.schema_id()) {
+            Some(SchemaType::Edge(ea)) => ea.self_loops_count_twice,
+            _ => return Ok(Err(EdgeError::CannotFindSchema))
+        };
+        let body_id = cell.id();
+        let old_shared = Self::is_shared_entry(&vertex_a_id, &old_b_id);
+        let new_shared = Self::is_shared_entry(&vertex_a_id, new_b_id);
+        let mut a_index = match Self::opposite_index(txn, &vertex_a_id, Self::vertex_a_field(), self.schema_id())? {
+            Ok(index) => index, Err(e) => return Ok(Err(e))
+        };
+        if !old_shared {
+            let mut old_b_index = match Self::opposite_index(txn, &old_b_id, Self::vertex_b_field(), self.schema_id())? {
+                Ok(index) => index, Err(e) => return Ok(Err(e))
+            };
+            if let Err(e) = old_b_index.remove(&vertex_a_id, false)? { return Ok(Err(EdgeError::IdListError(e))); }
+        }
+        if !new_shared {
+            let mut new_b_index = match Self::opposite_index(txn, new_b_id, Self::vertex_b_field(), self.schema_id())? {
+                Ok(index) => index, Err(e) => return Ok(Err(e))
+            };
+            if let Err(e) = new_b_index.add(&vertex_a_id)? { return Ok(Err(EdgeError::IdListError(e))); }
+        }
+        if let Err(e) = a_index.remove(&old_b_id, false)? { return Ok(Err(EdgeError::IdListError(e))); }
+        if let Err(e) = a_index.add(new_b_id)? { return Ok(Err(EdgeError::IdListError(e))); }
+        let mut old_b_list = IdList::from_txn_and_container(txn, &old_b_id, Self::vertex_b_field(), self.schema_id());
+        let removed = if old_shared {
+            if count_twice { old_b_list.adjust_count(-1)? } else { Ok(()) }
+        } else {
+            old_b_list.remove(&body_id, false)?
+        };
+        if let Err(e) = removed { return Ok(Err(EdgeError::IdListError(e))); }
+        let mut new_b_list = IdList::from_txn_and_container(txn, new_b_id, Self::vertex_b_field(), self.schema_id());
+        let added = if new_shared {
+            if count_twice { new_b_list.adjust_count(1)? } else { Ok(()) }
+        } else {
+            new_b_list.add(&body_id)?
+        };
+        if let Err(e) = added { return Ok(Err(EdgeError::IdListError(e))); }
+        cell.data[Self::edge_b_field()] = Value::Id(*new_b_id);
+        txn.update(&cell)?;
+        Ok(Ok(Self::build_edge(vertex_a_id, *new_b_id, self.schema_id(), Some(cell))))
+    }
     fn oppisite_vertex_id(&self, vertex_id: &Id) -> Option<&Id> {
         let v1_id = self.vertex_a();
         let v2_id = self.vertex_b();
@@ -18,7 +18,7 @@ lazy_static! {
         ];
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DirectedEdge {
     inbound_id: Id,
     outbound_id: Id,
@@ -14,6 +14,8 @@ use neb::dovahkiin::types::Value;
 use graph::edge::bilateral::BilateralEdge;
 use server::schema::{SchemaContainer, SchemaType};
 use super::id_list::IdListError;
+use graph::result_limit::Overflow;
+use graph::encryption::{self, EncryptionError};
 use std::sync::Arc;
 
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy)]
@@ -25,16 +27,36 @@ pub enum EdgeType {
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy)]
 pub struct EdgeAttributes {
     pub edge_type: EdgeType,
-    pub has_body: bool
+    pub has_body: bool,
+    pub unique_pairs: bool,
+    pub allow_self_loops: bool,
+    pub self_loops_count_twice: bool
 }
 
 impl EdgeAttributes {
     pub fn new(edge_type: EdgeType, has_body: bool) -> EdgeAttributes {
         EdgeAttributes {
             edge_type: edge_type,
-            has_body: has_body
+            has_body: has_body,
+            unique_pairs: false,
+            allow_self_loops: true,
+            self_loops_count_twice: true
         }
     }
+    // at most one edge of the schema between the same pair of vertices
+    pub fn with_unique_pairs(mut self, unique_pairs: bool) -> EdgeAttributes {
+        self.unique_pairs = unique_pairs;
+        self
+    }
+    pub fn with_self_loops(mut self, allow_self_loops: bool) -> EdgeAttributes {
+        self.allow_self_loops = allow_self_loops;
+        self
+    }
+    // an undirected self-loop is stored once, this decides whether it adds one or two to the degree
+    pub fn with_self_loops_count_twice(mut self, count_twice: bool) -> EdgeAttributes {
+        self.self_loops_count_twice = count_twice;
+        self
+    }
 }
 
 #[derive(Debug)]
@@ -47,7 +69,12 @@ pub enum EdgeError {
     IdListError(IdListError),
     SimpleEdgeShouldNotHaveBody,
     NormalEdgeShouldHaveBody,
-    FilterEvalError(String)
+    DuplicateEdge,
+    FilterEvalError(String),
+    // more edges than the graph's result limits allow, see graph::result_limit
+    Overflow(Overflow),
+    EncryptionError(EncryptionError),
+    ReadOnly
 }
 
 pub trait TEdge {
@@ -55,20 +82,28 @@ pub trait TEdge {
     fn edge_type() -> EdgeType;
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Edge {
     Directed(directed::DirectedEdge),
     Undirected(undirectd::UndirectedEdge)
 }
 
 impl Edge {
-    pub fn remove (self, txn: &Transaction)
+    pub fn remove (self, txn: &Transaction, schemas: &Arc<SchemaContainer>)
         -> Result<Result<(), EdgeError>, TxnError> {
         match self {
-            Edge::Directed(mut e) => e.remove(txn),
-            Edge::Undirected(mut e) => e.remove(txn),
+            Edge::Directed(mut e) => e.remove(txn, schemas),
+            Edge::Undirected(mut e) => e.remove(txn, schemas),
         }
     }
+    // moves the second end onto another vertex, see BilateralEdge::rewire
+    pub fn rewire(self, new_b_id: &Id, txn: &Transaction, schemas: &Arc<SchemaContainer>)
+        -> Result<Result<Edge, EdgeError>, TxnError> {
+        Ok(match self {
+            Edge::Directed(mut e) => e.rewire(new_b_id, txn, schemas)?.map(Edge::Directed),
+            Edge::Undirected(mut e) => e.rewire(new_b_id, txn, schemas)?.map(Edge::Undirected),
+        })
+    }
     pub fn get_data(&self) -> &Option<Cell> {
         match self {
             &Edge::Directed(ref e) => e.edge_cell(),
@@ -81,6 +116,19 @@ impl Edge {
             &Edge::Undirected(ref e) => e.oppisite_vertex_id(vertex_id),
         }
     }
+    // the two vertices in link order, from and to for directed edges
+    pub fn ends(&self) -> (&Id, &Id) {
+        match self {
+            &Edge::Directed(ref e) => (e.vertex_a(), e.vertex_b()),
+            &Edge::Undirected(ref e) => (e.vertex_a(), e.vertex_b()),
+        }
+    }
+    pub fn schema_id(&self) -> u32 {
+        match self {
+            &Edge::Directed(ref e) => e.schema_id(),
+            &Edge::Undirected(ref e) => e.schema_id(),
+        }
+    }
 }
 
 impl Index<u64> for Edge {
@@ -121,6 +169,32 @@ impl IndexMut<u64> for Edge {
     }
 }
 
+// edge with a body, built from its own cell
+pub fn from_cell(mut cell: Cell, schemas: &Arc<SchemaContainer>) -> Result<Edge, EdgeError> {
+    let schema_id = cell.header.schema;
+    encryption::open_cell(schemas, &mut cell);
+    match schemas.schema_type(schema_id) {
+        Some(SchemaType::Edge(ea)) => {
+            match ea.edge_type {
+                EdgeType::Directed => directed::DirectedEdge::from_cell(cell, schema_id).map(Edge::Directed),
+                EdgeType::Undirected => undirectd::UndirectedEdge::from_cell(cell, schema_id).map(Edge::Undirected)
+            }
+        },
+        Some(_) => Err(EdgeError::WrongSchema),
+        None => Err(EdgeError::CannotFindSchema)
+    }
+}
+
+// simple edge without a body, vertex_a is the owner of the list the pair was found in
+pub fn from_pair(edge_type: EdgeType, vertex_a: Id, vertex_b: Id, schema_id: u32) -> Edge {
+    match edge_type {
+        EdgeType::Directed => Edge::Directed(
+            directed::DirectedEdge::build_edge(vertex_a, vertex_b, schema_id, None)),
+        EdgeType::Undirected => Edge::Undirected(
+            undirectd::UndirectedEdge::build_edge(vertex_a, vertex_b, schema_id, None))
+    }
+}
+
 pub fn from_id(
     vertex_id: &Id, vertex_field: u64, schema_id: u32,
     schemas: &Arc<SchemaContainer>, txn: &Transaction, id: &Id
@@ -139,4 +213,23 @@ pub fn from_id(
         Some(_) => return Ok(Err(EdgeError::WrongSchema)),
         None => return Ok(Err(EdgeError::CannotFindSchema))
     }
-}
\ No newline at end of file
+}
+pub fn from_trace_cell(
+    vertex_id: &Id, vertex_field: u64, schema_id: u32,
+    schemas: &Arc<SchemaContainer>, id: &Id, trace_cell: Cell
+) -> Result<Edge, EdgeError> {
+    match schemas.schema_type(schema_id) {
+        Some(SchemaType::Edge(ea)) => {
+            match ea.edge_type {
+                EdgeType::Directed => directed::DirectedEdge::from_trace_cell(
+                    vertex_id, vertex_field, schema_id, schemas, id, trace_cell
+                ).map(Edge::Directed),
+                EdgeType::Undirected => undirectd::UndirectedEdge::from_trace_cell(
+                    vertex_id, vertex_field, schema_id, schemas, id, trace_cell
+                ).map(Edge::Undirected)
+            }
+        },
+        Some(_) => Err(EdgeError::WrongSchema),
+        None => Err(EdgeError::CannotFindSchema)
+    }
+}
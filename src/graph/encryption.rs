@@ -0,0 +1,215 @@
+// Fields encrypted at rest. A schema names its encrypted fields with the key each one takes, see
+// MorpheusSchema::with_encrypted. Values are sealed with AES-256-GCM on their way into a cell and
+// opened when the cell is read back into a vertex or an edge, so neb only ever stores ciphertext.
+// Keys come from a KeyProvider, fetched on first use and kept in memory. Sealed values are strings,
+// so encrypted fields are declared as strings or left to dynamic schemas; null stays null. They can
+// be neither keys nor geo or vector indexed, and filters pushed down to neb see the ciphertext.
+// Values that fail to open, a missing key say, are read as null and logged. The vertex cache and
+// the change log hold what they are given, plaintext vertices and sealed cells respectively.
+
+use neb::ram::cell::Cell;
+use neb::ram::types::key_hash;
+use neb::dovahkiin::types::{Map, Value};
+use parking_lot::RwLock;
+use ring::aead::{self, SealingKey, OpeningKey, AES_256_GCM};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_json;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Arc;
+
+use graph::vertex::Vertex;
+use server::schema::SchemaContainer;
+
+static SEALED_PREFIX: &'static str = "enc1:";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EncryptionError {
+    // the schema has encrypted fields but no key provider is set
+    NoKeyProvider,
+    // the key name and why it could not be had
+    KeyUnavailable(String, String),
+    // the value could not be sealed, or was tampered with or sealed with another key
+    CipherFailed(String)
+}
+
+// Hands out 256 bit keys by name, a KMS client for instance
+pub trait KeyProvider: Send + Sync {
+    fn key(&self, name: &str) -> Result<Vec<u8>, String>;
+}
+
+// the providers that can be set up from the config, keys are written in hex
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum KeyProviderOptions {
+    // keys by name in the config itself
+    Static(HashMap<String, String>),
+    // a file named like the key under the directory
+    Directory(String),
+    // the program and its arguments, run with the key name appended, printing the key
+    Command(Vec<String>)
+}
+
+fn from_hex(text: &str) -> Result<Vec<u8>, String> {
+    let text = text.trim();
+    if text.len() % 2 != 0 { return Err("odd number of hex digits".to_string()); }
+    text.as_bytes().chunks(2)
+        .map(|pair| ::std::str::from_utf8(pair).map_err(|e| e.to_string())
+            .and_then(|pair| u8::from_str_radix(pair, 16).map_err(|e| e.to_string())))
+        .collect()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl KeyProvider for KeyProviderOptions {
+    fn key(&self, name: &str) -> Result<Vec<u8>, String> {
+        let hex = match self {
+            &KeyProviderOptions::Static(ref keys) => keys.get(name).cloned().ok_or_else(|| "no such key".to_string())?,
+            &KeyProviderOptions::Directory(ref dir) =>
+                fs::read_to_string(Path::new(dir).join(name)).map_err(|e| e.to_string())?,
+            &KeyProviderOptions::Command(ref command) => {
+                let (program, args) = command.split_first().ok_or_else(|| "empty command".to_string())?;
+                let output = Command::new(program).args(args).arg(name).output().map_err(|e| e.to_string())?;
+                if !output.status.success() {
+                    return Err(format!("{} exited with {}", program, output.status));
+                }
+                String::from_utf8(output.stdout).map_err(|e| e.to_string())?
+            }
+        };
+        from_hex(&hex)
+    }
+}
+
+struct FieldKey {
+    sealing: SealingKey,
+    opening: OpeningKey
+}
+
+pub struct FieldCipher {
+    provider: Box<KeyProvider>,
+    keys: RwLock<HashMap<String, Arc<FieldKey>>>,
+    random: SystemRandom
+}
+
+impl FieldCipher {
+    pub fn new<P>(provider: P) -> Arc<FieldCipher> where P: KeyProvider + 'static {
+        Arc::new(FieldCipher { provider: Box::new(provider), keys: RwLock::new(HashMap::new()), random: SystemRandom::new() })
+    }
+
+    fn field_key(&self, name: &str) -> Result<Arc<FieldKey>, EncryptionError> {
+        if let Some(key) = self.keys.read().get(name) { return Ok(key.clone()); }
+        let unavailable = |reason: String| EncryptionError::KeyUnavailable(name.to_string(), reason);
+        let bytes = self.provider.key(name).map_err(&unavailable)?;
+        let key = Arc::new(FieldKey {
+            sealing: SealingKey::new(&AES_256_GCM, &bytes).map_err(|_| unavailable("not a 256 bit key".to_string()))?,
+            opening: OpeningKey::new(&AES_256_GCM, &bytes).map_err(|_| unavailable("not a 256 bit key".to_string()))?
+        });
+        self.keys.write().insert(name.to_string(), key.clone());
+        Ok(key)
+    }
+
+    // the field name is authenticated with the value, so sealed values cannot be moved between fields
+    pub fn seal(&self, key: &str, field: &str, value: &Value) -> Result<Value, EncryptionError> {
+        let key = self.field_key(key)?;
+        let failed = |what: &str| EncryptionError::CipherFailed(what.to_string());
+        let mut nonce = vec![0u8; AES_256_GCM.nonce_len()];
+        self.random.fill(&mut nonce).map_err(|_| failed("no randomness for the nonce"))?;
+        let mut sealed = serde_json::to_vec(value).map_err(|e| failed(&e.to_string()))?;
+        let tag_len = AES_256_GCM.tag_len();
+        sealed.extend(vec![0u8; tag_len]);
+        let len = aead::seal_in_place(&key.sealing, &nonce, field.as_bytes(), &mut sealed, tag_len)
+            .map_err(|_| failed("sealing"))?;
+        sealed.truncate(len);
+        Ok(Value::String(format!("{}{}{}", SEALED_PREFIX, to_hex(&nonce), to_hex(&sealed))))
+    }
+
+    // values that were not sealed are returned as they are
+    pub fn open(&self, key: &str, field: &str, value: &Value) -> Result<Value, EncryptionError> {
+        let text = match value {
+            &Value::String(ref text) if text.starts_with(SEALED_PREFIX) => &text[SEALED_PREFIX.len()..],
+            other => return Ok(other.clone())
+        };
+        let key = self.field_key(key)?;
+        let failed = |what: &str| EncryptionError::CipherFailed(what.to_string());
+        let mut nonce = from_hex(text).map_err(|e| failed(&e))?;
+        let nonce_len = AES_256_GCM.nonce_len();
+        if nonce.len() < nonce_len { return Err(failed("truncated")); }
+        let mut sealed = nonce.split_off(nonce_len);
+        let opened = aead::open_in_place(&key.opening, &nonce, field.as_bytes(), 0, &mut sealed)
+            .map_err(|_| failed("opening"))?;
+        serde_json::from_slice(opened).map_err(|e| failed(&e.to_string()))
+    }
+}
+
+// Seals the encrypted fields of data about to be written as a cell of the schema
+pub fn seal_fields(schemas: &SchemaContainer, schema_id: u32, data: &mut Map) -> Result<(), EncryptionError> {
+    let fields = schemas.encrypted(schema_id);
+    if fields.is_empty() { return Ok(()); }
+    let cipher = schemas.field_cipher().ok_or(EncryptionError::NoKeyProvider)?;
+    // all or nothing, a plaintext value must not be written along sealed ones
+    let mut sealed = Vec::with_capacity(fields.len());
+    for &(ref field, ref key) in &fields {
+        match data.get_by_key_id(key_hash(field)) {
+            &Value::Null => continue,
+            value => sealed.push((field, cipher.seal(key, field, value)?))
+        }
+    }
+    for (field, value) in sealed {
+        data.insert(field, value);
+    }
+    Ok(())
+}
+
+fn opened_fields(schemas: &SchemaContainer, fields: &[(String, String)], data: &Map)
+    -> Vec<(String, Result<Value, EncryptionError>)>
+{
+    let cipher = schemas.field_cipher();
+    fields.iter().filter_map(|&(ref field, ref key)| {
+        let opened = match (data.get_by_key_id(key_hash(field)), &cipher) {
+            (&Value::Null, _) => return None,
+            (value, &Some(ref cipher)) => cipher.open(key, field, value),
+            (_, &None) => Err(EncryptionError::NoKeyProvider)
+        };
+        Some((field.clone(), opened))
+    }).collect()
+}
+
+// Opens the encrypted fields of a cell read back, the ones that fail are null
+pub fn open_cell(schemas: &SchemaContainer, cell: &mut Cell) {
+    let fields = schemas.encrypted(cell.header.schema);
+    if fields.is_empty() { return; }
+    let id = cell.id();
+    if let Value::Map(ref mut data) = cell.data {
+        for (field, opened) in opened_fields(schemas, &fields, data) {
+            data.insert(&field, opened.unwrap_or_else(|e| {
+                warn!("Cannot decrypt field {} of {:?}: {:?}", field, id, e);
+                Value::Null
+            }));
+        }
+    }
+}
+
+// Opens every encrypted field or leaves the cell as it is, for updates that write the fields back
+pub fn try_open_cell(schemas: &SchemaContainer, cell: &mut Cell) -> Result<(), EncryptionError> {
+    let fields = schemas.encrypted(cell.header.schema);
+    if fields.is_empty() { return Ok(()); }
+    if let Value::Map(ref mut data) = cell.data {
+        let mut opened = Vec::new();
+        for (field, value) in opened_fields(schemas, &fields, data) {
+            opened.push((field, value?));
+        }
+        for (field, value) in opened {
+            data.insert(&field, value);
+        }
+    }
+    Ok(())
+}
+
+pub fn open_vertex(schemas: &SchemaContainer, mut vertex: Vertex) -> Vertex {
+    open_cell(schemas, &mut vertex.cell);
+    vertex
+}
@@ -0,0 +1,68 @@
+// Which vertex schemas the edges of a schema may connect, so works_at only goes from a person to
+// a company. The lists name vertex schemas, an empty list allows any vertex at that end. Undirected
+// edges pass when their ends fit the lists in either order. EdgeAttributes is Copy and part of every
+// schema type, so the lists sit beside it in the schema container like placements and defaults.
+// Checking reads the schema of both ends, edge schemas without restrictions skip those reads.
+
+use neb::ram::types::Id;
+use neb::client::transaction::TxnError;
+
+use graph::{GraphTransaction, LinkVerticesError};
+use graph::edge::EdgeType;
+use server::schema::SchemaContainer;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct EdgeEndpoints {
+    pub from: Vec<String>,
+    pub to: Vec<String>
+}
+
+impl EdgeEndpoints {
+    pub fn new(from: &[&str], to: &[&str]) -> EdgeEndpoints {
+        EdgeEndpoints {
+            from: from.iter().map(|name| name.to_string()).collect(),
+            to: to.iter().map(|name| name.to_string()).collect()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.from.is_empty() && self.to.is_empty()
+    }
+
+    // whether edges may go from a vertex of one schema to one of the other
+    pub fn allows(&self, schemas: &SchemaContainer, edge_type: EdgeType, from_schema: u32, to_schema: u32) -> bool {
+        let forward = fits(schemas, &self.from, from_schema) && fits(schemas, &self.to, to_schema);
+        match edge_type {
+            EdgeType::Directed => forward,
+            EdgeType::Undirected => forward || (fits(schemas, &self.from, to_schema) && fits(schemas, &self.to, from_schema))
+        }
+    }
+}
+
+fn fits(schemas: &SchemaContainer, names: &Vec<String>, schema_id: u32) -> bool {
+    names.is_empty() || names.iter().any(|name| schemas.id_from_name(name) == Some(schema_id))
+}
+
+impl <'a> GraphTransaction<'a> {
+    fn vertex_schema(&self, id: Id) -> Result<Option<u32>, TxnError> {
+        Ok(self.neb_txn.read(&id)?.map(|cell| cell.header.schema))
+    }
+
+    // Fails with the end that does not fit when the edge schema restricts its endpoints
+    pub(super) fn check_endpoints(&self, schema_id: u32, edge_type: EdgeType, from: Id, to: Id)
+        -> Result<Result<(), LinkVerticesError>, TxnError>
+    {
+        let endpoints = self.schemas.endpoints(schema_id);
+        if endpoints.is_empty() { return Ok(Ok(())); }
+        let from_schema = match self.vertex_schema(from)? {
+            Some(schema) => schema, None => return Ok(Err(LinkVerticesError::VertexNotFound(from)))
+        };
+        let to_schema = match self.vertex_schema(to)? {
+            Some(schema) => schema, None => return Ok(Err(LinkVerticesError::VertexNotFound(to)))
+        };
+        if endpoints.allows(&self.schemas, edge_type, from_schema, to_schema) { return Ok(Ok(())); }
+        // name the end that breaks the restriction, the from end when both do
+        let offending = if fits(&self.schemas, &endpoints.from, from_schema) { to } else { from };
+        Ok(Err(LinkVerticesError::EndpointNotAllowed(offending)))
+    }
+}
@@ -4,17 +4,28 @@ use neb::ram::types::{TypeId, Id, key_hash};
 pub const INBOUND_KEY: &'static str = "_inbound";
 pub const OUTBOUND_KEY: &'static str = "_outbound";
 pub const UNDIRECTED_KEY: &'static str = "_undirected";
+pub const VERSION_KEY: &'static str = "_version";
+pub const KEY_KEY: &'static str = "_key";
 
 lazy_static! {
     pub static ref INBOUND_NAME: String = String::from(INBOUND_KEY);
     pub static ref OUTBOUND_NAME: String = String::from(OUTBOUND_KEY);
     pub static ref UNDIRECTED_NAME: String = String::from(UNDIRECTED_KEY);
+    pub static ref VERSION_NAME: String = String::from(VERSION_KEY);
+    pub static ref KEY_NAME: String = String::from(KEY_KEY);
     pub static ref VERTEX_TEMPLATE: Vec<Field> = vec![
             Field::new(&*OUTBOUND_NAME, TypeId::Id as u32, false, false, None),
             Field::new(&*INBOUND_NAME, TypeId::Id as u32, false, false, None),
-            Field::new(&*UNDIRECTED_NAME, TypeId::Id as u32, false, false, None)
+            Field::new(&*UNDIRECTED_NAME, TypeId::Id as u32, false, false, None),
+            // nullable so vertices written before the field existed still read, they count as version 0
+            Field::new(&*VERSION_NAME, TypeId::U64 as u32, true, false, None),
+            // the key a vertex of a keyed schema was created with, see graph::keys
+            Field::new(&*KEY_NAME, TypeId::String as u32, true, false, None)
         ];
     pub static ref INBOUND_KEY_ID: u64 = key_hash(&*INBOUND_NAME);
     pub static ref OUTBOUND_KEY_ID: u64 = key_hash(&*OUTBOUND_NAME);
     pub static ref UNDIRECTED_KEY_ID: u64 = key_hash(&*UNDIRECTED_NAME);
+    pub static ref VERSION_KEY_ID: u64 = key_hash(&*VERSION_NAME);
+    pub static ref KEY_KEY_ID: u64 = key_hash(&*KEY_NAME);
+    pub static ref VERTEX_PROBE_FIELDS: Vec<u64> = vec![*OUTBOUND_KEY_ID, *INBOUND_KEY_ID, *UNDIRECTED_KEY_ID];
 }
\ No newline at end of file
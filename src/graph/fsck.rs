@@ -0,0 +1,319 @@
+// Consistency checks over the adjacency of the whole graph. Every entry in a vertex's edge lists
+// must lead to a live cell, edge bodies must name the vertex holding them, and the opposite end
+// must list the edge back. Bodies no live end lists are orphans. A crash between the writes of a
+// link or a bug can break any of these and nothing else notices.
+// Vertices are checked one scan page per transaction, edge bodies after all vertices so bodies
+// orphaned by repairs of the first pass are picked up. Checking while the graph takes writes can
+// report edges that are only half linked at that moment, repair on a quiet graph.
+
+use neb::ram::types::Id;
+use neb::ram::cell::Cell;
+use neb::dovahkiin::types::Value;
+use neb::client::transaction::TxnError;
+use futures::prelude::*;
+use futures::{future, stream};
+
+use std::sync::Arc;
+
+use graph::{GraphInner, GraphTransaction, EdgeDirection, fields};
+use graph::id_list::IdList;
+use graph::scan::{self, ScanError};
+use graph::edge::EdgeType;
+use graph::edge::bilateral::BilateralEdge;
+use graph::edge::directed::DirectedEdge;
+use graph::edge::undirectd::UndirectedEdge;
+use server::schema::SchemaType;
+
+#[derive(Debug, Clone, Copy)]
+pub struct FsckOptions {
+    // fix what can be fixed, otherwise only report
+    pub repair: bool,
+    pub batch_size: usize
+}
+
+impl Default for FsckOptions {
+    fn default() -> FsckOptions {
+        FsckOptions { repair: false, batch_size: scan::DEFAULT_SCAN_BATCH }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inconsistency {
+    // the entry leads to no cell, the vertex or edge body was removed. Repair drops the entry
+    DanglingEntry { vertex: Id, schema: u32, direction: EdgeDirection, entry: Id },
+    // the edge body does not have the vertex holding the entry at that end. Repair drops the entry
+    MismatchedBody { vertex: Id, schema: u32, direction: EdgeDirection, body: Id },
+    // vertex is the opposite end, its list lacks the entry. Repair adds it
+    MissingReverse { vertex: Id, schema: u32, direction: EdgeDirection, entry: Id },
+    // the body is listed by none of its ends, or one end is gone. Repair removes the body
+    // and the entry left at the other end
+    OrphanEdge { edge: Id, schema: u32 },
+    // the list cannot be read, left for manual repair
+    UnreadableList { vertex: Id, schema: u32, direction: EdgeDirection }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FsckReport {
+    pub vertices_checked: usize,
+    pub edges_checked: usize,
+    pub inconsistencies: Vec<Inconsistency>,
+    pub repaired: usize
+}
+
+#[derive(Debug)]
+pub enum FsckError {
+    ScanError(ScanError),
+    TxnError(TxnError),
+    ReadOnly
+}
+
+// the list fields of the two ends and the body fields naming them
+fn end_fields(edge_type: EdgeType) -> (u64, u64, u64, u64) {
+    match edge_type {
+        EdgeType::Directed => (
+            DirectedEdge::vertex_a_field(), DirectedEdge::vertex_b_field(),
+            DirectedEdge::edge_a_field(), DirectedEdge::edge_b_field()
+        ),
+        EdgeType::Undirected => (
+            UndirectedEdge::vertex_a_field(), UndirectedEdge::vertex_b_field(),
+            UndirectedEdge::edge_a_field(), UndirectedEdge::edge_b_field()
+        )
+    }
+}
+
+fn direction_of(field: u64) -> EdgeDirection {
+    if field == *fields::INBOUND_KEY_ID { EdgeDirection::Inbound }
+    else if field == *fields::OUTBOUND_KEY_ID { EdgeDirection::Outbound }
+    else { EdgeDirection::Undirected }
+}
+
+fn body_ends(cell: &Cell, a_body: u64, b_body: u64) -> Option<(Id, Id)> {
+    match (&cell.data[a_body], &cell.data[b_body]) {
+        (&Value::Id(a), &Value::Id(b)) => Some((a, b)),
+        _ => None
+    }
+}
+
+impl <'a> GraphTransaction<'a> {
+    fn list_contains(&self, vertex: &Id, field: u64, schema: u32, entry: &Id)
+        -> Result<Option<bool>, TxnError>
+    {
+        Ok(IdList::from_txn_and_container(self.neb_txn, vertex, field, schema).contains(entry)?.ok())
+    }
+
+    // the opposite vertex index of a repaired list is rebuilt from the bodies on its next change
+    fn drop_index(&self, vertex: &Id, field: u64, schema: u32) -> Result<(), TxnError> {
+        IdList::opposite_index(self.neb_txn, vertex, field, schema).discard().map(|_| ())
+    }
+
+    // Checks every edge list of the vertex, returns what was found and how much of it was repaired
+    pub fn check_adjacency(&self, vertex: Id, repair: bool) -> Result<(Vec<Inconsistency>, usize), TxnError> {
+        self.watch.touch("check_adjacency", None, vertex)?;
+        let mut found = Vec::new();
+        let mut repaired = 0;
+        for (direction, field) in EdgeDirection::every_list() {
+            let schema_ids = match IdList::cell_types(self.neb_txn, &vertex, field)? {
+                Some((_, schema_ids)) => schema_ids, None => continue
+            };
+            for schema in schema_ids {
+                let edge_type = match self.schemas.schema_type(schema) {
+                    Some(SchemaType::Edge(attrs)) => attrs.edge_type, _ => continue
+                };
+                let (a_list, b_list, a_body, b_body) = end_fields(edge_type);
+                let entries = match IdList::from_txn_and_container(self.neb_txn, &vertex, field, schema).all()? {
+                    Ok(entries) => entries,
+                    Err(_) => {
+                        found.push(Inconsistency::UnreadableList { vertex, schema, direction });
+                        continue;
+                    }
+                };
+                for entry in entries {
+                    let trace_cell = match self.neb_txn.read(&entry)? {
+                        Some(cell) => cell,
+                        None => {
+                            found.push(Inconsistency::DanglingEntry { vertex, schema, direction, entry });
+                            if repair && IdList::from_txn_and_container(self.neb_txn, &vertex, field, schema)
+                                .remove(&entry, false)?.is_ok() {
+                                repaired += 1;
+                                self.drop_index(&vertex, field, schema)?;
+                            }
+                            continue;
+                        }
+                    };
+                    // the opposite end and the list there that should hold expected
+                    let (opposite, reverse_field, expected) = match self.schemas.schema_type(trace_cell.header.schema) {
+                        Some(SchemaType::Edge(_)) => match body_ends(&trace_cell, a_body, b_body) {
+                            Some((a, b)) if field == a_list && a == vertex => (b, b_list, entry),
+                            Some((a, b)) if field == b_list && b == vertex => (a, a_list, entry),
+                            _ => {
+                                found.push(Inconsistency::MismatchedBody { vertex, schema, direction, body: entry });
+                                if repair && IdList::from_txn_and_container(self.neb_txn, &vertex, field, schema)
+                                    .remove(&entry, false)?.is_ok() {
+                                    repaired += 1;
+                                    self.drop_index(&vertex, field, schema)?;
+                                }
+                                continue;
+                            }
+                        },
+                        _ => (entry, if field == a_list { b_list } else { a_list }, vertex)
+                    };
+                    // a self-loop in a shared list has a single entry
+                    if opposite == vertex && reverse_field == field { continue; }
+                    if opposite != entry && self.neb_txn.read(&opposite)?.is_none() {
+                        // only the body is left of the opposite end
+                        found.push(Inconsistency::OrphanEdge { edge: entry, schema });
+                        if repair {
+                            self.neb_txn.remove(&entry)?;
+                            if IdList::from_txn_and_container(self.neb_txn, &vertex, field, schema)
+                                .remove(&entry, false)?.is_ok() {
+                                repaired += 1;
+                                self.drop_index(&vertex, field, schema)?;
+                            }
+                        }
+                        continue;
+                    }
+                    match self.list_contains(&opposite, reverse_field, schema, &expected)? {
+                        Some(true) => {},
+                        Some(false) => {
+                            found.push(Inconsistency::MissingReverse {
+                                vertex: opposite, schema, direction: direction_of(reverse_field), entry: expected
+                            });
+                            if repair && IdList::from_txn_and_container(self.neb_txn, &opposite, reverse_field, schema)
+                                .add(&expected)?.is_ok() {
+                                repaired += 1;
+                                self.drop_index(&opposite, reverse_field, schema)?;
+                            }
+                        },
+                        None => found.push(Inconsistency::UnreadableList {
+                            vertex: opposite, schema, direction: direction_of(reverse_field)
+                        })
+                    }
+                }
+            }
+        }
+        Ok((found, repaired))
+    }
+
+    // Checks that an edge body is listed by at least one of its ends, both of them live
+    pub fn check_edge_body(&self, body: Id, edge_type: EdgeType, repair: bool)
+        -> Result<(Vec<Inconsistency>, usize), TxnError>
+    {
+        self.watch.touch("check_edge_body", None, body)?;
+        let cell = match self.neb_txn.read(&body)? {
+            Some(cell) => cell, None => return Ok((vec![], 0)) // removed since the scan
+        };
+        let schema = cell.header.schema;
+        let (a_list, b_list, a_body, b_body) = end_fields(edge_type);
+        let listed_by = |end: &Id, list: u64| -> Result<bool, TxnError> {
+            if self.neb_txn.read(end)?.is_none() { return Ok(false); }
+            Ok(self.list_contains(end, list, schema, &body)?.unwrap_or(true))
+        };
+        let orphan = match body_ends(&cell, a_body, b_body) {
+            Some((a, b)) => !listed_by(&a, a_list)? && !listed_by(&b, b_list)?,
+            None => true
+        };
+        if !orphan { return Ok((vec![], 0)); }
+        if repair {
+            self.neb_txn.remove(&body)?;
+            if let Some((a, b)) = body_ends(&cell, a_body, b_body) {
+                if self.neb_txn.read(&a)?.is_some() { self.drop_index(&a, a_list, schema)?; }
+                if self.neb_txn.read(&b)?.is_some() { self.drop_index(&b, b_list, schema)?; }
+            }
+        }
+        Ok((vec![Inconsistency::OrphanEdge { edge: body, schema }], if repair { 1 } else { 0 }))
+    }
+}
+
+impl GraphInner {
+    // edge schemas with bodies, the only edges with cells of their own to check
+    pub(super) fn bodied_edge_schemas(&self) -> Vec<(u32, EdgeType)> {
+        self.schemas.all_schema_types().into_iter()
+            .filter_map(|(id, schema_type)| match schema_type {
+                SchemaType::Edge(attrs) if attrs.has_body => Some((id, attrs.edge_type)),
+                _ => None
+            })
+            .collect()
+    }
+
+    // one transaction for a page of vertices
+    pub(super) fn check_vertices(&self, ids: Vec<Id>, repair: bool)
+        -> impl Future<Item = (Vec<Inconsistency>, usize), Error = TxnError>
+    {
+        self.graph_transaction(move |txn| {
+            let (mut found, mut repaired) = (Vec::new(), 0);
+            for id in &ids {
+                let (vertex_found, vertex_repaired) = txn.check_adjacency(*id, repair)?;
+                found.extend(vertex_found);
+                repaired += vertex_repaired;
+            }
+            Ok((found, repaired))
+        })
+    }
+
+    // one transaction for a page of edge bodies
+    pub(super) fn check_edge_bodies(&self, ids: Vec<Id>, edge_type: EdgeType, repair: bool)
+        -> impl Future<Item = (Vec<Inconsistency>, usize), Error = TxnError>
+    {
+        self.graph_transaction(move |txn| {
+            let (mut found, mut repaired) = (Vec::new(), 0);
+            for id in &ids {
+                let (edge_found, edge_repaired) = txn.check_edge_body(*id, edge_type, repair)?;
+                found.extend(edge_found);
+                repaired += edge_repaired;
+            }
+            Ok((found, repaired))
+        })
+    }
+
+    pub fn check_consistency(this: Arc<Self>, options: FsckOptions)
+        -> impl Future<Item = FsckReport, Error = FsckError>
+    {
+        if options.repair && this.is_read_only() {
+            return future::Either::A(future::err(FsckError::ReadOnly));
+        }
+        let vertex_schemas = this.schemas.vertex_schema_ids();
+        let edge_schemas = this.bodied_edge_schemas();
+        let (vertex_graph, edge_graph) = (this.clone(), this.clone());
+        let (vertex_client, edge_client) = (this.neb_client.clone(), this.neb_client.clone());
+        let vertices = stream::iter_ok::<_, FsckError>(vertex_schemas)
+            .map(move |schema_id| {
+                scan::scan_cells(vertex_client.clone(), schema_id, options.batch_size).map_err(FsckError::ScanError)
+            })
+            .flatten()
+            .and_then(move |cells| {
+                let ids: Vec<Id> = cells.iter().map(|cell| cell.id()).collect();
+                let checked = ids.len();
+                vertex_graph.check_vertices(ids, options.repair)
+                    .map(move |(found, repaired)| (checked, found, repaired))
+                    .map_err(FsckError::TxnError)
+            })
+            .fold(FsckReport::default(), |mut report, (checked, found, repaired)| {
+                report.vertices_checked += checked;
+                report.inconsistencies.extend(found);
+                report.repaired += repaired;
+                Ok::<_, FsckError>(report)
+            });
+        future::Either::B(vertices.and_then(move |report| {
+            stream::iter_ok::<_, FsckError>(edge_schemas)
+                .map(move |(schema_id, edge_type)| {
+                    scan::scan_cells(edge_client.clone(), schema_id, options.batch_size)
+                        .map_err(FsckError::ScanError)
+                        .map(move |cells| (edge_type, cells))
+                })
+                .flatten()
+                .and_then(move |(edge_type, cells)| {
+                    let ids: Vec<Id> = cells.iter().map(|cell| cell.id()).collect();
+                    let checked = ids.len();
+                    edge_graph.check_edge_bodies(ids, edge_type, options.repair)
+                        .map(move |(found, repaired)| (checked, found, repaired))
+                        .map_err(FsckError::TxnError)
+                })
+                .fold(report, |mut report, (checked, found, repaired)| {
+                    report.edges_checked += checked;
+                    report.inconsistencies.extend(found);
+                    report.repaired += repaired;
+                    Ok::<_, FsckError>(report)
+                })
+        }))
+    }
+}
@@ -0,0 +1,139 @@
+// Background collection of broken adjacency, the incremental counterpart of a repairing fsck run.
+// The collector walks the vertex schemas and then the edge body schemas one scan page at a time:
+// entries leading to vanished cells are dropped from their lists, missing reverse entries are added
+// back and bodies no end lists are removed. Pages are paced so no more than cells_per_second cells are
+// checked, each page in a transaction of its own, which keeps the collector from competing with
+// foreground writes for long. A round over the whole graph starts every round_interval_secs.
+// Read only graphs are left alone.
+
+use neb::ram::types::Id;
+use neb::ram::cell::Cell;
+use futures::prelude::*;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use graph::GraphInner;
+use graph::scan;
+use server::metrics;
+
+fn default_cells_per_second() -> u64 { 200 }
+fn default_page_size() -> usize { 64 }
+fn default_round_interval_secs() -> u64 { 3600 }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrphanGcOptions {
+    // upper bound of vertices and edge bodies checked per second
+    #[serde(default = "default_cells_per_second")]
+    pub cells_per_second: u64,
+    // cells checked in one transaction
+    #[serde(default = "default_page_size")]
+    pub page_size: usize,
+    // pause between the end of one round and the start of the next
+    #[serde(default = "default_round_interval_secs")]
+    pub round_interval_secs: u64
+}
+
+impl Default for OrphanGcOptions {
+    fn default() -> OrphanGcOptions {
+        OrphanGcOptions {
+            cells_per_second: default_cells_per_second(),
+            page_size: default_page_size(),
+            round_interval_secs: default_round_interval_secs()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GcRound {
+    pub vertices_checked: usize,
+    pub edges_checked: usize,
+    pub repaired: usize
+}
+
+// sleeps in short steps, false when the collector should stop
+pub fn pause(running: &AtomicBool, duration: Duration) -> bool {
+    let step = Duration::from_millis(100);
+    let until = Instant::now() + duration;
+    while running.load(Ordering::Relaxed) {
+        let now = Instant::now();
+        if now >= until { return true; }
+        thread::sleep(::std::cmp::min(step, until - now));
+    }
+    false
+}
+
+fn ids_of(cells: &[Cell]) -> Vec<Id> {
+    cells.iter().map(|cell| cell.id()).collect()
+}
+
+// Runs one round, None when the collector was stopped half way
+pub fn collect_round(graph: &Arc<GraphInner>, options: &OrphanGcOptions, running: &AtomicBool) -> Option<GcRound> {
+    let mut round = GcRound::default();
+    let page_size = ::std::cmp::max(options.page_size, 1);
+    let cells_per_second = ::std::cmp::max(options.cells_per_second, 1);
+    // the time a page of the given size may take at the configured rate
+    let page_budget = |cells: usize| Duration::from_millis(cells as u64 * 1000 / cells_per_second);
+    let vertex_schemas = graph.schemas.vertex_schema_ids();
+    for schema_id in vertex_schemas {
+        for page in scan::scan_cells(graph.neb_client.clone(), schema_id, page_size).wait() {
+            if graph.is_read_only() { return Some(round); }
+            let started = Instant::now();
+            let cells = match page {
+                Ok(cells) => cells,
+                Err(e) => { warn!("Orphan GC cannot scan schema {}: {:?}", schema_id, e); break; }
+            };
+            round.vertices_checked += cells.len();
+            match graph.check_vertices(ids_of(&cells), true).wait() {
+                Ok((_, repaired)) => round.repaired += repaired,
+                Err(e) => debug!("Orphan GC skipped a page of schema {}: {:?}", schema_id, e)
+            }
+            if !pause(running, page_budget(cells.len()).checked_sub(started.elapsed()).unwrap_or_default()) {
+                return None;
+            }
+        }
+    }
+    for (schema_id, edge_type) in graph.bodied_edge_schemas() {
+        for page in scan::scan_cells(graph.neb_client.clone(), schema_id, page_size).wait() {
+            if graph.is_read_only() { return Some(round); }
+            let started = Instant::now();
+            let cells = match page {
+                Ok(cells) => cells,
+                Err(e) => { warn!("Orphan GC cannot scan schema {}: {:?}", schema_id, e); break; }
+            };
+            round.edges_checked += cells.len();
+            match graph.check_edge_bodies(ids_of(&cells), edge_type, true).wait() {
+                Ok((_, repaired)) => round.repaired += repaired,
+                Err(e) => debug!("Orphan GC skipped a page of schema {}: {:?}", schema_id, e)
+            }
+            if !pause(running, page_budget(cells.len()).checked_sub(started.elapsed()).unwrap_or_default()) {
+                return None;
+            }
+        }
+    }
+    Some(round)
+}
+
+pub fn start_gc(graph: Arc<GraphInner>, options: OrphanGcOptions, running: Arc<AtomicBool>)
+    -> thread::JoinHandle<()>
+{
+    let interval = Duration::from_secs(options.round_interval_secs);
+    thread::Builder::new()
+        .name("morpheus-orphan-gc".to_string())
+        .spawn(move || {
+            while pause(&running, interval) {
+                let round = match collect_round(&graph, &options, &running) {
+                    Some(round) => round, None => break
+                };
+                let _ = metrics::ORPHAN_GC_REPAIRS.inc_by(round.repaired as f64);
+                if round.repaired > 0 {
+                    info!("Orphan GC repaired {} entries checking {} vertices and {} edges",
+                          round.repaired, round.vertices_checked, round.edges_checked);
+                }
+            }
+            debug!("Orphan GC stopped");
+        })
+        .unwrap()
+}
@@ -0,0 +1,274 @@
+// Geo points and radius queries. By convention a vertex field holding a map of F64 "lat" and "lon"
+// sub-fields is a geo point, point_field builds one. Every such field is indexed by geohash without
+// further setup: a vertex is added to one bucket per precision in INDEX_PRECISIONS, and a radius
+// query reads the buckets covering the circle at the finest precision whose cells are not smaller
+// than the radius, then checks the exact distance. Only vertices written after a schema gained the
+// field are indexed, a bucket is an id list kept on a bucket cell named after schema, field and hash.
+
+use neb::ram::schema::{Field, Schema};
+use neb::ram::types::{TypeId, Id, Map, Value, key_hash};
+use neb::ram::cell::Cell;
+use neb::client::transaction::TxnError;
+
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::f64::consts::PI;
+
+use graph::GraphTransaction;
+use graph::encryption;
+use graph::id_list::{IdList, IdListError};
+use graph::vertex::{self, Vertex};
+use server::schema::ToSchemaId;
+
+pub const LAT_KEY: &'static str = "lat";
+pub const LON_KEY: &'static str = "lon";
+pub const GEO_MEMBERS_KEY: &'static str = "_members";
+
+pub static GEO_BUCKET_SCHEMA_ID: u32 = 160;
+// finest first, 6 is about 1.2km x 0.6km at the equator and 1 about 5000km x 5000km
+pub static INDEX_PRECISIONS: [usize; 6] = [6, 5, 4, 3, 2, 1];
+pub static EARTH_RADIUS_M: f64 = 6371008.8;
+static METERS_PER_DEGREE: f64 = 111320.0;
+static BASE32: &'static [u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+lazy_static! {
+    pub static ref LAT_KEY_ID: u64 = key_hash(&String::from(LAT_KEY));
+    pub static ref LON_KEY_ID: u64 = key_hash(&String::from(LON_KEY));
+    pub static ref GEO_MEMBERS_KEY_ID: u64 = key_hash(&String::from(GEO_MEMBERS_KEY));
+    pub static ref GEO_BUCKET: Field = Field::new("*", TypeId::Map as u32, false, false, Some(vec![
+        Field::new(&String::from(GEO_MEMBERS_KEY), TypeId::Id as u32, false, false, None)
+    ]));
+}
+
+#[derive(Debug)]
+pub enum GeoError {
+    SchemaNotFound,
+    NotGeoField,
+    InvalidPoint,
+    IdListError(IdListError)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeoPoint {
+    pub lat: f64,
+    pub lon: f64
+}
+
+impl GeoPoint {
+    pub fn new(lat: f64, lon: f64) -> GeoPoint {
+        GeoPoint { lat, lon }
+    }
+    pub fn is_valid(&self) -> bool {
+        self.lat >= -90.0 && self.lat <= 90.0 && self.lon >= -180.0 && self.lon <= 180.0
+    }
+    pub fn from_value(value: &Value) -> Option<GeoPoint> {
+        match (&value[*LAT_KEY_ID], &value[*LON_KEY_ID]) {
+            (&Value::F64(lat), &Value::F64(lon)) => Some(GeoPoint { lat, lon }),
+            _ => None
+        }
+    }
+    pub fn to_value(&self) -> Value {
+        let mut map = Map::new();
+        map.insert_key_id(*LAT_KEY_ID, Value::F64(self.lat));
+        map.insert_key_id(*LON_KEY_ID, Value::F64(self.lon));
+        Value::Map(map)
+    }
+    // great-circle distance in meters
+    pub fn distance(&self, other: &GeoPoint) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.lon - self.lon).to_radians();
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_M * a.sqrt().min(1.0).asin()
+    }
+}
+
+// a geo point field for vertex schemas
+pub fn point_field(name: &str, nullable: bool) -> Field {
+    Field::new(&name.to_string(), TypeId::Map as u32, nullable, false, Some(vec![
+        Field::new(&String::from(LAT_KEY), TypeId::F64 as u32, false, false, None),
+        Field::new(&String::from(LON_KEY), TypeId::F64 as u32, false, false, None)
+    ]))
+}
+
+fn is_point_field(field: &Field) -> bool {
+    match field.sub_fields {
+        Some(ref sub_fields) if !field.is_array && sub_fields.len() == 2 =>
+            sub_fields.iter().all(|f| {
+                (f.name == LAT_KEY || f.name == LON_KEY) && f.type_id == TypeId::F64 as u32 && !f.is_array
+            }),
+        _ => false
+    }
+}
+
+// names of the top level geo point fields of a schema
+pub fn point_fields(schema: &Schema) -> Vec<String> {
+    match schema.fields.sub_fields {
+        Some(ref fields) => fields.iter().filter(|f| is_point_field(f)).map(|f| f.name.clone()).collect(),
+        None => Vec::new()
+    }
+}
+
+pub fn geohash(point: &GeoPoint, precision: usize) -> String {
+    let (mut lat_range, mut lon_range) = ((-90.0, 90.0), (-180.0, 180.0));
+    let mut hash = String::with_capacity(precision);
+    let (mut bits, mut char_index, mut even) = (0, 0, true);
+    while hash.len() < precision {
+        let (range, value) = if even { (&mut lon_range, point.lon) } else { (&mut lat_range, point.lat) };
+        let mid = (range.0 + range.1) / 2.0;
+        char_index <<= 1;
+        if value >= mid {
+            char_index |= 1;
+            range.0 = mid;
+        } else {
+            range.1 = mid;
+        }
+        even = !even;
+        bits += 1;
+        if bits == 5 {
+            hash.push(BASE32[char_index] as char);
+            bits = 0;
+            char_index = 0;
+        }
+    }
+    hash
+}
+
+// cell height and width in degrees
+fn cell_degrees(precision: usize) -> (f64, f64) {
+    let bits = 5 * precision as i32;
+    let lon_bits = (bits + 1) / 2;
+    let lat_bits = bits / 2;
+    (180.0 / 2f64.powi(lat_bits), 360.0 / 2f64.powi(lon_bits))
+}
+
+// the finest indexed precision whose cells are at least radius tall and wide around the center
+fn query_precision(center: &GeoPoint, radius: f64) -> usize {
+    let lon_scale = (center.lat * PI / 180.0).cos().abs().max(1e-6);
+    INDEX_PRECISIONS.iter().cloned().find(|&precision| {
+        let (lat_deg, lon_deg) = cell_degrees(precision);
+        lat_deg * METERS_PER_DEGREE >= radius && lon_deg * METERS_PER_DEGREE * lon_scale >= radius
+    }).unwrap_or(INDEX_PRECISIONS[INDEX_PRECISIONS.len() - 1])
+}
+
+fn wrap_lon(lon: f64) -> f64 {
+    let wrapped = (lon + 180.0) % 360.0;
+    (if wrapped < 0.0 { wrapped + 360.0 } else { wrapped }) - 180.0
+}
+
+// hashes of every cell touched by the box around the circle, sampled one cell apart
+fn covering(center: &GeoPoint, radius: f64, precision: usize) -> Vec<String> {
+    let (lat_step, lon_step) = cell_degrees(precision);
+    let dlat = radius / METERS_PER_DEGREE;
+    let lon_scale = (center.lat * PI / 180.0).cos().abs().max(1e-6);
+    let dlon = (radius / (METERS_PER_DEGREE * lon_scale)).min(180.0);
+    let (lat_min, lat_max) = ((center.lat - dlat).max(-90.0), (center.lat + dlat).min(90.0));
+    let mut hashes = BTreeSet::new();
+    let mut lat = lat_min;
+    loop {
+        let mut lon = center.lon - dlon;
+        loop {
+            hashes.insert(geohash(&GeoPoint::new(lat, wrap_lon(lon)), precision));
+            if lon >= center.lon + dlon { break; }
+            lon = (lon + lon_step).min(center.lon + dlon);
+        }
+        if lat >= lat_max { break; }
+        lat = (lat + lat_step).min(lat_max);
+    }
+    hashes.into_iter().collect()
+}
+
+fn bucket_id(schema_id: u32, field: &str, hash: &str) -> Id {
+    let group = format!("GEO-{}-{}", schema_id, field);
+    Id::new(key_hash(&group), key_hash(&format!("{}-{}", group, hash)))
+}
+
+impl <'a> GraphTransaction<'a> {
+    fn geo_bucket(&self, schema_id: u32, field: &str, hash: &str, create: bool) -> Result<Option<Id>, TxnError> {
+        let id = bucket_id(schema_id, field, hash);
+        if self.neb_txn.read_selected(&id, &vec![*GEO_MEMBERS_KEY_ID])?.is_some() {
+            return Ok(Some(id));
+        }
+        if !create { return Ok(None); }
+        let mut data = Map::new();
+        data.insert_key_id(*GEO_MEMBERS_KEY_ID, Value::Id(Id::unit_id()));
+        self.neb_txn.write(&Cell::new_with_id(GEO_BUCKET_SCHEMA_ID, &id, Value::Map(data)))?;
+        Ok(Some(id))
+    }
+
+    // Moves the vertex between buckets for every geo field whose point changed. Pass the vertex cell
+    // before and after the write, None for a vertex that did not exist before or does not any more.
+    pub(super) fn reindex_geo(&self, before: Option<&Cell>, after: Option<&Cell>)
+        -> Result<Result<(), GeoError>, TxnError>
+    {
+        let cell = match after.or(before) { Some(cell) => cell, None => return Ok(Ok(())) };
+        let schema_id = cell.header.schema;
+        let fields = match self.schemas.get_neb_schema(schema_id) {
+            Some(schema) => point_fields(&schema), None => return Ok(Ok(()))
+        };
+        let vertex_id = cell.id();
+        for field in fields {
+            let point = |cell: Option<&Cell>| cell.and_then(|c| GeoPoint::from_value(&c.data[field.as_str()]));
+            let (old, new) = (point(before), point(after));
+            if old == new { continue; }
+            for &precision in INDEX_PRECISIONS.iter() {
+                let old_hash = old.map(|p| geohash(&p, precision));
+                let new_hash = new.map(|p| geohash(&p, precision));
+                if old_hash == new_hash { continue; }
+                if let Some(hash) = old_hash {
+                    if let Some(bucket) = self.geo_bucket(schema_id, &field, &hash, false)? {
+                        let mut members = IdList::from_txn_and_container(self.neb_txn, &bucket, *GEO_MEMBERS_KEY_ID, schema_id);
+                        if let Err(e) = members.remove(&vertex_id, true)? { return Ok(Err(GeoError::IdListError(e))); }
+                    }
+                }
+                if let Some(hash) = new_hash {
+                    let bucket = self.geo_bucket(schema_id, &field, &hash, true)?.unwrap();
+                    let mut members = IdList::from_txn_and_container(self.neb_txn, &bucket, *GEO_MEMBERS_KEY_ID, schema_id);
+                    if let Err(e) = members.add(&vertex_id)? { return Ok(Err(GeoError::IdListError(e))); }
+                }
+            }
+        }
+        Ok(Ok(()))
+    }
+
+    // Vertices with field within radius meters of the point, nearest first with their distance
+    pub fn vertices_near<S>(&self, schema: S, field: &str, center: GeoPoint, radius: f64)
+        -> Result<Result<Vec<(Vertex, f64)>, GeoError>, TxnError>
+        where S: ToSchemaId
+    {
+        let schema_id = schema.to_id(&self.schemas);
+        self.watch.touch("vertices_near", Some(schema_id), Id::unit_id())?;
+        if !center.is_valid() || !(radius >= 0.0) { return Ok(Err(GeoError::InvalidPoint)); }
+        match self.schemas.get_neb_schema(schema_id) {
+            Some(schema) => if !point_fields(&schema).iter().any(|f| f == field) {
+                return Ok(Err(GeoError::NotGeoField));
+            },
+            None => return Ok(Err(GeoError::SchemaNotFound))
+        }
+        let precision = query_precision(&center, radius);
+        let mut candidates = BTreeSet::new();
+        for hash in covering(&center, radius, precision) {
+            let bucket = match self.geo_bucket(schema_id, field, &hash, false)? {
+                Some(bucket) => bucket, None => continue
+            };
+            match IdList::from_txn_and_container(self.neb_txn, &bucket, *GEO_MEMBERS_KEY_ID, schema_id).all()? {
+                Ok(ids) => candidates.extend(ids.into_iter().map(|id| (id.higher, id.lower))),
+                Err(e) => return Ok(Err(GeoError::IdListError(e)))
+            }
+        }
+        let mut near = Vec::new();
+        for (higher, lower) in candidates {
+            let cell = match self.neb_txn.read(&Id::new(higher, lower))? {
+                Some(cell) => cell, None => continue
+            };
+            let distance = match GeoPoint::from_value(&cell.data[field]) {
+                Some(point) => point.distance(&center), None => continue
+            };
+            if distance <= radius {
+                near.push((encryption::open_vertex(&self.schemas, vertex::cell_to_vertex(cell)), distance));
+            }
+        }
+        near.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal));
+        Ok(Ok(near))
+    }
+}
@@ -0,0 +1,105 @@
+// Packed id blocks for id list segments.
+// Layout: varint(count) followed by zigzag varint deltas of (higher, lower) against the previous id.
+// Ids in one adjacency list usually share the same higher part, so most entries take a few bytes.
+// Sorted blocks also get restart points every RESTART_INTERVAL ids: the byte offset of the entry
+// and the id before it, so a lookup decodes one run of entries in place of the whole block.
+
+use neb::ram::types::Id;
+
+fn zigzag(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+fn unzigzag(n: u64) -> i64 {
+    ((n >> 1) as i64) ^ -((n & 1) as i64)
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut result = 0u64;
+    let mut shift = 0;
+    loop {
+        if shift >= 64 { return None; }
+        let byte = *bytes.get(*pos)?;
+        *pos += 1;
+        result |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 { return Some(result); }
+        shift += 7;
+    }
+}
+
+pub const RESTART_INTERVAL: usize = 64;
+
+pub struct Restart {
+    pub offset: usize,
+    pub prev: Id
+}
+
+pub fn encode_with_restarts(ids: &[Id]) -> (Vec<u8>, Vec<Restart>) {
+    let mut buf = Vec::with_capacity(ids.len() * 4 + 2);
+    write_varint(&mut buf, ids.len() as u64);
+    let mut prev_higher = 0u64;
+    let mut prev_lower = 0u64;
+    let mut restarts = Vec::with_capacity(ids.len() / RESTART_INTERVAL + 1);
+    for (i, id) in ids.iter().enumerate() {
+        if i % RESTART_INTERVAL == 0 {
+            restarts.push(Restart { offset: buf.len(), prev: Id::new(prev_higher, prev_lower) });
+        }
+        write_varint(&mut buf, zigzag(id.higher.wrapping_sub(prev_higher) as i64));
+        write_varint(&mut buf, zigzag(id.lower.wrapping_sub(prev_lower) as i64));
+        prev_higher = id.higher;
+        prev_lower = id.lower;
+    }
+    (buf, restarts)
+}
+
+pub fn decode(bytes: &[u8]) -> Option<Vec<Id>> {
+    if bytes.is_empty() { return Some(Vec::new()); }
+    let mut pos = 0;
+    let count = read_varint(bytes, &mut pos)? as usize;
+    let mut ids = Vec::with_capacity(count);
+    let mut higher = 0u64;
+    let mut lower = 0u64;
+    for _ in 0..count {
+        higher = higher.wrapping_add(unzigzag(read_varint(bytes, &mut pos)?) as u64);
+        lower = lower.wrapping_add(unzigzag(read_varint(bytes, &mut pos)?) as u64);
+        ids.push(Id::new(higher, lower));
+    }
+    Some(ids)
+}
+
+// only reads the header, used for counting without decoding the whole block
+pub fn decoded_len(bytes: &[u8]) -> Option<usize> {
+    if bytes.is_empty() { return Some(0); }
+    let mut pos = 0;
+    read_varint(bytes, &mut pos).map(|c| c as usize)
+}
+
+// membership in a block encoded from sorted ids, starting at the last restart point before the id
+pub fn contains_sorted(bytes: &[u8], restarts: &[Restart], id: &Id) -> Option<bool> {
+    if bytes.is_empty() { return Some(false); }
+    let count = read_varint(bytes, &mut 0)? as usize;
+    let target = (id.higher, id.lower);
+    let run = match restarts.iter().rposition(|r| (r.prev.higher, r.prev.lower) < target) {
+        Some(run) => run, None => return Some(false)
+    };
+    let restart = &restarts[run];
+    let mut pos = restart.offset;
+    let mut higher = restart.prev.higher;
+    let mut lower = restart.prev.lower;
+    let remains = count.saturating_sub(run * RESTART_INTERVAL);
+    for _ in 0..RESTART_INTERVAL.min(remains) {
+        higher = higher.wrapping_add(unzigzag(read_varint(bytes, &mut pos)?) as u64);
+        lower = lower.wrapping_add(unzigzag(read_varint(bytes, &mut pos)?) as u64);
+        if (higher, lower) == target { return Some(true); }
+        if (higher, lower) > target { return Some(false); }
+    }
+    Some(false)
+}
@@ -3,63 +3,133 @@ use neb::ram::cell::{MAX_CELL_SIZE, Cell};
 use neb::ram::types::{TypeId, Id, Map, Value, id_io, u32_io, key_hash};
 use neb::client::transaction::{Transaction, TxnError};
 
-use std::collections::BTreeSet;
+use std::cmp::Ordering;
+use parking_lot::RwLock;
 
 use utils::transaction::set_map_by_key_id;
+use super::id_codec;
 
 pub const NEXT_KEY: &'static str = "_next";
 pub const LIST_KEY: &'static str = "_list";
+pub const PACKED_KEY: &'static str = "_packed";
+pub const SEGMENT_COUNT_KEY: &'static str = "_count";
+pub const TAIL_KEY: &'static str = "_tail";
+pub const DEPTH_KEY: &'static str = "_depth";
+pub const SKIP_KEY: &'static str = "_skip";
 
 pub const ID_TYPES_MAP_KEY: &'static str = "_edges";
 pub const ID_TYPE_SCHEMA_ID_KEY: &'static str = "_type";
 pub const ID_TYPE_ID_LIST_KEY: &'static str = "_type_list";
+pub const ID_TYPE_COUNT_KEY: &'static str = "_count";
+pub const ID_TYPE_SHARDS_KEY: &'static str = "_shards";
 
 #[derive(Debug)]
 pub enum IdListError {
     ContainerCellNotFound,
     FormatError,
-    Unexpected
+    Unexpected,
+    ReadOnly
 }
 
 pub static ID_LIST_SCHEMA_ID: u32 = 100;
 pub static TYPE_LIST_SCHEMA_ID: u32 = 150;
+// set in the schema slot of a type list entry for the opposite vertex index of a bodied edge list,
+// see IdList::opposite_index
+pub const OPPOSITE_INDEX_FLAG: u32 = 1 << 31;
 
 lazy_static! {
     pub static ref ID_TYPE_LIST: Field = Field::new("*", TypeId::Map as u32, false, false, Some(vec![
         Field::new(&String::from(ID_TYPES_MAP_KEY), TypeId::Map as u32, false, true,
             Some(vec![
                 Field::new(&String::from(ID_TYPE_SCHEMA_ID_KEY), TypeId::U32 as u32, false, false, None),
-                Field::new(&String::from(ID_TYPE_ID_LIST_KEY), TypeId::Id as u32, false, false, None)
+                Field::new(&String::from(ID_TYPE_ID_LIST_KEY), TypeId::Id as u32, false, false, None),
+                Field::new(&String::from(ID_TYPE_COUNT_KEY), TypeId::U64 as u32, true, false, None),
+                Field::new(&String::from(ID_TYPE_SHARDS_KEY), TypeId::Id as u32, true, true, None)
             ]))
     ]));
     pub static ref ID_LINKED_LIST: Field = Field::new("*", TypeId::Map as u32, false, false, Some(vec![
         Field::new(&String::from(NEXT_KEY), TypeId::Id as u32, false, false, None),
-        Field::new(&String::from(LIST_KEY), TypeId::Id as u32, false, true, None)
+        Field::new(&String::from(LIST_KEY), TypeId::Id as u32, false, true, None),
+        Field::new(&String::from(PACKED_KEY), TypeId::U8 as u32, true, true, None),
+        Field::new(&String::from(SEGMENT_COUNT_KEY), TypeId::U64 as u32, true, false, None),
+        Field::new(&String::from(TAIL_KEY), TypeId::Id as u32, true, false, None),
+        Field::new(&String::from(DEPTH_KEY), TypeId::U64 as u32, true, false, None),
+        Field::new(&String::from(SKIP_KEY), TypeId::U64 as u32, true, true, None)
     ]));
-    pub static ref LIST_CAPACITY: usize =
-        ((MAX_CELL_SIZE - u32_io::size(0) - id_io::size(0)) / id_io::size(0));
+    // segment payload budget in bytes, shared by the packed block and the raw tail
+    pub static ref SEGMENT_CAPACITY: usize =
+        MAX_CELL_SIZE - u32_io::size(0) * 2 - id_io::size(0);
     pub static ref NEXT_KEY_ID: u64 = key_hash(&String::from(NEXT_KEY));
     pub static ref LIST_KEY_ID: u64 = key_hash(&String::from(LIST_KEY));
+    pub static ref PACKED_KEY_ID: u64 = key_hash(&String::from(PACKED_KEY));
+    pub static ref SEGMENT_COUNT_KEY_ID: u64 = key_hash(&String::from(SEGMENT_COUNT_KEY));
+    pub static ref TAIL_KEY_ID: u64 = key_hash(&String::from(TAIL_KEY));
+    pub static ref DEPTH_KEY_ID: u64 = key_hash(&String::from(DEPTH_KEY));
+    pub static ref SKIP_KEY_ID: u64 = key_hash(&String::from(SKIP_KEY));
+    pub static ref CHAIN_END_KEY_IDS: Vec<u64> = vec![*TAIL_KEY_ID, *DEPTH_KEY_ID];
     pub static ref NEXT_KEY_ID_VEC: Vec<u64> = vec![*NEXT_KEY_ID];
 
     pub static ref ID_TYPES_MAP_ID: u64 = key_hash(&String::from(ID_TYPES_MAP_KEY));
     pub static ref ID_TYPES_SCHEMA_ID_ID: u64 = key_hash(&String::from(ID_TYPE_SCHEMA_ID_KEY));
     pub static ref ID_TYPES_LIST_ID: u64 = key_hash(&String::from(ID_TYPE_ID_LIST_KEY));
+    pub static ref ID_TYPES_COUNT_ID: u64 = key_hash(&String::from(ID_TYPE_COUNT_KEY));
+    pub static ref ID_TYPES_SHARDS_ID: u64 = key_hash(&String::from(ID_TYPE_SHARDS_KEY));
+}
+
+// raw ids appended to a segment are folded into its packed block once the tail reaches this size
+pub static PACK_THRESHOLD: usize = 64;
+
+// Supernodes: once a list holds `threshold` ids, later ids go to `count` sub-lists picked by
+// id hash. Each shard is its own segment chain whose head keeps the shard's count, so concurrent
+// appends touch different cells instead of all fighting over the last segment and the type list.
+// Ids added before the split stay in the original chain, which keeps its count in the type list.
+// The shard count of a list is fixed when it splits, changing `count` only affects later splits.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct ShardOptions {
+    pub threshold: usize,
+    pub count: usize
+}
+
+impl Default for ShardOptions {
+    fn default() -> ShardOptions {
+        ShardOptions {
+            threshold: 4096,
+            count: 16
+        }
+    }
+}
+
+lazy_static! {
+    static ref SHARD_OPTIONS: RwLock<ShardOptions> = RwLock::new(ShardOptions::default());
+}
+
+pub fn configure_shards(options: ShardOptions) {
+    *SHARD_OPTIONS.write() = options;
 }
 
 pub struct IdList<'a> {
     pub txn: &'a Transaction,
     container_id: Id,
     field_id: u64,
-    schema_id: u32
+    schema_id: u32,
+    shards: ShardOptions
 }
 
-fn empty_list_segment(container_id: &Id, field_id: u64, schema_id: u32, level: usize) -> (Id, Value) {
-    let str_id = format!("IDLIST-{},{}-{}-{}-{}", container_id.higher, container_id.lower, field_id, schema_id, level);
+fn empty_list_segment(container_id: &Id, field_id: u64, schema_id: u32, shard: Option<usize>, level: usize) -> (Id, Value) {
+    let str_id = match shard {
+        None => format!("IDLIST-{},{}-{}-{}-{}", container_id.higher, container_id.lower, field_id, schema_id, level),
+        Some(shard) => format!(
+            "IDLIST-{},{}-{}-{}-S{}-{}", container_id.higher, container_id.lower, field_id, schema_id, shard, level)
+    };
     let list_id = Id::new(container_id.higher, key_hash(&str_id));
     let mut list_map = Map::new();
     list_map.insert_key_id(*NEXT_KEY_ID, Value::Id(Id::unit_id()));
     list_map.insert_key_id(*LIST_KEY_ID, Value::Array(Vec::<Value>::new()));
+    list_map.insert_key_id(*PACKED_KEY_ID, Value::Array(Vec::<Value>::new()));
+    if level == 0 { // a new chain is its own tail
+        list_map.insert_key_id(*TAIL_KEY_ID, Value::Id(list_id));
+        list_map.insert_key_id(*DEPTH_KEY_ID, Value::U64(1));
+    }
     return (list_id, Value::Map(list_map));
 }
 
@@ -71,10 +141,10 @@ fn empty_type_list(container_id: &Id, field_id: u64) -> (Id, Value) {
     return (list_id, Value::Map(list_map));
 }
 
-fn count_cell_list(seg: &Cell) -> Result<usize, IdListError> {
+fn seg_raw_list(seg: &Cell) -> Result<&Vec<Value>, IdListError> {
     if let &Value::Map(ref map) = &seg.data {
         if let &Value::Array(ref array) = map.get_by_key_id(*LIST_KEY_ID) {
-            Ok(array.len())
+            Ok(array)
         } else {
             Err(IdListError::FormatError)
         }
@@ -83,14 +153,101 @@ fn count_cell_list(seg: &Cell) -> Result<usize, IdListError> {
     }
 }
 
-fn val_is_id(val: &Value, id: &Id) -> bool {
-    if let &Value::Id(ref val_id) = val {
-        return val_id != id;
+// segments written before packing was introduced have no packed block
+fn seg_packed_bytes(seg: &Cell) -> Result<Vec<u8>, IdListError> {
+    match &seg.data[*PACKED_KEY_ID] {
+        &Value::Array(ref array) => array.iter().map(|v| match v {
+            &Value::U8(b) => Ok(b), _ => Err(IdListError::FormatError)
+        }).collect(),
+        &Value::Null => Ok(Vec::new()),
+        _ => Err(IdListError::FormatError)
+    }
+}
+
+fn segment_ids(seg: &Cell) -> Result<Vec<Id>, IdListError> {
+    let mut ids = match id_codec::decode(&seg_packed_bytes(seg)?) {
+        Some(ids) => ids, None => return Err(IdListError::FormatError)
+    };
+    for val in seg_raw_list(seg)? {
+        if let &Value::Id(id) = val {
+            ids.push(id);
+        }
+    }
+    Ok(ids)
+}
+
+fn cmp_ids(a: &Id, b: &Id) -> Ordering {
+    (a.higher, a.lower).cmp(&(b.higher, b.lower))
+}
+
+// restart points of the packed block as (offset, higher, lower) triples of the id before each run,
+// None for blocks packed before they were recorded
+fn seg_restarts(seg: &Cell) -> Result<Option<Vec<id_codec::Restart>>, IdListError> {
+    match &seg.data[*SKIP_KEY_ID] {
+        &Value::Array(ref array) => {
+            if array.len() % 3 != 0 { return Err(IdListError::FormatError); }
+            array.chunks(3).map(|triple| match (&triple[0], &triple[1], &triple[2]) {
+                (&Value::U64(offset), &Value::U64(higher), &Value::U64(lower)) =>
+                    Ok(id_codec::Restart { offset: offset as usize, prev: Id::new(higher, lower) }),
+                _ => Err(IdListError::FormatError)
+            }).collect::<Result<Vec<_>, _>>().map(Some)
+        },
+        &Value::Null => Ok(None),
+        _ => Err(IdListError::FormatError)
+    }
+}
+
+// Packed blocks are kept sorted and membership decodes a single run from the closest restart point.
+// The raw tail is scanned, it is folded into the block once it reaches PACK_THRESHOLD entries.
+fn segment_contains(seg: &Cell, id: &Id) -> Result<bool, IdListError> {
+    if seg_raw_list(seg)?.iter().any(|v| v == &Value::Id(*id)) {
+        return Ok(true);
+    }
+    let packed = seg_packed_bytes(seg)?;
+    let found = match seg_restarts(seg)? {
+        Some(restarts) => id_codec::contains_sorted(&packed, &restarts, id),
+        None => id_codec::decode(&packed).map(|ids| ids.binary_search_by(|probe| cmp_ids(probe, id)).is_ok())
+    };
+    found.ok_or(IdListError::FormatError)
+}
+
+// Sorts the ids before packing, so a segment iterates in id order from then on rather than in the
+// order the ids were added
+fn set_segment_ids(seg: &mut Cell, mut ids: Vec<Id>) -> Result<(), IdListError> {
+    ids.sort_by(cmp_ids);
+    let (bytes, restarts) = id_codec::encode_with_restarts(&ids);
+    let packed = bytes.into_iter().map(Value::U8).collect();
+    let skip = restarts.into_iter().flat_map(|r| vec![
+        Value::U64(r.offset as u64), Value::U64(r.prev.higher), Value::U64(r.prev.lower)
+    ]).collect();
+    if let &mut Value::Map(ref mut map) = &mut seg.data {
+        map.insert_key_id(*PACKED_KEY_ID, Value::Array(packed));
+        map.insert_key_id(*SKIP_KEY_ID, Value::Array(skip));
+        map.insert_key_id(*LIST_KEY_ID, Value::Array(Vec::<Value>::new()));
+        Ok(())
     } else {
-        return true;
+        Err(IdListError::FormatError)
     }
 }
 
+fn count_cell_list(seg: &Cell) -> Result<usize, IdListError> {
+    let packed_count = match id_codec::decoded_len(&seg_packed_bytes(seg)?) {
+        Some(c) => c, None => return Err(IdListError::FormatError)
+    };
+    Ok(packed_count + seg_raw_list(seg)?.len())
+}
+
+fn segment_size(seg: &Cell) -> Result<usize, IdListError> {
+    let skip_len = match &seg.data[*SKIP_KEY_ID] {
+        &Value::Array(ref array) => array.len(), _ => 0
+    };
+    Ok(seg_packed_bytes(seg)?.len() + skip_len * 8 + seg_raw_list(seg)?.len() * id_io::size(0))
+}
+
+fn shard_of(id: &Id, shards: usize) -> usize {
+    ((id.higher ^ id.lower) % shards as u64) as usize
+}
+
 fn seg_cell_by_id(txn: &Transaction, id: Option<Id>) -> Result<Option<Cell>, TxnError> {
     match id {
         Some(id) => txn.read(&id),
@@ -98,6 +255,56 @@ fn seg_cell_by_id(txn: &Transaction, id: Option<Id>) -> Result<Option<Cell>, Txn
     }
 }
 
+// Walks every chain of a list with plain cell reads in place of a transaction, for readers that
+// validate what they saw on their own, see graph::snapshot
+pub fn read_ids<R, E>(read: &R, container_id: &Id, field_id: u64, schema_id: u32)
+    -> Result<Result<Vec<Id>, IdListError>, E>
+    where R: Fn(&Id) -> Result<Option<Cell>, E>
+{
+    let container = match read(container_id)? {
+        Some(cell) => cell, None => return Ok(Err(IdListError::ContainerCellNotFound))
+    };
+    let type_list_id = match container.data[field_id] {
+        Value::Id(id) if !id.is_unit_id() => id,
+        _ => return Ok(Ok(Vec::new()))
+    };
+    let type_list_cell = match read(&type_list_id)? {
+        Some(cell) => cell, None => return Ok(Ok(Vec::new()))
+    };
+    let mut heads = Vec::new();
+    if let Value::Array(ref type_list) = type_list_cell.data[*ID_TYPES_MAP_ID] {
+        for pair in type_list {
+            match pair[*ID_TYPES_SCHEMA_ID_ID] {
+                Value::U32(id) if id == schema_id => {},
+                _ => continue
+            }
+            if let Value::Id(root) = pair[*ID_TYPES_LIST_ID] { heads.push(root); }
+            if let Value::Array(ref shards) = pair[*ID_TYPES_SHARDS_ID] {
+                heads.extend(shards.iter().filter_map(|val| match val {
+                    &Value::Id(id) => Some(id), _ => None
+                }));
+            }
+        }
+    }
+    let mut ids = Vec::new();
+    for head in heads {
+        let mut next = head;
+        while !next.is_unit_id() {
+            let seg = match read(&next)? {
+                Some(seg) => seg, None => break
+            };
+            match segment_ids(&seg) {
+                Ok(seg_ids) => ids.extend(seg_ids),
+                Err(e) => return Ok(Err(e))
+            }
+            next = match seg.data[*NEXT_KEY_ID] {
+                Value::Id(id) => id, _ => Id::unit_id()
+            };
+        }
+    }
+    Ok(Ok(ids))
+}
+
 impl<'a> IdList <'a> {
     pub fn from_txn_and_container(
         txn: &'a Transaction,
@@ -109,9 +316,25 @@ impl<'a> IdList <'a> {
             txn: txn,
             container_id: *container_id,
             field_id: field_id,
-            schema_id: schema_id
+            schema_id: schema_id,
+            shards: *SHARD_OPTIONS.read()
         }
     }
+    // Opposite vertex ids of the edges in a bodied edge list, one per edge. Kept as its own list in the
+    // same type list so asking for an edge between two vertices is a contains, not a read of every body
+    pub fn opposite_index(
+        txn: &'a Transaction,
+        container_id: &Id,
+        field_id: u64,
+        schema_id: u32
+    ) -> IdList<'a> {
+        Self::from_txn_and_container(txn, container_id, field_id, schema_id | OPPOSITE_INDEX_FLAG)
+    }
+    // splits this list by the options given instead of the configured ones
+    pub fn with_shards(mut self, shards: ShardOptions) -> IdList<'a> {
+        self.shards = shards;
+        self
+    }
     pub fn cell_types(txn: &Transaction, container_id: &Id, field_id: u64) -> Result<Option<(Id, Vec<u32>)>, TxnError> {
         if let Some(fields) = txn.read_selected(container_id, &vec![field_id])? {
             if let Some(&Value::Id(id)) = fields.get(0) {
@@ -120,8 +343,9 @@ impl<'a> IdList <'a> {
                         if let Value::Array(ref type_list) = cell.data[*ID_TYPES_MAP_ID] {
                             let mut res = Vec::new();
                             for value in type_list {
-                                if let Value::U32(ref schema_id) = value[*ID_TYPES_SCHEMA_ID_ID] {
-                                    res.push(*schema_id);
+                                match value[*ID_TYPES_SCHEMA_ID_ID] {
+                                    Value::U32(schema_id) if schema_id & OPPOSITE_INDEX_FLAG == 0 => res.push(schema_id),
+                                    _ => {}
                                 }
                             }
                             return Ok(Some((id, res)))
@@ -166,13 +390,14 @@ impl<'a> IdList <'a> {
                         } else { return Ok(Err(IdListError::Unexpected)); }
                         if ensure_container {
                             // if not, create the id list and add it into schema list
-                            let (list_id, list_value) = empty_list_segment(&self.container_id, self.field_id, self.schema_id, 0);
+                            let (list_id, list_value) = empty_list_segment(&self.container_id, self.field_id, self.schema_id, None, 0);
                             let list_cell = Cell::new_with_id(ID_LIST_SCHEMA_ID, &list_id, list_value);
                             self.txn.write(&list_cell)?; // create schema id list
 
                             let mut id_list_pair_map = Map::new();
                             id_list_pair_map.insert_key_id(*ID_TYPES_SCHEMA_ID_ID, Value::U32(self.schema_id));
                             id_list_pair_map.insert_key_id(*ID_TYPES_LIST_ID, Value::Id(list_id));
+                            id_list_pair_map.insert_key_id(*ID_TYPES_COUNT_ID, Value::U64(0));
                             if let &mut Value::Array(ref mut type_list) = &mut type_list_cell.data[*ID_TYPES_MAP_ID] {
                                 type_list.push(Value::Map(id_list_pair_map));
                             } else { return Ok(Err(IdListError::Unexpected)); }
@@ -191,135 +416,406 @@ impl<'a> IdList <'a> {
             }
         }
     }
+    // false until the first add, lists written before an index existed have none
+    pub fn is_assigned(&mut self) -> Result<Result<bool, IdListError>, TxnError> {
+        Ok(self.get_root_list_id(false)?.map(|id| !id.is_unit_id()))
+    }
+    fn type_list_cell(&self) -> Result<Option<Cell>, TxnError> {
+        if let Some(fields) = self.txn.read_selected(&self.container_id, &vec![self.field_id])? {
+            if let Some(&Value::Id(id)) = fields.get(0) {
+                if !id.is_unit_id() {
+                    return self.txn.read(&id);
+                }
+            }
+        }
+        Ok(None)
+    }
+    fn type_list_pos(&self, type_list_cell: &Cell) -> Option<usize> {
+        if let Value::Array(ref type_list) = type_list_cell.data[*ID_TYPES_MAP_ID] {
+            type_list.iter().position(|val| {
+                match val[*ID_TYPES_SCHEMA_ID_ID] {
+                    Value::U32(schema_id) => schema_id == self.schema_id,
+                    _ => false
+                }
+            })
+        } else { None }
+    }
+    // degree counter kept beside the list id in the type list, None for lists created before it existed
+    fn cached_count(&self) -> Result<Option<usize>, TxnError> {
+        if let Some(type_list_cell) = self.type_list_cell()? {
+            if let Some(pos) = self.type_list_pos(&type_list_cell) {
+                if let Value::Array(ref type_list) = type_list_cell.data[*ID_TYPES_MAP_ID] {
+                    if let Value::U64(count) = type_list[pos][*ID_TYPES_COUNT_ID] {
+                        return Ok(Some(count as usize));
+                    }
+                }
+            } else {
+                return Ok(Some(0));
+            }
+        } else {
+            return Ok(Some(0));
+        }
+        Ok(None)
+    }
+    pub fn adjust_count(&mut self, delta: i64) -> Result<Result<(), IdListError>, TxnError> {
+        let stored = self.cached_count()?;
+        let current = match stored {
+            Some(c) => c as i64,
+            None => match self.iter()?.and_then(|iter| iter.count_ids()) { // legacy list, recount before caching
+                Ok(count) => count as i64 - delta,
+                Err(e) => return Ok(Err(e))
+            }
+        };
+        let mut type_list_cell = match self.type_list_cell()? {
+            Some(cell) => cell, None => return Ok(Err(IdListError::Unexpected))
+        };
+        let pos = match self.type_list_pos(&type_list_cell) {
+            Some(pos) => pos, None => return Ok(Err(IdListError::Unexpected))
+        };
+        let new_count = ::std::cmp::max(current + delta, 0) as u64;
+        if let &mut Value::Array(ref mut type_list) = &mut type_list_cell.data[*ID_TYPES_MAP_ID] {
+            if let &mut Value::Map(ref mut pair) = &mut type_list[pos] {
+                pair.insert_key_id(*ID_TYPES_COUNT_ID, Value::U64(new_count));
+            } else { return Ok(Err(IdListError::FormatError)); }
+        } else { return Ok(Err(IdListError::FormatError)); }
+        self.txn.update(&type_list_cell)?;
+        Ok(Ok(()))
+    }
+    // heads of the shard chains, None until the list has been split
+    fn shard_heads(&self) -> Result<Option<Vec<Id>>, TxnError> {
+        if let Some(type_list_cell) = self.type_list_cell()? {
+            if let Some(pos) = self.type_list_pos(&type_list_cell) {
+                if let Value::Array(ref type_list) = type_list_cell.data[*ID_TYPES_MAP_ID] {
+                    if let Value::Array(ref heads) = type_list[pos][*ID_TYPES_SHARDS_ID] {
+                        let heads: Vec<Id> = heads.iter().filter_map(|val| match val {
+                            &Value::Id(id) => Some(id), _ => None
+                        }).collect();
+                        if !heads.is_empty() { return Ok(Some(heads)); }
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+    // the original chain first, then every shard chain with its index
+    fn chains(&self, list_root_id: Id) -> Result<Vec<(Id, Option<usize>)>, TxnError> {
+        let mut chains = vec![(list_root_id, None)];
+        if let Some(heads) = self.shard_heads()? {
+            chains.extend(heads.into_iter().enumerate().map(|(shard, head)| (head, Some(shard))));
+        }
+        Ok(chains)
+    }
+    fn shard_count(&self, head: &Id) -> Result<usize, TxnError> {
+        Ok(match self.txn.read(head)? {
+            Some(cell) => match cell.data[*SEGMENT_COUNT_KEY_ID] {
+                Value::U64(count) => count as usize,
+                _ => 0
+            },
+            None => 0
+        })
+    }
+    fn adjust_shard_count(&mut self, head: &Id, delta: i64) -> Result<Result<(), IdListError>, TxnError> {
+        let current = self.shard_count(head)? as i64;
+        let new_count = ::std::cmp::max(current + delta, 0) as u64;
+        set_map_by_key_id(self.txn, head, *SEGMENT_COUNT_KEY_ID, Value::U64(new_count))?;
+        Ok(Ok(()))
+    }
+    fn split_into_shards(&mut self, shard_count: usize) -> Result<Result<(), IdListError>, TxnError> {
+        let mut heads = Vec::with_capacity(shard_count);
+        for shard in 0..shard_count {
+            let (head_id, mut head_value) =
+                empty_list_segment(&self.container_id, self.field_id, self.schema_id, Some(shard), 0);
+            if let &mut Value::Map(ref mut map) = &mut head_value {
+                map.insert_key_id(*SEGMENT_COUNT_KEY_ID, Value::U64(0));
+            }
+            self.txn.write(&Cell::new_with_id(ID_LIST_SCHEMA_ID, &head_id, head_value))?;
+            heads.push(Value::Id(head_id));
+        }
+        let mut type_list_cell = match self.type_list_cell()? {
+            Some(cell) => cell, None => return Ok(Err(IdListError::Unexpected))
+        };
+        let pos = match self.type_list_pos(&type_list_cell) {
+            Some(pos) => pos, None => return Ok(Err(IdListError::Unexpected))
+        };
+        if let &mut Value::Array(ref mut type_list) = &mut type_list_cell.data[*ID_TYPES_MAP_ID] {
+            if let &mut Value::Map(ref mut pair) = &mut type_list[pos] {
+                pair.insert_key_id(*ID_TYPES_SHARDS_ID, Value::Array(heads));
+            } else { return Ok(Err(IdListError::FormatError)); }
+        } else { return Ok(Err(IdListError::FormatError)); }
+        self.txn.update(&type_list_cell)?;
+        Ok(Ok(()))
+    }
+    pub fn is_sharded(&self) -> Result<bool, TxnError> {
+        Ok(self.shard_heads()?.is_some())
+    }
+    // Ids come segment by segment. Packed blocks are sorted, so within a segment only the raw tail
+    // keeps the order ids were added in
     pub fn iter(&mut self) -> Result<Result<IdListIterator, IdListError>, TxnError> {
         let list_root_id = match self.get_root_list_id(false)? {
             Err(e) => return Ok(Err(e)), Ok(id) => id
         };
-        let mut segments = IdListSegmentIterator::new(self.txn, list_root_id);
-        let first_seg = segments.next();
-        Ok(Ok(IdListIterator {
-            segments: segments,
-            current_seg: first_seg,
+        let heads = self.chains(list_root_id)?.into_iter().map(|(head, _)| head).collect();
+        let mut iter = IdListIterator {
+            segments: IdListSegmentIterator::chained(self.txn, heads),
+            current_seg: None,
+            current_ids: Vec::new(),
             current_pos: 0,
-        }))
+            error: None
+        };
+        iter.next_seg();
+        Ok(Ok(iter))
     }
     pub fn all(&mut self) -> Result<Result<Vec<Id>, IdListError>, TxnError> {
-        Ok(self.iter()?.map(|l| l.collect()))
+        Ok(self.iter()?.and_then(|l| l.collect_ids()))
     }
     pub fn count(&mut self) -> Result<Result<usize, IdListError>, TxnError> {
-        Ok(self.iter()?.map(|l| l.count()))
+        let mut count = match self.cached_count()? {
+            Some(count) => count,
+            None => return Ok(self.iter()?.and_then(|l| l.count_ids()))
+        };
+        if let Some(heads) = self.shard_heads()? {
+            for head in &heads {
+                count += self.shard_count(head)?;
+            }
+        }
+        Ok(Ok(count))
     }
-    pub fn add(&mut self, id: &Id) -> Result<Result<(), IdListError>, TxnError> {
-        let list_root_id = self.get_root_list_id(true)?;
-        let mut list_level = 0;
-        let mut last_seg = { // TODO: refill segment under capacity
-            let last_seg_id = {
-                let mut segments = IdListSegmentIdIterator::new(
-                    self.txn,
-                    match list_root_id {
-                        Ok(v) => v, Err(e) => return Ok(Err(e))
-                    });
-                let mut last_seg_id = None;
-                for seg in segments {
-                    list_level += 1;
-                    last_seg_id = Some(seg);
+    pub fn contains(&mut self, id: &Id) -> Result<Result<bool, IdListError>, TxnError> {
+        let list_root_id = match self.get_root_list_id(false)? {
+            Ok(v) => v, Err(e) => return Ok(Err(e))
+        };
+        // an id is either in the original chain or in the shard its hash points to
+        let mut heads = vec![list_root_id];
+        if let Some(shard_heads) = self.shard_heads()? {
+            heads.push(shard_heads[shard_of(id, shard_heads.len())]);
+        }
+        for seg in IdListSegmentIterator::chained(self.txn, heads) {
+            match segment_contains(&seg, id) {
+                Ok(true) => return Ok(Ok(true)),
+                Ok(false) => {},
+                Err(e) => return Ok(Err(e))
+            }
+        }
+        Ok(Ok(false))
+    }
+    // last segment of a chain and the number of segments, kept in the head. Chains written before
+    // the head kept them are walked once, the caller records what it found
+    fn chain_end(&self, head: Id) -> Result<(Option<Id>, usize, bool), TxnError> {
+        if let Some(fields) = self.txn.read_selected(&head, &*CHAIN_END_KEY_IDS)? {
+            if let (Some(&Value::Id(tail)), Some(&Value::U64(depth))) = (fields.get(0), fields.get(1)) {
+                if !tail.is_unit_id() {
+                    return Ok((Some(tail), depth as usize, true));
                 }
-                last_seg_id
-            };
+            }
+        }
+        let mut depth = 0;
+        let mut tail = None;
+        for seg in IdListSegmentIdIterator::new(self.txn, head) {
+            depth += 1;
+            tail = Some(seg);
+        }
+        Ok((tail, depth, false))
+    }
+    fn append_to_chain(&mut self, head: Id, shard: Option<usize>, id: &Id) -> Result<Result<(), IdListError>, TxnError> {
+        let (last_seg_id, mut list_level, recorded) = self.chain_end(head)?;
+        let mut last_seg = { // TODO: refill segment under capacity
             let last_seg = seg_cell_by_id(&mut self.txn, last_seg_id)?;
             if let Some(seg) = last_seg { seg } else { return Ok(Err(IdListError::Unexpected)); }
         };
-        if match count_cell_list(&mut last_seg) {
+        let mut tail_moved = !recorded;
+        if match segment_size(&last_seg) {
             Ok(c) => c, Err(e) => return Ok(Err(e))
-        } >= *LIST_CAPACITY { // create new segment to prevent cell overflow
+        } + id_io::size(0) > *SEGMENT_CAPACITY { // create new segment to prevent cell overflow
             list_level += 1;
-            let (next_seg_id, next_seg_value) = empty_list_segment(&self.container_id, self.field_id, self.schema_id, list_level);
+            let (next_seg_id, next_seg_value) = empty_list_segment(&self.container_id, self.field_id, self.schema_id, shard, list_level);
             let next_seg_cell = Cell::new_with_id(ID_LIST_SCHEMA_ID, &next_seg_id, next_seg_value);
             self.txn.write(&next_seg_cell)?;
             set_map_by_key_id(&mut self.txn, &last_seg.id(), *NEXT_KEY_ID, Value::Id(next_seg_id))?;
             last_seg = next_seg_cell;
+            tail_moved = true;
+        }
+        let tail_in_head = tail_moved && last_seg.id() == head;
+        if tail_in_head {
+            if let &mut Value::Map(ref mut map) = &mut last_seg.data {
+                map.insert_key_id(*TAIL_KEY_ID, Value::Id(head));
+                map.insert_key_id(*DEPTH_KEY_ID, Value::U64(list_level as u64));
+            }
         }
-        if let &mut Value::Map(ref mut map) = &mut last_seg.data {
+        let raw_len = if let &mut Value::Map(ref mut map) = &mut last_seg.data {
             if let &mut Value::Array(ref mut array) = map.get_mut_by_key_id(*LIST_KEY_ID) {
                 array.push(Value::Id(*id));
+                array.len()
             } else {
                 return Ok(Err(IdListError::FormatError));
             }
         } else {
             return Ok(Err(IdListError::FormatError));
+        };
+        if raw_len >= PACK_THRESHOLD {
+            let ids = match segment_ids(&last_seg) {
+                Ok(ids) => ids, Err(e) => return Ok(Err(e))
+            };
+            if let Err(e) = set_segment_ids(&mut last_seg, ids) { return Ok(Err(e)); }
+        }
+        self.txn.update(&last_seg)?;
+        if tail_moved && !tail_in_head {
+            if let Some(mut head_cell) = self.txn.read(&head)? {
+                if let &mut Value::Map(ref mut map) = &mut head_cell.data {
+                    map.insert_key_id(*TAIL_KEY_ID, Value::Id(last_seg.id()));
+                    map.insert_key_id(*DEPTH_KEY_ID, Value::U64(list_level as u64));
+                } else { return Ok(Err(IdListError::FormatError)); }
+                self.txn.update(&head_cell)?;
+            } else { return Ok(Err(IdListError::Unexpected)); }
+        }
+        Ok(Ok(()))
+    }
+    pub fn add(&mut self, id: &Id) -> Result<Result<(), IdListError>, TxnError> {
+        let list_root_id = match self.get_root_list_id(true)? {
+            Ok(v) => v, Err(e) => return Ok(Err(e))
+        };
+        if let Some(heads) = self.shard_heads()? {
+            let shard = shard_of(id, heads.len());
+            if let Err(e) = self.append_to_chain(heads[shard], Some(shard), id)? { return Ok(Err(e)); }
+            return self.adjust_shard_count(&heads[shard], 1);
+        }
+        if let Err(e) = self.append_to_chain(list_root_id, None, id)? { return Ok(Err(e)); }
+        if let Err(e) = self.adjust_count(1)? { return Ok(Err(e)); }
+        let shards = self.shards;
+        match self.cached_count()? {
+            Some(count) if count >= shards.threshold && shards.count > 0 => self.split_into_shards(shards.count),
+            _ => Ok(Ok(()))
         }
-        Ok(Ok(self.txn.update(&last_seg)?))
     }
     pub fn remove(&mut self, id: &Id, all: bool) -> Result<Result<(), IdListError>, TxnError> {
-        let id_value = Value::Id(*id);
-        let mut contained_segs = { // collect affected segment cell ids
-            let mut iter = match self.iter()? {
-                Ok(v) => v, Err(e) => return Ok(Err(e))
-            };
-            let mut seg_ids = BTreeSet::new();
-            while let Some(iter_id) = iter.next() {
-                if iter_id == *id {
-                    if let Some(ref seg) = iter.current_seg {
-                        seg_ids.insert(seg.id());
-                        if !all { break; }
-                    } else {
-                        return Ok(Err(IdListError::Unexpected));
-                    }
+        let list_root_id = match self.get_root_list_id(false)? {
+            Ok(v) => v, Err(e) => return Ok(Err(e))
+        };
+        for (head, shard) in self.chains(list_root_id)? {
+            let segments: Vec<_> = IdListSegmentIterator::new(self.txn, head).collect();
+            let mut removed = 0;
+            for mut seg in segments { // rewrite affected segments
+                match segment_contains(&seg, id) {
+                    Ok(true) => {}, Ok(false) => continue, Err(e) => return Ok(Err(e))
+                }
+                let mut ids = match segment_ids(&seg) {
+                    Ok(ids) => ids, Err(e) => return Ok(Err(e))
+                };
+                if all {
+                    let len_before = ids.len();
+                    ids.retain(|list_id| list_id != id);
+                    removed += len_before - ids.len();
+                } else {
+                    let index = match ids.iter().position(|list_id| list_id == id) {
+                        Some(pos) => pos, None => return Ok(Err(IdListError::Unexpected))
+                    };
+                    ids.remove(index);
+                    removed += 1;
                 }
+                if let Err(e) = set_segment_ids(&mut seg, ids) { return Ok(Err(e)); }
+                self.txn.update(&seg)?;
+                if !all { break; }
             }
-            seg_ids
-        };
-        for seg_id in &contained_segs { // mutate cell array
-            match self.txn.read(seg_id)? {
-                Some(mut seg) => {
-                    if let &mut Value::Map(ref mut map) = &mut seg.data {
-                        if let &mut Value::Array(ref mut array) = map.get_mut_by_key_id(*LIST_KEY_ID) {
-                            if all {
-                                array.retain(|v| { !val_is_id(v, id) });
-                            } else {
-                                let index = match array.iter().position(|v| { val_is_id(v, id) }) {
-                                    Some(pos) => pos, None => return Ok(Err(IdListError::Unexpected))
-                                };
-                                array.remove(index);
-                            }
-                        } else {
-                            return Ok(Err(IdListError::FormatError));
-                        }
-                    } else {
-                        return Ok(Err(IdListError::FormatError));
-                    }
-                    self.txn.update(&seg);
-                    if !all { break; }
-                },
-                None => return Ok(Err(IdListError::Unexpected))
+            if removed > 0 {
+                let adjusted = match shard {
+                    None => self.adjust_count(-(removed as i64))?,
+                    Some(_) => self.adjust_shard_count(&head, -(removed as i64))?
+                };
+                if let Err(e) = adjusted { return Ok(Err(e)); }
+                if !all { break; }
             }
         }
         return Ok(Ok(()));
     }
+    // packs raw entries of every segment, also migrates segments written in the unpacked layout and
+    // packed blocks that have no restart points yet
+    pub fn compact(&mut self) -> Result<Result<(), IdListError>, TxnError> {
+        let list_root_id = match self.get_root_list_id(false)? {
+            Ok(v) => v, Err(e) => return Ok(Err(e))
+        };
+        let heads = self.chains(list_root_id)?.into_iter().map(|(head, _)| head).collect();
+        let segments: Vec<_> = IdListSegmentIterator::chained(self.txn, heads).collect();
+        for mut seg in segments {
+            let raw_len = match seg_raw_list(&seg) {
+                Ok(list) => list.len(), Err(e) => return Ok(Err(e))
+            };
+            let indexed = match seg_restarts(&seg) {
+                Ok(restarts) => restarts.is_some(), Err(e) => return Ok(Err(e))
+            };
+            let packed_len = match seg_packed_bytes(&seg) {
+                Ok(bytes) => bytes.len(), Err(e) => return Ok(Err(e))
+            };
+            if raw_len == 0 && (indexed || packed_len == 0) { continue; }
+            let ids = match segment_ids(&seg) {
+                Ok(ids) => ids, Err(e) => return Ok(Err(e))
+            };
+            if let Err(e) = set_segment_ids(&mut seg, ids) { return Ok(Err(e)); }
+            self.txn.update(&seg)?;
+        }
+        Ok(Ok(()))
+    }
     pub fn clear_segments(&mut self) -> Result<Result<(), IdListError>, TxnError> {
-        let list_root_id = match self.get_root_list_id(true)? {
+        let list_root_id = match self.get_root_list_id(false)? {
             Ok(v) => v, Err(e) => return Ok(Err(e))
         };
-        let segments: Vec<_> = IdListSegmentIdIterator::new(self.txn, list_root_id).collect();
+        if list_root_id.is_unit_id() { return Ok(Ok(())); } // never assigned, nothing to clear
+        let heads = self.chains(list_root_id)?.into_iter().map(|(head, _)| head).collect();
+        let segments: Vec<_> = IdListSegmentIdIterator::chained(self.txn, heads).collect();
         for seg_id in segments {
             self.txn.remove(&seg_id)?;
         }
+        // shard heads went with the segments, the type list describes an empty list again
+        let mut type_list_cell = match self.type_list_cell()? {
+            Some(cell) => cell, None => return Ok(Err(IdListError::Unexpected))
+        };
+        let pos = match self.type_list_pos(&type_list_cell) {
+            Some(pos) => pos, None => return Ok(Err(IdListError::Unexpected))
+        };
+        if let &mut Value::Array(ref mut type_list) = &mut type_list_cell.data[*ID_TYPES_MAP_ID] {
+            if let &mut Value::Map(ref mut pair) = &mut type_list[pos] {
+                pair.insert_key_id(*ID_TYPES_COUNT_ID, Value::U64(0));
+                pair.insert_key_id(*ID_TYPES_SHARDS_ID, Value::Array(Vec::new()));
+            } else { return Ok(Err(IdListError::FormatError)); }
+        } else { return Ok(Err(IdListError::FormatError)); }
+        self.txn.update(&type_list_cell)?;
         return Ok(Ok(()))
     }
+    // clears the list and takes it out of the type list, it reads as never assigned afterwards
+    pub fn discard(&mut self) -> Result<Result<(), IdListError>, TxnError> {
+        if let Err(e) = self.clear_segments()? { return Ok(Err(e)); }
+        let mut type_list_cell = match self.type_list_cell()? {
+            Some(cell) => cell, None => return Ok(Ok(()))
+        };
+        let pos = match self.type_list_pos(&type_list_cell) {
+            Some(pos) => pos, None => return Ok(Ok(()))
+        };
+        if let &mut Value::Array(ref mut type_list) = &mut type_list_cell.data[*ID_TYPES_MAP_ID] {
+            type_list.remove(pos);
+        } else { return Ok(Err(IdListError::FormatError)); }
+        self.txn.update(&type_list_cell)?;
+        Ok(Ok(()))
+    }
 }
 
 pub struct IdListSegmentIdIterator<'a> {
     pub txn: &'a Transaction,
     next: Id,
-    level: u32
+    level: u32,
+    pending_heads: Vec<Id>
 }
 
 impl <'a> IdListSegmentIdIterator<'a> {
     pub fn new(txn: &'a Transaction, head_id: Id) -> IdListSegmentIdIterator<'a> {
+        Self::chained(txn, vec![head_id])
+    }
+    // walks several chains one after another, used for sharded lists
+    pub fn chained(txn: &'a Transaction, mut heads: Vec<Id>) -> IdListSegmentIdIterator<'a> {
+        heads.reverse();
+        let first = heads.pop().unwrap_or_else(Id::unit_id);
         IdListSegmentIdIterator {
             txn: txn,
-            next: head_id,
-            level: 1
+            next: first,
+            level: 1,
+            pending_heads: heads
         }
     }
 }
@@ -329,20 +825,26 @@ impl <'a> Iterator for IdListSegmentIdIterator<'a> {
     type Item = Id;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if !self.next.is_unit_id() {
-            match self.txn.read_selected(&self.next, &*NEXT_KEY_ID_VEC) {
-                Ok(Some(fields)) => {
-                    let current_id = self.next;
+        loop {
+            if !self.next.is_unit_id() {
+                let current_id = self.next;
+                self.next = Id::unit_id();
+                if let Ok(Some(fields)) = self.txn.read_selected(&current_id, &*NEXT_KEY_ID_VEC) {
                     if let Some(&Value::Id(ref id)) = fields.get(0) {
                         self.next = *id;
                         self.level += 1;
                         return Some(current_id);
                     }
+                }
+            }
+            match self.pending_heads.pop() {
+                Some(head) => {
+                    self.next = head;
+                    self.level = 1;
                 },
-                _ => {}
+                None => return None
             }
         }
-        None
     }
 }
 
@@ -356,6 +858,11 @@ impl <'a>IdListSegmentIterator<'a> {
             id_iter: IdListSegmentIdIterator::new(txn, head_id)
         }
     }
+    pub fn chained(txn: &'a Transaction, heads: Vec<Id>) -> IdListSegmentIterator<'a> {
+        IdListSegmentIterator {
+            id_iter: IdListSegmentIdIterator::chained(txn, heads)
+        }
+    }
 }
 
 impl <'a> Iterator for IdListSegmentIterator <'a> {
@@ -371,28 +878,46 @@ impl <'a> Iterator for IdListSegmentIterator <'a> {
     }
 }
 
+// Iteration stops at the first segment that cannot be decoded, the error is kept for take_error
 pub struct IdListIterator<'a> {
     pub segments: IdListSegmentIterator<'a>,
     current_seg: Option<Cell>,
-    current_pos: u32
+    current_ids: Vec<Id>,
+    current_pos: u32,
+    error: Option<IdListError>
 }
 
 impl <'a> IdListIterator <'a> {
     pub fn next_seg(&mut self) {
         self.current_seg = self.segments.next();
+        self.current_ids = match self.current_seg.as_ref().map(segment_ids) {
+            Some(Ok(ids)) => ids,
+            Some(Err(e)) => {
+                self.error = Some(e);
+                self.current_seg = None;
+                Vec::new()
+            },
+            None => Vec::new()
+        };
         self.current_pos = 0;
     }
-
-    fn get_curr_seg_list(&self) -> Option<&Vec<Value>> {
-        if let Some(ref cell) = self.current_seg {
-            let pos = self.current_pos;
-            if let &Value::Map(ref map) = &cell.data {
-                if let &Value::Array(ref list) = map.get_by_key_id(*LIST_KEY_ID) {
-                    return Some(&list);
-                }
-            }
+    // the error that ended the iteration early, None when every segment was read
+    pub fn take_error(&mut self) -> Option<IdListError> {
+        self.error.take()
+    }
+    pub fn collect_ids(mut self) -> Result<Vec<Id>, IdListError> {
+        let ids = self.by_ref().collect();
+        match self.take_error() {
+            Some(e) => Err(e), None => Ok(ids)
         }
-        None
+    }
+    pub fn count_ids(mut self) -> Result<usize, IdListError> {
+        if let Some(e) = self.take_error() { return Err(e); }
+        let mut count = self.current_ids.len();
+        for seg in self.segments {
+            count += count_cell_list(&seg)?;
+        }
+        Ok(count)
     }
 }
 
@@ -400,55 +925,34 @@ impl <'a> Iterator for IdListIterator<'a> {
     type Item = Id;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let mut need_next_seg = false;
-        let item = if let Some(list) = self.get_curr_seg_list() {
-            if let Some(&Value::Id(id)) = list.get(self.current_pos as usize) {
-                Some(id)
-            } else {
-                need_next_seg = true;
-                None
+        loop {
+            if self.current_seg.is_none() {
+                return None;
             }
-        } else {
-            None
-        };
-        if need_next_seg {
-            self.next_seg();
-            self.next()
-        } else {
-            if item.is_some() {
+            if let Some(id) = self.current_ids.get(self.current_pos as usize).cloned() {
                 self.current_pos += 1;
+                return Some(id);
             }
-            item
+            self.next_seg();
         }
     }
 
     fn last(self) -> Option<Self::Item> where Self: Sized {
-        let last_value = self.get_curr_seg_list().map(|l| l.last()).unwrap_or(None).cloned();
-        if let Some(last_seg) = self.segments.last() {
-            if let &Value::Map(ref map) = &last_seg.data {
-                if let &Value::Array(ref list) = map.get_by_key_id(*LIST_KEY_ID) {
-                    if let Some(&Value::Id(id)) = list.last() {
-                        return Some(id);
-                    }
+        let mut last_value = self.current_ids.last().cloned();
+        for seg in self.segments {
+            if let Ok(ids) = segment_ids(&seg) {
+                if let Some(id) = ids.last() {
+                    last_value = Some(*id);
                 }
             }
         }
-        return if let Some(Value::Id(id)) = last_value {
-            Some(id)
-        } else {
-            None
-        };
+        return last_value;
     }
+    // undecodable segments count as empty here, count_ids reports them
     fn count(self) -> usize where Self: Sized {
-        let mut count = self.get_curr_seg_list()
-            .map(|l| l.len())
-            .unwrap_or(0);
+        let mut count = self.current_ids.len();
         for seg in self.segments {
-            if let &Value::Map(ref map) = &seg.data {
-                if let &Value::Array(ref list) = map.get_by_key_id(*LIST_KEY_ID) {
-                    count += list.len();
-                }
-            }
+            count += count_cell_list(&seg).unwrap_or(0);
         }
         return count;
     }
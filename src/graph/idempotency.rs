@@ -0,0 +1,139 @@
+// Idempotency keys for new_vertex and link. The first call with a key writes a record cell holding
+// the id of what it created in the same transaction as the write, so a client retrying after a
+// timeout either sees the record and gets the vertex or edge of the first call back, or the first
+// call never committed and the write happens now. Keys of new_vertex and link are separate spaces.
+// Records are kept until forget_idempotency_key removes them.
+
+use neb::ram::schema::Field;
+use neb::ram::types::{TypeId, Id, Map, Value, key_hash};
+use neb::ram::cell::Cell;
+use neb::client::transaction::TxnError;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use graph::{GraphTransaction, EdgeDirection, NewVertexError, LinkVerticesError, edge_attr_from_schema};
+use graph::edge::{self, EdgeError};
+use graph::vertex::{Vertex, ToVertexId};
+use graph::savepoint::Undo;
+use server::schema::ToSchemaId;
+
+pub static IDEMPOTENCY_SCHEMA_ID: u32 = 170;
+pub const RESULT_KEY: &'static str = "result";
+pub const CREATED_KEY: &'static str = "created";
+
+lazy_static! {
+    pub static ref RESULT_KEY_ID: u64 = key_hash(&String::from(RESULT_KEY));
+    pub static ref CREATED_KEY_ID: u64 = key_hash(&String::from(CREATED_KEY));
+    pub static ref IDEMPOTENCY_RECORD: Field = Field::new("*", TypeId::Map as u32, false, false, Some(vec![
+        Field::new(&String::from(RESULT_KEY), TypeId::Id as u32, false, false, None),
+        // milliseconds since the epoch
+        Field::new(&String::from(CREATED_KEY), TypeId::U64 as u32, false, false, None)
+    ]));
+}
+
+#[derive(Debug, Clone)]
+pub enum Outcome<T> {
+    // first call with the key
+    Applied(T),
+    // the key was used before, with what that call created, None when it is gone since
+    Replayed(Option<T>)
+}
+
+impl <T> Outcome<T> {
+    pub fn is_replayed(&self) -> bool {
+        match self { &Outcome::Replayed(_) => true, _ => false }
+    }
+}
+
+const NEW_VERTEX_OP: &'static str = "new_vertex";
+const LINK_OP: &'static str = "link";
+
+fn record_id(op: &str, key: &str) -> Id {
+    Id::new(key_hash(&String::from("IDEMPOTENCY")), key_hash(&format!("{}:{}", op, key)))
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() * 1000 + d.subsec_nanos() as u64 / 1_000_000)
+        .unwrap_or(0)
+}
+
+impl <'a> GraphTransaction<'a> {
+    fn idempotency_record(&self, record_id: &Id) -> Result<Option<Id>, TxnError> {
+        Ok(match self.neb_txn.read_selected(record_id, &vec![*RESULT_KEY_ID])? {
+            Some(values) => match values.get(0) { Some(&Value::Id(id)) => Some(id), _ => Some(Id::unit_id()) },
+            None => None
+        })
+    }
+
+    fn write_idempotency_record(&self, record_id: &Id, result: Id) -> Result<(), TxnError> {
+        let mut data = Map::new();
+        data.insert_key_id(*RESULT_KEY_ID, Value::Id(result));
+        data.insert_key_id(*CREATED_KEY_ID, Value::U64(now_ms()));
+        self.neb_txn.write(&Cell::new_with_id(IDEMPOTENCY_SCHEMA_ID, record_id, Value::Map(data)))?;
+        self.record_undo(Undo::IdempotencyRecord(*record_id));
+        Ok(())
+    }
+
+    pub fn new_vertex_idempotent<S>(&self, key: &str, schema: S, data: Map)
+        -> Result<Result<Outcome<Vertex>, NewVertexError>, TxnError>
+        where S: ToSchemaId
+    {
+        let record_id = record_id(NEW_VERTEX_OP, key);
+        if let Some(vertex_id) = self.idempotency_record(&record_id)? {
+            return Ok(Ok(Outcome::Replayed(self.read_vertex(vertex_id)?)));
+        }
+        let vertex = match self.new_vertex(schema, data)? { Ok(vertex) => vertex, Err(e) => return Ok(Err(e)) };
+        self.write_idempotency_record(&record_id, vertex.cell.id())?;
+        Ok(Ok(Outcome::Applied(vertex)))
+    }
+
+    // The record holds the id of the edge body, edges without a body are found again by their ends
+    pub fn link_idempotent<V, S>(&self, key: &str, from: V, schema: S, to: V, body: Option<Map>)
+        -> Result<Result<Outcome<edge::Edge>, LinkVerticesError>, TxnError>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        let (from_id, to_id) = (from.to_id(), to.to_id());
+        let schema_id = schema.to_id(&self.schemas);
+        let record_id = record_id(LINK_OP, key);
+        if let Some(body_id) = self.idempotency_record(&record_id)? {
+            return Ok(self.find_edge(from_id, schema_id, to_id, body_id)?
+                .map(Outcome::Replayed)
+                .map_err(LinkVerticesError::EdgeError));
+        }
+        let edge = match self.link(from_id, schema_id, to_id, body)? { Ok(edge) => edge, Err(e) => return Ok(Err(e)) };
+        let body_id = edge.get_data().as_ref().map(|cell| cell.id()).unwrap_or_else(Id::unit_id);
+        self.write_idempotency_record(&record_id, body_id)?;
+        Ok(Ok(Outcome::Applied(edge)))
+    }
+
+    fn find_edge(&self, from: Id, schema_id: u32, to: Id, body_id: Id)
+        -> Result<Result<Option<edge::Edge>, EdgeError>, TxnError>
+    {
+        let (_, edge_attr) = match edge_attr_from_schema(schema_id, &self.schemas) {
+            Err(e) => return Ok(Err(e)), Ok(t) => t
+        };
+        let direction = match edge_attr.edge_type {
+            edge::EdgeType::Directed => EdgeDirection::Outbound,
+            edge::EdgeType::Undirected => EdgeDirection::Undirected
+        };
+        let edges = match self.edges(&from, schema_id, direction, &None)? {
+            Ok(edges) => edges, Err(e) => return Ok(Err(e))
+        };
+        Ok(Ok(edges.into_iter().find(|edge| {
+            let body = edge.get_data().as_ref().map(|cell| cell.id()).unwrap_or_else(Id::unit_id);
+            edge.one_opposite_id_vertex_id(&from) == Some(&to) && body == body_id
+        })))
+    }
+
+    // the key can be used again afterwards, for a new write
+    pub fn forget_idempotency_key(&self, key: &str) -> Result<(), TxnError> {
+        for op in &[NEW_VERTEX_OP, LINK_OP] {
+            let record_id = record_id(op, key);
+            if self.neb_txn.read_selected(&record_id, &vec![*RESULT_KEY_ID])?.is_some() {
+                self.neb_txn.remove(&record_id)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,147 @@
+// Id strategies for vertices of schemas without a key, keyed schemas always take the ids derived
+// from their keys. Ids are two halves, neb places cells by the higher one and ids sort by both:
+//   Hash, the default, leaves neb's random ids alone.
+//   Snowflake puts 41 bits of milliseconds since 2018, 10 bits of node and a 12 bit sequence in
+//   both halves, so ids sort by creation time across the cluster up to clock skew between servers.
+//   UuidV7 writes a version 7 UUID over both halves, the higher half leads with milliseconds since the epoch.
+//   Sequential counts per schema in a sequence cell written in the same transaction as the vertex,
+//   every new vertex of the schema contends on that cell, so it suits low write rates. The higher half
+//   holds the number, the lower one the schema and the number, which keeps ids unique across schemas.
+// Placement hints and policies still take over the higher half, such ids stay unique by their lower
+// half but sort by placement first.
+
+use neb::ram::schema::Field;
+use neb::ram::types::{TypeId, Id, Map, Value, key_hash};
+use neb::ram::cell::Cell;
+use neb::client::transaction::TxnError;
+use bifrost_hasher::hash_str;
+use parking_lot::Mutex;
+use rand::{thread_rng, Rng};
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use graph::GraphTransaction;
+use graph::placement::{Placement, PlacementPolicy};
+use server::schema::SchemaContainer;
+
+pub static ID_SEQUENCE_SCHEMA_ID: u32 = 180;
+pub const NEXT_KEY: &'static str = "next";
+
+// 2018-01-01 in milliseconds since the epoch
+const SNOWFLAKE_EPOCH: u64 = 1514764800000;
+const NODE_BITS: u64 = 10;
+const SEQUENCE_BITS: u64 = 12;
+// sequential ids leave 40 bits of the lower half to the number
+const SEQUENTIAL_BITS: u64 = 40;
+
+lazy_static! {
+    pub static ref NEXT_KEY_ID: u64 = key_hash(&String::from(NEXT_KEY));
+    pub static ref ID_SEQUENCE: Field = Field::new("*", TypeId::Map as u32, false, false, Some(vec![
+        Field::new(&String::from(NEXT_KEY), TypeId::U64 as u32, false, false, None)
+    ]));
+    // drawn at random until the server sets it from its address
+    static ref NODE: AtomicUsize = AtomicUsize::new(thread_rng().gen::<usize>() & ((1 << NODE_BITS) - 1));
+    // milliseconds and sequence of the last snowflake
+    static ref LAST_SNOWFLAKE: Mutex<(u64, u64)> = Mutex::new((0, 0));
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdStrategy {
+    Hash,
+    Snowflake,
+    UuidV7,
+    Sequential
+}
+
+impl Default for IdStrategy {
+    fn default() -> IdStrategy {
+        IdStrategy::Hash
+    }
+}
+
+// the node bits of snowflake ids, servers set it from their address
+pub fn set_node(node_name: &str) {
+    NODE.store((hash_str(node_name) & ((1 << NODE_BITS) - 1)) as usize, Ordering::Relaxed);
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() * 1000 + d.subsec_nanos() as u64 / 1_000_000)
+        .unwrap_or(0)
+}
+
+pub fn snowflake() -> u64 {
+    let mut last = LAST_SNOWFLAKE.lock();
+    let mut ms = ::std::cmp::max(now_ms(), last.0);
+    let sequence = if ms == last.0 { (last.1 + 1) & ((1 << SEQUENCE_BITS) - 1) } else { 0 };
+    // the sequence ran out within the millisecond, borrow the next one
+    if ms == last.0 && sequence == 0 { ms += 1; }
+    *last = (ms, sequence);
+    (ms - SNOWFLAKE_EPOCH) << (NODE_BITS + SEQUENCE_BITS) | (NODE.load(Ordering::Relaxed) as u64) << SEQUENCE_BITS | sequence
+}
+
+pub fn uuid_v7() -> Id {
+    let mut rng = thread_rng();
+    let higher = now_ms() << 16 | 0x7 << 12 | (rng.gen::<u64>() & 0xfff);
+    let lower = 0b10 << 62 | (rng.gen::<u64>() >> 2);
+    Id::new(higher, lower)
+}
+
+pub fn is_unkeyed(schemas: &SchemaContainer, schema_id: u32) -> bool {
+    schemas.get_neb_schema(schema_id).map_or(false, |schema| schema.str_key_field.is_none())
+}
+
+// whether placement chooses the higher half of the schema's ids
+pub fn is_placed(schemas: &SchemaContainer, schema_id: u32, placement: &Placement) -> bool {
+    *placement != Placement::Auto || schemas.placement(schema_id) != PlacementPolicy::Random
+}
+
+fn sequence_id(schema_id: u32) -> Id {
+    Id::new(key_hash(&String::from("ID_SEQUENCE")), schema_id as u64)
+}
+
+impl <'a> GraphTransaction<'a> {
+    // the next number of the schema's sequence, from 1
+    pub fn next_in_sequence(&self, schema_id: u32) -> Result<u64, TxnError> {
+        let id = sequence_id(schema_id);
+        let next = match self.neb_txn.read_selected(&id, &vec![*NEXT_KEY_ID])? {
+            Some(values) => match values.get(0) { Some(&Value::U64(next)) => next, _ => 1 },
+            None => 1
+        };
+        let mut data = Map::new();
+        data.insert_key_id(*NEXT_KEY_ID, Value::U64(next + 1));
+        self.neb_txn.write(&Cell::new_with_id(ID_SEQUENCE_SCHEMA_ID, &id, Value::Map(data)))?;
+        Ok(next)
+    }
+
+    // Gives the cell of a new vertex the id of its schema's strategy, keyed schemas are left alone
+    pub(super) fn assign_id(&self, cell: &mut Cell, placement: &Placement) -> Result<(), TxnError> {
+        let schema_id = cell.header.schema;
+        let strategy = self.schemas.id_strategy(schema_id);
+        if strategy == IdStrategy::Hash || !is_unkeyed(&self.schemas, schema_id) { return Ok(()); }
+        // copies of vertices from elsewhere keep their ids
+        if let &Placement::Exact(_) = placement { return Ok(()); }
+        let sequence = match strategy {
+            IdStrategy::Sequential => Some(self.next_in_sequence(schema_id)?),
+            _ => None
+        };
+        apply(cell, strategy, sequence, is_placed(&self.schemas, schema_id, placement));
+        Ok(())
+    }
+}
+
+// Writes the id of the strategy over the cell's, keeping the higher half when placement chose it.
+// Sequential ids take the number drawn from the schema's sequence.
+pub fn apply(cell: &mut Cell, strategy: IdStrategy, sequence: Option<u64>, placed: bool) {
+    let id = match (strategy, sequence) {
+        (IdStrategy::Hash, _) => return,
+        (IdStrategy::Snowflake, _) => { let flake = snowflake(); Id::new(flake, flake) },
+        (IdStrategy::UuidV7, _) => uuid_v7(),
+        (IdStrategy::Sequential, Some(n)) =>
+            Id::new(n, (cell.header.schema as u64) << SEQUENTIAL_BITS | (n & ((1 << SEQUENTIAL_BITS) - 1))),
+        (IdStrategy::Sequential, None) => return
+    };
+    if !placed { cell.header.partition = id.higher; }
+    cell.header.hash = id.lower;
+}
@@ -0,0 +1,199 @@
+// The k shortest loopless paths between two vertices by Yen's algorithm. Every round takes the last
+// path found, and for each vertex on it searches the shortest way to the target that leaves the
+// shared root of the paths found so far by another edge, the cheapest of those becomes the next path.
+// Weights are the number in a field of the edge bodies, or one per hop without a field. Edges without
+// the field or with a negative weight are not taken. Edge lists are read once per vertex and shared
+// by all the searches.
+
+use neb::ram::types::{Id, key_hash};
+use neb::dovahkiin::types::Value;
+use neb::client::transaction::TxnError;
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use graph::{GraphTransaction, EdgeDirection, edge_attr_from_schema};
+use graph::edge::{EdgeError, EdgeType};
+use server::path_pattern::Path;
+
+// the opposite vertex, the body cell and the weight of an edge
+type Adjacent = (Id, Option<Id>, f64);
+
+// the vertex an edge leaves and its position in that vertex's adjacency
+type EdgeRef = (Id, usize);
+
+#[derive(Clone)]
+struct Found {
+    vertices: Vec<Id>,
+    edges: Vec<EdgeRef>,
+    weight: f64
+}
+
+// ordered for a min heap, by weight and then by hops
+struct Queued(f64, usize, Id);
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Queued) -> bool { self.cmp(other) == Ordering::Equal }
+}
+impl Eq for Queued {}
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Queued) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+impl Ord for Queued {
+    fn cmp(&self, other: &Queued) -> Ordering {
+        other.0.partial_cmp(&self.0).unwrap_or(Ordering::Equal).then_with(|| other.1.cmp(&self.1))
+    }
+}
+
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        &Value::I8(n) => Some(n as f64), &Value::I16(n) => Some(n as f64),
+        &Value::I32(n) => Some(n as f64), &Value::I64(n) => Some(n as f64),
+        &Value::U8(n) => Some(n as f64), &Value::U16(n) => Some(n as f64),
+        &Value::U32(n) => Some(n as f64), &Value::U64(n) => Some(n as f64),
+        &Value::F32(n) => Some(n as f64), &Value::F64(n) => Some(n),
+        _ => None
+    }
+}
+
+struct Search<'t, 'a: 't> {
+    txn: &'t GraphTransaction<'a>,
+    schema_id: u32,
+    direction: EdgeDirection,
+    weight_key: Option<u64>,
+    adjacency: HashMap<Id, Vec<Adjacent>>
+}
+
+impl <'t, 'a> Search<'t, 'a> {
+    fn load(&mut self, id: Id) -> Result<Result<(), EdgeError>, TxnError> {
+        if self.adjacency.contains_key(&id) { return Ok(Ok(())); }
+        let edges = match self.txn.edges(id, self.schema_id, self.direction, &None)? {
+            Ok(edges) => edges, Err(e) => return Ok(Err(e))
+        };
+        let mut adjacent = Vec::with_capacity(edges.len());
+        for edge in edges {
+            let opposite = match edge.one_opposite_id_vertex_id(&id) { Some(opposite) => *opposite, None => continue };
+            let body = edge.get_data().as_ref();
+            let weight = match self.weight_key {
+                None => Some(1.0),
+                Some(key) => body.and_then(|cell| match cell.data {
+                    Value::Map(ref map) => number(map.get_by_key_id(key)),
+                    _ => None
+                })
+            };
+            match weight {
+                Some(weight) if weight >= 0.0 => adjacent.push((opposite, body.map(|cell| cell.id()), weight)),
+                _ => {}
+            }
+        }
+        self.adjacency.insert(id, adjacent);
+        Ok(Ok(()))
+    }
+
+    // Dijkstra from one vertex to the other around the vertices and edges removed
+    fn shortest(&mut self, from: Id, to: Id, removed_vertices: &HashSet<Id>, removed_edges: &HashSet<EdgeRef>)
+        -> Result<Result<Option<Found>, EdgeError>, TxnError>
+    {
+        let mut best: HashMap<Id, (f64, Option<EdgeRef>)> = HashMap::new();
+        let mut done = HashSet::new();
+        let mut queue = BinaryHeap::new();
+        best.insert(from, (0.0, None));
+        queue.push(Queued(0.0, 0, from));
+        while let Some(Queued(weight, hops, vertex)) = queue.pop() {
+            if !done.insert(vertex) { continue; }
+            if vertex == to {
+                let mut found = Found { vertices: vec![to], edges: Vec::new(), weight };
+                let mut current = to;
+                while let Some(&(_, Some(edge))) = best.get(&current) {
+                    found.edges.push(edge);
+                    found.vertices.push(edge.0);
+                    current = edge.0;
+                }
+                found.vertices.reverse();
+                found.edges.reverse();
+                return Ok(Ok(Some(found)));
+            }
+            if let Err(e) = self.load(vertex)? { return Ok(Err(e)); }
+            for (index, &(opposite, _, edge_weight)) in self.adjacency[&vertex].iter().enumerate() {
+                if removed_vertices.contains(&opposite) || removed_edges.contains(&(vertex, index)) { continue; }
+                let candidate = weight + edge_weight;
+                let better = best.get(&opposite).map_or(true, |&(known, _)| candidate < known);
+                if better && !done.contains(&opposite) {
+                    best.insert(opposite, (candidate, Some((vertex, index))));
+                    queue.push(Queued(candidate, hops + 1, opposite));
+                }
+            }
+        }
+        Ok(Ok(None))
+    }
+
+    fn to_path(&self, found: &Found) -> Path {
+        Path {
+            vertices: found.vertices.clone(),
+            edges: found.edges.iter().map(|&(from, index)| self.adjacency[&from][index].1).collect(),
+            weight: Some(found.weight)
+        }
+    }
+}
+
+impl <'a> GraphTransaction<'a> {
+    // Up to k loopless paths from one vertex to the other over the edge schema, the lightest first
+    pub fn k_shortest_paths(&self, from: Id, to: Id, edge_schema: u32, k: usize, weight_field: Option<&str>)
+        -> Result<Result<Vec<Path>, EdgeError>, TxnError>
+    {
+        let edge_attr = match edge_attr_from_schema(edge_schema, &self.schemas) {
+            Ok((_, edge_attr)) => edge_attr, Err(e) => return Ok(Err(e))
+        };
+        let mut search = Search {
+            txn: self,
+            schema_id: edge_schema,
+            direction: match edge_attr.edge_type {
+                EdgeType::Directed => EdgeDirection::Outbound,
+                EdgeType::Undirected => EdgeDirection::Undirected
+            },
+            weight_key: weight_field.map(|field| key_hash(&field.to_string())),
+            adjacency: HashMap::new()
+        };
+        let mut accepted: Vec<Found> = Vec::new();
+        if k == 0 { return Ok(Ok(Vec::new())); }
+        match search.shortest(from, to, &HashSet::new(), &HashSet::new())? {
+            Ok(Some(found)) => accepted.push(found),
+            Ok(None) => return Ok(Ok(Vec::new())),
+            Err(e) => return Ok(Err(e))
+        }
+        let mut candidates: Vec<Found> = Vec::new();
+        while accepted.len() < k {
+            let last = accepted[accepted.len() - 1].clone();
+            for spur in 0..last.edges.len() {
+                let spur_vertex = last.vertices[spur];
+                let root_vertices = &last.vertices[..spur + 1];
+                let root_edges = &last.edges[..spur];
+                // the next edge of every path found sharing this root is taken already
+                let removed_edges: HashSet<EdgeRef> = accepted.iter()
+                    .filter(|path| path.edges.len() > spur && &path.edges[..spur] == root_edges)
+                    .map(|path| path.edges[spur])
+                    .collect();
+                let removed_vertices: HashSet<Id> = root_vertices[..spur].iter().cloned().collect();
+                let spur_path = match search.shortest(spur_vertex, to, &removed_vertices, &removed_edges)? {
+                    Ok(Some(spur_path)) => spur_path, Ok(None) => continue, Err(e) => return Ok(Err(e))
+                };
+                let root_weight: f64 = root_edges.iter().map(|&(from, index)| search.adjacency[&from][index].2).sum();
+                let mut vertices = root_vertices.to_vec();
+                vertices.extend(spur_path.vertices.into_iter().skip(1));
+                let mut edges = root_edges.to_vec();
+                edges.extend(spur_path.edges);
+                let candidate = Found { vertices, edges, weight: root_weight + spur_path.weight };
+                if !candidates.iter().chain(accepted.iter()).any(|path| path.edges == candidate.edges) {
+                    candidates.push(candidate);
+                }
+            }
+            if candidates.is_empty() { break; }
+            let lightest = (0..candidates.len()).min_by(|&a, &b| {
+                let (a, b) = (&candidates[a], &candidates[b]);
+                a.weight.partial_cmp(&b.weight).unwrap_or(Ordering::Equal).then_with(|| a.edges.len().cmp(&b.edges.len()))
+            }).unwrap();
+            accepted.push(candidates.swap_remove(lightest));
+        }
+        Ok(Ok(accepted.iter().map(|found| search.to_path(found)).collect()))
+    }
+}
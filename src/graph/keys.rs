@@ -0,0 +1,67 @@
+// Keyed schemas derive vertex ids from a hash of the key, the id alone cannot be turned back into the
+// key and two keys may hash to the same id. New vertices of keyed schemas keep the key they were created
+// with in the reserved _key field, as json of the value so keys of every type fit one template field.
+// The field is not touched afterwards, like the id it stays with the key the vertex was created by.
+// A new vertex whose id is taken compares the keys: the same key is the usual duplicate, another one
+// is a collision, reported with the key holding the id. Vertices written before the field existed
+// have no key to return or compare.
+
+use neb::ram::schema::Schema;
+use neb::ram::types::{Id, Map, Value, key_hash};
+use neb::client::transaction::TxnError;
+use serde_json;
+
+use graph::GraphTransaction;
+use graph::fields::KEY_KEY_ID;
+use graph::vertex::ToVertexId;
+
+// the value at the key path of the vertex data
+pub fn key_value<'a>(data: &'a Map, path: &[String]) -> Option<&'a Value> {
+    let (last, parents) = match path.split_last() { Some(split) => split, None => return None };
+    let mut map = data;
+    for name in parents {
+        map = match map.get_by_key_id(key_hash(name)) { &Value::Map(ref inner) => inner, _ => return None };
+    }
+    match map.get_by_key_id(key_hash(last)) { &Value::Null => None, value => Some(value) }
+}
+
+pub fn encode(key: &Value) -> Option<Value> {
+    serde_json::to_string(key).ok().map(Value::String)
+}
+
+pub fn decode(stored: &Value) -> Option<Value> {
+    match stored {
+        &Value::String(ref json) => serde_json::from_str(json).ok(),
+        _ => None
+    }
+}
+
+// keeps the key of a new vertex of a keyed schema in its data
+pub fn record(neb_schema: &Schema, data: &mut Map) {
+    let key = match neb_schema.str_key_field {
+        Some(ref path) => match key_value(data, path).and_then(encode) { Some(key) => key, None => return },
+        None => return
+    };
+    data.insert_key_id(*KEY_KEY_ID, key);
+}
+
+// the key kept in the data of a vertex cell
+pub fn stored_key(data: &Value) -> Option<Value> {
+    match data {
+        &Value::Map(ref map) => decode(map.get_by_key_id(*KEY_KEY_ID)),
+        _ => None
+    }
+}
+
+impl <'a> GraphTransaction<'a> {
+    // the key the vertex was created with, None for unkeyed schemas and vertices that predate keeping it
+    pub fn key_of<V>(&self, vertex: V) -> Result<Option<Value>, TxnError> where V: ToVertexId {
+        let values = self.neb_txn.read_selected(&vertex.to_id(), &vec![*KEY_KEY_ID])?;
+        Ok(values.and_then(|values| values.get(0).and_then(decode)))
+    }
+
+    // the other key already holding the id of a new vertex with this key
+    pub(super) fn colliding_key(&self, id: Id, key: &Value) -> Result<Option<Value>, TxnError> {
+        Ok(self.key_of(id)?.and_then(|existing| if &existing != key { Some(existing) } else { None }))
+    }
+}
@@ -0,0 +1,150 @@
+// Graph data held in process memory, loaded by scanning schemas or from an extracted subgraph.
+// Vertices keep the order they were added in and that order is their petgraph node index,
+// so results of petgraph algorithms map back with MemGraph::vertices()[index.index()].
+
+use neb::ram::types::Id;
+use futures::prelude::*;
+use futures::stream;
+use petgraph;
+use petgraph::graph::NodeIndex;
+
+use std::collections::HashMap;
+
+use graph::{Graph, ScanVerticesError, ScanEdgesError};
+use graph::edge;
+use graph::subgraph::Subgraph;
+use graph::vertex::Vertex;
+use server::schema::ToSchemaId;
+
+#[derive(Debug)]
+pub enum MemGraphError {
+    ScanVerticesError(ScanVerticesError),
+    ScanEdgesError(ScanEdgesError)
+}
+
+#[derive(Debug, Clone)]
+pub struct MemEdge {
+    pub from: usize,
+    pub to: usize,
+    pub edge: edge::Edge
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MemGraph {
+    vertices: Vec<Vertex>,
+    edges: Vec<MemEdge>,
+    index: HashMap<Id, usize>
+}
+
+impl MemGraph {
+    pub fn new() -> MemGraph {
+        MemGraph::default()
+    }
+
+    // Every vertex of the vertex schemas and every edge of the edge schemas between them.
+    // Edges with an end outside the loaded vertices are left out.
+    pub fn from_scans<S>(graph: &Graph, vertex_schemas: Vec<S>, edge_schemas: Vec<S>)
+        -> impl Future<Item = MemGraph, Error = MemGraphError>
+        where S: ToSchemaId
+    {
+        let vertex_scans: Vec<_> = vertex_schemas.into_iter()
+            .map(|schema| graph.scan_vertices::<_, String>(schema, &None, None)
+                .map_err(MemGraphError::ScanVerticesError))
+            .collect();
+        let edge_scans: Vec<_> = edge_schemas.into_iter()
+            .map(|schema| graph.scan_edges::<_, String>(schema, &None)
+                .map_err(MemGraphError::ScanEdgesError))
+            .collect();
+        stream::iter_ok(vertex_scans).flatten()
+            .fold(MemGraph::new(), |mut mem, vertex| {
+                mem.add_vertex(vertex);
+                Ok::<_, MemGraphError>(mem)
+            })
+            .and_then(move |mem| stream::iter_ok(edge_scans).flatten()
+                .fold(mem, |mut mem, edge| {
+                    mem.add_edge(edge);
+                    Ok::<_, MemGraphError>(mem)
+                }))
+    }
+
+    // returns the local index, adding a vertex twice keeps the first copy
+    pub fn add_vertex(&mut self, vertex: Vertex) -> usize {
+        let id = vertex.cell.id();
+        if let Some(index) = self.index.get(&id) {
+            return *index;
+        }
+        let index = self.vertices.len();
+        self.vertices.push(vertex);
+        self.index.insert(id, index);
+        index
+    }
+
+    // None when either end is not in this graph
+    pub fn add_edge(&mut self, edge: edge::Edge) -> Option<usize> {
+        let (from, to) = {
+            let (from_id, to_id) = edge.ends();
+            match (self.index_of(from_id), self.index_of(to_id)) {
+                (Some(from), Some(to)) => (from, to),
+                _ => return None
+            }
+        };
+        self.edges.push(MemEdge { from, to, edge });
+        Some(self.edges.len() - 1)
+    }
+
+    pub fn index_of(&self, id: &Id) -> Option<usize> {
+        self.index.get(id).cloned()
+    }
+
+    pub fn vertex(&self, id: &Id) -> Option<&Vertex> {
+        self.index_of(id).map(|i| &self.vertices[i])
+    }
+
+    pub fn vertices(&self) -> &[Vertex] {
+        &self.vertices
+    }
+
+    pub fn edges(&self) -> &[MemEdge] {
+        &self.edges
+    }
+
+    // Undirected edges go from their first to their second end, pick petgraph::Undirected to
+    // have algorithms ignore edge directions altogether.
+    pub fn to_petgraph<Ty>(&self) -> petgraph::Graph<Vertex, edge::Edge, Ty> where Ty: petgraph::EdgeType {
+        let mut graph = petgraph::Graph::with_capacity(self.vertices.len(), self.edges.len());
+        for vertex in &self.vertices {
+            graph.add_node(vertex.clone());
+        }
+        for edge in &self.edges {
+            graph.add_edge(NodeIndex::new(edge.from), NodeIndex::new(edge.to), edge.edge.clone());
+        }
+        graph
+    }
+}
+
+impl From<Subgraph> for MemGraph {
+    fn from(subgraph: Subgraph) -> MemGraph {
+        let mut mem = MemGraph::new();
+        for vertex in subgraph.vertices {
+            mem.add_vertex(vertex);
+        }
+        // subgraph indexes follow the same insertion order
+        mem.edges = subgraph.edges.into_iter()
+            .map(|e| MemEdge { from: e.from, to: e.to, edge: e.edge })
+            .collect();
+        mem
+    }
+}
+
+impl From<MemGraph> for petgraph::Graph<Vertex, edge::Edge> {
+    fn from(mem: MemGraph) -> petgraph::Graph<Vertex, edge::Edge> {
+        let mut graph = petgraph::Graph::with_capacity(mem.vertices.len(), mem.edges.len());
+        for vertex in mem.vertices {
+            graph.add_node(vertex);
+        }
+        for edge in mem.edges {
+            graph.add_edge(NodeIndex::new(edge.from), NodeIndex::new(edge.to), edge.edge);
+        }
+        graph
+    }
+}
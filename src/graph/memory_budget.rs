@@ -0,0 +1,122 @@
+// Approximate memory held by neighbourhood traversals in flight on a server, so concurrent supernode
+// expansions wait or fail instead of getting the process killed. A traversal reserves its estimate
+// once it knows its edges, before the opposite vertices are read, and holds it until its result is
+// handed back: the edge sizes and vertex_bytes for every vertex to read. Over the budget it waits up
+// to queue_timeout_ms for others to finish, then is shed with BudgetExceeded. A traversal alone on
+// the server always runs, so one larger than the whole budget is not refused forever. Results held
+// by callers afterwards are theirs, only the work in progress is counted. The server shares one
+// budget among all its graphs. Waiting blocks the thread polling the traversal, like retry backoff.
+
+use parking_lot::{Condvar, Mutex};
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use server::metrics;
+
+fn default_queue_timeout_ms() -> u64 { 1000 }
+fn default_vertex_bytes() -> u64 { 256 }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MemoryBudgetOptions {
+    pub max_bytes: u64,
+    // how long a traversal waits for room before it is shed, 0 sheds at once
+    #[serde(default = "default_queue_timeout_ms")]
+    pub queue_timeout_ms: u64,
+    // reserved for every vertex a traversal is about to read
+    #[serde(default = "default_vertex_bytes")]
+    pub vertex_bytes: u64
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BudgetExceeded {
+    pub requested: u64,
+    // held by other traversals when this one gave up
+    pub used: u64,
+    pub max_bytes: u64
+}
+
+pub struct MemoryBudget {
+    options: MemoryBudgetOptions,
+    used: Mutex<u64>,
+    released: Condvar
+}
+
+// gives its bytes back when dropped
+pub struct Reservation {
+    budget: Arc<MemoryBudget>,
+    bytes: u64
+}
+
+impl MemoryBudgetOptions {
+    pub fn new(max_bytes: u64) -> MemoryBudgetOptions {
+        MemoryBudgetOptions {
+            max_bytes,
+            queue_timeout_ms: default_queue_timeout_ms(),
+            vertex_bytes: default_vertex_bytes()
+        }
+    }
+    pub fn with_queue_timeout_ms(mut self, queue_timeout_ms: u64) -> MemoryBudgetOptions {
+        self.queue_timeout_ms = queue_timeout_ms;
+        self
+    }
+    pub fn with_vertex_bytes(mut self, vertex_bytes: u64) -> MemoryBudgetOptions {
+        self.vertex_bytes = vertex_bytes;
+        self
+    }
+}
+
+impl MemoryBudget {
+    pub fn new(options: MemoryBudgetOptions) -> Arc<MemoryBudget> {
+        Arc::new(MemoryBudget { options, used: Mutex::new(0), released: Condvar::new() })
+    }
+
+    pub fn options(&self) -> &MemoryBudgetOptions {
+        &self.options
+    }
+
+    // bytes reserved by traversals in flight
+    pub fn used(&self) -> u64 {
+        *self.used.lock()
+    }
+
+    // the estimate of a traversal reading the opposite vertices of its edges
+    pub fn estimate(&self, edge_bytes: u64, vertices: usize) -> u64 {
+        edge_bytes + vertices as u64 * self.options.vertex_bytes
+    }
+
+    pub fn reserve(this: &Arc<Self>, bytes: u64) -> Result<Reservation, BudgetExceeded> {
+        let deadline = Instant::now() + Duration::from_millis(this.options.queue_timeout_ms);
+        let mut used = this.used.lock();
+        let mut queued = false;
+        while *used > 0 && *used + bytes > this.options.max_bytes {
+            let now = Instant::now();
+            if now >= deadline {
+                metrics::TRAVERSALS_SHED.inc();
+                debug!("Shed a traversal of {} bytes, {} of {} in use", bytes, *used, this.options.max_bytes);
+                return Err(BudgetExceeded { requested: bytes, used: *used, max_bytes: this.options.max_bytes });
+            }
+            if !queued {
+                metrics::TRAVERSALS_QUEUED.inc();
+                queued = true;
+            }
+            this.released.wait_for(&mut used, deadline - now);
+        }
+        *used += bytes;
+        Ok(Reservation { budget: this.clone(), bytes })
+    }
+}
+
+impl Reservation {
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        let mut used = self.budget.used.lock();
+        *used = used.saturating_sub(self.bytes);
+        self.budget.released.notify_all();
+    }
+}
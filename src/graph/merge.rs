@@ -0,0 +1,232 @@
+// Merging a duplicate vertex into a survivor, for entity resolution. The duplicate's edges move to the
+// survivor chunk_size edges at a time, every chunk one transaction that links the survivor and unlinks
+// the duplicate, so an interrupted merge leaves each edge on one of the two and can simply run again.
+// Schemas with unique pairs drop moved edges the survivor already has, edges between the two vertices
+// become self loops on the survivor where the schema allows them and are dropped elsewhere.
+// A last transaction merges the duplicate's fields into the survivor and removes the duplicate.
+// Reserved fields, the ones starting with an underscore, always stay the survivor's.
+
+use neb::ram::types::{Id, key_hash};
+use neb::dovahkiin::types::{Map, Value};
+use neb::client::transaction::TxnError;
+use futures::prelude::*;
+
+use std::sync::Arc;
+
+use graph::{GraphInner, GraphTransaction, EdgeDirection, LinkVerticesError, UpdateOptions, edge_attr_from_schema, changes, savepoint, vertex};
+use graph::edge::EdgeError;
+
+fn default_chunk_size() -> usize { 500 }
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldMerge {
+    // the survivor's value
+    Survivor,
+    // the duplicate's value, unless it is null
+    Duplicate,
+    // the survivor's value, unless it is null
+    Coalesce,
+    // items of both arrays without repeats, other values coalesce
+    Union
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MergeStrategy {
+    pub default: FieldMerge,
+    // overrides the default for these fields
+    #[serde(default)]
+    pub fields: Vec<(String, FieldMerge)>,
+    #[serde(default = "default_chunk_size")]
+    pub chunk_size: usize
+}
+
+impl Default for MergeStrategy {
+    fn default() -> MergeStrategy {
+        MergeStrategy {
+            default: FieldMerge::Coalesce,
+            fields: Vec::new(),
+            chunk_size: default_chunk_size()
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct MergeReport {
+    // edges now on the survivor
+    pub moved: usize,
+    // edges the survivor already had in schemas with unique pairs
+    pub deduplicated: usize,
+    // edges between the two in schemas without self loops
+    pub dropped_loops: usize,
+    pub chunks: usize
+}
+
+#[derive(Debug)]
+pub enum MergeError {
+    SameVertex,
+    VertexNotFound(Id),
+    EdgeError(EdgeError),
+    LinkError(LinkVerticesError),
+    UpdateError(vertex::UpdateError),
+    RemoveError(vertex::RemoveError),
+    ReadOnly
+}
+
+impl MergeStrategy {
+    pub fn with_field(mut self, name: &str, merge: FieldMerge) -> MergeStrategy {
+        self.fields.retain(|&(ref field, _)| field != name);
+        self.fields.push((name.to_string(), merge));
+        self
+    }
+
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> MergeStrategy {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    fn field_merge(&self, name: &str) -> FieldMerge {
+        self.fields.iter().find(|&&(ref field, _)| field == name).map_or(self.default, |&(_, merge)| merge)
+    }
+
+    // merges the duplicate's fields into the survivor's data
+    pub fn merge(&self, survivor: &mut Map, duplicate: &Map) {
+        for name in duplicate.fields.iter().filter(|name| !name.starts_with('_')) {
+            let key_id = key_hash(name);
+            let merged = merge_value(self.field_merge(name), survivor.get_by_key_id(key_id), duplicate.get_by_key_id(key_id));
+            survivor.insert(name, merged);
+        }
+    }
+}
+
+fn merge_value(merge: FieldMerge, survivor: &Value, duplicate: &Value) -> Value {
+    match (merge, survivor, duplicate) {
+        (FieldMerge::Survivor, _, _) => survivor.clone(),
+        (_, _, &Value::Null) => survivor.clone(),
+        (FieldMerge::Duplicate, _, _) | (_, &Value::Null, _) => duplicate.clone(),
+        (FieldMerge::Union, &Value::Array(ref ours), &Value::Array(ref theirs)) => {
+            let mut items = ours.clone();
+            for item in theirs {
+                if !items.contains(item) { items.push(item.clone()); }
+            }
+            Value::Array(items)
+        },
+        _ => survivor.clone()
+    }
+}
+
+impl <'a> GraphTransaction<'a> {
+    // Moves up to chunk_size edges of the duplicate onto the survivor, only those of the edge schemas given
+    pub fn move_edges(&self, survivor: Id, duplicate: Id, edge_schemas: &Option<Vec<u32>>, chunk_size: usize)
+        -> Result<Result<MergeReport, MergeError>, TxnError>
+    {
+        if self.read_only { return Ok(Err(MergeError::ReadOnly)); }
+        for &id in &[survivor, duplicate] {
+            if !self.vertex_exists(id)? { return Ok(Err(MergeError::VertexNotFound(id))); }
+        }
+        let incident = match self.incident_edge_schemas(duplicate)? {
+            Ok(incident) => incident, Err(e) => return Ok(Err(MergeError::EdgeError(e)))
+        };
+        let mut report = MergeReport::default();
+        let mut processed = 0;
+        let selected = |schema: u32| edge_schemas.as_ref().map_or(true, |schemas| schemas.contains(&schema));
+        for list in incident.into_iter().filter(|list| list.count > 0 && selected(list.schema)) {
+            let edge_attr = match edge_attr_from_schema(list.schema, &self.schemas) {
+                Ok((_, edge_attr)) => edge_attr, Err(e) => return Ok(Err(MergeError::EdgeError(e)))
+            };
+            let edges = match self.edges(duplicate, list.schema, list.direction, &None)? {
+                Ok(edges) => edges, Err(e) => return Ok(Err(MergeError::EdgeError(e)))
+            };
+            for edge in edges {
+                if processed >= chunk_size { return Ok(Ok(report)); }
+                let (a, b) = { let (a, b) = edge.ends(); (*a, *b) };
+                // loops on the duplicate sit in both of its directed lists, they move with the outbound one
+                if list.direction == EdgeDirection::Inbound && a == duplicate { continue; }
+                let (from, to) = (if a == duplicate { survivor } else { a }, if b == duplicate { survivor } else { b });
+                let known = if edge_attr.unique_pairs {
+                    match self.has_edge(from, list.schema, to)? {
+                        Ok(known) => known, Err(e) => return Ok(Err(MergeError::EdgeError(e)))
+                    }
+                } else { false };
+                if from == to && !edge_attr.allow_self_loops {
+                    report.dropped_loops += 1;
+                } else if known {
+                    report.deduplicated += 1;
+                } else {
+                    let body = edge.get_data().as_ref().and_then(|cell| match cell.data {
+                        Value::Map(ref map) => Some(map.clone()), _ => None
+                    });
+                    if let Err(e) = self.link_uncounted(from, list.schema, to, body)? {
+                        return Ok(Err(MergeError::LinkError(e)));
+                    }
+                    report.moved += 1;
+                }
+                if let Err(e) = edge.clone().remove(self.neb_txn, &self.schemas)? {
+                    return Ok(Err(MergeError::EdgeError(e)));
+                }
+                self.record_edge_change(changes::ChangeKind::Unlinked, &edge);
+                if self.has_savepoint() { self.record_undo(savepoint::Undo::Unlink(edge)); }
+                processed += 1;
+            }
+        }
+        Ok(Ok(report))
+    }
+
+    // Merges the duplicate's fields into the survivor and removes the duplicate, edges moved already
+    pub fn absorb_vertex(&self, survivor: Id, duplicate: Id, strategy: &MergeStrategy)
+        -> Result<Result<(), MergeError>, TxnError>
+    {
+        // stored fields only, virtual computed ones are not merged
+        let duplicate_data = match self.neb_txn.read(&duplicate)? {
+            Some(cell) => match cell.data { Value::Map(map) => map, _ => Map::new() },
+            None => return Ok(Err(MergeError::VertexNotFound(duplicate)))
+        };
+        let updated = self.update_vertex_with(survivor, UpdateOptions::default(), |mut vertex| {
+            if let Value::Map(ref mut data) = vertex.cell.data { strategy.merge(data, &duplicate_data); }
+            Some(vertex)
+        })?;
+        if let Err(e) = updated { return Ok(Err(MergeError::UpdateError(e))); }
+        Ok(self.remove_vertex(duplicate)?.map_err(MergeError::RemoveError))
+    }
+}
+
+impl GraphInner {
+    // moves the edges of the duplicate onto the survivor a chunk per transaction until none are left
+    #[async]
+    pub fn move_all_edges(this: Arc<Self>, survivor: Id, duplicate: Id, edge_schemas: Option<Vec<u32>>, chunk_size: usize)
+        -> Result<Result<MergeReport, MergeError>, TxnError>
+    {
+        let chunk_size = ::std::cmp::max(chunk_size, 1);
+        let edge_schemas = Arc::new(edge_schemas);
+        let mut report = MergeReport::default();
+        loop {
+            let chunk_schemas = edge_schemas.clone();
+            let chunk = match await!(this.graph_transaction(move |txn| {
+                txn.move_edges(survivor, duplicate, &chunk_schemas, chunk_size)
+            }))? {
+                Ok(chunk) => chunk, Err(e) => return Ok(Err(e))
+            };
+            let processed = chunk.moved + chunk.deduplicated + chunk.dropped_loops;
+            report.moved += chunk.moved;
+            report.deduplicated += chunk.deduplicated;
+            report.dropped_loops += chunk.dropped_loops;
+            report.chunks += 1;
+            if processed < chunk_size { break; }
+        }
+        Ok(Ok(report))
+    }
+
+    #[async]
+    pub fn merge_vertices(this: Arc<Self>, survivor: Id, duplicate: Id, strategy: MergeStrategy)
+        -> Result<Result<MergeReport, MergeError>, TxnError>
+    {
+        if survivor == duplicate { return Ok(Err(MergeError::SameVertex)); }
+        let report = match await!(Self::move_all_edges(this.clone(), survivor, duplicate, None, strategy.chunk_size))? {
+            Ok(report) => report, Err(e) => return Ok(Err(e))
+        };
+        let strategy = Arc::new(strategy);
+        match await!(this.graph_transaction(move |txn| txn.absorb_vertex(survivor, duplicate, &strategy)))? {
+            Ok(()) => Ok(Ok(report)),
+            Err(e) => Ok(Err(e))
+        }
+    }
+}
@@ -0,0 +1,164 @@
+// Migrates stored schemas that predate fields this binary writes: the base schemas of id lists and
+// type lists (_packed, _count, _shards) and the vertex template (_version, _key). neb encodes cells
+// by the stored schema, so values of fields it does not know are dropped on write and adjacency,
+// degrees or versions are lost without an error. Graphs with such schemas refuse to open, see
+// graph::startup, until they are migrated. A migration spills every cell of the schema to a file,
+// replaces the stored schema with the new layout and writes the cells back, the new fields null.
+// Nothing else may write the graph meanwhile: run it from one server with the others stopped.
+// A run interrupted once the spill is complete resumes from the file on the next start, one
+// interrupted before starts over, the stored schema being unchanged then.
+
+use neb::ram::schema::{Field, Schema};
+use neb::ram::cell::Cell;
+use neb::client::{AsyncClient as NebClient};
+use bifrost::raft::state_machine::master::ExecError;
+use bifrost::rpc::RPCError;
+use futures::prelude::*;
+use serde_json;
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use graph::scan::{self, ScanError};
+use graph::startup;
+use server::schema::MorpheusSchema;
+
+fn default_batch_size() -> usize { 512 }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SchemaMigrationOptions {
+    // where cells are kept while their schema is replaced, must survive a restart of the server
+    pub spill_dir: String,
+    // cells read or written in one round trip
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize
+}
+
+#[derive(Debug)]
+pub enum MigrationError {
+    ExecError(ExecError),
+    ScanError(ScanError),
+    RPCError(RPCError),
+    // the cell that could not be written back and why
+    WriteFailed(String),
+    SpillError(String)
+}
+
+// the stored schema and the layout it migrates to
+pub struct Migration {
+    pub stored: Arc<Schema>,
+    pub fields: Field
+}
+
+impl SchemaMigrationOptions {
+    pub fn new(spill_dir: &str) -> SchemaMigrationOptions {
+        SchemaMigrationOptions { spill_dir: spill_dir.to_string(), batch_size: default_batch_size() }
+    }
+}
+
+fn spill_error(e: io::Error) -> MigrationError {
+    MigrationError::SpillError(e.to_string())
+}
+
+fn is_migratable(problems: &[String], notes: &[String]) -> bool {
+    problems.is_empty() && !notes.is_empty()
+}
+
+// a base schema that only lacks nullable fields takes the layout of this binary
+pub fn base_migration(stored: &Arc<Schema>, expected: &Field) -> Option<Migration> {
+    let (mut problems, mut notes) = (Vec::new(), Vec::new());
+    startup::check_base_schema(&stored.name, expected, &stored.fields, &mut problems, &mut notes);
+    if !is_migratable(&problems, &notes) { return None; }
+    Some(Migration { stored: stored.clone(), fields: expected.clone() })
+}
+
+// a vertex or edge schema whose template only lacks nullable fields keeps its own fields after the new template
+pub fn template_migration(schema: &MorpheusSchema, stored: &Arc<Schema>) -> Option<Migration> {
+    let template = match startup::template(schema.schema_type) { Some(template) => template, None => return None };
+    let (mut problems, mut notes) = (Vec::new(), Vec::new());
+    startup::check_template(schema, &stored.fields, &mut problems, &mut notes);
+    if !is_migratable(&problems, &notes) { return None; }
+    let own = stored.fields.sub_fields.as_ref().map_or(Vec::new(), |fields| {
+        fields.iter().filter(|field| !template.iter().any(|t| t.name == field.name)).cloned().collect()
+    });
+    let mut fields = template.clone();
+    fields.extend(own);
+    Some(Migration {
+        stored: stored.clone(),
+        fields: Field::new(&stored.fields.name, stored.fields.type_id, stored.fields.nullable, stored.fields.is_array, Some(fields))
+    })
+}
+
+impl Migration {
+    fn spill_path(&self, options: &SchemaMigrationOptions, suffix: &str) -> PathBuf {
+        PathBuf::from(&options.spill_dir).join(format!("{}-{}.{}", self.stored.name, self.stored.id, suffix))
+    }
+
+    // whether an earlier run completed its spill
+    pub fn is_spilled(&self, options: &SchemaMigrationOptions) -> bool {
+        self.spill_path(options, "done").exists()
+    }
+
+    // every cell of the schema as neb decodes it with the stored layout, one json line each
+    fn spill(&self, neb_client: &Arc<NebClient>, options: &SchemaMigrationOptions) -> Result<usize, MigrationError> {
+        fs::create_dir_all(&options.spill_dir).map_err(spill_error)?;
+        let path = self.spill_path(options, "cells");
+        let mut file = File::create(&path).map_err(spill_error)?;
+        let mut spilled = 0;
+        for page in scan::scan_cells(neb_client.clone(), self.stored.id, options.batch_size).wait() {
+            for cell in page.map_err(MigrationError::ScanError)? {
+                let line = serde_json::to_string(&cell).map_err(|e| MigrationError::SpillError(e.to_string()))?;
+                writeln!(file, "{}", line).map_err(spill_error)?;
+                spilled += 1;
+            }
+        }
+        file.sync_all().map_err(spill_error)?;
+        // marks the spill complete, from here on the cells are only in the file until written back
+        OpenOptions::new().create(true).write(true).open(self.spill_path(options, "done"))
+            .and_then(|done| done.sync_all()).map_err(spill_error)?;
+        Ok(spilled)
+    }
+
+    // writes the spilled cells back with the new layout, cells already written are written again
+    fn restore(&self, neb_client: &Arc<NebClient>, options: &SchemaMigrationOptions) -> Result<usize, MigrationError> {
+        let file = File::open(self.spill_path(options, "cells")).map_err(spill_error)?;
+        let mut restored = 0;
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(spill_error)?;
+            if line.is_empty() { continue; }
+            let cell: Cell = serde_json::from_str(&line).map_err(|e| MigrationError::SpillError(e.to_string()))?;
+            let id = cell.id();
+            // the stored bytes are in the old layout, the cell is replaced rather than updated
+            let _ = neb_client.remove_cell(id).wait().map_err(MigrationError::RPCError)?;
+            match neb_client.write_cell(cell).wait().map_err(MigrationError::RPCError)? {
+                Ok(_) => restored += 1,
+                Err(e) => return Err(MigrationError::WriteFailed(format!("{:?}: {:?}", id, e)))
+            }
+        }
+        Ok(restored)
+    }
+
+    pub fn run(&self, neb_client: &Arc<NebClient>, options: &SchemaMigrationOptions) -> Result<usize, MigrationError> {
+        if !self.is_spilled(options) {
+            let spilled = self.spill(neb_client, options)?;
+            info!("Spilled {} cells of schema {} before migrating it", spilled, self.stored.name);
+        }
+        neb_client.new_schema_with_id(Schema::new_with_id(
+            self.stored.id, &self.stored.name, self.stored.str_key_field.clone(), self.fields.clone(), self.stored.is_dynamic
+        )).wait().map_err(MigrationError::ExecError)?;
+        let restored = self.restore(neb_client, options)?;
+        for suffix in &["cells", "done"] {
+            fs::remove_file(self.spill_path(options, suffix)).map_err(spill_error)?;
+        }
+        info!("Migrated schema {}, {} cells written back", self.stored.name, restored);
+        Ok(restored)
+    }
+}
+
+// a migration interrupted once the schema was replaced, its cells are still in the spill file
+pub fn pending_migration(stored: &Arc<Schema>, options: &SchemaMigrationOptions) -> Option<Migration> {
+    let migration = Migration { stored: stored.clone(), fields: stored.fields.clone() };
+    if migration.is_spilled(options) { Some(migration) } else { None }
+}
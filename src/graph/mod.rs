@@ -4,24 +4,104 @@ use neb::dovahkiin::types::{Map, Value, ToValue};
 use neb::dovahkiin::expr::SExpr;
 use neb::ram::cell::{Cell, WriteError, ReadError};
 use neb::client::{AsyncClient as NebClient};
+use neb::server::{ServerMeta as NebServerMeta};
 use neb::client::transaction::{Transaction, TxnError};
-use bifrost::raft::state_machine::master::ExecError;
 use bifrost::rpc::RPCError;
 
+use server::metrics;
+use server::slow_log;
+use server::watchdog::{self, TxnWatch};
+use utils::trace::Span;
+use utils::timer;
 use server::schema::{MorpheusSchema, SchemaType, SchemaContainer, SchemaError, ToSchemaId};
+use server::path_pattern::Path;
 use graph::vertex::{Vertex, ToVertexId};
 use graph::edge::bilateral::BilateralEdge;
 use graph::edge::{EdgeAttributes, EdgeError};
-use query::{Tester, Expr, parse_optional_expr};
+use graph::placement::Placement;
+use graph::retry::RetryPolicy;
+use graph::snapshot::{ReadTransaction, SnapshotError};
+use graph::neighbour_set::NeighbourSetOp;
+use graph::similarity::SimilarityMetric;
+use graph::subgraph::Subgraph;
+use graph::multi_schema::{EdgeSchemas, IncidentSchema};
+use graph::traverse::{TraverseOptions, Neighbour};
+use graph::geo::{GeoPoint, GeoError};
+use graph::vector::{HnswOptions, VectorError};
+use graph::computed::{ComputedMode, ComputedFieldError};
+use graph::fsck::{FsckOptions, FsckReport, FsckError};
+use graph::startup::StartupError;
+use query::{self, Tester, Expr, FilterContext, parse_optional_expr};
 use futures::prelude::*;
 use futures::future;
+use futures::stream;
 
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, AtomicBool, Ordering};
+use std::collections::HashMap;
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+use std::thread::JoinHandle;
+use parking_lot::RwLock;
+use chashmap::CHashMap;
 
 pub mod vertex;
 pub mod edge;
 pub mod fields;
-mod id_list;
+pub mod placement;
+pub mod ids;
+pub mod endpoints;
+pub mod keys;
+pub mod batch;
+pub mod bulk;
+pub mod merge;
+pub mod rekey;
+pub mod model;
+pub mod repository;
+pub mod sync;
+pub mod unified;
+#[cfg(feature = "std-future")]
+pub mod compat;
+pub mod idempotency;
+pub mod quota;
+pub mod result_limit;
+pub mod memory_budget;
+pub mod prefetch;
+pub mod procedure;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod retry;
+pub mod session;
+pub mod snapshot;
+pub mod savepoint;
+pub mod neighbour_set;
+pub mod similarity;
+pub mod subgraph;
+pub mod dot;
+pub mod tree;
+pub mod reachability;
+pub mod k_paths;
+pub mod multi_schema;
+pub mod traverse;
+pub mod geo;
+pub mod vector;
+pub mod validation;
+pub mod computed;
+pub mod encryption;
+pub mod sample;
+pub mod window_counter;
+pub mod fsck;
+pub mod gc;
+pub mod columnar;
+pub mod changes;
+pub mod cache;
+pub mod vertex_cache;
+pub mod mem;
+pub mod startup;
+pub mod migrate;
+pub mod id_list;
+mod id_codec;
+mod scan;
 
 #[derive(Debug)]
 pub enum NewVertexError {
@@ -30,7 +110,19 @@ pub enum NewVertexError {
     CannotGenerateCellByData,
     DataNotMap,
     RPCError(RPCError),
-    WriteError(WriteError)
+    WriteError(WriteError),
+    GeoIndexError(geo::GeoError),
+    ValidationError(validation::ValidationError),
+    ComputedFieldError(ComputedFieldError),
+    // the sequence of a schema with sequential ids could not be advanced
+    IdSequenceError(TxnError),
+    // another key hashes to the id of the vertex, with that key
+    KeyCollision(Value),
+    QuotaExceeded(quota::QuotaExceeded),
+    // the quota counters of the schema could not be updated
+    QuotaUsageError(TxnError),
+    EncryptionError(encryption::EncryptionError),
+    ReadOnly
 }
 
 #[derive(Debug)]
@@ -45,7 +137,16 @@ pub enum LinkVerticesError {
     SchemaNotEdge,
     BodyRequired,
     BodyShouldNotExisted,
+    SelfLoopNotAllowed,
     EdgeError(edge::EdgeError),
+    ValidationError(validation::ValidationError),
+    // the new end of a rewired edge, or an end of an edge schema with endpoint restrictions
+    VertexNotFound(Id),
+    // the vertex's schema is not allowed at its end of the edge
+    EndpointNotAllowed(Id),
+    ReadOnly,
+    // the link batch flusher went away before answering, the link may or may not have been written
+    Unanswered
 }
 
 #[derive(Debug)]
@@ -53,7 +154,75 @@ pub enum NeighbourhoodError {
     EdgeError(edge::EdgeError),
     VertexNotFound(Id),
     CannotFindOppositeId(Id),
-    FilterEvalError(String)
+    FilterEvalError(String),
+    ReadVertexError(ReadVertexError),
+    // more neighbours than the graph's result limits allow, see graph::result_limit
+    Overflow(result_limit::Overflow),
+    // shed for want of room in the server's memory budget, see graph::memory_budget
+    BudgetExceeded(memory_budget::BudgetExceeded)
+}
+
+#[derive(Debug)]
+pub enum RemoveVerticesError {
+    SchemaNotVertex,
+    FilterEvalError(String),
+    ScanError(scan::ScanError),
+    TxnError(TxnError),
+    // removing a matched vertex failed, vertices removed before it in the same page stay removed
+    RemoveError(vertex::RemoveError),
+    ReadOnly
+}
+
+#[derive(Debug)]
+pub enum ScanVerticesError {
+    SchemaNotVertex,
+    FilterEvalError(String),
+    ScanError(scan::ScanError)
+}
+
+#[derive(Debug)]
+pub enum ScanEdgesError {
+    SchemaNotFound,
+    SchemaNotEdge,
+    FilterEvalError(String),
+    ScanError(scan::ScanError),
+    EdgeError(EdgeError),
+    IdListError(id_list::IdListError),
+    TxnError(TxnError)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CountMode {
+    Exact,
+    Approximate
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CountEstimate {
+    pub count: usize,
+    pub exact: bool
+}
+
+// counts older than this are not served as estimates, two passes of the statistics collector
+pub static COUNT_MAX_AGE_SECS: u64 = 600;
+
+// count of a whole schema and when it was taken, in seconds since the epoch
+#[derive(Debug, Clone, Copy)]
+struct KnownCount {
+    count: usize,
+    at: u64
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UpdateOptions {
+    // fail with UpdateError::VersionMismatch unless the vertex is still at this version
+    pub check_version: Option<u64>
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RemovalProgress {
+    pub scanned: usize,
+    pub removed: usize
 }
 
 #[derive(Clone, Copy, Serialize, Deserialize)]
@@ -62,24 +231,55 @@ pub enum CellType {
     Edge(edge::EdgeType)
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub enum EdgeDirection {
     Inbound,
     Outbound,
     Undirected,
+    // outbound and inbound edges of a directed schema in one call
+    Both,
 }
 
 impl EdgeDirection {
-    pub fn as_field(&self) -> u64 {
+    // Both has no list of its own, read the lists of expand() instead
+    pub fn as_field(&self) -> Option<u64> {
+        match self {
+            &EdgeDirection::Inbound => Some(*fields::INBOUND_KEY_ID),
+            &EdgeDirection::Outbound => Some(*fields::OUTBOUND_KEY_ID),
+            &EdgeDirection::Undirected => Some(*fields::UNDIRECTED_KEY_ID),
+            &EdgeDirection::Both => None
+        }
+    }
+    // the directions whose lists make up this one with their list fields, outbound first for Both
+    pub fn expand(&self) -> Vec<(EdgeDirection, u64)> {
         match self {
-            &EdgeDirection::Inbound => *fields::INBOUND_KEY_ID,
-            &EdgeDirection::Outbound => *fields::OUTBOUND_KEY_ID,
-            &EdgeDirection::Undirected => *fields::UNDIRECTED_KEY_ID,
+            &EdgeDirection::Both => vec![
+                (EdgeDirection::Outbound, *fields::OUTBOUND_KEY_ID),
+                (EdgeDirection::Inbound, *fields::INBOUND_KEY_ID)
+            ],
+            &direction => direction.as_field().map(|field| (direction, field)).into_iter().collect()
         }
     }
+    // every edge list a vertex can have
+    pub fn every_list() -> Vec<(EdgeDirection, u64)> {
+        let mut lists = EdgeDirection::Both.expand();
+        lists.extend(EdgeDirection::Undirected.expand());
+        lists
+    }
+    // undirected schemas only have the undirected list, Both reads that one on them
+    pub fn for_schema(&self, schema_id: u32, schemas: &SchemaContainer) -> EdgeDirection {
+        if let (EdgeDirection::Both, Some(SchemaType::Edge(attrs))) = (*self, schemas.schema_type(schema_id)) {
+            if let edge::EdgeType::Undirected = attrs.edge_type { return EdgeDirection::Undirected; }
+        }
+        *self
+    }
+    // a directed self-loop is in both lists, Both returns the outbound copy only
+    pub fn repeats(&self, part: EdgeDirection, vertex_id: &Id, opposite_id: &Id) -> bool {
+        *self == EdgeDirection::Both && part == EdgeDirection::Inbound && vertex_id == opposite_id
+    }
 }
 
-fn vertex_to_cell_for_write(schemas: &Arc<SchemaContainer>, vertex: Vertex) -> Result<Cell, NewVertexError> {
+fn vertex_to_cell_for_write(schemas: &Arc<SchemaContainer>, vertex: Vertex, placement: &Placement) -> Result<Cell, NewVertexError> {
     let schema_id = vertex.schema();
     if let Some(stype) = schemas.schema_type(schema_id) {
         if stype != SchemaType::Vertex {
@@ -101,8 +301,24 @@ fn vertex_to_cell_for_write(schemas: &Arc<SchemaContainer>, vertex: Vertex) -> R
     data.insert_key_id(*fields::INBOUND_KEY_ID, Value::Id(Id::unit_id()));
     data.insert_key_id(*fields::OUTBOUND_KEY_ID, Value::Id(Id::unit_id()));
     data.insert_key_id(*fields::UNDIRECTED_KEY_ID, Value::Id(Id::unit_id()));
+    data.insert_key_id(*fields::VERSION_KEY_ID, Value::U64(0));
+    validation::apply_defaults(&schemas.defaults(schema_id), &mut data);
+    let new_vertex_context = FilterContext { in_degree: Some(0), out_degree: Some(0), ..FilterContext::default() };
+    if let Err(e) = computed::evaluate(&schemas.computed(schema_id), ComputedMode::Materialized, &mut data, &new_vertex_context) {
+        return Err(NewVertexError::ComputedFieldError(e));
+    }
+    if let Err(e) = validation::validate_vertex(&neb_schema, &data) {
+        return Err(NewVertexError::ValidationError(e));
+    }
+    keys::record(&neb_schema, &mut data);
+    if let Err(e) = encryption::seal_fields(schemas, schema_id, &mut data) {
+        return Err(NewVertexError::EncryptionError(e));
+    }
     match Cell::new(&neb_schema, Value::Map(data)) {
-        Some(cell) => Ok(cell),
+        Some(mut cell) => {
+            placement::apply(&mut cell, &neb_schema, placement, &schemas.placement(schema_id));
+            Ok(cell)
+        },
         None => return Err(NewVertexError::CannotGenerateCellByData)
     }
 }
@@ -113,15 +329,35 @@ pub struct Graph {
 
 pub struct GraphInner {
     schemas: Arc<SchemaContainer>,
-    neb_client: Arc<NebClient>
+    neb_client: Arc<NebClient>,
+    counts: Arc<CHashMap<u32, KnownCount>>,
+    count_max_age: RwLock<Duration>,
+    read_only: Arc<AtomicBool>,
+    link_queue: Arc<batch::LinkQueue>,
+    retry_policy: RwLock<RetryPolicy>,
+    vectors: Arc<vector::VectorIndexes>,
+    change_log: RwLock<Option<Arc<changes::ChangeLog>>>,
+    query_cache: RwLock<Option<Arc<cache::QueryCache>>>,
+    vertex_cache: RwLock<Option<Arc<vertex_cache::VertexCache>>>,
+    quotas: RwLock<Option<Arc<quota::QuotaOptions>>>,
+    result_limits: RwLock<result_limit::ResultLimits>,
+    memory_budget: RwLock<Option<Arc<memory_budget::MemoryBudget>>>,
+    procedures: Arc<procedure::Procedures>
 }
 
 impl Graph {
-    pub fn new(schemas: &Arc<SchemaContainer>, neb_client: &Arc<NebClient>) -> impl Future<Item = Graph, Error = ExecError> {
+    // fails with StartupError::Incompatible when the stored schemas do not fit this binary, see graph::startup
+    pub fn new(schemas: &Arc<SchemaContainer>, neb_client: &Arc<NebClient>) -> impl Future<Item = Graph, Error = StartupError> {
+        Graph::new_with_migration(schemas, neb_client, None)
+    }
+    // migrates stored schemas that only lack fields of this binary before opening, see graph::migrate
+    pub fn new_with_migration(
+        schemas: &Arc<SchemaContainer>, neb_client: &Arc<NebClient>, migration: Option<migrate::SchemaMigrationOptions>
+    ) -> impl Future<Item = Graph, Error = StartupError> {
         let schemas = schemas.clone();
         let schemas_clone = schemas.clone();
         let neb_client = neb_client.clone();
-        GraphInner::check_base_schemas(schemas)
+        GraphInner::check_base_schemas(schemas, migration)
             .and_then(|_| {
                 GraphInner::new(schemas_clone, neb_client)
             })
@@ -129,15 +365,20 @@ impl Graph {
                 Ok(Graph { inner: Arc::new(inner) })
             })
     }
-    fn check_base_schema(schemas: &Arc<SchemaContainer>, schema_id: u32, schema_name: & 'static str, fields: &'static Field)
-        -> impl Future<Item = (), Error = ExecError>
-    {
-        GraphInner::check_base_schema(schemas.clone(), schema_id, schema_name, fields)
+    // Opens the named graph, its schemas and data are invisible to every other graph in the group.
+    // Meta servers need the name in their graph list to host its schema state machine.
+    pub fn open<'a>(
+        name: &'a str, group: &'a str, neb_client: &Arc<NebClient>, neb_meta: &Arc<NebServerMeta>,
+        migration: Option<migrate::SchemaMigrationOptions>
+    ) -> impl Future<Item = Graph, Error = StartupError> {
+        let neb_client = neb_client.clone();
+        future::result(SchemaContainer::new_namespaced_client(
+            group, Some(name), &neb_client.raft_client(), &neb_client, neb_meta
+        ).map_err(StartupError::ExecError))
+            .and_then(move |schemas| Graph::new_with_migration(&schemas, &neb_client, migration))
     }
-    fn check_base_schemas(schemas: &Arc<SchemaContainer>)
-        -> impl Future<Item = (), Error = ExecError>
-    {
-        GraphInner::check_base_schemas(schemas.clone())
+    pub fn namespace(&self) -> Option<&String> {
+        self.inner.schemas.namespace()
     }
     pub fn new_vertex_group(&self, schema: MorpheusSchema)
         -> impl Future<Item = u32, Error = SchemaError>
@@ -149,23 +390,71 @@ impl Graph {
     {
         self.inner.new_edge_group(schema, edge_attrs)
     }
+    // schemas and writes of typed models, see graph::model
+    pub fn new_vertex_group_of<M>(&self) -> impl Future<Item = u32, Error = SchemaError> where M: model::VertexModel {
+        self.new_vertex_group(M::schema())
+    }
+    pub fn new_edge_group_of<M>(&self) -> impl Future<Item = u32, Error = SchemaError> where M: model::EdgeModel {
+        self.new_edge_group(M::schema(), M::attributes())
+    }
+    pub fn new_model_vertex<M>(&self, model: &M)
+        -> impl Future<Item = Vertex, Error = NewVertexError>
+        where M: model::VertexModel
+    {
+        self.new_vertex(M::schema_name(), model.to_map())
+    }
+    pub fn link_model<V, M>(&self, from: V, to: V, body: &M)
+        -> impl Future<Item = Result<edge::Edge, LinkVerticesError>, Error = TxnError>
+        where V: ToVertexId, M: model::EdgeModel
+    {
+        self.link(from, M::schema_name(), to, Some(body.to_map()))
+    }
+    pub fn repository<M>(&self) -> repository::Repository<M> where M: model::VertexModel + 'static {
+        repository::Repository::new(self)
+    }
+    // the same graph with blocking calls, see graph::sync
+    pub fn blocking(&self) -> sync::Graph {
+        sync::Graph::new(self)
+    }
+    // the same graph with every error as a morpheus::Error with context, see graph::unified
+    pub fn unified(&self) -> unified::Graph {
+        unified::Graph::new(self)
+    }
+    // the same graph with std futures, see graph::compat
+    #[cfg(feature = "std-future")]
+    pub fn std_futures(&self) -> compat::Graph {
+        compat::Graph::new(self)
+    }
     pub fn new_vertex<S>(&self, schema: S, data: Map)
         -> impl Future<Item = Vertex, Error = NewVertexError>
         where S: ToSchemaId
     {
-        GraphInner::new_vertex(self.inner.clone(), schema, data)
+        GraphInner::new_vertex(self.inner.clone(), schema, data, Placement::Auto)
+    }
+    // the hint is ignored for keyed schemas, their ids follow the key
+    pub fn new_vertex_with_placement<S>(&self, schema: S, data: Map, placement: Placement)
+        -> impl Future<Item = Vertex, Error = NewVertexError>
+        where S: ToSchemaId
+    {
+        GraphInner::new_vertex(self.inner.clone(), schema, data, placement)
     }
     pub fn remove_vertex<V>(&self, vertex: V)
-        -> impl Future<Item = (), Error = TxnError> where V: ToVertexId
+        -> impl Future<Item = Result<(), vertex::RemoveError>, Error = TxnError> where V: ToVertexId
     {
         self.inner.remove_vertex(vertex)
     }
     pub fn remove_vertex_by_key<K, S>(&self, schema: S, key: K)
-        -> impl Future<Item = (), Error = TxnError>
+        -> impl Future<Item = Result<(), vertex::RemoveError>, Error = TxnError>
         where K: ToValue, S: ToSchemaId
     {
         self.inner.remove_vertex_by_key(schema, key)
     }
+    pub fn remove_vertices_where<S, F>(&self, schema: S, filter: &Option<F>, batch_size: usize)
+        -> impl Stream<Item = RemovalProgress, Error = RemoveVerticesError>
+        where S: ToSchemaId, F: Expr
+    {
+        GraphInner::remove_vertices_where(self.inner.clone(), schema, filter, batch_size)
+    }
     pub fn update_vertex<V, U>(&self, vertex: V, update: U)
         -> impl Future<Item = (), Error = TxnError>
         where V: ToVertexId, U: Fn(Vertex) -> Option<Vertex>, U: 'static
@@ -178,6 +467,12 @@ impl Graph {
     {
         self.inner.update_vertex_by_key(schema, key, update)
     }
+    pub fn update_vertex_with<V, U>(&self, vertex: V, options: UpdateOptions, update: U)
+        -> impl Future<Item = Result<(), vertex::UpdateError>, Error = TxnError>
+        where V: ToVertexId, U: Fn(Vertex) -> Option<Vertex>, U: 'static
+    {
+        self.inner.update_vertex_with(vertex, options, update)
+    }
 
     pub fn vertex_by<V>(&self, vertex: V)
         -> impl Future<Item = Option<Vertex>, Error = ReadVertexError>
@@ -193,17 +488,389 @@ impl Graph {
         GraphInner::vertex_by_key(self.inner.clone(), schema, key)
     }
 
+    pub fn vertices_by<V>(&self, vertices: Vec<V>)
+        -> impl Future<Item = Vec<Option<Vertex>>, Error = ReadVertexError>
+        where V: ToVertexId
+    {
+        GraphInner::vertices_by(self.inner.clone(), vertices)
+    }
+
+    pub fn vertex_exists<V>(&self, vertex: V)
+        -> impl Future<Item = bool, Error = TxnError>
+        where V: ToVertexId
+    {
+        self.inner.vertex_exists(vertex)
+    }
+
+    pub fn key_of<V>(&self, vertex: V)
+        -> impl Future<Item = Option<Value>, Error = TxnError>
+        where V: ToVertexId
+    {
+        self.inner.key_of(vertex)
+    }
+
+    pub fn has_edge<V, S>(&self, from: V, schema: S, to: V)
+        -> impl Future<Item = Result<bool, EdgeError>, Error = TxnError>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        self.inner.has_edge(from, schema, to)
+    }
+
+    // "mutual friends", neighbours shared by both vertices
+    pub fn common_neighbours<V, S>(&self, a: V, b: V, schema: S, direction: EdgeDirection)
+        -> impl Future<Item = Result<Vec<Id>, EdgeError>, Error = TxnError>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        self.inner.neighbour_set(a, b, schema, direction, NeighbourSetOp::Intersection)
+    }
+    pub fn neighbours_union<V, S>(&self, a: V, b: V, schema: S, direction: EdgeDirection)
+        -> impl Future<Item = Result<Vec<Id>, EdgeError>, Error = TxnError>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        self.inner.neighbour_set(a, b, schema, direction, NeighbourSetOp::Union)
+    }
+    // neighbours of a that are not neighbours of b
+    pub fn neighbours_difference<V, S>(&self, a: V, b: V, schema: S, direction: EdgeDirection)
+        -> impl Future<Item = Result<Vec<Id>, EdgeError>, Error = TxnError>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        self.inner.neighbour_set(a, b, schema, direction, NeighbourSetOp::Difference)
+    }
+
+    // the top_k vertices sharing the most neighbours with this one, scored by metric
+    pub fn similar_vertices<V, S>(&self, vertex: V, schema: S, direction: EdgeDirection, metric: SimilarityMetric, top_k: usize)
+        -> impl Future<Item = Result<Vec<(Id, f64)>, EdgeError>, Error = TxnError>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        self.inner.similar_vertices(vertex, schema, direction, metric, top_k)
+    }
+
+    // random vertices of the schema, see graph::sample
+    pub fn sample_vertices<S>(&self, schema: S, size: sample::SampleSize)
+        -> impl Future<Item = Vec<Vertex>, Error = ScanVerticesError>
+        where S: ToSchemaId
+    {
+        GraphInner::sample_vertices(self.inner.clone(), schema, size)
+    }
+    // up to k random neighbours with their edges, only the sampled ones are read
+    pub fn sample_neighbours<V, S>(&self, vertex: V, schema: S, direction: EdgeDirection, k: usize)
+        -> impl Future<Item = Result<Vec<(Vertex, edge::Edge)>, NeighbourhoodError>, Error = TxnError>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        let vertex_id = vertex.to_id();
+        let schema_id = schema.to_id(&self.inner.schemas);
+        self.graph_transaction(move |txn| txn.sample_neighbours(vertex_id, schema_id, direction, k))
+    }
+    // what the owner counted within the window, see graph::window_counter
+    pub fn window_count<V>(&self, owner: V, name: &str) -> impl Future<Item = u64, Error = TxnError>
+        where V: ToVertexId
+    {
+        GraphInner::window_counts(self.inner.clone(), vec![owner.to_id()], name.to_string())
+            .map(|counts| counts.into_iter().next().unwrap_or(0))
+    }
+    pub fn window_counts(&self, owners: Vec<Id>, name: &str) -> impl Future<Item = Vec<u64>, Error = TxnError> {
+        GraphInner::window_counts(self.inner.clone(), owners, name.to_string())
+    }
+    // adds to a counter by hand, for interactions that are not links
+    pub fn bump_counter<V>(&self, owner: V, counter: window_counter::WindowCounter, delta: u64)
+        -> impl Future<Item = (), Error = TxnError>
+        where V: ToVertexId
+    {
+        let owner = owner.to_id();
+        self.graph_transaction(move |txn| txn.bump_counter(&owner, &counter, delta))
+    }
+
+    // everything within depth hops of the start vertices over the edge schemas, in one transaction
+    pub fn subgraph<V, S, F>(&self, start: Vec<V>, edge_schemas: Vec<S>, depth: usize, filter: &Option<F>)
+        -> impl Future<Item = Result<Subgraph, NeighbourhoodError>, Error = TxnError>
+        where V: ToVertexId, S: ToSchemaId, F: Expr
+    {
+        self.inner.subgraph(start, edge_schemas, depth, filter)
+    }
+    // the subgraph as Graphviz DOT, see graph::dot
+    pub fn export_dot<V, S, F>(&self, start: Vec<V>, edge_schemas: Vec<S>, depth: usize, filter: &Option<F>, style: dot::DotStyle)
+        -> impl Future<Item = Result<String, NeighbourhoodError>, Error = TxnError>
+        where V: ToVertexId, S: ToSchemaId, F: Expr
+    {
+        let schemas = self.inner.schemas.clone();
+        self.subgraph(start, edge_schemas, depth, filter).map(move |result| result.map(|subgraph| {
+            dot::to_dot(&subgraph, &style, |schema_id| schemas.get_neb_schema(schema_id).map(|schema| schema.name.clone()))
+        }))
+    }
+    // whether a path of at most max_depth hops over the edge schemas leads from one to the other,
+    // see graph::reachability
+    pub fn is_reachable<V, S>(&self, from: V, to: V, edge_schemas: Vec<S>, max_depth: usize)
+        -> impl Future<Item = Result<bool, EdgeError>, Error = TxnError>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        self.reachable_path(from, to, edge_schemas, max_depth).map(|result| result.map(|path| path.is_some()))
+    }
+    // the vertices of a shortest such path, both ends included
+    pub fn reachable_path<V, S>(&self, from: V, to: V, edge_schemas: Vec<S>, max_depth: usize)
+        -> impl Future<Item = Result<Option<Vec<Id>>, EdgeError>, Error = TxnError>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        let (from, to) = (from.to_id(), to.to_id());
+        let schema_ids: Vec<u32> = edge_schemas.iter().map(|s| s.to_id(&self.inner.schemas)).collect();
+        self.inner.graph_transaction(move |txn| txn.reachable_path(from, to, &schema_ids, max_depth))
+    }
+    // Up to k loopless paths over the edge schema, the lightest first, see graph::k_paths.
+    // Without a weight field every hop weighs one.
+    pub fn k_shortest_paths<V, S>(&self, from: V, to: V, edge_schema: S, k: usize, weight_field: Option<&str>)
+        -> impl Future<Item = Result<Vec<Path>, EdgeError>, Error = TxnError>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        let (from, to) = (from.to_id(), to.to_id());
+        let schema_id = edge_schema.to_id(&self.inner.schemas);
+        let weight_field = weight_field.map(|field| field.to_string());
+        self.inner.graph_transaction(move |txn| {
+            txn.k_shortest_paths(from, to, schema_id, k, weight_field.as_ref().map(|field| field.as_str()))
+        })
+    }
+    // vertices below the root over the edge schema, up to max_depth levels down, see graph::tree
+    pub fn descendants<V, S>(&self, root: V, edge_schema: S, max_depth: usize)
+        -> impl Future<Item = Result<tree::Tree, NeighbourhoodError>, Error = TxnError>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        self.inner.tree(root, edge_schema, true, max_depth)
+    }
+    // vertices above, the closest first
+    pub fn ancestors<V, S>(&self, vertex: V, edge_schema: S, max_depth: usize)
+        -> impl Future<Item = Result<tree::Tree, NeighbourhoodError>, Error = TxnError>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        self.inner.tree(vertex, edge_schema, false, max_depth)
+    }
+    // the aggregate of the subtree below every descendant of the root, the root first
+    pub fn aggregate_subtree<V, S>(&self, root: V, edge_schema: S, max_depth: usize, aggregate: tree::SubtreeAggregate)
+        -> impl Future<Item = Result<Vec<(Id, Option<f64>)>, NeighbourhoodError>, Error = TxnError>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        self.descendants(root, edge_schema, max_depth).map(move |result| result.map(|tree| {
+            tree.ids().into_iter().zip(tree.aggregate(&aggregate)).collect()
+        }))
+    }
+
+    pub fn compact_adjacency<V>(&self, vertex: V)
+        -> impl Future<Item = Result<(), id_list::IdListError>, Error = TxnError>
+        where V: ToVertexId
+    {
+        self.inner.compact_adjacency(vertex)
+    }
+
+    pub fn scan_vertices<S, F>(&self, schema: S, filter: &Option<F>, projection: Option<Vec<String>>)
+        -> impl Stream<Item = Vertex, Error = ScanVerticesError>
+        where S: ToSchemaId, F: Expr
+    {
+        GraphInner::scan_vertices(self.inner.clone(), schema, filter, projection)
+    }
+
+    pub fn scan_edges<S, F>(&self, schema: S, filter: &Option<F>)
+        -> impl Stream<Item = edge::Edge, Error = ScanEdgesError>
+        where S: ToSchemaId, F: Expr
+    {
+        GraphInner::scan_edges(self.inner.clone(), schema, filter)
+    }
+    // every edge of the schema as from, to and body, for exports and validation jobs
+    pub fn all_edges<S>(&self, schema: S)
+        -> impl Stream<Item = (Id, Id, Option<Cell>), Error = ScanEdgesError>
+        where S: ToSchemaId
+    {
+        GraphInner::all_edges(self.inner.clone(), schema)
+    }
+    // scan_vertices and scan_edges in columns, rows vertices or edges per batch, see graph::columnar
+    pub fn scan_vertex_batches<S, F>(&self, schema: S, filter: &Option<F>, rows: usize)
+        -> impl Stream<Item = columnar::ColumnBatch, Error = ScanVerticesError>
+        where S: ToSchemaId, F: Expr
+    {
+        GraphInner::scan_vertex_batches(self.inner.clone(), schema, filter, rows)
+    }
+    pub fn scan_edge_batches<S, F>(&self, schema: S, filter: &Option<F>, rows: usize)
+        -> impl Stream<Item = columnar::ColumnBatch, Error = ScanEdgesError>
+        where S: ToSchemaId, F: Expr
+    {
+        GraphInner::scan_edge_batches(self.inner.clone(), schema, filter, rows)
+    }
+
+    pub fn count_vertices<S, F>(&self, schema: S, filter: &Option<F>, mode: CountMode)
+        -> impl Future<Item = CountEstimate, Error = ScanVerticesError>
+        where S: ToSchemaId, F: Expr
+    {
+        GraphInner::count_vertices(self.inner.clone(), schema, filter, mode)
+    }
+
+    pub fn count_edges<S>(&self, schema: S, mode: CountMode)
+        -> impl Future<Item = CountEstimate, Error = ScanEdgesError>
+        where S: ToSchemaId
+    {
+        GraphInner::count_edges(self.inner.clone(), schema, mode)
+    }
+
+    // A read-only graph keeps serving reads and traversals but refuses every mutation,
+    // for replicas and for the primary during maintenance windows.
+    pub fn set_read_only(&self, read_only: bool) {
+        self.inner.read_only.store(read_only, Ordering::SeqCst);
+    }
+    pub fn is_read_only(&self) -> bool {
+        self.inner.is_read_only()
+    }
+    // feeds approximate counts from collected statistics
+    pub(crate) fn record_count(&self, schema_id: u32, count: usize, collected_at: u64) {
+        self.inner.record_count(schema_id, count, collected_at);
+    }
+    // how old a recorded count can be for CountMode::Approximate to return it
+    pub fn set_count_max_age(&self, max_age: Duration) {
+        *self.inner.count_max_age.write() = max_age;
+    }
+    // id and type of the schema with that name, None when there is none
+    pub fn schema_by_name(&self, name: &str) -> Option<(u32, SchemaType)> {
+        let schemas = &self.inner.schemas;
+        schemas.id_from_name(name).and_then(|id| schemas.schema_type(id).map(|t| (id, t)))
+    }
+    pub fn schema_name(&self, schema_id: u32) -> Option<String> {
+        self.inner.schemas.get_neb_schema(schema_id).map(|schema| schema.name.clone())
+    }
+    pub fn neb_schema(&self, schema_id: u32) -> Option<Arc<Schema>> {
+        self.inner.schemas.get_neb_schema(schema_id)
+    }
+    pub fn schema_types(&self) -> Vec<(u32, SchemaType)> {
+        self.inner.schemas.all_schema_types()
+    }
+
     pub fn graph_transaction<TFN, TR>(&self, func: TFN)
         -> impl Future<Item = TR, Error = TxnError>
         where TFN: Fn(&GraphTransaction) -> Result<TR, TxnError>, TR: 'static, TFN: 'static
     {
         self.inner.graph_transaction(func)
     }
+    // Consistent multi-read snapshot that takes no locks, see graph::snapshot.
+    // The closure runs again when a concurrent write changed something it read.
+    pub fn read_transaction<TFN, TR>(&self, func: TFN)
+        -> impl Future<Item = TR, Error = SnapshotError>
+        where TFN: Fn(&ReadTransaction) -> Result<TR, SnapshotError>
+    {
+        let neb_client = self.inner.neb_client.clone();
+        let schemas = self.inner.schemas.clone();
+        future::lazy(move || snapshot::run(neb_client, schemas, func))
+    }
+    // same as graph_transaction with a policy for this call only
+    pub fn graph_transaction_with_retry<TFN, TR>(&self, policy: RetryPolicy, func: TFN)
+        -> impl Future<Item = TR, Error = TxnError>
+        where TFN: Fn(&GraphTransaction) -> Result<TR, TxnError>, TR: 'static, TFN: 'static
+    {
+        self.inner.graph_transaction_with_retry(policy, func)
+    }
+    // used by every operation running a graph transaction unless the call brings its own
+    pub fn set_retry_policy(&self, policy: RetryPolicy) {
+        *self.inner.retry_policy.write() = policy;
+    }
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.inner.retry_policy.read().clone()
+    }
     pub fn link<V, S>(&self, from: V, schema: S, to: V, body: Option<Map>)
         -> impl Future<Item = Result<edge::Edge, LinkVerticesError>, Error = TxnError>
         where V: ToVertexId, S: ToSchemaId
     {
-        self.inner.link(from, schema, to, body)
+        GraphInner::link(self.inner.clone(), from, schema, to, body)
+    }
+    // Retries with the same key resolve to what the first call created, see graph::idempotency
+    pub fn new_vertex_idempotent<S>(&self, key: &str, schema: S, data: Map)
+        -> impl Future<Item = Result<idempotency::Outcome<Vertex>, NewVertexError>, Error = TxnError>
+        where S: ToSchemaId
+    {
+        let key = key.to_string();
+        let schema_id = schema.to_id(&self.inner.schemas);
+        self.inner.graph_transaction(move |txn| txn.new_vertex_idempotent(&key, schema_id, data.clone()))
+    }
+    // Not coalesced by link batching, the record has to commit with the edge
+    pub fn link_idempotent<V, S>(&self, key: &str, from: V, schema: S, to: V, body: Option<Map>)
+        -> impl Future<Item = Result<idempotency::Outcome<edge::Edge>, LinkVerticesError>, Error = TxnError>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        let key = key.to_string();
+        let (from_id, to_id) = (from.to_id(), to.to_id());
+        let schema_id = schema.to_id(&self.inner.schemas);
+        self.inner.graph_transaction(move |txn| txn.link_idempotent(&key, from_id, schema_id, to_id, body.clone()))
+    }
+    pub fn forget_idempotency_key(&self, key: &str) -> impl Future<Item = (), Error = TxnError> {
+        let key = key.to_string();
+        self.inner.graph_transaction(move |txn| txn.forget_idempotency_key(&key))
+    }
+    // Many writes in as many transactions as their size needs, see graph::bulk
+    pub fn bulk_transaction(&self, ops: Vec<bulk::BulkOp>, options: bulk::BulkOptions)
+        -> impl Future<Item = bulk::BulkReport, Error = ()>
+    {
+        GraphInner::bulk_transaction(self.inner.clone(), ops, options)
+    }
+    // Folds the duplicate into the survivor, edges first in chunks, see graph::merge
+    pub fn merge_vertices<V>(&self, survivor: V, duplicate: V, strategy: merge::MergeStrategy)
+        -> impl Future<Item = Result<merge::MergeReport, merge::MergeError>, Error = TxnError>
+        where V: ToVertexId
+    {
+        GraphInner::merge_vertices(self.inner.clone(), survivor.to_id(), duplicate.to_id(), strategy)
+    }
+    // Moves the vertex to the id of a new key, resolves to the new id, see graph::rekey
+    pub fn rekey_vertex<V, K>(&self, vertex: V, key: K)
+        -> impl Future<Item = Result<Id, rekey::RekeyError>, Error = TxnError>
+        where V: ToVertexId, K: ToValue
+    {
+        GraphInner::rekey_vertex(self.inner.clone(), vertex.to_id(), key.value())
+    }
+    // Writes a second vertex and moves the edges of the edge schemas given to it
+    pub fn split_vertex<V, S, E>(&self, vertex: V, schema: S, data: Map, edge_schemas: Vec<E>)
+        -> impl Future<Item = Result<(Id, merge::MergeReport), rekey::RekeyError>, Error = TxnError>
+        where V: ToVertexId, S: ToSchemaId, E: ToSchemaId
+    {
+        let schema_id = schema.to_id(&self.inner.schemas);
+        let edge_schemas = edge_schemas.into_iter().map(|edge_schema| edge_schema.to_id(&self.inner.schemas)).collect();
+        GraphInner::split_vertex(self.inner.clone(), vertex.to_id(), schema_id, data, edge_schemas)
+    }
+    // Procedures are deployed on this server only, see graph::procedure
+    pub fn deploy_procedure(&self, name: &str, procedure: procedure::Procedure) -> Result<(), procedure::ProcedureError> {
+        self.inner.procedures.deploy(name, procedure)
+    }
+    pub fn remove_procedure(&self, name: &str) -> bool {
+        self.inner.procedures.remove(name)
+    }
+    pub fn procedures(&self) -> Vec<String> {
+        self.inner.procedures.names()
+    }
+    // runs the procedure in a transaction of its own
+    pub fn call_procedure(&self, name: &str, args: Vec<Value>) -> impl Future<Item = Value, Error = procedure::ProcedureError> {
+        let deployed = match self.inner.procedures.get(name) {
+            Some(deployed) => deployed,
+            None => return future::Either::A(future::err(procedure::ProcedureError::NotFound(name.to_string())))
+        };
+        future::Either::B(self.inner.graph_transaction(move |txn| txn.call_procedure(&deployed, args.clone()))
+            .then(|result| match result {
+                Ok(result) => result,
+                Err(e) => Err(procedure::ProcedureError::TxnError(e))
+            }))
+    }
+    // From here on link calls are coalesced, see graph::batch. The flusher drains the queue
+    // and returns once `running` turns false.
+    pub fn start_link_batching(&self, options: batch::LinkBatchOptions, running: Arc<AtomicBool>)
+        -> JoinHandle<()>
+    {
+        batch::start_flusher(self.inner.clone(), options, running)
+    }
+    // Repairs broken adjacency in the background at a bounded rate, see graph::gc.
+    // Returns once `running` turns false.
+    pub fn start_orphan_gc(&self, options: gc::OrphanGcOptions, running: Arc<AtomicBool>)
+        -> JoinHandle<()>
+    {
+        gc::start_gc(self.inner.clone(), options, running)
+    }
+    // one round of the orphan GC on the calling thread, None when `running` turned false half way
+    pub fn collect_orphans(&self, options: &gc::OrphanGcOptions, running: &AtomicBool) -> Option<gc::GcRound> {
+        gc::collect_round(&self.inner, options, running)
+    }
+    // Ages out the window counters in the background, see graph::window_counter.
+    // Returns once `running` turns false.
+    pub fn start_counter_decay(&self, options: window_counter::CounterDecayOptions, running: Arc<AtomicBool>)
+        -> JoinHandle<()>
+    {
+        window_counter::start_decay(self.inner.clone(), options, running)
     }
     pub fn degree<V, S>(&self, vertex: V, schema: S, direction: EdgeDirection)
         -> impl Future<Item = Result<usize, edge::EdgeError>, Error = TxnError>
@@ -223,139 +890,1008 @@ impl Graph {
     {
         GraphInner::edges(self.inner.clone(), vertex, schema, direction, filter)
     }
+    // edges of all the schemas in one transaction, Both also covers undirected schemas
+    pub fn edges_of_schemas<V, S, F>(&self, vertex: V, schemas: Vec<S>, direction: EdgeDirection, filter: &Option<F>)
+        -> impl Future<Item = Result<Vec<edge::Edge>, EdgeError>, Error = TxnError>
+        where V: ToVertexId, S: ToSchemaId, F: Expr
+    {
+        let schema_ids = schemas.iter().map(|s| s.to_id(&self.inner.schemas)).collect();
+        self.inner.edges_of_schemas(vertex, EdgeSchemas::Of(schema_ids), direction, filter)
+    }
+    pub fn edges_of_any_schema<V, F>(&self, vertex: V, direction: EdgeDirection, filter: &Option<F>)
+        -> impl Future<Item = Result<Vec<edge::Edge>, EdgeError>, Error = TxnError>
+        where V: ToVertexId, F: Expr
+    {
+        self.inner.edges_of_schemas(vertex, EdgeSchemas::Any, direction, filter)
+    }
+    pub fn neighbourhoods_of_schemas<V, S, F>(&self, vertex: V, schemas: Vec<S>, direction: EdgeDirection, filter: &Option<F>)
+        -> impl Future<Item = Result<Vec<(Vertex, edge::Edge)>, NeighbourhoodError>, Error = TxnError>
+        where V: ToVertexId, S: ToSchemaId, F: Expr
+    {
+        let schema_ids = schemas.iter().map(|s| s.to_id(&self.inner.schemas)).collect();
+        self.inner.neighbourhoods_of_schemas(vertex, EdgeSchemas::Of(schema_ids), direction, filter)
+    }
+    // Neighbours of the vertex as configured by options, prefer this over the edges and
+    // neighbourhoods variants when more than one of schemas, sort, limit or projection is needed
+    pub fn traverse_neighbours<V>(&self, vertex: V, options: TraverseOptions)
+        -> impl Future<Item = Result<Vec<Neighbour>, NeighbourhoodError>, Error = TxnError>
+        where V: ToVertexId
+    {
+        GraphInner::traverse_neighbours(self.inner.clone(), vertex, options)
+    }
+    // vertices of the schema whose geo point field is within radius meters, nearest first
+    pub fn vertices_near<S>(&self, schema: S, field: &str, lat: f64, lon: f64, radius: f64)
+        -> impl Future<Item = Result<Vec<(Vertex, f64)>, GeoError>, Error = TxnError>
+        where S: ToSchemaId
+    {
+        self.inner.vertices_near(schema, field, GeoPoint::new(lat, lon), radius)
+    }
+    // indexes the vector field of the schema on this server, resolves to the number of vectors indexed
+    pub fn build_vector_index<S>(&self, schema: S, field: &str, options: HnswOptions)
+        -> impl Future<Item = usize, Error = ScanVerticesError>
+        where S: ToSchemaId
+    {
+        GraphInner::build_vector_index(self.inner.clone(), schema, field, options)
+    }
+    // scans every vertex and edge body for broken adjacency, see graph::fsck
+    pub fn check_consistency(&self, options: FsckOptions) -> impl Future<Item = FsckReport, Error = FsckError> {
+        GraphInner::check_consistency(self.inner.clone(), options)
+    }
+    // starts recording committed writes, keeping the last capacity of them, see graph::changes
+    pub fn enable_change_log(&self, capacity: usize) -> Arc<changes::ChangeLog> {
+        let mut change_log = self.inner.change_log.write();
+        if let Some(ref log) = *change_log { return log.clone(); }
+        let log = Arc::new(changes::ChangeLog::new(capacity));
+        *change_log = Some(log.clone());
+        log
+    }
+    pub fn change_log(&self) -> Option<Arc<changes::ChangeLog>> {
+        self.inner.change_log.read().clone()
+    }
+    // Caches the results of neighbourhoods on this server, see graph::cache
+    pub fn enable_query_cache(&self, options: cache::QueryCacheOptions) -> Arc<cache::QueryCache> {
+        let mut query_cache = self.inner.query_cache.write();
+        if let Some(ref cache) = *query_cache { return cache.clone(); }
+        let cache = Arc::new(cache::QueryCache::new(options));
+        *query_cache = Some(cache.clone());
+        cache
+    }
+    pub fn query_cache(&self) -> Option<Arc<cache::QueryCache>> {
+        self.inner.query_cache.read().clone()
+    }
+    // Limits what the graph stores from now on, see graph::quota
+    pub fn set_quotas(&self, quotas: quota::QuotaOptions) {
+        *self.inner.quotas.write() = Some(Arc::new(quotas));
+    }
+    pub fn quotas(&self) -> Option<Arc<quota::QuotaOptions>> {
+        self.inner.quotas.read().clone()
+    }
+    pub fn quota_usage(&self) -> impl Future<Item = Vec<quota::QuotaReport>, Error = TxnError> {
+        self.inner.quota_usage()
+    }
+    // counts the vertices stored before the quotas were set
+    pub fn recount_quota_usage(&self) -> impl Future<Item = (), Error = quota::RecountError> {
+        GraphInner::recount_quota_usage(self.inner.clone())
+    }
+    // Caps what one neighbourhood or edges call returns, see graph::result_limit
+    pub fn set_result_limits(&self, limits: result_limit::ResultLimits) {
+        *self.inner.result_limits.write() = limits;
+    }
+    pub fn result_limits(&self) -> result_limit::ResultLimits {
+        *self.inner.result_limits.read()
+    }
+    // Counts neighbourhood traversals in flight against the budget, see graph::memory_budget
+    pub fn set_memory_budget(&self, budget: Arc<memory_budget::MemoryBudget>) {
+        *self.inner.memory_budget.write() = Some(budget);
+    }
+    pub fn memory_budget(&self) -> Option<Arc<memory_budget::MemoryBudget>> {
+        self.inner.memory_budget.read().clone()
+    }
+    // Seals and opens the encrypted fields of this graph's schemas, see graph::encryption
+    pub fn set_field_cipher(&self, cipher: Arc<encryption::FieldCipher>) {
+        self.inner.schemas.set_field_cipher(cipher);
+    }
+    // Caches vertex cells read on this server, see graph::vertex_cache
+    pub fn enable_vertex_cache(&self, options: vertex_cache::VertexCacheOptions) -> Arc<vertex_cache::VertexCache> {
+        let mut vertex_cache = self.inner.vertex_cache.write();
+        if let Some(ref cache) = *vertex_cache { return cache.clone(); }
+        let cache = Arc::new(vertex_cache::VertexCache::new(options));
+        *vertex_cache = Some(cache.clone());
+        cache
+    }
+    pub fn vertex_cache(&self) -> Option<Arc<vertex_cache::VertexCache>> {
+        self.inner.vertex_cache.read().clone()
+    }
+    // Reads the vertices and their neighbourhoods into the caches of this server, see graph::prefetch
+    pub fn prefetch<V, S>(&self, vertices: Vec<V>, schemas: Vec<S>, direction: EdgeDirection)
+        -> impl Future<Item = prefetch::PrefetchReport, Error = ()>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        let vertex_ids = vertices.iter().map(|v| v.to_id()).collect();
+        let schema_ids = schemas.iter().map(|s| s.to_id(&self.inner.schemas)).collect();
+        GraphInner::prefetch(self.inner.clone(), vertex_ids, schema_ids, direction)
+    }
+    // builds the index of the field again with the options it had, None when the field has no index here
+    pub fn rebuild_vector_index<S>(&self, schema: S, field: &str)
+        -> Option<impl Future<Item = usize, Error = ScanVerticesError>>
+        where S: ToSchemaId
+    {
+        let schema_id = schema.to_id(&self.inner.schemas);
+        let options = match self.inner.vectors.get(schema_id, field) {
+            Some(index) => index.read().options().clone(), None => return None
+        };
+        Some(GraphInner::build_vector_index(self.inner.clone(), schema_id, field, options))
+    }
+    pub fn drop_vector_index<S>(&self, schema: S, field: &str) -> bool where S: ToSchemaId {
+        self.inner.vectors.remove(schema.to_id(&self.inner.schemas), field)
+    }
+    // approximate k nearest vertices by the indexed vector field, nearest first with their distances
+    pub fn knn<S>(&self, schema: S, field: &str, query_vector: Vec<f32>, k: usize)
+        -> impl Future<Item = Result<Vec<(Vertex, f32)>, VectorError>, Error = TxnError>
+        where S: ToSchemaId
+    {
+        self.inner.knn(schema, field, query_vector, k)
+    }
+    // which edge schemas the vertex has lists for, by direction and with edge counts
+    pub fn incident_edge_schemas<V>(&self, vertex: V)
+        -> impl Future<Item = Result<Vec<IncidentSchema>, EdgeError>, Error = TxnError>
+        where V: ToVertexId
+    {
+        let vertex_id = vertex.to_id();
+        self.inner.graph_transaction(move |txn| txn.incident_edge_schemas(vertex_id))
+    }
+    pub fn neighbourhoods_of_any_schema<V, F>(&self, vertex: V, direction: EdgeDirection, filter: &Option<F>)
+        -> impl Future<Item = Result<Vec<(Vertex, edge::Edge)>, NeighbourhoodError>, Error = TxnError>
+        where V: ToVertexId, F: Expr
+    {
+        self.inner.neighbourhoods_of_schemas(vertex, EdgeSchemas::Any, direction, filter)
+    }
 }
 
 impl GraphInner {
     #[async]
-    pub fn new(schemas: Arc<SchemaContainer>, neb_client: Arc<NebClient>) -> Result<GraphInner, ExecError> {
+    pub fn new(schemas: Arc<SchemaContainer>, neb_client: Arc<NebClient>) -> Result<GraphInner, StartupError> {
         await!(GraphInner::check_base_schemas(schemas.clone()))?;
         Ok(GraphInner {
             schemas: schemas.clone(),
-            neb_client: neb_client.clone()
+            neb_client: neb_client.clone(),
+            counts: Arc::new(CHashMap::new()),
+            count_max_age: RwLock::new(Duration::from_secs(COUNT_MAX_AGE_SECS)),
+            read_only: Arc::new(AtomicBool::new(false)),
+            link_queue: Arc::new(batch::LinkQueue::new()),
+            retry_policy: RwLock::new(RetryPolicy::default()),
+            vectors: Arc::new(vector::VectorIndexes::default()),
+            change_log: RwLock::new(None),
+            query_cache: RwLock::new(None),
+            vertex_cache: RwLock::new(None),
+            quotas: RwLock::new(None),
+            result_limits: RwLock::new(result_limit::ResultLimits::default()),
+            memory_budget: RwLock::new(None),
+            procedures: Arc::new(procedure::Procedures::default())
         })
     }
+    // runs the migration the stored schema needs, or one an earlier start left half done
+    fn migrate_schema(
+        schemas: &Arc<SchemaContainer>, stored: &Arc<Schema>, needed: Option<migrate::Migration>,
+        migration: &Option<migrate::SchemaMigrationOptions>
+    ) -> Result<bool, StartupError> {
+        let options = match migration { &Some(ref options) => options, &None => return Ok(false) };
+        match needed.or_else(|| migrate::pending_migration(stored, options)) {
+            Some(needed) => {
+                needed.run(&schemas.neb_client, options).map_err(StartupError::MigrationError)?;
+                Ok(true)
+            },
+            None => Ok(false)
+        }
+    }
     #[async]
-    fn check_base_schema(schemas: Arc<SchemaContainer>, schema_id: u32, schema_name: &'static str, fields: &'static Field) -> Result<(), ExecError> {
+    fn check_base_schema(
+        schemas: Arc<SchemaContainer>, schema_id: u32, schema_name: &'static str, fields: &'static Field,
+        migration: Option<migrate::SchemaMigrationOptions>
+    ) -> Result<(), StartupError> {
         match schemas.get_neb_schema(schema_id) {
             None => {
                 await!(schemas.neb_client.new_schema_with_id(
                     Schema::new_with_id(
                         schema_id, schema_name, None, fields.clone(), false
                     )
-                ))?;
+                )).map_err(StartupError::ExecError)?;
             },
-            _ => {}
+            Some(stored) => {
+                let needed = migrate::base_migration(&stored, fields);
+                if GraphInner::migrate_schema(&schemas, &stored, needed, &migration)? { return Ok(()); }
+                let (mut problems, mut notes) = (Vec::new(), Vec::new());
+                startup::check_base_schema(schema_name, fields, &stored.fields, &mut problems, &mut notes);
+                startup::conclude(problems, notes)?;
+            }
         }
         Ok(())
     }
     #[async]
-    fn check_base_schemas(schemas: Arc<SchemaContainer>) -> Result<(), ExecError> {
-        await!(GraphInner::check_base_schema(schemas.clone(), id_list::ID_LIST_SCHEMA_ID, "_NEB_ID_LIST", &*id_list::ID_LINKED_LIST))?;
-        await!(GraphInner::check_base_schema(schemas, id_list::TYPE_LIST_SCHEMA_ID, "_NEB_TYPE_ID_LIST", &*id_list::ID_TYPE_LIST))?;
+    fn check_templates(schemas: Arc<SchemaContainer>, migration: Option<migrate::SchemaMigrationOptions>) -> Result<(), StartupError> {
+        let (mut problems, mut notes) = (Vec::new(), Vec::new());
+        for schema in await!(schemas.all_morpheus_schemas()).map_err(StartupError::ExecError)? {
+            if let Some(stored) = schemas.get_neb_schema(schema.id) {
+                let needed = migrate::template_migration(&schema, &stored);
+                if GraphInner::migrate_schema(&schemas, &stored, needed, &migration)? { continue; }
+                startup::check_template(&schema, &stored.fields, &mut problems, &mut notes);
+            }
+        }
+        startup::conclude(problems, notes)
+    }
+    #[async]
+    fn check_base_schemas(schemas: Arc<SchemaContainer>, migration: Option<migrate::SchemaMigrationOptions>) -> Result<(), StartupError> {
+        let base_schemas: Vec<(u32, &'static str, &'static Field)> = vec![
+            (id_list::ID_LIST_SCHEMA_ID, "_NEB_ID_LIST", &*id_list::ID_LINKED_LIST),
+            (id_list::TYPE_LIST_SCHEMA_ID, "_NEB_TYPE_ID_LIST", &*id_list::ID_TYPE_LIST),
+            (geo::GEO_BUCKET_SCHEMA_ID, "_NEB_GEO_BUCKET", &*geo::GEO_BUCKET),
+            (idempotency::IDEMPOTENCY_SCHEMA_ID, "_NEB_IDEMPOTENCY", &*idempotency::IDEMPOTENCY_RECORD),
+            (ids::ID_SEQUENCE_SCHEMA_ID, "_NEB_ID_SEQUENCE", &*ids::ID_SEQUENCE),
+            (quota::QUOTA_USAGE_SCHEMA_ID, "_NEB_QUOTA_USAGE", &*quota::QUOTA_USAGE),
+            (window_counter::WINDOW_COUNTER_SCHEMA_ID, "_NEB_WINDOW_COUNTER", &*window_counter::WINDOW_COUNTER)
+        ];
+        for (schema_id, schema_name, fields) in base_schemas {
+            await!(GraphInner::check_base_schema(schemas.clone(), schema_id, schema_name, fields, migration.clone()))?;
+        }
+        await!(GraphInner::check_templates(schemas, migration))?;
         Ok(())
     }
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::SeqCst)
+    }
     pub fn new_vertex_group(&self, mut schema: MorpheusSchema)
         -> impl Future<Item = u32, Error = SchemaError>
     {
+        if self.is_read_only() { return future::Either::A(future::err(SchemaError::ReadOnly)); }
         schema.schema_type = SchemaType::Vertex;
-        self.schemas.new_schema(schema)
+        future::Either::B(self.schemas.new_schema(schema))
+    }
+    pub fn new_edge_group(&self, mut schema: MorpheusSchema, edge_attrs: edge::EdgeAttributes)
+        -> impl Future<Item = u32, Error = SchemaError>
+    {
+        if self.is_read_only() { return future::Either::A(future::err(SchemaError::ReadOnly)); }
+        schema.schema_type = SchemaType::Edge(edge_attrs);
+        future::Either::B(self.schemas.new_schema(schema))
+    }
+    pub fn new_vertex<S>(this: Arc<Self>, schema: S, data: Map, placement: Placement)
+        -> impl Future<Item = Vertex, Error = NewVertexError>
+        where S: ToSchemaId
+    {
+        if this.is_read_only() { return future::Either::A(future::err(NewVertexError::ReadOnly)); }
+        let vertex = Vertex::new(schema.to_id(&this.schemas), data);
+        let mut cell_result = vertex_to_cell_for_write(&this.schemas, vertex, &placement);
+        metrics::VERTEX_WRITES.inc();
+        let timer = metrics::VERTEX_WRITE_SECONDS.start_timer();
+        future::Either::B(async_block! {
+            let _timer = timer;
+            let mut cell = cell_result?;
+            let schema_id = cell.header.schema;
+            let strategy = this.schemas.id_strategy(schema_id);
+            let exact = if let Placement::Exact(_) = placement { true } else { false };
+            if strategy != ids::IdStrategy::Hash && !exact && ids::is_unkeyed(&this.schemas, schema_id) {
+                let sequence = match strategy {
+                    ids::IdStrategy::Sequential => Some(await!(this.graph_transaction(move |txn| txn.next_in_sequence(schema_id)))
+                        .map_err(NewVertexError::IdSequenceError)?),
+                    _ => None
+                };
+                ids::apply(&mut cell, strategy, sequence, ids::is_placed(&this.schemas, schema_id, &placement));
+            }
+            // counted before the write, given back when the write fails
+            let counted = this.quotas.read().as_ref().map_or(false, |quotas| quotas.covers(&this.schemas, schema_id));
+            if counted {
+                let reserved = cell.clone();
+                await!(this.graph_transaction(move |txn| txn.account_quota(None, Some(&reserved), true)))
+                    .map_err(NewVertexError::QuotaUsageError)?
+                    .map_err(NewVertexError::QuotaExceeded)?;
+            }
+            let mut span = Span::enter("neb_write_cell");
+            span.record("schema", cell.header.schema);
+            let written = await!(this.neb_client.write_cell(cell.clone()));
+            if counted && !written.as_ref().map_or(false, |r| r.is_ok()) {
+                let reserved = cell.clone();
+                let _ = await!(this.graph_transaction(move |txn| txn.account_quota(Some(&reserved), None, false)));
+            }
+            let header = match written {
+                Ok(Ok(header)) => header,
+                Ok(Err(e)) => {
+                    span.fail(&e);
+                    // the id may be taken by another key rather than the same one
+                    if let Some(key) = keys::stored_key(&cell.data) {
+                        let id = cell.id();
+                        if let Ok(Some(existing)) = await!(this.graph_transaction(move |txn| txn.colliding_key(id, &key))) {
+                            return Err(NewVertexError::KeyCollision(existing));
+                        }
+                    }
+                    return Err(NewVertexError::WriteError(e))
+                },
+                Err(e) => {
+                    span.fail(&e);
+                    return Err(NewVertexError::RPCError(e))
+                }
+            };
+            cell.header = header;
+            Ok(encryption::open_vertex(&this.schemas, vertex::cell_to_vertex(cell)))
+        })
+    }
+    pub fn remove_vertex<V>(&self, vertex: V)
+        -> impl Future<Item = Result<(), vertex::RemoveError>, Error = TxnError> where V: ToVertexId
+    {
+        let id = vertex.to_id();
+        self.graph_transaction(move |txn| txn.remove_vertex(id))
+    }
+    pub fn remove_vertex_by_key<K, S>(&self, schema: S, key: K)
+        -> impl Future<Item = Result<(), vertex::RemoveError>, Error = TxnError>
+        where K: ToValue, S: ToSchemaId
+    {
+        let id = Cell::encode_cell_key(schema.to_id(&self.schemas), &key.value());
+        self.remove_vertex(id)
+    }
+    // every scanned page is filtered and removed in its own transaction, progress is cumulative
+    pub fn remove_vertices_where<S, F>(this: Arc<Self>, schema: S, filter: &Option<F>, batch_size: usize)
+        -> impl Stream<Item = RemovalProgress, Error = RemoveVerticesError>
+        where S: ToSchemaId, F: Expr
+    {
+        let schema_id = schema.to_id(&this.schemas);
+        let checked_filter = match this.schemas.schema_type(schema_id) {
+            _ if this.is_read_only() => Err(RemoveVerticesError::ReadOnly),
+            Some(SchemaType::Vertex) => parse_optional_expr(filter)
+                .map_err(RemoveVerticesError::FilterEvalError),
+            _ => Err(RemoveVerticesError::SchemaNotVertex)
+        };
+        let mut scanned = 0;
+        let mut removed = 0;
+        future::result(checked_filter)
+            .map(move |filter_sexpr| {
+                let txn_graph = this.clone();
+                scan::scan_cells(this.neb_client.clone(), schema_id, batch_size)
+                    .map_err(RemoveVerticesError::ScanError)
+                    .and_then(move |cells| {
+                        let batch_scanned = cells.len();
+                        let mut matched = Vec::new();
+                        for cell in cells {
+                            let vertex = encryption::open_vertex(&txn_graph.schemas, vertex::cell_to_vertex(cell));
+                            match Tester::eval_with_vertex(&filter_sexpr, &vertex) {
+                                Ok(true) => matched.push(vertex.cell.id()),
+                                Ok(false) => {},
+                                Err(e) => return future::Either::A(
+                                    future::err(RemoveVerticesError::FilterEvalError(e)))
+                            }
+                        }
+                        future::Either::B(txn_graph.graph_transaction(move |txn| {
+                            let mut batch_removed = 0;
+                            for id in &matched {
+                                match txn.remove_vertex(id)? {
+                                    Ok(()) => batch_removed += 1,
+                                    Err(vertex::RemoveError::NotFound) => {}, // removed concurrently
+                                    Err(e) => return Ok(Err(e))
+                                }
+                            }
+                            Ok(Ok(batch_removed))
+                        })
+                            .map_err(RemoveVerticesError::TxnError)
+                            .and_then(move |batch_removed| batch_removed
+                                .map(|batch_removed| (batch_scanned, batch_removed))
+                                .map_err(RemoveVerticesError::RemoveError)))
+                    })
+            })
+            .flatten_stream()
+            .map(move |(batch_scanned, batch_removed)| {
+                scanned += batch_scanned;
+                removed += batch_removed;
+                RemovalProgress { scanned, removed }
+            })
+    }
+    pub fn update_vertex<V, U>(&self, vertex: V, update: U) -> impl Future<Item = (), Error = TxnError>
+        where V: ToVertexId, U: Fn(Vertex) -> Option<Vertex>, U: 'static
+    {
+        let id = vertex.to_id();
+        self.graph_transaction(move |txn| txn.update_vertex(id, &update))
+    }
+    pub fn update_vertex_by_key<K, U, S>(&self, schema: S, key: K, update: U)
+        -> impl Future<Item = (), Error = TxnError>
+        where K: ToValue, S: ToSchemaId, U: Fn(Vertex) -> Option<Vertex>, U: 'static
+    {
+        let id = Cell::encode_cell_key(schema.to_id(&self.schemas), &key.value());
+        self.update_vertex(id, update)
+    }
+    pub fn update_vertex_with<V, U>(&self, vertex: V, options: UpdateOptions, update: U)
+        -> impl Future<Item = Result<(), vertex::UpdateError>, Error = TxnError>
+        where V: ToVertexId, U: Fn(Vertex) -> Option<Vertex>, U: 'static
+    {
+        let id = vertex.to_id();
+        self.graph_transaction(move |txn| txn.update_vertex_with(id, options, &update))
+    }
+
+    pub fn vertex_by<V>(this: Arc<Self>, vertex: V)
+        -> impl Future<Item = Option<Vertex>, Error = ReadVertexError> where V: ToVertexId
+    {
+        let id = vertex.to_id();
+        let schemas = this.schemas.clone();
+        let cache = this.vertex_cache.read().clone();
+        if let Some(ref cache) = cache {
+            if let Some(vertex) = cache.get(&id) { return future::Either::A(future::ok(Some(vertex))); }
+            if cache.is_missing(&id) { return future::Either::A(future::ok(None)); }
+        }
+        let generation = cache.as_ref().map(|cache| cache.generation());
+        let mut span = Span::enter("neb_read_cell");
+        span.record("id", format!("{},{}", id.higher, id.lower));
+        future::Either::B(this.neb_client.read_cell(id)
+            .then(move |result| {
+                if let Err(ref e) = result { span.fail(e); }
+                match result {
+                    Err(e) => Err(ReadVertexError::RPCError(e)),
+                    Ok(Err(ReadError::CellDoesNotExisted)) => {
+                        if let (&Some(ref cache), Some(generation)) = (&cache, generation) {
+                            cache.insert_missing(id, generation);
+                        }
+                        Ok(None)
+                    },
+                    Ok(Err(e)) => Err(ReadVertexError::ReadError(e)),
+                    Ok(Ok(cell)) => {
+                        let mut vertex = encryption::open_vertex(&schemas, vertex::cell_to_vertex(cell));
+                        computed::evaluate_virtual(&schemas.computed(vertex.schema()), &mut vertex);
+                        if let (&Some(ref cache), Some(generation)) = (&cache, generation) {
+                            cache.insert(&vertex, generation);
+                        }
+                        Ok(Some(vertex))
+                    }
+                }
+            }))
+    }
+
+    // reads are issued concurrently, results keep the order of the requested ids
+    pub fn vertices_by<V>(this: Arc<Self>, vertices: Vec<V>)
+        -> impl Future<Item = Vec<Option<Vertex>>, Error = ReadVertexError> where V: ToVertexId
+    {
+        let reads: Vec<_> = vertices
+            .into_iter()
+            .map(|v| Self::vertex_by(this.clone(), v.to_id()))
+            .collect();
+        future::join_all(reads)
+    }
+
+    pub fn vertex_by_key<K, S>(this: Arc<Self>, schema: S, key: K)
+        -> impl Future<Item = Option<Vertex>, Error = ReadVertexError>
+        where K: ToValue, S: ToSchemaId
+    {
+        let id = Cell::encode_cell_key(schema.to_id(&this.schemas), &key.value());
+        Self::vertex_by(this, id)
+    }
+
+    pub fn vertex_exists<V>(&self, vertex: V)
+        -> impl Future<Item = bool, Error = TxnError> where V: ToVertexId
+    {
+        let id = vertex.to_id();
+        self.graph_transaction(move |txn| txn.vertex_exists(id))
+    }
+
+    pub fn key_of<V>(&self, vertex: V)
+        -> impl Future<Item = Option<Value>, Error = TxnError> where V: ToVertexId
+    {
+        let id = vertex.to_id();
+        self.graph_transaction(move |txn| txn.key_of(id))
+    }
+
+    pub fn compact_adjacency<V>(&self, vertex: V)
+        -> impl Future<Item = Result<(), id_list::IdListError>, Error = TxnError>
+        where V: ToVertexId
+    {
+        let id = vertex.to_id();
+        self.graph_transaction(move |txn| txn.compact_adjacency(id))
+    }
+
+    pub fn has_edge<V, S>(&self, from: V, schema: S, to: V)
+        -> impl Future<Item = Result<bool, EdgeError>, Error = TxnError>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        let from_id = from.to_id();
+        let to_id = to.to_id();
+        let schema_id = schema.to_id(&self.schemas);
+        self.graph_transaction(move |txn| txn.has_edge(from_id, schema_id, to_id))
+    }
+
+    pub fn neighbour_set<V, S>(&self, a: V, b: V, schema: S, ed: EdgeDirection, op: NeighbourSetOp)
+        -> impl Future<Item = Result<Vec<Id>, EdgeError>, Error = TxnError>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        let a_id = a.to_id();
+        let b_id = b.to_id();
+        let schema_id = schema.to_id(&self.schemas);
+        self.graph_transaction(move |txn| txn.neighbour_set(a_id, b_id, schema_id, ed, op))
+    }
+
+    pub fn similar_vertices<V, S>(&self, vertex: V, schema: S, ed: EdgeDirection, metric: SimilarityMetric, top_k: usize)
+        -> impl Future<Item = Result<Vec<(Id, f64)>, EdgeError>, Error = TxnError>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        let id = vertex.to_id();
+        let schema_id = schema.to_id(&self.schemas);
+        self.graph_transaction(move |txn| txn.similar_vertices(id, schema_id, ed, metric, top_k))
+    }
+
+    pub fn subgraph<V, S, F>(&self, start: Vec<V>, edge_schemas: Vec<S>, depth: usize, filter: &Option<F>)
+        -> impl Future<Item = Result<Subgraph, NeighbourhoodError>, Error = TxnError>
+        where V: ToVertexId, S: ToSchemaId, F: Expr
+    {
+        let start: Vec<Id> = start.iter().map(|v| v.to_id()).collect();
+        let schema_ids: Vec<u32> = edge_schemas.iter().map(|s| s.to_id(&self.schemas)).collect();
+        let filter = match parse_optional_expr(filter) {
+            Ok(filter) => filter,
+            Err(e) => return future::Either::A(future::ok(Err(NeighbourhoodError::FilterEvalError(e))))
+        };
+        future::Either::B(self.graph_transaction(move |txn| txn.subgraph(&start, &schema_ids, depth, &filter)))
+    }
+
+    pub fn tree<V, S>(&self, start: V, edge_schema: S, towards_children: bool, max_depth: usize)
+        -> impl Future<Item = Result<tree::Tree, NeighbourhoodError>, Error = TxnError>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        let start = start.to_id();
+        let schema_id = edge_schema.to_id(&self.schemas);
+        self.graph_transaction(move |txn| txn.tree(start, schema_id, towards_children, max_depth))
+    }
+
+    pub fn edges_of_schemas<V, F>(&self, vertex: V, schemas: EdgeSchemas, ed: EdgeDirection, filter: &Option<F>)
+        -> impl Future<Item = Result<Vec<edge::Edge>, EdgeError>, Error = TxnError>
+        where V: ToVertexId, F: Expr
+    {
+        let vertex_id = vertex.to_id();
+        let filter = match parse_optional_expr(filter) {
+            Ok(filter) => filter,
+            Err(e) => return future::Either::A(future::ok(Err(EdgeError::FilterEvalError(e))))
+        };
+        let limits = *self.result_limits.read();
+        future::Either::B(self.graph_transaction(move |txn| txn.edges_of_schemas(vertex_id, &schemas, ed, &filter))
+            .map(move |result| result.and_then(|edges| {
+                limits.check(&edges, result_limit::edge_size).map(|_| edges).map_err(EdgeError::Overflow)
+            })))
+    }
+
+    pub fn vertices_near<S>(&self, schema: S, field: &str, center: GeoPoint, radius: f64)
+        -> impl Future<Item = Result<Vec<(Vertex, f64)>, GeoError>, Error = TxnError>
+        where S: ToSchemaId
+    {
+        let schema_id = schema.to_id(&self.schemas);
+        let field = field.to_string();
+        self.graph_transaction(move |txn| txn.vertices_near(schema_id, &field, center, radius))
+    }
+
+    // The index is registered before the scan so writes committing meanwhile reach it too. A write
+    // committing between the scan reading a vertex and indexing it is overwritten by the older vector,
+    // build again after bulk writes if that matters.
+    pub fn build_vector_index<S>(this: Arc<Self>, schema: S, field: &str, options: HnswOptions)
+        -> impl Future<Item = usize, Error = ScanVerticesError>
+        where S: ToSchemaId
+    {
+        let schema_id = schema.to_id(&this.schemas);
+        let index = Arc::new(RwLock::new(vector::Hnsw::new(options)));
+        this.vectors.put(schema_id, field, index.clone());
+        let field = field.to_string();
+        GraphInner::scan_vertices(this, schema_id, &None::<String>, Some(vec![field.clone()]))
+            .fold(0, move |indexed, vertex| {
+                let vector = match vector::vector_from_value(&vertex[field.as_str()]) {
+                    Some(vector) => vector, None => return Ok(indexed)
+                };
+                match index.write().insert(vertex.cell.id(), vector) {
+                    Ok(()) => Ok(indexed + 1),
+                    Err(e) => {
+                        warn!("Vector of {:?} not indexed for field {}: {:?}", vertex.cell.id(), field, e);
+                        Ok(indexed)
+                    }
+                }
+            })
+    }
+
+    pub fn knn<S>(&self, schema: S, field: &str, query_vector: Vec<f32>, k: usize)
+        -> impl Future<Item = Result<Vec<(Vertex, f32)>, VectorError>, Error = TxnError>
+        where S: ToSchemaId
+    {
+        let schema_id = schema.to_id(&self.schemas);
+        let nearest = match self.vectors.get(schema_id, field) {
+            Some(index) => index.read().search(&query_vector, k),
+            None => Err(VectorError::IndexNotFound)
+        };
+        let nearest = match nearest {
+            Ok(nearest) => nearest,
+            Err(e) => return future::Either::A(future::ok(Err(e)))
+        };
+        // the index can be ahead of a vertex removed by another server, those are left out
+        future::Either::B(self.graph_transaction(move |txn| {
+            let mut vertices = Vec::with_capacity(nearest.len());
+            for &(id, distance) in &nearest {
+                if let Some(vertex) = txn.read_vertex(id)? {
+                    vertices.push((vertex, distance));
+                }
+            }
+            Ok(Ok(vertices))
+        }))
+    }
+
+    pub fn traverse_neighbours<V>(this: Arc<Self>, vertex: V, options: TraverseOptions)
+        -> impl Future<Item = Result<Vec<Neighbour>, NeighbourhoodError>, Error = TxnError>
+        where V: ToVertexId
+    {
+        let vertex_id = vertex.to_id();
+        let started = Instant::now();
+        let filter = match parse_optional_expr(&options.filter) {
+            Ok(filter) => filter,
+            Err(e) => return future::Either::A(future::ok(Err(NeighbourhoodError::FilterEvalError(e))))
+        };
+        let logged_schemas = match options.schemas {
+            EdgeSchemas::Of(ref schema_ids) => schema_ids.clone(),
+            EdgeSchemas::Any => vec![]
+        };
+        let logged_filter = filter.clone();
+        let limits = *this.result_limits.read();
+        future::Either::B(this.self_logged_transaction(move |txn| txn.traverse_neighbours(vertex_id, &options, &filter))
+            .map(move |result| {
+                if let Ok(ref neighbours) = result {
+                    slow_log::check("traverse_neighbours", started, neighbours.len(), &logged_schemas, &logged_filter);
+                    if let Err(overflow) = limits.check(neighbours, result_limit::traversed_size) {
+                        return Err(NeighbourhoodError::Overflow(overflow));
+                    }
+                }
+                result
+            }))
     }
-    pub fn new_edge_group(&self, mut schema: MorpheusSchema, edge_attrs: edge::EdgeAttributes)
-        -> impl Future<Item = u32, Error = SchemaError>
+
+    pub fn neighbourhoods_of_schemas<V, F>(&self, vertex: V, schemas: EdgeSchemas, ed: EdgeDirection, filter: &Option<F>)
+        -> impl Future<Item = Result<Vec<(Vertex, edge::Edge)>, NeighbourhoodError>, Error = TxnError>
+        where V: ToVertexId, F: Expr
     {
-        schema.schema_type = SchemaType::Edge(edge_attrs);
-        self.schemas.new_schema(schema)
+        let vertex_id = vertex.to_id();
+        let filter = match parse_optional_expr(filter) {
+            Ok(filter) => filter,
+            Err(e) => return future::Either::A(future::ok(Err(NeighbourhoodError::FilterEvalError(e))))
+        };
+        let limits = *self.result_limits.read();
+        future::Either::B(self.graph_transaction(move |txn| txn.neighbourhoods_of_schemas(vertex_id, &schemas, ed, &filter))
+            .map(move |result| result.and_then(|neighbours| {
+                limits.check(&neighbours, result_limit::neighbour_size).map(|_| neighbours).map_err(NeighbourhoodError::Overflow)
+            })))
     }
-    pub fn new_vertex<S>(this: Arc<Self>, schema: S, data: Map)
-        -> impl Future<Item = Vertex, Error = NewVertexError>
-        where S: ToSchemaId
+
+    pub fn scan_vertices<S, F>(this: Arc<Self>, schema: S, filter: &Option<F>, projection: Option<Vec<String>>)
+        -> impl Stream<Item = Vertex, Error = ScanVerticesError>
+        where S: ToSchemaId, F: Expr
     {
-        let vertex = Vertex::new(schema.to_id(&this.schemas), data);
-        let mut cell_result = vertex_to_cell_for_write(&this.schemas, vertex);
-        async_block! {
-            let mut cell = cell_result?;
-            let header = match await!(this.neb_client.write_cell(cell.clone())) {
-                Ok(Ok(header)) => header,
-                Ok(Err(e)) => return Err(NewVertexError::WriteError(e)),
-                Err(e) => return Err(NewVertexError::RPCError(e))
-            };
-            cell.header = header;
-            Ok(vertex::cell_to_vertex(cell))
-        }
+        let schema_id = schema.to_id(&this.schemas);
+        let checked_filter = match this.schemas.schema_type(schema_id) {
+            Some(SchemaType::Vertex) => parse_optional_expr(filter)
+                .map_err(ScanVerticesError::FilterEvalError),
+            _ => Err(ScanVerticesError::SchemaNotVertex)
+        };
+        let neb_client = this.neb_client.clone();
+        let schemas = this.schemas.clone();
+        future::result(checked_filter)
+            .map(move |filter_sexpr| {
+                scan::scan_cells(neb_client, schema_id, scan::DEFAULT_SCAN_BATCH)
+                    .map_err(ScanVerticesError::ScanError)
+                    .map(|cells| stream::iter_ok::<_, ScanVerticesError>(cells))
+                    .flatten()
+                    .and_then(move |cell| {
+                        let vertex = encryption::open_vertex(&schemas, vertex::cell_to_vertex(cell));
+                        match Tester::eval_with_vertex(&filter_sexpr, &vertex) {
+                            Ok(true) => Ok(Some(vertex)),
+                            Ok(false) => Ok(None),
+                            Err(e) => Err(ScanVerticesError::FilterEvalError(e))
+                        }
+                    })
+                    .filter_map(move |vertex| vertex.map(|v| vertex::project(v, &projection)))
+            })
+            .flatten_stream()
     }
-    pub fn remove_vertex<V>(&self, vertex: V)
-        -> impl Future<Item = (), Error = TxnError> where V: ToVertexId
+
+    // bodied edges come from their own cells, simple edges are synthesized from vertex id lists
+    pub fn scan_edges<S, F>(this: Arc<Self>, schema: S, filter: &Option<F>)
+        -> impl Stream<Item = edge::Edge, Error = ScanEdgesError>
+        where S: ToSchemaId, F: Expr
     {
-        let id = vertex.to_id();
-        self.graph_transaction(move |txn| txn.remove_vertex(id)?
-            .map_err(|_| TxnError::Aborted(None)))
+        let schema_id = schema.to_id(&this.schemas);
+        let checked_filter = match this.schemas.schema_type(schema_id) {
+            Some(SchemaType::Edge(edge_attrs)) => parse_optional_expr(filter)
+                .map(|filter_sexpr| (edge_attrs, filter_sexpr))
+                .map_err(ScanEdgesError::FilterEvalError),
+            Some(_) => Err(ScanEdgesError::SchemaNotEdge),
+            None => Err(ScanEdgesError::SchemaNotFound)
+        };
+        future::result(checked_filter)
+            .map(move |(edge_attrs, filter_sexpr)| {
+                let edges: Box<Stream<Item = edge::Edge, Error = ScanEdgesError>> = if edge_attrs.has_body {
+                    let schemas = this.schemas.clone();
+                    Box::new(scan::scan_cells(this.neb_client.clone(), schema_id, scan::DEFAULT_SCAN_BATCH)
+                        .map_err(ScanEdgesError::ScanError)
+                        .map(|cells| stream::iter_ok::<_, ScanEdgesError>(cells))
+                        .flatten()
+                        .and_then(move |cell| edge::from_cell(cell, &schemas).map_err(ScanEdgesError::EdgeError)))
+                } else {
+                    Box::new(Self::scan_simple_edges(this, schema_id, edge_attrs))
+                };
+                edges
+                    .and_then(move |edge| {
+                        match Tester::eval_with_edge(&filter_sexpr, &edge) {
+                            Ok(true) => Ok(Some(edge)),
+                            Ok(false) => Ok(None),
+                            Err(e) => Err(ScanEdgesError::FilterEvalError(e))
+                        }
+                    })
+                    .filter_map(|edge| edge)
+            })
+            .flatten_stream()
     }
-    pub fn remove_vertex_by_key<K, S>(&self, schema: S, key: K)
-        -> impl Future<Item = (), Error = TxnError>
-        where K: ToValue, S: ToSchemaId
+
+    fn scan_simple_edges(this: Arc<Self>, schema_id: u32, edge_attrs: EdgeAttributes)
+        -> impl Stream<Item = edge::Edge, Error = ScanEdgesError>
     {
-        let id = Cell::encode_cell_key(schema.to_id(&self.schemas), &key.value());
-        self.remove_vertex(id)
+        let edge_type = edge_attrs.edge_type;
+        let vertex_field = match edge_type {
+            edge::EdgeType::Directed => *fields::OUTBOUND_KEY_ID,
+            edge::EdgeType::Undirected => *fields::UNDIRECTED_KEY_ID
+        };
+        let neb_client = this.neb_client.clone();
+        stream::iter_ok::<_, ScanEdgesError>(this.schemas.vertex_schema_ids())
+            .map(move |vertex_schema_id| {
+                scan::scan_cells(neb_client.clone(), vertex_schema_id, scan::DEFAULT_SCAN_BATCH)
+                    .map_err(ScanEdgesError::ScanError)
+            })
+            .flatten()
+            .and_then(move |cells| {
+                let vertex_ids: Vec<Id> = cells.iter().map(|cell| cell.id()).collect();
+                this.graph_transaction(move |txn| {
+                    let mut pairs = Vec::new();
+                    for vertex_id in &vertex_ids {
+                        let opposite_ids = match id_list::IdList::from_txn_and_container(
+                            txn.neb_txn, vertex_id, vertex_field, schema_id
+                        ).all()? {
+                            Ok(ids) => ids, Err(e) => return Ok(Err(e))
+                        };
+                        for opposite_id in opposite_ids {
+                            // undirected edges sit in both endpoint lists, only emit them from the lower id
+                            if edge_type == edge::EdgeType::Undirected &&
+                                (vertex_id.higher, vertex_id.lower) > (opposite_id.higher, opposite_id.lower) {
+                                continue;
+                            }
+                            pairs.push((*vertex_id, opposite_id));
+                        }
+                    }
+                    Ok(Ok(pairs))
+                }).then(|result| {
+                    match result {
+                        Ok(Ok(pairs)) => Ok(pairs),
+                        Ok(Err(e)) => Err(ScanEdgesError::IdListError(e)),
+                        Err(e) => Err(ScanEdgesError::TxnError(e))
+                    }
+                })
+            })
+            .map(move |pairs| {
+                stream::iter_ok::<_, ScanEdgesError>(pairs.into_iter().map(move |(vertex_a, vertex_b)| {
+                    edge::from_pair(edge_type, vertex_a, vertex_b, schema_id)
+                }))
+            })
+            .flatten()
     }
-    pub fn update_vertex<V, U>(&self, vertex: V, update: U) -> impl Future<Item = (), Error = TxnError>
-        where V: ToVertexId, U: Fn(Vertex) -> Option<Vertex>, U: 'static
-    {
-        let id = vertex.to_id();
-        self.neb_client.transaction(move |txn|{
-            vertex::txn_update(txn, id, &update)
-        })
+
+    // a count taken later than the one recorded wins, whether it came from statistics or a scan
+    fn record_count(&self, schema_id: u32, count: usize, at: u64) {
+        self.counts.upsert(schema_id, || KnownCount { count, at }, |known| {
+            if known.at <= at { *known = KnownCount { count, at }; }
+        });
     }
-    pub fn update_vertex_by_key<K, U, S>(&self, schema: S, key: K, update: U)
-        -> impl Future<Item = (), Error = TxnError>
-        where K: ToValue, S: ToSchemaId, U: Fn(Vertex) -> Option<Vertex>, U: 'static
+
+    // Approximate counts are served from the last count of the schema while it is younger than
+    // count_max_age. Mutations since then are not accounted, the bound is what keeps the estimate
+    // close; past it the schema is counted by a scan, which records a fresh count.
+    fn known_count(&self, schema_id: u32, mode: CountMode) -> Option<CountEstimate> {
+        if mode != CountMode::Approximate { return None; }
+        let max_age = self.count_max_age.read().as_secs();
+        let known = match self.counts.get(&schema_id) {
+            Some(known) => *known, None => return None
+        };
+        if window_counter::now_secs().saturating_sub(known.at) > max_age {
+            self.counts.remove(&schema_id);
+            return None;
+        }
+        Some(CountEstimate { count: known.count, exact: false })
+    }
+
+    pub fn count_vertices<S, F>(this: Arc<Self>, schema: S, filter: &Option<F>, mode: CountMode)
+        -> impl Future<Item = CountEstimate, Error = ScanVerticesError>
+        where S: ToSchemaId, F: Expr
     {
-        let id = Cell::encode_cell_key(schema.to_id(&self.schemas), &key.value());
-        self.update_vertex(id, update)
+        let schema_id = schema.to_id(&this.schemas);
+        let unfiltered = filter.is_none();
+        if unfiltered {
+            if let Some(estimate) = this.known_count(schema_id, mode) {
+                return future::Either::A(future::ok(estimate));
+            }
+        }
+        let started = window_counter::now_secs();
+        let inner = this.clone();
+        future::Either::B(Self::scan_vertices(this, schema_id, filter, Some(Vec::new()))
+            .fold(0, |count, _| Ok::<_, ScanVerticesError>(count + 1))
+            .map(move |count| {
+                if unfiltered { inner.record_count(schema_id, count, started); }
+                CountEstimate { count, exact: true }
+            }))
     }
 
-    pub fn vertex_by<V>(this: Arc<Self>, vertex: V)
-        -> impl Future<Item = Option<Vertex>, Error = ReadVertexError> where V: ToVertexId
+    // undirected edges come once, their ends in the order the scan found them
+    pub fn all_edges<S>(this: Arc<Self>, schema: S)
+        -> impl Stream<Item = (Id, Id, Option<Cell>), Error = ScanEdgesError>
+        where S: ToSchemaId
     {
-        this.neb_client.read_cell(vertex.to_id())
-            .then(|result| {
-                match result {
-                    Err(e) => Err(ReadVertexError::RPCError(e)),
-                    Ok(Err(ReadError::CellDoesNotExisted)) => Ok(None),
-                    Ok(Err(e)) => Err(ReadVertexError::ReadError(e)),
-                    Ok(Ok(cell)) => Ok(Some(vertex::cell_to_vertex(cell)))
-                }
-            })
+        Self::scan_edges::<_, String>(this, schema, &None).map(|edge| {
+            let (from, to) = { let (from, to) = edge.ends(); (*from, *to) };
+            (from, to, edge.get_data().clone())
+        })
     }
 
-    pub fn vertex_by_key<K, S>(this: Arc<Self>, schema: S, key: K)
-        -> impl Future<Item = Option<Vertex>, Error = ReadVertexError>
-        where K: ToValue, S: ToSchemaId
+    pub fn count_edges<S>(this: Arc<Self>, schema: S, mode: CountMode)
+        -> impl Future<Item = CountEstimate, Error = ScanEdgesError>
+        where S: ToSchemaId
     {
-        let id = Cell::encode_cell_key(schema.to_id(&this.schemas), &key.value());
-        Self::vertex_by(this, id)
+        let schema_id = schema.to_id(&this.schemas);
+        if let Some(estimate) = this.known_count(schema_id, mode) {
+            return future::Either::A(future::ok(estimate));
+        }
+        let started = window_counter::now_secs();
+        let inner = this.clone();
+        future::Either::B(Self::scan_edges::<_, String>(this, schema_id, &None)
+            .fold(0, |count, _| Ok::<_, ScanEdgesError>(count + 1))
+            .map(move |count| {
+                inner.record_count(schema_id, count, started);
+                CountEstimate { count, exact: true }
+            }))
     }
 
     pub fn graph_transaction<TFN, TR>(&self, func: TFN) -> impl Future<Item = TR, Error = TxnError>
         where TFN: Fn(&GraphTransaction) -> Result<TR, TxnError>, TR: 'static, TFN: 'static
+    {
+        let policy = self.retry_policy.read().clone();
+        self.graph_transaction_with_retry(policy, func)
+    }
+    // for operations that slow log themselves, with their result size, schemas and filter
+    fn self_logged_transaction<TFN, TR>(&self, func: TFN) -> impl Future<Item = TR, Error = TxnError>
+        where TFN: Fn(&GraphTransaction) -> Result<TR, TxnError>, TR: 'static, TFN: 'static
+    {
+        let policy = self.retry_policy.read().clone();
+        self.run_transaction(policy, func, false)
+    }
+    pub fn graph_transaction_with_retry<TFN, TR>(&self, policy: RetryPolicy, func: TFN)
+        -> impl Future<Item = TR, Error = TxnError>
+        where TFN: Fn(&GraphTransaction) -> Result<TR, TxnError>, TR: 'static, TFN: 'static
+    {
+        self.run_transaction(policy, func, true)
+    }
+    // Every attempt is a new neb transaction, started again after the backoff on the shared timer.
+    // Transactions aborted on purpose, by GraphTransaction::abort, are not started again.
+    fn run_transaction<TFN, TR>(&self, policy: RetryPolicy, func: TFN, slow_logged: bool)
+        -> impl Future<Item = TR, Error = TxnError>
+        where TFN: Fn(&GraphTransaction) -> Result<TR, TxnError>, TR: 'static, TFN: 'static
     {
         let schemas = self.schemas.clone();
-        let wrapper = move |neb_txn: &Transaction| {
-            func(&GraphTransaction {
-                neb_txn,
-                schemas: schemas.clone()
+        let read_only = self.is_read_only();
+        let neb_client = self.neb_client.clone();
+        let vectors = self.vectors.clone();
+        let change_log = self.change_log.read().clone();
+        let query_cache = self.query_cache.read().clone();
+        let vertex_cache = self.vertex_cache.read().clone();
+        let quotas = self.quotas.read().clone();
+        // writes are recorded for the change log and for invalidating the caches
+        let recording = change_log.is_some() || query_cache.is_some() || vertex_cache.is_some();
+        let func = Arc::new(func);
+        let mut span = Span::enter("graph_transaction");
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let attempts_counter = attempts.clone();
+        let timer = metrics::TRANSACTION_SECONDS.start_timer();
+        let started = Instant::now();
+        // one watch for all attempts, the watchdog limit covers the whole operation
+        let guard = watchdog::register();
+        let watch = guard.watch().clone();
+        span.record("txn", watch.id());
+        future::loop_fn(1, move |attempt| {
+            let func = func.clone();
+            let schemas = schemas.clone();
+            let attempts_counter = attempts_counter.clone();
+            let policy = policy.clone();
+            let txn_watch = watch.clone();
+            let watch = watch.clone();
+            let txn_vectors = vectors.clone();
+            let vectors = vectors.clone();
+            let vector_changes: vector::PendingVectorChanges = Default::default();
+            let txn_vector_changes = vector_changes.clone();
+            let change_log = change_log.clone();
+            let query_cache = query_cache.clone();
+            let vertex_cache = vertex_cache.clone();
+            let quotas = quotas.clone();
+            let pending_changes: Option<changes::PendingChanges> = if recording { Some(Default::default()) } else { None };
+            let txn_pending_changes = pending_changes.clone();
+            let aborted = Arc::new(AtomicBool::new(false));
+            let txn_aborted = aborted.clone();
+            let txn_neb_client = neb_client.clone();
+            let wrapper = move |neb_txn: &Transaction| {
+                // neb runs the closure again on every retry
+                attempts_counter.fetch_add(1, Ordering::Relaxed);
+                txn_aborted.store(false, Ordering::Relaxed);
+                txn_vector_changes.lock().clear();
+                if let Some(ref pending) = txn_pending_changes { pending.lock().clear(); }
+                func(&GraphTransaction {
+                    neb_txn,
+                    neb_client: txn_neb_client.clone(),
+                    schemas: schemas.clone(),
+                    read_only,
+                    undo_log: RefCell::new(None),
+                    watch: txn_watch.clone(),
+                    vectors: txn_vectors.clone(),
+                    vector_changes: txn_vector_changes.clone(),
+                    changes: txn_pending_changes.clone(),
+                    quotas: quotas.clone(),
+                    aborted: txn_aborted.clone()
+                })
+            };
+            neb_client.transaction(wrapper).then(move |result| match result {
+                Ok(r) => {
+                    let changes = ::std::mem::replace(&mut *vector_changes.lock(), Vec::new());
+                    vectors.apply(changes);
+                    if let Some(ref pending) = pending_changes {
+                        let events = ::std::mem::replace(&mut *pending.lock(), Vec::new());
+                        if let Some(ref cache) = query_cache { cache.invalidate(&events); }
+                        if let Some(ref cache) = vertex_cache { cache.invalidate_committed(&events); }
+                        if let Some(ref log) = change_log { log.append(events); }
+                    }
+                    future::Either::A(future::ok(future::Loop::Break(r)))
+                },
+                // aborts from the watchdog or on purpose are final
+                Err(ref e) if !watch.is_aborted() && !aborted.load(Ordering::Relaxed) && policy.should_retry(attempt, e) => {
+                    debug!("Transaction attempt {} failed with {:?}, retrying", attempt, e);
+                    metrics::TRANSACTION_RETRIES.inc();
+                    future::Either::B(timer::delay(policy.backoff(attempt))
+                        .then(move |_| Ok(future::Loop::Continue(attempt + 1))))
+                },
+                Err(e) => future::Either::A(future::err(e))
             })
-        };
-        self.neb_client.transaction(wrapper)
+        }).then(move |result| {
+            drop(guard);
+            timer.observe_duration();
+            if slow_logged { slow_log::check("graph_transaction", started, 0, &[], &None); }
+            span.record("attempts", attempts.load(Ordering::Relaxed));
+            if let Err(ref e) = result {
+                metrics::TRANSACTION_FAILURES.inc();
+                span.fail(e);
+            }
+            result
+        })
     }
-    pub fn link<V, S>(&self, from: V, schema: S, to: V, body: Option<Map>)
+    pub fn link<V, S>(this: Arc<Self>, from: V, schema: S, to: V, body: Option<Map>)
         -> impl Future<Item = Result<edge::Edge, LinkVerticesError>, Error = TxnError>
         where V: ToVertexId, S: ToSchemaId
     {
-        let from_id = from.to_id();
-        let to_id = to.to_id();
-        let schema_id = schema.to_id(&self.schemas);
+        let link = batch::PendingLink {
+            from: from.to_id(),
+            schema_id: schema.to_id(&this.schemas),
+            to: to.to_id(),
+            body
+        };
+        metrics::LINK_OPS.inc();
+        let timer = metrics::LINK_SECONDS.start_timer();
+        let result = match this.link_queue.push(link) {
+            // the flusher answers every queued link, even on shutdown, unless it panicked
+            Ok(receiver) => future::Either::A(receiver.then(|result| match result {
+                Ok(link_result) => link_result,
+                Err(_) => Ok(Err(LinkVerticesError::Unanswered))
+            })),
+            Err(link) => future::Either::B(this.link_now(link))
+        };
+        result.then(move |result| {
+            timer.observe_duration();
+            result
+        })
+    }
+    fn link_now(&self, link: batch::PendingLink)
+        -> impl Future<Item = Result<edge::Edge, LinkVerticesError>, Error = TxnError>
+    {
         self.graph_transaction(move |txn| {
-            txn.link(from_id, schema_id, to_id, body.clone())
+            txn.link(link.from, link.schema_id, link.to, link.body.clone())
         })
     }
     pub fn degree<V, S>(&self, vertex: V, schema: S, ed: EdgeDirection)
@@ -374,20 +1910,75 @@ impl GraphInner {
     {
         let vertex_id = vertex.to_id();
         let schema_id = schema.to_id(&this.schemas);
+        let started = Instant::now();
+        let query_cache = this.query_cache.read().clone();
+        let limits = *this.result_limits.read();
+        let traversal_budget = this.memory_budget.read().clone();
         future::result(parse_optional_expr(filter))
             .map_err(|e| {
                 NeighbourhoodError::FilterEvalError(e)
             })
             .then(move |filter_sexpr_result| {
                 async_block! {
-                    match filter_sexpr_result {
-                        Ok(filter_sexpr) => {
-                            return await!(this.graph_transaction(move |txn| {
-                                txn.neighbourhoods(vertex_id, schema_id, ed, &filter_sexpr)
-                            }))
-                        },
+                    let filter_sexpr = match filter_sexpr_result {
+                        Ok(filter_sexpr) => filter_sexpr,
                         Err(e) => return Ok(Err(e))
+                    };
+                    let cache_key = cache::CacheKey::new(vertex_id, schema_id, ed, &filter_sexpr);
+                    if let Some(ref cache) = query_cache {
+                        if let Some(result) = cache.get(&cache_key) {
+                            return Ok(limits.check(&result, result_limit::neighbour_size)
+                                .map(|_| result).map_err(NeighbourhoodError::Overflow));
+                        }
                     }
+                    let generation = query_cache.as_ref().map(|cache| cache.generation());
+                    let txn_filter = filter_sexpr.clone();
+                    // edges are read in the transaction, their opposite vertices at once through the client pool
+                    let (edges, vertices, _reservation) = match await!(this.self_logged_transaction(move |txn| {
+                        let edges = match txn.neighbour_edges_in_context(&vertex_id, schema_id, ed, &txn_filter)? {
+                            Ok(edges) => edges, Err(e) => return Ok(Err(e))
+                        };
+                        if txn_filter.is_none() {
+                            if let Err(overflow) = limits.check_count(edges.len()) {
+                                return Ok(Err(NeighbourhoodError::Overflow(overflow)));
+                            }
+                        }
+                        // held until the result is handed back
+                        let reservation = match traversal_budget {
+                            Some(ref budget) => {
+                                let edge_bytes = edges.iter().map(|&(_, ref edge, _)| result_limit::edge_size(edge)).sum();
+                                match memory_budget::MemoryBudget::reserve(budget, budget.estimate(edge_bytes, edges.len())) {
+                                    Ok(reservation) => Some(reservation),
+                                    Err(e) => return Ok(Err(NeighbourhoodError::BudgetExceeded(e)))
+                                }
+                            },
+                            None => None
+                        };
+                        let vertices = match txn.opposite_vertices(&edges)? {
+                            Ok(vertices) => vertices, Err(e) => return Ok(Err(e))
+                        };
+                        Ok(Ok((edges, vertices, reservation)))
+                    }))? {
+                        Ok(read) => read, Err(e) => return Ok(Err(e))
+                    };
+                    metrics::TRAVERSAL_SIZE.observe(edges.len() as f64);
+                    let mut result = Vec::with_capacity(edges.len());
+                    for (opposite_id, edge, context) in edges {
+                        let vertex = vertices[&opposite_id].clone();
+                        match Tester::eval_in_context(&filter_sexpr, Some(&vertex), &edge, &context) {
+                            Ok(true) => {result.push((vertex, edge));},
+                            Ok(false) => {},
+                            Err(err) => return Ok(Err(NeighbourhoodError::FilterEvalError(err))),
+                        }
+                    }
+                    slow_log::check("neighbourhoods", started, result.len(), &[schema_id], &filter_sexpr);
+                    if let Err(overflow) = limits.check(&result, result_limit::neighbour_size) {
+                        return Ok(Err(NeighbourhoodError::Overflow(overflow)));
+                    }
+                    if let (&Some(ref cache), Some(generation)) = (&query_cache, generation) {
+                        cache.insert(cache_key, generation, &result);
+                    }
+                    Ok(Ok(result))
                 }
             })
     }
@@ -397,6 +1988,8 @@ impl GraphInner {
     {
         let vertex_id = vertex.to_id();
         let schema_id = schema.to_id(&this.schemas);
+        let started = Instant::now();
+        let limits = *this.result_limits.read();
         future::result(parse_optional_expr(filter))
             .map_err(|e| {
                 EdgeError::FilterEvalError(e)
@@ -405,9 +1998,17 @@ impl GraphInner {
                 async_block! {
                     match filter_result {
                         Ok(filter) => {
-                            return await!(this.graph_transaction(move |txn| {
+                            let logged_filter = filter.clone();
+                            let result = await!(this.self_logged_transaction(move |txn| {
                                 txn.edges(vertex_id, schema_id, ed, &filter)
-                            }))
+                            }))?;
+                            if let Ok(ref edges) = result {
+                                slow_log::check("edges", started, edges.len(), &[schema_id], &logged_filter);
+                                if let Err(overflow) = limits.check(edges, result_limit::edge_size) {
+                                    return Ok(Err(EdgeError::Overflow(overflow)));
+                                }
+                            }
+                            return Ok(result)
                         },
                         Err(e) => return Ok(Err(e))
                     }
@@ -418,25 +2019,98 @@ impl GraphInner {
 
 pub struct GraphTransaction<'a> {
     pub neb_txn: &'a Transaction,
-    schemas: Arc<SchemaContainer>
+    // reads outside of neb_txn that need no part in its conflict checks, see opposite_vertices
+    neb_client: Arc<NebClient>,
+    schemas: Arc<SchemaContainer>,
+    read_only: bool,
+    // None until the first savepoint, see graph::savepoint
+    undo_log: RefCell<Option<Vec<savepoint::Undo>>>,
+    watch: Arc<TxnWatch>,
+    vectors: Arc<vector::VectorIndexes>,
+    // applied to the vector indexes after commit, see graph::vector
+    vector_changes: vector::PendingVectorChanges,
+    // appended to the change log after commit, None while the log is off, see graph::changes
+    changes: Option<changes::PendingChanges>,
+    // None while no quota is set, see graph::quota
+    quotas: Option<Arc<quota::QuotaOptions>>,
+    // set by abort, the retry policy leaves such transactions aborted
+    aborted: Arc<AtomicBool>
 }
 
 impl <'a>GraphTransaction<'a> {
+    // Aborts the transaction for good. Aborts made on neb_txn directly look like conflicts to the
+    // retry policy and start the transaction again.
+    pub fn abort(&self) -> Result<(), TxnError> {
+        self.aborted.store(true, Ordering::Relaxed);
+        self.neb_txn.abort()
+    }
+    fn record_undo(&self, undo: savepoint::Undo) {
+        if let Some(ref mut log) = *self.undo_log.borrow_mut() {
+            log.push(undo);
+        }
+    }
+    fn has_savepoint(&self) -> bool {
+        self.undo_log.borrow().is_some()
+    }
     pub fn new_vertex<S>(&self, schema: S, data: Map)
         -> Result<Result<Vertex, NewVertexError>, TxnError>
         where S: ToSchemaId
     {
-        let vertex = Vertex::new(schema.to_id(&self.schemas), data);
-        let mut cell = match vertex_to_cell_for_write(&self.schemas, vertex) {
+        self.new_vertex_with_placement(schema, data, Placement::Auto)
+    }
+    pub fn new_vertex_with_placement<S>(&self, schema: S, data: Map, placement: Placement)
+        -> Result<Result<Vertex, NewVertexError>, TxnError>
+        where S: ToSchemaId
+    {
+        if self.read_only { return Ok(Err(NewVertexError::ReadOnly)); }
+        let schema_id = schema.to_id(&self.schemas);
+        self.watch.touch("new_vertex", Some(schema_id), Id::unit_id())?;
+        let vertex = Vertex::new(schema_id, data);
+        let mut cell = match vertex_to_cell_for_write(&self.schemas, vertex, &placement) {
             Ok(cell) => cell, Err(e) => return Ok(Err(e))
         };
+        self.assign_id(&mut cell, &placement)?;
+        if let Some(key) = keys::stored_key(&cell.data) {
+            if let Some(existing) = self.colliding_key(cell.id(), &key)? {
+                return Ok(Err(NewVertexError::KeyCollision(existing)));
+            }
+        }
+        if let Err(e) = self.account_quota(None, Some(&cell), true)? {
+            return Ok(Err(NewVertexError::QuotaExceeded(e)));
+        }
         self.neb_txn.write(&cell)?;
-        Ok(Ok(vertex::cell_to_vertex(cell)))
+        if let Err(e) = self.reindex_geo(None, Some(&cell))? {
+            return Ok(Err(NewVertexError::GeoIndexError(e)));
+        }
+        self.record_vectors(None, Some(&cell));
+        self.record_change(None, Some(&cell));
+        self.record_undo(savepoint::Undo::NewVertex(cell.id()));
+        Ok(Ok(encryption::open_vertex(&self.schemas, vertex::cell_to_vertex(cell))))
     }
     pub fn remove_vertex<V>(&self, vertex: V)
         -> Result<Result<(), vertex::RemoveError>, TxnError> where V: ToVertexId
     {
-        vertex::txn_remove(self.neb_txn, &self.schemas, vertex)
+        if self.read_only { return Ok(Err(vertex::RemoveError::ReadOnly)); }
+        let id = vertex.to_id();
+        self.watch.touch("remove_vertex", None, id)?;
+        let undo = if self.has_savepoint() {
+            match self.capture_removal(&id)? {
+                Ok(undo) => undo, Err(e) => return Ok(Err(vertex::RemoveError::EdgeError(e)))
+            }
+        } else { None };
+        if let Some(cell) = self.neb_txn.read(&id)? {
+            if let Err(e) = self.reindex_geo(Some(&cell), None)? {
+                return Ok(Err(vertex::RemoveError::GeoIndexError(e)));
+            }
+            self.record_vectors(Some(&cell), None);
+            self.record_change(Some(&cell), None);
+            let _ = self.account_quota(Some(&cell), None, false)?;
+        }
+        let removed = vertex::txn_remove(self.neb_txn, &self.schemas, id)?;
+        if let (&Ok(()), Some(undo)) = (&removed, undo) {
+            self.record_undo(undo);
+        }
+        Ok(removed)
     }
     pub fn remove_vertex_by_key<K, S>(&self, schema: S, key: K)
         -> Result<Result<(), vertex::RemoveError>, TxnError>
@@ -446,33 +2120,227 @@ impl <'a>GraphTransaction<'a> {
         self.remove_vertex(&id)
     }
 
+    pub fn compact_adjacency<V>(&self, vertex: V)
+        -> Result<Result<(), id_list::IdListError>, TxnError> where V: ToVertexId
+    {
+        if self.read_only { return Ok(Err(id_list::IdListError::ReadOnly)); }
+        let id = vertex.to_id();
+        self.watch.touch("compact_adjacency", None, id)?;
+        vertex::txn_compact_adjacency(self.neb_txn, id)
+    }
+
     pub fn link<V, S>(&self, from: V, schema: S, to: V, body: Option<Map>)
         -> Result<Result<edge::Edge, LinkVerticesError>, TxnError>
         where V: ToVertexId, S: ToSchemaId
     {
+        let (from_id, to_id) = (from.to_id(), to.to_id());
+        let schema_id = schema.to_id(&self.schemas);
+        let linked = self.link_uncounted(from_id, schema_id, to_id, body)?;
+        if linked.is_ok() { self.count_link(schema_id, &from_id, &to_id)?; }
+        Ok(linked)
+    }
+    // links without adding to the window counters, for edges restored or moved rather than made
+    pub(super) fn link_uncounted<V, S>(&self, from: V, schema: S, to: V, body: Option<Map>)
+        -> Result<Result<edge::Edge, LinkVerticesError>, TxnError>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        if self.read_only { return Ok(Err(LinkVerticesError::ReadOnly)); }
         let from_id = &from.to_id();
         let to_id = &to.to_id();
         let schema_id = schema.to_id(&self.schemas);
+        self.watch.touch("link", Some(schema_id), *from_id)?;
         let edge_attr = match self.schemas.schema_type(schema_id) {
             Some(SchemaType::Edge(ea)) => ea,
             Some(_) => return Ok(Err(LinkVerticesError::SchemaNotEdge)),
             None => return Ok(Err(LinkVerticesError::EdgeSchemaNotFound))
         };
-        match edge_attr.edge_type {
+        if !edge_attr.allow_self_loops && from_id == to_id {
+            return Ok(Err(LinkVerticesError::SelfLoopNotAllowed));
+        }
+        if let Err(e) = self.check_endpoints(schema_id, edge_attr.edge_type, *from_id, *to_id)? {
+            return Ok(Err(e));
+        }
+        let body = body.map(|mut body| {
+            validation::apply_defaults(&self.schemas.defaults(schema_id), &mut body);
+            body
+        });
+        if let (true, &Some(ref body)) = (edge_attr.has_body, &body) {
+            if let Some(neb_schema) = self.schemas.get_neb_schema(schema_id) {
+                if let Err(e) = validation::validate_edge_body(&neb_schema, edge_attr.edge_type, body) {
+                    return Ok(Err(LinkVerticesError::ValidationError(e)));
+                }
+            }
+        }
+        if edge_attr.unique_pairs {
+            match self.has_edge(from_id, schema_id, to_id)? {
+                Ok(false) => {},
+                Ok(true) => return Ok(Err(LinkVerticesError::EdgeError(EdgeError::DuplicateEdge))),
+                Err(e) => return Ok(Err(LinkVerticesError::EdgeError(e)))
+            }
+        }
+        let linked = match edge_attr.edge_type {
             edge::EdgeType::Directed =>
-                Ok(edge::directed::DirectedEdge::link(from_id, to_id, body, &self.neb_txn, schema_id, &self.schemas)?
-                    .map_err(LinkVerticesError::EdgeError).map(edge::Edge::Directed)),
+                edge::directed::DirectedEdge::link(from_id, to_id, body, &self.neb_txn, schema_id, &self.schemas)?
+                    .map_err(LinkVerticesError::EdgeError).map(edge::Edge::Directed),
 
             edge::EdgeType::Undirected =>
-                Ok(edge::undirectd::UndirectedEdge::link(from_id, to_id, body, &self.neb_txn, schema_id, &self.schemas)?
-                    .map_err(LinkVerticesError::EdgeError).map(edge::Edge::Undirected))
+                edge::undirectd::UndirectedEdge::link(from_id, to_id, body, &self.neb_txn, schema_id, &self.schemas)?
+                    .map_err(LinkVerticesError::EdgeError).map(edge::Edge::Undirected)
+        };
+        if let Ok(ref edge) = linked {
+            self.record_edge_change(changes::ChangeKind::Linked, edge);
+            if self.has_savepoint() { self.record_undo(savepoint::Undo::Link(edge.clone())); }
+        }
+        Ok(linked)
+    }
+    // removes every edge of the schema going from one vertex to the other, resolves to how many there were
+    pub fn unlink<V, S>(&self, from: V, schema: S, to: V)
+        -> Result<Result<usize, EdgeError>, TxnError>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        if self.read_only { return Ok(Err(EdgeError::ReadOnly)); }
+        let (schema_id, edge_attr) = match edge_attr_from_schema(schema, &self.schemas) {
+            Err(e) => return Ok(Err(e)), Ok(t) => t
+        };
+        let from_id = from.to_id();
+        let to_id = to.to_id();
+        self.watch.touch("unlink", Some(schema_id), from_id)?;
+        let direction = match edge_attr.edge_type {
+            edge::EdgeType::Directed => EdgeDirection::Outbound,
+            edge::EdgeType::Undirected => EdgeDirection::Undirected
+        };
+        let edges = match self.edges(&from_id, schema_id, direction, &None)? {
+            Ok(edges) => edges, Err(e) => return Ok(Err(e))
+        };
+        let mut removed = 0;
+        for edge in edges {
+            if edge.one_opposite_id_vertex_id(&from_id) != Some(&to_id) { continue; }
+            if let Err(e) = edge.clone().remove(self.neb_txn, &self.schemas)? {
+                return Ok(Err(e));
+            }
+            self.record_edge_change(changes::ChangeKind::Unlinked, &edge);
+            if self.has_savepoint() { self.record_undo(savepoint::Undo::Unlink(edge)); }
+            removed += 1;
+        }
+        Ok(Ok(removed))
+    }
+    // Moves the second end of the edge, the to end of directed ones, onto the new target.
+    // Bodied edges keep their body cell, resolves to the edge as it is now.
+    pub fn rewire_edge<V>(&self, edge: &edge::Edge, new_target: V)
+        -> Result<Result<edge::Edge, LinkVerticesError>, TxnError>
+        where V: ToVertexId
+    {
+        if self.read_only { return Ok(Err(LinkVerticesError::ReadOnly)); }
+        let schema_id = edge.schema_id();
+        let (from_id, to_id) = { let (from, to) = edge.ends(); (*from, *to) };
+        let target_id = new_target.to_id();
+        self.watch.touch("rewire_edge", Some(schema_id), from_id)?;
+        if target_id == to_id { return Ok(Ok(edge.clone())); }
+        let edge_attr = match self.schemas.schema_type(schema_id) {
+            Some(SchemaType::Edge(ea)) => ea,
+            Some(_) => return Ok(Err(LinkVerticesError::SchemaNotEdge)),
+            None => return Ok(Err(LinkVerticesError::EdgeSchemaNotFound))
+        };
+        if !self.vertex_exists(target_id)? {
+            return Ok(Err(LinkVerticesError::VertexNotFound(target_id)));
+        }
+        if !edge_attr.allow_self_loops && from_id == target_id {
+            return Ok(Err(LinkVerticesError::SelfLoopNotAllowed));
+        }
+        if let Err(e) = self.check_endpoints(schema_id, edge_attr.edge_type, from_id, target_id)? {
+            return Ok(Err(e));
+        }
+        if edge_attr.unique_pairs {
+            match self.has_edge(from_id, schema_id, target_id)? {
+                Ok(false) => {},
+                Ok(true) => return Ok(Err(LinkVerticesError::EdgeError(EdgeError::DuplicateEdge))),
+                Err(e) => return Ok(Err(LinkVerticesError::EdgeError(e)))
+            }
+        }
+        let rewired = edge.clone().rewire(&target_id, self.neb_txn, &self.schemas)?.map_err(LinkVerticesError::EdgeError);
+        if let Ok(ref rewired) = rewired {
+            self.record_edge_change(changes::ChangeKind::Unlinked, edge);
+            self.record_edge_change(changes::ChangeKind::Linked, rewired);
+            if self.has_savepoint() {
+                self.record_undo(savepoint::Undo::Unlink(edge.clone()));
+                self.record_undo(savepoint::Undo::Link(rewired.clone()));
+            }
         }
+        Ok(rewired)
     }
 
+    // aborts on any UpdateError, ReadOnly included, update_vertex_with reports them instead
     pub fn update_vertex<V, U>(&self, vertex: V, update: U) -> Result<(), TxnError>
         where V: ToVertexId, U: Fn(Vertex) -> Option<Vertex>
     {
-        vertex::txn_update(self.neb_txn, vertex, &update)
+        match self.update_vertex_with(vertex, UpdateOptions::default(), update)? {
+            Ok(()) => Ok(()),
+            Err(_) => self.abort()
+        }
+    }
+    pub fn update_vertex_with<V, U>(&self, vertex: V, options: UpdateOptions, update: U)
+        -> Result<Result<(), vertex::UpdateError>, TxnError>
+        where V: ToVertexId, U: Fn(Vertex) -> Option<Vertex>
+    {
+        if self.read_only { return Ok(Err(vertex::UpdateError::ReadOnly)); }
+        let id = vertex.to_id();
+        self.watch.touch("update_vertex", None, id)?;
+        let before = self.neb_txn.read(&id)?;
+        let computed_fields = before.as_ref().map(|c| self.schemas.computed(c.header.schema)).unwrap_or_default();
+        let context = if computed::has_mode(&computed_fields, ComputedMode::Materialized) {
+            Some(self.computed_context(id, &computed_fields, ComputedMode::Materialized)?)
+        } else { None };
+        let computed_failure = RefCell::new(None);
+        let encryption_failure = RefCell::new(None);
+        let updated = {
+            // the update sees the encrypted fields opened, they are sealed again after the computed ones.
+            // Vertices that cannot be opened are written back as they are, ones that cannot be sealed abort.
+            let materialized_update = |mut vertex: Vertex| {
+                if let Err(e) = encryption::try_open_cell(&self.schemas, &mut vertex.cell) {
+                    *encryption_failure.borrow_mut() = Some(e);
+                    return Some(vertex);
+                }
+                let mut vertex = match update(vertex) {
+                    Some(vertex) => vertex,
+                    // txn_update_checked aborts, on purpose
+                    None => { self.aborted.store(true, Ordering::Relaxed); return None; }
+                };
+                let schema_id = vertex.schema();
+                if let &mut Value::Map(ref mut data) = &mut vertex.cell.data {
+                    if let &Some(ref context) = &context {
+                        if let Err(e) = computed::evaluate(&computed_fields, ComputedMode::Materialized, data, context) {
+                            *computed_failure.borrow_mut() = Some(e);
+                        }
+                    }
+                    if let Err(e) = encryption::seal_fields(&self.schemas, schema_id, data) {
+                        *encryption_failure.borrow_mut() = Some(e);
+                        self.aborted.store(true, Ordering::Relaxed);
+                        return None;
+                    }
+                }
+                Some(vertex)
+            };
+            vertex::txn_update_checked(self.neb_txn, id, options.check_version, &materialized_update)?
+        };
+        if let Some(e) = computed_failure.into_inner() {
+            return Ok(Err(vertex::UpdateError::ComputedFieldError(e)));
+        }
+        if let Some(e) = encryption_failure.into_inner() {
+            return Ok(Err(vertex::UpdateError::EncryptionError(e)));
+        }
+        if updated.is_ok() {
+            let after = self.neb_txn.read(&id)?;
+            if let Err(e) = self.reindex_geo(before.as_ref(), after.as_ref())? {
+                return Ok(Err(vertex::UpdateError::GeoIndexError(e)));
+            }
+            self.record_vectors(before.as_ref(), after.as_ref());
+            self.record_change(before.as_ref(), after.as_ref());
+            let _ = self.account_quota(before.as_ref(), after.as_ref(), false)?;
+        }
+        if let (&Ok(()), Some(cell)) = (&updated, before) {
+            if self.has_savepoint() { self.record_undo(savepoint::Undo::UpdateVertex(cell)); }
+        }
+        Ok(updated)
     }
     pub fn update_vertex_by_key<K, U, S>(&self, schema: S, key: K, update: U)
         -> Result<(), TxnError>
@@ -485,7 +2353,73 @@ impl <'a>GraphTransaction<'a> {
     pub fn read_vertex<V>(&self, vertex: V)
         -> Result<Option<Vertex>, TxnError> where V: ToVertexId
     {
-        self.neb_txn.read(&vertex.to_id()).map(|c| c.map(vertex::cell_to_vertex))
+        let id = vertex.to_id();
+        self.watch.touch("read_vertex", None, id)?;
+        match self.neb_txn.read(&id)? {
+            Some(cell) => self.with_virtual_fields(encryption::open_vertex(&self.schemas, vertex::cell_to_vertex(cell))).map(Some),
+            None => Ok(None)
+        }
+    }
+
+    // probe the adjacency fields of the vertex template instead of reading the whole vertex body.
+    // Only vertex schemas carry them, cells of edge and id list schemas probe as Null
+    pub fn vertex_exists<V>(&self, vertex: V) -> Result<bool, TxnError> where V: ToVertexId {
+        Ok(match self.neb_txn.read_selected(&vertex.to_id(), &*fields::VERTEX_PROBE_FIELDS)? {
+            Some(probed) => !probed.is_empty() && probed.iter().all(|v| match v { &Value::Id(_) => true, _ => false }),
+            None => false
+        })
+    }
+
+    pub fn has_edge<V, S>(&self, from: V, schema: S, to: V)
+        -> Result<Result<bool, EdgeError>, TxnError>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        let (schema_id, edge_attr) = match edge_attr_from_schema(schema, &self.schemas) {
+            Err(e) => return Ok(Err(e)), Ok(t) => t
+        };
+        let from_id = &from.to_id();
+        let to_id = to.to_id();
+        self.watch.touch("has_edge", Some(schema_id), *from_id)?;
+        let (vertex_field, opposite_fields) = match edge_attr.edge_type {
+            edge::EdgeType::Directed => (
+                *fields::OUTBOUND_KEY_ID,
+                vec![edge::directed::DirectedEdge::edge_b_field()]
+            ),
+            edge::EdgeType::Undirected => (
+                *fields::UNDIRECTED_KEY_ID,
+                vec![edge::undirectd::UndirectedEdge::edge_a_field(),
+                     edge::undirectd::UndirectedEdge::edge_b_field()]
+            )
+        };
+        let mut id_list = id_list::IdList::from_txn_and_container(
+            self.neb_txn, from_id, vertex_field, schema_id);
+        if !edge_attr.has_body {
+            // simple edges store the opposite vertex id in the list directly
+            return Ok(id_list.contains(&to_id)?.map_err(EdgeError::IdListError));
+        }
+        let mut index = id_list::IdList::opposite_index(self.neb_txn, from_id, vertex_field, schema_id);
+        match index.is_assigned()? {
+            Ok(true) => return Ok(index.contains(&to_id)?.map_err(EdgeError::IdListError)),
+            Ok(false) => {}, Err(e) => return Ok(Err(EdgeError::IdListError(e)))
+        }
+        // a list not indexed yet was written before the index existed, find the edge through the bodies
+        let edge_ids = match id_list.all()? {
+            Ok(ids) => ids, Err(e) => return Ok(Err(EdgeError::IdListError(e)))
+        };
+        for edge_id in edge_ids {
+            if let Some(ends) = self.neb_txn.read_selected(&edge_id, &opposite_fields)? {
+                // an undirected edge is listed on both ends, the opposite of from is whichever end it is not
+                let opposite = match (ends.get(0), ends.get(1)) {
+                    (Some(&Value::Id(a)), Some(&Value::Id(b))) => if &a == from_id { b } else { a },
+                    (Some(&Value::Id(b)), None) => b,
+                    _ => continue
+                };
+                if opposite == to_id {
+                    return Ok(Ok(true));
+                }
+            }
+        }
+        Ok(Ok(false))
     }
 
     pub fn get_vertex<K, S>(&self, schema: u32, key: K) -> Result<Option<Vertex>, TxnError>
@@ -500,31 +2434,155 @@ impl <'a>GraphTransaction<'a> {
     ) -> Result<Result<Vec<edge::Edge>, edge::EdgeError>, TxnError>
         where V: ToVertexId, S: ToSchemaId
     {
-        let vertex_field = ed.as_field();
         let schema_id = schema.to_id(&self.schemas);
         let vertex_id = &vertex.to_id();
-        match id_list::IdList::from_txn_and_container
-            (self.neb_txn, vertex_id, vertex_field, schema_id).iter()? {
-            Err(e) => Ok(Err(edge::EdgeError::IdListError(e))),
-            Ok(ids) => Ok(Ok({
-                let mut edges = Vec::new();
-                for id in ids {
-                    match edge::from_id(
-                        vertex_id, vertex_field, schema_id, &self.schemas, self.neb_txn, &id
-                    )? {
-                        Ok(e) => {
-                            match Tester::eval_with_edge(filter, &e) {
-                                Ok(true) => {edges.push(e);},
-                                Ok(false) => {},
-                                Err(err) => return Ok(Err(EdgeError::FilterEvalError(err))),
-                            }
-                        },
-                        Err(er) => return Ok(Err(er))
+        self.watch.touch("edges", Some(schema_id), *vertex_id)?;
+        let ed = ed.for_schema(schema_id, &self.schemas);
+        let mut edges = Vec::new();
+        let degrees = query::refers_to_degrees(filter);
+        for (part, vertex_field) in ed.expand() {
+            match id_list::IdList::from_txn_and_container
+                (self.neb_txn, vertex_id, vertex_field, schema_id).iter()? {
+                Err(e) => return Ok(Err(edge::EdgeError::IdListError(e))),
+                Ok(mut ids) => {
+                    for id in ids.by_ref() {
+                        match edge::from_id(
+                            vertex_id, vertex_field, schema_id, &self.schemas, self.neb_txn, &id
+                        )? {
+                            Ok(e) => {
+                                if e.one_opposite_id_vertex_id(vertex_id).map(|o| ed.repeats(part, vertex_id, o)) == Some(true) {
+                                    continue;
+                                }
+                                let context = match e.one_opposite_id_vertex_id(vertex_id) {
+                                    Some(opposite_id) => match self.filter_context(opposite_id, &e, filter, degrees)? {
+                                        Ok(context) => context, Err(er) => return Ok(Err(er))
+                                    },
+                                    None => FilterContext::default()
+                                };
+                                match Tester::eval_in_context(filter, None, &e, &context) {
+                                    Ok(true) => {edges.push(e);},
+                                    Ok(false) => {},
+                                    Err(err) => return Ok(Err(EdgeError::FilterEvalError(err))),
+                                }
+                            },
+                            Err(er) => return Ok(Err(er))
+                        }
                     }
+                    if let Some(e) = ids.take_error() { return Ok(Err(edge::EdgeError::IdListError(e))); }
                 }
-                edges
-            }))
+            }
+        }
+        Ok(Ok(edges))
+    }
+
+    fn neighbour_edges(&self, vertex_id: &Id, schema_id: u32, ed: EdgeDirection)
+        -> Result<Result<Vec<(Id, edge::Edge)>, NeighbourhoodError>, TxnError>
+    {
+        let ed = ed.for_schema(schema_id, &self.schemas);
+        let mut result = Vec::new();
+        for (part, vertex_field) in ed.expand() {
+            let ids = match id_list::IdList::from_txn_and_container
+                (self.neb_txn, vertex_id, vertex_field, schema_id).all()? {
+                Err(e) => return Ok(Err(NeighbourhoodError::EdgeError(EdgeError::IdListError(e)))),
+                Ok(ids) => ids
+            };
+            for id in ids {
+                let edge = match edge::from_id(
+                    vertex_id, vertex_field, schema_id, &self.schemas, self.neb_txn, &id
+                )? {
+                    Ok(edge) => edge,
+                    Err(edge_error) => return Ok(Err(NeighbourhoodError::EdgeError(edge_error)))
+                };
+                let opposite_id = match edge.one_opposite_id_vertex_id(vertex_id) {
+                    Some(opposite_id) => *opposite_id,
+                    None => return Ok(Err(NeighbourhoodError::CannotFindOppositeId(*vertex_id)))
+                };
+                if ed.repeats(part, vertex_id, &opposite_id) { continue; }
+                result.push((opposite_id, edge));
+            }
+        }
+        Ok(Ok(result))
+    }
+
+    // Schema name, ends and degrees of the opposite vertex for filters to refer to. Left empty
+    // without a filter. The degrees cost two list counts per edge, they are left out unless the
+    // filter refers to them, see query::refers_to_degrees.
+    fn filter_context(&self, opposite_id: &Id, edge: &edge::Edge, filter: &Option<Vec<SExpr>>, degrees: bool)
+        -> Result<Result<FilterContext, EdgeError>, TxnError>
+    {
+        if filter.is_none() { return Ok(Ok(FilterContext::default())); }
+        let schema_id = edge.schema_id();
+        let (from, to) = edge.ends();
+        let context = FilterContext {
+            vertex_id: Some(*opposite_id),
+            edge_schema: self.schemas.get_neb_schema(schema_id)
+                .and_then(|schema| self.schemas.neb_to_morpheus_schema(&schema))
+                .map(|schema| schema.name),
+            edge_ends: Some((*from, *to)),
+            ..FilterContext::default()
+        };
+        if !degrees { return Ok(Ok(context)); }
+        let edge_type = match edge_attr_from_schema(schema_id, &self.schemas) {
+            Ok((_, edge_attr)) => edge_attr.edge_type, Err(e) => return Ok(Err(e))
+        };
+        let (in_list, out_list) = match edge_type {
+            edge::EdgeType::Directed => (EdgeDirection::Inbound, EdgeDirection::Outbound),
+            edge::EdgeType::Undirected => (EdgeDirection::Undirected, EdgeDirection::Undirected)
+        };
+        let in_degree = match self.degree(*opposite_id, schema_id, in_list)? {
+            Ok(degree) => degree, Err(e) => return Ok(Err(e))
+        };
+        let out_degree = if out_list == in_list { in_degree } else {
+            match self.degree(*opposite_id, schema_id, out_list)? {
+                Ok(degree) => degree, Err(e) => return Ok(Err(e))
+            }
+        };
+        Ok(Ok(FilterContext { in_degree: Some(in_degree), out_degree: Some(out_degree), ..context }))
+    }
+
+    // neighbour_edges with the filter context of every edge
+    fn neighbour_edges_in_context(&self, vertex_id: &Id, schema_id: u32, ed: EdgeDirection, filter: &Option<Vec<SExpr>>)
+        -> Result<Result<Vec<(Id, edge::Edge, FilterContext)>, NeighbourhoodError>, TxnError>
+    {
+        let edges = match self.neighbour_edges(vertex_id, schema_id, ed)? {
+            Ok(edges) => edges, Err(e) => return Ok(Err(e))
+        };
+        let mut result = Vec::with_capacity(edges.len());
+        let degrees = query::refers_to_degrees(filter);
+        for (opposite_id, edge) in edges {
+            let context = match self.filter_context(&opposite_id, &edge, filter, degrees)? {
+                Ok(context) => context, Err(e) => return Ok(Err(NeighbourhoodError::EdgeError(e)))
+            };
+            result.push((opposite_id, edge, context));
+        }
+        Ok(Ok(result))
+    }
+
+    // Every distinct opposite vertex once. Neb's transaction reads take a round trip each, so the
+    // vertices are fetched all at once through the client pool, as of their last commit. Those the
+    // pool has no committed cell for, created by this transaction or gone, are read through it.
+    fn opposite_vertices(&self, edges: &[(Id, edge::Edge, FilterContext)])
+        -> Result<Result<HashMap<Id, Vertex>, NeighbourhoodError>, TxnError>
+    {
+        let mut ids: Vec<Id> = edges.iter().map(|&(id, _, _)| id).collect();
+        ids.sort_by_key(|id| (id.higher, id.lower));
+        ids.dedup();
+        let fetches: Vec<_> = ids.iter().map(|&id| self.neb_client.read_cell(id).then(|result| {
+            Ok::<_, TxnError>(match result { Ok(Ok(cell)) => Some(cell), _ => None })
+        })).collect();
+        let cells = future::join_all(fetches).wait()?;
+        let mut vertices = HashMap::with_capacity(ids.len());
+        for (id, cell) in ids.into_iter().zip(cells.into_iter()) {
+            let cell = match cell {
+                Some(cell) => cell,
+                None => match self.neb_txn.read(&id)? {
+                    Some(cell) => cell, None => return Ok(Err(NeighbourhoodError::VertexNotFound(id)))
+                }
+            };
+            let vertex = self.with_virtual_fields(encryption::open_vertex(&self.schemas, vertex::cell_to_vertex(cell)))?;
+            vertices.insert(id, vertex);
         }
+        Ok(Ok(vertices))
     }
 
     pub fn neighbourhoods<V, S>(
@@ -533,36 +2591,25 @@ impl <'a>GraphTransaction<'a> {
         -> Result<Result<Vec<(Vertex, edge::Edge)>, NeighbourhoodError>, TxnError>
         where V: ToVertexId, S: ToSchemaId
     {
-        let vertex_field = ed.as_field();
         let schema_id = schema.to_id(&self.schemas);
         let vertex_id = &vertex.to_id();
-        match id_list::IdList::from_txn_and_container
-            (self.neb_txn, vertex_id, vertex_field, schema_id).iter()? {
-            Err(e) => Ok(Err(NeighbourhoodError::EdgeError(EdgeError::IdListError(e)))),
-            Ok(ids) => {
-                let mut result: Vec<(Vertex, edge::Edge)> = Vec::new();
-                for id in ids {
-                    match edge::from_id(
-                        vertex_id, vertex_field, schema_id, &self.schemas, self.neb_txn, &id
-                    )? {
-                        Ok(edge) => {
-                            let vertex = if let Some(opposite_id) = edge.one_opposite_id_vertex_id(vertex_id) {
-                                if let Some(v) = self.read_vertex(opposite_id)? { v } else {
-                                    return Ok(Err(NeighbourhoodError::VertexNotFound(*opposite_id)))
-                                }
-                            } else { return Ok(Err(NeighbourhoodError::CannotFindOppositeId(*vertex_id))) };
-                            match Tester::eval_with_edge_and_vertex(filter, &vertex, &edge) {
-                                Ok(true) => {result.push((vertex, edge));},
-                                Ok(false) => {},
-                                Err(err) => return Ok(Err(NeighbourhoodError::FilterEvalError(err))),
-                            }
-                        },
-                        Err(edge_error) => return Ok(Err(NeighbourhoodError::EdgeError(edge_error)))
-                    }
-                }
-                return Ok(Ok(result));
+        self.watch.touch("neighbourhoods", Some(schema_id), *vertex_id)?;
+        let edges = match self.neighbour_edges_in_context(vertex_id, schema_id, ed, filter)? {
+            Ok(edges) => edges, Err(e) => return Ok(Err(e))
+        };
+        let vertices = match self.opposite_vertices(&edges)? {
+            Ok(vertices) => vertices, Err(e) => return Ok(Err(e))
+        };
+        let mut result: Vec<(Vertex, edge::Edge)> = Vec::with_capacity(edges.len());
+        for (opposite_id, edge, context) in edges {
+            let vertex = vertices[&opposite_id].clone();
+            match Tester::eval_in_context(filter, Some(&vertex), &edge, &context) {
+                Ok(true) => {result.push((vertex, edge));},
+                Ok(false) => {},
+                Err(err) => return Ok(Err(NeighbourhoodError::FilterEvalError(err))),
             }
         }
+        return Ok(Ok(result));
     }
 
     pub fn degree<V, S>(&self, vertex: V, schema: S, ed: EdgeDirection)
@@ -572,13 +2619,18 @@ impl <'a>GraphTransaction<'a> {
         let (schema_id, edge_attr) = match edge_attr_from_schema(schema, &self.schemas) {
             Err(e) => return Ok(Err(e)), Ok(t) => t
         };
-        let vertex_field = ed.as_field();
         let vertex_id = &vertex.to_id();
-        match id_list::IdList::from_txn_and_container
-            (self.neb_txn, vertex_id, vertex_field, schema_id).count()? {
-            Err(e) => Ok(Err(edge::EdgeError::IdListError(e))),
-            Ok(count) => Ok(Ok(count))
+        self.watch.touch("degree", Some(schema_id), *vertex_id)?;
+        // with Both a directed self-loop counts twice, once per list
+        let mut degree = 0;
+        for (_, vertex_field) in ed.for_schema(schema_id, &self.schemas).expand() {
+            match id_list::IdList::from_txn_and_container
+                (self.neb_txn, vertex_id, vertex_field, schema_id).count()? {
+                Err(e) => return Ok(Err(edge::EdgeError::IdListError(e))),
+                Ok(count) => degree += count
+            }
         }
+        Ok(Ok(degree))
     }
 }
 
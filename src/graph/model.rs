@@ -0,0 +1,141 @@
+// Typed models of vertices and edge bodies. #[derive(MorpheusVertex)] and #[derive(MorpheusEdge)] from
+// the morpheus_derive crate implement the traits below for structs with named fields. The schema is
+// named after the struct, or #[morpheus(name = "...")], with a field per struct field typed by its
+// ModelField, Option fields are nullable and Vec fields arrays. #[morpheus(key)] on a vertex field
+// makes it the key. Edge models describe the body and take #[morpheus(directed)] or
+// #[morpheus(undirected)]. Reading a model checks every field, a missing or mistyped one fails by name.
+
+pub use neb::dovahkiin::types::{Map, Value};
+pub use neb::ram::schema::Field;
+use neb::ram::types::{TypeId, Id, key_hash};
+
+use graph::vertex::Vertex;
+use graph::edge::{Edge, EdgeType, EdgeAttributes};
+use server::schema::MorpheusSchema;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModelError {
+    DataNotMap,
+    // edges of schemas without a body have nothing to read
+    NoBody,
+    Missing(String),
+    WrongType(String)
+}
+
+pub trait ModelField: Sized {
+    fn type_id() -> TypeId;
+    fn nullable() -> bool { false }
+    fn is_array() -> bool { false }
+    fn to_value(&self) -> Value;
+    fn from_value(value: &Value) -> Option<Self>;
+}
+
+macro_rules! model_field {
+    ($t:ty, $variant:ident) => {
+        impl ModelField for $t {
+            fn type_id() -> TypeId { TypeId::$variant }
+            fn to_value(&self) -> Value { Value::$variant(self.clone()) }
+            fn from_value(value: &Value) -> Option<$t> {
+                match value { &Value::$variant(ref v) => Some(v.clone()), _ => None }
+            }
+        }
+    };
+}
+
+model_field!(bool, Bool);
+model_field!(u8, U8);
+model_field!(u16, U16);
+model_field!(u32, U32);
+model_field!(u64, U64);
+model_field!(i8, I8);
+model_field!(i16, I16);
+model_field!(i32, I32);
+model_field!(i64, I64);
+model_field!(f32, F32);
+model_field!(f64, F64);
+model_field!(String, String);
+model_field!(Id, Id);
+
+impl <T> ModelField for Option<T> where T: ModelField {
+    fn type_id() -> TypeId { T::type_id() }
+    fn nullable() -> bool { true }
+    fn is_array() -> bool { T::is_array() }
+    fn to_value(&self) -> Value {
+        match self { &Some(ref v) => v.to_value(), &None => Value::Null }
+    }
+    fn from_value(value: &Value) -> Option<Option<T>> {
+        match value { &Value::Null => Some(None), value => T::from_value(value).map(Some) }
+    }
+}
+
+impl <T> ModelField for Vec<T> where T: ModelField {
+    fn type_id() -> TypeId { T::type_id() }
+    fn is_array() -> bool { true }
+    fn to_value(&self) -> Value {
+        Value::Array(self.iter().map(|v| v.to_value()).collect())
+    }
+    fn from_value(value: &Value) -> Option<Vec<T>> {
+        match value {
+            &Value::Array(ref items) => items.iter().map(T::from_value).collect(),
+            _ => None
+        }
+    }
+}
+
+// the schema field of a model field, used by the derived code
+pub fn model_field<T: ModelField>(name: &str) -> Field {
+    Field::new(name, T::type_id() as u32, T::nullable(), T::is_array(), None)
+}
+
+pub fn write_field<T: ModelField>(map: &mut Map, name: &str, value: &T) {
+    map.insert(&name.to_string(), value.to_value());
+}
+
+pub fn read_field<T: ModelField>(map: &Map, name: &str) -> Result<T, ModelError> {
+    let value = map.get_by_key_id(key_hash(&name.to_string()));
+    T::from_value(value).ok_or_else(|| match value {
+        &Value::Null => ModelError::Missing(name.to_string()),
+        _ => ModelError::WrongType(name.to_string())
+    })
+}
+
+fn data_map(data: &Value) -> Result<&Map, ModelError> {
+    match data { &Value::Map(ref map) => Ok(map), _ => Err(ModelError::DataNotMap) }
+}
+
+pub trait VertexModel: Sized {
+    fn schema_name() -> &'static str;
+    fn key_field() -> Option<&'static str>;
+    fn fields() -> Vec<Field>;
+    fn to_map(&self) -> Map;
+    fn from_map(map: &Map) -> Result<Self, ModelError>;
+
+    fn schema() -> MorpheusSchema {
+        let key_field = Self::key_field().map(|key| vec![key.to_string()]);
+        MorpheusSchema::new(Self::schema_name(), key_field.as_ref(), &Self::fields(), false)
+    }
+    fn from_vertex(vertex: &Vertex) -> Result<Self, ModelError> {
+        Self::from_map(data_map(&vertex.cell.data)?)
+    }
+}
+
+pub trait EdgeModel: Sized {
+    fn schema_name() -> &'static str;
+    fn edge_type() -> EdgeType;
+    fn fields() -> Vec<Field>;
+    fn to_map(&self) -> Map;
+    fn from_map(map: &Map) -> Result<Self, ModelError>;
+
+    fn schema() -> MorpheusSchema {
+        MorpheusSchema::new(Self::schema_name(), None, &Self::fields(), false)
+    }
+    fn attributes() -> EdgeAttributes {
+        EdgeAttributes::new(Self::edge_type(), true)
+    }
+    fn from_edge(edge: &Edge) -> Result<Self, ModelError> {
+        match edge.get_data() {
+            &Some(ref cell) => Self::from_map(data_map(&cell.data)?),
+            &None => Err(ModelError::NoBody)
+        }
+    }
+}
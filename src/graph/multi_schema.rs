@@ -0,0 +1,156 @@
+// Traversals over several edge schemas in one transaction, for "all relationships of any type".
+// The wildcard reads the vertex's type lists, so only schemas the vertex actually has lists for
+// are visited. Results are grouped by schema in the order the schemas were resolved.
+
+use neb::ram::types::Id;
+use neb::dovahkiin::expr::SExpr;
+use neb::client::transaction::TxnError;
+
+use std::collections::HashMap;
+
+use graph::{GraphTransaction, EdgeDirection, NeighbourhoodError, edge_attr_from_schema, id_list};
+use graph::edge::{self, EdgeType, EdgeError};
+use graph::vertex::{Vertex, ToVertexId};
+use query::Tester;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EdgeSchemas {
+    // every edge schema the vertex has a list for in the direction
+    Any,
+    Of(Vec<u32>)
+}
+
+// one edge list present on a vertex
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncidentSchema {
+    pub schema: u32,
+    pub direction: EdgeDirection,
+    pub count: usize
+}
+
+impl <'a> GraphTransaction<'a> {
+    // Pairs every schema with the direction its list is read in. Both also picks up undirected
+    // schemas, which have no inbound or outbound lists, so it covers every edge of the vertex.
+    pub fn resolve_edge_schemas<V>(&self, vertex: V, schemas: &EdgeSchemas, ed: EdgeDirection)
+        -> Result<Result<Vec<(u32, EdgeDirection)>, EdgeError>, TxnError>
+        where V: ToVertexId
+    {
+        let vertex_id = vertex.to_id();
+        let mut resolved: Vec<(u32, EdgeDirection)> = Vec::new();
+        match schemas {
+            &EdgeSchemas::Of(ref schema_ids) => {
+                for &schema_id in schema_ids {
+                    let edge_attr = match edge_attr_from_schema(schema_id, &self.schemas) {
+                        Ok((_, edge_attr)) => edge_attr, Err(e) => return Ok(Err(e))
+                    };
+                    let direction = match (ed, edge_attr.edge_type) {
+                        (EdgeDirection::Both, EdgeType::Undirected) => EdgeDirection::Undirected,
+                        (direction, _) => direction
+                    };
+                    if !resolved.contains(&(schema_id, direction)) {
+                        resolved.push((schema_id, direction));
+                    }
+                }
+            },
+            &EdgeSchemas::Any => {
+                let mut lists = ed.expand();
+                if ed == EdgeDirection::Both {
+                    lists.extend(EdgeDirection::Undirected.expand());
+                }
+                for (part, field) in lists {
+                    let direction = if part == EdgeDirection::Undirected { part } else { ed };
+                    let schema_ids = match id_list::IdList::cell_types(self.neb_txn, &vertex_id, field)? {
+                        Some((_, schema_ids)) => schema_ids, None => continue
+                    };
+                    for schema_id in schema_ids {
+                        if !resolved.contains(&(schema_id, direction)) {
+                            resolved.push((schema_id, direction));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(Ok(resolved))
+    }
+
+    // Every edge list of the vertex with its length, outbound lists first, then inbound and undirected.
+    // Lists emptied by unlinking keep their type list entry and are reported with count 0.
+    pub fn incident_edge_schemas<V>(&self, vertex: V)
+        -> Result<Result<Vec<IncidentSchema>, EdgeError>, TxnError>
+        where V: ToVertexId
+    {
+        let vertex_id = vertex.to_id();
+        self.watch.touch("incident_edge_schemas", None, vertex_id)?;
+        let mut incident = Vec::new();
+        for (direction, field) in EdgeDirection::every_list() {
+            let schema_ids = match id_list::IdList::cell_types(self.neb_txn, &vertex_id, field)? {
+                Some((_, schema_ids)) => schema_ids, None => continue
+            };
+            for schema in schema_ids {
+                let count = match id_list::IdList::from_txn_and_container(self.neb_txn, &vertex_id, field, schema).count()? {
+                    Ok(count) => count, Err(e) => return Ok(Err(EdgeError::IdListError(e)))
+                };
+                incident.push(IncidentSchema { schema, direction, count });
+            }
+        }
+        Ok(Ok(incident))
+    }
+
+    pub fn edges_of_schemas<V>(
+        &self, vertex: V, schemas: &EdgeSchemas, ed: EdgeDirection, filter: &Option<Vec<SExpr>>
+    )
+        -> Result<Result<Vec<edge::Edge>, EdgeError>, TxnError>
+        where V: ToVertexId
+    {
+        let vertex_id = vertex.to_id();
+        self.watch.touch("edges_of_schemas", None, vertex_id)?;
+        let resolved = match self.resolve_edge_schemas(vertex_id, schemas, ed)? {
+            Ok(resolved) => resolved, Err(e) => return Ok(Err(e))
+        };
+        let mut edges = Vec::new();
+        for (schema_id, direction) in resolved {
+            match self.edges(vertex_id, schema_id, direction, filter)? {
+                Ok(schema_edges) => edges.extend(schema_edges), Err(e) => return Ok(Err(e))
+            }
+        }
+        Ok(Ok(edges))
+    }
+
+    pub fn neighbourhoods_of_schemas<V>(
+        &self, vertex: V, schemas: &EdgeSchemas, ed: EdgeDirection, filter: &Option<Vec<SExpr>>
+    )
+        -> Result<Result<Vec<(Vertex, edge::Edge)>, NeighbourhoodError>, TxnError>
+        where V: ToVertexId
+    {
+        let vertex_id = &vertex.to_id();
+        self.watch.touch("neighbourhoods_of_schemas", None, *vertex_id)?;
+        let resolved = match self.resolve_edge_schemas(*vertex_id, schemas, ed)? {
+            Ok(resolved) => resolved, Err(e) => return Ok(Err(NeighbourhoodError::EdgeError(e)))
+        };
+        let mut edges = Vec::new();
+        for (schema_id, direction) in resolved {
+            match self.neighbour_edges_in_context(vertex_id, schema_id, direction, filter)? {
+                Ok(schema_edges) => edges.extend(schema_edges), Err(e) => return Ok(Err(e))
+            }
+        }
+        // a vertex related in several ways is still read only once
+        let mut vertices: HashMap<Id, Vertex> = HashMap::new();
+        for &(opposite_id, _, _) in &edges {
+            if vertices.contains_key(&opposite_id) { continue; }
+            match self.read_vertex(opposite_id)? {
+                Some(v) => { vertices.insert(opposite_id, v); },
+                None => return Ok(Err(NeighbourhoodError::VertexNotFound(opposite_id)))
+            }
+        }
+        let mut result = Vec::with_capacity(edges.len());
+        for (opposite_id, edge, context) in edges {
+            let vertex = vertices[&opposite_id].clone();
+            match Tester::eval_in_context(filter, Some(&vertex), &edge, &context) {
+                Ok(true) => result.push((vertex, edge)),
+                Ok(false) => {},
+                Err(err) => return Ok(Err(NeighbourhoodError::FilterEvalError(err)))
+            }
+        }
+        Ok(Ok(result))
+    }
+}
@@ -0,0 +1,99 @@
+// Set operations over the neighbours of two vertices, for mutual friends and link prediction.
+// Only id lists are walked, plus the two end fields of edge cells for edges with a body,
+// so no vertex bodies are read and only the resulting ids leave the transaction.
+
+use neb::ram::types::{Id, Value};
+use neb::client::transaction::TxnError;
+
+use std::collections::HashSet;
+
+use graph::{GraphTransaction, EdgeDirection, edge_attr_from_schema, id_list};
+use graph::edge::{self, EdgeError};
+use graph::edge::bilateral::BilateralEdge;
+use graph::vertex::ToVertexId;
+use server::schema::ToSchemaId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NeighbourSetOp {
+    // neighbours of both vertices
+    Intersection,
+    // neighbours of either vertex
+    Union,
+    // neighbours of the first vertex that are not neighbours of the second
+    Difference
+}
+
+impl <'a> GraphTransaction<'a> {
+    // Distinct ids across the edges in list order, a self-loop yields the vertex itself
+    pub fn neighbour_ids<V, S>(&self, vertex: V, schema: S, ed: EdgeDirection)
+        -> Result<Result<Vec<Id>, EdgeError>, TxnError>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        let (schema_id, edge_attr) = match edge_attr_from_schema(schema, &self.schemas) {
+            Err(e) => return Ok(Err(e)), Ok(t) => t
+        };
+        let vertex_id = vertex.to_id();
+        self.watch.touch("neighbour_ids", Some(schema_id), vertex_id)?;
+        let mut ids = Vec::new();
+        for (_, vertex_field) in ed.for_schema(schema_id, &self.schemas).expand() {
+            match id_list::IdList::from_txn_and_container(
+                self.neb_txn, &vertex_id, vertex_field, schema_id).all()? {
+                Ok(part_ids) => ids.extend(part_ids), Err(e) => return Ok(Err(EdgeError::IdListError(e)))
+            }
+        }
+        let mut seen = HashSet::with_capacity(ids.len());
+        if !edge_attr.has_body {
+            // simple edges store the opposite vertex id in the list directly
+            return Ok(Ok(ids.into_iter().filter(|id| seen.insert(*id)).collect()));
+        }
+        let end_fields = match edge_attr.edge_type {
+            edge::EdgeType::Directed => vec![
+                edge::directed::DirectedEdge::edge_a_field(),
+                edge::directed::DirectedEdge::edge_b_field()
+            ],
+            edge::EdgeType::Undirected => vec![
+                edge::undirectd::UndirectedEdge::edge_a_field(),
+                edge::undirectd::UndirectedEdge::edge_b_field()
+            ]
+        };
+        let mut neighbours = Vec::with_capacity(ids.len());
+        for edge_id in ids {
+            let ends = match self.neb_txn.read_selected(&edge_id, &end_fields)? {
+                Some(ends) => ends, None => return Ok(Err(EdgeError::CellNotFound))
+            };
+            let opposite = ends.iter()
+                .filter_map(|end| match end { &Value::Id(id) if id != vertex_id => Some(id), _ => None })
+                .next()
+                .unwrap_or(vertex_id);
+            if seen.insert(opposite) {
+                neighbours.push(opposite);
+            }
+        }
+        Ok(Ok(neighbours))
+    }
+
+    // Results keep the list order of the first vertex, union appends the rest of the second
+    pub fn neighbour_set<V, S>(&self, a: V, b: V, schema: S, ed: EdgeDirection, op: NeighbourSetOp)
+        -> Result<Result<Vec<Id>, EdgeError>, TxnError>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        let schema_id = schema.to_id(&self.schemas);
+        let a_ids = match self.neighbour_ids(a, schema_id, ed)? {
+            Ok(ids) => ids, Err(e) => return Ok(Err(e))
+        };
+        let b_ids = match self.neighbour_ids(b, schema_id, ed)? {
+            Ok(ids) => ids, Err(e) => return Ok(Err(e))
+        };
+        let b_set: HashSet<Id> = b_ids.iter().cloned().collect();
+        Ok(Ok(match op {
+            NeighbourSetOp::Intersection => a_ids.into_iter().filter(|id| b_set.contains(id)).collect(),
+            NeighbourSetOp::Difference => a_ids.into_iter().filter(|id| !b_set.contains(id)).collect(),
+            NeighbourSetOp::Union => {
+                let a_set: HashSet<Id> = a_ids.iter().cloned().collect();
+                let mut union = a_ids;
+                union.extend(b_ids.into_iter().filter(|id| !a_set.contains(id)));
+                union
+            }
+        }))
+    }
+}
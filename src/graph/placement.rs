@@ -0,0 +1,68 @@
+// Cells land on neb servers by the higher part of their id, so vertices sharing it are stored together.
+// Placement only rewrites that part, keyed schemas keep their key-derived ids or key lookups would break.
+
+use neb::ram::types::Id;
+use neb::ram::cell::Cell;
+use neb::ram::schema::Schema;
+use neb::dovahkiin::types::Value;
+use bifrost_hasher::hash_str;
+
+// per vertex hint given to new_vertex
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Placement {
+    // follow the default policy of the schema
+    Auto,
+    ColocateWith(Id),
+    PartitionKey(String),
+    // takes exactly this id, for copies of vertices from another cluster
+    Exact(Id)
+}
+
+// schema wide default, applied when new_vertex gets Placement::Auto
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum PlacementPolicy {
+    Random,
+    // the field holds the id of a vertex to colocate with
+    ColocateWithField(String),
+    // vertices with the same string in this field share a server
+    PartitionByField(String)
+}
+
+impl Default for PlacementPolicy {
+    fn default() -> PlacementPolicy {
+        PlacementPolicy::Random
+    }
+}
+
+fn partition_of_key<'a>(key: &'a str) -> u64 {
+    hash_str(key)
+}
+
+fn partition_from_policy(policy: &PlacementPolicy, data: &Value) -> Option<u64> {
+    match policy {
+        &PlacementPolicy::Random => None,
+        &PlacementPolicy::ColocateWithField(ref field) => match data[field.as_str()] {
+            Value::Id(ref id) if id != &Id::unit_id() => Some(id.higher),
+            _ => None
+        },
+        &PlacementPolicy::PartitionByField(ref field) =>
+            data[field.as_str()].String().map(|key| partition_of_key(key))
+    }
+}
+
+pub fn apply(cell: &mut Cell, neb_schema: &Schema, placement: &Placement, policy: &PlacementPolicy) {
+    if neb_schema.str_key_field.is_some() { return; }
+    let partition = match placement {
+        &Placement::Exact(ref id) => {
+            cell.header.partition = id.higher;
+            cell.header.hash = id.lower;
+            return;
+        },
+        &Placement::ColocateWith(ref id) => Some(id.higher),
+        &Placement::PartitionKey(ref key) => Some(partition_of_key(key)),
+        &Placement::Auto => partition_from_policy(policy, &cell.data)
+    };
+    if let Some(partition) = partition {
+        cell.header.partition = partition;
+    }
+}
@@ -0,0 +1,66 @@
+// Warms the caches of this server ahead of an expected burst of neighbourhood queries, a timeline
+// fan-in about to read the followers of the same accounts, say. The vertices are read into the
+// vertex cache and their unfiltered neighbourhoods of every schema are read like neighbourhoods
+// does, which walks the edge lists, puts the opposite vertices in the vertex cache and the result
+// in the query cache. Queries with a filter get their own entries, only their vertex reads profit.
+// With neither cache enabled there is nowhere to keep anything and nothing is read. Failures, of
+// one neighbourhood or of a vertex read, are reported and the rest is still fetched.
+
+use neb::ram::types::Id;
+use futures::prelude::*;
+use futures::future;
+
+use std::sync::Arc;
+
+use graph::{GraphInner, EdgeDirection};
+
+#[derive(Debug, Default)]
+pub struct PrefetchReport {
+    // the vertices themselves found and cached
+    pub vertices: usize,
+    // neighbourhoods read, one per vertex and schema
+    pub neighbourhoods: usize,
+    // opposite vertices read with them
+    pub neighbours: usize,
+    // the vertex, the schema where a neighbourhood failed, and why
+    pub failed: Vec<(Id, Option<u32>, String)>
+}
+
+impl GraphInner {
+    pub fn prefetch(this: Arc<Self>, vertices: Vec<Id>, schemas: Vec<u32>, direction: EdgeDirection)
+        -> impl Future<Item = PrefetchReport, Error = ()>
+    {
+        if this.query_cache.read().is_none() && this.vertex_cache.read().is_none() {
+            return future::Either::A(future::ok(PrefetchReport::default()));
+        }
+        let reads: Vec<_> = vertices.iter().map(|&id| {
+            GraphInner::vertex_by(this.clone(), id).then(move |result| Ok::<_, ()>((id, result)))
+        }).collect();
+        let expansions: Vec<_> = vertices.iter().flat_map(|&id| schemas.iter().map(move |&schema| (id, schema)))
+            .map(|(id, schema)| {
+                GraphInner::neighbourhoods(this.clone(), id, schema, direction, &None::<String>)
+                    .then(move |result| Ok::<_, ()>((id, schema, result)))
+            }).collect();
+        future::Either::B(future::join_all(reads).join(future::join_all(expansions)).map(|(reads, expansions)| {
+            let mut report = PrefetchReport::default();
+            for (id, result) in reads {
+                match result {
+                    Ok(Some(_)) => report.vertices += 1,
+                    Ok(None) => {},
+                    Err(e) => report.failed.push((id, None, format!("{:?}", e)))
+                }
+            }
+            for (id, schema, result) in expansions {
+                match result {
+                    Ok(Ok(neighbours)) => {
+                        report.neighbourhoods += 1;
+                        report.neighbours += neighbours.len();
+                    },
+                    Ok(Err(e)) => report.failed.push((id, Some(schema), format!("{:?}", e))),
+                    Err(e) => report.failed.push((id, Some(schema), format!("{:?}", e)))
+                }
+            }
+            report
+        }))
+    }
+}
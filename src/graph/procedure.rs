@@ -0,0 +1,293 @@
+// Stored procedures. A procedure is deployed under a name on this server and call_procedure runs it
+// in one graph transaction, so a composite write like creating a user and linking it to its
+// organization costs one round trip and commits or fails as a whole.
+// Scripts are lists of steps whose arguments are lisp expressions. Expressions see the parameters
+// by name and every value bound by an earlier step, a new vertex binds its id and a read binds the
+// vertex data or null. Native procedures are Rust functions given the transaction, for plugins.
+// A step or native procedure that fails rolls back what the procedure wrote through a savepoint,
+// so its error is returned and the transaction still commits the writes made before the call.
+
+use neb::ram::types::{Id, key_hash};
+use neb::dovahkiin::types::{Map, Value};
+use neb::dovahkiin::expr::SExpr;
+use neb::dovahkiin::expr::symbols::utils::is_true;
+use neb::client::transaction::TxnError;
+use bifrost_hasher::hash_str;
+use parking_lot::RwLock;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use graph::{GraphTransaction, UpdateOptions};
+use graph::savepoint::SavepointError;
+use query::{Expr, Tester};
+
+pub type NativeProcedure = Fn(&GraphTransaction, Vec<Value>) -> Result<Result<Value, String>, TxnError> + Send + Sync;
+
+// a field name and the expression of its value
+pub type FieldExprs = Vec<(String, String)>;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Step {
+    Let { name: String, expr: String },
+    // fails the procedure with the message unless the expression is true
+    Check { expr: String, message: String },
+    ReadVertex { name: String, id: String },
+    NewVertex { name: Option<String>, schema: String, fields: FieldExprs },
+    // sets the fields given and keeps the rest
+    UpdateVertex { id: String, fields: FieldExprs },
+    RemoveVertex { id: String },
+    Link { from: String, schema: String, to: String, body: Option<FieldExprs> },
+    Unlink { from: String, schema: String, to: String }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Script {
+    pub params: Vec<String>,
+    pub steps: Vec<Step>,
+    // the value the procedure returns, null without one
+    #[serde(default)]
+    pub result: Option<String>
+}
+
+#[derive(Clone)]
+pub enum Procedure {
+    Script(Script),
+    Native(Arc<NativeProcedure>)
+}
+
+impl Procedure {
+    pub fn native<F>(func: F) -> Procedure
+        where F: Fn(&GraphTransaction, Vec<Value>) -> Result<Result<Value, String>, TxnError> + Send + Sync + 'static
+    {
+        Procedure::Native(Arc::new(func))
+    }
+}
+
+#[derive(Debug)]
+pub enum ProcedureError {
+    NotFound(String),
+    WrongArity { expected: usize, given: usize },
+    // position of the step, or of the result after the last one, and the parser message
+    CannotParse(usize, String),
+    StepFailed(usize, String),
+    CheckFailed(String),
+    // returned by a native procedure
+    Failed(String),
+    SavepointError(SavepointError),
+    TxnError(TxnError)
+}
+
+// steps with their expressions parsed at deployment
+enum CompiledStep {
+    Let(u64, Vec<SExpr>),
+    Check(Vec<SExpr>, String),
+    ReadVertex(u64, Vec<SExpr>),
+    NewVertex(Option<u64>, String, Vec<(String, Vec<SExpr>)>),
+    UpdateVertex(Vec<SExpr>, Vec<(String, Vec<SExpr>)>),
+    RemoveVertex(Vec<SExpr>),
+    Link(Vec<SExpr>, String, Vec<SExpr>, Option<Vec<(String, Vec<SExpr>)>>),
+    Unlink(Vec<SExpr>, String, Vec<SExpr>)
+}
+
+struct CompiledScript {
+    params: Vec<u64>,
+    steps: Vec<CompiledStep>,
+    result: Option<Vec<SExpr>>
+}
+
+pub struct Deployed {
+    name: String,
+    body: DeployedBody
+}
+
+enum DeployedBody {
+    Script(CompiledScript),
+    Native(Arc<NativeProcedure>)
+}
+
+fn symbol(name: &str) -> u64 {
+    hash_str(&name.to_string())
+}
+
+fn compile_fields(fields: &FieldExprs) -> Result<Vec<(String, Vec<SExpr>)>, String> {
+    fields.iter().map(|&(ref name, ref expr)| Ok((name.clone(), expr.as_str().to_sexpr()?))).collect()
+}
+
+fn compile_step(step: &Step) -> Result<CompiledStep, String> {
+    let parse = |expr: &String| expr.as_str().to_sexpr();
+    Ok(match step {
+        &Step::Let { ref name, ref expr } => CompiledStep::Let(symbol(name), parse(expr)?),
+        &Step::Check { ref expr, ref message } => CompiledStep::Check(parse(expr)?, message.clone()),
+        &Step::ReadVertex { ref name, ref id } => CompiledStep::ReadVertex(symbol(name), parse(id)?),
+        &Step::NewVertex { ref name, ref schema, ref fields } =>
+            CompiledStep::NewVertex(name.as_ref().map(|n| symbol(n)), schema.clone(), compile_fields(fields)?),
+        &Step::UpdateVertex { ref id, ref fields } => CompiledStep::UpdateVertex(parse(id)?, compile_fields(fields)?),
+        &Step::RemoveVertex { ref id } => CompiledStep::RemoveVertex(parse(id)?),
+        &Step::Link { ref from, ref schema, ref to, ref body } => CompiledStep::Link(
+            parse(from)?, schema.clone(), parse(to)?,
+            match body { &Some(ref body) => Some(compile_fields(body)?), &None => None }
+        ),
+        &Step::Unlink { ref from, ref schema, ref to } => CompiledStep::Unlink(parse(from)?, schema.clone(), parse(to)?)
+    })
+}
+
+fn compile(script: &Script) -> Result<CompiledScript, ProcedureError> {
+    let mut steps = Vec::with_capacity(script.steps.len());
+    for (pos, step) in script.steps.iter().enumerate() {
+        steps.push(compile_step(step).map_err(|e| ProcedureError::CannotParse(pos, e))?);
+    }
+    let result = match script.result {
+        Some(ref expr) => Some(expr.as_str().to_sexpr().map_err(|e| ProcedureError::CannotParse(steps.len(), e))?),
+        None => None
+    };
+    Ok(CompiledScript { params: script.params.iter().map(|p| symbol(p)).collect(), steps, result })
+}
+
+#[derive(Default)]
+pub struct Procedures {
+    procedures: RwLock<HashMap<String, Arc<Deployed>>>
+}
+
+impl Procedures {
+    // scripts are parsed here, a procedure under the same name is replaced
+    pub fn deploy(&self, name: &str, procedure: Procedure) -> Result<(), ProcedureError> {
+        let body = match procedure {
+            Procedure::Script(ref script) => DeployedBody::Script(compile(script)?),
+            Procedure::Native(func) => DeployedBody::Native(func)
+        };
+        self.procedures.write().insert(name.to_string(), Arc::new(Deployed { name: name.to_string(), body }));
+        Ok(())
+    }
+    pub fn get(&self, name: &str) -> Option<Arc<Deployed>> {
+        self.procedures.read().get(name).cloned()
+    }
+    pub fn remove(&self, name: &str) -> bool {
+        self.procedures.write().remove(name).is_some()
+    }
+    // in name order
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.procedures.read().keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
+
+fn to_id(value: Value) -> Result<Id, String> {
+    match value {
+        Value::Id(id) => Ok(id),
+        other => Err(format!("expected a vertex id, got {:?}", other))
+    }
+}
+
+fn eval_fields(fields: &Vec<(String, Vec<SExpr>)>, scope: &[(u64, Value)]) -> Result<Map, String> {
+    let mut map = Map::new();
+    for &(ref name, ref expr) in fields {
+        map.insert(name, Tester::eval_bound(expr, scope)?);
+    }
+    Ok(map)
+}
+
+impl <'a> GraphTransaction<'a> {
+    pub fn call_procedure(&self, procedure: &Deployed, args: Vec<Value>)
+        -> Result<Result<Value, ProcedureError>, TxnError>
+    {
+        let savepoint = self.savepoint();
+        let result = match procedure.body {
+            DeployedBody::Script(ref script) => self.run_script(script, args)?,
+            DeployedBody::Native(ref func) => func(self, args)?.map_err(ProcedureError::Failed)
+        };
+        if let Err(ref e) = result {
+            debug!("Procedure {} failed, rolling back: {:?}", procedure.name, e);
+            if let Err(e) = self.rollback_to(&savepoint)? {
+                return Ok(Err(ProcedureError::SavepointError(e)));
+            }
+        }
+        Ok(result)
+    }
+
+    fn run_script(&self, script: &CompiledScript, args: Vec<Value>) -> Result<Result<Value, ProcedureError>, TxnError> {
+        if args.len() != script.params.len() {
+            return Ok(Err(ProcedureError::WrongArity { expected: script.params.len(), given: args.len() }));
+        }
+        let mut scope: Vec<(u64, Value)> = script.params.iter().cloned().zip(args.into_iter()).collect();
+        for (pos, step) in script.steps.iter().enumerate() {
+            if let Err(e) = self.run_step(pos, step, &mut scope)? { return Ok(Err(e)); }
+        }
+        Ok(match script.result {
+            Some(ref expr) => Tester::eval_bound(expr, &scope)
+                .map_err(|e| ProcedureError::StepFailed(script.steps.len(), e)),
+            None => Ok(Value::Null)
+        })
+    }
+
+    fn run_step(&self, pos: usize, step: &CompiledStep, scope: &mut Vec<(u64, Value)>)
+        -> Result<Result<(), ProcedureError>, TxnError>
+    {
+        macro_rules! fail {
+            ($e: expr) => { return Ok(Err(ProcedureError::StepFailed(pos, $e))) };
+        }
+        macro_rules! eval {
+            ($expr: expr) => { match Tester::eval_bound($expr, scope) { Ok(v) => v, Err(e) => fail!(e) } };
+        }
+        macro_rules! eval_id {
+            ($expr: expr) => { match to_id(eval!($expr)) { Ok(id) => id, Err(e) => fail!(e) } };
+        }
+        macro_rules! eval_fields {
+            ($fields: expr) => { match eval_fields($fields, scope) { Ok(map) => map, Err(e) => fail!(e) } };
+        }
+        let bound = match step {
+            &CompiledStep::Let(name, ref expr) => Some((name, eval!(expr))),
+            &CompiledStep::Check(ref expr, ref message) => {
+                if !is_true(SExpr::Value(eval!(expr))) { return Ok(Err(ProcedureError::CheckFailed(message.clone()))); }
+                None
+            },
+            &CompiledStep::ReadVertex(name, ref id) => {
+                let id = eval_id!(id);
+                Some((name, self.read_vertex(id)?.map(|v| v.cell.data).unwrap_or(Value::Null)))
+            },
+            &CompiledStep::NewVertex(name, ref schema, ref fields) => {
+                let data = eval_fields!(fields);
+                match self.new_vertex(schema.as_str(), data)? {
+                    Ok(vertex) => name.map(|name| (name, Value::Id(vertex.cell.id()))),
+                    Err(e) => fail!(format!("{:?}", e))
+                }
+            },
+            &CompiledStep::UpdateVertex(ref id, ref fields) => {
+                let (id, data) = (eval_id!(id), eval_fields!(fields));
+                let updated = self.update_vertex_with(id, UpdateOptions::default(), |mut vertex| {
+                    if let Value::Map(ref mut map) = vertex.cell.data {
+                        for name in &data.fields {
+                            map.insert(name, data.get_by_key_id(key_hash(name)).clone());
+                        }
+                    }
+                    Some(vertex)
+                })?;
+                if let Err(e) = updated { fail!(format!("{:?}", e)); }
+                None
+            },
+            &CompiledStep::RemoveVertex(ref id) => {
+                let id = eval_id!(id);
+                if let Err(e) = self.remove_vertex(id)? { fail!(format!("{:?}", e)); }
+                None
+            },
+            &CompiledStep::Link(ref from, ref schema, ref to, ref body) => {
+                let (from, to) = (eval_id!(from), eval_id!(to));
+                let body = match body { &Some(ref body) => Some(eval_fields!(body)), &None => None };
+                if let Err(e) = self.link(from, schema.as_str(), to, body)? { fail!(format!("{:?}", e)); }
+                None
+            },
+            &CompiledStep::Unlink(ref from, ref schema, ref to) => {
+                let (from, to) = (eval_id!(from), eval_id!(to));
+                if let Err(e) = self.unlink(from, schema.as_str(), to)? { fail!(format!("{:?}", e)); }
+                None
+            }
+        };
+        // rebinding a name shadows the earlier value
+        if let Some((name, value)) = bound {
+            scope.retain(|&(n, _)| n != name);
+            scope.push((name, value));
+        }
+        Ok(Ok(()))
+    }
+}
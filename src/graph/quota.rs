@@ -0,0 +1,263 @@
+// Storage quotas of a graph, for multi-tenant deployments where each tenant gets a named graph.
+// Limits cap the vertices and the approximate bytes of vertex data, per schema and for the whole
+// graph. Usage is kept in counter cells written in the transaction that writes the vertex, only
+// for schemas under a limit of their own or of the graph, every write of such schemas contends on
+// their counter. New vertices over a limit are refused. Updates and removals are counted but never
+// refused, a vertex grown past the byte limit by updates blocks the next new vertex instead.
+// Counters start at zero when a quota is first set, recount_quota_usage counts what is stored.
+// Sizes are estimated from the values, close to but not exactly what neb stores.
+
+use neb::ram::schema::Field;
+use neb::ram::types::{TypeId, Id, Map, Value, key_hash};
+use neb::ram::cell::Cell;
+use neb::client::transaction::TxnError;
+use futures::prelude::*;
+
+use std::sync::Arc;
+
+use graph::{GraphInner, GraphTransaction, ScanVerticesError};
+use server::schema::SchemaContainer;
+
+pub static QUOTA_USAGE_SCHEMA_ID: u32 = 190;
+pub const VERTICES_KEY: &'static str = "vertices";
+pub const BYTES_KEY: &'static str = "bytes";
+
+lazy_static! {
+    pub static ref VERTICES_KEY_ID: u64 = key_hash(&String::from(VERTICES_KEY));
+    pub static ref BYTES_KEY_ID: u64 = key_hash(&String::from(BYTES_KEY));
+    pub static ref QUOTA_USAGE: Field = Field::new("*", TypeId::Map as u32, false, false, Some(vec![
+        Field::new(&String::from(VERTICES_KEY), TypeId::U64 as u32, false, false, None),
+        Field::new(&String::from(BYTES_KEY), TypeId::U64 as u32, false, false, None)
+    ]));
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct QuotaLimits {
+    #[serde(default)]
+    pub max_vertices: Option<u64>,
+    #[serde(default)]
+    pub max_bytes: Option<u64>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct QuotaOptions {
+    // for all vertex schemas of the graph together
+    #[serde(default)]
+    pub graph: QuotaLimits,
+    // by schema name
+    #[serde(default)]
+    pub schemas: Vec<(String, QuotaLimits)>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct QuotaUsage {
+    pub vertices: u64,
+    pub bytes: u64
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuotaKind {
+    Vertices,
+    Bytes
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuotaExceeded {
+    // None for the limit of the whole graph
+    pub schema: Option<u32>,
+    pub kind: QuotaKind,
+    pub limit: u64,
+    pub usage: QuotaUsage
+}
+
+#[derive(Debug)]
+pub enum RecountError {
+    ScanError(ScanVerticesError),
+    TxnError(TxnError)
+}
+
+#[derive(Debug, Clone)]
+pub struct QuotaReport {
+    // None for the whole graph
+    pub schema: Option<String>,
+    pub limits: QuotaLimits,
+    pub usage: QuotaUsage
+}
+
+impl QuotaLimits {
+    pub fn is_empty(&self) -> bool {
+        self.max_vertices.is_none() && self.max_bytes.is_none()
+    }
+
+    fn exceeded_by(&self, usage: &QuotaUsage, schema: Option<u32>) -> Option<QuotaExceeded> {
+        let over = |limit: Option<u64>, used: u64| limit.map_or(false, |limit| used > limit);
+        let kind = if over(self.max_vertices, usage.vertices) {
+            QuotaKind::Vertices
+        } else if over(self.max_bytes, usage.bytes) {
+            QuotaKind::Bytes
+        } else {
+            return None;
+        };
+        let limit = match kind { QuotaKind::Vertices => self.max_vertices, QuotaKind::Bytes => self.max_bytes };
+        Some(QuotaExceeded { schema, kind, limit: limit.unwrap_or(0), usage: *usage })
+    }
+}
+
+impl QuotaOptions {
+    pub fn with_graph_limits(mut self, limits: QuotaLimits) -> QuotaOptions {
+        self.graph = limits;
+        self
+    }
+    pub fn with_schema_limits(mut self, schema: &str, limits: QuotaLimits) -> QuotaOptions {
+        self.schemas.retain(|&(ref name, _)| name != schema);
+        self.schemas.push((schema.to_string(), limits));
+        self
+    }
+
+    pub fn schema_limits(&self, schemas: &SchemaContainer, schema_id: u32) -> Option<&QuotaLimits> {
+        self.schemas.iter()
+            .find(|&&(ref name, ref limits)| !limits.is_empty() && schemas.id_from_name(name) == Some(schema_id))
+            .map(|&(_, ref limits)| limits)
+    }
+
+    // whether writes of the schema are counted
+    pub fn covers(&self, schemas: &SchemaContainer, schema_id: u32) -> bool {
+        !self.graph.is_empty() || self.schema_limits(schemas, schema_id).is_some()
+    }
+}
+
+// the approximate stored size of a value
+pub fn value_size(value: &Value) -> u64 {
+    match value {
+        &Value::Null => 0,
+        &Value::Bool(_) | &Value::U8(_) | &Value::I8(_) => 1,
+        &Value::U16(_) | &Value::I16(_) => 2,
+        &Value::U32(_) | &Value::I32(_) | &Value::F32(_) => 4,
+        &Value::U64(_) | &Value::I64(_) | &Value::F64(_) => 8,
+        &Value::Id(_) => 16,
+        &Value::String(ref s) => 4 + s.len() as u64,
+        &Value::Array(ref items) => 4 + items.iter().map(value_size).sum::<u64>(),
+        &Value::Map(ref map) => map.fields.iter().map(|name| value_size(map.get_by_key_id(key_hash(name)))).sum(),
+        _ => 8
+    }
+}
+
+pub fn cell_usage(cell: &Cell) -> QuotaUsage {
+    QuotaUsage { vertices: 1, bytes: value_size(&cell.data) }
+}
+
+pub fn usage_id(schema: Option<u32>, namespace: Option<&String>) -> Id {
+    match schema {
+        Some(schema_id) => Id::new(key_hash(&String::from("QUOTA_USAGE")), schema_id as u64),
+        None => Id::new(key_hash(&String::from("QUOTA_USAGE_GRAPH")), key_hash(&namespace.cloned().unwrap_or_default()))
+    }
+}
+
+fn apply(usage: QuotaUsage, added: &QuotaUsage, removed: &QuotaUsage) -> QuotaUsage {
+    QuotaUsage {
+        vertices: (usage.vertices + added.vertices).saturating_sub(removed.vertices),
+        bytes: (usage.bytes + added.bytes).saturating_sub(removed.bytes)
+    }
+}
+
+impl <'a> GraphTransaction<'a> {
+    pub fn read_quota_usage(&self, id: &Id) -> Result<QuotaUsage, TxnError> {
+        Ok(match self.neb_txn.read_selected(id, &vec![*VERTICES_KEY_ID, *BYTES_KEY_ID])? {
+            Some(values) => match (values.get(0), values.get(1)) {
+                (Some(&Value::U64(vertices)), Some(&Value::U64(bytes))) => QuotaUsage { vertices, bytes },
+                _ => QuotaUsage::default()
+            },
+            None => QuotaUsage::default()
+        })
+    }
+
+    pub fn write_quota_usage(&self, id: &Id, usage: &QuotaUsage) -> Result<(), TxnError> {
+        let mut data = Map::new();
+        data.insert_key_id(*VERTICES_KEY_ID, Value::U64(usage.vertices));
+        data.insert_key_id(*BYTES_KEY_ID, Value::U64(usage.bytes));
+        self.neb_txn.write(&Cell::new_with_id(QUOTA_USAGE_SCHEMA_ID, id, Value::Map(data)))
+    }
+
+    // Counts a vertex written from before to after against the quotas of its schema and the graph.
+    // With enforce, nothing is counted and the limit is returned when the write would go over one.
+    pub(super) fn account_quota(&self, before: Option<&Cell>, after: Option<&Cell>, enforce: bool)
+        -> Result<Result<(), QuotaExceeded>, TxnError>
+    {
+        let quotas = match self.quotas { Some(ref quotas) => quotas.clone(), None => return Ok(Ok(())) };
+        let schema_id = match after.or(before) { Some(cell) => cell.header.schema, None => return Ok(Ok(())) };
+        if !quotas.covers(&self.schemas, schema_id) { return Ok(Ok(())); }
+        let added = after.map(cell_usage).unwrap_or_default();
+        let removed = before.map(cell_usage).unwrap_or_default();
+        let mut counters = Vec::new();
+        if let Some(limits) = quotas.schema_limits(&self.schemas, schema_id) {
+            counters.push((Some(schema_id), limits));
+        }
+        if !quotas.graph.is_empty() {
+            counters.push((None, &quotas.graph));
+        }
+        let mut updated = Vec::with_capacity(counters.len());
+        for (schema, limits) in counters {
+            let id = usage_id(schema, self.schemas.namespace());
+            let usage = apply(self.read_quota_usage(&id)?, &added, &removed);
+            if enforce {
+                if let Some(exceeded) = limits.exceeded_by(&usage, schema) { return Ok(Err(exceeded)); }
+            }
+            updated.push((id, usage));
+        }
+        for (id, usage) in updated {
+            self.write_quota_usage(&id, &usage)?;
+        }
+        Ok(Ok(()))
+    }
+}
+
+impl GraphInner {
+    // the usage of every limit set, the graph's first
+    pub fn quota_usage(&self) -> impl Future<Item = Vec<QuotaReport>, Error = TxnError> {
+        let quotas = self.quotas.read().clone();
+        let schemas = self.schemas.clone();
+        self.graph_transaction(move |txn| {
+            let quotas = match quotas { Some(ref quotas) => quotas, None => return Ok(Vec::new()) };
+            let mut reports = Vec::new();
+            if !quotas.graph.is_empty() {
+                let usage = txn.read_quota_usage(&usage_id(None, schemas.namespace()))?;
+                reports.push(QuotaReport { schema: None, limits: quotas.graph.clone(), usage });
+            }
+            for &(ref name, ref limits) in quotas.schemas.iter().filter(|&&(_, ref limits)| !limits.is_empty()) {
+                let usage = match schemas.id_from_name(name) {
+                    Some(schema_id) => txn.read_quota_usage(&usage_id(Some(schema_id), schemas.namespace()))?,
+                    None => QuotaUsage::default()
+                };
+                reports.push(QuotaReport { schema: Some(name.clone()), limits: limits.clone(), usage });
+            }
+            Ok(reports)
+        })
+    }
+
+    // Scans the vertex schemas under a limit, all of them for a graph limit, and resets their counters
+    #[async]
+    pub fn recount_quota_usage(this: Arc<Self>) -> Result<(), RecountError> {
+        let quotas = match this.quotas.read().clone() { Some(quotas) => quotas, None => return Ok(()) };
+        let mut counted = Vec::new();
+        let mut total = QuotaUsage::default();
+        for schema_id in this.schemas.vertex_schema_ids() {
+            if !quotas.covers(&this.schemas, schema_id) { continue; }
+            let usage = await!(GraphInner::scan_vertices(this.clone(), schema_id, &None::<String>, None)
+                .fold(QuotaUsage::default(), |usage, vertex| {
+                    Ok::<_, ScanVerticesError>(apply(usage, &cell_usage(&vertex.cell), &QuotaUsage::default()))
+                })).map_err(RecountError::ScanError)?;
+            total = apply(total, &usage, &QuotaUsage::default());
+            if quotas.schema_limits(&this.schemas, schema_id).is_some() {
+                counted.push((usage_id(Some(schema_id), this.schemas.namespace()), usage));
+            }
+        }
+        if !quotas.graph.is_empty() {
+            counted.push((usage_id(None, this.schemas.namespace()), total));
+        }
+        let counted = Arc::new(counted);
+        await!(this.graph_transaction(move |txn| {
+            for &(ref id, ref usage) in counted.iter() { txn.write_quota_usage(id, usage)?; }
+            Ok(())
+        })).map_err(RecountError::TxnError)
+    }
+}
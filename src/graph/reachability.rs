@@ -0,0 +1,95 @@
+// Reachability between two vertices by bidirectional breadth first search. The side with the smaller
+// frontier takes the next hop, forward from the source over outbound edges and backward from the
+// target over inbound ones, undirected schemas both ways, and the search ends at the first vertex both
+// sides reached. Only edge lists are read, never the vertices on the way.
+
+use neb::ram::types::Id;
+use neb::client::transaction::TxnError;
+
+use std::collections::HashMap;
+
+use graph::{GraphTransaction, EdgeDirection, edge_attr_from_schema};
+use graph::edge::{EdgeError, EdgeType};
+
+// the vertex each one was reached from, None for where the side started
+type Parents = HashMap<Id, Option<Id>>;
+
+fn walk_back(parents: &Parents, from: Id) -> Vec<Id> {
+    let mut path = vec![from];
+    let mut current = from;
+    while let Some(&Some(parent)) = parents.get(&current) {
+        path.push(parent);
+        current = parent;
+    }
+    path
+}
+
+impl <'a> GraphTransaction<'a> {
+    // The vertices of a shortest path of at most max_depth hops from one vertex to the other, both
+    // ends included, or None when there is no such path.
+    pub fn reachable_path(&self, from: Id, to: Id, edge_schemas: &[u32], max_depth: usize)
+        -> Result<Result<Option<Vec<Id>>, EdgeError>, TxnError>
+    {
+        if from == to { return Ok(Ok(Some(vec![from]))); }
+        let mut forward_directions = Vec::new();
+        let mut backward_directions = Vec::new();
+        for &schema_id in edge_schemas {
+            let edge_attr = match edge_attr_from_schema(schema_id, &self.schemas) {
+                Ok((_, edge_attr)) => edge_attr, Err(e) => return Ok(Err(e))
+            };
+            match edge_attr.edge_type {
+                EdgeType::Directed => {
+                    forward_directions.push((schema_id, EdgeDirection::Outbound));
+                    backward_directions.push((schema_id, EdgeDirection::Inbound));
+                },
+                EdgeType::Undirected => {
+                    forward_directions.push((schema_id, EdgeDirection::Undirected));
+                    backward_directions.push((schema_id, EdgeDirection::Undirected));
+                }
+            }
+        }
+        let (mut forward, mut backward): (Parents, Parents) = (HashMap::new(), HashMap::new());
+        forward.insert(from, None);
+        backward.insert(to, None);
+        let (mut forward_frontier, mut backward_frontier) = (vec![from], vec![to]);
+        let mut depth = 0;
+        while depth < max_depth && !forward_frontier.is_empty() && !backward_frontier.is_empty() {
+            depth += 1;
+            let is_forward = forward_frontier.len() <= backward_frontier.len();
+            let (frontier, parents, others, directions) = if is_forward {
+                (&mut forward_frontier, &mut forward, &backward, &forward_directions)
+            } else {
+                (&mut backward_frontier, &mut backward, &forward, &backward_directions)
+            };
+            let mut next = Vec::new();
+            for &vertex in frontier.iter() {
+                for &(schema_id, direction) in directions {
+                    let edges = match self.edges(vertex, schema_id, direction, &None)? {
+                        Ok(edges) => edges, Err(e) => return Ok(Err(e))
+                    };
+                    for edge in edges {
+                        let opposite = match edge.one_opposite_id_vertex_id(&vertex) {
+                            Some(opposite) => *opposite, None => continue
+                        };
+                        if parents.contains_key(&opposite) { continue; }
+                        parents.insert(opposite, Some(vertex));
+                        if others.contains_key(&opposite) {
+                            // both halves run from the meeting vertex to where their side started
+                            let (mut path, rest) = if is_forward {
+                                (walk_back(parents, opposite), walk_back(others, opposite))
+                            } else {
+                                (walk_back(others, opposite), walk_back(parents, opposite))
+                            };
+                            path.reverse();
+                            path.extend(rest.into_iter().skip(1));
+                            return Ok(Ok(Some(path)));
+                        }
+                        next.push(opposite);
+                    }
+                }
+            }
+            *frontier = next;
+        }
+        Ok(Ok(None))
+    }
+}
@@ -0,0 +1,97 @@
+// Changing the key of a vertex and splitting a vertex in two. The id of a keyed vertex is the hash of
+// its key, so a new key is a new vertex: rekey_vertex writes a copy of the stored fields under the new
+// key, moves every edge over with the machinery of graph::merge, a chunk per transaction, and removes
+// the old vertex. split_vertex writes a second vertex with the data given and moves the edges of the
+// edge schemas given to it, the others stay. Id lists and edge cells of the other ends are rewritten
+// by unlinking and linking, bodies go along. An interrupted re-key leaves both vertices with the edges
+// split between them, merge_vertices from the old vertex into the new one finishes it.
+
+use neb::ram::types::{Id, key_hash};
+use neb::dovahkiin::types::{Map, Value};
+use neb::client::transaction::TxnError;
+use futures::prelude::*;
+
+use std::sync::Arc;
+
+use graph::{GraphInner, GraphTransaction, NewVertexError};
+use graph::merge::{MergeReport, MergeError};
+use graph::vertex;
+
+fn default_chunk_size() -> usize { 500 }
+
+#[derive(Debug)]
+pub enum RekeyError {
+    VertexNotFound(Id),
+    // the schema of the vertex has no key
+    NotKeyed,
+    NewVertexError(NewVertexError),
+    MergeError(MergeError),
+    RemoveError(vertex::RemoveError)
+}
+
+// sets the value at the key path, creating the maps on the way
+fn set_key_value(data: &mut Map, path: &[String], key: Value) {
+    let (last, parents) = match path.split_last() { Some(split) => split, None => return };
+    match parents.split_first() {
+        None => { data.insert(last, key); },
+        Some((name, rest)) => {
+            let mut inner = match data.get_by_key_id(key_hash(name)) { &Value::Map(ref inner) => inner.clone(), _ => Map::new() };
+            let mut path = rest.to_vec();
+            path.push(last.clone());
+            set_key_value(&mut inner, &path, key);
+            data.insert(name, Value::Map(inner));
+        }
+    }
+}
+
+impl <'a> GraphTransaction<'a> {
+    // Writes a copy of the vertex's stored fields under the new key, reserved fields are left to the copy
+    pub fn copy_with_key(&self, vertex: Id, key: &Value) -> Result<Result<Id, RekeyError>, TxnError> {
+        let cell = match self.neb_txn.read(&vertex)? {
+            Some(cell) => cell, None => return Ok(Err(RekeyError::VertexNotFound(vertex)))
+        };
+        let schema_id = cell.header.schema;
+        let path = match self.schemas.get_neb_schema(schema_id).and_then(|schema| schema.str_key_field.clone()) {
+            Some(path) => path, None => return Ok(Err(RekeyError::NotKeyed))
+        };
+        let mut data = Map::new();
+        if let Value::Map(ref stored) = cell.data {
+            for name in stored.fields.iter().filter(|name| !name.starts_with('_')) {
+                data.insert(name, stored.get_by_key_id(key_hash(name)).clone());
+            }
+        }
+        set_key_value(&mut data, &path, key.clone());
+        Ok(self.new_vertex(schema_id, data)?.map(|copy| copy.cell.id()).map_err(RekeyError::NewVertexError))
+    }
+}
+
+impl GraphInner {
+    #[async]
+    pub fn rekey_vertex(this: Arc<Self>, vertex: Id, key: Value) -> Result<Result<Id, RekeyError>, TxnError> {
+        let new_id = match await!(this.graph_transaction(move |txn| txn.copy_with_key(vertex, &key)))? {
+            Ok(id) => id, Err(e) => return Ok(Err(e))
+        };
+        if let Err(e) = await!(Self::move_all_edges(this.clone(), new_id, vertex, None, default_chunk_size()))? {
+            return Ok(Err(RekeyError::MergeError(e)));
+        }
+        match await!(this.graph_transaction(move |txn| txn.remove_vertex(vertex)))? {
+            Ok(()) => Ok(Ok(new_id)),
+            Err(e) => Ok(Err(RekeyError::RemoveError(e)))
+        }
+    }
+
+    #[async]
+    pub fn split_vertex(this: Arc<Self>, vertex: Id, schema_id: u32, data: Map, edge_schemas: Vec<u32>)
+        -> Result<Result<(Id, MergeReport), RekeyError>, TxnError>
+    {
+        let exists = await!(this.graph_transaction(move |txn| txn.vertex_exists(vertex)))?;
+        if !exists { return Ok(Err(RekeyError::VertexNotFound(vertex))); }
+        let new_id = match await!(this.graph_transaction(move |txn| txn.new_vertex(schema_id, data.clone())))? {
+            Ok(split) => split.cell.id(), Err(e) => return Ok(Err(RekeyError::NewVertexError(e)))
+        };
+        match await!(Self::move_all_edges(this.clone(), new_id, vertex, Some(edge_schemas), default_chunk_size()))? {
+            Ok(report) => Ok(Ok((new_id, report))),
+            Err(e) => Ok(Err(RekeyError::MergeError(e)))
+        }
+    }
+}
@@ -0,0 +1,133 @@
+// Typed reads and writes of the vertices of one model, on top of graph::model. Graph::repository
+// gives the repository of a vertex model, entities pair a vertex id with its model. Links name the
+// edge model and the model of the other end, so linked::<Movie, ActedIn> only returns movies, other
+// vertices at the end of the edges are skipped. Updates write the model's fields and keep the rest.
+
+use neb::ram::types::{Id, key_hash};
+use neb::dovahkiin::types::{ToValue, Value};
+use neb::client::transaction::TxnError;
+use futures::prelude::*;
+
+use std::marker::PhantomData;
+
+use graph::{Graph, EdgeDirection, NewVertexError, ReadVertexError, LinkVerticesError, NeighbourhoodError};
+use graph::model::{VertexModel, EdgeModel, ModelError};
+use graph::vertex::{Vertex, RemoveError};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entity<M> {
+    pub id: Id,
+    pub model: M
+}
+
+#[derive(Debug)]
+pub enum RepositoryError {
+    NewVertexError(NewVertexError),
+    ReadVertexError(ReadVertexError),
+    LinkVerticesError(LinkVerticesError),
+    NeighbourhoodError(NeighbourhoodError),
+    ModelError(ModelError),
+    RemoveError(RemoveError),
+    TxnError(TxnError),
+    // the vertex belongs to another schema than the model's
+    WrongSchema(Id)
+}
+
+pub struct Repository<'a, M> {
+    graph: &'a Graph,
+    model: PhantomData<M>
+}
+
+fn entity<M: VertexModel>(graph: &Graph, vertex: Vertex) -> Result<Entity<M>, RepositoryError> {
+    let id = vertex.cell.id();
+    if graph.schema_by_name(M::schema_name()).map(|(schema_id, _)| schema_id) != Some(vertex.schema()) {
+        return Err(RepositoryError::WrongSchema(id));
+    }
+    M::from_vertex(&vertex).map(|model| Entity { id, model }).map_err(RepositoryError::ModelError)
+}
+
+impl <'a, M> Repository<'a, M> where M: VertexModel + 'static {
+    pub fn new(graph: &'a Graph) -> Repository<'a, M> {
+        Repository { graph, model: PhantomData }
+    }
+
+    pub fn create(&self, model: M) -> impl Future<Item = Entity<M>, Error = RepositoryError> {
+        self.graph.new_model_vertex(&model)
+            .map(move |vertex| Entity { id: vertex.cell.id(), model })
+            .map_err(RepositoryError::NewVertexError)
+    }
+
+    pub fn find(&self, id: Id) -> impl Future<Item = Option<Entity<M>>, Error = RepositoryError> + 'a {
+        let graph = self.graph;
+        graph.vertex_by(id)
+            .map_err(RepositoryError::ReadVertexError)
+            .and_then(move |vertex| match vertex {
+                Some(vertex) => entity(graph, vertex).map(Some),
+                None => Ok(None)
+            })
+    }
+
+    pub fn find_by_key<K>(&self, key: K) -> impl Future<Item = Option<Entity<M>>, Error = RepositoryError> + 'a
+        where K: ToValue
+    {
+        let graph = self.graph;
+        graph.vertex_by_key(M::schema_name(), key)
+            .map_err(RepositoryError::ReadVertexError)
+            .and_then(move |vertex| match vertex {
+                Some(vertex) => entity(graph, vertex).map(Some),
+                None => Ok(None)
+            })
+    }
+
+    pub fn update(&self, entity: &Entity<M>) -> impl Future<Item = (), Error = RepositoryError> {
+        let fields = entity.model.to_map();
+        self.graph.update_vertex(entity.id, move |mut vertex| {
+            if let Value::Map(ref mut data) = vertex.cell.data {
+                for name in &fields.fields {
+                    data.insert(name, fields.get_by_key_id(key_hash(name)).clone());
+                }
+            }
+            Some(vertex)
+        }).map_err(RepositoryError::TxnError)
+    }
+
+    pub fn remove(&self, entity: &Entity<M>) -> impl Future<Item = (), Error = RepositoryError> {
+        self.graph.remove_vertex(entity.id).map_err(RepositoryError::TxnError)
+            .and_then(|removed| removed.map_err(RepositoryError::RemoveError))
+    }
+
+    pub fn link_to<O, E>(&self, from: &Entity<M>, to: &Entity<O>, body: &E)
+        -> impl Future<Item = (), Error = RepositoryError>
+        where O: VertexModel, E: EdgeModel
+    {
+        self.graph.link_model(from.id, to.id, body).then(|result| match result {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err(e)) => Err(RepositoryError::LinkVerticesError(e)),
+            Err(e) => Err(RepositoryError::TxnError(e))
+        })
+    }
+
+    // the O models at the other end of the entity's E edges, with the edge bodies
+    pub fn linked<O, E>(&self, from: &Entity<M>, direction: EdgeDirection)
+        -> impl Future<Item = Vec<(Entity<O>, E)>, Error = RepositoryError> + 'a
+        where O: VertexModel + 'a, E: EdgeModel + 'a
+    {
+        let graph = self.graph;
+        let schema = graph.schema_by_name(O::schema_name()).map(|(schema_id, _)| schema_id);
+        graph.neighbourhoods(from.id, E::schema_name(), direction, &None::<String>)
+            .then(move |result| {
+                let neighbours = match result {
+                    Ok(Ok(neighbours)) => neighbours,
+                    Ok(Err(e)) => return Err(RepositoryError::NeighbourhoodError(e)),
+                    Err(e) => return Err(RepositoryError::TxnError(e))
+                };
+                let mut linked = Vec::new();
+                for (vertex, edge) in neighbours {
+                    if Some(vertex.schema()) != schema { continue; }
+                    let body = E::from_edge(&edge).map_err(RepositoryError::ModelError)?;
+                    linked.push((entity(graph, vertex)?, body));
+                }
+                Ok(linked)
+            })
+    }
+}
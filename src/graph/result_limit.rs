@@ -0,0 +1,80 @@
+// Caps on what one neighbourhood or edges call returns, now that hub vertices can have more
+// neighbours than a caller should hold at once. A call crossing a limit resolves to an Overflow
+// in place of its result, with how far it got, so the caller can page with traverse_neighbours
+// and a limit below the cap. Without a filter the edge count is checked before any opposite vertex
+// is read, filtered calls are checked on what passes the filter. Sizes are estimated like quotas do.
+// Internal operations, removals, merges and the like, read whole lists and are never limited.
+
+use graph::edge::Edge;
+use graph::vertex::Vertex;
+use graph::traverse::Neighbour;
+use graph::quota::value_size;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct ResultLimits {
+    // items, edges or vertex and edge pairs
+    #[serde(default)]
+    pub max_count: Option<usize>,
+    // approximate bytes of the vertices and edge bodies returned
+    #[serde(default)]
+    pub max_bytes: Option<u64>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverflowKind {
+    Count,
+    Bytes
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Overflow {
+    pub kind: OverflowKind,
+    pub limit: u64,
+    // items counted when the limit was crossed, a page size that fits is below it
+    pub count: usize
+}
+
+impl ResultLimits {
+    pub fn new(max_count: Option<usize>, max_bytes: Option<u64>) -> ResultLimits {
+        ResultLimits { max_count, max_bytes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.max_count.is_none() && self.max_bytes.is_none()
+    }
+
+    pub fn check_count(&self, count: usize) -> Result<(), Overflow> {
+        match self.max_count {
+            Some(max_count) if count > max_count =>
+                Err(Overflow { kind: OverflowKind::Count, limit: max_count as u64, count: max_count + 1 }),
+            _ => Ok(())
+        }
+    }
+
+    // counts items with their sizes until one of the limits is crossed
+    pub fn check<T, F>(&self, items: &[T], size: F) -> Result<(), Overflow> where F: Fn(&T) -> u64 {
+        self.check_count(items.len())?;
+        let max_bytes = match self.max_bytes { Some(max_bytes) => max_bytes, None => return Ok(()) };
+        let mut bytes = 0;
+        for (index, item) in items.iter().enumerate() {
+            bytes += size(item);
+            if bytes > max_bytes {
+                return Err(Overflow { kind: OverflowKind::Bytes, limit: max_bytes, count: index + 1 });
+            }
+        }
+        Ok(())
+    }
+}
+
+// the ids of both ends and the body
+pub fn edge_size(edge: &Edge) -> u64 {
+    32 + edge.get_data().as_ref().map_or(0, |cell| value_size(&cell.data))
+}
+
+pub fn neighbour_size(pair: &(Vertex, Edge)) -> u64 {
+    value_size(&pair.0.cell.data) + edge_size(&pair.1)
+}
+
+pub fn traversed_size(neighbour: &Neighbour) -> u64 {
+    neighbour.vertex.as_ref().map_or(0, |vertex| value_size(&vertex.cell.data)) + edge_size(&neighbour.edge)
+}
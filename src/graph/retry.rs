@@ -0,0 +1,72 @@
+// How graph operations react to a transaction neb gave up on. Neb already re-runs the closure
+// internally while it can, this policy decides whether to start a whole new transaction after that.
+// Transactions aborted on purpose, through GraphTransaction::abort or by an update returning None,
+// are never started again whatever the policy says. A read only graph fails mutations with ReadOnly
+// errors instead of aborting.
+
+use neb::client::transaction::TxnError;
+use rand::{self, Rng};
+
+use std::cmp;
+use std::time::Duration;
+
+fn default_max_attempts() -> u32 { 3 }
+fn default_initial_backoff_ms() -> u64 { 10 }
+fn default_max_backoff_ms() -> u64 { 1000 }
+fn default_true() -> bool { true }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RetryPolicy {
+    // counts the first try, 1 surfaces the first error
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    // doubled after every attempt up to max_backoff_ms
+    #[serde(default = "default_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    #[serde(default = "default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+    // sleeps a random time between half and the full backoff so conflicting writers spread out
+    #[serde(default = "default_true")]
+    pub jitter: bool,
+    // only retry aborts caused by conflicts, other transaction errors are returned at once
+    #[serde(default = "default_true")]
+    pub conflict_only: bool
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: default_max_attempts(),
+            initial_backoff_ms: default_initial_backoff_ms(),
+            max_backoff_ms: default_max_backoff_ms(),
+            jitter: true,
+            conflict_only: true
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn no_retry() -> RetryPolicy {
+        RetryPolicy { max_attempts: 1, ..RetryPolicy::default() }
+    }
+
+    // attempt starts from 1
+    pub fn should_retry(&self, attempt: u32, error: &TxnError) -> bool {
+        if attempt >= self.max_attempts { return false; }
+        match error {
+            &TxnError::Aborted(_) => true,
+            _ => !self.conflict_only
+        }
+    }
+
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exp = cmp::min(attempt.saturating_sub(1), 32);
+        let full = cmp::min(self.initial_backoff_ms.saturating_mul(1 << exp), self.max_backoff_ms);
+        let millis = if self.jitter && full > 1 {
+            rand::thread_rng().gen_range(full / 2, full + 1)
+        } else {
+            full
+        };
+        Duration::from_millis(millis)
+    }
+}
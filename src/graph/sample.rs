@@ -0,0 +1,118 @@
+// Random samples for approximate analytics, for dashboards and feature pipelines that do without
+// every vertex. Vertices are sampled in one scan of the schema: a count keeps a uniform reservoir of
+// that many, a fraction keeps each vertex with that probability, so the sample size varies around
+// it. Neighbours are sampled from the id lists of the vertex by a reservoir over the list entries,
+// only the sampled edges and their opposite vertices are read. Self loops listed twice under Both
+// are only returned once, which can leave such a sample one short of k.
+
+use neb::ram::types::Id;
+use neb::client::transaction::TxnError;
+use futures::prelude::*;
+use rand::{self, Rng};
+
+use std::sync::Arc;
+
+use graph::{GraphInner, GraphTransaction, EdgeDirection, NeighbourhoodError, ScanVerticesError, id_list};
+use graph::edge::{self, EdgeError};
+use graph::vertex::{Vertex, ToVertexId};
+use server::schema::ToSchemaId;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SampleSize {
+    Count(usize),
+    // 0 keeps nothing, 1 or more keeps everything
+    Fraction(f64)
+}
+
+// Uniform sample of k items offered one by one, algorithm R
+pub struct Reservoir<T> {
+    k: usize,
+    seen: usize,
+    items: Vec<T>
+}
+
+impl <T> Reservoir<T> {
+    pub fn new(k: usize) -> Reservoir<T> {
+        Reservoir { k, seen: 0, items: Vec::with_capacity(k) }
+    }
+
+    pub fn offer(&mut self, item: T) {
+        self.seen += 1;
+        if self.items.len() < self.k {
+            self.items.push(item);
+        } else {
+            let slot = rand::thread_rng().gen_range(0, self.seen);
+            if slot < self.k { self.items[slot] = item; }
+        }
+    }
+
+    // items offered so far
+    pub fn seen(&self) -> usize {
+        self.seen
+    }
+
+    pub fn into_vec(self) -> Vec<T> {
+        self.items
+    }
+}
+
+impl GraphInner {
+    pub fn sample_vertices<S>(this: Arc<Self>, schema: S, size: SampleSize)
+        -> impl Future<Item = Vec<Vertex>, Error = ScanVerticesError>
+        where S: ToSchemaId
+    {
+        let (reservoir, fraction) = match size {
+            SampleSize::Count(k) => (Some(Reservoir::new(k)), 1.0),
+            SampleSize::Fraction(fraction) => (None, fraction)
+        };
+        GraphInner::scan_vertices(this, schema, &None::<String>, None)
+            .fold((reservoir, Vec::new()), move |(mut reservoir, mut kept), vertex| {
+                match reservoir {
+                    Some(ref mut reservoir) => reservoir.offer(vertex),
+                    None => if rand::thread_rng().gen::<f64>() < fraction { kept.push(vertex); }
+                }
+                Ok::<_, ScanVerticesError>((reservoir, kept))
+            })
+            .map(|(reservoir, kept)| reservoir.map_or(kept, Reservoir::into_vec))
+    }
+}
+
+impl <'a> GraphTransaction<'a> {
+    pub fn sample_neighbours<V, S>(&self, vertex: V, schema: S, ed: EdgeDirection, k: usize)
+        -> Result<Result<Vec<(Vertex, edge::Edge)>, NeighbourhoodError>, TxnError>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        let schema_id = schema.to_id(&self.schemas);
+        let vertex_id = &vertex.to_id();
+        self.watch.touch("sample_neighbours", Some(schema_id), *vertex_id)?;
+        let mut reservoir: Reservoir<(EdgeDirection, u64, Id)> = Reservoir::new(k);
+        for (part, vertex_field) in ed.for_schema(schema_id, &self.schemas).expand() {
+            match id_list::IdList::from_txn_and_container(self.neb_txn, vertex_id, vertex_field, schema_id).iter()? {
+                Ok(mut ids) => {
+                    for id in ids.by_ref() { reservoir.offer((part, vertex_field, id)); }
+                    if let Some(e) = ids.take_error() {
+                        return Ok(Err(NeighbourhoodError::EdgeError(EdgeError::IdListError(e))));
+                    }
+                },
+                Err(e) => return Ok(Err(NeighbourhoodError::EdgeError(EdgeError::IdListError(e))))
+            }
+        }
+        let mut result = Vec::with_capacity(k);
+        for (part, vertex_field, id) in reservoir.into_vec() {
+            let edge = match edge::from_id(vertex_id, vertex_field, schema_id, &self.schemas, self.neb_txn, &id)? {
+                Ok(edge) => edge,
+                Err(e) => return Ok(Err(NeighbourhoodError::EdgeError(e)))
+            };
+            let opposite_id = match edge.one_opposite_id_vertex_id(vertex_id) {
+                Some(opposite_id) => *opposite_id,
+                None => return Ok(Err(NeighbourhoodError::CannotFindOppositeId(*vertex_id)))
+            };
+            if ed.repeats(part, vertex_id, &opposite_id) { continue; }
+            match self.read_vertex(opposite_id)? {
+                Some(vertex) => result.push((vertex, edge)),
+                None => return Ok(Err(NeighbourhoodError::VertexNotFound(opposite_id)))
+            }
+        }
+        Ok(Ok(result))
+    }
+}
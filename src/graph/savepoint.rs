@@ -0,0 +1,180 @@
+// Savepoints inside a graph transaction. Neb has no nested transactions, so once the first savepoint
+// is taken every mutation through GraphTransaction logs how to undo itself, and rolling back applies
+// those undos in reverse inside the same neb transaction. Writes made directly on neb_txn are not logged.
+// Undoing a vertex removal or an unlink links the edges again, edges with a body get new body cell ids.
+
+use neb::ram::types::{Id, Map, Value};
+use neb::ram::cell::Cell;
+use neb::client::transaction::TxnError;
+
+use graph::{GraphTransaction, EdgeDirection, LinkVerticesError, id_list};
+use graph::changes::ChangeKind;
+use graph::edge::{self, EdgeError};
+use graph::vertex::{self, RemoveError};
+use graph::geo::GeoError;
+
+// position in the undo log, savepoints nest by taking positions further down the log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Savepoint(usize);
+
+#[derive(Debug)]
+pub enum SavepointError {
+    // already rolled back past this savepoint
+    UnknownSavepoint,
+    EdgeError(EdgeError),
+    RemoveError(RemoveError),
+    LinkError(LinkVerticesError),
+    GeoIndexError(GeoError)
+}
+
+pub struct RemovedEdge {
+    from: Id,
+    schema_id: u32,
+    to: Id,
+    body: Option<Map>
+}
+
+pub enum Undo {
+    NewVertex(Id),
+    UpdateVertex(Cell),
+    Link(edge::Edge),
+    Unlink(edge::Edge),
+    RemoveVertex(Cell, Vec<RemovedEdge>),
+    // written with an idempotency key, see graph::idempotency
+    IdempotencyRecord(Id),
+    // the counter cell and what it held before, see graph::window_counter
+    WindowCounter(Id, Option<Cell>)
+}
+
+impl <'a> GraphTransaction<'a> {
+    pub fn savepoint(&self) -> Savepoint {
+        let mut log = self.undo_log.borrow_mut();
+        if log.is_none() { *log = Some(Vec::new()); }
+        Savepoint(log.as_ref().map(|l| l.len()).unwrap_or(0))
+    }
+
+    // Undoes everything done through this transaction after the savepoint was taken.
+    // The savepoint stays usable, savepoints taken after it are gone.
+    pub fn rollback_to(&self, savepoint: &Savepoint) -> Result<Result<(), SavepointError>, TxnError> {
+        // taken out so the undo operations below are not logged themselves
+        let mut log = match self.undo_log.borrow_mut().take() {
+            Some(log) => log, None => return Ok(Err(SavepointError::UnknownSavepoint))
+        };
+        if savepoint.0 > log.len() {
+            *self.undo_log.borrow_mut() = Some(log);
+            return Ok(Err(SavepointError::UnknownSavepoint));
+        }
+        let undos = log.split_off(savepoint.0);
+        let mut result = Ok(());
+        for undo in undos.into_iter().rev() {
+            if let Err(e) = self.apply_undo(undo)? {
+                result = Err(e);
+                break;
+            }
+        }
+        *self.undo_log.borrow_mut() = Some(log);
+        Ok(result)
+    }
+
+    fn apply_undo(&self, undo: Undo) -> Result<Result<(), SavepointError>, TxnError> {
+        match undo {
+            Undo::NewVertex(id) => {
+                let cell = self.neb_txn.read(&id)?;
+                if let Err(e) = self.reindex_geo(cell.as_ref(), None)? {
+                    return Ok(Err(SavepointError::GeoIndexError(e)));
+                }
+                self.record_vectors(cell.as_ref(), None);
+                self.record_change(cell.as_ref(), None);
+                Ok(vertex::txn_remove(self.neb_txn, &self.schemas, id)?.map_err(SavepointError::RemoveError))
+            },
+            Undo::UpdateVertex(cell) => {
+                let current = self.neb_txn.read(&cell.id())?;
+                if let Err(e) = self.reindex_geo(current.as_ref(), Some(&cell))? {
+                    return Ok(Err(SavepointError::GeoIndexError(e)));
+                }
+                self.record_vectors(current.as_ref(), Some(&cell));
+                self.record_change(current.as_ref(), Some(&cell));
+                self.neb_txn.update(&cell)?;
+                Ok(Ok(()))
+            },
+            Undo::Link(edge) => {
+                let removed = edge.clone().remove(self.neb_txn, &self.schemas)?;
+                if removed.is_ok() { self.record_edge_change(ChangeKind::Unlinked, &edge); }
+                Ok(removed.map_err(SavepointError::EdgeError))
+            },
+            Undo::Unlink(edge) => {
+                let (from, to) = edge.ends();
+                let body = edge.get_data().as_ref().map(|body_cell| match body_cell.data {
+                    Value::Map(ref map) => map.clone(),
+                    _ => Map::new()
+                });
+                Ok(self.link_uncounted(*from, edge.schema_id(), *to, body)?.map(|_| ()).map_err(SavepointError::LinkError))
+            },
+            Undo::RemoveVertex(mut cell, edges) => {
+                // the edge lists were removed with the vertex, linking again creates new ones
+                for (_, field) in EdgeDirection::every_list() {
+                    cell.data[field] = Value::Id(Id::unit_id());
+                }
+                self.neb_txn.write(&cell)?;
+                if let Err(e) = self.reindex_geo(None, Some(&cell))? {
+                    return Ok(Err(SavepointError::GeoIndexError(e)));
+                }
+                self.record_vectors(None, Some(&cell));
+                self.record_change(None, Some(&cell));
+                for edge in edges {
+                    if let Err(e) = self.link_uncounted(edge.from, edge.schema_id, edge.to, edge.body)? {
+                        return Ok(Err(SavepointError::LinkError(e)));
+                    }
+                }
+                Ok(Ok(()))
+            },
+            Undo::IdempotencyRecord(id) => {
+                self.neb_txn.remove(&id)?;
+                Ok(Ok(()))
+            },
+            Undo::WindowCounter(_, Some(cell)) => {
+                self.neb_txn.update(&cell)?;
+                Ok(Ok(()))
+            },
+            Undo::WindowCounter(id, None) => {
+                self.neb_txn.remove(&id)?;
+                Ok(Ok(()))
+            }
+        }
+    }
+
+    // what remove_vertex needs to be undone, None when the vertex does not exist
+    pub(super) fn capture_removal(&self, id: &Id) -> Result<Result<Option<Undo>, EdgeError>, TxnError> {
+        let cell = match self.neb_txn.read(id)? {
+            Some(cell) => cell, None => return Ok(Ok(None))
+        };
+        let mut removed = Vec::new();
+        for (direction, field) in EdgeDirection::every_list() {
+            let schema_ids = match id_list::IdList::cell_types(self.neb_txn, id, field)? {
+                Some((_, schema_ids)) => schema_ids, None => continue
+            };
+            for schema_id in schema_ids {
+                let edges = match self.edges(id, schema_id, direction, &None)? {
+                    Ok(edges) => edges, Err(e) => return Ok(Err(e))
+                };
+                for edge in edges {
+                    let opposite = match edge.one_opposite_id_vertex_id(id) {
+                        Some(opposite) => *opposite, None => continue
+                    };
+                    // a directed self-loop shows up in both lists of the vertex
+                    if direction == EdgeDirection::Inbound && opposite == *id { continue; }
+                    let (from, to) = match direction {
+                        EdgeDirection::Inbound => (opposite, *id),
+                        _ => (*id, opposite)
+                    };
+                    let body = edge.get_data().as_ref().map(|body_cell| match body_cell.data {
+                        Value::Map(ref map) => map.clone(),
+                        _ => Map::new()
+                    });
+                    removed.push(RemovedEdge { from, schema_id, to, body });
+                }
+            }
+        }
+        Ok(Ok(Some(Undo::RemoveVertex(cell, removed))))
+    }
+}
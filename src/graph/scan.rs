@@ -0,0 +1,36 @@
+use neb::ram::types::Id;
+use neb::ram::cell::{Cell, ReadError};
+use neb::client::{AsyncClient as NebClient};
+use bifrost::rpc::RPCError;
+use futures::prelude::*;
+use futures::stream;
+
+use std::sync::Arc;
+
+pub static DEFAULT_SCAN_BATCH: usize = 512;
+
+#[derive(Debug)]
+pub enum ScanError {
+    RPCError(RPCError),
+    ReadError(ReadError)
+}
+
+// Pages through every cell of a schema across all chunks and servers.
+// Each page starts after the last id of the previous one, a short page ends the scan.
+pub fn scan_cells(neb_client: Arc<NebClient>, schema_id: u32, batch_size: usize)
+    -> impl Stream<Item = Vec<Cell>, Error = ScanError>
+{
+    stream::unfold(Some(Id::unit_id()), move |cursor| {
+        let cursor = match cursor { Some(id) => id, None => return None };
+        Some(neb_client.scan_cells(schema_id, cursor, batch_size).then(move |result| {
+            match result {
+                Err(e) => Err(ScanError::RPCError(e)),
+                Ok(Err(e)) => Err(ScanError::ReadError(e)),
+                Ok(Ok(cells)) => {
+                    let next = if cells.len() < batch_size { None } else { cells.last().map(|c| c.id()) };
+                    Ok((cells, next))
+                }
+            }
+        }))
+    }).filter(|cells| !cells.is_empty())
+}
@@ -0,0 +1,204 @@
+// Read-your-writes on top of Graph. A session remembers the version (_version, see Vertex::version) of
+// every vertex it wrote and reads of those vertices wait until the server hands back at least that
+// version, so a client can render what it just wrote. Edge lists are not tracked, only vertex cells.
+// Sessions are meant to live as long as one interactive client. They track up to max_tracked vertices,
+// past that the ones tracked the longest are forgotten and read without waiting.
+
+use neb::ram::types::{Id, Map};
+use neb::client::transaction::TxnError;
+use futures::prelude::*;
+use futures::future::{self, Loop};
+use parking_lot::Mutex;
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+use graph::{Graph, NewVertexError, ReadVertexError};
+use graph::vertex::{Vertex, ToVertexId, RemoveError};
+use server::schema::ToSchemaId;
+use utils::timer;
+
+#[derive(Debug, Clone)]
+pub struct SessionOptions {
+    // reads of a vertex newer than what the server returns are tried this many times
+    pub max_read_attempts: u32,
+    pub read_retry_interval_ms: u64,
+    pub max_tracked: usize
+}
+
+impl Default for SessionOptions {
+    fn default() -> SessionOptions {
+        SessionOptions {
+            max_read_attempts: 10,
+            read_retry_interval_ms: 5,
+            max_tracked: 10000
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SessionReadError {
+    ReadError(ReadVertexError),
+    // the server kept returning an older version than this session wrote
+    Stale(Id)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Written {
+    Version(u64),
+    Removed
+}
+
+// tracked vertices in the order they were first tracked
+#[derive(Default)]
+struct Tracked {
+    versions: HashMap<Id, Written>,
+    order: VecDeque<Id>
+}
+
+impl Tracked {
+    fn get(&self, id: &Id) -> Option<Written> {
+        self.versions.get(id).cloned()
+    }
+
+    // versions only go forward, a removal replaces whatever was known
+    fn record(&mut self, id: Id, written: Written, max_tracked: usize) {
+        let newly_tracked = !self.versions.contains_key(&id);
+        {
+            let known = self.versions.entry(id).or_insert(written);
+            match (*known, written) {
+                (Written::Version(known_version), Written::Version(version)) if known_version >= version => {},
+                _ => *known = written
+            }
+        }
+        if newly_tracked { self.order.push_back(id); }
+        while self.order.len() > max_tracked {
+            if let Some(oldest) = self.order.pop_front() {
+                self.versions.remove(&oldest);
+            }
+        }
+    }
+}
+
+pub struct Session {
+    graph: Arc<Graph>,
+    tracked: Arc<Mutex<Tracked>>,
+    options: SessionOptions
+}
+
+fn satisfies(expected: &Option<Written>, vertex: &Option<Vertex>) -> bool {
+    match (expected, vertex) {
+        (&None, _) => true,
+        (&Some(Written::Version(version)), &Some(ref vertex)) => vertex.version() >= version,
+        (&Some(Written::Version(_)), &None) => false,
+        (&Some(Written::Removed), vertex) => vertex.is_none()
+    }
+}
+
+impl Session {
+    pub fn new(graph: &Arc<Graph>) -> Session {
+        Session::with_options(graph, SessionOptions::default())
+    }
+
+    pub fn with_options(graph: &Arc<Graph>, options: SessionOptions) -> Session {
+        Session {
+            graph: graph.clone(),
+            tracked: Arc::new(Mutex::new(Tracked::default())),
+            options
+        }
+    }
+
+    pub fn graph(&self) -> &Arc<Graph> {
+        &self.graph
+    }
+
+    // number of vertices whose versions the session waits for
+    pub fn tracked(&self) -> usize {
+        self.tracked.lock().versions.len()
+    }
+
+    pub fn new_vertex<S>(&self, schema: S, data: Map)
+        -> impl Future<Item = Vertex, Error = NewVertexError>
+        where S: ToSchemaId
+    {
+        let tracked = self.tracked.clone();
+        let max_tracked = self.options.max_tracked;
+        self.graph.new_vertex(schema, data).map(move |vertex| {
+            tracked.lock().record(vertex.cell.id(), Written::Version(vertex.version()), max_tracked);
+            vertex
+        })
+    }
+
+    // The version written is read back in the update transaction, so it is the one committed
+    pub fn update_vertex<V, U>(&self, vertex: V, update: U)
+        -> impl Future<Item = (), Error = TxnError>
+        where V: ToVertexId, U: Fn(Vertex) -> Option<Vertex>, U: 'static
+    {
+        let id = vertex.to_id();
+        let tracked = self.tracked.clone();
+        let max_tracked = self.options.max_tracked;
+        self.graph.graph_transaction(move |txn| {
+            txn.update_vertex(id, &update)?;
+            Ok(txn.read_vertex(id)?.map(|vertex| vertex.version()))
+        }).map(move |version| {
+            if let Some(version) = version {
+                tracked.lock().record(id, Written::Version(version), max_tracked);
+            }
+        })
+    }
+
+    pub fn remove_vertex<V>(&self, vertex: V)
+        -> impl Future<Item = Result<(), RemoveError>, Error = TxnError> where V: ToVertexId
+    {
+        let id = vertex.to_id();
+        let tracked = self.tracked.clone();
+        let max_tracked = self.options.max_tracked;
+        self.graph.remove_vertex(id).map(move |removed| {
+            if removed.is_ok() { tracked.lock().record(id, Written::Removed, max_tracked); }
+            removed
+        })
+    }
+
+    // Tries again on the shared timer until the vertex is at least as new as this session's last
+    // write to it. Versions seen by reads are kept too, later reads never go back in time.
+    pub fn vertex_by<V>(&self, vertex: V)
+        -> impl Future<Item = Option<Vertex>, Error = SessionReadError>
+        where V: ToVertexId
+    {
+        let id = vertex.to_id();
+        let expected = self.tracked.lock().get(&id);
+        let graph = self.graph.clone();
+        let tracked = self.tracked.clone();
+        let max_tracked = self.options.max_tracked;
+        let max_attempts = self.options.max_read_attempts;
+        let interval = Duration::from_millis(self.options.read_retry_interval_ms);
+        future::loop_fn(1, move |attempt| {
+            let tracked = tracked.clone();
+            let vertex_cache = graph.vertex_cache();
+            graph.vertex_by(id)
+                .map_err(SessionReadError::ReadError)
+                .and_then(move |vertex| {
+                    if satisfies(&expected, &vertex) {
+                        if let Some(ref vertex) = vertex {
+                            tracked.lock().record(id, Written::Version(vertex.version()), max_tracked);
+                        }
+                        return future::Either::A(future::ok(Loop::Break(vertex)));
+                    }
+                    if attempt >= max_attempts {
+                        return future::Either::A(future::err(SessionReadError::Stale(id)));
+                    }
+                    // the write may have gone through a server that does not notify this one
+                    if let Some(ref cache) = vertex_cache { cache.invalidate(&[id]); }
+                    future::Either::B(timer::delay(interval).then(move |_| Ok(Loop::Continue(attempt + 1))))
+                })
+        })
+    }
+
+    // forget every tracked version, reads go straight to the graph again
+    pub fn clear(&self) {
+        let mut tracked = self.tracked.lock();
+        tracked.versions.clear();
+        tracked.order.clear();
+    }
+}
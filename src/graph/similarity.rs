@@ -0,0 +1,80 @@
+// Similar vertices by shared neighbours, for recommendations. Candidates are the vertices two hops
+// away, reached across the edges and back the opposite way, so a user who likes the same movies is a
+// candidate for a user. Each candidate is scored by comparing neighbour sets in the given direction.
+
+use neb::ram::types::Id;
+use neb::client::transaction::TxnError;
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use graph::{GraphTransaction, EdgeDirection};
+use graph::edge::EdgeError;
+use graph::vertex::ToVertexId;
+use server::schema::ToSchemaId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimilarityMetric {
+    // shared neighbours over all neighbours of the two
+    Jaccard,
+    // shared neighbours over the geometric mean of both degrees
+    Cosine
+}
+
+impl SimilarityMetric {
+    fn score(&self, shared: usize, a: usize, b: usize) -> f64 {
+        let shared = shared as f64;
+        match *self {
+            SimilarityMetric::Jaccard => shared / ((a + b) as f64 - shared),
+            SimilarityMetric::Cosine => shared / ((a * b) as f64).sqrt()
+        }
+    }
+}
+
+fn reverse(ed: EdgeDirection) -> EdgeDirection {
+    match ed {
+        EdgeDirection::Inbound => EdgeDirection::Outbound,
+        EdgeDirection::Outbound => EdgeDirection::Inbound,
+        EdgeDirection::Undirected => EdgeDirection::Undirected,
+        EdgeDirection::Both => EdgeDirection::Both
+    }
+}
+
+impl <'a> GraphTransaction<'a> {
+    // Top k candidates with their score, best first. Every candidate shares at least one neighbour.
+    pub fn similar_vertices<V, S>(&self, vertex: V, schema: S, ed: EdgeDirection, metric: SimilarityMetric, top_k: usize)
+        -> Result<Result<Vec<(Id, f64)>, EdgeError>, TxnError>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        let schema_id = schema.to_id(&self.schemas);
+        let vertex_id = vertex.to_id();
+        let neighbours = match self.neighbour_ids(vertex_id, schema_id, ed)? {
+            Ok(ids) => ids, Err(e) => return Ok(Err(e))
+        };
+        // candidate -> neighbours it shares with the vertex, in the order candidates were found
+        let mut shared: HashMap<Id, usize> = HashMap::new();
+        let mut candidates = Vec::new();
+        for neighbour in &neighbours {
+            let back = match self.neighbour_ids(neighbour, schema_id, reverse(ed))? {
+                Ok(ids) => ids, Err(e) => return Ok(Err(e))
+            };
+            for candidate in back {
+                if candidate == vertex_id { continue; }
+                let count = shared.entry(candidate).or_insert(0);
+                if *count == 0 { candidates.push(candidate); }
+                *count += 1;
+            }
+        }
+        let degree = neighbours.len();
+        let mut scored = Vec::with_capacity(candidates.len());
+        for candidate in candidates {
+            let candidate_degree = match self.neighbour_ids(candidate, schema_id, ed)? {
+                Ok(ids) => ids.len(), Err(e) => return Ok(Err(e))
+            };
+            scored.push((candidate, metric.score(shared[&candidate], degree, candidate_degree)));
+        }
+        scored.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+        scored.truncate(top_k);
+        Ok(Ok(scored))
+    }
+}
@@ -0,0 +1,216 @@
+// Read-only transactions for analytics style reads. Cells are read without a neb transaction, so
+// readers take no locks and never abort writers. Once the closure returns, every cell it read is
+// read again and the closure runs again if any of them changed in between. All reads happen before
+// all checks, so a passing check means there was a moment where every cell held what was read.
+// Edge lists, their trace cells and the opposite vertices are each read in one concurrent batch and
+// the checks are issued together without blocking, which keeps the window for changes short.
+
+use neb::ram::types::Id;
+use neb::ram::cell::{Cell, ReadError};
+use neb::dovahkiin::expr::SExpr;
+use neb::client::{AsyncClient as NebClient};
+use bifrost::rpc::RPCError;
+use futures::prelude::*;
+use futures::future::{self, Loop};
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use graph::{EdgeDirection, NeighbourhoodError, id_list, encryption};
+use graph::edge::{self, EdgeError};
+use graph::vertex::{self, Vertex, ToVertexId};
+use server::schema::{SchemaContainer, ToSchemaId};
+use query::Tester;
+
+// runs of the closure before giving up on a snapshot that keeps changing under it
+pub static SNAPSHOT_ATTEMPTS: u32 = 5;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    RPCError(RPCError),
+    ReadError(ReadError),
+    Unstable(u32)
+}
+
+pub struct ReadTransaction {
+    neb_client: Arc<NebClient>,
+    schemas: Arc<SchemaContainer>,
+    // every cell read so far, None for cells that did not exist, later reads of the same id come from here
+    cells: RefCell<HashMap<Id, Option<Cell>>>
+}
+
+fn fetch(neb_client: &Arc<NebClient>, id: Id) -> impl Future<Item = Option<Cell>, Error = SnapshotError> {
+    neb_client.read_cell(id).then(|result| match result {
+        Ok(Ok(cell)) => Ok(Some(cell)),
+        Ok(Err(ReadError::CellDoesNotExisted)) => Ok(None),
+        Ok(Err(e)) => Err(SnapshotError::ReadError(e)),
+        Err(e) => Err(SnapshotError::RPCError(e))
+    })
+}
+
+impl ReadTransaction {
+    fn new(neb_client: &Arc<NebClient>, schemas: &Arc<SchemaContainer>) -> ReadTransaction {
+        ReadTransaction {
+            neb_client: neb_client.clone(),
+            schemas: schemas.clone(),
+            cells: RefCell::new(HashMap::new())
+        }
+    }
+
+    fn read_cell(&self, id: &Id) -> Result<Option<Cell>, SnapshotError> {
+        Ok(self.read_cells(&[*id])?.pop().unwrap())
+    }
+
+    // cells not read before are fetched concurrently, results keep the order of the ids
+    fn read_cells(&self, ids: &[Id]) -> Result<Vec<Option<Cell>>, SnapshotError> {
+        let missing: Vec<Id> = {
+            let cells = self.cells.borrow();
+            let mut requested = HashSet::new();
+            let missing = ids.iter().filter(|id| !cells.contains_key(id) && requested.insert(**id)).cloned().collect();
+            missing
+        };
+        if !missing.is_empty() {
+            let fetched = future::join_all(missing.iter().map(|&id| fetch(&self.neb_client, id)).collect::<Vec<_>>()).wait()?;
+            let mut cells = self.cells.borrow_mut();
+            for (id, cell) in missing.into_iter().zip(fetched) {
+                cells.insert(id, cell);
+            }
+        }
+        let cells = self.cells.borrow();
+        let read = ids.iter().map(|id| cells[id].clone()).collect();
+        Ok(read)
+    }
+
+    // every cell read is read again at once, the future holds no borrow of the transaction
+    fn validate(&self) -> impl Future<Item = bool, Error = SnapshotError> {
+        let checks: Vec<_> = self.cells.borrow().iter().map(|(id, seen)| {
+            let seen = seen.as_ref().map(|cell| cell.header.version);
+            fetch(&self.neb_client, *id).map(move |current| current.map(|cell| cell.header.version) == seen)
+        }).collect();
+        future::join_all(checks).map(|checks| checks.into_iter().all(|unchanged| unchanged))
+    }
+
+    pub fn read_vertex<V>(&self, vertex: V) -> Result<Option<Vertex>, SnapshotError> where V: ToVertexId {
+        Ok(self.read_cell(&vertex.to_id())?.map(|cell| encryption::open_vertex(&self.schemas, vertex::cell_to_vertex(cell))))
+    }
+
+    // reads the vertices in one batch, in the order given
+    pub fn read_vertices<V>(&self, vertices: Vec<V>) -> Result<Vec<Option<Vertex>>, SnapshotError> where V: ToVertexId {
+        let ids: Vec<Id> = vertices.into_iter().map(|v| v.to_id()).collect();
+        Ok(self.read_cells(&ids)?.into_iter()
+            .map(|cell| cell.map(|cell| encryption::open_vertex(&self.schemas, vertex::cell_to_vertex(cell))))
+            .collect())
+    }
+
+    pub fn vertex_exists<V>(&self, vertex: V) -> Result<bool, SnapshotError> where V: ToVertexId {
+        Ok(self.read_cell(&vertex.to_id())?.is_some())
+    }
+
+    fn adjacent_ids(&self, vertex_id: &Id, vertex_field: u64, schema_id: u32)
+        -> Result<Result<Vec<Id>, EdgeError>, SnapshotError>
+    {
+        let read = |id: &Id| self.read_cell(id);
+        Ok(id_list::read_ids(&read, vertex_id, vertex_field, schema_id)?.map_err(EdgeError::IdListError))
+    }
+
+    // counts list entries, an undirected self-loop stored once counts once here
+    pub fn degree<V, S>(&self, vertex: V, schema: S, ed: EdgeDirection)
+        -> Result<Result<usize, EdgeError>, SnapshotError>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        let schema_id = schema.to_id(&self.schemas);
+        let vertex_id = vertex.to_id();
+        let mut degree = 0;
+        for (_, vertex_field) in ed.for_schema(schema_id, &self.schemas).expand() {
+            match self.adjacent_ids(&vertex_id, vertex_field, schema_id)? {
+                Ok(ids) => degree += ids.len(), Err(e) => return Ok(Err(e))
+            }
+        }
+        Ok(Ok(degree))
+    }
+
+    pub fn edges<V, S>(&self, vertex: V, schema: S, ed: EdgeDirection, filter: &Option<Vec<SExpr>>)
+        -> Result<Result<Vec<edge::Edge>, EdgeError>, SnapshotError>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        let schema_id = schema.to_id(&self.schemas);
+        let vertex_id = &vertex.to_id();
+        let ed = ed.for_schema(schema_id, &self.schemas);
+        let mut edges = Vec::new();
+        for (part, vertex_field) in ed.expand() {
+            let ids = match self.adjacent_ids(vertex_id, vertex_field, schema_id)? {
+                Ok(ids) => ids, Err(e) => return Ok(Err(e))
+            };
+            let trace_cells = self.read_cells(&ids)?;
+            for (id, trace_cell) in ids.into_iter().zip(trace_cells) {
+                let trace_cell = match trace_cell {
+                    Some(cell) => cell, None => return Ok(Err(EdgeError::CellNotFound))
+                };
+                let edge = match edge::from_trace_cell(vertex_id, vertex_field, schema_id, &self.schemas, &id, trace_cell) {
+                    Ok(edge) => edge, Err(e) => return Ok(Err(e))
+                };
+                if edge.one_opposite_id_vertex_id(vertex_id).map(|o| ed.repeats(part, vertex_id, o)) == Some(true) {
+                    continue;
+                }
+                match Tester::eval_with_edge(filter, &edge) {
+                    Ok(true) => edges.push(edge),
+                    Ok(false) => {},
+                    Err(err) => return Ok(Err(EdgeError::FilterEvalError(err)))
+                }
+            }
+        }
+        Ok(Ok(edges))
+    }
+
+    pub fn neighbourhoods<V, S>(&self, vertex: V, schema: S, ed: EdgeDirection, filter: &Option<Vec<SExpr>>)
+        -> Result<Result<Vec<(Vertex, edge::Edge)>, NeighbourhoodError>, SnapshotError>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        let vertex_id = &vertex.to_id();
+        let edges = match self.edges(vertex_id, schema, ed, &None)? {
+            Ok(edges) => edges, Err(e) => return Ok(Err(NeighbourhoodError::EdgeError(e)))
+        };
+        let mut opposite_ids = Vec::with_capacity(edges.len());
+        for edge in &edges {
+            match edge.one_opposite_id_vertex_id(vertex_id) {
+                Some(id) => opposite_ids.push(*id),
+                None => return Ok(Err(NeighbourhoodError::CannotFindOppositeId(*vertex_id)))
+            }
+        }
+        let opposites = self.read_vertices(opposite_ids.clone())?;
+        let mut result = Vec::with_capacity(edges.len());
+        for ((edge, opposite_id), vertex) in edges.into_iter().zip(opposite_ids).zip(opposites) {
+            let vertex = match vertex {
+                Some(vertex) => vertex, None => return Ok(Err(NeighbourhoodError::VertexNotFound(opposite_id)))
+            };
+            match Tester::eval_with_edge_and_vertex(filter, &vertex, &edge) {
+                Ok(true) => result.push((vertex, edge)),
+                Ok(false) => {},
+                Err(err) => return Ok(Err(NeighbourhoodError::FilterEvalError(err)))
+            }
+        }
+        Ok(Ok(result))
+    }
+}
+
+pub fn run<TFN, TR>(neb_client: Arc<NebClient>, schemas: Arc<SchemaContainer>, func: TFN)
+    -> impl Future<Item = TR, Error = SnapshotError>
+    where TFN: Fn(&ReadTransaction) -> Result<TR, SnapshotError>
+{
+    future::loop_fn(1, move |attempt| {
+        let txn = ReadTransaction::new(&neb_client, &schemas);
+        let result = match func(&txn) {
+            Ok(result) => result, Err(e) => return future::Either::A(future::err(e))
+        };
+        future::Either::B(txn.validate().and_then(move |unchanged| {
+            if unchanged {
+                Ok(Loop::Break(result))
+            } else if attempt >= SNAPSHOT_ATTEMPTS {
+                Err(SnapshotError::Unstable(SNAPSHOT_ATTEMPTS))
+            } else {
+                Ok(Loop::Continue(attempt + 1))
+            }
+        }))
+    })
+}
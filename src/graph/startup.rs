@@ -0,0 +1,112 @@
+// Checks the schemas stored in neb against the ones this binary writes before a graph opens.
+// Base schemas missing from neb are created, as on a fresh cluster. Base schemas that exist and the
+// template fields leading every vertex and edge schema must match field by field: name, type,
+// nullability, arrays and sub fields. A stored schema lacking a nullable field this binary adds
+// cannot be opened as it is either, neb would drop the field's values on every write, but it can be
+// migrated, see graph::migrate. Any difference refuses to open the graph with what was found, so a
+// mismatched cluster fails at boot instead of losing data at its first write.
+
+use neb::ram::schema::Field;
+use bifrost::raft::state_machine::master::ExecError;
+
+use graph::edge::{self, EdgeType};
+use graph::migrate::MigrationError;
+use graph::fields::VERTEX_TEMPLATE;
+use server::schema::{MorpheusSchema, SchemaType};
+
+#[derive(Debug)]
+pub enum StartupError {
+    ExecError(ExecError),
+    // what differs between the stored schemas and this binary
+    Incompatible(Vec<String>),
+    // fields this binary writes that stored schemas lack, a migration adds them
+    NeedsMigration(Vec<String>),
+    MigrationError(MigrationError)
+}
+
+fn describe(field: &Field) -> String {
+    format!("type {}{}{}", field.type_id, if field.is_array { " array" } else { "" }, if field.nullable { " nullable" } else { "" })
+}
+
+// Compares the fields expected by the binary with those stored. Notes are the nullable fields a
+// migration would add, problems cannot be migrated. Only the leading fields are compared when
+// prefix is set, the rest belong to the schema.
+pub fn compare_fields(
+    path: &str, expected: &[Field], stored: &[Field], prefix: bool, problems: &mut Vec<String>, notes: &mut Vec<String>
+) {
+    let mut stored_fields = stored.iter().peekable();
+    for field in expected {
+        let name = format!("{}.{}", path, field.name);
+        // templates are kept in order, so leading fields line up one to one
+        let found = if prefix {
+            let lines_up = stored_fields.peek().map_or(false, |stored| stored.name == field.name);
+            if lines_up { stored_fields.next() } else { None }
+        } else {
+            stored.iter().find(|stored| stored.name == field.name)
+        };
+        let found = match found {
+            Some(found) => found,
+            None if field.nullable => {
+                notes.push(format!("{} is not stored, its values would be dropped on write", name));
+                continue;
+            },
+            None => {
+                problems.push(format!("{} is missing", name));
+                continue;
+            }
+        };
+        if found.type_id != field.type_id || found.is_array != field.is_array || found.nullable != field.nullable {
+            problems.push(format!("{} is stored as {}, expected {}", name, describe(found), describe(field)));
+            continue;
+        }
+        match (&field.sub_fields, &found.sub_fields) {
+            (&Some(ref expected), &Some(ref stored)) => compare_fields(&name, expected, stored, false, problems, notes),
+            (&None, &None) => {},
+            _ => problems.push(format!("{} differs in sub fields", name))
+        }
+    }
+    if !prefix {
+        for field in stored.iter().filter(|stored| !expected.iter().any(|field| field.name == stored.name)) {
+            problems.push(format!("{}.{} is not known to this binary", path, field.name));
+        }
+    }
+}
+
+// fails on any difference, only those that are all notes can be migrated
+pub fn conclude(mut problems: Vec<String>, notes: Vec<String>) -> Result<(), StartupError> {
+    match (problems.is_empty(), notes.is_empty()) {
+        (true, true) => Ok(()),
+        (true, false) => Err(StartupError::NeedsMigration(notes)),
+        (false, _) => {
+            problems.extend(notes);
+            Err(StartupError::Incompatible(problems))
+        }
+    }
+}
+
+pub fn check_base_schema(name: &str, expected: &Field, stored: &Field, problems: &mut Vec<String>, notes: &mut Vec<String>) {
+    match (&expected.sub_fields, &stored.sub_fields) {
+        (&Some(ref expected), &Some(ref stored)) => compare_fields(name, expected, stored, false, problems, notes),
+        _ => problems.push(format!("{} is not a map of fields", name))
+    }
+}
+
+// the template fields every cell of the schema starts with
+pub fn template(schema_type: SchemaType) -> Option<&'static Vec<Field>> {
+    match schema_type {
+        SchemaType::Vertex => Some(&*VERTEX_TEMPLATE),
+        SchemaType::Edge(attributes) => Some(match attributes.edge_type {
+            EdgeType::Directed => &*edge::directed::EDGE_TEMPLATE,
+            EdgeType::Undirected => &*edge::undirectd::EDGE_TEMPLATE
+        }),
+        SchemaType::Unspecified => None
+    }
+}
+
+pub fn check_template(schema: &MorpheusSchema, stored: &Field, problems: &mut Vec<String>, notes: &mut Vec<String>) {
+    let expected = match template(schema.schema_type) { Some(expected) => expected, None => return };
+    match stored.sub_fields {
+        Some(ref stored) => compare_fields(&schema.name, expected, stored, true, problems, notes),
+        None => problems.push(format!("{} has no fields", schema.name))
+    }
+}
@@ -0,0 +1,123 @@
+// Self-contained subgraphs for algorithms and export. Vertices are numbered in the order they were
+// reached and edges refer to them by that local index, so the result can be walked without the graph.
+// Directed edge schemas are followed both ways, the filter decides which vertices and edges join.
+
+use neb::ram::types::Id;
+use neb::dovahkiin::expr::SExpr;
+use neb::client::transaction::TxnError;
+
+use std::collections::{HashMap, HashSet};
+
+use graph::{GraphTransaction, EdgeDirection, NeighbourhoodError, edge_attr_from_schema};
+use graph::edge::{self, EdgeType};
+use graph::vertex::{Vertex, ToVertexId};
+
+#[derive(Debug, Clone)]
+pub struct SubgraphEdge {
+    // local indexes into Subgraph::vertices, from is the outbound end of directed edges
+    pub from: usize,
+    pub to: usize,
+    pub schema: u32,
+    pub edge: edge::Edge
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Subgraph {
+    pub vertices: Vec<Vertex>,
+    pub edges: Vec<SubgraphEdge>,
+    index: HashMap<Id, usize>
+}
+
+// identifies an edge found from both of its ends, edges without a body are told apart by their ends only
+#[derive(Hash, PartialEq, Eq)]
+struct EdgeKey(u32, usize, usize, Option<Id>);
+
+impl Subgraph {
+    pub fn index_of(&self, id: &Id) -> Option<usize> {
+        self.index.get(id).cloned()
+    }
+
+    pub fn vertex(&self, id: &Id) -> Option<&Vertex> {
+        self.index_of(id).map(|i| &self.vertices[i])
+    }
+
+    // edges touching the vertex at this local index
+    pub fn incident_edges<'a>(&'a self, index: usize) -> impl Iterator<Item = &'a SubgraphEdge> + 'a {
+        self.edges.iter().filter(move |e| e.from == index || e.to == index)
+    }
+
+    fn add_vertex(&mut self, vertex: Vertex) -> usize {
+        let id = vertex.cell.id();
+        if let Some(index) = self.index.get(&id) {
+            return *index;
+        }
+        let index = self.vertices.len();
+        self.vertices.push(vertex);
+        self.index.insert(id, index);
+        index
+    }
+}
+
+impl <'a> GraphTransaction<'a> {
+    // Vertices within depth hops of the start set over the edge schemas and every edge among them.
+    pub fn subgraph(&self, start: &[Id], edge_schemas: &[u32], depth: usize, filter: &Option<Vec<SExpr>>)
+        -> Result<Result<Subgraph, NeighbourhoodError>, TxnError>
+    {
+        let mut directions = Vec::new();
+        for schema_id in edge_schemas {
+            let edge_attr = match edge_attr_from_schema(*schema_id, &self.schemas) {
+                Ok((_, edge_attr)) => edge_attr, Err(e) => return Ok(Err(NeighbourhoodError::EdgeError(e)))
+            };
+            match edge_attr.edge_type {
+                EdgeType::Directed => {
+                    directions.push((*schema_id, EdgeDirection::Outbound));
+                    directions.push((*schema_id, EdgeDirection::Inbound));
+                },
+                EdgeType::Undirected => directions.push((*schema_id, EdgeDirection::Undirected))
+            }
+        }
+        let mut subgraph = Subgraph::default();
+        let mut frontier = Vec::with_capacity(start.len());
+        for id in start {
+            match self.read_vertex(id)? {
+                Some(vertex) => frontier.push(subgraph.add_vertex(vertex)),
+                None => return Ok(Err(NeighbourhoodError::VertexNotFound(*id)))
+            }
+        }
+        let mut seen_edges = HashSet::new();
+        // the last round only collects edges between vertices already in the subgraph
+        for hop in 0..depth + 1 {
+            let mut next = Vec::new();
+            for index in frontier {
+                let vertex_id = subgraph.vertices[index].cell.id();
+                for &(schema_id, ed) in &directions {
+                    let neighbours = match self.neighbourhoods(vertex_id, schema_id, ed, filter)? {
+                        Ok(neighbours) => neighbours, Err(e) => return Ok(Err(e))
+                    };
+                    for (vertex, edge) in neighbours {
+                        let opposite = match subgraph.index_of(&vertex.cell.id()) {
+                            Some(opposite) => opposite,
+                            None if hop < depth => {
+                                let opposite = subgraph.add_vertex(vertex);
+                                next.push(opposite);
+                                opposite
+                            },
+                            None => continue
+                        };
+                        let (from, to) = match ed {
+                            EdgeDirection::Inbound => (opposite, index),
+                            _ => (index, opposite)
+                        };
+                        let ends = if ed == EdgeDirection::Undirected && from > to { (to, from) } else { (from, to) };
+                        let body = edge.get_data().as_ref().map(|cell| cell.id());
+                        if seen_edges.insert(EdgeKey(schema_id, ends.0, ends.1, body)) {
+                            subgraph.edges.push(SubgraphEdge { from, to, schema: schema_id, edge });
+                        }
+                    }
+                }
+            }
+            frontier = next;
+        }
+        Ok(Ok(subgraph))
+    }
+}
@@ -0,0 +1,165 @@
+// Blocking wrappers over Graph for scripts, tests and tools that would rather not handle futures.
+// Each call waits for its future on the calling thread while neb's client threads drive the RPCs,
+// so calls must not be made from code already running on those threads. Results come as one
+// morpheus::Error with the context graph::unified attaches; streams are collected.
+
+use neb::ram::types::Id;
+use neb::dovahkiin::types::{Map, ToValue};
+use neb::client::transaction::TxnError;
+use futures::prelude::*;
+
+use error;
+use graph::{self as graph_mod, GraphTransaction, EdgeDirection, CountMode, CountEstimate};
+use graph::unified;
+use graph::vertex::{Vertex, ToVertexId};
+use graph::edge::{Edge, EdgeAttributes};
+use graph::subgraph::Subgraph;
+use graph::traverse::{TraverseOptions, Neighbour};
+use graph::tree::Tree;
+use server::schema::{MorpheusSchema, ToSchemaId};
+use server::path_pattern::Path;
+use query::Expr;
+
+pub struct Graph {
+    graph: unified::Graph
+}
+
+impl Graph {
+    pub fn new(graph: &graph_mod::Graph) -> Graph {
+        Graph { graph: unified::Graph::new(graph) }
+    }
+    // the asynchronous graph, for what is not wrapped here
+    pub fn graph(&self) -> &graph_mod::Graph {
+        self.graph.graph()
+    }
+
+    pub fn new_vertex_group(&self, schema: MorpheusSchema) -> error::Result<u32> {
+        self.graph.new_vertex_group(schema).wait()
+    }
+    pub fn new_edge_group(&self, schema: MorpheusSchema, edge_attrs: EdgeAttributes) -> error::Result<u32> {
+        self.graph.new_edge_group(schema, edge_attrs).wait()
+    }
+
+    pub fn new_vertex<S>(&self, schema: S, data: Map) -> error::Result<Vertex> where S: ToSchemaId {
+        self.graph.new_vertex(schema, data).wait()
+    }
+    pub fn vertex_by<V>(&self, vertex: V) -> error::Result<Option<Vertex>> where V: ToVertexId {
+        self.graph.vertex_by(vertex).wait()
+    }
+    pub fn vertex_by_key<K, S>(&self, schema: S, key: K) -> error::Result<Option<Vertex>>
+        where K: ToValue, S: ToSchemaId
+    {
+        self.graph.vertex_by_key(schema, key).wait()
+    }
+    pub fn vertices_by<V>(&self, vertices: Vec<V>) -> error::Result<Vec<Option<Vertex>>> where V: ToVertexId {
+        self.graph.vertices_by(vertices).wait()
+    }
+    pub fn vertex_exists<V>(&self, vertex: V) -> error::Result<bool> where V: ToVertexId {
+        self.graph.vertex_exists(vertex).wait()
+    }
+    pub fn update_vertex<V, U>(&self, vertex: V, update: U) -> error::Result<()>
+        where V: ToVertexId, U: Fn(Vertex) -> Option<Vertex>, U: 'static
+    {
+        self.graph.update_vertex(vertex, update).wait()
+    }
+    pub fn update_vertex_by_key<K, U, S>(&self, schema: S, key: K, update: U) -> error::Result<()>
+        where K: ToValue, S: ToSchemaId, U: Fn(Vertex) -> Option<Vertex>, U: 'static
+    {
+        self.graph.update_vertex_by_key(schema, key, update).wait()
+    }
+    pub fn remove_vertex<V>(&self, vertex: V) -> error::Result<()> where V: ToVertexId {
+        self.graph.remove_vertex(vertex).wait()
+    }
+    pub fn remove_vertex_by_key<K, S>(&self, schema: S, key: K) -> error::Result<()>
+        where K: ToValue, S: ToSchemaId
+    {
+        self.graph.remove_vertex_by_key(schema, key).wait()
+    }
+
+    pub fn link<V, S>(&self, from: V, schema: S, to: V, body: Option<Map>) -> error::Result<Edge>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        self.graph.link(from, schema, to, body).wait()
+    }
+    pub fn has_edge<V, S>(&self, from: V, schema: S, to: V) -> error::Result<bool>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        self.graph.has_edge(from, schema, to).wait()
+    }
+    pub fn degree<V, S>(&self, vertex: V, schema: S, direction: EdgeDirection) -> error::Result<usize>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        self.graph.degree(vertex, schema, direction).wait()
+    }
+    pub fn edges<V, S, F>(&self, vertex: V, schema: S, direction: EdgeDirection, filter: &Option<F>)
+        -> error::Result<Vec<Edge>>
+        where V: ToVertexId, S: ToSchemaId, F: Expr
+    {
+        self.graph.edges(vertex, schema, direction, filter).wait()
+    }
+    pub fn neighbourhoods<V, S, F>(&self, vertex: V, schema: S, direction: EdgeDirection, filter: &Option<F>)
+        -> error::Result<Vec<(Vertex, Edge)>>
+        where V: ToVertexId, S: ToSchemaId, F: Expr
+    {
+        self.graph.neighbourhoods(vertex, schema, direction, filter).wait()
+    }
+    pub fn traverse_neighbours<V>(&self, vertex: V, options: TraverseOptions) -> error::Result<Vec<Neighbour>>
+        where V: ToVertexId
+    {
+        self.graph.traverse_neighbours(vertex, options).wait()
+    }
+    pub fn subgraph<V, S, F>(&self, start: Vec<V>, edge_schemas: Vec<S>, depth: usize, filter: &Option<F>)
+        -> error::Result<Subgraph>
+        where V: ToVertexId, S: ToSchemaId, F: Expr
+    {
+        self.graph.subgraph(start, edge_schemas, depth, filter).wait()
+    }
+    pub fn reachable_path<V, S>(&self, from: V, to: V, edge_schemas: Vec<S>, max_depth: usize)
+        -> error::Result<Option<Vec<Id>>>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        self.graph.reachable_path(from, to, edge_schemas, max_depth).wait()
+    }
+    pub fn k_shortest_paths<V, S>(&self, from: V, to: V, edge_schema: S, k: usize, weight_field: Option<&str>)
+        -> error::Result<Vec<Path>>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        self.graph.k_shortest_paths(from, to, edge_schema, k, weight_field).wait()
+    }
+    pub fn descendants<V, S>(&self, root: V, edge_schema: S, max_depth: usize) -> error::Result<Tree>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        self.graph.descendants(root, edge_schema, max_depth).wait()
+    }
+    pub fn ancestors<V, S>(&self, vertex: V, edge_schema: S, max_depth: usize) -> error::Result<Tree>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        self.graph.ancestors(vertex, edge_schema, max_depth).wait()
+    }
+
+    pub fn scan_vertices<S, F>(&self, schema: S, filter: &Option<F>, projection: Option<Vec<String>>)
+        -> error::Result<Vec<Vertex>>
+        where S: ToSchemaId, F: Expr
+    {
+        self.graph.scan_vertices(schema, filter, projection).collect().wait()
+    }
+    pub fn scan_edges<S, F>(&self, schema: S, filter: &Option<F>) -> error::Result<Vec<Edge>>
+        where S: ToSchemaId, F: Expr
+    {
+        self.graph.scan_edges(schema, filter).collect().wait()
+    }
+    pub fn count_vertices<S, F>(&self, schema: S, filter: &Option<F>, mode: CountMode) -> error::Result<CountEstimate>
+        where S: ToSchemaId, F: Expr
+    {
+        self.graph.count_vertices(schema, filter, mode).wait()
+    }
+    pub fn count_edges<S>(&self, schema: S, mode: CountMode) -> error::Result<CountEstimate> where S: ToSchemaId {
+        self.graph.count_edges(schema, mode).wait()
+    }
+
+    pub fn graph_transaction<TFN, TR>(&self, func: TFN) -> Result<TR, TxnError>
+        where TFN: Fn(&GraphTransaction) -> Result<TR, TxnError>, TR: 'static, TFN: 'static
+    {
+        self.graph.graph().graph_transaction(func).wait()
+    }
+}
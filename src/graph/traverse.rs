@@ -0,0 +1,161 @@
+// One traversal call configured by TraverseOptions, instead of picking among the edges and
+// neighbourhoods variants. Opposite vertices are read only when they are returned, sorted on or
+// referred to by the filter, all of them at once.
+
+use neb::ram::types::Id;
+use neb::dovahkiin::types::Value;
+use neb::dovahkiin::expr::SExpr;
+use neb::client::transaction::TxnError;
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use graph::{GraphTransaction, EdgeDirection, NeighbourhoodError};
+use graph::edge;
+use graph::multi_schema::EdgeSchemas;
+use graph::vertex::{self, Vertex, ToVertexId};
+use query::{self, Tester};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    Ascending,
+    Descending
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraverseSort {
+    VertexField(String, SortOrder),
+    EdgeField(String, SortOrder)
+}
+
+#[derive(Debug, Clone)]
+pub struct TraverseOptions {
+    pub direction: EdgeDirection,
+    pub schemas: EdgeSchemas,
+    // lisp expression over vertex and edge
+    pub filter: Option<String>,
+    // vertex fields to keep, None keeps everything
+    pub projection: Option<Vec<String>>,
+    pub sort: Option<TraverseSort>,
+    // applied after sorting
+    pub limit: Option<usize>,
+    pub include_vertices: bool
+}
+
+impl Default for TraverseOptions {
+    fn default() -> TraverseOptions {
+        TraverseOptions {
+            direction: EdgeDirection::Both,
+            schemas: EdgeSchemas::Any,
+            filter: None,
+            projection: None,
+            sort: None,
+            limit: None,
+            include_vertices: true
+        }
+    }
+}
+
+impl TraverseOptions {
+    fn reads_vertices(&self, filter: &Option<Vec<SExpr>>) -> bool {
+        self.include_vertices || query::refers_to_vertex(filter) || match self.sort {
+            Some(TraverseSort::VertexField(_, _)) => true,
+            _ => false
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Neighbour {
+    pub id: Id,
+    pub edge: edge::Edge,
+    // None unless include_vertices is set
+    pub vertex: Option<Vertex>
+}
+
+// numbers compare by value whatever their width, null and other kinds sort last
+fn cmp_values(a: &Value, b: &Value) -> Ordering {
+    fn number(value: &Value) -> Option<f64> {
+        match value {
+            &Value::I8(n) => Some(n as f64), &Value::I16(n) => Some(n as f64),
+            &Value::I32(n) => Some(n as f64), &Value::I64(n) => Some(n as f64),
+            &Value::U8(n) => Some(n as f64), &Value::U16(n) => Some(n as f64),
+            &Value::U32(n) => Some(n as f64), &Value::U64(n) => Some(n as f64),
+            &Value::F32(n) => Some(n as f64), &Value::F64(n) => Some(n),
+            _ => None
+        }
+    }
+    match (number(a), number(b), a, b) {
+        (Some(x), Some(y), _, _) => x.partial_cmp(&y).unwrap_or(Ordering::Equal),
+        (_, _, &Value::String(ref x), &Value::String(ref y)) => x.cmp(y),
+        (Some(_), None, _, _) => Ordering::Less,
+        (None, Some(_), _, _) => Ordering::Greater,
+        (_, _, &Value::String(_), _) => Ordering::Less,
+        (_, _, _, &Value::String(_)) => Ordering::Greater,
+        _ => Ordering::Equal
+    }
+}
+
+impl <'a> GraphTransaction<'a> {
+    pub fn traverse_neighbours<V>(&self, vertex: V, options: &TraverseOptions, filter: &Option<Vec<SExpr>>)
+        -> Result<Result<Vec<Neighbour>, NeighbourhoodError>, TxnError>
+        where V: ToVertexId
+    {
+        let vertex_id = &vertex.to_id();
+        self.watch.touch("traverse_neighbours", None, *vertex_id)?;
+        let resolved = match self.resolve_edge_schemas(*vertex_id, &options.schemas, options.direction)? {
+            Ok(resolved) => resolved, Err(e) => return Ok(Err(NeighbourhoodError::EdgeError(e)))
+        };
+        let mut edges = Vec::new();
+        for (schema_id, direction) in resolved {
+            match self.neighbour_edges_in_context(vertex_id, schema_id, direction, filter)? {
+                Ok(schema_edges) => edges.extend(schema_edges), Err(e) => return Ok(Err(e))
+            }
+        }
+        let vertices: HashMap<Id, Vertex> = if options.reads_vertices(filter) {
+            match self.opposite_vertices(&edges)? {
+                Ok(vertices) => vertices, Err(e) => return Ok(Err(e))
+            }
+        } else {
+            HashMap::new()
+        };
+        let mut neighbours = Vec::with_capacity(edges.len());
+        for (id, edge, context) in edges {
+            let vertex = vertices.get(&id).cloned();
+            match Tester::eval_in_context(filter, vertex.as_ref(), &edge, &context) {
+                Ok(true) => neighbours.push(Neighbour { id, edge, vertex }),
+                Ok(false) => {},
+                Err(err) => return Ok(Err(NeighbourhoodError::FilterEvalError(err)))
+            }
+        }
+        if let Some(ref sort) = options.sort {
+            // sort_by is stable, equal keys keep list order
+            neighbours.sort_by(|a, b| {
+                let (ordering, order) = match sort {
+                    &TraverseSort::EdgeField(ref field, order) =>
+                        (cmp_values(&a.edge[field.as_str()], &b.edge[field.as_str()]), order),
+                    &TraverseSort::VertexField(ref field, order) => {
+                        let a_vertex = a.vertex.as_ref().unwrap();
+                        let b_vertex = b.vertex.as_ref().unwrap();
+                        (cmp_values(&a_vertex[field.as_str()], &b_vertex[field.as_str()]), order)
+                    }
+                };
+                match order {
+                    SortOrder::Ascending => ordering,
+                    SortOrder::Descending => ordering.reverse()
+                }
+            });
+        }
+        if let Some(limit) = options.limit {
+            neighbours.truncate(limit);
+        }
+        for neighbour in &mut neighbours {
+            neighbour.vertex = if options.include_vertices {
+                neighbour.vertex.take().map(|v| vertex::project(v, &options.projection))
+            } else {
+                None
+            };
+        }
+        Ok(Ok(neighbours))
+    }
+}
@@ -0,0 +1,160 @@
+// Tree shaped data like org charts and category trees. Directed edges of the schema point from
+// parent to child, descendants follow them outbound and ancestors inbound, undirected schemas are
+// followed either way by both. Every vertex joins once, at the depth it is first reached from, and
+// an edge to a vertex already in the tree is kept as a revisit instead of being followed, so cycles
+// and vertices with more than one parent end the walk there.
+
+use neb::ram::types::{Id, key_hash};
+use neb::dovahkiin::types::Value;
+use neb::client::transaction::TxnError;
+
+use std::collections::HashMap;
+
+use graph::{GraphTransaction, EdgeDirection, NeighbourhoodError, edge_attr_from_schema};
+use graph::edge::EdgeType;
+use graph::vertex::Vertex;
+
+#[derive(Debug, Clone)]
+pub struct TreeNode {
+    pub vertex: Vertex,
+    // hops from the start, which is at 0
+    pub depth: usize,
+    // index into Tree::nodes of the vertex it was reached from, None for the start
+    pub parent: Option<usize>
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Tree {
+    // breadth first, every node comes after its parent
+    pub nodes: Vec<TreeNode>,
+    // edges from a node to one reached before, as indexes into nodes
+    pub revisits: Vec<(usize, usize)>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubtreeAggregate {
+    Count,
+    // over a numeric vertex field, vertices without it are left out
+    Sum(String),
+    Min(String),
+    Max(String)
+}
+
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        &Value::I8(n) => Some(n as f64), &Value::I16(n) => Some(n as f64),
+        &Value::I32(n) => Some(n as f64), &Value::I64(n) => Some(n as f64),
+        &Value::U8(n) => Some(n as f64), &Value::U16(n) => Some(n as f64),
+        &Value::U32(n) => Some(n as f64), &Value::U64(n) => Some(n as f64),
+        &Value::F32(n) => Some(n as f64), &Value::F64(n) => Some(n),
+        _ => None
+    }
+}
+
+impl Tree {
+    pub fn ids(&self) -> Vec<Id> {
+        self.nodes.iter().map(|node| node.vertex.cell.id()).collect()
+    }
+
+    pub fn children(&self, index: usize) -> Vec<usize> {
+        (index + 1..self.nodes.len()).filter(|&i| self.nodes[i].parent == Some(index)).collect()
+    }
+
+    // the indexes from the node up to the start, the node first
+    pub fn path_to_root(&self, index: usize) -> Vec<usize> {
+        let mut path = vec![index];
+        let mut current = index;
+        while let Some(parent) = self.nodes[current].parent {
+            path.push(parent);
+            current = parent;
+        }
+        path
+    }
+
+    // revisits leading back to a node on the way from the start, the rest are second parents
+    pub fn cycles(&self) -> Vec<(usize, usize)> {
+        self.revisits.iter().cloned().filter(|&(from, to)| self.path_to_root(from).contains(&to)).collect()
+    }
+
+    // The aggregate over the subtree of every node, the node included, indexed like nodes.
+    // None where the subtree has no vertex with the field.
+    pub fn aggregate(&self, aggregate: &SubtreeAggregate) -> Vec<Option<f64>> {
+        let own = |node: &TreeNode| -> Option<f64> {
+            match aggregate {
+                &SubtreeAggregate::Count => Some(1.0),
+                &SubtreeAggregate::Sum(ref field) |
+                &SubtreeAggregate::Min(ref field) |
+                &SubtreeAggregate::Max(ref field) => match node.vertex.cell.data {
+                    Value::Map(ref map) => number(map.get_by_key_id(key_hash(field))),
+                    _ => None
+                }
+            }
+        };
+        let combine = |a: Option<f64>, b: Option<f64>| -> Option<f64> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(match aggregate {
+                    &SubtreeAggregate::Count | &SubtreeAggregate::Sum(_) => a + b,
+                    &SubtreeAggregate::Min(_) => a.min(b),
+                    &SubtreeAggregate::Max(_) => a.max(b)
+                }),
+                (a, None) => a,
+                (None, b) => b
+            }
+        };
+        let mut results: Vec<Option<f64>> = self.nodes.iter().map(|node| own(node)).collect();
+        // children come after their parents, so folding from the back finishes every subtree first
+        for index in (1..self.nodes.len()).rev() {
+            if let Some(parent) = self.nodes[index].parent {
+                results[parent] = combine(results[parent], results[index]);
+            }
+        }
+        results
+    }
+}
+
+impl <'a> GraphTransaction<'a> {
+    // Vertices within max_depth hops from the start following the edge schema, parents to children
+    // when towards_children is set and children to parents otherwise.
+    pub fn tree(&self, start: Id, edge_schema: u32, towards_children: bool, max_depth: usize)
+        -> Result<Result<Tree, NeighbourhoodError>, TxnError>
+    {
+        let edge_attr = match edge_attr_from_schema(edge_schema, &self.schemas) {
+            Ok((_, edge_attr)) => edge_attr, Err(e) => return Ok(Err(NeighbourhoodError::EdgeError(e)))
+        };
+        let direction = match edge_attr.edge_type {
+            EdgeType::Undirected => EdgeDirection::Undirected,
+            EdgeType::Directed if towards_children => EdgeDirection::Outbound,
+            EdgeType::Directed => EdgeDirection::Inbound
+        };
+        let root = match self.read_vertex(start)? {
+            Some(vertex) => vertex, None => return Ok(Err(NeighbourhoodError::VertexNotFound(start)))
+        };
+        let mut tree = Tree::default();
+        let mut index = HashMap::new();
+        index.insert(start, 0);
+        tree.nodes.push(TreeNode { vertex: root, depth: 0, parent: None });
+        let mut next = 0;
+        while next < tree.nodes.len() {
+            let (current, depth) = (next, tree.nodes[next].depth);
+            next += 1;
+            if depth >= max_depth { continue; }
+            let id = tree.nodes[current].vertex.cell.id();
+            let neighbours = match self.neighbourhoods(id, edge_schema, direction, &None)? {
+                Ok(neighbours) => neighbours, Err(e) => return Ok(Err(e))
+            };
+            for (vertex, _) in neighbours {
+                let vertex_id = vertex.cell.id();
+                if let Some(&seen) = index.get(&vertex_id) {
+                    // the undirected edge back to the parent is the one just followed
+                    if direction != EdgeDirection::Undirected || tree.nodes[current].parent != Some(seen) {
+                        tree.revisits.push((current, seen));
+                    }
+                    continue;
+                }
+                index.insert(vertex_id, tree.nodes.len());
+                tree.nodes.push(TreeNode { vertex, depth: depth + 1, parent: Some(current) });
+            }
+        }
+        Ok(Ok(tree))
+    }
+}
@@ -0,0 +1,243 @@
+// Graph operations that fail with one morpheus::Error. Each call resolves the schema it is given
+// and attaches the operation, the vertex and the schema name to whatever error comes back, so
+// callers get the context without adding it themselves. Transaction results are flattened.
+// graph::sync waits on these futures, graph::compat turns them into std futures.
+
+use neb::ram::types::Id;
+use neb::dovahkiin::types::{Map, ToValue};
+use futures::prelude::*;
+
+use error::{self, Error};
+use graph::{self as graph_mod, EdgeDirection, CountMode, CountEstimate};
+use graph::vertex::{Vertex, ToVertexId};
+use graph::edge::{Edge, EdgeAttributes};
+use graph::subgraph::Subgraph;
+use graph::traverse::{TraverseOptions, Neighbour};
+use graph::tree::Tree;
+use server::schema::{MorpheusSchema, ToSchemaId};
+use server::path_pattern::Path;
+use query::Expr;
+
+#[derive(Clone)]
+struct Context {
+    operation: &'static str,
+    vertex: Option<Id>,
+    schema: Option<String>
+}
+
+impl Context {
+    fn of(&self, e: Error) -> Error {
+        let mut e = e.with_operation(self.operation);
+        if let Some(vertex) = self.vertex { e = e.with_vertex(vertex); }
+        if let Some(ref schema) = self.schema { e = e.with_schema(schema); }
+        e
+    }
+}
+
+pub struct Graph {
+    graph: graph_mod::Graph
+}
+
+impl Graph {
+    pub fn new(graph: &graph_mod::Graph) -> Graph {
+        Graph { graph: graph_mod::Graph { inner: graph.inner.clone() } }
+    }
+    pub fn graph(&self) -> &graph_mod::Graph {
+        &self.graph
+    }
+
+    fn context(&self, operation: &'static str, vertex: Option<Id>, schema_id: Option<u32>) -> Context {
+        Context { operation, vertex, schema: schema_id.and_then(|id| self.graph.schema_name(id)) }
+    }
+    fn schema_id<S>(&self, schema: S) -> u32 where S: ToSchemaId {
+        schema.to_id(&self.graph.inner.schemas)
+    }
+
+    pub fn new_vertex_group(&self, schema: MorpheusSchema) -> impl Future<Item = u32, Error = Error> {
+        let context = Context { operation: "new vertex group", vertex: None, schema: Some(schema.name.clone()) };
+        self.graph.new_vertex_group(schema).map_err(move |e| context.of(e.into()))
+    }
+    pub fn new_edge_group(&self, schema: MorpheusSchema, edge_attrs: EdgeAttributes) -> impl Future<Item = u32, Error = Error> {
+        let context = Context { operation: "new edge group", vertex: None, schema: Some(schema.name.clone()) };
+        self.graph.new_edge_group(schema, edge_attrs).map_err(move |e| context.of(e.into()))
+    }
+
+    pub fn new_vertex<S>(&self, schema: S, data: Map) -> impl Future<Item = Vertex, Error = Error> where S: ToSchemaId {
+        let schema_id = self.schema_id(schema);
+        let context = self.context("new vertex", None, Some(schema_id));
+        self.graph.new_vertex(schema_id, data).map_err(move |e| context.of(e.into()))
+    }
+    pub fn vertex_by<V>(&self, vertex: V) -> impl Future<Item = Option<Vertex>, Error = Error> where V: ToVertexId {
+        let id = vertex.to_id();
+        let context = self.context("read vertex", Some(id), None);
+        self.graph.vertex_by(id).map_err(move |e| context.of(e.into()))
+    }
+    pub fn vertex_by_key<K, S>(&self, schema: S, key: K) -> impl Future<Item = Option<Vertex>, Error = Error>
+        where K: ToValue, S: ToSchemaId
+    {
+        let schema_id = self.schema_id(schema);
+        let context = self.context("read vertex by key", None, Some(schema_id));
+        self.graph.vertex_by_key(schema_id, key).map_err(move |e| context.of(e.into()))
+    }
+    pub fn vertices_by<V>(&self, vertices: Vec<V>) -> impl Future<Item = Vec<Option<Vertex>>, Error = Error>
+        where V: ToVertexId
+    {
+        let context = self.context("read vertices", None, None);
+        self.graph.vertices_by(vertices).map_err(move |e| context.of(e.into()))
+    }
+    pub fn vertex_exists<V>(&self, vertex: V) -> impl Future<Item = bool, Error = Error> where V: ToVertexId {
+        let id = vertex.to_id();
+        let context = self.context("check vertex", Some(id), None);
+        self.graph.vertex_exists(id).map_err(move |e| context.of(e.into()))
+    }
+    pub fn update_vertex<V, U>(&self, vertex: V, update: U) -> impl Future<Item = (), Error = Error>
+        where V: ToVertexId, U: Fn(Vertex) -> Option<Vertex>, U: 'static
+    {
+        let id = vertex.to_id();
+        let context = self.context("update vertex", Some(id), None);
+        self.graph.update_vertex(id, update).map_err(move |e| context.of(e.into()))
+    }
+    pub fn update_vertex_by_key<K, U, S>(&self, schema: S, key: K, update: U) -> impl Future<Item = (), Error = Error>
+        where K: ToValue, S: ToSchemaId, U: Fn(Vertex) -> Option<Vertex>, U: 'static
+    {
+        let schema_id = self.schema_id(schema);
+        let context = self.context("update vertex by key", None, Some(schema_id));
+        self.graph.update_vertex_by_key(schema_id, key, update).map_err(move |e| context.of(e.into()))
+    }
+    pub fn remove_vertex<V>(&self, vertex: V) -> impl Future<Item = (), Error = Error> where V: ToVertexId {
+        let id = vertex.to_id();
+        let context = self.context("remove vertex", Some(id), None);
+        self.graph.remove_vertex(id).then(move |r| error::flatten(r).map_err(|e| context.of(e)))
+    }
+    pub fn remove_vertex_by_key<K, S>(&self, schema: S, key: K) -> impl Future<Item = (), Error = Error>
+        where K: ToValue, S: ToSchemaId
+    {
+        let schema_id = self.schema_id(schema);
+        let context = self.context("remove vertex by key", None, Some(schema_id));
+        self.graph.remove_vertex_by_key(schema_id, key).then(move |r| error::flatten(r).map_err(|e| context.of(e)))
+    }
+
+    pub fn link<V, S>(&self, from: V, schema: S, to: V, body: Option<Map>) -> impl Future<Item = Edge, Error = Error>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        let (from, to) = (from.to_id(), to.to_id());
+        let schema_id = self.schema_id(schema);
+        let context = self.context("link vertices", Some(from), Some(schema_id));
+        self.graph.link(from, schema_id, to, body).then(move |r| error::flatten(r).map_err(|e| context.of(e)))
+    }
+    pub fn has_edge<V, S>(&self, from: V, schema: S, to: V) -> impl Future<Item = bool, Error = Error>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        let (from, to) = (from.to_id(), to.to_id());
+        let schema_id = self.schema_id(schema);
+        let context = self.context("check edge", Some(from), Some(schema_id));
+        self.graph.has_edge(from, schema_id, to).then(move |r| error::flatten(r).map_err(|e| context.of(e)))
+    }
+    pub fn degree<V, S>(&self, vertex: V, schema: S, direction: EdgeDirection) -> impl Future<Item = usize, Error = Error>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        let id = vertex.to_id();
+        let schema_id = self.schema_id(schema);
+        let context = self.context("degree", Some(id), Some(schema_id));
+        self.graph.degree(id, schema_id, direction).then(move |r| error::flatten(r).map_err(|e| context.of(e)))
+    }
+    pub fn edges<V, S, F>(&self, vertex: V, schema: S, direction: EdgeDirection, filter: &Option<F>)
+        -> impl Future<Item = Vec<Edge>, Error = Error>
+        where V: ToVertexId, S: ToSchemaId, F: Expr
+    {
+        let id = vertex.to_id();
+        let schema_id = self.schema_id(schema);
+        let context = self.context("edges", Some(id), Some(schema_id));
+        self.graph.edges(id, schema_id, direction, filter).then(move |r| error::flatten(r).map_err(|e| context.of(e)))
+    }
+    pub fn neighbourhoods<V, S, F>(&self, vertex: V, schema: S, direction: EdgeDirection, filter: &Option<F>)
+        -> impl Future<Item = Vec<(Vertex, Edge)>, Error = Error>
+        where V: ToVertexId, S: ToSchemaId, F: Expr
+    {
+        let id = vertex.to_id();
+        let schema_id = self.schema_id(schema);
+        let context = self.context("neighbourhoods", Some(id), Some(schema_id));
+        self.graph.neighbourhoods(id, schema_id, direction, filter)
+            .then(move |r| error::flatten(r).map_err(|e| context.of(e)))
+    }
+    pub fn traverse_neighbours<V>(&self, vertex: V, options: TraverseOptions) -> impl Future<Item = Vec<Neighbour>, Error = Error>
+        where V: ToVertexId
+    {
+        let id = vertex.to_id();
+        let context = self.context("traverse neighbours", Some(id), None);
+        self.graph.traverse_neighbours(id, options).then(move |r| error::flatten(r).map_err(|e| context.of(e)))
+    }
+    pub fn subgraph<V, S, F>(&self, start: Vec<V>, edge_schemas: Vec<S>, depth: usize, filter: &Option<F>)
+        -> impl Future<Item = Subgraph, Error = Error>
+        where V: ToVertexId, S: ToSchemaId, F: Expr
+    {
+        let context = self.context("subgraph", None, None);
+        self.graph.subgraph(start, edge_schemas, depth, filter).then(move |r| error::flatten(r).map_err(|e| context.of(e)))
+    }
+    pub fn reachable_path<V, S>(&self, from: V, to: V, edge_schemas: Vec<S>, max_depth: usize)
+        -> impl Future<Item = Option<Vec<Id>>, Error = Error>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        let (from, to) = (from.to_id(), to.to_id());
+        let context = self.context("reachable path", Some(from), None);
+        self.graph.reachable_path(from, to, edge_schemas, max_depth)
+            .then(move |r| error::flatten(r).map_err(|e| context.of(e)))
+    }
+    pub fn k_shortest_paths<V, S>(&self, from: V, to: V, edge_schema: S, k: usize, weight_field: Option<&str>)
+        -> impl Future<Item = Vec<Path>, Error = Error>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        let (from, to) = (from.to_id(), to.to_id());
+        let schema_id = self.schema_id(edge_schema);
+        let context = self.context("k shortest paths", Some(from), Some(schema_id));
+        self.graph.k_shortest_paths(from, to, schema_id, k, weight_field)
+            .then(move |r| error::flatten(r).map_err(|e| context.of(e)))
+    }
+    pub fn descendants<V, S>(&self, root: V, edge_schema: S, max_depth: usize) -> impl Future<Item = Tree, Error = Error>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        let root = root.to_id();
+        let schema_id = self.schema_id(edge_schema);
+        let context = self.context("descendants", Some(root), Some(schema_id));
+        self.graph.descendants(root, schema_id, max_depth).then(move |r| error::flatten(r).map_err(|e| context.of(e)))
+    }
+    pub fn ancestors<V, S>(&self, vertex: V, edge_schema: S, max_depth: usize) -> impl Future<Item = Tree, Error = Error>
+        where V: ToVertexId, S: ToSchemaId
+    {
+        let id = vertex.to_id();
+        let schema_id = self.schema_id(edge_schema);
+        let context = self.context("ancestors", Some(id), Some(schema_id));
+        self.graph.ancestors(id, schema_id, max_depth).then(move |r| error::flatten(r).map_err(|e| context.of(e)))
+    }
+
+    pub fn scan_vertices<S, F>(&self, schema: S, filter: &Option<F>, projection: Option<Vec<String>>)
+        -> impl Stream<Item = Vertex, Error = Error>
+        where S: ToSchemaId, F: Expr
+    {
+        let schema_id = self.schema_id(schema);
+        let context = self.context("scan vertices", None, Some(schema_id));
+        self.graph.scan_vertices(schema_id, filter, projection).map_err(move |e| context.of(e.into()))
+    }
+    pub fn scan_edges<S, F>(&self, schema: S, filter: &Option<F>) -> impl Stream<Item = Edge, Error = Error>
+        where S: ToSchemaId, F: Expr
+    {
+        let schema_id = self.schema_id(schema);
+        let context = self.context("scan edges", None, Some(schema_id));
+        self.graph.scan_edges(schema_id, filter).map_err(move |e| context.of(e.into()))
+    }
+    pub fn count_vertices<S, F>(&self, schema: S, filter: &Option<F>, mode: CountMode)
+        -> impl Future<Item = CountEstimate, Error = Error>
+        where S: ToSchemaId, F: Expr
+    {
+        let schema_id = self.schema_id(schema);
+        let context = self.context("count vertices", None, Some(schema_id));
+        self.graph.count_vertices(schema_id, filter, mode).map_err(move |e| context.of(e.into()))
+    }
+    pub fn count_edges<S>(&self, schema: S, mode: CountMode) -> impl Future<Item = CountEstimate, Error = Error>
+        where S: ToSchemaId
+    {
+        let schema_id = self.schema_id(schema);
+        let context = self.context("count edges", None, Some(schema_id));
+        self.graph.count_edges(schema_id, mode).map_err(move |e| context.of(e.into()))
+    }
+}
@@ -0,0 +1,123 @@
+// Checks vertex data and edge bodies against their schema before anything is written, so a
+// malformed write fails with the field at fault instead of neb refusing the cell or dropping the
+// mismatched value. Types neb has but the checks do not know about are let through to neb.
+
+use neb::ram::schema::{Field, Schema};
+use neb::ram::types::{TypeId, key_hash};
+use neb::dovahkiin::types::{Map, Value};
+
+use graph::edge::{self, EdgeType};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    // dotted path of the field
+    MissingField(String),
+    TypeMismatch { field: String, expected: String, actual: String }
+}
+
+fn known_type(type_id: u32) -> Option<&'static str> {
+    let types = [
+        (TypeId::Bool as u32, "bool"),
+        (TypeId::I8 as u32, "i8"), (TypeId::I16 as u32, "i16"), (TypeId::I32 as u32, "i32"), (TypeId::I64 as u32, "i64"),
+        (TypeId::U8 as u32, "u8"), (TypeId::U16 as u32, "u16"), (TypeId::U32 as u32, "u32"), (TypeId::U64 as u32, "u64"),
+        (TypeId::F32 as u32, "f32"), (TypeId::F64 as u32, "f64"),
+        (TypeId::Id as u32, "id"), (TypeId::String as u32, "string"), (TypeId::Map as u32, "map")
+    ];
+    types.iter().find(|&&(t, _)| t == type_id).map(|&(_, name)| name)
+}
+
+fn value_type(value: &Value) -> &'static str {
+    match value {
+        &Value::Null => "null",
+        &Value::Bool(_) => "bool",
+        &Value::I8(_) => "i8", &Value::I16(_) => "i16", &Value::I32(_) => "i32", &Value::I64(_) => "i64",
+        &Value::U8(_) => "u8", &Value::U16(_) => "u16", &Value::U32(_) => "u32", &Value::U64(_) => "u64",
+        &Value::F32(_) => "f32", &Value::F64(_) => "f64",
+        &Value::Id(_) => "id",
+        &Value::String(_) => "string",
+        &Value::Map(_) => "map",
+        &Value::Array(_) => "array",
+        _ => "other"
+    }
+}
+
+fn mismatch(path: &str, expected: String, value: &Value) -> ValidationError {
+    ValidationError::TypeMismatch { field: path.to_string(), expected, actual: value_type(value).to_string() }
+}
+
+fn validate_scalar(field: &Field, path: &str, value: &Value) -> Result<(), ValidationError> {
+    match (&field.sub_fields, value) {
+        (&Some(ref sub_fields), &Value::Map(ref map)) => validate_fields(sub_fields, map, &format!("{}.", path)),
+        (&Some(_), _) => Err(mismatch(path, "map".to_string(), value)),
+        (&None, _) => match known_type(field.type_id) {
+            Some(expected) if expected != value_type(value) => Err(mismatch(path, expected.to_string(), value)),
+            _ => Ok(())
+        }
+    }
+}
+
+fn validate_field(field: &Field, path: &str, value: &Value) -> Result<(), ValidationError> {
+    if let &Value::Null = value {
+        return if field.nullable { Ok(()) } else { Err(ValidationError::MissingField(path.to_string())) };
+    }
+    if !field.is_array {
+        return validate_scalar(field, path, value);
+    }
+    match value {
+        &Value::Array(ref items) => {
+            for (i, item) in items.iter().enumerate() {
+                validate_scalar(field, &format!("{}[{}]", path, i), item)?;
+            }
+            Ok(())
+        },
+        _ => {
+            let element = if field.sub_fields.is_some() { Some("map") } else { known_type(field.type_id) };
+            Err(mismatch(path, format!("array of {}", element.unwrap_or("values")), value))
+        }
+    }
+}
+
+fn validate_fields(fields: &[Field], data: &Map, prefix: &str) -> Result<(), ValidationError> {
+    for field in fields {
+        let path = format!("{}{}", prefix, field.name);
+        validate_field(field, &path, data.get_by_key_id(key_hash(&field.name)))?;
+    }
+    Ok(())
+}
+
+pub fn validate_field_value(field: &Field, value: &Value) -> Result<(), ValidationError> {
+    validate_field(field, &field.name, value)
+}
+
+// fills fields left null with the defaults of the schema, before the data is validated
+pub fn apply_defaults(defaults: &[(String, Value)], data: &mut Map) {
+    for &(ref name, ref value) in defaults {
+        if let &Value::Null = data.get_by_key_id(key_hash(name)) {
+            data.insert(name, value.clone());
+        }
+    }
+}
+
+fn schema_fields(schema: &Schema) -> &[Field] {
+    match schema.fields.sub_fields {
+        Some(ref fields) => fields, None => &[]
+    }
+}
+
+// the vertex data with the internal fields already filled in
+pub fn validate_vertex(schema: &Schema, data: &Map) -> Result<(), ValidationError> {
+    validate_fields(schema_fields(schema), data, "")
+}
+
+// edge ends are filled in by link, only the fields declared for the body are checked
+pub fn validate_edge_body(schema: &Schema, edge_type: EdgeType, body: &Map) -> Result<(), ValidationError> {
+    let template = match edge_type {
+        EdgeType::Directed => &*edge::directed::EDGE_TEMPLATE,
+        EdgeType::Undirected => &*edge::undirectd::EDGE_TEMPLATE
+    };
+    let body_fields: Vec<Field> = schema_fields(schema).iter()
+        .filter(|f| !template.iter().any(|t| t.name == f.name))
+        .cloned()
+        .collect();
+    validate_fields(&body_fields, body, "")
+}
@@ -0,0 +1,325 @@
+// Vector fields and approximate nearest neighbour search. A vector field is an array of F32 on a
+// vertex, vector_field builds one. Indexes are HNSW graphs kept in memory by the server that built
+// them with Graph::build_vector_index from a scan of the schema. Vertex writes made through the same
+// server keep them current once their transaction commits, other servers hold their own indexes.
+// Removed and replaced vectors stay in the graph as waypoints and are only left out of results.
+
+use neb::ram::schema::Field;
+use neb::ram::types::{TypeId, Id, Value};
+use neb::ram::cell::Cell;
+use parking_lot::{Mutex, RwLock};
+use rand::{self, Rng};
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::sync::Arc;
+
+use graph::GraphTransaction;
+
+#[derive(Debug)]
+pub enum VectorError {
+    IndexNotFound,
+    DimensionMismatch { expected: usize, actual: usize }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectorMetric {
+    // 1 - cosine similarity
+    Cosine,
+    Euclidean
+}
+
+#[derive(Debug, Clone)]
+pub struct HnswOptions {
+    pub metric: VectorMetric,
+    // links per node above layer 0, layer 0 keeps twice as many
+    pub m: usize,
+    pub ef_construction: usize,
+    pub ef_search: usize
+}
+
+impl Default for HnswOptions {
+    fn default() -> HnswOptions {
+        HnswOptions {
+            metric: VectorMetric::Cosine,
+            m: 16,
+            ef_construction: 100,
+            ef_search: 50
+        }
+    }
+}
+
+// a vector field for vertex schemas
+pub fn vector_field(name: &str, nullable: bool) -> Field {
+    Field::new(&name.to_string(), TypeId::F32 as u32, nullable, true, None)
+}
+
+pub fn vector_from_value(value: &Value) -> Option<Vec<f32>> {
+    match value {
+        &Value::Array(ref items) => items.iter().map(|item| match item {
+            &Value::F32(n) => Some(n),
+            &Value::F64(n) => Some(n as f32),
+            _ => None
+        }).collect(),
+        _ => None
+    }
+}
+
+pub fn vector_to_value(vector: &[f32]) -> Value {
+    Value::Array(vector.iter().map(|n| Value::F32(*n)).collect())
+}
+
+fn distance(metric: VectorMetric, a: &[f32], b: &[f32]) -> f32 {
+    match metric {
+        VectorMetric::Euclidean =>
+            a.iter().zip(b.iter()).map(|(x, y)| (x - y) * (x - y)).sum::<f32>().sqrt(),
+        VectorMetric::Cosine => {
+            let dot: f32 = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum();
+            let norms = a.iter().map(|x| x * x).sum::<f32>().sqrt() * b.iter().map(|y| y * y).sum::<f32>().sqrt();
+            if norms == 0.0 { 1.0 } else { 1.0 - dot / norms }
+        }
+    }
+}
+
+// distance and node, ordered by distance for the heaps below
+#[derive(Clone, Copy, PartialEq)]
+struct Scored(f32, usize);
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Scored) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Scored) -> Ordering {
+        self.0.partial_cmp(&other.0).unwrap_or(Ordering::Equal).then(self.1.cmp(&other.1))
+    }
+}
+
+struct Node {
+    id: Id,
+    vector: Vec<f32>,
+    // links by layer, from 0 up to the node's level
+    links: Vec<Vec<usize>>,
+    deleted: bool
+}
+
+pub struct Hnsw {
+    options: HnswOptions,
+    dimensions: Option<usize>,
+    nodes: Vec<Node>,
+    live: HashMap<Id, usize>,
+    entry: Option<usize>
+}
+
+impl Hnsw {
+    pub fn new(options: HnswOptions) -> Hnsw {
+        Hnsw { options, dimensions: None, nodes: Vec::new(), live: HashMap::new(), entry: None }
+    }
+
+    pub fn len(&self) -> usize {
+        self.live.len()
+    }
+
+    pub fn dimensions(&self) -> Option<usize> {
+        self.dimensions
+    }
+
+    pub fn options(&self) -> &HnswOptions {
+        &self.options
+    }
+
+    fn distance_to(&self, query: &[f32], node: usize) -> f32 {
+        distance(self.options.metric, query, &self.nodes[node].vector)
+    }
+
+    fn max_links(&self, layer: usize) -> usize {
+        if layer == 0 { self.options.m * 2 } else { self.options.m }
+    }
+
+    fn random_level(&self) -> usize {
+        let ml = 1.0 / (self.options.m.max(2) as f64).ln();
+        let uniform: f64 = rand::thread_rng().gen_range(1e-12, 1.0);
+        (-uniform.ln() * ml).floor() as usize
+    }
+
+    // the ef closest nodes reachable from the entry points on one layer, nearest first
+    fn search_layer(&self, query: &[f32], entries: &[usize], ef: usize, layer: usize) -> Vec<Scored> {
+        let mut visited: HashSet<usize> = entries.iter().cloned().collect();
+        // candidates pop nearest first, found pops furthest first
+        let mut candidates: BinaryHeap<Reverse<Scored>> = BinaryHeap::new();
+        let mut found: BinaryHeap<Scored> = BinaryHeap::new();
+        for &entry in entries {
+            let scored = Scored(self.distance_to(query, entry), entry);
+            candidates.push(Reverse(scored));
+            found.push(scored);
+        }
+        while let Some(Reverse(current)) = candidates.pop() {
+            let furthest = found.peek().map(|s| s.0).unwrap_or(::std::f32::MAX);
+            if current.0 > furthest && found.len() >= ef { break; }
+            let links = match self.nodes[current.1].links.get(layer) {
+                Some(links) => links, None => continue
+            };
+            for &next in links {
+                if !visited.insert(next) { continue; }
+                let scored = Scored(self.distance_to(query, next), next);
+                let furthest = found.peek().map(|s| s.0).unwrap_or(::std::f32::MAX);
+                if found.len() < ef || scored.0 < furthest {
+                    candidates.push(Reverse(scored));
+                    found.push(scored);
+                    if found.len() > ef { found.pop(); }
+                }
+            }
+        }
+        found.into_sorted_vec()
+    }
+
+    fn top_level(&self) -> usize {
+        self.entry.map(|e| self.nodes[e].links.len() - 1).unwrap_or(0)
+    }
+
+    // greedy descent to the given layer, starting from the entry point
+    fn descend(&self, query: &[f32], to_layer: usize) -> Vec<usize> {
+        let mut entry = match self.entry { Some(entry) => vec![entry], None => return Vec::new() };
+        let mut layer = self.top_level();
+        while layer > to_layer {
+            entry = self.search_layer(query, &entry, 1, layer).into_iter().take(1).map(|s| s.1).collect();
+            layer -= 1;
+        }
+        entry
+    }
+
+    fn check_dimensions(&self, vector: &[f32]) -> Result<(), VectorError> {
+        match self.dimensions {
+            Some(expected) if expected != vector.len() =>
+                Err(VectorError::DimensionMismatch { expected, actual: vector.len() }),
+            _ => Ok(())
+        }
+    }
+
+    // adds the vector, replacing the one the vertex had before
+    pub fn insert(&mut self, id: Id, vector: Vec<f32>) -> Result<(), VectorError> {
+        self.check_dimensions(&vector)?;
+        self.dimensions = Some(vector.len());
+        self.remove(&id);
+        let node = self.nodes.len();
+        let level = self.random_level();
+        self.nodes.push(Node { id, vector, links: vec![Vec::new(); level + 1], deleted: false });
+        self.live.insert(id, node);
+        let entry = match self.entry {
+            Some(entry) => entry,
+            None => { self.entry = Some(node); return Ok(()); }
+        };
+        let top = self.top_level();
+        let query = self.nodes[node].vector.clone();
+        let mut entries = if level < top { self.descend(&query, level) } else { vec![entry] };
+        for layer in (0..(level.min(top) + 1)).rev() {
+            let found = self.search_layer(&query, &entries, self.options.ef_construction, layer);
+            let max_links = self.max_links(layer);
+            let neighbours: Vec<usize> = found.iter().filter(|s| s.1 != node).take(self.options.m).map(|s| s.1).collect();
+            self.nodes[node].links[layer] = neighbours.clone();
+            for &neighbour in &neighbours {
+                self.nodes[neighbour].links[layer].push(node);
+                if self.nodes[neighbour].links[layer].len() > max_links {
+                    // keep the closest links of the neighbour
+                    let neighbour_vector = self.nodes[neighbour].vector.clone();
+                    let mut links: Vec<Scored> = self.nodes[neighbour].links[layer].iter()
+                        .map(|&l| Scored(self.distance_to(&neighbour_vector, l), l))
+                        .collect();
+                    links.sort();
+                    self.nodes[neighbour].links[layer] = links.into_iter().take(max_links).map(|s| s.1).collect();
+                }
+            }
+            entries = found.into_iter().map(|s| s.1).collect();
+        }
+        if level > top {
+            self.entry = Some(node);
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, id: &Id) {
+        if let Some(node) = self.live.remove(id) {
+            self.nodes[node].deleted = true;
+        }
+    }
+
+    // the k nearest live vectors with their distances, nearest first
+    pub fn search(&self, query: &[f32], k: usize) -> Result<Vec<(Id, f32)>, VectorError> {
+        self.check_dimensions(query)?;
+        let entries = self.descend(query, 0);
+        if entries.is_empty() { return Ok(Vec::new()); }
+        // deleted nodes take room in the candidate list, widen it by as many
+        let ef = self.options.ef_search.max(k) + (self.nodes.len() - self.live.len()).min(self.options.ef_search);
+        Ok(self.search_layer(query, &entries, ef, 0).into_iter()
+            .filter(|s| !self.nodes[s.1].deleted)
+            .take(k)
+            .map(|s| (self.nodes[s.1].id, s.0))
+            .collect())
+    }
+}
+
+// a vector write to apply to the indexes once its transaction commits, None removes
+#[derive(Debug, Clone)]
+pub struct VectorChange {
+    pub schema: u32,
+    pub field: String,
+    pub id: Id,
+    pub vector: Option<Vec<f32>>
+}
+
+#[derive(Default)]
+pub struct VectorIndexes {
+    indexes: RwLock<HashMap<(u32, String), Arc<RwLock<Hnsw>>>>
+}
+
+impl VectorIndexes {
+    pub fn get(&self, schema: u32, field: &str) -> Option<Arc<RwLock<Hnsw>>> {
+        self.indexes.read().get(&(schema, field.to_string())).cloned()
+    }
+    pub fn fields(&self, schema: u32) -> Vec<String> {
+        self.indexes.read().keys().filter(|&&(s, _)| s == schema).map(|&(_, ref f)| f.clone()).collect()
+    }
+    pub fn put(&self, schema: u32, field: &str, index: Arc<RwLock<Hnsw>>) {
+        self.indexes.write().insert((schema, field.to_string()), index);
+    }
+    pub fn remove(&self, schema: u32, field: &str) -> bool {
+        self.indexes.write().remove(&(schema, field.to_string())).is_some()
+    }
+    // vectors that do not fit their index are skipped with a warning
+    pub fn apply(&self, changes: Vec<VectorChange>) {
+        for change in changes {
+            let index = match self.get(change.schema, &change.field) { Some(index) => index, None => continue };
+            let mut index = index.write();
+            match change.vector {
+                Some(vector) => if let Err(e) = index.insert(change.id, vector) {
+                    warn!("Vector of {:?} not indexed for field {}: {:?}", change.id, change.field, e);
+                },
+                None => index.remove(&change.id)
+            }
+        }
+    }
+}
+
+impl <'a> GraphTransaction<'a> {
+    // Notes the vector fields changed by a vertex write for the indexes, as reindex_geo does for points
+    pub(super) fn record_vectors(&self, before: Option<&Cell>, after: Option<&Cell>) {
+        let cell = match after.or(before) { Some(cell) => cell, None => return };
+        let schema = cell.header.schema;
+        let fields = self.vectors.fields(schema);
+        if fields.is_empty() { return; }
+        let mut changes = self.vector_changes.lock();
+        for field in fields {
+            let vector = |cell: Option<&Cell>| cell.and_then(|c| vector_from_value(&c.data[field.as_str()]));
+            let (old, new) = (vector(before), vector(after));
+            if old == new { continue; }
+            changes.push(VectorChange { schema, field: field.clone(), id: cell.id(), vector: new });
+        }
+    }
+}
+
+// collects the changes of one transaction attempt
+pub type PendingVectorChanges = Arc<Mutex<Vec<VectorChange>>>;
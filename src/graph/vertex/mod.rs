@@ -4,22 +4,39 @@ use neb::client::transaction::{Transaction, TxnError};
 use neb::dovahkiin::types::Value;
 use graph::id_list::{IdList, IdListError};
 use graph::edge;
+use graph::fields;
+use graph::geo::GeoError;
+use graph::computed::ComputedFieldError;
+use graph::encryption::EncryptionError;
 use server::schema::SchemaContainer;
 
 use std::ops::{Index, IndexMut};
 use std::sync::Arc;
-use super::EdgeDirection;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Vertex {
     pub cell: Cell
 }
 
+#[derive(Debug)]
 pub enum RemoveError {
     NotFound,
     FormatError,
     IdListError(IdListError),
-    EdgeError(edge::EdgeError)
+    EdgeError(edge::EdgeError),
+    GeoIndexError(GeoError),
+    ReadOnly
+}
+
+#[derive(Debug)]
+pub enum UpdateError {
+    NotFound,
+    // someone else updated the vertex since the caller read it
+    VersionMismatch { expected: u64, actual: u64 },
+    GeoIndexError(GeoError),
+    ComputedFieldError(ComputedFieldError),
+    EncryptionError(EncryptionError),
+    ReadOnly
 }
 
 pub fn cell_to_vertex(cell: Cell) -> Vertex {
@@ -32,6 +49,18 @@ pub fn vertex_to_cell(vertex: Vertex) -> Cell {
     vertex.cell
 }
 
+// keeps only the listed fields in the vertex data, None keeps everything
+pub fn project(mut vertex: Vertex, fields: &Option<Vec<String>>) -> Vertex {
+    if let &Some(ref fields) = fields {
+        let mut projected = Map::new();
+        for field in fields {
+            projected.insert(field, vertex.cell.data[field.as_str()].clone());
+        }
+        vertex.cell.data = Value::Map(projected);
+    }
+    vertex
+}
+
 impl Vertex {
     pub fn new(schema: u32, data: Map) -> Vertex {
         Vertex {
@@ -41,6 +70,17 @@ impl Vertex {
     pub fn schema(&self) -> u32 {
         self.cell.header.schema
     }
+    // bumped by every update, new vertices start from 0
+    pub fn version(&self) -> u64 {
+        cell_version(&self.cell)
+    }
+}
+
+fn cell_version(cell: &Cell) -> u64 {
+    match cell.data[*fields::VERSION_KEY_ID] {
+        Value::U64(version) => version,
+        _ => 0
+    }
 }
 
 pub fn txn_remove<V>(txn: &Transaction, schemas: &Arc<SchemaContainer>, vertex: V)
@@ -66,25 +106,29 @@ pub fn txn_remove<V>(txn: &Transaction, schemas: &Arc<SchemaContainer>, vertex:
                             )? {
                                 Ok(edge) => edge, Err(e) => return Ok(Err(RemoveError::EdgeError(e)))
                             };
-                            match edge.remove(iter.segments.id_iter.txn)? {
+                            match edge.remove(iter.segments.id_iter.txn, schemas)? {
                                 Ok(()) => {}, Err(e) => return Ok(Err(RemoveError::EdgeError(e)))
                             }
                         }
+                        if let Some(e) = iter.take_error() { return Ok(Err(RemoveError::IdListError(e))); }
                     }
                     match id_list.clear_segments()? { // remove segment cells
                         Ok(()) => {}, Err(e) => return Ok(Err(RemoveError::IdListError(e)))
                     }
+                    match IdList::opposite_index(txn, id, field_id, schema_id).clear_segments()? {
+                        Ok(()) => {}, Err(e) => return Ok(Err(RemoveError::IdListError(e)))
+                    }
                 }
                 txn.remove(&type_list_id)?; // remove field schema list cell
                 Ok(Ok(()))
             };
-            match remove_field_lists(id, txn, EdgeDirection::Undirected.as_field())? {
+            match remove_field_lists(id, txn, *fields::UNDIRECTED_KEY_ID)? {
                 Ok(()) => {}, Err(e) => return Ok(Err(e))
             }
-            match remove_field_lists(id, txn, EdgeDirection::Inbound.as_field())? {
+            match remove_field_lists(id, txn, *fields::INBOUND_KEY_ID)? {
                 Ok(()) => {}, Err(e) => return Ok(Err(e))
             }
-            match remove_field_lists(id, txn, EdgeDirection::Outbound.as_field())? {
+            match remove_field_lists(id, txn, *fields::OUTBOUND_KEY_ID)? {
                 Ok(()) => {}, Err(e) => return Ok(Err(e))
             }
             txn.remove(id).map(|_| Ok(())) // remove vertex cell
@@ -93,25 +137,58 @@ pub fn txn_remove<V>(txn: &Transaction, schemas: &Arc<SchemaContainer>, vertex:
     }
 }
 
+pub fn txn_compact_adjacency<V>(txn: &Transaction, vertex: V)
+    -> Result<Result<(), IdListError>, TxnError> where V: ToVertexId {
+    let id = &vertex.to_id();
+    let fields = vec![
+        *fields::UNDIRECTED_KEY_ID,
+        *fields::INBOUND_KEY_ID,
+        *fields::OUTBOUND_KEY_ID
+    ];
+    for field_id in fields {
+        let schema_ids = match IdList::cell_types(txn, id, field_id)? {
+            Some((_, schema_ids)) => schema_ids, None => continue
+        };
+        for schema_id in schema_ids {
+            match IdList::from_txn_and_container(txn, id, field_id, schema_id).compact()? {
+                Ok(()) => {}, Err(e) => return Ok(Err(e))
+            }
+        }
+    }
+    Ok(Ok(()))
+}
+
 pub fn txn_update<U, V>(txn: &Transaction, vertex: V, update: &U) -> Result<(), TxnError>
+    where V: ToVertexId, U: Fn(Vertex) -> Option<Vertex> {
+    match txn_update_checked(txn, vertex, None, update)? {
+        Ok(()) => Ok(()),
+        Err(_) => txn.abort()
+    }
+}
+
+// The version is set here after the update function ran, whatever it wrote to _version is replaced.
+// Returning None from the update function aborts the transaction, as txn_update does.
+pub fn txn_update_checked<U, V>(txn: &Transaction, vertex: V, check_version: Option<u64>, update: &U)
+    -> Result<Result<(), UpdateError>, TxnError>
     where V: ToVertexId, U: Fn(Vertex) -> Option<Vertex> {
     let id = &vertex.to_id();
-    let update_cell = |cell: Cell| {
-        match update(cell_to_vertex(cell)) {
-            Some(vertex) => Some(vertex_to_cell(vertex)),
-            None => None
+    let cell = match txn.read(id)? {
+        Some(cell) => cell, None => return Ok(Err(UpdateError::NotFound))
+    };
+    let version = cell_version(&cell);
+    if let Some(expected) = check_version {
+        if expected != version {
+            return Ok(Err(UpdateError::VersionMismatch { expected, actual: version }));
         }
+    }
+    let mut cell = match update(cell_to_vertex(cell)) {
+        Some(vertex) => vertex_to_cell(vertex),
+        None => return txn.abort().map(Ok)
     };
-    let cell = txn.read(id)?;
-    match cell {
-        Some(cell) => {
-            match update_cell(cell) {
-                Some(cell) => txn.update(&cell),
-                None => txn.abort()
-            }
-        },
-        None => txn.abort()
+    if let Value::Map(ref mut map) = cell.data {
+        map.insert_key_id(*fields::VERSION_KEY_ID, Value::U64(version + 1));
     }
+    txn.update(&cell).map(Ok)
 }
 
 pub trait ToVertexId {
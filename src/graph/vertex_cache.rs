@@ -0,0 +1,224 @@
+// Least recently used cache of vertex cells read by this server, for traversals that keep coming
+// back to the same hub vertices. Writes committed through this server drop the vertices they touch,
+// found in the change events of the transaction (see graph::changes), and the ids are queued for the
+// servers in peers, which drop them too when the notification arrives (see server::cache_sync).
+// Writes through servers that do not notify this one are only seen once the entry is max_age_ms old.
+// Reads of vertices that do not exist are remembered for missing_max_age_ms, so repeated lookups of
+// absent keys skip the cluster. Inserts drop them like any other write, 0 turns this off.
+
+use neb::ram::types::Id;
+use parking_lot::Mutex;
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use graph::vertex::Vertex;
+use graph::changes::ChangeEvent;
+use server::metrics;
+
+fn default_capacity() -> usize { 4096 }
+fn default_max_age_ms() -> u64 { 2000 }
+fn default_notify_interval_ms() -> u64 { 10 }
+fn default_missing_max_age_ms() -> u64 { 500 }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VertexCacheOptions {
+    #[serde(default = "default_capacity")]
+    pub capacity: usize,
+    #[serde(default = "default_max_age_ms")]
+    pub max_age_ms: u64,
+    // rpc addresses of the servers told about local writes
+    #[serde(default)]
+    pub peers: Vec<String>,
+    #[serde(default = "default_notify_interval_ms")]
+    pub notify_interval_ms: u64,
+    #[serde(default = "default_missing_max_age_ms")]
+    pub missing_max_age_ms: u64
+}
+
+impl Default for VertexCacheOptions {
+    fn default() -> VertexCacheOptions {
+        VertexCacheOptions {
+            capacity: default_capacity(),
+            max_age_ms: default_max_age_ms(),
+            peers: Vec::new(),
+            notify_interval_ms: default_notify_interval_ms(),
+            missing_max_age_ms: default_missing_max_age_ms()
+        }
+    }
+}
+
+struct Entry {
+    vertex: Vertex,
+    cached_at: Instant,
+    tick: u64
+}
+
+struct CacheState {
+    entries: HashMap<Id, Entry>,
+    // last use of every entry, the first one is evicted
+    recency: BTreeMap<u64, Id>,
+    next_tick: u64,
+    // ids read as missing, up to capacity of them in the order they were read
+    missing: HashMap<Id, Instant>,
+    missing_order: VecDeque<(Id, Instant)>,
+    // bumped by every invalidation, reads issued across one are not cached
+    generation: u64,
+    // ids written here and not yet sent to the peers
+    outbox: Vec<Id>
+}
+
+pub struct VertexCache {
+    options: VertexCacheOptions,
+    state: Mutex<CacheState>
+}
+
+impl VertexCache {
+    pub fn new(options: VertexCacheOptions) -> VertexCache {
+        VertexCache {
+            options,
+            state: Mutex::new(CacheState {
+                entries: HashMap::new(),
+                recency: BTreeMap::new(),
+                next_tick: 0,
+                missing: HashMap::new(),
+                missing_order: VecDeque::new(),
+                generation: 0,
+                outbox: Vec::new()
+            })
+        }
+    }
+
+    pub fn options(&self) -> &VertexCacheOptions {
+        &self.options
+    }
+
+    // taken before reading a vertex and handed back to insert
+    pub fn generation(&self) -> u64 {
+        self.state.lock().generation
+    }
+
+    pub fn get(&self, id: &Id) -> Option<Vertex> {
+        let mut state = self.state.lock();
+        let max_age = Duration::from_millis(self.options.max_age_ms);
+        let (found, old_tick) = match state.entries.get(id) {
+            Some(entry) if entry.cached_at.elapsed() < max_age => (Some(entry.vertex.clone()), entry.tick),
+            Some(entry) => (None, entry.tick),
+            None => {
+                metrics::VERTEX_CACHE_MISSES.inc();
+                return None;
+            }
+        };
+        state.recency.remove(&old_tick);
+        if found.is_none() {
+            metrics::VERTEX_CACHE_MISSES.inc();
+            state.entries.remove(id);
+            return None;
+        }
+        metrics::VERTEX_CACHE_HITS.inc();
+        let tick = state.next_tick;
+        state.next_tick += 1;
+        state.recency.insert(tick, *id);
+        if let Some(entry) = state.entries.get_mut(id) { entry.tick = tick; }
+        found
+    }
+
+    pub fn is_missing(&self, id: &Id) -> bool {
+        let mut state = self.state.lock();
+        let max_age = Duration::from_millis(self.options.missing_max_age_ms);
+        let fresh = match state.missing.get(id) {
+            Some(read_at) => read_at.elapsed() < max_age,
+            None => return false
+        };
+        if fresh {
+            metrics::VERTEX_CACHE_MISSING_HITS.inc();
+        } else {
+            state.missing.remove(id);
+        }
+        fresh
+    }
+
+    // Remembers that id was read as missing, skipped like insert when a write was seen since generation
+    pub fn insert_missing(&self, id: Id, generation: u64) {
+        if self.options.missing_max_age_ms == 0 { return; }
+        let mut state = self.state.lock();
+        if state.generation != generation { return; }
+        let read_at = Instant::now();
+        state.missing.insert(id, read_at);
+        state.missing_order.push_back((id, read_at));
+        while state.missing.len() > self.options.capacity || state.missing_order.len() > self.options.capacity * 2 {
+            let (oldest, read_at) = match state.missing_order.pop_front() { Some(oldest) => oldest, None => break };
+            if state.missing.get(&oldest) == Some(&read_at) {
+                state.missing.remove(&oldest);
+            }
+        }
+    }
+
+    // Skipped when a write was seen since generation was taken, the vertex may predate it
+    pub fn insert(&self, vertex: &Vertex, generation: u64) {
+        let mut state = self.state.lock();
+        if state.generation != generation { return; }
+        let id = vertex.cell.id();
+        if let Some(old) = state.entries.remove(&id) {
+            state.recency.remove(&old.tick);
+        }
+        let tick = state.next_tick;
+        state.next_tick += 1;
+        state.recency.insert(tick, id);
+        state.entries.insert(id, Entry { vertex: vertex.clone(), cached_at: Instant::now(), tick });
+        while state.entries.len() > self.options.capacity {
+            let oldest = match state.recency.iter().next() { Some((&tick, &id)) => (tick, id), None => break };
+            state.recency.remove(&oldest.0);
+            state.entries.remove(&oldest.1);
+        }
+    }
+
+    pub fn invalidate(&self, ids: &[Id]) {
+        let mut state = self.state.lock();
+        state.generation += 1;
+        for id in ids {
+            if let Some(old) = state.entries.remove(id) {
+                state.recency.remove(&old.tick);
+            }
+            state.missing.remove(id);
+        }
+    }
+
+    // Called with the events of every transaction committed through this server. Links change the
+    // edge lists of both ends, so the ends are dropped as well.
+    pub fn invalidate_committed(&self, events: &[ChangeEvent]) {
+        if events.is_empty() { return; }
+        let mut ids = Vec::with_capacity(events.len());
+        for event in events {
+            match event.ends {
+                Some((from, to)) => { ids.push(from); ids.push(to); },
+                None => ids.push(event.id)
+            }
+        }
+        self.invalidate(&ids);
+        if !self.options.peers.is_empty() {
+            self.state.lock().outbox.extend(ids);
+        }
+    }
+
+    // ids to send to the peers since the last call
+    pub fn take_outbox(&self) -> Vec<Id> {
+        let mut outbox = ::std::mem::replace(&mut self.state.lock().outbox, Vec::new());
+        outbox.sort_by_key(|id| (id.higher, id.lower));
+        outbox.dedup();
+        outbox
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.state.lock();
+        state.entries.clear();
+        state.recency.clear();
+        state.missing.clear();
+        state.missing_order.clear();
+        state.generation += 1;
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().entries.len()
+    }
+}
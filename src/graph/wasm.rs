@@ -0,0 +1,389 @@
+// WebAssembly plugins, built with the wasm feature. A module compiled for wasm32 can provide filter
+// and scoring functions, registered as user defined functions (see query::symbols::udf), and stored
+// procedures (see graph::procedure). Every call runs in a fresh instance, so no state survives from
+// one call to the next and nothing reaches the host but the functions below.
+// Values cross the boundary as JSON in the memory of the module. The module exports `memory`,
+// `alloc(len: i32) -> i32` returning where the host may write len bytes, and the functions called,
+// which take `(ptr: i32, len: i32)` of their JSON argument array and return `ptr << 32 | len` of
+// their JSON result. Procedures can import `morpheus.call` with the same signature, taking a request
+// and returning {"ok": result} or {"error": reason}, in the transaction of the procedure:
+//   {"op": "read_vertex", "id": "1:2"}                              the data of the vertex or null
+//   {"op": "neighbours", "id": "1:2", "schema": "follows", "direction": "Outbound"}
+//   {"op": "new_vertex", "schema": "user", "data": {...}}            the id of the new vertex
+//   {"op": "update_vertex", "id": "1:2", "data": {...}}
+//   {"op": "remove_vertex", "id": "1:2"}
+//   {"op": "link", "from": "1:2", "schema": "follows", "to": "3:4", "body": {...}}
+//   {"op": "unlink", "from": "1:2", "schema": "follows", "to": "3:4"}
+// Calls are limited in the gas metered into the module at compile time, about one unit per
+// instruction, and in the pages of memory the instance may grow to, checked as gas is spent.
+
+use neb::ram::types::{Id, key_hash};
+use neb::dovahkiin::types::{Map, Value};
+use neb::client::transaction::TxnError;
+use serde_json::{self, Value as Json, Map as JsonMap};
+use parity_wasm;
+use parity_wasm::elements;
+use pwasm_utils;
+use wasmi::{
+    self, ImportsBuilder, ModuleInstance, ModuleRef, ModuleImportResolver, FuncInstance, FuncRef, Signature,
+    ValueType, RuntimeValue, RuntimeArgs, Externals, Trap, TrapKind, HostError, MemoryRef
+};
+
+use std::fmt;
+use std::sync::Arc;
+
+use graph::{GraphTransaction, EdgeDirection};
+use graph::bulk::{self, BulkOp};
+use graph::procedure::Procedure;
+use import::stream::to_value;
+use query::symbols::udf::{self, UdfError};
+use server::graphql::exec::{to_json, format_id, parse_id};
+
+fn default_max_gas() -> u64 { 10_000_000 }
+fn default_max_memory_pages() -> u32 { 16 }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WasmLimits {
+    #[serde(default = "default_max_gas")]
+    pub max_gas: u64,
+    // of 64 KiB each
+    #[serde(default = "default_max_memory_pages")]
+    pub max_memory_pages: u32
+}
+
+impl Default for WasmLimits {
+    fn default() -> WasmLimits {
+        WasmLimits {
+            max_gas: default_max_gas(),
+            max_memory_pages: default_max_memory_pages()
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum WasmError {
+    InvalidModule(String),
+    CannotInstantiate(String),
+    // the module does not follow the calling convention above
+    Abi(String),
+    Trap(String),
+    OutOfGas,
+    OutOfMemory,
+    TxnError(TxnError)
+}
+
+impl fmt::Display for WasmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+// raised by the host functions, ends the call
+#[derive(Debug)]
+enum HostTrap {
+    OutOfGas,
+    OutOfMemory,
+    // the transaction failed, the error is kept by the host
+    Txn,
+    Abi(String)
+}
+
+impl fmt::Display for HostTrap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl HostError for HostTrap {}
+
+const GAS_FUNC: usize = 0;
+const CALL_FUNC: usize = 1;
+
+struct EnvResolver;
+
+impl ModuleImportResolver for EnvResolver {
+    fn resolve_func(&self, field_name: &str, signature: &Signature) -> Result<FuncRef, wasmi::Error> {
+        match field_name {
+            "gas" => Ok(FuncInstance::alloc_host(signature.clone(), GAS_FUNC)),
+            _ => Err(wasmi::Error::Instantiation(format!("env.{} is not provided", field_name)))
+        }
+    }
+}
+
+struct MorpheusResolver;
+
+impl ModuleImportResolver for MorpheusResolver {
+    fn resolve_func(&self, field_name: &str, _signature: &Signature) -> Result<FuncRef, wasmi::Error> {
+        match field_name {
+            "call" => Ok(FuncInstance::alloc_host(
+                Signature::new(&[ValueType::I32, ValueType::I32][..], Some(ValueType::I64)), CALL_FUNC
+            )),
+            _ => Err(wasmi::Error::Instantiation(format!("morpheus.{} is not provided", field_name)))
+        }
+    }
+}
+
+// A validated module with gas metering injected, instantiated again for every call
+pub struct WasmModule {
+    module: wasmi::Module,
+    limits: WasmLimits
+}
+
+impl WasmModule {
+    pub fn compile(bytes: &[u8], limits: WasmLimits) -> Result<WasmModule, WasmError> {
+        let module: elements::Module = parity_wasm::deserialize_buffer(bytes)
+            .map_err(|e| WasmError::InvalidModule(format!("{:?}", e)))?;
+        if let Some(memory) = module.memory_section().and_then(|s| s.entries().get(0)) {
+            if memory.limits().initial() > limits.max_memory_pages {
+                return Err(WasmError::InvalidModule(format!(
+                    "{} initial memory pages, at most {} allowed", memory.limits().initial(), limits.max_memory_pages
+                )));
+            }
+        }
+        let module = pwasm_utils::inject_gas_counter(module, &pwasm_utils::rules::Set::default())
+            .map_err(|_| WasmError::InvalidModule("cannot meter gas".to_string()))?;
+        let module = wasmi::Module::from_parity_wasm_module(module)
+            .map_err(|e| WasmError::InvalidModule(format!("{}", e)))?;
+        Ok(WasmModule { module, limits })
+    }
+
+    // Calls the export with the arguments, txn is None outside procedures
+    fn call(&self, txn: Option<&GraphTransaction>, export: &str, args: &Json) -> Result<Json, WasmError> {
+        let imports = ImportsBuilder::new()
+            .with_resolver("env", &EnvResolver)
+            .with_resolver("morpheus", &MorpheusResolver);
+        let instance = ModuleInstance::new(&self.module, &imports)
+            .map_err(|e| WasmError::CannotInstantiate(format!("{}", e)))?;
+        let memory = match instance.not_started_instance().export_by_name("memory").and_then(|e| e.as_memory().cloned()) {
+            Some(memory) => memory,
+            None => return Err(WasmError::Abi("no memory exported".to_string()))
+        };
+        let mut host = Host {
+            txn, instance: None, memory, gas_left: self.limits.max_gas, limits: &self.limits, txn_error: None
+        };
+        let instance = instance.run_start(&mut host).map_err(|e| trap_error(e.kind()))?;
+        host.instance = Some(instance.clone());
+        let input = serde_json::to_vec(args).map_err(|e| WasmError::Abi(format!("{}", e)))?;
+        let (ptr, len) = host.write(&input).map_err(|e| host_error(&e))?;
+        let result = instance.invoke_export(export, &[RuntimeValue::I32(ptr), RuntimeValue::I32(len)], &mut host);
+        if let Some(e) = host.txn_error.take() {
+            return Err(WasmError::TxnError(e));
+        }
+        let result = result.map_err(|e| match e {
+            wasmi::Error::Trap(trap) => trap_error(trap.kind()),
+            e => WasmError::Abi(format!("{}", e))
+        })?;
+        let output = match result {
+            Some(RuntimeValue::I64(packed)) => host.read(packed).map_err(|e| host_error(&e))?,
+            other => return Err(WasmError::Abi(format!("{} returned {:?}", export, other)))
+        };
+        serde_json::from_slice(&output).map_err(|e| WasmError::Abi(format!("{} returned no JSON: {}", export, e)))
+    }
+}
+
+fn host_error(trap: &HostTrap) -> WasmError {
+    match trap {
+        &HostTrap::OutOfGas => WasmError::OutOfGas,
+        &HostTrap::OutOfMemory => WasmError::OutOfMemory,
+        &HostTrap::Txn => WasmError::Trap("transaction failed".to_string()),
+        &HostTrap::Abi(ref e) => WasmError::Abi(e.clone())
+    }
+}
+
+fn trap_error(kind: &TrapKind) -> WasmError {
+    match kind {
+        &TrapKind::Host(ref e) => match e.downcast_ref::<HostTrap>() {
+            Some(trap) => host_error(trap),
+            None => WasmError::Trap(format!("{}", e))
+        },
+        other => WasmError::Trap(format!("{:?}", other))
+    }
+}
+
+struct Host<'t, 'a: 't> {
+    txn: Option<&'t GraphTransaction<'a>>,
+    // set once the start function ran, alloc cannot be called before
+    instance: Option<ModuleRef>,
+    memory: MemoryRef,
+    gas_left: u64,
+    limits: &'t WasmLimits,
+    // what a host call ran into, handed to the retry logic of the transaction
+    txn_error: Option<TxnError>
+}
+
+impl <'t, 'a> Host<'t, 'a> {
+    fn read(&self, packed: i64) -> Result<Vec<u8>, HostTrap> {
+        let (ptr, len) = ((packed as u64 >> 32) as u32, packed as u32);
+        self.memory.get(ptr, len as usize).map_err(|e| HostTrap::Abi(format!("{}", e)))
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(i32, i32), HostTrap> {
+        let instance = self.instance.clone().ok_or_else(|| HostTrap::Abi("not started".to_string()))?;
+        let ptr = match instance.invoke_export("alloc", &[RuntimeValue::I32(bytes.len() as i32)], self) {
+            Ok(Some(RuntimeValue::I32(ptr))) => ptr,
+            Ok(other) => return Err(HostTrap::Abi(format!("alloc returned {:?}", other))),
+            Err(e) => return Err(match e.as_host_error().and_then(|e| e.downcast_ref::<HostTrap>()) {
+                Some(&HostTrap::OutOfGas) => HostTrap::OutOfGas,
+                Some(&HostTrap::OutOfMemory) => HostTrap::OutOfMemory,
+                _ => HostTrap::Abi(format!("alloc failed: {}", e))
+            })
+        };
+        self.memory.set(ptr as u32, bytes).map_err(|e| HostTrap::Abi(format!("{}", e)))?;
+        Ok((ptr, bytes.len() as i32))
+    }
+
+    fn charge(&mut self, gas: u64) -> Result<(), HostTrap> {
+        if gas > self.gas_left { return Err(HostTrap::OutOfGas); }
+        self.gas_left -= gas;
+        if self.memory.current_size().0 > self.limits.max_memory_pages as usize {
+            return Err(HostTrap::OutOfMemory);
+        }
+        Ok(())
+    }
+
+    fn host_call(&mut self, ptr: i32, len: i32) -> Result<i64, HostTrap> {
+        let request = self.memory.get(ptr as u32, len as usize).map_err(|e| HostTrap::Abi(format!("{}", e)))?;
+        let response = match self.txn {
+            Some(txn) => match serde_json::from_slice(&request) {
+                Ok(Json::Object(request)) => match txn.wasm_request(&request) {
+                    Ok(Ok(result)) => response("ok", result),
+                    Ok(Err(reason)) => response("error", Json::String(reason)),
+                    Err(e) => {
+                        self.txn_error = Some(e);
+                        return Err(HostTrap::Txn);
+                    }
+                },
+                _ => response("error", Json::String("the request is no JSON object".to_string()))
+            },
+            None => response("error", Json::String("the graph is only reachable from procedures".to_string()))
+        };
+        let bytes = serde_json::to_vec(&response).map_err(|e| HostTrap::Abi(format!("{}", e)))?;
+        let (ptr, len) = self.write(&bytes)?;
+        Ok(((ptr as u32 as i64) << 32) | len as u32 as i64)
+    }
+}
+
+impl <'t, 'a> Externals for Host<'t, 'a> {
+    fn invoke_index(&mut self, index: usize, args: RuntimeArgs) -> Result<Option<RuntimeValue>, Trap> {
+        let result = match index {
+            GAS_FUNC => {
+                let gas: u32 = args.nth_checked(0)?;
+                self.charge(gas as u64).map(|_| None)
+            },
+            CALL_FUNC => {
+                let (ptr, len): (i32, i32) = (args.nth_checked(0)?, args.nth_checked(1)?);
+                self.host_call(ptr, len).map(|packed| Some(RuntimeValue::I64(packed)))
+            },
+            _ => Err(HostTrap::Abi(format!("no host function {}", index)))
+        };
+        result.map_err(|e| Trap::new(TrapKind::Host(Box::new(e))))
+    }
+}
+
+fn response(key: &str, value: Json) -> Json {
+    let mut object = JsonMap::new();
+    object.insert(key.to_string(), value);
+    Json::Object(object)
+}
+
+fn id_of(request: &JsonMap<String, Json>, name: &str) -> Result<Id, String> {
+    request.get(name).and_then(|id| id.as_str()).and_then(parse_id)
+        .ok_or_else(|| format!("no vertex id {}", name))
+}
+
+fn str_of<'r>(request: &'r JsonMap<String, Json>, name: &str) -> Result<&'r str, String> {
+    request.get(name).and_then(|s| s.as_str()).ok_or_else(|| format!("no {}", name))
+}
+
+impl <'a> GraphTransaction<'a> {
+    // the data of a request converted like the fields of the schema
+    fn wasm_data(&self, request: &JsonMap<String, Json>, name: &str, schema_id: u32) -> Result<Option<Map>, String> {
+        let fields = self.schemas.get_neb_schema(schema_id).map(|s| s.fields.clone());
+        match request.get(name) {
+            None | Some(&Json::Null) => Ok(None),
+            Some(json @ &Json::Object(_)) => match to_value(json, fields.as_ref())? {
+                Value::Map(map) => Ok(Some(map)),
+                _ => Err(format!("{} is not an object", name))
+            },
+            Some(_) => Err(format!("{} is not an object", name))
+        }
+    }
+
+    fn wasm_request(&self, request: &JsonMap<String, Json>) -> Result<Result<Json, String>, TxnError> {
+        macro_rules! tri {
+            ($e: expr) => { match $e { Ok(v) => v, Err(e) => return Ok(Err(e)) } };
+        }
+        let schema_id = |request: &JsonMap<String, Json>| -> Result<u32, String> {
+            let name = str_of(request, "schema")?;
+            self.schemas.id_from_name(name).ok_or_else(|| format!("unknown schema {}", name))
+        };
+        let op = match tri!(str_of(request, "op")) {
+            "read_vertex" => {
+                let id = tri!(id_of(request, "id"));
+                return Ok(Ok(self.read_vertex(id)?.map(|v| to_json(&v.cell.data)).unwrap_or(Json::Null)));
+            },
+            "neighbours" => {
+                let (id, schema) = (tri!(id_of(request, "id")), tri!(schema_id(request)));
+                let direction: EdgeDirection = tri!(serde_json::from_value(
+                    request.get("direction").cloned().unwrap_or(Json::Null)
+                ).map_err(|e| format!("direction: {}", e)));
+                let edges = tri!(self.edges(id, schema, direction, &None)?.map_err(|e| format!("{:?}", e)));
+                return Ok(Ok(Json::Array(edges.iter()
+                    .filter_map(|edge| edge.one_opposite_id_vertex_id(&id).map(|id| Json::String(format_id(id))))
+                    .collect())));
+            },
+            "new_vertex" => {
+                let schema = tri!(schema_id(request));
+                let data = tri!(self.wasm_data(request, "data", schema)).unwrap_or_else(Map::new);
+                let vertex = tri!(self.new_vertex(schema, data)?.map_err(|e| format!("{:?}", e)));
+                return Ok(Ok(Json::String(format_id(&vertex.cell.id()))));
+            },
+            "update_vertex" => {
+                let id = tri!(id_of(request, "id"));
+                let schema = match self.read_vertex(id)? {
+                    Some(vertex) => vertex.schema(), None => return Ok(Err("vertex not found".to_string()))
+                };
+                let data = tri!(self.wasm_data(request, "data", schema)).unwrap_or_else(Map::new);
+                let fields = data.fields.iter()
+                    .map(|name| (name.clone(), data.get_by_key_id(key_hash(name)).clone()))
+                    .collect();
+                BulkOp::UpdateVertex { id, fields }
+            },
+            "remove_vertex" => BulkOp::RemoveVertex { id: tri!(id_of(request, "id")) },
+            "link" => {
+                let schema = tri!(schema_id(request));
+                let (from, to) = (tri!(id_of(request, "from")), tri!(id_of(request, "to")));
+                BulkOp::Link { from, schema, to, body: tri!(self.wasm_data(request, "body", schema)) }
+            },
+            "unlink" => {
+                let schema = tri!(schema_id(request));
+                BulkOp::Unlink { from: tri!(id_of(request, "from")), schema, to: tri!(id_of(request, "to")) }
+            },
+            other => return Ok(Err(format!("unknown op {}", other)))
+        };
+        Ok(bulk::apply_op(self, &op)?.map(|_| Json::Null))
+    }
+}
+
+// Registers the export as a function filters and computed fields can call, see query::symbols::udf
+pub fn register_function(name: &str, module: Arc<WasmModule>, export: &str) -> Result<(), UdfError> {
+    let export = export.to_string();
+    udf::register(name, move |args| {
+        let args = Json::Array(args.iter().map(to_json).collect());
+        let result = module.call(None, &export, &args).map_err(|e| format!("{}", e))?;
+        to_value(&result, None)
+    })
+}
+
+// A procedure calling the export with its arguments, deploy it with Graph::deploy_procedure
+pub fn procedure(module: Arc<WasmModule>, export: &str) -> Procedure {
+    let export = export.to_string();
+    Procedure::native(move |txn, args| {
+        let args = Json::Array(args.iter().map(to_json).collect());
+        match module.call(Some(txn), &export, &args) {
+            Ok(result) => Ok(to_value(&result, None)),
+            Err(WasmError::TxnError(e)) => Err(e),
+            Err(e) => Ok(Err(format!("{}", e)))
+        }
+    })
+}
@@ -0,0 +1,304 @@
+// Rolling window counters, interactions in the last 24 hours or 7 days say, for feeds and ranking
+// where counting edges with a time filter on every read costs too much. An edge schema declares its
+// counters, see MorpheusSchema::with_window_counter, and every link of the schema adds one to the
+// counter of the ends it names, in the transaction of the link. Counters can also be bumped by hand
+// on any id, an edge body for instance. A counter lives in a cell of its own placed with its owner,
+// the window split into buckets as a ring: writes add to the bucket of the current time and reads sum
+// the buckets still in the window, so counts age out a bucket at a time. Unlinking takes nothing off,
+// the interaction happened; links restored by savepoint rollbacks and edges moved by merges are not
+// counted again. The decay job clears expired buckets, removes counters that came down to zero and
+// keeps the stored totals current for scans. Times are the clocks of the servers writing, a bucket
+// from a server running behind is added to the latest one.
+
+use neb::ram::schema::Field;
+use neb::ram::types::{TypeId, Id, Map, Value, key_hash};
+use neb::ram::cell::Cell;
+use neb::client::transaction::TxnError;
+use futures::prelude::*;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use graph::{GraphInner, GraphTransaction, scan};
+use graph::gc::pause;
+use graph::savepoint::Undo;
+
+pub static WINDOW_COUNTER_SCHEMA_ID: u32 = 200;
+pub const BUCKET_SECS_KEY: &'static str = "bucket_secs";
+pub const LAST_KEY: &'static str = "last";
+pub const COUNTS_KEY: &'static str = "counts";
+pub const TOTAL_KEY: &'static str = "total";
+
+lazy_static! {
+    pub static ref BUCKET_SECS_KEY_ID: u64 = key_hash(&String::from(BUCKET_SECS_KEY));
+    pub static ref LAST_KEY_ID: u64 = key_hash(&String::from(LAST_KEY));
+    pub static ref COUNTS_KEY_ID: u64 = key_hash(&String::from(COUNTS_KEY));
+    pub static ref TOTAL_KEY_ID: u64 = key_hash(&String::from(TOTAL_KEY));
+    pub static ref WINDOW_COUNTER: Field = Field::new("*", TypeId::Map as u32, false, false, Some(vec![
+        Field::new(&String::from(BUCKET_SECS_KEY), TypeId::U64 as u32, false, false, None),
+        Field::new(&String::from(LAST_KEY), TypeId::U64 as u32, false, false, None),
+        Field::new(&String::from(COUNTS_KEY), TypeId::U64 as u32, false, true, None),
+        Field::new(&String::from(TOTAL_KEY), TypeId::U64 as u32, false, false, None)
+    ]));
+}
+
+fn default_interval_secs() -> u64 { 60 }
+fn default_page_size() -> usize { 256 }
+
+// the ends of a link whose counters it adds to
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CounterEnds {
+    From,
+    To,
+    Both
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WindowCounter {
+    pub name: String,
+    pub window_secs: u64,
+    // the window ages out window_secs / buckets at a time
+    pub buckets: u32,
+    pub ends: CounterEnds
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CounterDecayOptions {
+    // pause between the end of one round over the counters and the start of the next
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    // counters checked in one transaction
+    #[serde(default = "default_page_size")]
+    pub page_size: usize
+}
+
+impl Default for CounterDecayOptions {
+    fn default() -> CounterDecayOptions {
+        CounterDecayOptions { interval_secs: default_interval_secs(), page_size: default_page_size() }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DecayRound {
+    pub checked: usize,
+    pub decayed: usize,
+    pub removed: usize
+}
+
+// The buckets of a counter, last is the number of the latest bucket since the epoch
+#[derive(Debug, Clone, PartialEq)]
+pub struct CounterBuckets {
+    pub bucket_secs: u64,
+    pub last: u64,
+    pub counts: Vec<u64>
+}
+
+pub fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// next to its owner, one cell for each counter name
+pub fn counter_id(owner: &Id, name: &str) -> Id {
+    Id::new(owner.higher, key_hash(&format!("{}:{}:{}", owner.higher, owner.lower, name)))
+}
+
+impl WindowCounter {
+    pub fn new(name: &str, window_secs: u64, buckets: u32, ends: CounterEnds) -> WindowCounter {
+        WindowCounter { name: name.to_string(), window_secs, buckets, ends }
+    }
+    pub fn bucket_secs(&self) -> u64 {
+        ::std::cmp::max(self.window_secs / ::std::cmp::max(self.buckets as u64, 1), 1)
+    }
+    // the ends of a link from one vertex to another that count it
+    pub fn owners(&self, from: &Id, to: &Id) -> Vec<Id> {
+        match self.ends {
+            CounterEnds::From => vec![*from],
+            CounterEnds::To => vec![*to],
+            CounterEnds::Both if from == to => vec![*from],
+            CounterEnds::Both => vec![*from, *to]
+        }
+    }
+}
+
+impl CounterBuckets {
+    pub fn new(counter: &WindowCounter) -> CounterBuckets {
+        CounterBuckets {
+            bucket_secs: counter.bucket_secs(),
+            last: 0,
+            counts: vec![0; ::std::cmp::max(counter.buckets as usize, 1)]
+        }
+    }
+
+    pub fn from_cell(cell: &Cell) -> Option<CounterBuckets> {
+        let data = match cell.data { Value::Map(ref data) => data, _ => return None };
+        let (bucket_secs, last) = match (data.get_by_key_id(*BUCKET_SECS_KEY_ID), data.get_by_key_id(*LAST_KEY_ID)) {
+            (&Value::U64(bucket_secs), &Value::U64(last)) if bucket_secs > 0 => (bucket_secs, last),
+            _ => return None
+        };
+        let counts: Vec<u64> = match data.get_by_key_id(*COUNTS_KEY_ID) {
+            &Value::Array(ref counts) => counts.iter().map(|count| match count { &Value::U64(n) => n, _ => 0 }).collect(),
+            _ => return None
+        };
+        if counts.is_empty() { return None; }
+        Some(CounterBuckets { bucket_secs, last, counts })
+    }
+
+    pub fn to_cell(&self, id: &Id) -> Cell {
+        let mut data = Map::new();
+        data.insert_key_id(*BUCKET_SECS_KEY_ID, Value::U64(self.bucket_secs));
+        data.insert_key_id(*LAST_KEY_ID, Value::U64(self.last));
+        data.insert_key_id(*COUNTS_KEY_ID, Value::Array(self.counts.iter().map(|n| Value::U64(*n)).collect()));
+        data.insert_key_id(*TOTAL_KEY_ID, Value::U64(self.total()));
+        Cell::new_with_id(WINDOW_COUNTER_SCHEMA_ID, id, Value::Map(data))
+    }
+
+    // moves the ring on to the bucket of the time, clearing the buckets that left the window
+    pub fn advance(&mut self, now_secs: u64) {
+        let current = now_secs / self.bucket_secs;
+        if current <= self.last { return; }
+        let len = self.counts.len() as u64;
+        let steps = ::std::cmp::min(current - self.last, len);
+        for step in 1..steps + 1 {
+            self.counts[((self.last + step) % len) as usize] = 0;
+        }
+        self.last = current;
+    }
+
+    pub fn add(&mut self, now_secs: u64, delta: u64) {
+        self.advance(now_secs);
+        let len = self.counts.len() as u64;
+        let bucket = &mut self.counts[(self.last % len) as usize];
+        *bucket = bucket.saturating_add(delta);
+    }
+
+    // the sum of the buckets as of the last advance
+    pub fn total(&self) -> u64 {
+        self.counts.iter().fold(0u64, |total, n| total.saturating_add(*n))
+    }
+
+    pub fn total_at(&self, now_secs: u64) -> u64 {
+        let mut buckets = self.clone();
+        buckets.advance(now_secs);
+        buckets.total()
+    }
+
+    fn fits(&self, counter: &WindowCounter) -> bool {
+        self.bucket_secs == counter.bucket_secs() && self.counts.len() == ::std::cmp::max(counter.buckets as usize, 1)
+    }
+}
+
+impl <'a> GraphTransaction<'a> {
+    // Adds to the counter of the owner, a counter whose window changed since starts over
+    pub fn bump_counter(&self, owner: &Id, counter: &WindowCounter, delta: u64) -> Result<(), TxnError> {
+        // nothing is counted on a read-only graph, like nothing is linked
+        if self.read_only { return Ok(()); }
+        let id = counter_id(owner, &counter.name);
+        let stored = self.neb_txn.read(&id)?;
+        let mut buckets = match stored.as_ref().and_then(CounterBuckets::from_cell) {
+            Some(ref buckets) if buckets.fits(counter) => buckets.clone(),
+            _ => CounterBuckets::new(counter)
+        };
+        buckets.add(now_secs(), delta);
+        let cell = buckets.to_cell(&id);
+        match stored {
+            Some(_) => self.neb_txn.update(&cell)?,
+            None => self.neb_txn.write(&cell)?
+        }
+        self.record_undo(Undo::WindowCounter(id, stored));
+        Ok(())
+    }
+
+    // what the counters of the edge schema count for a new link
+    pub(super) fn count_link(&self, schema_id: u32, from: &Id, to: &Id) -> Result<(), TxnError> {
+        for counter in self.schemas.window_counters(schema_id) {
+            for owner in counter.owners(from, to) {
+                self.bump_counter(&owner, &counter, 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    // 0 for owners nothing was counted for within the window
+    pub fn window_count(&self, owner: &Id, name: &str) -> Result<u64, TxnError> {
+        Ok(self.neb_txn.read(&counter_id(owner, name))?
+            .and_then(|cell| CounterBuckets::from_cell(&cell))
+            .map_or(0, |buckets| buckets.total_at(now_secs())))
+    }
+
+    // brings the counters up to the time, removing those that came down to zero
+    fn decay_counters(&self, ids: &[Id], now_secs: u64) -> Result<DecayRound, TxnError> {
+        let mut round = DecayRound::default();
+        for id in ids {
+            let mut buckets = match self.neb_txn.read(id)?.as_ref().and_then(CounterBuckets::from_cell) {
+                Some(buckets) => buckets, None => continue
+            };
+            round.checked += 1;
+            let before = buckets.clone();
+            buckets.advance(now_secs);
+            if buckets.total() == 0 {
+                self.neb_txn.remove(id)?;
+                round.removed += 1;
+            } else if buckets != before {
+                self.neb_txn.update(&buckets.to_cell(id))?;
+                round.decayed += 1;
+            }
+        }
+        Ok(round)
+    }
+}
+
+impl GraphInner {
+    pub fn window_counts(this: Arc<Self>, owners: Vec<Id>, name: String)
+        -> impl Future<Item = Vec<u64>, Error = TxnError>
+    {
+        this.graph_transaction(move |txn| {
+            owners.iter().map(|owner| txn.window_count(owner, &name)).collect()
+        })
+    }
+}
+
+// Runs one round, None when the job was stopped half way
+pub fn decay_round(graph: &Arc<GraphInner>, options: &CounterDecayOptions, running: &AtomicBool) -> Option<DecayRound> {
+    let mut round = DecayRound::default();
+    // replicas leave the counters to the writable cluster
+    if graph.is_read_only() { return Some(round); }
+    let page_size = ::std::cmp::max(options.page_size, 1);
+    for page in scan::scan_cells(graph.neb_client.clone(), WINDOW_COUNTER_SCHEMA_ID, page_size).wait() {
+        if !running.load(Ordering::Relaxed) { return None; }
+        let ids: Vec<Id> = match page {
+            Ok(cells) => cells.iter().map(|cell| cell.id()).collect(),
+            Err(e) => { warn!("Counter decay cannot scan the counters: {:?}", e); break; }
+        };
+        let now = now_secs();
+        match graph.graph_transaction(move |txn| txn.decay_counters(&ids, now)).wait() {
+            Ok(page_round) => {
+                round.checked += page_round.checked;
+                round.decayed += page_round.decayed;
+                round.removed += page_round.removed;
+            },
+            Err(e) => debug!("Counter decay skipped a page: {:?}", e)
+        }
+    }
+    Some(round)
+}
+
+pub fn start_decay(graph: Arc<GraphInner>, options: CounterDecayOptions, running: Arc<AtomicBool>)
+    -> thread::JoinHandle<()>
+{
+    let interval = Duration::from_secs(options.interval_secs);
+    thread::Builder::new()
+        .name("morpheus-counter-decay".to_string())
+        .spawn(move || {
+            while pause(&running, interval) {
+                let round = match decay_round(&graph, &options, &running) {
+                    Some(round) => round, None => break
+                };
+                debug!("Counter decay checked {} counters, decayed {} and removed {}",
+                       round.checked, round.decayed, round.removed);
+            }
+            debug!("Counter decay stopped");
+        })
+        .unwrap()
+}
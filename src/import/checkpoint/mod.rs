@@ -0,0 +1,72 @@
+// Progress of named imports kept in raft, so an import cut off by a crash or a restart picks up
+// at its last checkpoint instead of starting over. A checkpoint is only committed once everything
+// read before it is written, the records after it may or may not be. Checkpointed imports give
+// their vertices ids derived from the import name and the source key, so the resumed import finds
+// vertices written before the cut without the keys the Loader kept in memory, and while replaying
+// up to the next checkpoint it skips vertices and edges that are already there.
+
+use bifrost::raft::RaftService;
+use bifrost::raft::client::RaftClient;
+use bifrost::raft::state_machine::master::ExecError;
+use bifrost_hasher::hash_str;
+use neb::ram::types::Id;
+
+use std::sync::Arc;
+
+use import::checkpoint::sm::import_checkpoints::client::SMClient;
+
+mod sm;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Checkpoint {
+    // position of the file in the list given to the import, and its name
+    pub file_index: usize,
+    pub file: String,
+    // byte offset, line and record number of the next record to read in it
+    pub offset: u64,
+    pub line: u64,
+    pub record: u64,
+    // checkpoints committed so far
+    pub batch: u64,
+    // set when the import ran to its end, running it again does nothing
+    pub finished: bool
+}
+
+pub struct CheckpointStore {
+    sm_client: Arc<SMClient>
+}
+
+pub fn generate_sm_id<'a>(group: &'a str) -> u64 {
+    hash_str(&format!("{}-{}", sm::DEFAULT_RAFT_PREFIX, group))
+}
+
+// the id a checkpointed import gives the vertex of a source key
+pub fn vertex_id<'a>(import: &'a str, key: &'a str) -> Id {
+    Id::new(hash_str(&format!("{}/{}", import, key)), hash_str(&format!("{}#{}", key, import)))
+}
+
+impl CheckpointStore {
+    pub fn new_meta_service<'a>(group: &'a str, raft_service: &Arc<RaftService>) {
+        let mut checkpoints_sm = sm::import_checkpoints::Map::new(generate_sm_id(group));
+        checkpoints_sm.init_callback(raft_service);
+        raft_service.register_state_machine(Box::new(checkpoints_sm));
+    }
+
+    pub fn new_client<'a>(group: &'a str, raft_client: &Arc<RaftClient>) -> Arc<CheckpointStore> {
+        Arc::new(CheckpointStore { sm_client: Arc::new(SMClient::new(generate_sm_id(group), &raft_client)) })
+    }
+
+    // None when the import never committed one
+    pub fn get<'a>(&self, import: &'a str) -> Result<Option<Checkpoint>, ExecError> {
+        self.sm_client.get(&import.to_string())
+    }
+
+    pub fn commit<'a>(&self, import: &'a str, checkpoint: &Checkpoint) -> Result<(), ExecError> {
+        self.sm_client.insert(&import.to_string(), checkpoint).map(|_| ())
+    }
+
+    // the next run of the import starts from the first record again
+    pub fn reset<'a>(&self, import: &'a str) -> Result<(), ExecError> {
+        self.commit(import, &Checkpoint::default())
+    }
+}
@@ -0,0 +1,7 @@
+use std::collections::HashMap;
+
+use import::checkpoint::Checkpoint;
+
+pub static DEFAULT_RAFT_PREFIX: &'static str = "MORPHEUS_IMPORT_CHECKPOINT_RAFT_SM";
+
+def_store_hash_map!(import_checkpoints <String, Checkpoint>);
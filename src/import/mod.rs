@@ -0,0 +1,398 @@
+// Bulk loading from other graph stores. Readers turn their source into vertices and edges named by
+// the source's own keys, a Loader creates the schemas they name on first sight and writes them
+// batch_size records per transaction, resolving edge ends through the keys of the vertices it wrote.
+// Records that cannot be written are skipped and listed in the report, the rest of the batch goes on.
+// Exports write the graph out in the formats the readers take and share their error type, with
+// the values of chosen fields scrubbed, see import::scrub.
+// Streams of mutation commands are applied as they come, see import::stream.
+// Named imports can commit checkpoints to resume from after a crash, see import::checkpoint.
+
+use neb::ram::types::{Id, key_hash};
+use neb::dovahkiin::types::{Map, Value};
+use neb::client::transaction::TxnError;
+use bifrost::raft::state_machine::master::ExecError;
+use futures::prelude::*;
+use csv;
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+
+use graph::{Graph, ScanVerticesError};
+use graph::edge::{EdgeAttributes, EdgeType};
+use graph::placement::Placement;
+use import::checkpoint::{Checkpoint, CheckpointStore};
+use server::schema::{MorpheusSchema, SchemaError, SchemaType};
+
+pub mod checkpoint;
+pub mod neo4j;
+pub mod rdf;
+pub mod scrub;
+pub mod stream;
+
+pub static DEFAULT_BATCH_SIZE: usize = 256;
+
+#[derive(Debug)]
+pub enum ImportError {
+    IoError(io::Error),
+    CsvError(csv::Error),
+    SchemaError(SchemaError),
+    // a schema of that name exists but is not of the kind the records need
+    SchemaConflict(String),
+    // source, line and what is wrong with the text there
+    SyntaxError(String, u64, String),
+    TxnError(TxnError),
+    ScanVerticesError(ScanVerticesError),
+    CheckpointError(ExecError),
+    // the checkpoint is in the middle of another file than the import was given at its position
+    CheckpointMismatch(Checkpoint),
+    ReadOnly
+}
+
+// a record left out of the import, line is 0 for sources without lines
+#[derive(Debug, Clone)]
+pub struct SkippedRecord {
+    pub source: String,
+    pub line: u64,
+    pub reason: String
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ImportReport {
+    pub vertices: usize,
+    pub edges: usize,
+    pub schemas_created: Vec<String>,
+    pub skipped: Vec<SkippedRecord>,
+    // where a checkpointed import went on, counts are of this run only
+    pub resumed_from: Option<Checkpoint>
+}
+
+fn add_value(data: &mut Map, name: &str, value: Value) {
+    let merged = match data.get_by_key_id(key_hash(name)) {
+        &Value::Null => value,
+        &Value::Array(ref items) => {
+            let mut items = items.clone();
+            items.push(value);
+            Value::Array(items)
+        },
+        existing => Value::Array(vec![existing.clone(), value])
+    };
+    data.insert(name, merged);
+}
+
+struct PendingVertex {
+    key: String,
+    schema: u32,
+    data: Map,
+    source: String,
+    line: u64
+}
+
+struct PendingEdge {
+    from: String,
+    to: String,
+    schema: u32,
+    body: Option<Map>,
+    source: String,
+    line: u64
+}
+
+pub struct Loader {
+    graph: Arc<Graph>,
+    batch_size: usize,
+    // vertex ids by source key
+    ids: HashMap<String, Id>,
+    // schema ids by name, with whether edges of the schema take a body
+    schemas: HashMap<String, (u32, bool)>,
+    vertices: Vec<PendingVertex>,
+    // position in vertices by key
+    pending: HashMap<String, usize>,
+    edges: Vec<PendingEdge>,
+    // properties of vertices already written, by key
+    updates: HashMap<String, Vec<(String, Value)>>,
+    // store and name of a checkpointed import
+    checkpoints: Option<(Arc<CheckpointStore>, String)>,
+    // the last checkpoint committed or resumed from
+    checkpoint: Checkpoint,
+    // records after the checkpoint resumed from may be written already, until the next is committed
+    replaying: bool,
+    report: ImportReport
+}
+
+impl Loader {
+    pub fn new(graph: &Arc<Graph>, batch_size: usize) -> Result<Loader, ImportError> {
+        if graph.is_read_only() { return Err(ImportError::ReadOnly); }
+        Ok(Loader {
+            graph: graph.clone(),
+            batch_size: ::std::cmp::max(batch_size, 1),
+            ids: HashMap::new(),
+            schemas: HashMap::new(),
+            vertices: Vec::new(),
+            pending: HashMap::new(),
+            edges: Vec::new(),
+            updates: HashMap::new(),
+            checkpoints: None,
+            checkpoint: Checkpoint::default(),
+            replaying: false,
+            report: ImportReport::default()
+        })
+    }
+
+    // A loader for the named import, starting at its last checkpoint if it has one
+    pub fn resume<'a>(graph: &Arc<Graph>, batch_size: usize, store: &Arc<CheckpointStore>, import: &'a str)
+        -> Result<Loader, ImportError>
+    {
+        let mut loader = Loader::new(graph, batch_size)?;
+        if let Some(checkpoint) = store.get(import).map_err(ImportError::CheckpointError)? {
+            loader.replaying = true;
+            loader.checkpoint = checkpoint.clone();
+            loader.report.resumed_from = Some(checkpoint);
+        }
+        loader.checkpoints = Some((store.clone(), import.to_string()));
+        Ok(loader)
+    }
+
+    pub fn checkpoint(&self) -> &Checkpoint {
+        &self.checkpoint
+    }
+
+    // Writes everything buffered, then records that the import goes on at next
+    pub fn commit_checkpoint(&mut self, mut next: Checkpoint) -> Result<(), ImportError> {
+        let (store, import) = match self.checkpoints {
+            Some((ref store, ref import)) => (store.clone(), import.clone()),
+            None => return Ok(())
+        };
+        self.flush_edges()?;
+        self.flush_updates()?;
+        next.batch = self.checkpoint.batch + 1;
+        store.commit(&import, &next).map_err(ImportError::CheckpointError)?;
+        self.checkpoint = next;
+        self.replaying = false;
+        Ok(())
+    }
+
+    pub fn vertex_id(&self, key: &str) -> Option<Id> {
+        self.ids.get(key).cloned()
+    }
+
+    // written or waiting in the batch
+    pub fn has_vertex(&self, key: &str) -> bool {
+        self.ids.contains_key(key) || self.pending.contains_key(key)
+    }
+
+    // dynamic schemas take whatever properties the source has
+    fn vertex_schema(&mut self, name: &str) -> Result<u32, ImportError> {
+        if let Some(&(id, _)) = self.schemas.get(name) { return Ok(id); }
+        let id = match self.graph.schema_by_name(name) {
+            Some((id, SchemaType::Vertex)) => id,
+            Some(_) => return Err(ImportError::SchemaConflict(name.to_string())),
+            None => {
+                let id = self.graph.new_vertex_group(MorpheusSchema::new(name, None, &Vec::new(), true))
+                    .wait().map_err(ImportError::SchemaError)?;
+                self.report.schemas_created.push(name.to_string());
+                id
+            }
+        };
+        self.schemas.insert(name.to_string(), (id, false));
+        Ok(id)
+    }
+
+    // edge schemas are directed, with dynamic bodies when has_body is set
+    fn edge_schema(&mut self, name: &str, has_body: bool) -> Result<(u32, bool), ImportError> {
+        if let Some(&schema) = self.schemas.get(name) { return Ok(schema); }
+        let schema = match self.graph.schema_by_name(name) {
+            Some((id, SchemaType::Edge(attrs))) => (id, attrs.has_body),
+            Some(_) => return Err(ImportError::SchemaConflict(name.to_string())),
+            None => {
+                let id = self.graph.new_edge_group(
+                    MorpheusSchema::new(name, None, &Vec::new(), has_body),
+                    EdgeAttributes::new(EdgeType::Directed, has_body)
+                ).wait().map_err(ImportError::SchemaError)?;
+                self.report.schemas_created.push(name.to_string());
+                (id, has_body)
+            }
+        };
+        self.schemas.insert(name.to_string(), schema);
+        Ok(schema)
+    }
+
+    pub fn skip(&mut self, source: &str, line: u64, reason: String) {
+        self.report.skipped.push(SkippedRecord { source: source.to_string(), line, reason });
+    }
+
+    pub fn add_vertex(&mut self, key: String, schema: &str, data: Map, source: &str, line: u64)
+        -> Result<(), ImportError>
+    {
+        let schema = self.vertex_schema(schema)?;
+        self.pending.insert(key.clone(), self.vertices.len());
+        self.vertices.push(PendingVertex { key, schema, data, source: source.to_string(), line });
+        if self.vertices.len() >= self.batch_size { self.flush_vertices()?; }
+        Ok(())
+    }
+
+    // Adds the value to a property of a vertex added before, repeated values make an array
+    pub fn add_property(&mut self, key: &str, name: &str, value: Value) -> Result<(), ImportError> {
+        if let Some(&pos) = self.pending.get(key) {
+            add_value(&mut self.vertices[pos].data, name, value);
+            return Ok(());
+        }
+        if !self.ids.contains_key(key) { return Ok(()); }
+        self.updates.entry(key.to_string()).or_insert_with(Vec::new).push((name.to_string(), value));
+        if self.updates.len() >= self.batch_size { self.flush_updates()?; }
+        Ok(())
+    }
+
+    // ends are source keys of vertices added before, edges with unknown ends are skipped when flushed
+    pub fn add_edge(&mut self, from: String, schema: &str, to: String, body: Option<Map>, has_body: bool,
+                    source: &str, line: u64) -> Result<(), ImportError>
+    {
+        let (schema, schema_has_body) = self.edge_schema(schema, has_body)?;
+        // bodied schemas need a body, bodyless ones cannot keep one
+        let body = if schema_has_body { Some(body.unwrap_or_else(Map::new)) } else { None };
+        self.edges.push(PendingEdge { from, to, schema, body, source: source.to_string(), line });
+        if self.edges.len() >= self.batch_size { self.flush_edges()?; }
+        Ok(())
+    }
+
+    fn flush_vertices(&mut self) -> Result<(), ImportError> {
+        if self.vertices.is_empty() { return Ok(()); }
+        let pending = ::std::mem::replace(&mut self.vertices, Vec::new());
+        self.pending.clear();
+        // checkpointed imports pick the ids, see import::checkpoint
+        let writes: Vec<(u32, Map, Option<Id>)> = pending.iter().map(|v| {
+            let id = self.checkpoints.as_ref().map(|&(_, ref import)| checkpoint::vertex_id(import, &v.key));
+            (v.schema, v.data.clone(), id)
+        }).collect();
+        let replaying = self.replaying;
+        let results = self.graph.graph_transaction(move |txn| {
+            let mut results = Vec::with_capacity(writes.len());
+            for &(schema, ref data, id) in &writes {
+                let placement = match id {
+                    Some(id) if replaying && txn.vertex_exists(id)? => { results.push(Ok((id, false))); continue; },
+                    Some(id) => Placement::Exact(id),
+                    None => Placement::Auto
+                };
+                results.push(txn.new_vertex_with_placement(schema, data.clone(), placement)?
+                    .map(|vertex| (vertex.cell.id(), true))
+                    .map_err(|e| format!("{:?}", e)));
+            }
+            Ok(results)
+        }).wait().map_err(ImportError::TxnError)?;
+        for (vertex, result) in pending.into_iter().zip(results.into_iter()) {
+            match result {
+                Ok((id, created)) => {
+                    self.ids.insert(vertex.key, id);
+                    if created { self.report.vertices += 1; }
+                },
+                Err(reason) => self.skip(&vertex.source, vertex.line, reason)
+            }
+        }
+        Ok(())
+    }
+
+    fn flush_updates(&mut self) -> Result<(), ImportError> {
+        if self.updates.is_empty() { return Ok(()); }
+        let updates: Vec<(Id, Vec<(String, Value)>)> = ::std::mem::replace(&mut self.updates, HashMap::new())
+            .into_iter()
+            .filter_map(|(key, properties)| self.ids.get(&key).map(|id| (*id, properties)))
+            .collect();
+        self.graph.graph_transaction(move |txn| {
+            for &(id, ref properties) in &updates {
+                txn.update_vertex(id, |mut vertex| {
+                    if let Value::Map(ref mut data) = vertex.cell.data {
+                        for &(ref name, ref value) in properties {
+                            add_value(data, name, value.clone());
+                        }
+                    }
+                    Some(vertex)
+                })?;
+            }
+            Ok(())
+        }).wait().map_err(ImportError::TxnError)
+    }
+
+    // Vertices a checkpointed import wrote before it resumed, for the ends this run has not seen.
+    // Vertices of keyed schemas keep their key ids and are not found, edges to them are skipped.
+    fn earlier_vertices(&self, edges: &[PendingEdge]) -> Result<HashMap<String, Id>, ImportError> {
+        let import = match self.checkpoints {
+            Some((_, ref import)) => import.clone(),
+            None => return Ok(HashMap::new())
+        };
+        let mut keys: Vec<String> = edges.iter()
+            .flat_map(|edge| vec![&edge.from, &edge.to])
+            .filter(|key| !self.ids.contains_key(*key))
+            .cloned()
+            .collect();
+        keys.sort();
+        keys.dedup();
+        if keys.is_empty() { return Ok(HashMap::new()); }
+        self.graph.graph_transaction(move |txn| {
+            let mut found = HashMap::new();
+            for key in &keys {
+                let id = checkpoint::vertex_id(&import, key);
+                if txn.vertex_exists(id)? { found.insert(key.clone(), id); }
+            }
+            Ok(found)
+        }).wait().map_err(ImportError::TxnError)
+    }
+
+    fn flush_edges(&mut self) -> Result<(), ImportError> {
+        // ends may still be waiting in the vertex batch
+        self.flush_vertices()?;
+        if self.edges.is_empty() { return Ok(()); }
+        let pending = ::std::mem::replace(&mut self.edges, Vec::new());
+        let earlier = self.earlier_vertices(&pending)?;
+        self.ids.extend(earlier);
+        let mut resolved = Vec::new();
+        let mut resolved_edges = Vec::new();
+        for edge in pending {
+            match (self.vertex_id(&edge.from), self.vertex_id(&edge.to)) {
+                (Some(from), Some(to)) => {
+                    resolved.push((from, edge.schema, to, edge.body.clone()));
+                    resolved_edges.push(edge);
+                },
+                (None, _) => {
+                    let reason = format!("unknown start vertex {}", edge.from);
+                    self.skip(&edge.source, edge.line, reason);
+                },
+                (_, None) => {
+                    let reason = format!("unknown end vertex {}", edge.to);
+                    self.skip(&edge.source, edge.line, reason);
+                }
+            }
+        }
+        if resolved.is_empty() { return Ok(()); }
+        // replayed edges already written are left alone, so are parallel ones in that stretch
+        let replaying = self.replaying;
+        let results = self.graph.graph_transaction(move |txn| {
+            let mut results = Vec::with_capacity(resolved.len());
+            for &(from, schema, to, ref body) in &resolved {
+                if replaying {
+                    if let Ok(true) = txn.has_edge(from, schema, to)? { results.push(Ok(false)); continue; }
+                }
+                results.push(txn.link(from, schema, to, body.clone())?.map(|_| true).map_err(|e| format!("{:?}", e)));
+            }
+            Ok(results)
+        }).wait().map_err(ImportError::TxnError)?;
+        for (edge, result) in resolved_edges.into_iter().zip(results.into_iter()) {
+            match result {
+                Ok(true) => self.report.edges += 1,
+                Ok(false) => {},
+                Err(reason) => self.skip(&edge.source, edge.line, reason)
+            }
+        }
+        Ok(())
+    }
+
+    // writes what is still buffered, a checkpointed import is marked finished
+    pub fn finish(mut self) -> Result<ImportReport, ImportError> {
+        if self.checkpoints.is_some() {
+            let finished = Checkpoint { finished: true, ..self.checkpoint.clone() };
+            self.commit_checkpoint(finished)?;
+        } else {
+            self.flush_edges()?;
+            self.flush_updates()?;
+        }
+        Ok(self.report)
+    }
+}
@@ -0,0 +1,281 @@
+// Neo4j CSV dumps. Two layouts are read, told apart by their headers:
+// - apoc.export.csv.all, one file with _id, _labels, _start, _end and _type columns next to the
+//   properties. Values are untyped, numbers and booleans are recognised, everything else is a string.
+// - neo4j-admin import files, nodes with name:ID(group), :LABEL and name:type columns and
+//   relationships with :START_ID(group), :END_ID(group) and :TYPE. Untyped columns are strings.
+// Labels map to vertex schemas and relationship types to directed edge schemas, created when first
+// seen. A node with several labels goes to the schema of its first one. Relationships can only point
+// to nodes imported before them, so list node files before relationship files.
+// There is no Bolt reader, export the database with either tool first.
+// Named imports commit a checkpoint every batch_size records and at the end of every file, and
+// resume by seeking to the last one, so the files must not change in between.
+
+use neb::dovahkiin::types::{Map, Value};
+use csv;
+
+use std::sync::Arc;
+
+use graph::Graph;
+use import::{Loader, ImportReport, ImportError, DEFAULT_BATCH_SIZE};
+use import::checkpoint::{Checkpoint, CheckpointStore};
+
+#[derive(Debug, Clone)]
+pub struct Neo4jCsvOptions {
+    pub delimiter: u8,
+    // separates the items of array columns in neo4j-admin files
+    pub array_delimiter: char,
+    // schema of nodes without labels
+    pub default_label: String,
+    // relationship types get edge schemas with bodies to keep their properties
+    pub edge_bodies: bool,
+    pub batch_size: usize
+}
+
+impl Default for Neo4jCsvOptions {
+    fn default() -> Neo4jCsvOptions {
+        Neo4jCsvOptions {
+            delimiter: b',',
+            array_delimiter: ';',
+            default_label: "Node".to_string(),
+            edge_bodies: true,
+            batch_size: DEFAULT_BATCH_SIZE
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Column {
+    // neo4j-admin keeps named id columns as properties too
+    Id { group: String, property: Option<String> },
+    Labels,
+    Start(String),
+    End(String),
+    Type,
+    Property(String, Option<String>),
+    Ignore
+}
+
+// the id space in parentheses, ID(people) is in people
+fn id_group(kind: &str) -> String {
+    match (kind.find('('), kind.rfind(')')) {
+        (Some(open), Some(close)) if open < close => kind[open + 1..close].to_string(),
+        _ => String::new()
+    }
+}
+
+fn parse_header(header: &str) -> Column {
+    match header {
+        "_id" => return Column::Id { group: String::new(), property: None },
+        "_labels" => return Column::Labels,
+        "_start" => return Column::Start(String::new()),
+        "_end" => return Column::End(String::new()),
+        "_type" => return Column::Type,
+        _ => {}
+    }
+    let colon = match header.find(':') {
+        Some(colon) => colon, None => return Column::Property(header.to_string(), None)
+    };
+    let (name, kind) = (&header[..colon], &header[colon + 1..]);
+    let upper = kind.to_uppercase();
+    if upper.starts_with("START_ID") { Column::Start(id_group(kind)) }
+    else if upper.starts_with("END_ID") { Column::End(id_group(kind)) }
+    else if upper.starts_with("ID") {
+        let property = if name.is_empty() { None } else { Some(name.to_string()) };
+        Column::Id { group: id_group(kind), property }
+    }
+    else if upper == "LABEL" { Column::Labels }
+    else if upper == "TYPE" { Column::Type }
+    else if upper == "IGNORE" { Column::Ignore }
+    else { Column::Property(name.to_string(), Some(kind.to_lowercase())) }
+}
+
+fn key(group: &str, id: &str) -> String {
+    format!("{}:{}", group, id)
+}
+
+fn infer_value(raw: &str) -> Value {
+    if raw == "true" { return Value::Bool(true); }
+    if raw == "false" { return Value::Bool(false); }
+    if let Ok(n) = raw.parse::<i64>() { return Value::I64(n); }
+    if let Ok(n) = raw.parse::<f64>() { return Value::F64(n); }
+    Value::String(raw.to_string())
+}
+
+fn parse<T: ::std::str::FromStr>(raw: &str, value_type: &str) -> Result<T, String> {
+    raw.parse().map_err(|_| format!("'{}' is not a valid {}", raw, value_type))
+}
+
+fn typed_scalar(raw: &str, value_type: &str) -> Result<Value, String> {
+    Ok(match value_type {
+        "int" => Value::I32(parse(raw, value_type)?),
+        "long" => Value::I64(parse(raw, value_type)?),
+        "short" => Value::I16(parse(raw, value_type)?),
+        "byte" => Value::I8(parse(raw, value_type)?),
+        "float" => Value::F32(parse(raw, value_type)?),
+        "double" => Value::F64(parse(raw, value_type)?),
+        "boolean" => Value::Bool(parse(raw, value_type)?),
+        // strings, chars and the temporal and spatial types, kept as written
+        _ => Value::String(raw.to_string())
+    })
+}
+
+fn parse_value(raw: &str, value_type: &Option<String>, array_delimiter: char) -> Result<Value, String> {
+    match value_type {
+        &None => Ok(infer_value(raw)),
+        &Some(ref t) if t.ends_with("[]") => {
+            let item_type = &t[..t.len() - 2];
+            let items: Result<Vec<Value>, String> = raw.split(array_delimiter)
+                .map(|item| typed_scalar(item, item_type))
+                .collect();
+            items.map(Value::Array)
+        },
+        &Some(ref t) => typed_scalar(raw, t)
+    }
+}
+
+// label lists are :A:B in apoc exports and A;B in neo4j-admin files
+fn first_label(raw: &str) -> Option<&str> {
+    raw.split(|c| c == ':' || c == ';').map(|l| l.trim()).find(|l| !l.is_empty())
+}
+
+struct Row<'a> {
+    id: Option<(&'a str, &'a str)>,
+    label: Option<&'a str>,
+    start: Option<(&'a str, &'a str)>,
+    end: Option<(&'a str, &'a str)>,
+    rel_type: Option<&'a str>,
+    properties: Map,
+    has_properties: bool
+}
+
+fn read_row<'a>(columns: &'a [Column], record: &'a csv::StringRecord, array_delimiter: char, infer_types: bool)
+    -> Result<Row<'a>, String>
+{
+    let mut row = Row {
+        id: None, label: None, start: None, end: None, rel_type: None,
+        properties: Map::new(), has_properties: false
+    };
+    for (column, raw) in columns.iter().zip(record.iter()) {
+        if raw.is_empty() { continue; }
+        match column {
+            &Column::Id { ref group, ref property } => {
+                row.id = Some((group.as_str(), raw));
+                if let &Some(ref name) = property {
+                    row.properties.insert(name, Value::String(raw.to_string()));
+                    row.has_properties = true;
+                }
+            },
+            &Column::Labels => row.label = first_label(raw),
+            &Column::Start(ref group) => row.start = Some((group.as_str(), raw)),
+            &Column::End(ref group) => row.end = Some((group.as_str(), raw)),
+            &Column::Type => row.rel_type = Some(raw),
+            &Column::Property(ref name, ref value_type) => {
+                let value = match (value_type, infer_types) {
+                    (&None, true) => Value::String(raw.to_string()),
+                    _ => parse_value(raw, value_type, array_delimiter)
+                        .map_err(|e| format!("property {}: {}", name, e))?
+                };
+                row.properties.insert(name, value);
+                row.has_properties = true;
+            },
+            &Column::Ignore => {}
+        }
+    }
+    Ok(row)
+}
+
+fn checkpoint_at(index: usize, file: &str, position: &csv::Position) -> Checkpoint {
+    Checkpoint {
+        file_index: index,
+        file: file.to_string(),
+        offset: position.byte(),
+        line: position.line(),
+        record: position.record(),
+        ..Checkpoint::default()
+    }
+}
+
+// index is the position of the file in the import, resume_at a checkpoint inside it
+fn import_file(loader: &mut Loader, index: usize, file: &str, resume_at: Option<&Checkpoint>, options: &Neo4jCsvOptions)
+    -> Result<(), ImportError>
+{
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(options.delimiter)
+        .flexible(true)
+        .from_path(file)
+        .map_err(ImportError::CsvError)?;
+    let headers = reader.headers().map_err(ImportError::CsvError)?.clone();
+    let columns: Vec<Column> = headers.iter().map(parse_header).collect();
+    // apoc exports carry no types, values are guessed for those only
+    let infer_types = headers.iter().any(|h| h == "_id" || h == "_start");
+    if let Some(checkpoint) = resume_at {
+        let mut position = csv::Position::new();
+        position.set_byte(checkpoint.offset).set_line(checkpoint.line).set_record(checkpoint.record);
+        reader.seek(position).map_err(ImportError::CsvError)?;
+    }
+    let checkpoint_every = ::std::cmp::max(options.batch_size, 1);
+    let mut record = csv::StringRecord::new();
+    let mut read = 0;
+    while reader.read_record(&mut record).map_err(ImportError::CsvError)? {
+        // everything before this record is handed to the loader
+        if read > 0 && read % checkpoint_every == 0 {
+            if let Some(position) = record.position() {
+                loader.commit_checkpoint(checkpoint_at(index, file, position))?;
+            }
+        }
+        read += 1;
+        let line = record.position().map(|p| p.line()).unwrap_or(0);
+        let row = match read_row(&columns, &record, options.array_delimiter, infer_types) {
+            Ok(row) => row,
+            Err(reason) => { loader.skip(file, line, reason); continue; }
+        };
+        match (row.id, row.start, row.end) {
+            (_, Some((start_group, start)), Some((end_group, end))) => {
+                let rel_type = match row.rel_type {
+                    Some(rel_type) => rel_type,
+                    None => { loader.skip(file, line, "relationship without a type".to_string()); continue; }
+                };
+                let body = if row.has_properties { Some(row.properties) } else { None };
+                loader.add_edge(
+                    key(start_group, start), rel_type, key(end_group, end), body, options.edge_bodies, file, line
+                )?;
+            },
+            (Some((group, id)), None, None) => {
+                let label = row.label.unwrap_or(options.default_label.as_str());
+                loader.add_vertex(key(group, id), label, row.properties, file, line)?;
+            },
+            _ => loader.skip(file, line, "neither a node nor a relationship".to_string())
+        }
+    }
+    loader.commit_checkpoint(Checkpoint { file_index: index + 1, ..Checkpoint::default() })
+}
+
+// Imports the files in order, see the layouts above
+pub fn import_csv(graph: &Arc<Graph>, files: &[&str], options: &Neo4jCsvOptions) -> Result<ImportReport, ImportError> {
+    let mut loader = Loader::new(graph, options.batch_size)?;
+    for (index, file) in files.iter().enumerate() {
+        import_file(&mut loader, index, file, None, options)?;
+    }
+    loader.finish()
+}
+
+// Imports the files like import_csv as the named import, going on from its last checkpoint.
+// A finished import does nothing until its checkpoint is reset.
+pub fn import_csv_checkpointed<'a>(graph: &Arc<Graph>, files: &[&str], options: &Neo4jCsvOptions,
+                                   checkpoints: &Arc<CheckpointStore>, import: &'a str)
+    -> Result<ImportReport, ImportError>
+{
+    let mut loader = Loader::resume(graph, options.batch_size, checkpoints, import)?;
+    let checkpoint = loader.checkpoint().clone();
+    if checkpoint.finished { return loader.finish(); }
+    for (index, file) in files.iter().enumerate().skip(checkpoint.file_index) {
+        let resume_at = if index == checkpoint.file_index && checkpoint.offset > 0 {
+            if checkpoint.file != *file { return Err(ImportError::CheckpointMismatch(checkpoint.clone())); }
+            Some(&checkpoint)
+        } else {
+            None
+        };
+        import_file(&mut loader, index, file, resume_at, options)?;
+    }
+    loader.finish()
+}
@@ -0,0 +1,669 @@
+// RDF in Turtle and N-Triples, N-Triples being the subset of Turtle without prefixes and
+// abbreviations, so one streaming reader takes both. Every subject and object resource becomes a
+// vertex of the resource schema, keeping its IRI in the iri property, blank nodes keep none.
+// Triples with a resource object become edges of a directed edge schema named by the predicate,
+// literal objects become properties of the subject named by the predicate. Names under base_iri are
+// kept without it, others are the full IRI. A predicate given several literals gets an array.
+// Export writes N-Triples, which Turtle readers take as well. Vertices without an iri property are
+// written as blank nodes, edge bodies are left out as triples have nowhere to put them.
+// Relative IRIs are resolved by appending them to the base, importing the same document twice
+// creates its resources twice. Exports apply the scrub rules, see import::scrub. A hashed iri
+// property makes an IRI of the digest under the base, other rules on it make blank nodes.
+
+use neb::ram::types::{Id, key_hash};
+use neb::dovahkiin::types::{Map, Value};
+use futures::prelude::*;
+
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::sync::Arc;
+
+use graph::Graph;
+use graph::EdgeDirection;
+use graph::vertex::Vertex;
+use server::schema::SchemaType;
+use import::{Loader, ImportReport, ImportError, DEFAULT_BATCH_SIZE};
+use import::scrub::{ScrubOptions, ScrubRule};
+
+pub static RDF_TYPE: &'static str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+static RDF_FIRST: &'static str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#first";
+static RDF_REST: &'static str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#rest";
+static RDF_NIL: &'static str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#nil";
+static XSD: &'static str = "http://www.w3.org/2001/XMLSchema#";
+
+#[derive(Debug, Clone)]
+pub struct RdfOptions {
+    // schema of every imported resource
+    pub resource_schema: String,
+    pub iri_property: String,
+    // prefix of predicates and properties named without one
+    pub base_iri: String,
+    pub batch_size: usize,
+    // applied to the exported values
+    pub scrub: ScrubOptions
+}
+
+impl Default for RdfOptions {
+    fn default() -> RdfOptions {
+        RdfOptions {
+            resource_schema: "Resource".to_string(),
+            iri_property: "iri".to_string(),
+            base_iri: "urn:morpheus:".to_string(),
+            batch_size: DEFAULT_BATCH_SIZE,
+            scrub: ScrubOptions::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    Iri(String),
+    Blank(String),
+    Literal { lexical: String, datatype: Option<String>, language: Option<String> }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Triple {
+    pub subject: Term,
+    pub predicate: String,
+    pub object: Term
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Iri(String),
+    // prefix and local part
+    PrefixedName(String, String),
+    Blank(String),
+    Literal(String),
+    Language(String),
+    Datatype,
+    Number(String),
+    // a, true, false, PREFIX and BASE
+    Word(String),
+    Prefix,
+    Base,
+    Punct(char)
+}
+
+struct Chars<R: BufRead> {
+    input: R,
+    buffer: Vec<char>,
+    pos: usize,
+    line: u64
+}
+
+impl <R: BufRead> Chars<R> {
+    fn new(input: R) -> Chars<R> {
+        Chars { input, buffer: Vec::new(), pos: 0, line: 0 }
+    }
+
+    // reads lines until the char at offset is there, None at the end of the input
+    fn peek_at(&mut self, offset: usize) -> Result<Option<char>, String> {
+        while self.pos + offset >= self.buffer.len() {
+            let mut line = String::new();
+            let read = self.input.read_line(&mut line).map_err(|e| format!("{}", e))?;
+            if read == 0 { return Ok(None); }
+            self.buffer.drain(..self.pos);
+            self.pos = 0;
+            self.buffer.extend(line.chars());
+        }
+        Ok(Some(self.buffer[self.pos + offset]))
+    }
+
+    fn peek(&mut self) -> Result<Option<char>, String> {
+        self.peek_at(0)
+    }
+
+    fn next(&mut self) -> Result<Option<char>, String> {
+        let c = self.peek()?;
+        if let Some(c) = c {
+            self.pos += 1;
+            if c == '\n' { self.line += 1; }
+        }
+        Ok(c)
+    }
+
+    fn expect_next(&mut self) -> Result<char, String> {
+        self.next()?.ok_or_else(|| "unexpected end of input".to_string())
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == ':' || c == '%' || c == '.'
+}
+
+fn unicode_escape<R: BufRead>(chars: &mut Chars<R>, digits: usize) -> Result<char, String> {
+    let mut code = String::new();
+    for _ in 0..digits { code.push(chars.expect_next()?); }
+    u32::from_str_radix(&code, 16).ok().and_then(::std::char::from_u32)
+        .ok_or_else(|| format!("invalid escape \\u{}", code))
+}
+
+struct Lexer<R: BufRead> {
+    chars: Chars<R>
+}
+
+impl <R: BufRead> Lexer<R> {
+    fn skip_blank(&mut self) -> Result<(), String> {
+        while let Some(c) = self.chars.peek()? {
+            if c == '#' {
+                while let Some(c) = self.chars.next()? { if c == '\n' { break; } }
+            } else if c.is_whitespace() {
+                self.chars.next()?;
+            } else {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn iri(&mut self) -> Result<String, String> {
+        let mut iri = String::new();
+        loop {
+            match self.chars.expect_next()? {
+                '>' => return Ok(iri),
+                '\\' => match self.chars.expect_next()? {
+                    'u' => iri.push(unicode_escape(&mut self.chars, 4)?),
+                    'U' => iri.push(unicode_escape(&mut self.chars, 8)?),
+                    c => return Err(format!("invalid escape \\{} in IRI", c))
+                },
+                '\n' => return Err("unterminated IRI".to_string()),
+                c => iri.push(c)
+            }
+        }
+    }
+
+    fn string(&mut self, quote: char) -> Result<String, String> {
+        let long = self.chars.peek()? == Some(quote) && self.chars.peek_at(1)? == Some(quote);
+        if long {
+            self.chars.next()?;
+            self.chars.next()?;
+        } else if self.chars.peek()? == Some(quote) {
+            // the empty string
+            self.chars.next()?;
+            return Ok(String::new());
+        }
+        let mut value = String::new();
+        loop {
+            let c = self.chars.expect_next()?;
+            if c == quote {
+                if !long { return Ok(value); }
+                if self.chars.peek()? == Some(quote) && self.chars.peek_at(1)? == Some(quote) {
+                    self.chars.next()?;
+                    self.chars.next()?;
+                    return Ok(value);
+                }
+                value.push(c);
+                continue;
+            }
+            match c {
+                '\\' => value.push(match self.chars.expect_next()? {
+                    't' => '\t', 'b' => '\u{8}', 'n' => '\n', 'r' => '\r', 'f' => '\u{c}',
+                    '"' => '"', '\'' => '\'', '\\' => '\\',
+                    'u' => unicode_escape(&mut self.chars, 4)?,
+                    'U' => unicode_escape(&mut self.chars, 8)?,
+                    c => return Err(format!("invalid escape \\{}", c))
+                }),
+                '\n' if !long => return Err("unterminated string".to_string()),
+                c => value.push(c)
+            }
+        }
+    }
+
+    // a name runs to whitespace or punctuation, a dot only belongs to it when more name follows
+    fn name(&mut self, first: char) -> Result<String, String> {
+        let mut name = first.to_string();
+        while let Some(c) = self.chars.peek()? {
+            if !is_name_char(c) { break; }
+            if c == '.' && !self.chars.peek_at(1)?.map(|n| is_name_char(n) && n != '.').unwrap_or(false) { break; }
+            name.push(c);
+            self.chars.next()?;
+        }
+        Ok(name)
+    }
+
+    fn number(&mut self, first: char) -> Result<String, String> {
+        let mut number = first.to_string();
+        while let Some(c) = self.chars.peek()? {
+            let exponent_sign = (c == '+' || c == '-') && number.ends_with(|e| e == 'e' || e == 'E');
+            let fraction = c == '.' && self.chars.peek_at(1)?.map(|n| n.is_digit(10)).unwrap_or(false);
+            if !(c.is_digit(10) || c == 'e' || c == 'E' || exponent_sign || fraction) { break; }
+            number.push(c);
+            self.chars.next()?;
+        }
+        Ok(number)
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token>, String> {
+        self.skip_blank()?;
+        let c = match self.chars.next()? { Some(c) => c, None => return Ok(None) };
+        Ok(Some(match c {
+            '<' => Token::Iri(self.iri()?),
+            '"' | '\'' => Token::Literal(self.string(c)?),
+            '^' => {
+                if self.chars.next()? != Some('^') { return Err("expected ^^".to_string()); }
+                Token::Datatype
+            },
+            '@' => {
+                let word = self.name('@')?;
+                match word.as_str() {
+                    "@prefix" => Token::Prefix,
+                    "@base" => Token::Base,
+                    _ => Token::Language(word[1..].to_string())
+                }
+            },
+            '.' | ';' | ',' | '[' | ']' | '(' | ')' => Token::Punct(c),
+            '_' if self.chars.peek()? == Some(':') => {
+                self.chars.next()?;
+                let label = self.name('_')?;
+                Token::Blank(label[1..].to_string())
+            },
+            c if c.is_digit(10) || c == '+' || c == '-' || c == '.' => Token::Number(self.number(c)?),
+            c if is_name_char(c) => {
+                let name = self.name(c)?;
+                match name.find(':') {
+                    Some(colon) => Token::PrefixedName(name[..colon].to_string(), name[colon + 1..].to_string()),
+                    None => Token::Word(name)
+                }
+            },
+            c => return Err(format!("unexpected character '{}'", c))
+        }))
+    }
+}
+
+// Reads Turtle one statement at a time
+pub struct TurtleReader<R: BufRead> {
+    lexer: Lexer<R>,
+    peeked: Option<Token>,
+    prefixes: HashMap<String, String>,
+    base: String,
+    generated: usize
+}
+
+impl <R: BufRead> TurtleReader<R> {
+    pub fn new(input: R) -> TurtleReader<R> {
+        TurtleReader {
+            lexer: Lexer { chars: Chars::new(input) },
+            peeked: None,
+            prefixes: HashMap::new(),
+            base: String::new(),
+            generated: 0
+        }
+    }
+
+    // line the reader is at, counting from 1
+    pub fn line(&self) -> u64 {
+        self.lexer.chars.line + 1
+    }
+
+    fn peek(&mut self) -> Result<Option<Token>, String> {
+        if self.peeked.is_none() {
+            self.peeked = self.lexer.next_token()?;
+        }
+        Ok(self.peeked.clone())
+    }
+
+    fn next(&mut self) -> Result<Option<Token>, String> {
+        match self.peeked.take() {
+            Some(token) => Ok(Some(token)),
+            None => self.lexer.next_token()
+        }
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next()? {
+            Some(ref token) if token == &expected => Ok(()),
+            other => Err(format!("expected {:?}, found {:?}", expected, other))
+        }
+    }
+
+    fn resolve(&self, iri: String) -> String {
+        if iri.contains(':') { iri } else { format!("{}{}", self.base, iri) }
+    }
+
+    fn expand(&self, prefix: &str, local: &str) -> Result<String, String> {
+        self.prefixes.get(prefix).map(|namespace| format!("{}{}", namespace, local))
+            .ok_or_else(|| format!("undeclared prefix {}:", prefix))
+    }
+
+    fn fresh_blank(&mut self) -> Term {
+        self.generated += 1;
+        Term::Blank(format!("genid{}", self.generated))
+    }
+
+    fn iri_token(&self, token: Token) -> Result<String, String> {
+        match token {
+            Token::Iri(iri) => Ok(self.resolve(iri)),
+            Token::PrefixedName(prefix, local) => self.expand(&prefix, &local),
+            Token::Word(ref word) if word == "a" => Ok(RDF_TYPE.to_string()),
+            other => Err(format!("expected an IRI, found {:?}", other))
+        }
+    }
+
+    fn literal(&mut self, lexical: String) -> Result<Term, String> {
+        match self.peek()? {
+            Some(Token::Language(language)) => {
+                self.next()?;
+                Ok(Term::Literal { lexical, datatype: None, language: Some(language) })
+            },
+            Some(Token::Datatype) => {
+                self.next()?;
+                let token = self.next()?.ok_or_else(|| "expected a datatype".to_string())?;
+                Ok(Term::Literal { lexical, datatype: Some(self.iri_token(token)?), language: None })
+            },
+            _ => Ok(Term::Literal { lexical, datatype: None, language: None })
+        }
+    }
+
+    fn term(&mut self, token: Token, triples: &mut Vec<Triple>) -> Result<Term, String> {
+        match token {
+            Token::Blank(label) => Ok(Term::Blank(label)),
+            Token::Literal(lexical) => self.literal(lexical),
+            Token::Number(number) => {
+                let datatype = if number.contains(|c| c == 'e' || c == 'E') { "double" }
+                    else if number.contains('.') { "decimal" } else { "integer" };
+                Ok(Term::Literal { lexical: number, datatype: Some(format!("{}{}", XSD, datatype)), language: None })
+            },
+            Token::Word(ref word) if word == "true" || word == "false" =>
+                Ok(Term::Literal { lexical: word.clone(), datatype: Some(format!("{}boolean", XSD)), language: None }),
+            Token::Punct('[') => {
+                let node = self.fresh_blank();
+                if self.peek()? != Some(Token::Punct(']')) {
+                    self.predicate_objects(&node, triples)?;
+                }
+                self.expect(Token::Punct(']'))?;
+                Ok(node)
+            },
+            Token::Punct('(') => self.collection(triples),
+            token => Ok(Term::Iri(self.iri_token(token)?))
+        }
+    }
+
+    fn collection(&mut self, triples: &mut Vec<Triple>) -> Result<Term, String> {
+        let mut items = Vec::new();
+        loop {
+            let token = self.next()?.ok_or_else(|| "unterminated collection".to_string())?;
+            if token == Token::Punct(')') { break; }
+            items.push(self.term(token, triples)?);
+        }
+        let mut list = Term::Iri(RDF_NIL.to_string());
+        for item in items.into_iter().rev() {
+            let node = self.fresh_blank();
+            triples.push(Triple { subject: node.clone(), predicate: RDF_FIRST.to_string(), object: item });
+            triples.push(Triple { subject: node.clone(), predicate: RDF_REST.to_string(), object: list });
+            list = node;
+        }
+        Ok(list)
+    }
+
+    fn predicate_objects(&mut self, subject: &Term, triples: &mut Vec<Triple>) -> Result<(), String> {
+        loop {
+            let token = self.next()?.ok_or_else(|| "expected a predicate".to_string())?;
+            let predicate = self.iri_token(token)?;
+            loop {
+                let token = self.next()?.ok_or_else(|| "expected an object".to_string())?;
+                let object = self.term(token, triples)?;
+                triples.push(Triple { subject: subject.clone(), predicate: predicate.clone(), object });
+                if self.peek()? != Some(Token::Punct(',')) { break; }
+                self.next()?;
+            }
+            if self.peek()? != Some(Token::Punct(';')) { return Ok(()); }
+            // repeated and trailing semicolons are allowed
+            while self.peek()? == Some(Token::Punct(';')) { self.next()?; }
+            match self.peek()? {
+                Some(Token::Punct('.')) | Some(Token::Punct(']')) | None => return Ok(()),
+                _ => {}
+            }
+        }
+    }
+
+    fn directive(&mut self, is_prefix: bool, sparql_style: bool) -> Result<(), String> {
+        if is_prefix {
+            let (prefix, local) = match self.next()? {
+                Some(Token::PrefixedName(prefix, local)) => (prefix, local),
+                other => return Err(format!("expected a prefix, found {:?}", other))
+            };
+            if !local.is_empty() { return Err(format!("invalid prefix {}:{}", prefix, local)); }
+            let namespace = match self.next()? {
+                Some(Token::Iri(iri)) => self.resolve(iri),
+                other => return Err(format!("expected an IRI, found {:?}", other))
+            };
+            self.prefixes.insert(prefix, namespace);
+        } else {
+            self.base = match self.next()? {
+                Some(Token::Iri(iri)) => self.resolve(iri),
+                other => return Err(format!("expected an IRI, found {:?}", other))
+            };
+        }
+        if !sparql_style { self.expect(Token::Punct('.'))?; }
+        Ok(())
+    }
+
+    // The triples of the next statement, None at the end of the input. Directives give no triples.
+    pub fn next_statement(&mut self) -> Result<Option<Vec<Triple>>, String> {
+        let token = match self.next()? { Some(token) => token, None => return Ok(None) };
+        let mut triples = Vec::new();
+        match token {
+            Token::Prefix => self.directive(true, false)?,
+            Token::Base => self.directive(false, false)?,
+            Token::Word(ref word) if word.eq_ignore_ascii_case("prefix") => self.directive(true, true)?,
+            Token::Word(ref word) if word.eq_ignore_ascii_case("base") => self.directive(false, true)?,
+            token => {
+                let blank_subject = token == Token::Punct('[');
+                let subject = self.term(token, &mut triples)?;
+                if let Term::Literal { .. } = subject { return Err("a literal cannot be a subject".to_string()); }
+                // [ ... ] . on its own is a complete statement
+                if !(blank_subject && self.peek()? == Some(Token::Punct('.'))) {
+                    self.predicate_objects(&subject, &mut triples)?;
+                }
+                self.expect(Token::Punct('.'))?;
+            }
+        }
+        Ok(Some(triples))
+    }
+}
+
+fn literal_value(lexical: &str, datatype: &Option<String>) -> Value {
+    let local = match datatype {
+        &Some(ref datatype) if datatype.starts_with(XSD) => &datatype[XSD.len()..],
+        _ => return Value::String(lexical.to_string())
+    };
+    let parsed = match local {
+        "integer" | "int" | "long" | "short" | "byte" | "nonNegativeInteger" | "positiveInteger" |
+        "negativeInteger" | "nonPositiveInteger" | "unsignedInt" | "unsignedLong" | "unsignedShort" |
+        "unsignedByte" => lexical.parse::<i64>().ok().map(Value::I64),
+        "double" | "float" | "decimal" => lexical.parse::<f64>().ok().map(Value::F64),
+        "boolean" => match lexical { "true" | "1" => Some(Value::Bool(true)), "false" | "0" => Some(Value::Bool(false)), _ => None },
+        _ => None
+    };
+    parsed.unwrap_or_else(|| Value::String(lexical.to_string()))
+}
+
+fn local_name(iri: &str, options: &RdfOptions) -> String {
+    if iri.starts_with(&options.base_iri) { iri[options.base_iri.len()..].to_string() } else { iri.to_string() }
+}
+
+fn resource_key(term: &Term) -> Option<String> {
+    match term {
+        &Term::Iri(ref iri) => Some(format!("<{}>", iri)),
+        &Term::Blank(ref label) => Some(format!("_:{}", label)),
+        &Term::Literal { .. } => None
+    }
+}
+
+fn ensure_resource(loader: &mut Loader, term: &Term, options: &RdfOptions, source: &str, line: u64)
+    -> Result<String, ImportError>
+{
+    let key = resource_key(term).unwrap();
+    if !loader.has_vertex(&key) {
+        let mut data = Map::new();
+        if let &Term::Iri(ref iri) = term {
+            data.insert(&options.iri_property, Value::String(iri.clone()));
+        }
+        loader.add_vertex(key.clone(), &options.resource_schema, data, source, line)?;
+    }
+    Ok(key)
+}
+
+// Imports a Turtle or N-Triples document, source names it in the report and in errors
+pub fn import_rdf<R: BufRead>(graph: &Arc<Graph>, source: &str, input: R, options: &RdfOptions)
+    -> Result<ImportReport, ImportError>
+{
+    let mut loader = Loader::new(graph, options.batch_size)?;
+    let mut reader = TurtleReader::new(input);
+    loop {
+        let triples = match reader.next_statement() {
+            Ok(Some(triples)) => triples,
+            Ok(None) => break,
+            Err(message) => return Err(ImportError::SyntaxError(source.to_string(), reader.line(), message))
+        };
+        // where the statement ended
+        let line = reader.line();
+        for triple in triples {
+            let subject = ensure_resource(&mut loader, &triple.subject, options, source, line)?;
+            let predicate = local_name(&triple.predicate, options);
+            match triple.object {
+                Term::Literal { ref lexical, ref datatype, .. } => {
+                    loader.add_property(&subject, &predicate, literal_value(lexical, datatype))?;
+                },
+                ref object => {
+                    let object = ensure_resource(&mut loader, object, options, source, line)?;
+                    loader.add_edge(subject, &predicate, object, None, false, source, line)?;
+                }
+            }
+        }
+    }
+    loader.finish()
+}
+
+fn escape_literal(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c)
+        }
+    }
+    escaped
+}
+
+fn literal_text(value: &Value) -> Option<String> {
+    let typed = |lexical: String, datatype: &str| Some(format!("\"{}\"^^<{}{}>", lexical, XSD, datatype));
+    match value {
+        &Value::String(ref s) => Some(format!("\"{}\"", escape_literal(s))),
+        &Value::Bool(b) => typed(b.to_string(), "boolean"),
+        &Value::I8(n) => typed(n.to_string(), "integer"),
+        &Value::I16(n) => typed(n.to_string(), "integer"),
+        &Value::I32(n) => typed(n.to_string(), "integer"),
+        &Value::I64(n) => typed(n.to_string(), "integer"),
+        &Value::U8(n) => typed(n.to_string(), "integer"),
+        &Value::U16(n) => typed(n.to_string(), "integer"),
+        &Value::U32(n) => typed(n.to_string(), "integer"),
+        &Value::U64(n) => typed(n.to_string(), "integer"),
+        &Value::F32(n) => typed(n.to_string(), "double"),
+        &Value::F64(n) => typed(n.to_string(), "double"),
+        _ => None
+    }
+}
+
+fn predicate_iri(name: &str, options: &RdfOptions) -> String {
+    if name.contains(':') { name.to_string() } else { format!("{}{}", options.base_iri, name) }
+}
+
+fn vertex_term(vertex: &Vertex, schema_name: &str, options: &RdfOptions) -> String {
+    let rule = options.scrub.rule(schema_name, &options.iri_property);
+    match (&vertex.cell.data[options.iri_property.as_str()], rule) {
+        (&Value::String(ref iri), None) => format!("<{}>", iri),
+        (&Value::String(ref iri), Some(&ScrubRule::Hash)) => format!("<{}{}>", options.base_iri, options.scrub.digest(iri)),
+        _ => {
+            let id = vertex.cell.id();
+            format!("_:v{}x{}", id.higher, id.lower)
+        }
+    }
+}
+
+// Writes every vertex schema as N-Triples, returns how many triples were written
+pub fn export_ntriples<W: Write>(graph: &Arc<Graph>, output: &mut W, options: &RdfOptions)
+    -> Result<usize, ImportError>
+{
+    let vertex_schemas: Vec<u32> = graph.schema_types().into_iter()
+        .filter_map(|(id, schema_type)| match schema_type { SchemaType::Vertex => Some(id), _ => None })
+        .collect();
+    let mut written = 0;
+    for schema_id in vertex_schemas {
+        let schema_name = graph.schema_name(schema_id).unwrap_or_default();
+        let pages = graph.scan_vertices(schema_id, &None::<String>, None).chunks(options.batch_size).wait();
+        for page in pages {
+            let vertices = page.map_err(ImportError::ScanVerticesError)?;
+            let ids: Vec<Id> = vertices.iter().map(|v| v.cell.id()).collect();
+            // the ends of every outgoing edge with the edge schema name, undirected edges from their lower end
+            let options_in_txn = options.clone();
+            let graph_in_txn = graph.clone();
+            let edges = graph.graph_transaction(move |txn| {
+                let mut edges = Vec::new();
+                for &id in &ids {
+                    let incident = match txn.incident_edge_schemas(id)? { Ok(incident) => incident, Err(_) => continue };
+                    for schema in incident {
+                        if schema.direction == EdgeDirection::Inbound || schema.count == 0 { continue; }
+                        let found = match txn.edges(id, schema.schema, schema.direction, &None)? {
+                            Ok(found) => found, Err(_) => continue
+                        };
+                        for edge in found {
+                            let opposite = match edge.one_opposite_id_vertex_id(&id) { Some(o) => *o, None => continue };
+                            let lower_end = (id.higher, id.lower) <= (opposite.higher, opposite.lower);
+                            if schema.direction == EdgeDirection::Undirected && !lower_end { continue; }
+                            let object = match txn.read_vertex(opposite)? {
+                                Some(vertex) => {
+                                    let opposite_schema = graph_in_txn.schema_name(vertex.schema()).unwrap_or_default();
+                                    vertex_term(&vertex, &opposite_schema, &options_in_txn)
+                                },
+                                None => continue
+                            };
+                            edges.push((id, schema.schema, object));
+                        }
+                    }
+                }
+                Ok(edges)
+            }).wait().map_err(ImportError::TxnError)?;
+            let mut by_vertex: HashMap<Id, Vec<(u32, String)>> = HashMap::new();
+            for (id, schema, object) in edges {
+                by_vertex.entry(id).or_insert_with(Vec::new).push((schema, object));
+            }
+            for vertex in &vertices {
+                let subject = vertex_term(vertex, &schema_name, options);
+                if schema_name != options.resource_schema {
+                    writeln!(output, "{} <{}> <{}> .", subject, RDF_TYPE, predicate_iri(&schema_name, options))
+                        .map_err(ImportError::IoError)?;
+                    written += 1;
+                }
+                if let Value::Map(ref data) = vertex.cell.data {
+                    let data = options.scrub.scrub(&schema_name, data);
+                    for name in &data.fields {
+                        if name.starts_with('_') || name == &options.iri_property { continue; }
+                        let values = match data.get_by_key_id(key_hash(name)) {
+                            &Value::Array(ref items) => items.clone(),
+                            value => vec![value.clone()]
+                        };
+                        for value in values {
+                            if let Some(object) = literal_text(&value) {
+                                writeln!(output, "{} <{}> {} .", subject, predicate_iri(name, options), object)
+                                    .map_err(ImportError::IoError)?;
+                                written += 1;
+                            }
+                        }
+                    }
+                }
+                for &(schema, ref object) in by_vertex.get(&vertex.cell.id()).unwrap_or(&Vec::new()) {
+                    let predicate = predicate_iri(&graph.schema_name(schema).unwrap_or_default(), options);
+                    writeln!(output, "{} <{}> {} .", subject, predicate, object).map_err(ImportError::IoError)?;
+                    written += 1;
+                }
+            }
+        }
+    }
+    Ok(written)
+}
@@ -0,0 +1,105 @@
+// Scrubbing of exported values, so dumps handed out for analytics or bug reports do not carry
+// personal data. Rules are set by schema and field name: Hash puts a salted sha256 digest in place
+// of the value, equal values keep equal digests so joins and counts still work; Redact leaves the
+// value out; Generalize keeps a coarse one, numbers rounded down to a multiple of the step and
+// strings cut to their first step characters, other values are left out. Array items are scrubbed
+// one by one. Only exports apply the rules, the graph keeps its values.
+
+use neb::ram::types::key_hash;
+use neb::dovahkiin::types::{Map, Value};
+use sha2::{Sha256, Digest};
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ScrubRule {
+    Hash,
+    Redact,
+    Generalize(u64)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ScrubOptions {
+    // mixed into every digest, so known values cannot be looked up by theirs
+    #[serde(default)]
+    pub salt: String,
+    // rules by schema name, then by field name
+    #[serde(default)]
+    pub schemas: HashMap<String, HashMap<String, ScrubRule>>
+}
+
+fn floor_to(n: i64, step: i64) -> i64 {
+    let rem = n % step;
+    if rem < 0 { n - rem - step } else { n - rem }
+}
+
+// numbers come out widened to 64 bits, so rounding down stays in range
+fn generalize(value: &Value, step: u64) -> Value {
+    let step = ::std::cmp::max(step, 1);
+    let signed = |n: i64| Value::I64(floor_to(n, step as i64));
+    let unsigned = |n: u64| Value::U64(n - n % step);
+    let float = |n: f64| Value::F64((n / step as f64).floor() * step as f64);
+    match value {
+        &Value::I8(n) => signed(n as i64),
+        &Value::I16(n) => signed(n as i64),
+        &Value::I32(n) => signed(n as i64),
+        &Value::I64(n) => signed(n),
+        &Value::U8(n) => unsigned(n as u64),
+        &Value::U16(n) => unsigned(n as u64),
+        &Value::U32(n) => unsigned(n as u64),
+        &Value::U64(n) => unsigned(n),
+        &Value::F32(n) => float(n as f64),
+        &Value::F64(n) => float(n),
+        &Value::String(ref s) => Value::String(s.chars().take(step as usize).collect()),
+        _ => Value::Null
+    }
+}
+
+impl ScrubOptions {
+    pub fn with_rule(mut self, schema: &str, field: &str, rule: ScrubRule) -> ScrubOptions {
+        self.schemas.entry(schema.to_string()).or_insert_with(HashMap::new).insert(field.to_string(), rule);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.schemas.values().all(|rules| rules.is_empty())
+    }
+
+    pub fn rule(&self, schema: &str, field: &str) -> Option<&ScrubRule> {
+        self.schemas.get(schema).and_then(|rules| rules.get(field))
+    }
+
+    // hex digest of text with the salt
+    pub fn digest(&self, text: &str) -> String {
+        Sha256::digest(format!("{}\0{}", self.salt, text).as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub fn scrub_value(&self, rule: &ScrubRule, value: &Value) -> Value {
+        match (rule, value) {
+            (_, &Value::Null) | (&ScrubRule::Redact, _) => Value::Null,
+            (_, &Value::Array(ref items)) =>
+                Value::Array(items.iter().map(|item| self.scrub_value(rule, item)).collect()),
+            (&ScrubRule::Generalize(step), value) => generalize(value, step),
+            (&ScrubRule::Hash, &Value::String(ref s)) => Value::String(self.digest(s)),
+            (&ScrubRule::Hash, value) => Value::String(self.digest(&format!("{:?}", value)))
+        }
+    }
+
+    // data as it is exported for a vertex of the schema, only copied when a rule applies to it
+    pub fn scrub<'a>(&self, schema: &str, data: &'a Map) -> Cow<'a, Map> {
+        let rules = match self.schemas.get(schema) {
+            Some(rules) if !rules.is_empty() => rules,
+            _ => return Cow::Borrowed(data)
+        };
+        let mut scrubbed = data.clone();
+        for (field, rule) in rules {
+            let value = match data.get_by_key_id(key_hash(field)) {
+                &Value::Null => continue,
+                value => self.scrub_value(rule, value)
+            };
+            scrubbed.insert(field, value);
+        }
+        Cow::Owned(scrubbed)
+    }
+}
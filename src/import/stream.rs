@@ -0,0 +1,361 @@
+// Ingestion of mutation commands from a Kafka topic. Every message is one command in JSON:
+//   {"op": "insert_vertex", "schema": "person", "data": {"name": "alice", "age": 30}}
+//   {"op": "update_vertex", "schema": "person", "key": "alice", "data": {"age": 31}}
+//   {"op": "remove_vertex", "schema": "person", "key": "alice"}
+//   {"op": "link", "schema": "follows", "from": {"schema": "person", "key": "alice"},
+//    "to": {"schema": "person", "key": "bob"}, "body": {"since": 2018}}
+//   {"op": "unlink", "schema": "follows", "from": {...}, "to": {...}}
+// Vertices are found by the key field of their schema, commands other than inserts need keyed vertex
+// schemas. Updates set the fields given and keep the rest. Numbers take the type of the field they
+// are written to when the schema declares it. Schemas are not created, unknown ones are rejected.
+// The commands of a poll are applied batch_size per transaction and the offsets of the consumer
+// group are committed to Kafka after all of them were applied or dead lettered, a command may be
+// applied again after a crash. Messages that are no valid command and commands the graph rejects go
+// to the dead letter topic with the reason, without one they are logged and dropped.
+// The Kafka client does not take part in group rebalancing, every ingesting server reads all the
+// partitions it is given, so give servers disjoint partitions or let one server ingest.
+
+use neb::ram::schema::Field;
+use neb::ram::types::{Id, TypeId};
+use neb::ram::cell::Cell;
+use neb::dovahkiin::types::{Map, Value};
+use neb::client::transaction::TxnError;
+use kafka::consumer::{Consumer, FetchOffset, GroupOffsetStorage, MessageSets};
+use kafka::producer::{Producer, Record, RequiredAcks};
+use kafka;
+use serde_json::{self, Value as Json, Map as JsonMap, Number};
+use futures::prelude::*;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use graph::Graph;
+use graph::bulk;
+use graph::gc::pause;
+use import::DEFAULT_BATCH_SIZE;
+use server::metrics;
+use server::schema::SchemaType;
+
+fn default_group() -> String { "morpheus-ingest".to_string() }
+fn default_batch_size() -> usize { DEFAULT_BATCH_SIZE }
+fn default_retry_interval_ms() -> u64 { 1000 }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StreamIngestOptions {
+    // host:port of the bootstrap brokers
+    pub brokers: Vec<String>,
+    pub topic: String,
+    // consumer group the offsets are committed for
+    #[serde(default = "default_group")]
+    pub group: String,
+    // all partitions of the topic when None
+    #[serde(default)]
+    pub partitions: Option<Vec<i32>>,
+    #[serde(default)]
+    pub dead_letter_topic: Option<String>,
+    // commands per transaction
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    // pause after a failed poll, transaction or dead letter send
+    #[serde(default = "default_retry_interval_ms")]
+    pub retry_interval_ms: u64
+}
+
+pub use graph::bulk::BulkOp as Command;
+
+// where a message came from, for the dead letter
+#[derive(Serialize, Debug, Clone)]
+pub struct DeadLetter {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    pub reason: String,
+    pub message: String
+}
+
+fn number(n: &Number, type_id: Option<u32>) -> Result<Value, String> {
+    let int = n.as_i64();
+    let in_range = |min: i64, max: i64| int.and_then(|v| if v >= min && v <= max { Some(v) } else { None });
+    let of = |t: TypeId| type_id == Some(t as u32);
+    let value = if type_id.is_none() {
+        int.map(Value::I64).or_else(|| n.as_u64().map(Value::U64)).or_else(|| n.as_f64().map(Value::F64))
+    }
+    else if of(TypeId::I8) { in_range(i8::min_value() as i64, i8::max_value() as i64).map(|v| Value::I8(v as i8)) }
+    else if of(TypeId::I16) { in_range(i16::min_value() as i64, i16::max_value() as i64).map(|v| Value::I16(v as i16)) }
+    else if of(TypeId::I32) { in_range(i32::min_value() as i64, i32::max_value() as i64).map(|v| Value::I32(v as i32)) }
+    else if of(TypeId::I64) { int.map(Value::I64) }
+    else if of(TypeId::U8) { in_range(0, u8::max_value() as i64).map(|v| Value::U8(v as u8)) }
+    else if of(TypeId::U16) { in_range(0, u16::max_value() as i64).map(|v| Value::U16(v as u16)) }
+    else if of(TypeId::U32) { in_range(0, u32::max_value() as i64).map(|v| Value::U32(v as u32)) }
+    else if of(TypeId::U64) { n.as_u64().map(Value::U64) }
+    else if of(TypeId::F32) { n.as_f64().map(|v| Value::F32(v as f32)) }
+    // floats, and numbers in fields of other types for validation to reject
+    else { n.as_f64().map(Value::F64) };
+    value.ok_or_else(|| format!("{} is out of range for its field", n))
+}
+
+// numbers take the type of the field when there is one
+pub fn to_value(json: &Json, field: Option<&Field>) -> Result<Value, String> {
+    Ok(match json {
+        &Json::Null => Value::Null,
+        &Json::Bool(b) => Value::Bool(b),
+        &Json::String(ref s) => Value::String(s.clone()),
+        &Json::Number(ref n) => number(n, field.map(|f| f.type_id))?,
+        &Json::Array(ref items) => Value::Array(
+            items.iter().map(|item| to_value(item, field)).collect::<Result<Vec<_>, _>>()?
+        ),
+        &Json::Object(ref object) => {
+            let sub_fields = field.and_then(|f| f.sub_fields.as_ref()).map(|f| &f[..]).unwrap_or(&[]);
+            Value::Map(to_map(object, sub_fields)?)
+        }
+    })
+}
+
+fn to_fields(object: &JsonMap<String, Json>, fields: &[Field]) -> Result<Vec<(String, Value)>, String> {
+    object.iter()
+        .map(|(name, json)| {
+            let field = fields.iter().find(|f| &f.name == name);
+            to_value(json, field).map(|value| (name.clone(), value)).map_err(|e| format!("field {}: {}", name, e))
+        })
+        .collect()
+}
+
+fn to_map(object: &JsonMap<String, Json>, fields: &[Field]) -> Result<Map, String> {
+    let mut map = Map::new();
+    for (name, value) in to_fields(object, fields)? {
+        map.insert(&name, value);
+    }
+    Ok(map)
+}
+
+fn schema(graph: &Graph, command: &JsonMap<String, Json>) -> Result<(u32, SchemaType), String> {
+    let name = command.get("schema").and_then(|s| s.as_str()).ok_or_else(|| "no schema".to_string())?;
+    graph.schema_by_name(name).ok_or_else(|| format!("unknown schema {}", name))
+}
+
+fn vertex_schema(graph: &Graph, command: &JsonMap<String, Json>) -> Result<u32, String> {
+    match schema(graph, command)? {
+        (id, SchemaType::Vertex) => Ok(id),
+        _ => Err("not a vertex schema".to_string())
+    }
+}
+
+fn declared_fields(graph: &Graph, schema_id: u32) -> Vec<Field> {
+    graph.neb_schema(schema_id).and_then(|s| s.fields.sub_fields.clone()).unwrap_or_default()
+}
+
+// the id of the vertex of the schema with the key, converted like the key field
+pub fn key_id(graph: &Graph, schema_id: u32, key: &Json) -> Result<Id, String> {
+    let neb_schema = graph.neb_schema(schema_id).ok_or_else(|| "unknown schema".to_string())?;
+    let key_field = match neb_schema.str_key_field {
+        Some(ref names) if names.len() == 1 => declared_fields(graph, schema_id).into_iter().find(|f| f.name == names[0]),
+        Some(_) => None,
+        None => return Err("vertices of the schema have no key".to_string())
+    };
+    Ok(Cell::encode_cell_key(schema_id, &to_value(key, key_field.as_ref())?))
+}
+
+fn vertex_id(graph: &Graph, command: &JsonMap<String, Json>) -> Result<Id, String> {
+    let schema_id = vertex_schema(graph, command)?;
+    key_id(graph, schema_id, command.get("key").ok_or_else(|| "no key".to_string())?)
+}
+
+fn end_id(graph: &Graph, command: &JsonMap<String, Json>, end: &str) -> Result<Id, String> {
+    match command.get(end) {
+        Some(&Json::Object(ref vertex)) => vertex_id(graph, vertex).map_err(|e| format!("{}: {}", end, e)),
+        _ => Err(format!("no {} vertex", end))
+    }
+}
+
+fn data(command: &JsonMap<String, Json>, name: &str) -> Result<Option<&JsonMap<String, Json>>, String> {
+    match command.get(name) {
+        None | Some(&Json::Null) => Ok(None),
+        Some(&Json::Object(ref object)) => Ok(Some(object)),
+        Some(_) => Err(format!("{} is not an object", name))
+    }
+}
+
+pub fn parse_command(graph: &Graph, message: &[u8]) -> Result<Command, String> {
+    let json: Json = serde_json::from_slice(message).map_err(|e| format!("not JSON: {}", e))?;
+    let command = match json {
+        Json::Object(command) => command, _ => return Err("not a JSON object".to_string())
+    };
+    let op = command.get("op").and_then(|op| op.as_str()).ok_or_else(|| "no op".to_string())?;
+    match op {
+        "insert_vertex" => {
+            let schema = vertex_schema(graph, &command)?;
+            let object = data(&command, "data")?.ok_or_else(|| "no data".to_string())?;
+            Ok(Command::InsertVertex { schema, data: to_map(object, &declared_fields(graph, schema))? })
+        },
+        "update_vertex" => {
+            let schema = vertex_schema(graph, &command)?;
+            let object = data(&command, "data")?.ok_or_else(|| "no data".to_string())?;
+            let fields = to_fields(object, &declared_fields(graph, schema))?;
+            Ok(Command::UpdateVertex { id: vertex_id(graph, &command)?, fields })
+        },
+        "remove_vertex" => Ok(Command::RemoveVertex { id: vertex_id(graph, &command)? }),
+        "link" | "unlink" => {
+            let schema = match schema(graph, &command)? {
+                (id, SchemaType::Edge(_)) => id, _ => return Err("not an edge schema".to_string())
+            };
+            let (from, to) = (end_id(graph, &command, "from")?, end_id(graph, &command, "to")?);
+            if op == "unlink" { return Ok(Command::Unlink { from, schema, to }); }
+            let body = match data(&command, "body")? {
+                Some(object) => Some(to_map(object, &declared_fields(graph, schema))?), None => None
+            };
+            Ok(Command::Link { from, schema, to, body })
+        },
+        _ => Err(format!("unknown op {}", op))
+    }
+}
+
+// Applies the commands in one transaction, resolves to the positions of the ones rejected with why
+pub fn apply_commands(graph: &Graph, commands: Vec<Command>)
+    -> impl Future<Item = Vec<(usize, String)>, Error = TxnError>
+{
+    graph.graph_transaction(move |txn| {
+        let mut rejected = Vec::new();
+        for (pos, command) in commands.iter().enumerate() {
+            if let Err(reason) = bulk::apply_op(txn, command)? { rejected.push((pos, reason)); }
+        }
+        Ok(rejected)
+    })
+}
+
+fn connect(options: &StreamIngestOptions) -> Result<Consumer, kafka::Error> {
+    let builder = Consumer::from_hosts(options.brokers.clone());
+    let builder = match options.partitions {
+        Some(ref partitions) => builder.with_topic_partitions(options.topic.clone(), partitions),
+        None => builder.with_topic(options.topic.clone())
+    };
+    builder
+        .with_group(options.group.clone())
+        .with_fallback_offset(FetchOffset::Earliest)
+        .with_offset_storage(GroupOffsetStorage::Kafka)
+        .create()
+}
+
+fn send_dead_letters(producer: &mut Option<Producer>, options: &StreamIngestOptions, topic: &str, letters: &[DeadLetter])
+    -> Result<(), kafka::Error>
+{
+    if producer.is_none() {
+        *producer = Some(Producer::from_hosts(options.brokers.clone())
+            .with_required_acks(RequiredAcks::All)
+            .create()?);
+    }
+    let values: Vec<Vec<u8>> = letters.iter()
+        .map(|letter| serde_json::to_vec(letter).unwrap_or_default())
+        .collect();
+    let records: Vec<_> = values.iter().map(|value| Record::from_value(topic, &value[..])).collect();
+    if let Some(ref mut producer) = *producer {
+        producer.send_all(&records)?;
+    }
+    Ok(())
+}
+
+// Parses and applies the messages of a poll, false when stopped before all of them were done
+fn ingest(graph: &Graph, options: &StreamIngestOptions, message_sets: &MessageSets,
+          dead_letter_producer: &mut Option<Producer>, running: &AtomicBool) -> bool
+{
+    let retry_interval = Duration::from_millis(options.retry_interval_ms);
+    let mut commands = Vec::new();
+    let mut dead_letters = Vec::new();
+    for set in message_sets.iter() {
+        for message in set.messages() {
+            let letter = DeadLetter {
+                topic: set.topic().to_string(), partition: set.partition(), offset: message.offset,
+                reason: String::new(), message: String::from_utf8_lossy(message.value).into_owned()
+            };
+            match parse_command(graph, message.value) {
+                Ok(command) => commands.push((command, letter)),
+                Err(reason) => dead_letters.push(DeadLetter { reason, ..letter })
+            }
+        }
+    }
+    while !commands.is_empty() {
+        let rest = commands.split_off(::std::cmp::min(::std::cmp::max(options.batch_size, 1), commands.len()));
+        let batch = ::std::mem::replace(&mut commands, rest);
+        let rejected = loop {
+            match apply_commands(graph, batch.iter().map(|&(ref c, _)| c.clone()).collect()).wait() {
+                Ok(rejected) => break rejected,
+                Err(e) => {
+                    warn!("Cannot apply {} commands from {}, retrying: {:?}", batch.len(), options.topic, e);
+                    if !pause(running, retry_interval) { return false; }
+                }
+            }
+        };
+        let _ = metrics::INGESTED_COMMANDS.inc_by((batch.len() - rejected.len()) as f64);
+        for (pos, reason) in rejected {
+            dead_letters.push(DeadLetter { reason, ..batch[pos].1.clone() });
+        }
+    }
+    if dead_letters.is_empty() { return true; }
+    let _ = metrics::INGEST_DEAD_LETTERS.inc_by(dead_letters.len() as f64);
+    let topic = match options.dead_letter_topic {
+        Some(ref topic) => topic,
+        None => {
+            for letter in &dead_letters {
+                warn!("Dropped message {}/{}@{}: {}", letter.topic, letter.partition, letter.offset, letter.reason);
+            }
+            return true;
+        }
+    };
+    while let Err(e) = send_dead_letters(dead_letter_producer, options, topic, &dead_letters) {
+        warn!("Cannot send {} dead letters to {}, retrying: {:?}", dead_letters.len(), topic, e);
+        *dead_letter_producer = None;
+        if !pause(running, retry_interval) { return false; }
+    }
+    true
+}
+
+// Consumes the topic into the graph until running is cleared
+pub fn start_ingest(graph: Arc<Graph>, options: StreamIngestOptions, running: Arc<AtomicBool>) -> thread::JoinHandle<()> {
+    thread::Builder::new()
+        .name("morpheus-stream-ingest".to_string())
+        .spawn(move || {
+            let retry_interval = Duration::from_millis(options.retry_interval_ms);
+            let mut consumer: Option<Consumer> = None;
+            let mut dead_letter_producer: Option<Producer> = None;
+            while running.load(Ordering::Relaxed) {
+                if consumer.is_none() {
+                    match connect(&options) {
+                        Ok(connected) => consumer = Some(connected),
+                        Err(e) => {
+                            warn!("Cannot consume {} from {:?}: {:?}", options.topic, options.brokers, e);
+                            if !pause(&running, retry_interval) { break; }
+                            continue;
+                        }
+                    }
+                }
+                let polled = match consumer {
+                    Some(ref mut consumer) => consumer.poll(), None => continue
+                };
+                let message_sets = match polled {
+                    Ok(message_sets) => message_sets,
+                    Err(e) => {
+                        warn!("Cannot poll {}: {:?}", options.topic, e);
+                        consumer = None;
+                        if !pause(&running, retry_interval) { break; }
+                        continue;
+                    }
+                };
+                if message_sets.is_empty() { continue; }
+                // offsets stay uncommitted when stopped half way, the messages come again on restart
+                if !ingest(&graph, &options, &message_sets, &mut dead_letter_producer, &running) { break; }
+                if let Some(ref mut consumer) = consumer {
+                    for set in message_sets.iter() {
+                        if let Err(e) = consumer.consume_messageset(set) {
+                            warn!("Cannot mark messages of {} consumed: {:?}", options.topic, e);
+                        }
+                    }
+                    if let Err(e) = consumer.commit_consumed() {
+                        warn!("Cannot commit offsets of {}, messages may be applied again: {:?}", options.topic, e);
+                    }
+                }
+            }
+            debug!("Stream ingestion of {} stopped", options.topic);
+        })
+        .unwrap()
+}
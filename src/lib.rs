@@ -0,0 +1,71 @@
+#![feature(proc_macro)]
+#![feature(plugin)]
+#![feature(conservative_impl_trait)]
+#![plugin(bifrost_plugins)]
+
+#![feature(conservative_impl_trait, generators)]
+#![cfg_attr(feature = "std-future", feature(futures_api))]
+
+#[macro_use]
+extern crate neb;
+#[macro_use]
+extern crate hivemind;
+#[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate bifrost;
+#[macro_use]
+extern crate bifrost_hasher;
+extern crate futures_await as futures;
+extern crate futures_cpupool;
+extern crate tokio_timer;
+extern crate parking_lot;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate chashmap;
+#[macro_use]
+extern crate log;
+extern crate log4rs;
+extern crate env_logger;
+extern crate yaml_rust;
+extern crate serde_yaml;
+#[macro_use]
+extern crate prometheus;
+extern crate rand;
+extern crate sha2;
+extern crate ring;
+extern crate petgraph;
+extern crate csv;
+extern crate kafka;
+extern crate serde_json;
+#[cfg(feature = "arrow")]
+extern crate arrow;
+#[cfg(feature = "wasm")]
+extern crate wasmi;
+#[cfg(feature = "wasm")]
+extern crate parity_wasm;
+#[cfg(feature = "wasm")]
+extern crate pwasm_utils;
+#[cfg(feature = "std-future")]
+extern crate futures_util;
+#[cfg(all(test, feature = "std-future"))]
+extern crate futures_executor;
+
+pub mod graph;
+pub mod server;
+pub mod utils;
+pub mod config;
+pub mod query;
+pub mod analytics;
+pub mod import;
+pub mod error;
+
+pub use error::Error;
+// The API stays on futures 0.1 with futures_await generators, neb, bifrost and hivemind hand out
+// futures 0.1 and need the nightly these generators run on. Futures and streams of this crate are
+// re-exported to compose with tokio 0.1 without naming the version; with the std-future feature
+// graph::compat offers the graph operations as std futures.
+pub use futures::{Future, Stream, IntoFuture};
+#[cfg(test)]
+mod tests;
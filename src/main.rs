@@ -1,52 +1,63 @@
-#![feature(proc_macro)]
-#![feature(plugin)]
-#![feature(conservative_impl_trait)]
-#![plugin(bifrost_plugins)]
-
-#![feature(conservative_impl_trait, generators)]
-
-#[macro_use]
-extern crate neb;
-#[macro_use]
-extern crate hivemind;
-#[macro_use]
-extern crate lazy_static;
-#[macro_use]
-extern crate bifrost;
-#[macro_use]
-extern crate bifrost_hasher;
+extern crate morpheus;
 extern crate futures_await as futures;
-extern crate futures_cpupool;
-extern crate parking_lot;
-extern crate serde;
-#[macro_use]
-extern crate serde_derive;
-extern crate chashmap;
 #[macro_use]
 extern crate log;
 extern crate log4rs;
-extern crate env_logger;
-extern crate yaml_rust;
-extern crate serde_yaml;
+extern crate ctrlc;
 
 use futures::Future;
+use morpheus::{config, graph, query, server};
 
-mod graph;
-mod server;
-mod utils;
-mod config;
-mod query;
-#[cfg(test)]
-mod tests;
-
-use std::thread;
+use std::env;
+use std::process;
+use std::sync::mpsc;
 
 fn main() {
-    log4rs::init_file("config/log4rs.yaml", Default::default()).unwrap();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let morpheus_config = match config::morpheus::load(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    log4rs::init_file(&morpheus_config.log_config, Default::default()).unwrap();
     info!("Shisoft Morpheus is initializing...");
     query::init().unwrap();
-    let neb_config = config::neb::options_from_file("config/neb.yaml");
-    let morpheus_server = server::MorpheusServer::new(neb_config).wait().unwrap();
+    server::slow_log::configure(morpheus_config.slow_query.clone());
+    server::watchdog::configure(morpheus_config.watchdog.clone());
+    graph::id_list::configure_shards(morpheus_config.supernode_shards);
+    let server_options = server::MorpheusServerOptions {
+        graphs: morpheus_config.graphs,
+        auth: morpheus_config.auth,
+        read_only: morpheus_config.read_only,
+        link_batch: morpheus_config.link_batch,
+        orphan_gc: morpheus_config.orphan_gc,
+        counter_decay: morpheus_config.counter_decay,
+        cdc_kafka: morpheus_config.cdc_kafka,
+        replication: morpheus_config.replication,
+        replica: morpheus_config.replica,
+        stream_ingest: morpheus_config.stream_ingest,
+        graphql: morpheus_config.graphql,
+        metrics_port: morpheus_config.metrics_port,
+        query_cache: morpheus_config.query_cache,
+        vertex_cache: morpheus_config.vertex_cache,
+        quotas: morpheus_config.quotas,
+        result_limits: morpheus_config.result_limits,
+        memory_budget: morpheus_config.memory_budget,
+        encryption: morpheus_config.encryption,
+        schema_migration: morpheus_config.schema_migration,
+        rate_limit: morpheus_config.rate_limit,
+        retry: morpheus_config.retry,
+        statistics: morpheus_config.statistics
+    };
+    let morpheus_server = server::MorpheusServer::new_with_options(morpheus_config.neb, server_options)
+        .wait().unwrap();
 
-    thread::park();
+    // SIGINT and SIGTERM both end up here
+    let (signal_tx, signal_rx) = mpsc::channel();
+    ctrlc::set_handler(move || { let _ = signal_tx.send(()); })
+        .expect("Cannot install signal handler");
+    signal_rx.recv().unwrap();
+    morpheus_server.shutdown();
 }
@@ -4,11 +4,72 @@ use neb::dovahkiin::expr::SExpr;
 use neb::dovahkiin::expr::symbols::utils::is_true;
 use neb::dovahkiin::integrated::lisp::parse_to_expr;
 use neb::dovahkiin::types::Value;
+use neb::ram::types::Id;
 use graph::edge::Edge;
 use graph::vertex::Vertex;
 
 pub static VERTEX_SYMBOL: u64 = hash_ident!(vertex) as u64;
 pub static EDGE_SYMBOL: u64 = hash_ident!(edge) as u64;
+pub static VERTEX_ID_SYMBOL: u64 = hash_ident!(vertex_id) as u64;
+pub static EDGE_SCHEMA_SYMBOL: u64 = hash_ident!(edge_schema) as u64;
+pub static EDGE_FROM_SYMBOL: u64 = hash_ident!(edge_from) as u64;
+pub static EDGE_TO_SYMBOL: u64 = hash_ident!(edge_to) as u64;
+pub static IN_DEGREE_SYMBOL: u64 = hash_ident!(in_degree) as u64;
+pub static OUT_DEGREE_SYMBOL: u64 = hash_ident!(out_degree) as u64;
+
+// Metadata filters can refer to next to the vertex and edge bodies. vertex_id and the degrees
+// are of the opposite vertex, degrees count its lists in the edge's schema. Missing ones bind to null.
+#[derive(Debug, Clone, Default)]
+pub struct FilterContext {
+    pub vertex_id: Option<Id>,
+    pub edge_schema: Option<String>,
+    pub edge_ends: Option<(Id, Id)>,
+    pub in_degree: Option<usize>,
+    pub out_degree: Option<usize>
+}
+
+// Whether the filter refers to in_degree or out_degree, the costly part of a context to fill. Looks for
+// the names in the parsed expression, a string literal holding one only costs the counts it spares.
+pub fn refers_to_degrees(sexpr: &Option<Vec<SExpr>>) -> bool {
+    match sexpr {
+        &Some(ref sexpr) => {
+            let printed = format!("{:?}", sexpr);
+            printed.contains("in_degree") || printed.contains("out_degree")
+        },
+        &None => false
+    }
+}
+
+// Whether the filter refers to the vertex body, which is bound to null unless the opposite vertex was
+// read. Looked for the same way as the degrees, vertex_id does not count.
+pub fn refers_to_vertex(sexpr: &Option<Vec<SExpr>>) -> bool {
+    match sexpr {
+        &Some(ref sexpr) => {
+            let printed = format!("{:?}", sexpr);
+            printed.match_indices("vertex").any(|(at, _)| !printed[at..].starts_with("vertex_id"))
+        },
+        &None => false
+    }
+}
+
+fn bind_context(context: &FilterContext) {
+    let id_value = |id: Option<Id>| id.map(Value::Id).unwrap_or(Value::Null);
+    let degree_value = |degree: Option<usize>| degree.map(|d| Value::U64(d as u64)).unwrap_or(Value::Null);
+    bind(VERTEX_ID_SYMBOL, SExpr::Value(id_value(context.vertex_id)));
+    bind(EDGE_SCHEMA_SYMBOL, SExpr::Value(
+        context.edge_schema.clone().map(Value::String).unwrap_or(Value::Null)
+    ));
+    bind(EDGE_FROM_SYMBOL, SExpr::Value(id_value(context.edge_ends.map(|(from, _)| from))));
+    bind(EDGE_TO_SYMBOL, SExpr::Value(id_value(context.edge_ends.map(|(_, to)| to))));
+    bind(IN_DEGREE_SYMBOL, SExpr::Value(degree_value(context.in_degree)));
+    bind(OUT_DEGREE_SYMBOL, SExpr::Value(degree_value(context.out_degree)));
+}
+
+fn edge_data(edge: &Edge) -> Value {
+    if let &Some(ref e) = edge.get_data() {
+        e.data.clone()
+    } else {Value::Null}
+}
 
 #[derive(Debug)]
 pub enum InitQueryError {
@@ -78,6 +139,43 @@ impl Tester {
         Ok(is_true(interp.eval(sexpr)?))
     }
     
+    // vertex is None when the traversal did not read the opposite vertex
+    pub fn eval_in_context(sexpr: &Option<Vec<SExpr>>, vertex: Option<&Vertex>, edge: &Edge, context: &FilterContext)
+        -> Result<bool, String> {
+        let sexpr = sexpr.clone();
+        let sexpr = if let Some(expr) = sexpr { expr } else { return Ok(true); };
+        let interp = prep_interp();
+        bind(VERTEX_SYMBOL, SExpr::Value(vertex.map(|v| v.cell.data.clone()).unwrap_or(Value::Null)));
+        bind(EDGE_SYMBOL, SExpr::Value(edge_data(edge)));
+        bind_context(context);
+        Ok(is_true(interp.eval(sexpr)?))
+    }
+
+    // the value the expression computes rather than its truth, for computed fields
+    pub fn eval_value(sexpr: &Vec<SExpr>, vertex_data: Value, context: &FilterContext)
+        -> Result<Value, String> {
+        let interp = prep_interp();
+        bind(VERTEX_SYMBOL, SExpr::Value(vertex_data));
+        bind(EDGE_SYMBOL, SExpr::Value(Value::Null));
+        bind_context(context);
+        match interp.eval(sexpr.clone())? {
+            SExpr::Value(value) => Ok(value),
+            other => Err(format!("expected a value, got {:?}", other))
+        }
+    }
+
+    // the value of the expression with the symbols bound, for stored procedures
+    pub fn eval_bound(sexpr: &Vec<SExpr>, bindings: &[(u64, Value)]) -> Result<Value, String> {
+        let interp = prep_interp();
+        for &(symbol, ref value) in bindings {
+            bind(symbol, SExpr::Value(value.clone()));
+        }
+        match interp.eval(sexpr.clone())? {
+            SExpr::Value(value) => Ok(value),
+            other => Err(format!("expected a value, got {:?}", other))
+        }
+    }
+
     pub fn eval_with_vertex(sexpr: &Option<Vec<SExpr>>, vertex: &Vertex)
         -> Result<bool, String> {
         let sexpr = sexpr.clone(); // TODO: Memory management
@@ -0,0 +1,48 @@
+use neb::dovahkiin::expr::symbols::Symbol;
+use neb::dovahkiin::expr::SExpr;
+use neb::dovahkiin::types::Value;
+
+use graph::geo::GeoPoint;
+
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        &Value::F64(n) => Some(n),
+        &Value::F32(n) => Some(n as f64),
+        &Value::I64(n) => Some(n as f64),
+        &Value::I32(n) => Some(n as f64),
+        _ => None
+    }
+}
+
+// (geo-distance point point) or (geo-distance lat lon lat lon), meters between the two
+#[derive(Debug)]
+pub struct Distance {}
+impl Symbol for Distance {
+    fn eval(&self, exprs: Vec<SExpr>) -> Result<SExpr, String> {
+        let mut values = Vec::with_capacity(exprs.len());
+        for expr in exprs {
+            match expr {
+                SExpr::Value(value) => values.push(value),
+                other => return Err(format!("geo-distance takes values, got {:?}", other))
+            }
+        }
+        let points = match values.len() {
+            2 => (GeoPoint::from_value(&values[0]), GeoPoint::from_value(&values[1])),
+            4 => {
+                let coords: Vec<Option<f64>> = values.iter().map(number).collect();
+                match (coords[0], coords[1], coords[2], coords[3]) {
+                    (Some(lat1), Some(lon1), Some(lat2), Some(lon2)) =>
+                        (Some(GeoPoint::new(lat1, lon1)), Some(GeoPoint::new(lat2, lon2))),
+                    _ => (None, None)
+                }
+            },
+            n => return Err(format!("geo-distance takes 2 points or 4 coordinates, got {} arguments", n))
+        };
+        match points {
+            (Some(a), Some(b)) => Ok(SExpr::Value(Value::F64(a.distance(&b)))),
+            // a vertex without the point is at no distance at all
+            _ => Ok(SExpr::Value(Value::Null))
+        }
+    }
+    fn is_macro(&self) -> bool { false }
+}
@@ -1,6 +1,8 @@
 use neb::dovahkiin::expr::symbols::ISYMBOL_MAP;
 
 pub mod crud;
+pub mod geo;
+pub mod udf;
 
 pub fn init_symbols() -> Result<(), ()> {
     ISYMBOL_MAP.insert("insert-cell", crud::cell::Insert {})?;
@@ -17,5 +19,7 @@ pub fn init_symbols() -> Result<(), ()> {
     ISYMBOL_MAP.insert("delete-cell", crud::cell::Delete {})?;
     ISYMBOL_MAP.insert("delete-vertex", crud::vertex::Delete {})?;
     ISYMBOL_MAP.insert("delete-edge", crud::edge::Delete {})?;
+
+    ISYMBOL_MAP.insert("geo-distance", geo::Distance {})?;
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,68 @@
+// User defined functions. Native functions and dovahkiin symbols registered here can be called from
+// filters like the built-in ones, so domain logic does not need a fork of the query module.
+// Register them after query::init and before the server starts taking queries, registrations are global.
+
+use neb::dovahkiin::expr::symbols::{Symbol, ISYMBOL_MAP};
+use neb::dovahkiin::expr::SExpr;
+use neb::dovahkiin::types::Value;
+use parking_lot::RwLock;
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+lazy_static! {
+    static ref REGISTERED: RwLock<BTreeSet<String>> = RwLock::new(BTreeSet::new());
+}
+
+#[derive(Debug)]
+pub enum UdfError {
+    NameTaken(String),
+    CannotRegister(String)
+}
+
+// arguments arrive evaluated, the function sees their values only
+pub struct NativeFunction {
+    name: String,
+    func: Box<Fn(Vec<Value>) -> Result<Value, String> + Send + Sync>
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NativeFunction({})", self.name)
+    }
+}
+
+impl Symbol for NativeFunction {
+    fn eval(&self, exprs: Vec<SExpr>) -> Result<SExpr, String> {
+        let mut args = Vec::with_capacity(exprs.len());
+        for expr in exprs {
+            match expr {
+                SExpr::Value(value) => args.push(value),
+                other => return Err(format!("{} takes values, got {:?}", self.name, other))
+            }
+        }
+        (self.func)(args).map(SExpr::Value)
+    }
+    fn is_macro(&self) -> bool { false }
+}
+
+pub fn register_symbol<S>(name: &str, symbol: S) -> Result<(), UdfError> where S: Symbol + 'static {
+    let mut registered = REGISTERED.write();
+    if registered.contains(name) {
+        return Err(UdfError::NameTaken(name.to_string()));
+    }
+    ISYMBOL_MAP.insert(name, symbol).map_err(|_| UdfError::CannotRegister(name.to_string()))?;
+    registered.insert(name.to_string());
+    Ok(())
+}
+
+pub fn register<F>(name: &str, func: F) -> Result<(), UdfError>
+    where F: Fn(Vec<Value>) -> Result<Value, String> + Send + Sync + 'static
+{
+    register_symbol(name, NativeFunction { name: name.to_string(), func: Box::new(func) })
+}
+
+// names of the functions registered so far, in name order
+pub fn registered() -> Vec<String> {
+    REGISTERED.read().iter().cloned().collect()
+}
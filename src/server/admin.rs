@@ -0,0 +1,409 @@
+use bifrost::rpc::*;
+use bifrost::raft::RaftService;
+use bifrost::raft::client::RaftClient;
+use bifrost::raft::state_machine::configs::CONFIG_SM_ID;
+use bifrost::raft::state_machine::configs::commands::client::SMClient as ConfigSMClient;
+use neb::ram::schema::Field;
+use futures::prelude::*;
+use futures::future;
+use futures::sync::oneshot;
+use std::sync::Arc;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::thread;
+
+use graph::{Graph, RemoveVerticesError};
+use server::schema::{SchemaContainer, SchemaType, SchemaError, MorpheusSchema};
+use server::stats::{self, StatisticsContainer, SchemaStatistics, DegreeReport};
+use server::auth::{AuthContainer, AuthError, Permission, Resource, Role};
+use server::rate_limit::{RateLimiter, RateLimited, RateClass};
+use server::audit::{AuditLog, AuditAction, AuditEntry, AuditQuery};
+use import::rdf::{self, RdfOptions};
+
+pub static ADMIN_SERVICE_ID: u64 = hash_ident!(MORPHEUS_ADMIN_RPC_SERVICE) as u64;
+// vertices scanned per removal transaction of remove_vertices
+const REMOVAL_BATCH_SIZE: usize = 512;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HealthReport {
+    pub group: String,
+    pub schemas: usize,
+    pub statistics: usize
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SchemaSummary {
+    pub id: u32,
+    pub name: String,
+    pub schema_type: SchemaType,
+    pub key_field: Option<Vec<String>>,
+    pub fields: Vec<String>,
+    pub is_dynamic: bool
+}
+
+// the raft group of the meta servers as one of them sees it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GroupHealth {
+    pub server_id: u64,
+    pub is_leader: bool,
+    // 0 while there is no leader
+    pub leader_id: u64,
+    pub members: usize,
+    pub logs: usize,
+    pub last_log_id: u64
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum AdminError {
+    AuthError(AuthError),
+    SchemaError(SchemaError),
+    RateLimited(RateLimited),
+    SchemaNotFound(String),
+    // no vector index of the field on the server called
+    IndexNotFound(String),
+    // raft membership is managed through meta servers only
+    NotMetaServer,
+    // no member of the raft group has that address
+    NotMember(String),
+    Internal(String)
+}
+
+// health and group_health stay open for probes, they tell the group name and raft membership and
+// nothing of the graph. Everything else takes the caller's token first.
+// Operations that change something are recorded in the audit log, see server::audit.
+// Operations that scan the graph run on a thread of their own, the RPC threads stay free meanwhile.
+service! {
+    rpc health() -> HealthReport | String;
+    rpc schemas(token: String) -> Vec<SchemaSummary> | AdminError;
+    rpc new_schema(token: String, name: String, schema_type: SchemaType, key_field: Option<Vec<String>>, fields: Vec<Field>, is_dynamic: bool) -> u32 | AdminError;
+    rpc statistics(token: String) -> Vec<SchemaStatistics> | AdminError;
+    rpc collect_statistics(token: String) -> usize | AdminError;
+    rpc degree_report(token: String, hubs: usize) -> Vec<DegreeReport> | AdminError;
+    rpc define_role(token: String, role: Role) -> () | AdminError;
+    rpc create_user(token: String, name: String, roles: Vec<String>) -> String | AdminError;
+    rpc group_health() -> GroupHealth | AdminError;
+    rpc join_group(token: String, members: Vec<String>) -> () | AdminError;
+    rpc leave_group(token: String) -> () | AdminError;
+    rpc remove_member(token: String, address: String) -> () | AdminError;
+    rpc remove_vertices(token: String, schema: String, filter: Option<String>) -> usize | AdminError;
+    rpc audit_log(token: String, query: AuditQuery) -> Vec<AuditEntry> | AdminError;
+    rpc snapshot(token: String, path: String) -> usize | AdminError;
+    rpc rebuild_index(token: String, schema: String, field: String) -> usize | AdminError;
+}
+
+pub struct AdminService {
+    group: String,
+    graph: Arc<Graph>,
+    schemas: Arc<SchemaContainer>,
+    statistics: Arc<StatisticsContainer>,
+    auth: Arc<AuthContainer>,
+    rate_limiter: Arc<RateLimiter>,
+    audit: Arc<AuditLog>,
+    // None on servers that are not meta servers
+    raft_service: Option<Arc<RaftService>>,
+    // membership of the raft group, changed through its leader from any server
+    raft_config: ConfigSMClient
+}
+
+impl AdminService {
+    pub fn new<'a>(
+        group: &'a str,
+        graph: &Arc<Graph>,
+        schemas: &Arc<SchemaContainer>,
+        statistics: &Arc<StatisticsContainer>,
+        auth: &Arc<AuthContainer>,
+        rate_limiter: &Arc<RateLimiter>,
+        raft_service: &Option<Arc<RaftService>>,
+        raft_client: &Arc<RaftClient>,
+        audit: &Arc<AuditLog>
+    ) -> Arc<AdminService> {
+        Arc::new(AdminService {
+            group: group.to_string(),
+            graph: graph.clone(),
+            schemas: schemas.clone(),
+            statistics: statistics.clone(),
+            auth: auth.clone(),
+            rate_limiter: rate_limiter.clone(),
+            audit: audit.clone(),
+            raft_service: raft_service.clone(),
+            raft_config: ConfigSMClient::new(CONFIG_SM_ID, raft_client)
+        })
+    }
+
+    // users, roles and raft membership are of the whole cluster, only cluster grants pass
+    fn check(&self, token: &String, permission: Permission) -> Result<(), AdminError> {
+        self.auth.check(token, permission, &Resource::default()).map_err(AdminError::AuthError)
+    }
+
+    // schemas and statistics are of the graph served, grants on its namespace pass too
+    fn check_graph(&self, token: &String, permission: Permission) -> Result<(), AdminError> {
+        self.auth.check(token, permission, &self.resource(None)).map_err(AdminError::AuthError)
+    }
+
+    fn resource(&self, schema: Option<u32>) -> Resource {
+        Resource { namespace: self.graph.namespace().cloned(), schema }
+    }
+
+    // checks the token, then takes a write token from the bucket of its user
+    fn check_write(&self, token: &String, permission: Permission) -> Result<(), AdminError> {
+        self.check_write_on(token, permission, &Resource::default())
+    }
+
+    fn check_write_on(&self, token: &String, permission: Permission, resource: &Resource) -> Result<(), AdminError> {
+        self.auth.check(token, permission, resource).map_err(AdminError::AuthError)?;
+        self.rate_limiter.acquire(RateClass::Write, &self.auth.client_of(token), 1).map_err(AdminError::RateLimited)
+    }
+
+    fn raft(&self) -> Result<&Arc<RaftService>, AdminError> {
+        self.raft_service.as_ref().ok_or(AdminError::NotMetaServer)
+    }
+
+    fn audited<T>(&self, token: &String, action: AuditAction, target: &str, result: Result<T, AdminError>)
+        -> Box<Future<Item = T, Error = AdminError>> where T: 'static
+    {
+        Box::new(future::result(self.audit.record(&self.auth.client_of(token), action, target, result)))
+    }
+
+    // runs the job on a thread of its own and records it in the audit log once it is done
+    fn in_background<T, F>(&self, token: &String, action: Option<AuditAction>, target: &str, job: F)
+        -> Box<Future<Item = T, Error = AdminError>>
+        where T: Send + 'static, F: FnOnce() -> Result<T, AdminError> + Send + 'static
+    {
+        let (sender, receiver) = oneshot::channel();
+        let audit = self.audit.clone();
+        let user = self.auth.client_of(token);
+        let target = target.to_string();
+        let spawned = thread::Builder::new()
+            .name("morpheus-admin-job".to_string())
+            .spawn(move || {
+                let result = match action {
+                    Some(action) => audit.record(&user, action, &target, job()),
+                    None => job()
+                };
+                let _ = sender.send(result);
+            });
+        if let Err(e) = spawned {
+            return Box::new(future::err(AdminError::Internal(format!("cannot start the job: {:?}", e))));
+        }
+        Box::new(receiver
+            .map_err(|_| AdminError::Internal("the job stopped without a result".to_string()))
+            .and_then(future::result))
+    }
+}
+
+impl Service for AdminService {
+    fn health(&self) -> Box<Future<Item = HealthReport, Error = String>> {
+        let group = self.group.clone();
+        let statistics = self.statistics.all().len();
+        Box::new(self.schemas.count()
+            .map(move |schemas| HealthReport { group, schemas, statistics })
+            .map_err(|e| format!("{:?}", e)))
+    }
+
+    fn schemas(&self, token: String) -> Box<Future<Item = Vec<SchemaSummary>, Error = AdminError>> {
+        if let Err(e) = self.check_graph(&token, Permission::Read) {
+            return Box::new(future::err(e));
+        }
+        Box::new(self.schemas.all_morpheus_schemas()
+            .map(|schemas| {
+                schemas.into_iter().map(|schema| SchemaSummary {
+                    id: schema.id,
+                    name: schema.name,
+                    schema_type: schema.schema_type,
+                    key_field: schema.key_field,
+                    fields: schema.fields.iter().map(|f| f.name.clone()).collect(),
+                    is_dynamic: schema.is_dynamic
+                }).collect()
+            })
+            .map_err(|e| AdminError::Internal(format!("{:?}", e))))
+    }
+
+    fn new_schema(
+        &self, token: String, name: String, schema_type: SchemaType, key_field: Option<Vec<String>>,
+        fields: Vec<Field>, is_dynamic: bool
+    ) -> Box<Future<Item = u32, Error = AdminError>> {
+        if let Err(e) = self.check_write_on(&token, Permission::SchemaAdmin, &self.resource(None)) {
+            return self.audited(&token, AuditAction::NewSchema, &name, Err(e));
+        }
+        if self.graph.is_read_only() {
+            return self.audited(&token, AuditAction::NewSchema, &name, Err(AdminError::SchemaError(SchemaError::ReadOnly)));
+        }
+        let mut schema = MorpheusSchema::new(&name, key_field.as_ref(), &fields, is_dynamic);
+        schema.schema_type = schema_type;
+        let audit = self.audit.clone();
+        let user = self.auth.client_of(&token);
+        Box::new(self.schemas.new_schema(schema).map_err(AdminError::SchemaError)
+            .then(move |result| audit.record(&user, AuditAction::NewSchema, &name, result)))
+    }
+
+    fn statistics(&self, token: String) -> Box<Future<Item = Vec<SchemaStatistics>, Error = AdminError>> {
+        Box::new(future::result(self.check_graph(&token, Permission::Read).map(|_| self.statistics.all())))
+    }
+
+    // runs a full collection pass in place of waiting for the background collector
+    fn collect_statistics(&self, token: String) -> Box<Future<Item = usize, Error = AdminError>> {
+        if let Err(e) = self.check_graph(&token, Permission::SchemaAdmin) {
+            return self.audited(&token, AuditAction::CollectStatistics, "", Err(e));
+        }
+        let (graph, schemas, statistics) = (self.graph.clone(), self.schemas.clone(), self.statistics.clone());
+        self.in_background(&token, Some(AuditAction::CollectStatistics), "", move || {
+            let all_stats = stats::collect(&graph, &schemas).map_err(|e| AdminError::Internal(format!("{:?}", e)))?;
+            for s in &all_stats {
+                statistics.save(s).map_err(|e| AdminError::Internal(format!("{:?}", e)))?;
+            }
+            Ok(all_stats.len())
+        })
+    }
+
+    // degree distributions of every edge schema with the top hubs of each, read in a full pass
+    fn degree_report(&self, token: String, hubs: usize) -> Box<Future<Item = Vec<DegreeReport>, Error = AdminError>> {
+        if let Err(e) = self.check_graph(&token, Permission::SchemaAdmin) {
+            return Box::new(future::err(e));
+        }
+        let (graph, schemas) = (self.graph.clone(), self.schemas.clone());
+        self.in_background(&token, None, "", move || {
+            stats::degree_report(&graph, &schemas, hubs).map_err(|e| AdminError::Internal(format!("{:?}", e)))
+        })
+    }
+
+    fn define_role(&self, token: String, role: Role) -> Box<Future<Item = (), Error = AdminError>> {
+        let result = self.check_write(&token, Permission::SchemaAdmin)
+            .and_then(|_| self.auth.define_role(role.clone()).map_err(AdminError::AuthError));
+        self.audited(&token, AuditAction::DefineRole, &role.name, result)
+    }
+
+    fn create_user(&self, token: String, name: String, roles: Vec<String>) -> Box<Future<Item = String, Error = AdminError>> {
+        let result = self.check_write(&token, Permission::SchemaAdmin)
+            .and_then(|_| self.auth.create_user(name.clone(), roles).map_err(AdminError::AuthError));
+        self.audited(&token, AuditAction::CreateUser, &name, result)
+    }
+
+    fn group_health(&self) -> Box<Future<Item = GroupHealth, Error = AdminError>> {
+        let result = self.raft().map(|raft| GroupHealth {
+            server_id: raft.get_server_id(),
+            is_leader: raft.is_leader(),
+            leader_id: raft.leader_id(),
+            members: raft.num_members(),
+            logs: raft.num_logs(),
+            last_log_id: raft.last_log_id()
+        });
+        Box::new(future::result(result))
+    }
+
+    // Called on the server to add, with the addresses of meta servers already in the group.
+    // The server must have been started as a meta server.
+    fn join_group(&self, token: String, members: Vec<String>) -> Box<Future<Item = (), Error = AdminError>> {
+        let result = self.check(&token, Permission::SchemaAdmin)
+            .and_then(|_| self.raft())
+            .and_then(|raft| raft.join(&members).map(|_| ()).map_err(|e| AdminError::Internal(format!("{:?}", e))));
+        self.audited(&token, AuditAction::JoinGroup, &members.join(","), result)
+    }
+
+    // Called on the server to remove, it stops taking part in elections and replication
+    fn leave_group(&self, token: String) -> Box<Future<Item = (), Error = AdminError>> {
+        let result = self.check(&token, Permission::SchemaAdmin)
+            .and_then(|_| self.raft())
+            .and_then(|raft| if raft.leave() { Ok(()) } else {
+                Err(AdminError::Internal("cannot leave the raft group".to_string()))
+            });
+        self.audited(&token, AuditAction::LeaveGroup, &self.group, result)
+    }
+
+    // Removes the member with that address, on any server and whether the member is up or not.
+    // Raft has no way to hand leadership to a chosen member, removing the leader makes the others
+    // elect a new one, it takes part again once it is joined back with join_group.
+    fn remove_member(&self, token: String, address: String) -> Box<Future<Item = (), Error = AdminError>> {
+        let result = self.check(&token, Permission::SchemaAdmin).and_then(|_| {
+            let members = match self.raft_config.member_address() {
+                Ok(Ok(members)) => members,
+                other => return Err(AdminError::Internal(format!("cannot read the raft members: {:?}", other)))
+            };
+            if !members.contains(&address) {
+                return Err(AdminError::NotMember(address.clone()));
+            }
+            if members.len() < 2 {
+                return Err(AdminError::Internal("cannot remove the last member".to_string()));
+            }
+            match self.raft_config.del_member_(&address) {
+                Ok(Ok(_)) => Ok(()),
+                other => Err(AdminError::Internal(format!("cannot remove the member: {:?}", other)))
+            }
+        });
+        self.audited(&token, AuditAction::RemoveMember, &address, result)
+    }
+
+    // removes the vertices of the schema passing the filter, all of them without one, and returns how many
+    fn remove_vertices(&self, token: String, schema: String, filter: Option<String>)
+        -> Box<Future<Item = usize, Error = AdminError>>
+    {
+        let target = match filter {
+            Some(ref filter) => format!("{} {}", schema, filter),
+            None => schema.clone()
+        };
+        let checked = self.schemas.id_from_name(&schema)
+            .ok_or_else(|| AdminError::SchemaNotFound(schema.clone()))
+            .and_then(|schema_id| {
+                self.check_write_on(&token, Permission::Write, &self.resource(Some(schema_id))).map(|_| schema_id)
+            });
+        let schema_id = match checked {
+            Ok(schema_id) => schema_id,
+            Err(e) => return self.audited(&token, AuditAction::RemoveVertices, &target, Err(e))
+        };
+        let graph = self.graph.clone();
+        self.in_background(&token, Some(AuditAction::RemoveVertices), &target, move || {
+            graph.remove_vertices_where(schema_id, &filter, REMOVAL_BATCH_SIZE)
+                .fold(0, |_, progress| Ok::<_, RemoveVerticesError>(progress.removed))
+                .wait()
+                .map_err(|e| AdminError::Internal(format!("{:?}", e)))
+        })
+    }
+
+    fn audit_log(&self, token: String, query: AuditQuery) -> Box<Future<Item = Vec<AuditEntry>, Error = AdminError>> {
+        let result = self.check(&token, Permission::SchemaAdmin)
+            .and_then(|_| self.audit.query(&query).map_err(|e| AdminError::Internal(format!("{:?}", e))));
+        Box::new(future::result(result))
+    }
+
+    // Writes every vertex and edge as N-Triples to the path, on the server called, and returns the number
+    // of triples. Vertices are read in pages of their own transactions, writes committing meanwhile
+    // may or may not be in the file.
+    fn snapshot(&self, token: String, path: String) -> Box<Future<Item = usize, Error = AdminError>> {
+        if let Err(e) = self.check_graph(&token, Permission::SchemaAdmin) {
+            return self.audited(&token, AuditAction::Snapshot, &path, Err(e));
+        }
+        let graph = self.graph.clone();
+        let file_path = path.clone();
+        self.in_background(&token, Some(AuditAction::Snapshot), &path, move || {
+            let file = File::create(&file_path).map_err(|e| AdminError::Internal(format!("{:?}", e)))?;
+            let mut output = BufWriter::new(file);
+            let triples = rdf::export_ntriples(&graph, &mut output, &RdfOptions::default())
+                .map_err(|e| AdminError::Internal(format!("{:?}", e)))?;
+            output.flush().map_err(|e| AdminError::Internal(format!("{:?}", e)))?;
+            Ok(triples)
+        })
+    }
+
+    // Builds the vector index of the field again on the server called, with the options it was built
+    // with, and returns the number of vectors indexed. Vector indexes are kept per server.
+    fn rebuild_index(&self, token: String, schema: String, field: String) -> Box<Future<Item = usize, Error = AdminError>> {
+        let target = format!("{}.{}", schema, field);
+        let checked = self.schemas.id_from_name(&schema)
+            .ok_or_else(|| AdminError::SchemaNotFound(schema.clone()))
+            .and_then(|schema_id| {
+                self.check_write_on(&token, Permission::SchemaAdmin, &self.resource(Some(schema_id))).map(|_| schema_id)
+            });
+        let schema_id = match checked {
+            Ok(schema_id) => schema_id,
+            Err(e) => return self.audited(&token, AuditAction::RebuildIndex, &target, Err(e))
+        };
+        let graph = self.graph.clone();
+        let index = target.clone();
+        self.in_background(&token, Some(AuditAction::RebuildIndex), &target, move || {
+            match graph.rebuild_vector_index(schema_id, &field) {
+                Some(rebuild) => rebuild.wait().map_err(|e| AdminError::Internal(format!("{:?}", e))),
+                None => Err(AdminError::IndexNotFound(index))
+            }
+        })
+    }
+}
+dispatch_rpc_service_functions!(AdminService);
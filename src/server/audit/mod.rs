@@ -0,0 +1,140 @@
+// Audit log of schema and data administration, kept in raft. Every operation of the admin service
+// that changes something is recorded with the user of its token, as the auth container knows them,
+// and how it ended, denied attempts included; reads and health checks are not. Entries are keyed by
+// snowflake ids, so they are unique across servers and sort by time up to clock skew, and are only
+// ever inserted. A failure to record is logged and does not fail the operation it records. Queries
+// read the whole log from raft and filter it on the server, they are meant for admins, not for hot paths.
+
+use bifrost::raft::RaftService;
+use bifrost::raft::client::RaftClient;
+use bifrost::raft::state_machine::master::ExecError;
+use bifrost_hasher::hash_str;
+
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use graph::ids;
+use server::audit::sm::audit_entries::client::SMClient;
+
+mod sm;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    NewSchema,
+    CollectStatistics,
+    DefineRole,
+    CreateUser,
+    RemoveVertices,
+    JoinGroup,
+    LeaveGroup,
+    // no longer recorded, kept so older entries still decode
+    StepDown,
+    Snapshot,
+    RebuildIndex,
+    RemoveMember
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub id: u64,
+    // milliseconds since the epoch on the server that recorded it
+    pub at_ms: u64,
+    // anonymous while authentication is off
+    pub user: String,
+    pub action: AuditAction,
+    // what the operation was about, a schema name, a role, raft members
+    pub target: String,
+    // why it failed or was denied, None when it went through
+    pub error: Option<String>
+}
+
+// entries match when they match every field that is set
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AuditQuery {
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default)]
+    pub action: Option<AuditAction>,
+    #[serde(default)]
+    pub since_ms: Option<u64>,
+    #[serde(default)]
+    pub until_ms: Option<u64>,
+    // the latest entries when there are more, all of them when None
+    #[serde(default)]
+    pub limit: Option<usize>
+}
+
+pub struct AuditLog {
+    sm_client: Arc<SMClient>
+}
+
+pub fn generate_sm_id<'a>(group: &'a str) -> u64 {
+    hash_str(&format!("{}-{}", sm::DEFAULT_RAFT_PREFIX, group))
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() * 1000 + d.subsec_nanos() as u64 / 1_000_000)
+        .unwrap_or(0)
+}
+
+impl AuditQuery {
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        self.user.as_ref().map_or(true, |user| user == &entry.user)
+            && self.action.map_or(true, |action| action == entry.action)
+            && self.since_ms.map_or(true, |since_ms| entry.at_ms >= since_ms)
+            && self.until_ms.map_or(true, |until_ms| entry.at_ms < until_ms)
+    }
+}
+
+impl AuditLog {
+    pub fn new_meta_service<'a>(group: &'a str, raft_service: &Arc<RaftService>) {
+        let mut entries_sm = sm::audit_entries::Map::new(generate_sm_id(group));
+        entries_sm.init_callback(raft_service);
+        raft_service.register_state_machine(Box::new(entries_sm));
+    }
+
+    pub fn new_client<'a>(group: &'a str, raft_client: &Arc<RaftClient>) -> Arc<AuditLog> {
+        Arc::new(AuditLog { sm_client: Arc::new(SMClient::new(generate_sm_id(group), &raft_client)) })
+    }
+
+    pub fn append(&self, user: &str, action: AuditAction, target: &str, error: Option<String>)
+        -> Result<AuditEntry, ExecError>
+    {
+        let entry = AuditEntry {
+            id: ids::snowflake(),
+            at_ms: now_ms(),
+            user: user.to_string(),
+            action,
+            target: target.to_string(),
+            error
+        };
+        self.sm_client.insert(&entry.id, &entry)?;
+        Ok(entry)
+    }
+
+    // appends the outcome of an operation and hands the outcome back
+    pub fn record<T, E>(&self, user: &str, action: AuditAction, target: &str, result: Result<T, E>) -> Result<T, E>
+        where E: ::std::fmt::Debug
+    {
+        let error = result.as_ref().err().map(|e| format!("{:?}", e));
+        if let Err(e) = self.append(user, action, target, error) {
+            warn!("Cannot record {:?} of {} by {} in the audit log: {:?}", action, target, user, e);
+        }
+        result
+    }
+
+    // matching entries, oldest first
+    pub fn query(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>, ExecError> {
+        let mut entries: Vec<AuditEntry> = self.sm_client.entries()?.unwrap().into_iter()
+            .map(|(_, entry)| entry)
+            .filter(|entry| query.matches(entry))
+            .collect();
+        entries.sort_by_key(|entry| entry.id);
+        if let Some(limit) = query.limit {
+            let skip = entries.len().saturating_sub(limit);
+            entries.drain(..skip);
+        }
+        Ok(entries)
+    }
+}
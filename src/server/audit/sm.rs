@@ -0,0 +1,6 @@
+use std::collections::HashMap;
+use super::AuditEntry;
+
+pub static DEFAULT_RAFT_PREFIX: &'static str = "MORPHEUS_AUDIT_RAFT_SM";
+
+def_store_hash_map!(audit_entries <u64, AuditEntry>);
@@ -0,0 +1,268 @@
+use bifrost::raft::RaftService;
+use bifrost::raft::client::RaftClient;
+use bifrost::raft::state_machine::master::ExecError;
+use bifrost_hasher::hash_str;
+use chashmap::CHashMap;
+use rand::{self, Rng};
+use sha2::{Sha256, Digest};
+use std::sync::Arc;
+
+use server::auth::sm::auth_users::client::SMClient as UsersSMClient;
+use server::auth::sm::auth_roles::client::SMClient as RolesSMClient;
+use server::rate_limit::ANONYMOUS_CLIENT;
+
+mod sm;
+
+pub static ROOT_USER: &'static str = "root";
+pub static ROOT_ROLE: &'static str = "root";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Permission {
+    Read,
+    Write,
+    SchemaAdmin
+}
+
+impl Permission {
+    // schema admins can write and writers can read
+    pub fn implies(&self, other: Permission) -> bool {
+        match (*self, other) {
+            (Permission::SchemaAdmin, _) => true,
+            (Permission::Write, Permission::Read) => true,
+            (a, b) => a == b
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub enum Scope {
+    Cluster,
+    Namespace(String),
+    Schema(u32)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Grant {
+    pub permission: Permission,
+    pub scope: Scope
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Role {
+    pub name: String,
+    pub grants: Vec<Grant>
+}
+
+// tokens are only kept as sha256 digests, the plain token is handed out once on creation
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct User {
+    pub name: String,
+    pub token_digest: String,
+    pub roles: Vec<String>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum AuthError {
+    InvalidToken,
+    PermissionDenied,
+    UserExists,
+    UnknownRole(String),
+    ExecError(ExecError)
+}
+
+// what a request touches, a schema check also passes with a grant on the namespace holding it
+#[derive(Debug, Clone, Default)]
+pub struct Resource {
+    pub namespace: Option<String>,
+    pub schema: Option<u32>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AuthOptions {
+    #[serde(default)]
+    pub enabled: bool,
+    // token of the root user, created on first start when no users exist yet
+    #[serde(default)]
+    pub root_token: Option<String>
+}
+
+pub struct AuthContainer {
+    enabled: bool,
+    users: Arc<CHashMap<String, User>>,
+    roles: Arc<CHashMap<String, Role>>,
+    tokens: Arc<CHashMap<String, String>>,
+    users_sm: Arc<UsersSMClient>,
+    roles_sm: Arc<RolesSMClient>
+}
+
+fn sm_id<'a>(prefix: &'a str, group: &'a str) -> u64 {
+    hash_str(&format!("{}-{}", prefix, group))
+}
+
+fn token_digest<'a>(token: &'a str) -> String {
+    Sha256::digest(token.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn generate_token() -> String {
+    let mut rng = rand::thread_rng();
+    (0..32).map(|_| format!("{:02x}", rng.gen::<u8>())).collect()
+}
+
+impl AuthContainer {
+
+    pub fn new_meta_service<'a>(group: &'a str, raft_service: &Arc<RaftService>) {
+        let mut users_sm = sm::auth_users::Map::new(sm_id(sm::USERS_RAFT_PREFIX, group));
+        let mut roles_sm = sm::auth_roles::Map::new(sm_id(sm::ROLES_RAFT_PREFIX, group));
+        users_sm.init_callback(raft_service);
+        roles_sm.init_callback(raft_service);
+        raft_service.register_state_machine(Box::new(users_sm));
+        raft_service.register_state_machine(Box::new(roles_sm));
+    }
+
+    pub fn new_client<'a>(
+        group: &'a str,
+        raft_client: &Arc<RaftClient>,
+        options: &AuthOptions
+    ) -> Result<Arc<AuthContainer>, ExecError> {
+        let users_sm = Arc::new(UsersSMClient::new(sm_id(sm::USERS_RAFT_PREFIX, group), &raft_client));
+        let roles_sm = Arc::new(RolesSMClient::new(sm_id(sm::ROLES_RAFT_PREFIX, group), &raft_client));
+        let container = Arc::new(AuthContainer {
+            enabled: options.enabled,
+            users: Arc::new(CHashMap::new()),
+            roles: Arc::new(CHashMap::new()),
+            tokens: Arc::new(CHashMap::new()),
+            users_sm: users_sm.clone(),
+            roles_sm: roles_sm.clone()
+        });
+        for (name, user) in users_sm.entries()?.unwrap() {
+            container.tokens.insert(user.token_digest.clone(), name.clone());
+            container.users.insert(name, user);
+        }
+        for (name, role) in roles_sm.entries()?.unwrap() {
+            container.roles.insert(name, role);
+        }
+        let users = container.users.clone();
+        let tokens = container.tokens.clone();
+        users_sm.on_inserted(move |res| {
+            if let Ok((name, user)) = res {
+                if let Some(old) = users.get(&name) {
+                    tokens.remove(&old.token_digest);
+                }
+                tokens.insert(user.token_digest.clone(), name.clone());
+                users.insert(name, user);
+            }
+        })?;
+        let roles = container.roles.clone();
+        roles_sm.on_inserted(move |res| {
+            if let Ok((name, role)) = res {
+                roles.insert(name, role);
+            }
+        })?;
+        if options.enabled && container.users.len() == 0 {
+            if let Some(ref root_token) = options.root_token {
+                container.bootstrap_root(root_token)?;
+            } else {
+                warn!("Authentication is enabled but there are no users and no root token is configured");
+            }
+        }
+        Ok(container)
+    }
+
+    fn bootstrap_root<'a>(&self, root_token: &'a str) -> Result<(), ExecError> {
+        let role = Role {
+            name: ROOT_ROLE.to_string(),
+            grants: vec![Grant { permission: Permission::SchemaAdmin, scope: Scope::Cluster }]
+        };
+        let user = User {
+            name: ROOT_USER.to_string(),
+            token_digest: token_digest(root_token),
+            roles: vec![ROOT_ROLE.to_string()]
+        };
+        self.define_role(role).and_then(|_| self.save_user(user)).map_err(|e| match e {
+            AuthError::ExecError(e) => e,
+            other => panic!("unexpected error while creating root user: {:?}", other)
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn authenticate<'a>(&self, token: &'a str) -> Result<User, AuthError> {
+        let name = match self.tokens.get(&token_digest(token)) {
+            Some(name) => name.clone(),
+            None => return Err(AuthError::InvalidToken)
+        };
+        self.users.get(&name).map(|u| u.clone()).ok_or(AuthError::InvalidToken)
+    }
+
+    pub fn authorize(&self, user: &User, permission: Permission, resource: &Resource) -> Result<(), AuthError> {
+        for role_name in &user.roles {
+            if let Some(role) = self.roles.get(role_name) {
+                let granted = role.grants.iter().any(|grant| {
+                    grant.permission.implies(permission) && match grant.scope {
+                        Scope::Cluster => true,
+                        Scope::Namespace(ref ns) => resource.namespace.as_ref() == Some(ns),
+                        Scope::Schema(id) => resource.schema == Some(id)
+                    }
+                });
+                if granted { return Ok(()); }
+            }
+        }
+        Err(AuthError::PermissionDenied)
+    }
+
+    // Entry point for the RPC layer, always passes while authentication is off
+    pub fn check<'a>(&self, token: &'a str, permission: Permission, resource: &Resource) -> Result<(), AuthError> {
+        if !self.enabled { return Ok(()); }
+        let user = self.authenticate(token)?;
+        self.authorize(&user, permission, resource)
+    }
+
+    // who a request is from for rate limiting, the user of the token or anonymous
+    pub fn client_of<'a>(&self, token: &'a str) -> String {
+        match self.authenticate(token) {
+            Ok(user) if self.enabled => user.name,
+            _ => ANONYMOUS_CLIENT.to_string()
+        }
+    }
+
+    // the local caches are also filled here so the caller sees its own change before the raft callback
+    pub fn define_role(&self, role: Role) -> Result<(), AuthError> {
+        self.roles_sm.insert(&role.name, &role).map_err(AuthError::ExecError)?;
+        self.roles.insert(role.name.clone(), role);
+        Ok(())
+    }
+
+    fn save_user(&self, user: User) -> Result<(), AuthError> {
+        self.users_sm.insert(&user.name, &user).map_err(AuthError::ExecError)?;
+        if let Some(old) = self.users.get(&user.name) {
+            self.tokens.remove(&old.token_digest);
+        }
+        self.tokens.insert(user.token_digest.clone(), user.name.clone());
+        self.users.insert(user.name.clone(), user);
+        Ok(())
+    }
+
+    // returns the token for the new user, the cluster only keeps its digest
+    pub fn create_user(&self, name: String, roles: Vec<String>) -> Result<String, AuthError> {
+        if self.users.contains_key(&name) { return Err(AuthError::UserExists); }
+        if let Some(missing) = roles.iter().find(|r| !self.roles.contains_key(*r)) {
+            return Err(AuthError::UnknownRole(missing.clone()));
+        }
+        let token = generate_token();
+        self.save_user(User { name, token_digest: token_digest(&token), roles })?;
+        Ok(token)
+    }
+
+    pub fn rotate_token<'a>(&self, name: &'a str) -> Result<String, AuthError> {
+        let mut user = match self.users.get(name) {
+            Some(user) => user.clone(),
+            None => return Err(AuthError::InvalidToken)
+        };
+        let token = generate_token();
+        user.token_digest = token_digest(&token);
+        self.save_user(user)?;
+        Ok(token)
+    }
+}
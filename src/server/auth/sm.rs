@@ -0,0 +1,8 @@
+use std::collections::HashMap;
+use super::{User, Role};
+
+pub static USERS_RAFT_PREFIX: &'static str = "MORPHEUS_AUTH_USERS_RAFT_SM";
+pub static ROLES_RAFT_PREFIX: &'static str = "MORPHEUS_AUTH_ROLES_RAFT_SM";
+
+def_store_hash_map!(auth_users <String, User>);
+def_store_hash_map!(auth_roles <String, Role>);
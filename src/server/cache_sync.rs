@@ -0,0 +1,74 @@
+// Remote invalidation of the vertex cache. Every server with the vertex cache on serves invalidate,
+// and a notifier thread sends the ids written through this server to the configured peers every
+// notify_interval_ms. Notifications are best effort, a peer that cannot be reached is skipped and
+// its cache catches up when the entries expire.
+
+use bifrost::rpc::*;
+use neb::ram::types::Id;
+use futures::prelude::*;
+use futures::future;
+
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::thread;
+use std::time::Duration;
+
+use graph::Graph;
+use graph::gc::pause;
+use graph::vertex_cache::VertexCache;
+use server::metrics;
+
+pub static CACHE_SYNC_SERVICE_ID: u64 = hash_ident!(MORPHEUS_CACHE_SYNC_RPC_SERVICE) as u64;
+
+service! {
+    rpc invalidate(ids: Vec<Id>) -> () | String;
+}
+
+pub struct CacheSyncService {
+    graph: Arc<Graph>
+}
+
+impl CacheSyncService {
+    pub fn new(graph: &Arc<Graph>) -> Arc<CacheSyncService> {
+        Arc::new(CacheSyncService { graph: graph.clone() })
+    }
+}
+
+impl Service for CacheSyncService {
+    fn invalidate(&self, ids: Vec<Id>) -> Box<Future<Item = (), Error = String>> {
+        if let Some(cache) = self.graph.vertex_cache() {
+            cache.invalidate(&ids);
+        }
+        Box::new(future::ok(()))
+    }
+}
+dispatch_rpc_service_functions!(CacheSyncService);
+
+fn notify(peer: &String, ids: &Vec<Id>) -> Result<(), String> {
+    let rpc_client = DEFAULT_CLIENT_POOL.get(peer).map_err(|e| format!("{:?}", e))?;
+    let service = AsyncServiceClient::new(CACHE_SYNC_SERVICE_ID, &rpc_client);
+    match service.invalidate(ids).wait() {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) => Err(e),
+        Err(e) => Err(format!("{:?}", e))
+    }
+}
+
+pub fn start_notifier(cache: Arc<VertexCache>, running: Arc<AtomicBool>) -> thread::JoinHandle<()> {
+    let interval = Duration::from_millis(cache.options().notify_interval_ms);
+    thread::Builder::new()
+        .name("morpheus-cache-sync".to_string())
+        .spawn(move || {
+            while pause(&running, interval) {
+                let ids = cache.take_outbox();
+                if ids.is_empty() { continue; }
+                for peer in &cache.options().peers {
+                    match notify(peer, &ids) {
+                        Ok(()) => { let _ = metrics::VERTEX_CACHE_NOTIFICATIONS.inc_by(ids.len() as f64); },
+                        Err(e) => debug!("Cannot notify {} of {} written vertices: {}", peer, ids.len(), e)
+                    }
+                }
+            }
+        })
+        .unwrap()
+}
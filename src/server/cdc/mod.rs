@@ -0,0 +1,221 @@
+// Change data capture connectors, publishing the change log of graph::changes to other systems.
+// The Kafka sink sends every committed vertex and edge write of the default graph as a JSON message
+// to the topic of its schema, keyed by the id of the vertex or edge so the writes of one of them stay
+// in order within a partition. After Kafka acknowledges a batch from all in sync replicas, the sequence
+// number of its last event is saved in raft under the connector name and the server address, a
+// restarted sink resumes after it. Delivery is at least once: a batch is sent again when the
+// acknowledgement or the offset commit fails. The change log itself is in memory, events committed
+// but not yet published when the server stops are lost, as are those pushed out of a full log.
+
+use bifrost::raft::RaftService;
+use bifrost::raft::client::RaftClient;
+use bifrost::raft::state_machine::master::ExecError;
+use bifrost_hasher::hash_str;
+use kafka::producer::{Producer, Record, RequiredAcks};
+use kafka;
+use serde_json;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use graph::Graph;
+use graph::changes::ChangeEvent;
+use graph::gc::pause;
+use server::metrics;
+use server::cdc::sm::cdc_offsets::client::SMClient;
+
+mod sm;
+
+fn default_name() -> String { "default".to_string() }
+fn default_topic_prefix() -> String { "morpheus.".to_string() }
+fn default_batch_size() -> usize { 512 }
+fn default_log_capacity() -> usize { 100000 }
+fn default_ack_timeout_ms() -> u64 { 5000 }
+fn default_retry_interval_ms() -> u64 { 1000 }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KafkaSinkOptions {
+    // offsets are kept by name, a renamed sink starts from the oldest event still in the log
+    #[serde(default = "default_name")]
+    pub name: String,
+    // host:port of the bootstrap brokers
+    pub brokers: Vec<String>,
+    // topic by schema name, other schemas publish to topic_prefix followed by their name
+    #[serde(default)]
+    pub topics: HashMap<String, String>,
+    #[serde(default = "default_topic_prefix")]
+    pub topic_prefix: String,
+    // events per produce request
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    // events the change log keeps for the sink to catch up on
+    #[serde(default = "default_log_capacity")]
+    pub log_capacity: usize,
+    #[serde(default = "default_ack_timeout_ms")]
+    pub ack_timeout_ms: u64,
+    // pause after a failed send or offset lookup
+    #[serde(default = "default_retry_interval_ms")]
+    pub retry_interval_ms: u64
+}
+
+#[derive(Debug)]
+pub enum PublishError {
+    KafkaError(kafka::Error),
+    // topic, partition and the error code of a partition that did not take its messages
+    Rejected(String, i32, String),
+    SerializeError(serde_json::Error)
+}
+
+pub struct OffsetStore {
+    sm_client: Arc<SMClient>
+}
+
+pub fn generate_sm_id<'a>(group: &'a str) -> u64 {
+    hash_str(&format!("{}-{}", sm::DEFAULT_RAFT_PREFIX, group))
+}
+
+impl OffsetStore {
+    pub fn new_meta_service<'a>(group: &'a str, raft_service: &Arc<RaftService>) {
+        let mut offsets_sm = sm::cdc_offsets::Map::new(generate_sm_id(group));
+        offsets_sm.init_callback(raft_service);
+        raft_service.register_state_machine(Box::new(offsets_sm));
+    }
+
+    pub fn new_client<'a>(group: &'a str, raft_client: &Arc<RaftClient>) -> Arc<OffsetStore> {
+        Arc::new(OffsetStore { sm_client: Arc::new(SMClient::new(generate_sm_id(group), &raft_client)) })
+    }
+
+    // sequence number of the last event acknowledged, 0 when nothing was published yet
+    pub fn get(&self, key: &String) -> Result<u64, ExecError> {
+        self.sm_client.get(key).map(|offset| offset.unwrap_or(0))
+    }
+
+    pub fn commit(&self, key: &String, seq: u64) -> Result<(), ExecError> {
+        self.sm_client.insert(key, &seq).map(|_| ())
+    }
+}
+
+pub fn offset_key(name: &str, server_addr: &str) -> String {
+    format!("{}@{}", name, server_addr)
+}
+
+fn topic_of(graph: &Graph, options: &KafkaSinkOptions, schema_id: u32) -> String {
+    let name = graph.schema_name(schema_id).unwrap_or_else(|| schema_id.to_string());
+    match options.topics.get(&name) {
+        Some(topic) => topic.clone(),
+        None => format!("{}{}", options.topic_prefix, name)
+    }
+}
+
+// the vertex or edge body, edges without one by their ends
+fn message_key(event: &ChangeEvent) -> String {
+    match event.ends {
+        Some((from, to)) if event.id.is_unit_id() =>
+            format!("{}:{}-{}:{}", from.higher, from.lower, to.higher, to.lower),
+        _ => format!("{}:{}", event.id.higher, event.id.lower)
+    }
+}
+
+fn connect(options: &KafkaSinkOptions) -> Result<Producer, kafka::Error> {
+    Producer::from_hosts(options.brokers.clone())
+        .with_ack_timeout(Duration::from_millis(options.ack_timeout_ms))
+        .with_required_acks(RequiredAcks::All)
+        .create()
+}
+
+pub fn publish(producer: &mut Producer, graph: &Graph, options: &KafkaSinkOptions, events: &[ChangeEvent])
+    -> Result<(), PublishError>
+{
+    let mut messages = Vec::with_capacity(events.len());
+    for event in events {
+        let value = serde_json::to_vec(event).map_err(PublishError::SerializeError)?;
+        messages.push((topic_of(graph, options, event.schema), message_key(event), value));
+    }
+    let records: Vec<_> = messages.iter()
+        .map(|&(ref topic, ref key, ref value)| Record::from_key_value(topic.as_str(), key.as_bytes(), &value[..]))
+        .collect();
+    let confirms = producer.send_all(&records).map_err(PublishError::KafkaError)?;
+    for confirm in confirms {
+        for partition in confirm.partition_confirms {
+            if let Err(code) = partition.offset {
+                return Err(PublishError::Rejected(confirm.topic.to_string(), partition.partition, format!("{:?}", code)));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Turns the change log of the graph on and publishes it until running is cleared
+pub fn start_kafka_sink(
+    graph: Arc<Graph>, offsets: Arc<OffsetStore>, options: KafkaSinkOptions, server_addr: &str, running: Arc<AtomicBool>
+) -> thread::JoinHandle<()> {
+    let log = graph.enable_change_log(options.log_capacity);
+    let key = offset_key(&options.name, server_addr);
+    thread::Builder::new()
+        .name("morpheus-cdc-kafka".to_string())
+        .spawn(move || {
+            let retry_interval = Duration::from_millis(options.retry_interval_ms);
+            let poll = Duration::from_millis(100);
+            let mut producer: Option<Producer> = None;
+            // read from raft on first use, the meta servers may not be reachable yet
+            let mut offset: Option<u64> = None;
+            let mut batch: Vec<ChangeEvent> = Vec::new();
+            while running.load(Ordering::Relaxed) {
+                let committed = match offset {
+                    Some(committed) => committed,
+                    None => match offsets.get(&key) {
+                        Ok(committed) => { offset = Some(committed); committed },
+                        Err(e) => {
+                            warn!("CDC sink {} cannot read its offset: {:?}", key, e);
+                            if !pause(&running, retry_interval) { break; }
+                            continue;
+                        }
+                    }
+                };
+                if batch.is_empty() {
+                    batch = log.read_after(committed, ::std::cmp::max(options.batch_size, 1), poll);
+                    match batch.first() {
+                        Some(first) if committed > 0 && first.seq > committed + 1 =>
+                            warn!("CDC sink {} lost the events after {} up to {}", key, committed, first.seq),
+                        Some(_) => {},
+                        None => continue
+                    }
+                }
+                if producer.is_none() {
+                    match connect(&options) {
+                        Ok(connected) => producer = Some(connected),
+                        Err(e) => {
+                            warn!("CDC sink {} cannot connect to {:?}: {:?}", key, options.brokers, e);
+                            if !pause(&running, retry_interval) { break; }
+                            continue;
+                        }
+                    }
+                }
+                let published = match producer {
+                    Some(ref mut producer) => publish(producer, &graph, &options, &batch),
+                    None => continue
+                };
+                match published {
+                    Ok(()) => {
+                        let last = batch[batch.len() - 1].seq;
+                        let _ = metrics::CDC_EVENTS_PUBLISHED.inc_by(batch.len() as f64);
+                        batch.clear();
+                        offset = Some(last);
+                        if let Err(e) = offsets.commit(&key, last) {
+                            warn!("CDC sink {} cannot save offset {}, a restart sends again: {:?}", key, last, e);
+                        }
+                    },
+                    Err(e) => {
+                        warn!("CDC sink {} failed to publish {} events, retrying: {:?}", key, batch.len(), e);
+                        producer = None;
+                        if !pause(&running, retry_interval) { break; }
+                    }
+                }
+            }
+            debug!("CDC sink {} stopped", key);
+        })
+        .unwrap()
+}
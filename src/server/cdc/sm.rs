@@ -0,0 +1,5 @@
+use std::collections::HashMap;
+
+pub static DEFAULT_RAFT_PREFIX: &'static str = "MORPHEUS_CDC_RAFT_SM";
+
+def_store_hash_map!(cdc_offsets <String, u64>);
@@ -0,0 +1,279 @@
+// Runs query operations against the graph. Every root field is resolved in a read transaction of its
+// own, relations nested in it are traversed from the vertices found, fetching neighbourhoods of one
+// hop per level and converting only the fields selected. List fields take first and offset, first is
+// capped at the page size limit. Relations nest no deeper than the depth limit.
+
+use neb::ram::types::{Id, Value, key_hash};
+use serde_json::{Value as Json, Map as JsonMap, Number};
+use futures::prelude::*;
+
+use graph::Graph;
+use graph::vertex::Vertex;
+use graph::snapshot::ReadTransaction;
+use import::stream;
+use server::graphql::parser::{Document, Field, Fragment, Input, Directive, OperationKind, Selection};
+use server::graphql::schema::{GraphqlSchema, FieldKind, QUERY_TYPE, VERTEX_INTERFACE};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub max_page_size: usize,
+    pub max_depth: usize
+}
+
+pub fn format_id(id: &Id) -> String {
+    format!("{}:{}", id.higher, id.lower)
+}
+
+pub fn parse_id(id: &str) -> Option<Id> {
+    let mut parts = id.splitn(2, ':');
+    match (parts.next().and_then(|h| h.parse().ok()), parts.next().and_then(|l| l.parse().ok())) {
+        (Some(higher), Some(lower)) => Some(Id::new(higher, lower)),
+        _ => None
+    }
+}
+
+pub fn to_json(value: &Value) -> Json {
+    match value {
+        &Value::Null => Json::Null,
+        &Value::Bool(b) => Json::Bool(b),
+        &Value::I8(n) => Json::from(n),
+        &Value::I16(n) => Json::from(n),
+        &Value::I32(n) => Json::from(n),
+        &Value::I64(n) => Json::from(n),
+        &Value::U8(n) => Json::from(n),
+        &Value::U16(n) => Json::from(n),
+        &Value::U32(n) => Json::from(n),
+        &Value::U64(n) => Json::from(n),
+        &Value::F32(n) => Number::from_f64(n as f64).map(Json::Number).unwrap_or(Json::Null),
+        &Value::F64(n) => Number::from_f64(n).map(Json::Number).unwrap_or(Json::Null),
+        &Value::String(ref s) => Json::String(s.clone()),
+        &Value::Id(ref id) => Json::String(format_id(id)),
+        &Value::Array(ref items) => Json::Array(items.iter().map(to_json).collect()),
+        &Value::Map(ref map) => {
+            let mut object = JsonMap::new();
+            for name in &map.fields {
+                object.insert(name.clone(), to_json(map.get_by_key_id(key_hash(name))));
+            }
+            Json::Object(object)
+        },
+        other => Json::String(format!("{:?}", other))
+    }
+}
+
+struct Context<'a> {
+    graph: &'a Graph,
+    schema: &'a GraphqlSchema,
+    fragments: &'a [Fragment],
+    variables: JsonMap<String, Json>,
+    limits: Limits
+}
+
+impl <'a> Context<'a> {
+    fn input(&self, input: &Input) -> Json {
+        match input {
+            &Input::Null => Json::Null,
+            &Input::Bool(b) => Json::Bool(b),
+            &Input::Int(i) => Json::from(i),
+            &Input::Float(f) => Number::from_f64(f).map(Json::Number).unwrap_or(Json::Null),
+            &Input::String(ref s) | &Input::Enum(ref s) => Json::String(s.clone()),
+            &Input::List(ref items) => Json::Array(items.iter().map(|i| self.input(i)).collect()),
+            &Input::Object(ref fields) => Json::Object(
+                fields.iter().map(|&(ref name, ref value)| (name.clone(), self.input(value))).collect()
+            ),
+            &Input::Variable(ref name) => self.variables.get(name).cloned().unwrap_or(Json::Null)
+        }
+    }
+
+    fn argument(&self, field: &Field, name: &str) -> Json {
+        field.argument(name).map(|input| self.input(input)).unwrap_or(Json::Null)
+    }
+
+    // @skip(if: true) and @include(if: false) leave the selection out
+    fn included(&self, directives: &[Directive]) -> bool {
+        directives.iter().all(|directive| {
+            let condition = directive.arguments.iter()
+                .find(|&&(ref name, _)| name == "if")
+                .map(|&(_, ref value)| self.input(value) == Json::Bool(true));
+            match (directive.name.as_str(), condition) {
+                ("skip", Some(true)) => false,
+                ("include", Some(false)) => false,
+                _ => true
+            }
+        })
+    }
+
+    fn applies(&self, type_condition: Option<&str>, type_name: &str) -> bool {
+        match type_condition {
+            None => true,
+            Some(condition) => condition == type_name || (condition == VERTEX_INTERFACE && type_name != QUERY_TYPE)
+        }
+    }
+
+    // the fields selected on an object of the type, fragments flattened and fields of the same
+    // response key merged
+    fn collect_fields(&self, type_name: &str, selections: &[Selection], fields: &mut Vec<Field>, visited: &mut Vec<String>)
+        -> Result<(), String>
+    {
+        for selection in selections {
+            match selection {
+                &Selection::Field(ref field) => {
+                    if !self.included(&field.directives) { continue; }
+                    match fields.iter().position(|f| f.response_key() == field.response_key()) {
+                        Some(pos) => fields[pos].selections.extend(field.selections.iter().cloned()),
+                        None => fields.push(field.clone())
+                    }
+                },
+                &Selection::InlineFragment { ref type_condition, ref directives, ref selections } => {
+                    if !self.included(directives) { continue; }
+                    if self.applies(type_condition.as_ref().map(|t| t.as_str()), type_name) {
+                        self.collect_fields(type_name, selections, fields, visited)?;
+                    }
+                },
+                &Selection::FragmentSpread { ref name, ref directives } => {
+                    if !self.included(directives) || visited.contains(name) { continue; }
+                    let fragment = self.fragments.iter().find(|f| &f.name == name)
+                        .ok_or_else(|| format!("unknown fragment {}", name))?;
+                    if self.applies(Some(fragment.type_condition.as_str()), type_name) {
+                        visited.push(name.clone());
+                        self.collect_fields(type_name, &fragment.selections, fields, visited)?;
+                        visited.pop();
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn fields_of(&self, type_name: &str, selections: &[Selection]) -> Result<Vec<Field>, String> {
+        let mut fields = Vec::new();
+        self.collect_fields(type_name, selections, &mut fields, &mut Vec::new())?;
+        Ok(fields)
+    }
+
+    fn page(&self, field: &Field) -> Result<(usize, usize), String> {
+        let count = |name: &str, default: usize| match self.argument(field, name) {
+            Json::Null => Ok(default),
+            json => json.as_u64().map(|n| n as usize).ok_or_else(|| format!("{} of {} must be a non-negative Int", name, field.name))
+        };
+        let first = ::std::cmp::min(count("first", self.limits.max_page_size)?, self.limits.max_page_size);
+        Ok((first, count("offset", 0)?))
+    }
+
+    fn resolve_vertex(&self, txn: &ReadTransaction, vertex: &Vertex, selections: &[Selection], depth: usize)
+        -> Result<Json, String>
+    {
+        let schema_id = vertex.cell.header.schema;
+        let object_type = self.schema.type_by_schema(schema_id);
+        let type_name = object_type.map(|t| t.name.as_str()).unwrap_or(VERTEX_INTERFACE);
+        let mut object = JsonMap::new();
+        for field in self.fields_of(type_name, selections)? {
+            let kind = match field.name.as_str() {
+                "__typename" => { object.insert(field.response_key().to_string(), Json::String(type_name.to_string())); continue; },
+                "id" => FieldKind::Id,
+                "_schema" => FieldKind::SchemaName,
+                name => match object_type.and_then(|t| t.fields.iter().find(|f| f.name == name)) {
+                    Some(def) => def.kind.clone(),
+                    None => return Err(format!("cannot query field {} on type {}", name, type_name))
+                }
+            };
+            let value = match kind {
+                FieldKind::Id => Json::String(format_id(&vertex.cell.id())),
+                FieldKind::SchemaName => self.graph.schema_name(schema_id).map(Json::String).unwrap_or(Json::Null),
+                FieldKind::Data(ref name) => to_json(&vertex[name.as_str()]),
+                FieldKind::Relation { edge_schema, direction } => {
+                    if depth >= self.limits.max_depth {
+                        return Err(format!("relations are nested deeper than {}", self.limits.max_depth));
+                    }
+                    let (first, offset) = self.page(&field)?;
+                    let neighbours = txn.neighbourhoods(vertex.cell.id(), edge_schema, direction, &None)
+                        .map_err(|e| format!("{:?}", e))?
+                        .map_err(|e| format!("{:?}", e))?;
+                    let mut items = Vec::new();
+                    for &(ref neighbour, _) in neighbours.iter().skip(offset).take(first) {
+                        items.push(self.resolve_vertex(txn, neighbour, &field.selections, depth + 1)?);
+                    }
+                    Json::Array(items)
+                }
+            };
+            object.insert(field.response_key().to_string(), value);
+        }
+        Ok(Json::Object(object))
+    }
+
+    // one vertex read in a transaction of its own, null when there is none of the schema
+    fn resolve_one(&self, id: Id, schema_id: Option<u32>, field: &Field) -> Result<Json, String> {
+        self.graph.read_transaction(|txn| {
+            let vertex = match txn.read_vertex(id)? {
+                Some(ref vertex) if schema_id.map(|s| s == vertex.cell.header.schema).unwrap_or(true) => vertex.clone(),
+                _ => return Ok(Ok(Json::Null))
+            };
+            Ok(self.resolve_vertex(txn, &vertex, &field.selections, 0))
+        }).wait().map_err(|e| format!("{:?}", e))?
+    }
+
+    fn resolve_root(&self, field: &Field) -> Result<Json, String> {
+        let name = field.name.as_str();
+        if name == "__typename" { return Ok(Json::String(QUERY_TYPE.to_string())); }
+        if name == "vertex" {
+            return match self.argument(field, "id").as_str().and_then(parse_id) {
+                Some(id) => self.resolve_one(id, None, field),
+                None => Err("vertex needs an id".to_string())
+            };
+        }
+        if let Some(object_type) = self.schema.type_by_name(name) {
+            let id = match (self.argument(field, "id"), self.argument(field, "key")) {
+                (Json::String(ref id), _) => parse_id(id).ok_or_else(|| format!("invalid id {}", id))?,
+                (Json::Null, Json::Null) => return Err(format!("{} needs an id or a key", name)),
+                (Json::Null, ref key) => stream::key_id(self.graph, object_type.schema_id, key)?,
+                _ => return Err("ids are strings".to_string())
+            };
+            return self.resolve_one(id, Some(object_type.schema_id), field);
+        }
+        if name.ends_with("List") {
+            if let Some(object_type) = self.schema.type_by_name(&name[..name.len() - 4]) {
+                let (first, offset) = self.page(field)?;
+                let vertices = self.graph.scan_vertices(object_type.schema_id, &None::<String>, None)
+                    .skip(offset as u64)
+                    .take(first as u64)
+                    .collect()
+                    .wait()
+                    .map_err(|e| format!("{:?}", e))?;
+                return self.graph.read_transaction(|txn| {
+                    Ok(vertices.iter()
+                        .map(|vertex| self.resolve_vertex(txn, vertex, &field.selections, 0))
+                        .collect::<Result<Vec<_>, _>>()
+                        .map(Json::Array))
+                }).wait().map_err(|e| format!("{:?}", e))?;
+            }
+        }
+        Err(format!("cannot query field {} on type {}", name, QUERY_TYPE))
+    }
+}
+
+// Resolves to the data of the operation, or why it cannot be run
+pub fn execute(graph: &Graph, schema: &GraphqlSchema, document: &Document, operation_name: Option<&str>,
+               variables: JsonMap<String, Json>, limits: Limits) -> Result<Json, String>
+{
+    let operation = match operation_name {
+        Some(name) => document.operations.iter().find(|o| o.name.as_ref().map(|n| n == name).unwrap_or(false))
+            .ok_or_else(|| format!("unknown operation {}", name))?,
+        None if document.operations.len() == 1 => &document.operations[0],
+        None => return Err("the document has several operations, name the one to run".to_string())
+    };
+    if operation.kind != OperationKind::Query {
+        return Err("only queries are supported".to_string());
+    }
+    let mut context = Context { graph, schema, fragments: &document.fragments, variables, limits };
+    for &(ref name, ref default) in &operation.variables {
+        if let (false, &Some(ref default)) = (context.variables.contains_key(name), default) {
+            let value = context.input(default);
+            context.variables.insert(name.clone(), value);
+        }
+    }
+    let mut data = JsonMap::new();
+    for field in context.fields_of(QUERY_TYPE, &operation.selections)? {
+        let value = context.resolve_root(&field)?;
+        data.insert(field.response_key().to_string(), value);
+    }
+    Ok(Json::Object(data))
+}
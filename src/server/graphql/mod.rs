@@ -0,0 +1,201 @@
+// GraphQL over HTTP for web frontends. POST /graphql takes {"query", "variables", "operationName"}
+// and answers {"data"} or {"errors"}, GET /graphql/schema answers the generated schema in SDL, see
+// graphql::schema for how schemas map to types. Queries only, there are no mutations and no
+// introspection queries, tools read the SDL instead. With authentication enabled requests need a
+// token with read permission in an Authorization: Bearer header. Every query takes a traversal
+// token of the user, see server::rate_limit, and is answered 429 when there is none left.
+
+use serde_json::{self, Value as Json, Map as JsonMap};
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use graph::Graph;
+use server::auth::{AuthContainer, Permission, Resource};
+use server::rate_limit::{RateLimiter, RateClass};
+
+pub mod parser;
+pub mod schema;
+pub mod exec;
+
+pub use self::exec::Limits;
+pub use self::schema::GraphqlSchema;
+
+// request bodies larger than this are refused
+pub static MAX_BODY_BYTES: usize = 1024 * 1024;
+
+fn default_max_page_size() -> usize { 100 }
+fn default_max_depth() -> usize { 6 }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GraphqlOptions {
+    pub port: u16,
+    // items returned by a list or relation field at most
+    #[serde(default = "default_max_page_size")]
+    pub max_page_size: usize,
+    // relations nested in one query at most
+    #[serde(default = "default_max_depth")]
+    pub max_depth: usize
+}
+
+impl GraphqlOptions {
+    pub fn limits(&self) -> Limits {
+        Limits { max_page_size: self.max_page_size, max_depth: self.max_depth }
+    }
+}
+
+fn errors(message: String) -> Json {
+    let mut error = JsonMap::new();
+    error.insert("message".to_string(), Json::String(message));
+    let mut response = JsonMap::new();
+    response.insert("errors".to_string(), Json::Array(vec![Json::Object(error)]));
+    Json::Object(response)
+}
+
+// Parses and runs the query against the current schemas of the graph, answers the response object
+pub fn run_query(graph: &Graph, query: &str, variables: JsonMap<String, Json>, operation_name: Option<&str>,
+                 limits: Limits) -> Json
+{
+    let document = match parser::parse(query) {
+        Ok(document) => document, Err(e) => return errors(format!("syntax error: {}", e))
+    };
+    let schema = GraphqlSchema::from_graph(graph);
+    match exec::execute(graph, &schema, &document, operation_name, variables, limits) {
+        Ok(data) => {
+            let mut response = JsonMap::new();
+            response.insert("data".to_string(), data);
+            Json::Object(response)
+        },
+        Err(e) => errors(e)
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    authorization: Option<String>,
+    body: Vec<u8>
+}
+
+fn read_request(stream: &TcpStream) -> io::Result<Request> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let (method, path) = {
+        let mut parts = line.split_whitespace();
+        match (parts.next(), parts.next()) {
+            (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+            _ => return Err(invalid("malformed request line"))
+        }
+    };
+    let mut content_length = 0;
+    let mut authorization = None;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() { break; }
+        let (name, value) = match line.find(':') {
+            Some(colon) => (line[..colon].trim().to_lowercase(), line[colon + 1..].trim().to_string()),
+            None => continue
+        };
+        match name.as_str() {
+            "content-length" => content_length = value.parse().map_err(|_| invalid("malformed content length"))?,
+            "authorization" => authorization = Some(value),
+            _ => {}
+        }
+    }
+    if content_length > MAX_BODY_BYTES { return Err(invalid("request body too large")); }
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body)?;
+    let path = path.split('?').next().unwrap_or("").to_string();
+    Ok(Request { method, path, authorization, body })
+}
+
+fn respond(mut stream: &TcpStream, status: &str, content_type: &str, body: &[u8]) {
+    let head = format!("HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                       status, content_type, body.len());
+    if let Err(e) = stream.write_all(head.as_bytes()).and_then(|_| stream.write_all(body)) {
+        debug!("Cannot write GraphQL response: {:?}", e);
+    }
+}
+
+fn respond_json(stream: &TcpStream, status: &str, json: &Json) {
+    respond(stream, status, "application/json", &serde_json::to_vec(json).unwrap_or_default());
+}
+
+fn handle(stream: TcpStream, graph: &Graph, auth: &AuthContainer, rate_limiter: &RateLimiter, limits: Limits) {
+    let request = match read_request(&stream) {
+        Ok(request) => request,
+        Err(e) => return respond_json(&stream, "400 Bad Request", &errors(format!("{}", e)))
+    };
+    let token = request.authorization.as_ref()
+        .and_then(|value| if value.starts_with("Bearer ") { Some(value[7..].trim()) } else { None })
+        .unwrap_or("");
+    if let Err(e) = auth.check(token, Permission::Read, &Resource::default()) {
+        return respond_json(&stream, "401 Unauthorized", &errors(format!("{:?}", e)));
+    }
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/graphql/schema") =>
+            respond(&stream, "200 OK", "text/plain; charset=utf-8", GraphqlSchema::from_graph(graph).sdl().as_bytes()),
+        ("POST", "/graphql") => {
+            if let Err(limited) = rate_limiter.acquire(RateClass::Traversal, &auth.client_of(token), 1) {
+                return respond_json(&stream, "429 Too Many Requests", &errors(format!(
+                    "rate limited, retry after {}ms", limited.retry_after_ms)));
+            }
+            let body: Json = match serde_json::from_slice(&request.body) {
+                Ok(body) => body,
+                Err(e) => return respond_json(&stream, "400 Bad Request", &errors(format!("body is not JSON: {}", e)))
+            };
+            let query = match body.get("query").and_then(|q| q.as_str()) {
+                Some(query) => query,
+                None => return respond_json(&stream, "400 Bad Request", &errors("no query".to_string()))
+            };
+            let variables = match body.get("variables") {
+                Some(&Json::Object(ref variables)) => variables.clone(),
+                _ => JsonMap::new()
+            };
+            let operation_name = body.get("operationName").and_then(|o| o.as_str());
+            respond_json(&stream, "200 OK", &run_query(graph, query, variables, operation_name, limits));
+        },
+        _ => respond(&stream, "404 Not Found", "text/plain", b"")
+    }
+}
+
+// Serves GraphQL on the port until running is cleared, a thread per connection
+pub fn serve(graph: Arc<Graph>, auth: Arc<AuthContainer>, rate_limiter: Arc<RateLimiter>, options: GraphqlOptions,
+             running: Arc<AtomicBool>)
+    -> io::Result<thread::JoinHandle<()>>
+{
+    let listener = TcpListener::bind(("0.0.0.0", options.port))?;
+    // polled, so the thread notices when it should stop
+    listener.set_nonblocking(true)?;
+    info!("Serving GraphQL at 0.0.0.0:{}/graphql", options.port);
+    let limits = options.limits();
+    Ok(thread::Builder::new()
+        .name("morpheus-graphql".to_string())
+        .spawn(move || {
+            while running.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let (graph, auth, rate_limiter) = (graph.clone(), auth.clone(), rate_limiter.clone());
+                        let spawned = thread::Builder::new()
+                            .name("morpheus-graphql-request".to_string())
+                            .spawn(move || {
+                                let _ = stream.set_nonblocking(false);
+                                let _ = stream.set_read_timeout(Some(Duration::from_secs(30)));
+                                handle(stream, &graph, &auth, &rate_limiter, limits);
+                            });
+                        if let Err(e) = spawned { warn!("Cannot handle GraphQL request: {:?}", e); }
+                    },
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(50)),
+                    Err(e) => warn!("GraphQL connection failed: {:?}", e)
+                }
+            }
+            debug!("GraphQL server stopped");
+        })
+        .unwrap())
+}
@@ -0,0 +1,346 @@
+// Parser for the executable part of GraphQL documents: operations, fragments, selections with
+// aliases, arguments and directives. Type system definitions are not accepted.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Input {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Enum(String),
+    List(Vec<Input>),
+    Object(Vec<(String, Input)>),
+    Variable(String)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Directive {
+    pub name: String,
+    pub arguments: Vec<(String, Input)>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub alias: Option<String>,
+    pub name: String,
+    pub arguments: Vec<(String, Input)>,
+    pub directives: Vec<Directive>,
+    pub selections: Vec<Selection>
+}
+
+impl Field {
+    // the key of the field in the response
+    pub fn response_key(&self) -> &str {
+        self.alias.as_ref().unwrap_or(&self.name)
+    }
+
+    pub fn argument(&self, name: &str) -> Option<&Input> {
+        self.arguments.iter().find(|&&(ref n, _)| n == name).map(|&(_, ref value)| value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Selection {
+    Field(Field),
+    InlineFragment { type_condition: Option<String>, directives: Vec<Directive>, selections: Vec<Selection> },
+    FragmentSpread { name: String, directives: Vec<Directive> }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperationKind {
+    Query,
+    Mutation,
+    Subscription
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operation {
+    pub kind: OperationKind,
+    pub name: Option<String>,
+    // name and default value of the declared variables
+    pub variables: Vec<(String, Option<Input>)>,
+    pub selections: Vec<Selection>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fragment {
+    pub name: String,
+    pub type_condition: String,
+    pub selections: Vec<Selection>
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Document {
+    pub operations: Vec<Operation>,
+    pub fragments: Vec<Fragment>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Punct(char),
+    Spread,
+    Name(String),
+    Int(i64),
+    Float(f64),
+    String(String)
+}
+
+fn lex(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < chars.len() {
+        let c = chars[pos];
+        if c.is_whitespace() || c == ',' || c == '\u{feff}' { pos += 1; continue; }
+        if c == '#' {
+            while pos < chars.len() && chars[pos] != '\n' { pos += 1; }
+            continue;
+        }
+        if "!$():=@[]{}|&".contains(c) {
+            tokens.push(Token::Punct(c));
+            pos += 1;
+        } else if c == '.' {
+            if chars[pos..].starts_with(&['.', '.', '.']) {
+                tokens.push(Token::Spread);
+                pos += 3;
+            } else {
+                return Err(format!("unexpected . at {}", pos));
+            }
+        } else if c == '_' || c.is_ascii_alphabetic() {
+            let start = pos;
+            while pos < chars.len() && (chars[pos] == '_' || chars[pos].is_ascii_alphanumeric()) { pos += 1; }
+            tokens.push(Token::Name(chars[start..pos].iter().collect()));
+        } else if c == '-' || c.is_ascii_digit() {
+            let start = pos;
+            pos += 1;
+            let mut is_float = false;
+            while pos < chars.len() && (chars[pos].is_ascii_digit() || ".eE+-".contains(chars[pos])) {
+                if ".eE".contains(chars[pos]) { is_float = true; }
+                pos += 1;
+            }
+            let number: String = chars[start..pos].iter().collect();
+            tokens.push(if is_float {
+                Token::Float(number.parse().map_err(|_| format!("invalid number {}", number))?)
+            } else {
+                Token::Int(number.parse().map_err(|_| format!("invalid number {}", number))?)
+            });
+        } else if c == '"' {
+            if chars[pos..].starts_with(&['"', '"', '"']) {
+                let start = pos + 3;
+                let mut end = start;
+                while end < chars.len() && !chars[end..].starts_with(&['"', '"', '"']) { end += 1; }
+                if end >= chars.len() { return Err("unterminated block string".to_string()); }
+                tokens.push(Token::String(chars[start..end].iter().collect::<String>().trim().to_string()));
+                pos = end + 3;
+                continue;
+            }
+            pos += 1;
+            let mut value = String::new();
+            loop {
+                match chars.get(pos) {
+                    None | Some(&'\n') => return Err("unterminated string".to_string()),
+                    Some(&'"') => { pos += 1; break; },
+                    Some(&'\\') => {
+                        let escaped = match chars.get(pos + 1) {
+                            Some(&'n') => '\n', Some(&'t') => '\t', Some(&'r') => '\r',
+                            Some(&'b') => '\u{8}', Some(&'f') => '\u{c}',
+                            Some(&'u') => {
+                                let hex: String = chars.iter().skip(pos + 2).take(4).collect();
+                                let code = u32::from_str_radix(&hex, 16).map_err(|_| format!("invalid escape \\u{}", hex))?;
+                                pos += 4;
+                                ::std::char::from_u32(code).unwrap_or('\u{fffd}')
+                            },
+                            Some(&other) => other,
+                            None => return Err("unterminated string".to_string())
+                        };
+                        value.push(escaped);
+                        pos += 2;
+                    },
+                    Some(&other) => { value.push(other); pos += 1; }
+                }
+            }
+            tokens.push(Token::String(value));
+        } else {
+            return Err(format!("unexpected {} at {}", c, pos));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self.tokens.get(self.pos).cloned().ok_or_else(|| "unexpected end of document".to_string())?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn is_punct(&self, c: char) -> bool {
+        self.peek() == Some(&Token::Punct(c))
+    }
+
+    fn skip_punct(&mut self, c: char) -> bool {
+        if self.is_punct(c) { self.pos += 1; true } else { false }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        match self.next()? {
+            Token::Punct(p) if p == c => Ok(()),
+            other => Err(format!("expected {} but found {:?}", c, other))
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        match self.next()? {
+            Token::Name(name) => Ok(name),
+            other => Err(format!("expected a name but found {:?}", other))
+        }
+    }
+
+    fn value(&mut self, constant: bool) -> Result<Input, String> {
+        Ok(match self.next()? {
+            Token::Punct('$') if !constant => Input::Variable(self.name()?),
+            Token::Int(i) => Input::Int(i),
+            Token::Float(f) => Input::Float(f),
+            Token::String(s) => Input::String(s),
+            Token::Name(ref n) if n == "true" => Input::Bool(true),
+            Token::Name(ref n) if n == "false" => Input::Bool(false),
+            Token::Name(ref n) if n == "null" => Input::Null,
+            Token::Name(n) => Input::Enum(n),
+            Token::Punct('[') => {
+                let mut items = Vec::new();
+                while !self.skip_punct(']') { items.push(self.value(constant)?); }
+                Input::List(items)
+            },
+            Token::Punct('{') => {
+                let mut fields = Vec::new();
+                while !self.skip_punct('}') {
+                    let name = self.name()?;
+                    self.expect(':')?;
+                    fields.push((name, self.value(constant)?));
+                }
+                Input::Object(fields)
+            },
+            other => return Err(format!("expected a value but found {:?}", other))
+        })
+    }
+
+    fn arguments(&mut self) -> Result<Vec<(String, Input)>, String> {
+        let mut arguments = Vec::new();
+        if !self.skip_punct('(') { return Ok(arguments); }
+        while !self.skip_punct(')') {
+            let name = self.name()?;
+            self.expect(':')?;
+            arguments.push((name, self.value(false)?));
+        }
+        Ok(arguments)
+    }
+
+    fn directives(&mut self) -> Result<Vec<Directive>, String> {
+        let mut directives = Vec::new();
+        while self.skip_punct('@') {
+            let name = self.name()?;
+            directives.push(Directive { name, arguments: self.arguments()? });
+        }
+        Ok(directives)
+    }
+
+    fn selection_set(&mut self) -> Result<Vec<Selection>, String> {
+        self.expect('{')?;
+        let mut selections = Vec::new();
+        while !self.skip_punct('}') {
+            if self.peek() == Some(&Token::Spread) {
+                self.pos += 1;
+                let on = match self.peek() { Some(&Token::Name(ref n)) => n == "on", _ => false };
+                if on || self.is_punct('{') || self.is_punct('@') {
+                    let type_condition = if on { self.pos += 1; Some(self.name()?) } else { None };
+                    let directives = self.directives()?;
+                    selections.push(Selection::InlineFragment { type_condition, directives, selections: self.selection_set()? });
+                } else {
+                    let name = self.name()?;
+                    selections.push(Selection::FragmentSpread { name, directives: self.directives()? });
+                }
+                continue;
+            }
+            let first = self.name()?;
+            let (alias, name) = if self.skip_punct(':') { (Some(first), self.name()?) } else { (None, first) };
+            let arguments = self.arguments()?;
+            let directives = self.directives()?;
+            let selections_of_field = if self.is_punct('{') { self.selection_set()? } else { Vec::new() };
+            selections.push(Selection::Field(Field { alias, name, arguments, directives, selections: selections_of_field }));
+        }
+        Ok(selections)
+    }
+
+    // the type of a variable is not checked, only skipped
+    fn skip_type(&mut self) -> Result<(), String> {
+        if self.skip_punct('[') {
+            self.skip_type()?;
+            self.expect(']')?;
+        } else {
+            self.name()?;
+        }
+        self.skip_punct('!');
+        Ok(())
+    }
+
+    fn operation(&mut self, kind: OperationKind) -> Result<Operation, String> {
+        let name = match self.peek() { Some(&Token::Name(_)) => Some(self.name()?), _ => None };
+        let mut variables = Vec::new();
+        if self.skip_punct('(') {
+            while !self.skip_punct(')') {
+                self.expect('$')?;
+                let variable = self.name()?;
+                self.expect(':')?;
+                self.skip_type()?;
+                let default = if self.skip_punct('=') { Some(self.value(true)?) } else { None };
+                self.directives()?;
+                variables.push((variable, default));
+            }
+        }
+        self.directives()?;
+        Ok(Operation { kind, name, variables, selections: self.selection_set()? })
+    }
+
+    fn document(&mut self) -> Result<Document, String> {
+        let mut document = Document::default();
+        while self.peek().is_some() {
+            if self.is_punct('{') {
+                let selections = self.selection_set()?;
+                document.operations.push(Operation {
+                    kind: OperationKind::Query, name: None, variables: Vec::new(), selections
+                });
+                continue;
+            }
+            match self.name()?.as_str() {
+                "query" => { let operation = self.operation(OperationKind::Query)?; document.operations.push(operation); },
+                "mutation" => { let operation = self.operation(OperationKind::Mutation)?; document.operations.push(operation); },
+                "subscription" => { let operation = self.operation(OperationKind::Subscription)?; document.operations.push(operation); },
+                "fragment" => {
+                    let name = self.name()?;
+                    if self.name()? != "on" { return Err(format!("fragment {} needs a type condition", name)); }
+                    let type_condition = self.name()?;
+                    self.directives()?;
+                    let selections = self.selection_set()?;
+                    document.fragments.push(Fragment { name, type_condition, selections });
+                },
+                other => return Err(format!("unexpected {}, only operations and fragments are accepted", other))
+            }
+        }
+        if document.operations.is_empty() { return Err("the document has no operation".to_string()); }
+        Ok(document)
+    }
+}
+
+pub fn parse(text: &str) -> Result<Document, String> {
+    Parser { tokens: lex(text)?, pos: 0 }.document()
+}
@@ -0,0 +1,160 @@
+// The GraphQL schema of a graph, generated from its schemas on every request so schema changes show
+// at once. Vertex schemas become object types implementing the Vertex interface, with their declared
+// fields and one relation field per edge schema and direction: <edge>Out and <edge>In for directed
+// edges, <edge> for undirected ones. Relations lead to vertices of any schema, select their fields
+// with inline fragments. Schema names are changed to valid GraphQL names, declared fields win over
+// relations of the same name.
+// The query type has <type>(id, key) for one vertex, <type>List(first, offset) for a page of a scan
+// of the schema and vertex(id) for a vertex of any schema. Ids are written as higher:lower.
+
+use neb::ram::schema::Field;
+use neb::ram::types::TypeId;
+
+use std::fmt::Write;
+
+use graph::{Graph, EdgeDirection};
+use server::schema::SchemaType;
+
+pub static VERTEX_INTERFACE: &'static str = "Vertex";
+pub static QUERY_TYPE: &'static str = "Query";
+static RESERVED_NAMES: &'static [&'static str] = &[
+    "Query", "Mutation", "Subscription", "Vertex", "Int", "Float", "String", "Boolean", "ID", "Long", "JSON"
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldKind {
+    Id,
+    SchemaName,
+    // a declared field of the vertex, by its name in the schema
+    Data(String),
+    Relation { edge_schema: u32, direction: EdgeDirection }
+}
+
+#[derive(Debug, Clone)]
+pub struct FieldDef {
+    pub name: String,
+    pub kind: FieldKind,
+    pub type_ref: String
+}
+
+#[derive(Debug, Clone)]
+pub struct ObjectType {
+    pub name: String,
+    pub schema_id: u32,
+    pub fields: Vec<FieldDef>
+}
+
+#[derive(Debug, Clone)]
+pub struct GraphqlSchema {
+    pub types: Vec<ObjectType>
+}
+
+// letters, digits and underscores, not starting with a digit
+pub fn graphql_name(name: &str) -> String {
+    let mut sanitized: String = name.chars()
+        .map(|c| if c == '_' || c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if sanitized.is_empty() || sanitized.starts_with(|c: char| c.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+    if sanitized.starts_with("__") { sanitized.insert(0, 'x'); }
+    sanitized
+}
+
+fn scalar_type(type_id: u32) -> &'static str {
+    let of = |types: &[u32]| types.contains(&type_id);
+    if of(&[TypeId::Bool as u32]) { "Boolean" }
+    else if of(&[TypeId::I8 as u32, TypeId::I16 as u32, TypeId::I32 as u32, TypeId::U8 as u32, TypeId::U16 as u32]) { "Int" }
+    else if of(&[TypeId::I64 as u32, TypeId::U32 as u32, TypeId::U64 as u32]) { "Long" }
+    else if of(&[TypeId::F32 as u32, TypeId::F64 as u32]) { "Float" }
+    else if of(&[TypeId::String as u32]) { "String" }
+    else if of(&[TypeId::Id as u32]) { "ID" }
+    else { "JSON" }
+}
+
+fn field_type(field: &Field) -> String {
+    let scalar = if field.sub_fields.is_some() { "JSON" } else { scalar_type(field.type_id) };
+    let item = if field.nullable || field.is_array { scalar.to_string() } else { format!("{}!", scalar) };
+    if field.is_array { format!("[{}]", item) } else { item }
+}
+
+impl GraphqlSchema {
+    pub fn from_graph(graph: &Graph) -> GraphqlSchema {
+        let mut schema_types = graph.schema_types();
+        schema_types.sort_by_key(|&(id, _)| id);
+        let mut relations = Vec::new();
+        for &(id, ref schema_type) in &schema_types {
+            if let &SchemaType::Edge(ref attrs) = schema_type {
+                let name = graphql_name(&graph.schema_name(id).unwrap_or_default());
+                if attrs.edge_type == ::graph::edge::EdgeType::Directed {
+                    relations.push((format!("{}Out", name), id, EdgeDirection::Outbound));
+                    relations.push((format!("{}In", name), id, EdgeDirection::Inbound));
+                } else {
+                    relations.push((name, id, EdgeDirection::Undirected));
+                }
+            }
+        }
+        let mut types: Vec<ObjectType> = Vec::new();
+        for &(id, ref schema_type) in &schema_types {
+            if schema_type != &SchemaType::Vertex { continue; }
+            let mut name = graphql_name(&graph.schema_name(id).unwrap_or_default());
+            while RESERVED_NAMES.contains(&name.as_str()) || types.iter().any(|t| t.name == name) {
+                name.push('_');
+            }
+            let mut fields = vec![
+                FieldDef { name: "id".to_string(), kind: FieldKind::Id, type_ref: "ID!".to_string() },
+                FieldDef { name: "_schema".to_string(), kind: FieldKind::SchemaName, type_ref: "String!".to_string() }
+            ];
+            let declared = graph.neb_schema(id).and_then(|s| s.fields.sub_fields.clone()).unwrap_or_default();
+            for field in declared {
+                let field_name = graphql_name(&field.name);
+                // fields kept by the graph itself, like edge lists, start with an underscore
+                if field.name.starts_with('_') || fields.iter().any(|f| f.name == field_name) { continue; }
+                fields.push(FieldDef { name: field_name, type_ref: field_type(&field), kind: FieldKind::Data(field.name) });
+            }
+            for &(ref relation, edge_schema, direction) in &relations {
+                if fields.iter().any(|f| &f.name == relation) { continue; }
+                fields.push(FieldDef {
+                    name: relation.clone(),
+                    kind: FieldKind::Relation { edge_schema, direction },
+                    type_ref: format!("[{}!]!", VERTEX_INTERFACE)
+                });
+            }
+            types.push(ObjectType { name, schema_id: id, fields });
+        }
+        GraphqlSchema { types }
+    }
+
+    pub fn type_by_name(&self, name: &str) -> Option<&ObjectType> {
+        self.types.iter().find(|t| t.name == name)
+    }
+
+    pub fn type_by_schema(&self, schema_id: u32) -> Option<&ObjectType> {
+        self.types.iter().find(|t| t.schema_id == schema_id)
+    }
+
+    // the schema in the GraphQL schema definition language
+    pub fn sdl(&self) -> String {
+        let mut sdl = String::new();
+        let _ = writeln!(sdl, "scalar Long\nscalar JSON\n");
+        let _ = writeln!(sdl, "interface {} {{\n  id: ID!\n  _schema: String!\n}}\n", VERTEX_INTERFACE);
+        for object in &self.types {
+            let _ = writeln!(sdl, "type {} implements {} {{", object.name, VERTEX_INTERFACE);
+            for field in &object.fields {
+                let arguments = match field.kind {
+                    FieldKind::Relation { .. } => "(first: Int, offset: Int)", _ => ""
+                };
+                let _ = writeln!(sdl, "  {}{}: {}", field.name, arguments, field.type_ref);
+            }
+            let _ = writeln!(sdl, "}}\n");
+        }
+        let _ = writeln!(sdl, "type {} {{", QUERY_TYPE);
+        let _ = writeln!(sdl, "  vertex(id: ID!): {}", VERTEX_INTERFACE);
+        for object in &self.types {
+            let _ = writeln!(sdl, "  {}(id: ID, key: JSON): {}", object.name, object.name);
+            let _ = writeln!(sdl, "  {}List(first: Int, offset: Int): [{}!]!", object.name, object.name);
+        }
+        let _ = writeln!(sdl, "}}");
+        sdl
+    }
+}
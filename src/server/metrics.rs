@@ -0,0 +1,106 @@
+use prometheus::{self, Counter, Histogram, Encoder, TextEncoder};
+use std::io::{self, prelude::*};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+lazy_static! {
+    pub static ref VERTEX_WRITES: Counter = register_counter!(
+        "morpheus_vertex_writes_total", "Vertices written through the graph API").unwrap();
+    pub static ref VERTEX_WRITE_SECONDS: Histogram = register_histogram!(
+        "morpheus_vertex_write_seconds", "Latency of vertex writes").unwrap();
+    pub static ref LINK_OPS: Counter = register_counter!(
+        "morpheus_link_ops_total", "Link operations issued").unwrap();
+    pub static ref LINK_SECONDS: Histogram = register_histogram!(
+        "morpheus_link_seconds", "Latency of link operations").unwrap();
+    pub static ref TRAVERSAL_SIZE: Histogram = register_histogram!(
+        "morpheus_traversal_size", "Number of results returned by neighbourhood traversals",
+        vec![1.0, 10.0, 100.0, 1000.0, 10000.0, 100000.0]).unwrap();
+    pub static ref TRANSACTION_SECONDS: Histogram = register_histogram!(
+        "morpheus_transaction_seconds", "Latency of graph transactions").unwrap();
+    pub static ref TRANSACTION_FAILURES: Counter = register_counter!(
+        "morpheus_transaction_failures_total", "Graph transactions that aborted or failed").unwrap();
+    pub static ref TRANSACTION_RETRIES: Counter = register_counter!(
+        "morpheus_transaction_retries_total", "Graph transactions started again after a failed attempt").unwrap();
+    pub static ref WATCHDOG_ABORTS: Counter = register_counter!(
+        "morpheus_watchdog_aborts_total", "Graph transactions aborted by the watchdog").unwrap();
+    pub static ref SCHEMA_CHANGES: Counter = register_counter!(
+        "morpheus_schema_changes_total", "Schemas created").unwrap();
+    pub static ref ORPHAN_GC_REPAIRS: Counter = register_counter!(
+        "morpheus_orphan_gc_repairs_total", "Edge list entries and edge bodies repaired by the orphan GC").unwrap();
+    pub static ref CDC_EVENTS_PUBLISHED: Counter = register_counter!(
+        "morpheus_cdc_events_published_total", "Change log events acknowledged by Kafka").unwrap();
+    pub static ref REPLICATED_EVENTS: Counter = register_counter!(
+        "morpheus_replicated_events_total", "Change log events applied by the target cluster of a replication").unwrap();
+    pub static ref INGESTED_COMMANDS: Counter = register_counter!(
+        "morpheus_ingested_commands_total", "Mutation commands from streams applied to the graph").unwrap();
+    pub static ref INGEST_DEAD_LETTERS: Counter = register_counter!(
+        "morpheus_ingest_dead_letters_total", "Stream messages dead lettered as malformed or rejected").unwrap();
+    pub static ref QUERY_CACHE_HITS: Counter = register_counter!(
+        "morpheus_query_cache_hits_total", "Neighbourhood queries answered from the query cache").unwrap();
+    pub static ref QUERY_CACHE_MISSES: Counter = register_counter!(
+        "morpheus_query_cache_misses_total", "Neighbourhood queries not found or expired in the query cache").unwrap();
+    pub static ref VERTEX_CACHE_HITS: Counter = register_counter!(
+        "morpheus_vertex_cache_hits_total", "Vertex reads answered from the vertex cache").unwrap();
+    pub static ref VERTEX_CACHE_MISSES: Counter = register_counter!(
+        "morpheus_vertex_cache_misses_total", "Vertex reads not found or expired in the vertex cache").unwrap();
+    pub static ref VERTEX_CACHE_MISSING_HITS: Counter = register_counter!(
+        "morpheus_vertex_cache_missing_hits_total", "Reads of vertices remembered as missing by the vertex cache").unwrap();
+    pub static ref VERTEX_CACHE_NOTIFICATIONS: Counter = register_counter!(
+        "morpheus_vertex_cache_notifications_total", "Written vertex ids sent to peers for cache invalidation").unwrap();
+    pub static ref TRAVERSALS_QUEUED: Counter = register_counter!(
+        "morpheus_traversals_queued_total", "Traversals that waited for room in the memory budget").unwrap();
+    pub static ref TRAVERSALS_SHED: Counter = register_counter!(
+        "morpheus_traversals_shed_total", "Traversals refused for want of room in the memory budget").unwrap();
+}
+
+fn handle(mut stream: TcpStream) {
+    let mut request = [0u8; 512];
+    let read = stream.read(&mut request).unwrap_or(0);
+    let is_metrics = String::from_utf8_lossy(&request[..read]).starts_with("GET /metrics");
+    let response = if is_metrics {
+        let encoder = TextEncoder::new();
+        let mut body = Vec::new();
+        if let Err(e) = encoder.encode(&prometheus::gather(), &mut body) {
+            warn!("Cannot encode metrics: {:?}", e);
+        }
+        let mut response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+            encoder.format_type(), body.len()
+        ).into_bytes();
+        response.append(&mut body);
+        response
+    } else {
+        b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_vec()
+    };
+    if let Err(e) = stream.write_all(&response) {
+        debug!("Cannot write metrics response: {:?}", e);
+    }
+}
+
+// Serves the metrics on the port until running is cleared, the port is free again once the thread returns
+pub fn serve(port: u16, running: Arc<AtomicBool>) -> io::Result<thread::JoinHandle<()>> {
+    let listener = TcpListener::bind(("0.0.0.0", port))?;
+    // polled, so the thread notices when it should stop
+    listener.set_nonblocking(true)?;
+    info!("Serving metrics at 0.0.0.0:{}/metrics", port);
+    Ok(thread::Builder::new()
+        .name("morpheus-metrics".to_string())
+        .spawn(move || {
+            while running.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let _ = stream.set_nonblocking(false);
+                        let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+                        handle(stream);
+                    },
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(50)),
+                    Err(e) => warn!("Metrics connection failed: {:?}", e)
+                }
+            }
+            debug!("Metrics server stopped");
+        })
+        .unwrap())
+}
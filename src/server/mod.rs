@@ -1,5 +1,10 @@
 use bifrost::rpc;
+use std::io;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use parking_lot::Mutex;
+use chashmap::CHashMap;
 use bifrost::raft::state_machine::master::ExecError;
 use neb::client::{AsyncClient as NebClient, NebClientError};
 use neb::server::{ServerOptions as NebServerOptions, NebServer, ServerError};
@@ -9,34 +14,190 @@ use futures::{Future, future};
 use futures::prelude::*;
 
 use graph::Graph;
+use graph::startup::StartupError;
+use graph::migrate::{SchemaMigrationOptions, MigrationError};
+use graph::batch::LinkBatchOptions;
+use graph::gc::OrphanGcOptions;
+use graph::window_counter::CounterDecayOptions;
+use graph::retry::RetryPolicy;
+use graph::cache::QueryCacheOptions;
+use graph::vertex_cache::VertexCacheOptions;
+use graph::quota::QuotaOptions;
+use graph::result_limit::ResultLimits;
+use graph::memory_budget::{MemoryBudget, MemoryBudgetOptions};
+use graph::encryption::{FieldCipher, KeyProviderOptions};
+use import::stream::{self, StreamIngestOptions};
+use import::checkpoint::CheckpointStore;
 
 pub mod general;
 pub mod schema;
 pub mod traversal;
+pub mod pregel;
+pub mod path_pattern;
+pub mod stats;
+pub mod metrics;
+pub mod slow_log;
+pub mod watchdog;
+pub mod admin;
+pub mod namespace;
+pub mod auth;
+pub mod cdc;
+pub mod replication;
+pub mod cache_sync;
+pub mod graphql;
+pub mod rate_limit;
+pub mod audit;
 
 #[derive(Debug)]
 pub enum MorpheusServerError {
     ServerError(ServerError),
     ClientError(NebClientError),
-    InitSchemaError(ExecError)
+    InitSchemaError(ExecError),
+    // the schemas stored in neb do not fit this binary, see graph::startup
+    IncompatibleSchemas(Vec<String>),
+    SchemaMigrationError(MigrationError),
+    InitStatisticsError(ExecError),
+    InitAuthError(ExecError),
+    GraphqlError(io::Error),
+    MetricsError(io::Error)
+}
+
+// everything on top of the neb options, usually read from morpheus.yaml
+#[derive(Debug, Clone, Default)]
+pub struct MorpheusServerOptions {
+    pub graphs: Vec<namespace::GraphOptions>,
+    pub auth: auth::AuthOptions,
+    // joins as a replica that serves reads and traversals only
+    pub read_only: bool,
+    // coalesce concurrent link calls into group commits, off when None
+    pub link_batch: Option<LinkBatchOptions>,
+    // background repair of broken adjacency, run by meta servers, off when None
+    pub orphan_gc: Option<OrphanGcOptions>,
+    // ages out the window counters of edge schemas, run by meta servers, off when None
+    pub counter_decay: Option<CounterDecayOptions>,
+    // publishes the writes made through this server to Kafka, off when None
+    pub cdc_kafka: Option<cdc::KafkaSinkOptions>,
+    // replicates the writes made through this server to other clusters
+    pub replication: Vec<replication::ReplicationOptions>,
+    // takes writes replicated from other clusters, off when None
+    pub replica: Option<replication::ReplicaOptions>,
+    // applies mutation commands consumed from Kafka, off when None
+    pub stream_ingest: Option<StreamIngestOptions>,
+    // GraphQL over HTTP generated from the schemas, off when None
+    pub graphql: Option<graphql::GraphqlOptions>,
+    // Prometheus metrics over HTTP, off when None
+    pub metrics_port: Option<u16>,
+    // per-server cache of neighbourhood results, for every graph, off when None
+    pub query_cache: Option<QueryCacheOptions>,
+    // cache of vertex cells read through the default graph, off when None
+    pub vertex_cache: Option<VertexCacheOptions>,
+    // storage limits of the default graph, named graphs take theirs from GraphOptions
+    pub quotas: Option<QuotaOptions>,
+    // caps on what one neighbourhood or edges call returns, for every graph
+    pub result_limits: ResultLimits,
+    // bytes neighbourhood traversals in flight on this server may hold together, unlimited when None
+    pub memory_budget: Option<MemoryBudgetOptions>,
+    // where the keys of encrypted schema fields come from, for every graph
+    pub encryption: Option<KeyProviderOptions>,
+    // migrates stored schemas that lack fields of this binary instead of refusing to start, see graph::migrate
+    pub schema_migration: Option<SchemaMigrationOptions>,
+    // token buckets for writes and traversals taken through RPC and GraphQL, off when None
+    pub rate_limit: Option<rate_limit::RateLimitOptions>,
+    // applied to the default graph and every named graph opened later
+    pub retry: RetryPolicy,
+    // the background statistics collector of meta servers
+    pub statistics: stats::StatisticsOptions
+}
+
+// Runs neb, raft and the graph in the calling process with no peers.
+// storage is a local directory for the neb backup and raft logs, None keeps everything in memory.
+#[derive(Debug, Clone)]
+pub struct EmbeddedOptions {
+    pub group_name: String,
+    pub chunk_count: usize,
+    pub memory_size: u64, // megabytes
+    pub storage: Option<String>
+}
+
+impl Default for EmbeddedOptions {
+    fn default() -> EmbeddedOptions {
+        EmbeddedOptions {
+            group_name: "Morpheus-embedded".to_string(),
+            chunk_count: 1,
+            memory_size: 512,
+            storage: None
+        }
+    }
 }
 
 pub struct MorpheusServer {
     pub neb_server: Arc<NebServer>,
     pub neb_client: Arc<NebClient>,
     pub schema_container: Arc<schema::SchemaContainer>,
-    pub graph: Arc<Graph>
+    pub graph: Arc<Graph>,
+    pub statistics: Arc<stats::StatisticsContainer>,
+    pub auth: Arc<auth::AuthContainer>,
+    pub router: Arc<traversal::TraversalRouter>,
+    // runs Pregel jobs over the servers owning their vertices, see server::pregel
+    pub pregel: Arc<pregel::PregelService>,
+    pub rate_limiter: Arc<rate_limit::RateLimiter>,
+    // progress of named bulk imports, see import::checkpoint
+    pub import_checkpoints: Arc<CheckpointStore>,
+    // administration done through the admin service, see server::audit
+    pub audit: Arc<audit::AuditLog>,
+    group: String,
+    graphs: Vec<namespace::GraphOptions>,
+    opened_graphs: Arc<CHashMap<String, Arc<Graph>>>,
+    read_only: bool,
+    retry: RetryPolicy,
+    result_limits: ResultLimits,
+    memory_budget: Option<Arc<MemoryBudget>>,
+    field_cipher: Option<Arc<FieldCipher>>,
+    query_cache: Option<QueryCacheOptions>,
+    schema_migration: Option<SchemaMigrationOptions>,
+    running: Arc<AtomicBool>,
+    background_jobs: Mutex<Vec<JoinHandle<()>>>,
+    // registered on the RPC server of neb, removed on shutdown
+    services: Vec<u64>
+}
+
+impl MorpheusServerError {
+    fn from_startup(e: StartupError) -> MorpheusServerError {
+        match e {
+            StartupError::ExecError(e) => MorpheusServerError::InitSchemaError(e),
+            StartupError::Incompatible(problems) => {
+                error!("Refusing to start, the stored schemas do not fit this binary: {}", problems.join("; "));
+                MorpheusServerError::IncompatibleSchemas(problems)
+            },
+            StartupError::NeedsMigration(fields) => {
+                error!("Refusing to start, the stored schemas need a schema_migration: {}", fields.join("; "));
+                MorpheusServerError::IncompatibleSchemas(fields)
+            },
+            StartupError::MigrationError(e) => {
+                error!("Schema migration failed: {:?}", e);
+                MorpheusServerError::SchemaMigrationError(e)
+            }
+        }
+    }
 }
 
 impl MorpheusServer {
 
-    #[async]
     pub fn new(
         neb_opts: NebServerOptions
+    ) -> impl Future<Item = Arc<MorpheusServer>, Error = MorpheusServerError> {
+        Self::new_with_options(neb_opts, MorpheusServerOptions::default())
+    }
+
+    #[async]
+    pub fn new_with_options(
+        neb_opts: NebServerOptions,
+        options: MorpheusServerOptions
     ) -> Result<Arc<MorpheusServer>, MorpheusServerError> {
         let server_addr = {
             if neb_opts.standalone {&STANDALONE_ADDRESS_STRING} else {&neb_opts.address}
         }.clone();
+        graph::ids::set_node(&server_addr);
         let rpc_server = rpc::Server::new(&server_addr);
         rpc::Server::listen_and_resume(&rpc_server);
         if !neb_opts.is_meta && neb_opts.standalone {
@@ -52,6 +213,12 @@ impl MorpheusServer {
         if neb_opts.is_meta {
             if let &Some(ref raft_service) = &neb_server.raft_service {
                 schema::SchemaContainer::new_meta_service(&neb_opts.group_name, raft_service);
+                stats::StatisticsContainer::new_meta_service(&neb_opts.group_name, raft_service);
+                namespace::new_meta_services(&neb_opts.group_name, &options.graphs, raft_service);
+                auth::AuthContainer::new_meta_service(&neb_opts.group_name, raft_service);
+                cdc::OffsetStore::new_meta_service(&neb_opts.group_name, raft_service);
+                CheckpointStore::new_meta_service(&neb_opts.group_name, raft_service);
+                audit::AuditLog::new_meta_service(&neb_opts.group_name, raft_service);
             } else {
                 panic!("raft service should be ready for meta server");
             }
@@ -59,13 +226,237 @@ impl MorpheusServer {
         let schema_container = schema::SchemaContainer::new_client(
             &neb_opts.group_name, &neb_client.raft_client(), &neb_client, &neb_server.meta
         ).map_err(MorpheusServerError::InitSchemaError)?;
-        let graph = Arc::new(await!(Graph::new(&schema_container, &neb_client)
-            .map_err(MorpheusServerError::InitSchemaError))?);
+        let graph = Arc::new(await!(Graph::new_with_migration(&schema_container, &neb_client, options.schema_migration.clone())
+            .map_err(MorpheusServerError::from_startup))?);
+        graph.set_read_only(options.read_only);
+        graph.set_retry_policy(options.retry.clone());
+        graph.set_result_limits(options.result_limits);
+        let memory_budget = options.memory_budget.map(MemoryBudget::new);
+        if let Some(ref memory_budget) = memory_budget {
+            graph.set_memory_budget(memory_budget.clone());
+        }
+        let field_cipher = options.encryption.map(FieldCipher::new);
+        if let Some(ref field_cipher) = field_cipher {
+            graph.set_field_cipher(field_cipher.clone());
+        }
+        if let Some(ref query_cache) = options.query_cache {
+            graph.enable_query_cache(query_cache.clone());
+        }
+        let vertex_cache = options.vertex_cache.map(|vertex_cache| graph.enable_vertex_cache(vertex_cache));
+        if let Some(quotas) = options.quotas {
+            graph.set_quotas(quotas);
+        }
+        let statistics = stats::StatisticsContainer::new_client(
+            &neb_opts.group_name, &neb_client.raft_client(), &graph
+        ).map_err(MorpheusServerError::InitStatisticsError)?;
+        let auth = auth::AuthContainer::new_client(
+            &neb_opts.group_name, &neb_client.raft_client(), &options.auth
+        ).map_err(MorpheusServerError::InitAuthError)?;
+        let rate_limiter = Arc::new(match options.rate_limit {
+            Some(rate_limit) => rate_limit::RateLimiter::new(rate_limit),
+            None => rate_limit::RateLimiter::unlimited()
+        });
+        let running = Arc::new(AtomicBool::new(true));
+        let mut background_jobs = Vec::new();
+        if let (Some(raft_service), true) = (neb_server.raft_service.clone(), options.statistics.interval_secs > 0) {
+            background_jobs.push(stats::start_collector(
+                graph.clone(), schema_container.clone(), statistics.clone(),
+                raft_service, options.statistics, running.clone()
+            ));
+        }
+        background_jobs.push(watchdog::start(running.clone()));
+        if let Some(link_batch) = options.link_batch {
+            background_jobs.push(graph.start_link_batching(link_batch, running.clone()));
+        }
+        if let (true, Some(orphan_gc)) = (neb_opts.is_meta, options.orphan_gc) {
+            background_jobs.push(graph.start_orphan_gc(orphan_gc, running.clone()));
+        }
+        if let (true, Some(counter_decay)) = (neb_opts.is_meta, options.counter_decay) {
+            background_jobs.push(graph.start_counter_decay(counter_decay, running.clone()));
+        }
+        if let Some(cdc_kafka) = options.cdc_kafka {
+            let offsets = cdc::OffsetStore::new_client(&neb_opts.group_name, &neb_client.raft_client());
+            background_jobs.push(cdc::start_kafka_sink(
+                graph.clone(), offsets, cdc_kafka, &server_addr, running.clone()
+            ));
+        }
+        if !options.replication.is_empty() {
+            let offsets = cdc::OffsetStore::new_client(&neb_opts.group_name, &neb_client.raft_client());
+            for replication in options.replication {
+                background_jobs.push(replication::start_agent(
+                    graph.clone(), offsets.clone(), replication, &neb_opts.group_name, &server_addr, running.clone()
+                ));
+            }
+        }
+        let mut services = Vec::new();
+        if let Some(ref replica) = options.replica {
+            services.push(replication::REPLICATION_SERVICE_ID);
+            rpc_server.register_service(
+                replication::REPLICATION_SERVICE_ID,
+                &replication::ReplicationService::new(&graph, replica, &auth, &rate_limiter)
+            );
+        }
+        if let Some(stream_ingest) = options.stream_ingest {
+            background_jobs.push(stream::start_ingest(graph.clone(), stream_ingest, running.clone()));
+        }
+        if let Some(vertex_cache) = vertex_cache {
+            if !vertex_cache.options().peers.is_empty() {
+                background_jobs.push(cache_sync::start_notifier(vertex_cache, running.clone()));
+            }
+            services.push(cache_sync::CACHE_SYNC_SERVICE_ID);
+            rpc_server.register_service(
+                cache_sync::CACHE_SYNC_SERVICE_ID,
+                &cache_sync::CacheSyncService::new(&graph)
+            );
+        }
+        if let Some(graphql) = options.graphql {
+            background_jobs.push(graphql::serve(graph.clone(), auth.clone(), rate_limiter.clone(), graphql, running.clone())
+                .map_err(MorpheusServerError::GraphqlError)?);
+        }
+        if let Some(port) = options.metrics_port {
+            background_jobs.push(metrics::serve(port, running.clone()).map_err(MorpheusServerError::MetricsError)?);
+        }
+        let audit = audit::AuditLog::new_client(&neb_opts.group_name, &neb_client.raft_client());
+        services.push(admin::ADMIN_SERVICE_ID);
+        services.push(traversal::TRAVERSAL_SERVICE_ID);
+        services.push(pregel::PREGEL_SERVICE_ID);
+        rpc_server.register_service(
+            admin::ADMIN_SERVICE_ID,
+            &admin::AdminService::new(
+                &neb_opts.group_name, &graph, &schema_container, &statistics, &auth, &rate_limiter,
+                &neb_server.raft_service, &neb_client.raft_client(), &audit
+            )
+        );
+        rpc_server.register_service(
+            traversal::TRAVERSAL_SERVICE_ID,
+            &traversal::TraversalService::new(&graph, &auth, &rate_limiter)
+        );
+        let router = traversal::TraversalRouter::new(&graph, &neb_client, &auth, &server_addr);
+        let pregel = pregel::PregelService::new(&graph, &schema_container, &auth, &router);
+        rpc_server.register_service(pregel::PREGEL_SERVICE_ID, &pregel);
+        let import_checkpoints = CheckpointStore::new_client(&neb_opts.group_name, &neb_client.raft_client());
         Ok(Arc::new(MorpheusServer {
             neb_server,
             neb_client,
             schema_container,
-            graph
+            graph,
+            statistics,
+            auth,
+            router,
+            pregel,
+            rate_limiter,
+            import_checkpoints,
+            audit,
+            group: neb_opts.group_name.clone(),
+            graphs: options.graphs,
+            read_only: options.read_only,
+            retry: options.retry,
+            result_limits: options.result_limits,
+            memory_budget,
+            field_cipher,
+            query_cache: options.query_cache,
+            schema_migration: options.schema_migration,
+            opened_graphs: Arc::new(CHashMap::new()),
+            running,
+            background_jobs: Mutex::new(background_jobs),
+            services
         }))
     }
+
+    pub fn embedded(options: EmbeddedOptions) -> Result<Arc<MorpheusServer>, MorpheusServerError> {
+        let storage_dir = |sub: &str| options.storage.as_ref().map(|dir| format!("{}/{}", dir, sub));
+        let neb_opts = NebServerOptions {
+            chunk_count: options.chunk_count,
+            memory_size: options.memory_size * 1024 * 1024,
+            backup_storage: storage_dir("backup"),
+            meta_storage: storage_dir("meta"),
+            standalone: true,
+            is_meta: true,
+            meta_members: vec![STANDALONE_ADDRESS_STRING.clone()],
+            address: STANDALONE_ADDRESS_STRING.clone(),
+            group_name: options.group_name.clone()
+        };
+        MorpheusServer::new(neb_opts).wait()
+    }
+
+    // Named graphs come from the server configuration, the default graph stays at `graph`
+    pub fn open_graph<'a>(&self, name: &'a str, user: &'a str)
+        -> impl Future<Item = Arc<Graph>, Error = namespace::OpenGraphError>
+    {
+        let (allowed, quotas) = match self.graphs.iter().find(|g| g.name == name) {
+            Some(graph_opts) => (graph_opts.allows(user), graph_opts.quotas.clone()),
+            None => return future::Either::A(future::err(namespace::OpenGraphError::GraphNotFound))
+        };
+        if !allowed {
+            return future::Either::A(future::err(namespace::OpenGraphError::AccessDenied));
+        }
+        if let Some(graph) = self.opened_graphs.get(name) {
+            return future::Either::A(future::ok(graph.clone()));
+        }
+        let name = name.to_string();
+        let opened_graphs = self.opened_graphs.clone();
+        let read_only = self.read_only;
+        let retry = self.retry.clone();
+        let result_limits = self.result_limits;
+        let memory_budget = self.memory_budget.clone();
+        let field_cipher = self.field_cipher.clone();
+        let query_cache = self.query_cache.clone();
+        future::Either::B(Graph::open(&name, &self.group, &self.neb_client, &self.neb_server.meta, self.schema_migration.clone())
+            .map_err(|e| match e {
+                StartupError::ExecError(e) => namespace::OpenGraphError::InitSchemaError(e),
+                StartupError::Incompatible(problems) | StartupError::NeedsMigration(problems) =>
+                    namespace::OpenGraphError::IncompatibleSchemas(problems),
+                StartupError::MigrationError(e) => namespace::OpenGraphError::SchemaMigrationError(e)
+            })
+            .map(move |graph| {
+                graph.set_read_only(read_only);
+                graph.set_retry_policy(retry);
+                graph.set_result_limits(result_limits);
+                if let Some(memory_budget) = memory_budget {
+                    graph.set_memory_budget(memory_budget);
+                }
+                if let Some(field_cipher) = field_cipher {
+                    graph.set_field_cipher(field_cipher);
+                }
+                if let Some(query_cache) = query_cache {
+                    graph.enable_query_cache(query_cache);
+                }
+                if let Some(quotas) = quotas {
+                    graph.set_quotas(quotas);
+                }
+                let graph = Arc::new(graph);
+                opened_graphs.insert(name, graph.clone());
+                graph
+            }))
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    // Stops background jobs, the GraphQL and metrics listeners among them, so their ports are free once this
+    // returns. Queued links are flushed by the link batcher before it stops, so every write accepted before
+    // this call has reached neb by the time it returns. Then the services of Morpheus are taken off the RPC
+    // server, named graphs are closed and the server leaves the raft group. The RPC listener and the clients
+    // are neb's, they stay up for the rest of the process. Calling it more than once is a no-op.
+    pub fn shutdown(&self) {
+        if !self.running.swap(false, Ordering::SeqCst) { return; }
+        info!("Morpheus server is shutting down");
+        let jobs: Vec<_> = self.background_jobs.lock().drain(..).collect();
+        for job in jobs {
+            if job.join().is_err() {
+                warn!("A background job panicked before shutdown");
+            }
+        }
+        for service_id in &self.services {
+            self.neb_server.rpc.remove_service(*service_id);
+        }
+        self.opened_graphs.clear();
+        if let &Some(ref raft_service) = &self.neb_server.raft_service {
+            if !raft_service.leave() {
+                warn!("Cannot leave raft group cleanly");
+            }
+        }
+        info!("Morpheus server stopped");
+    }
 }
\ No newline at end of file
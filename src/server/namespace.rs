@@ -0,0 +1,43 @@
+use bifrost::raft::RaftService;
+use bifrost::raft::state_machine::master::ExecError;
+use std::sync::Arc;
+
+use server::schema::{SchemaContainer, namespaced_group};
+use graph::quota::QuotaOptions;
+use graph::migrate::MigrationError;
+
+// A named graph hosted next to the default one.
+// users lists who may open it, None leaves it open to everyone.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GraphOptions {
+    pub name: String,
+    #[serde(default)]
+    pub users: Option<Vec<String>>,
+    // storage limits of the graph's tenant, see graph::quota
+    #[serde(default)]
+    pub quotas: Option<QuotaOptions>
+}
+
+#[derive(Debug)]
+pub enum OpenGraphError {
+    GraphNotFound,
+    AccessDenied,
+    InitSchemaError(ExecError),
+    IncompatibleSchemas(Vec<String>),
+    SchemaMigrationError(MigrationError)
+}
+
+impl GraphOptions {
+    pub fn allows<'a>(&self, user: &'a str) -> bool {
+        match self.users {
+            Some(ref users) => users.iter().any(|u| u == user),
+            None => true
+        }
+    }
+}
+
+pub fn new_meta_services<'a>(group: &'a str, graphs: &Vec<GraphOptions>, raft_service: &Arc<RaftService>) {
+    for graph in graphs {
+        SchemaContainer::new_meta_service(&namespaced_group(group, &graph.name), raft_service);
+    }
+}
@@ -0,0 +1,280 @@
+// Path patterns with variable length segments, like `(a)-[:knows*1..3]->(b)-[:owns]->(c)`, matched
+// by the traversal router one hop at a time over the whole set of partial paths. Every segment is
+// a hop repeated between min and max times, its filter applies to every one of those hops.
+// `->` follows edges outbound, `<-` inbound and a plain `-` both ways. Vertices in parentheses only
+// name the positions, labels and properties are not matched. Paths never visit a vertex twice,
+// except that a path may end where it started, which is how rings are found.
+// A weight aggregates a numeric edge field along every path and keeps the paths whose aggregate is
+// in range. Paths are dropped during expansion once they cannot come back into range: minimums only
+// fall and maximums only rise, sums are taken to only rise and products to only fall, as they do over
+// non-negative costs like latencies and over probabilities. Edges without the field end the path.
+
+use neb::ram::types::Id;
+use futures::prelude::*;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use graph::{Graph, EdgeDirection};
+use graph::edge::EdgeType;
+use server::schema::SchemaType;
+use server::traversal::{TraversalRouter, Hop, RoutingError};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Segment {
+    pub hop: Hop,
+    pub min: usize,
+    pub max: usize
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WeightAggregate {
+    Sum,
+    Min,
+    Max,
+    Product
+}
+
+impl WeightAggregate {
+    // the aggregate of a path without edges, None when there is none
+    fn empty(&self) -> Option<f64> {
+        match self {
+            &WeightAggregate::Sum => Some(0.0),
+            &WeightAggregate::Product => Some(1.0),
+            _ => None
+        }
+    }
+
+    fn add(&self, aggregate: Option<f64>, weight: f64) -> f64 {
+        match (self, aggregate) {
+            (_, None) => weight,
+            (&WeightAggregate::Sum, Some(a)) => a + weight,
+            (&WeightAggregate::Min, Some(a)) => a.min(weight),
+            (&WeightAggregate::Max, Some(a)) => a.max(weight),
+            (&WeightAggregate::Product, Some(a)) => a * weight
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PathWeight {
+    // field of the edge bodies
+    pub field: String,
+    pub aggregate: WeightAggregate,
+    pub at_least: Option<f64>,
+    pub at_most: Option<f64>
+}
+
+impl PathWeight {
+    fn in_range(&self, weight: Option<f64>) -> bool {
+        match weight {
+            Some(w) => self.at_least.map_or(true, |min| w >= min) && self.at_most.map_or(true, |max| w <= max),
+            None => true
+        }
+    }
+
+    // whether a path with this aggregate can still end in range
+    fn can_match(&self, weight: f64) -> bool {
+        match self.aggregate {
+            WeightAggregate::Min | WeightAggregate::Product => self.at_least.map_or(true, |min| weight >= min),
+            WeightAggregate::Max | WeightAggregate::Sum => self.at_most.map_or(true, |max| weight <= max)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PathPattern {
+    pub segments: Vec<Segment>,
+    // matching stops once this many paths were found
+    pub max_paths: Option<usize>,
+    #[serde(default)]
+    pub weight: Option<PathWeight>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Path {
+    pub vertices: Vec<Id>,
+    // the body cell of every edge on the path, None for edges without one
+    pub edges: Vec<Option<Id>>,
+    // the aggregate of the pattern's weight, None without one
+    pub weight: Option<f64>
+}
+
+// a path still growing, hops counts the hops taken in its current segment
+#[derive(Clone)]
+struct Partial {
+    path: Path,
+    segment: usize,
+    hops: usize
+}
+
+fn parse_bounds(bounds: &str) -> Result<(usize, usize), String> {
+    let number = |n: &str| n.trim().parse::<usize>().map_err(|_| format!("{} is no hop count", n));
+    let (min, max) = match bounds.find("..") {
+        Some(pos) => (&bounds[..pos], &bounds[pos + 2..]),
+        None if bounds.trim().is_empty() => return Err("variable length segments need an upper bound".to_string()),
+        None => (bounds, bounds)
+    };
+    let min = if min.trim().is_empty() { 1 } else { number(min)? };
+    if max.trim().is_empty() {
+        return Err("variable length segments need an upper bound".to_string());
+    }
+    let max = number(max)?;
+    if min > max { return Err(format!("{}..{} is empty", min, max)); }
+    Ok((min, max))
+}
+
+// the schema and bounds inside the brackets of `-[:schema*min..max]-`
+fn parse_relation(graph: &Graph, relation: &str, outbound: bool, inbound: bool) -> Result<Segment, String> {
+    let relation = relation.trim();
+    if !relation.starts_with(':') { return Err(format!("[{}] names no edge schema", relation)); }
+    let (name, bounds) = match relation.find('*') {
+        Some(pos) => (&relation[1..pos], Some(&relation[pos + 1..])),
+        None => (&relation[1..], None)
+    };
+    let (schema, attributes) = match graph.schema_by_name(name.trim()) {
+        Some((id, SchemaType::Edge(attributes))) => (id, attributes),
+        Some(_) => return Err(format!("{} is no edge schema", name)),
+        None => return Err(format!("unknown schema {}", name))
+    };
+    let direction = match (outbound, inbound) {
+        (true, false) => EdgeDirection::Outbound,
+        (false, true) => EdgeDirection::Inbound,
+        (true, true) => return Err("edges point one way only".to_string()),
+        (false, false) => match attributes.edge_type {
+            EdgeType::Undirected => EdgeDirection::Undirected,
+            EdgeType::Directed => EdgeDirection::Both
+        }
+    };
+    let (min, max) = match bounds { Some(bounds) => parse_bounds(bounds)?, None => (1, 1) };
+    Ok(Segment { hop: Hop { schema, direction, filter: None }, min, max })
+}
+
+impl PathPattern {
+    pub fn parse(graph: &Graph, pattern: &str) -> Result<PathPattern, String> {
+        let mut segments = Vec::new();
+        let mut rest = pattern.trim();
+        let skip_vertex = |rest: &str| -> Result<usize, String> {
+            if !rest.starts_with('(') { return Ok(0); }
+            rest.find(')').map(|pos| pos + 1).ok_or_else(|| "unclosed (".to_string())
+        };
+        rest = rest[skip_vertex(rest)?..].trim_left();
+        while !rest.is_empty() {
+            let inbound = rest.starts_with("<-");
+            rest = if inbound { &rest[2..] } else if rest.starts_with('-') { &rest[1..] } else {
+                return Err(format!("expected an edge at {}", rest));
+            };
+            if !rest.starts_with('[') { return Err(format!("expected [ at {}", rest)); }
+            let close = rest.find(']').ok_or_else(|| "unclosed [".to_string())?;
+            let relation = &rest[1..close];
+            rest = &rest[close + 1..];
+            let outbound = rest.starts_with("->");
+            rest = if outbound { &rest[2..] } else if rest.starts_with('-') { &rest[1..] } else {
+                return Err(format!("expected - at {}", rest));
+            };
+            segments.push(parse_relation(graph, relation, outbound, inbound)?);
+            rest = rest.trim_left();
+            rest = rest[skip_vertex(rest)?..].trim_left();
+        }
+        if segments.is_empty() { return Err("the pattern has no edges".to_string()); }
+        Ok(PathPattern { segments, max_paths: None, weight: None })
+    }
+
+    // filter for every hop of the segment, a lisp expression over vertex and edge
+    pub fn with_filter(mut self, segment: usize, filter: &str) -> PathPattern {
+        if let Some(segment) = self.segments.get_mut(segment) {
+            segment.hop.filter = Some(filter.to_string());
+        }
+        self
+    }
+
+    pub fn with_max_paths(mut self, max_paths: usize) -> PathPattern {
+        self.max_paths = Some(max_paths);
+        self
+    }
+
+    pub fn with_weight(mut self, weight: PathWeight) -> PathPattern {
+        self.weight = Some(weight);
+        self
+    }
+
+    // Moves the partial path past the segments it has taken enough hops in, including segments
+    // that may be skipped entirely, and adds the ones that can take more hops to growing.
+    // True when the path matches the whole pattern.
+    fn settle(&self, partial: Partial, growing: &mut Vec<Partial>) -> bool {
+        let mut matched = false;
+        let mut current = partial;
+        loop {
+            let segment = &self.segments[current.segment];
+            if current.hops < segment.max { growing.push(current.clone()); }
+            if current.hops < segment.min { break; }
+            if current.segment + 1 == self.segments.len() {
+                matched = self.weight.as_ref().map_or(true, |weight| weight.in_range(current.path.weight));
+                break;
+            }
+            current = Partial { segment: current.segment + 1, hops: 0, ..current };
+        }
+        matched
+    }
+}
+
+impl TraversalRouter {
+    // Paths from the start vertices matching the pattern, shortest first
+    #[async]
+    pub fn match_paths(this: Arc<Self>, token: String, start: Vec<Id>, pattern: PathPattern) -> Result<Vec<Path>, RoutingError> {
+        let max_paths = pattern.max_paths.unwrap_or(usize::max_value());
+        let mut matched = Vec::new();
+        let mut growing = Vec::new();
+        for id in start {
+            let weight = pattern.weight.as_ref().and_then(|weight| weight.aggregate.empty());
+            let path = Path { vertices: vec![id], edges: Vec::new(), weight };
+            if pattern.settle(Partial { path: path.clone(), segment: 0, hops: 0 }, &mut growing) {
+                matched.push(path);
+            }
+        }
+        while !growing.is_empty() && matched.len() < max_paths {
+            // paths in the same segment expand with the same hop, one request per owner for all of them
+            let mut by_segment: HashMap<usize, Vec<Partial>> = HashMap::new();
+            for partial in growing.drain(..) {
+                by_segment.entry(partial.segment).or_insert_with(Vec::new).push(partial);
+            }
+            let mut segments: Vec<_> = by_segment.into_iter().collect();
+            segments.sort_by_key(|&(segment, _)| segment);
+            for (segment, partials) in segments {
+                let mut tails: Vec<Id> = partials.iter().map(|p| *p.path.vertices.last().unwrap()).collect();
+                tails.sort_by_key(|id| (id.higher, id.lower));
+                tails.dedup();
+                let mut hop = pattern.segments[segment].hop.clone();
+                hop.weight_field = pattern.weight.as_ref().map(|weight| weight.field.clone());
+                let expanded: HashMap<Id, _> = await!(this.expand(&token, tails, &hop))?.into_iter().collect();
+                for partial in partials {
+                    let tail = *partial.path.vertices.last().unwrap();
+                    let neighbours = match expanded.get(&tail) { Some(neighbours) => neighbours, None => continue };
+                    for neighbour in neighbours {
+                        let closes_ring = neighbour.vertex == partial.path.vertices[0];
+                        if partial.path.vertices.contains(&neighbour.vertex) && !closes_ring { continue; }
+                        let weight = match (pattern.weight.as_ref(), neighbour.weight) {
+                            (Some(weight), Some(edge_weight)) => {
+                                let aggregate = weight.aggregate.add(partial.path.weight, edge_weight);
+                                if !weight.can_match(aggregate) { continue; }
+                                Some(aggregate)
+                            },
+                            (Some(_), None) => continue,
+                            (None, _) => None
+                        };
+                        let mut path = partial.path.clone();
+                        path.weight = weight;
+                        path.vertices.push(neighbour.vertex);
+                        path.edges.push(neighbour.edge);
+                        let next = Partial { path: path.clone(), segment, hops: partial.hops + 1 };
+                        // a ring ends the path, it cannot grow past its start
+                        let mut ended = Vec::new();
+                        let matches = pattern.settle(next, if closes_ring { &mut ended } else { &mut growing });
+                        if matches && matched.len() < max_paths { matched.push(path); }
+                    }
+                }
+            }
+        }
+        Ok(matched)
+    }
+}
@@ -0,0 +1,367 @@
+// Pregel jobs spread over the servers owning their vertices. The coordinator scans the topology,
+// hands every server the vertices it owns with their neighbour ids, and then drives the supersteps:
+// each server computes its shard and sends the messages straight to the owners of their targets,
+// found the same way traversals are routed, see server::traversal. A superstep ends once every
+// server computed and delivered, messages are tagged with the superstep reading them so a fast
+// server cannot hand a slow one messages early. Programs must be registered on every server.
+
+use bifrost::rpc::*;
+use neb::ram::types::Id;
+use futures::prelude::*;
+use futures::future::{self, Loop};
+use futures_cpupool::CpuPool;
+use chashmap::CHashMap;
+use parking_lot::{Mutex, RwLock};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use analytics::{self, AnalyticsError};
+use analytics::pregel::{DistributedProgram, PregelOptions, Shard};
+use analytics::programs::{PageRank, ConnectedComponents, ShortestPaths};
+use graph::Graph;
+use graph::mem::MemGraph;
+use graph::vertex::Vertex;
+use server::auth::{AuthContainer, Permission, Resource};
+use server::schema::{SchemaContainer, ToSchemaId};
+use server::traversal::TraversalRouter;
+
+pub static PREGEL_SERVICE_ID: u64 = hash_ident!(MORPHEUS_PREGEL_RPC_SERVICE) as u64;
+
+// what a server did in one superstep
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct StepReport {
+    // a vertex of the shard has not voted to halt
+    pub active: bool,
+    pub sent: u64
+}
+
+// load checks the token against the schemas of the job, the other calls only need the job id
+service! {
+    rpc load(token: String, job: u64, program: String, args: Vec<u8>, schemas: Vec<u32>, vertices: Vec<(Id, Vec<Id>)>, num_vertices: u64) -> u64 | String;
+    rpc superstep(job: u64, superstep: u64) -> StepReport | String;
+    rpc deliver(job: u64, superstep: u64, messages: Vec<(Id, Vec<u8>)>) -> () | String;
+    rpc finish(job: u64) -> Vec<(Id, Vec<u8>)> | String;
+}
+
+// a shard with the types of its program erased, messages and states in json
+trait ShardJob: Send {
+    fn deliver(&mut self, messages: Vec<(Id, Vec<u8>)>) -> Result<(), String>;
+    fn superstep(&mut self, superstep: usize) -> Result<(Vec<(Id, Vec<u8>)>, bool), String>;
+    fn states(&self) -> Result<Vec<(Id, Vec<u8>)>, String>;
+    fn len(&self) -> usize;
+}
+
+impl <P> ShardJob for Shard<P>
+    where P: DistributedProgram, P::State: Serialize + DeserializeOwned, P::Message: Serialize + DeserializeOwned
+{
+    fn deliver(&mut self, messages: Vec<(Id, Vec<u8>)>) -> Result<(), String> {
+        for (to, message) in messages {
+            let message = serde_json::from_slice(&message).map_err(|e| e.to_string())?;
+            Shard::deliver(self, &to, message);
+        }
+        Ok(())
+    }
+    fn superstep(&mut self, superstep: usize) -> Result<(Vec<(Id, Vec<u8>)>, bool), String> {
+        let (outbox, active) = Shard::superstep(self, superstep);
+        let outbox = outbox.into_iter()
+            .map(|(to, message)| serde_json::to_vec(&message).map(|message| (to, message)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        Ok((outbox, active))
+    }
+    fn states(&self) -> Result<Vec<(Id, Vec<u8>)>, String> {
+        Shard::states(self).into_iter()
+            .map(|(id, state)| serde_json::to_vec(state).map(|state| (id, state)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())
+    }
+    fn len(&self) -> usize {
+        Shard::len(self)
+    }
+}
+
+type ShardFactory = Fn(&[u8], Vec<(Vertex, Vec<Id>)>, usize) -> Result<Box<ShardJob>, String> + Send + Sync;
+
+struct RunningJob {
+    shard: Mutex<Box<ShardJob>>,
+    // messages by the superstep that reads them
+    incoming: Mutex<HashMap<u64, Vec<(Id, Vec<u8>)>>>
+}
+
+impl RunningJob {
+    fn accept(&self, superstep: u64, mut messages: Vec<(Id, Vec<u8>)>) {
+        self.incoming.lock().entry(superstep).or_insert_with(Vec::new).append(&mut messages);
+    }
+}
+
+pub struct PregelService {
+    graph: Arc<Graph>,
+    schemas: Arc<SchemaContainer>,
+    auth: Arc<AuthContainer>,
+    router: Arc<TraversalRouter>,
+    programs: RwLock<HashMap<String, Arc<ShardFactory>>>,
+    jobs: Arc<CHashMap<u64, Arc<RunningJob>>>,
+    pool: CpuPool
+}
+
+impl PregelService {
+    pub fn new(
+        graph: &Arc<Graph>, schemas: &Arc<SchemaContainer>, auth: &Arc<AuthContainer>, router: &Arc<TraversalRouter>
+    ) -> Arc<PregelService> {
+        let service = PregelService {
+            graph: graph.clone(),
+            schemas: schemas.clone(),
+            auth: auth.clone(),
+            router: router.clone(),
+            programs: RwLock::new(HashMap::new()),
+            jobs: Arc::new(CHashMap::new()),
+            pool: CpuPool::new_num_cpus()
+        };
+        service.register::<PageRank>();
+        service.register::<ConnectedComponents>();
+        service.register::<ShortestPaths>();
+        Arc::new(service)
+    }
+
+    // makes the program runnable here, coordinators refuse programs they have not registered either
+    pub fn register<P>(&self)
+        where P: DistributedProgram, P::State: Serialize + DeserializeOwned, P::Message: Serialize + DeserializeOwned
+    {
+        let factory = |args: &[u8], vertices: Vec<(Vertex, Vec<Id>)>, num_vertices: usize| -> Result<Box<ShardJob>, String> {
+            let program: P = serde_json::from_slice(args).map_err(|e| e.to_string())?;
+            Ok(Box::new(Shard::new(program, vertices, num_vertices)) as Box<ShardJob>)
+        };
+        self.programs.write().insert(P::NAME.to_string(), Arc::new(factory));
+    }
+
+    fn job(&self, job: u64) -> Result<Arc<RunningJob>, String> {
+        match self.jobs.get(&job) {
+            Some(running) => Ok(running.clone()),
+            None => Err(format!("No pregel job {}", job))
+        }
+    }
+
+    fn worker(this: &Arc<Self>, owner: &String) -> Result<Worker, AnalyticsError> {
+        if this.router.is_local(owner) {
+            return Ok(Worker::Local(this.clone()));
+        }
+        DEFAULT_CLIENT_POOL.get(owner)
+            .map(|client| Worker::Remote(AsyncServiceClient::new(PREGEL_SERVICE_ID, &client)))
+            .map_err(|e| AnalyticsError::DistributedError(format!("{:?}", e)))
+    }
+
+    // Runs the program over the vertices of the schemas on the servers owning them and streams
+    // (vertex id, final state) pairs. The token needs read permission on every schema.
+    pub fn run<S, P>(this: Arc<Self>, token: &str, vertex_schemas: Vec<S>, edge_schemas: Vec<S>, program: P, options: PregelOptions)
+        -> impl Stream<Item = (Id, P::State), Error = AnalyticsError>
+        where S: ToSchemaId, P: DistributedProgram, P::State: Serialize + DeserializeOwned, P::Message: Serialize + DeserializeOwned
+    {
+        let token = token.to_string();
+        let directed = options.directed;
+        let max_supersteps = options.max_supersteps;
+        let schemas = vertex_schemas.iter().chain(edge_schemas.iter())
+            .map(|schema| schema.to_id(&this.schemas))
+            .collect::<Vec<_>>();
+        let job = ::rand::random::<u64>();
+        let registered = this.programs.read().contains_key(P::NAME);
+        let args = serde_json::to_vec(&program).map_err(|e| AnalyticsError::DistributedError(e.to_string()));
+        let graph = this.graph.clone();
+        future::result(args)
+            .and_then(move |args| if registered { Ok(args) } else {
+                Err(AnalyticsError::DistributedError(format!("Pregel program {} is not registered", P::NAME)))
+            })
+            .and_then(move |args| MemGraph::from_scans(&graph, vertex_schemas, edge_schemas)
+                .map_err(AnalyticsError::MemGraphError)
+                .map(move |mem| (mem, args)))
+            .and_then(move |(mem, args)| -> Result<_, AnalyticsError> {
+                let num_vertices = mem.vertices().len() as u64;
+                let mut shards: HashMap<String, Vec<(Id, Vec<Id>)>> = HashMap::new();
+                for (vertex, neighbours) in mem.vertices().iter().zip(analytics::adjacency(&mem, directed)) {
+                    let id = vertex.cell.id();
+                    let owner = this.router.owner_of(&id)
+                        .ok_or_else(|| AnalyticsError::DistributedError(format!("No owner for {:?}", id)))?;
+                    let neighbours = neighbours.into_iter().map(|v| mem.vertices()[v].cell.id()).collect();
+                    shards.entry(owner).or_insert_with(Vec::new).push((id, neighbours));
+                }
+                let mut workers = Vec::with_capacity(shards.len());
+                let mut loads = Vec::with_capacity(shards.len());
+                for (owner, vertices) in shards {
+                    let worker = Self::worker(&this, &owner)?;
+                    loads.push(worker.load(token.clone(), job, P::NAME.to_string(), args.clone(), schemas.clone(), vertices, num_vertices));
+                    workers.push(worker);
+                }
+                Ok((Arc::new(workers), loads, num_vertices))
+            })
+            .and_then(move |(workers, loads, num_vertices)| {
+                let workers_ref = workers.clone();
+                future::join_all(loads)
+                    .and_then(move |_| supersteps(workers, job, num_vertices > 0, max_supersteps))
+                    .and_then(move |workers| future::join_all(workers.iter().map(|w| w.finish(job)).collect::<Vec<_>>()))
+                    .or_else(move |e| {
+                        // drops the shards still held, the error is the one worth reporting
+                        future::join_all(workers_ref.iter().map(|w| w.finish(job).then(|_| Ok::<_, String>(()))).collect::<Vec<_>>())
+                            .then(move |_| Err(e))
+                    })
+                    .map_err(AnalyticsError::DistributedError)
+            })
+            .and_then(|shards| shards.into_iter()
+                .flat_map(|states| states)
+                .map(|(id, state)| serde_json::from_slice(&state).map(|state| (id, state)))
+                .collect::<Result<Vec<(Id, P::State)>, _>>()
+                .map_err(|e| AnalyticsError::DistributedError(e.to_string())))
+            .map(::futures::stream::iter_ok)
+            .flatten_stream()
+    }
+}
+
+// runs supersteps on every server until the job converges or max_supersteps ran out
+fn supersteps(workers: Arc<Vec<Worker>>, job: u64, has_work: bool, max_supersteps: usize)
+    -> impl Future<Item = Arc<Vec<Worker>>, Error = String>
+{
+    if !has_work || max_supersteps == 0 {
+        return future::Either::A(future::ok(workers));
+    }
+    let stepping = workers.clone();
+    future::Either::B(future::loop_fn(0, move |superstep| {
+        let steps: Vec<_> = stepping.iter().map(|w| w.superstep(job, superstep)).collect();
+        future::join_all(steps).map(move |reports| {
+            let has_work = reports.iter().any(|r| r.active || r.sent > 0);
+            let superstep = superstep + 1;
+            if has_work && (superstep as usize) < max_supersteps { Loop::Continue(superstep) } else { Loop::Break(()) }
+        })
+    }).map(move |_| workers))
+}
+
+// sends the messages of a superstep to the owners of their targets, messages to ids without an owner are dropped
+fn route(router: &Arc<TraversalRouter>, running: &Arc<RunningJob>, job: u64, superstep: u64, outbox: Vec<(Id, Vec<u8>)>)
+    -> impl Future<Item = (), Error = String>
+{
+    let mut by_owner: HashMap<String, Vec<(Id, Vec<u8>)>> = HashMap::new();
+    for (to, message) in outbox {
+        if let Some(owner) = router.owner_of(&to) {
+            by_owner.entry(owner).or_insert_with(Vec::new).push((to, message));
+        }
+    }
+    let mut sends: Vec<Box<Future<Item = (), Error = String>>> = Vec::with_capacity(by_owner.len());
+    for (owner, messages) in by_owner {
+        if router.is_local(&owner) {
+            running.accept(superstep, messages);
+            continue;
+        }
+        let rpc_client = match DEFAULT_CLIENT_POOL.get(&owner) {
+            Ok(client) => client,
+            Err(e) => return future::Either::A(future::err(format!("{:?}", e)))
+        };
+        let service = AsyncServiceClient::new(PREGEL_SERVICE_ID, &rpc_client);
+        sends.push(Box::new(service.deliver(&job, &superstep, &messages).then(flatten)));
+    }
+    future::Either::B(future::join_all(sends).map(|_| ()))
+}
+
+fn flatten<T>(result: Result<Result<T, String>, RPCError>) -> Result<T, String> {
+    match result {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(e)) => Err(e),
+        Err(e) => Err(format!("{:?}", e))
+    }
+}
+
+// a server computing a shard, this one is called without going through RPC
+enum Worker {
+    Local(Arc<PregelService>),
+    Remote(Arc<AsyncServiceClient>)
+}
+
+impl Worker {
+    fn load(&self, token: String, job: u64, program: String, args: Vec<u8>, schemas: Vec<u32>, vertices: Vec<(Id, Vec<Id>)>, num_vertices: u64)
+        -> Box<Future<Item = u64, Error = String>>
+    {
+        match self {
+            &Worker::Local(ref service) => service.load(token, job, program, args, schemas, vertices, num_vertices),
+            &Worker::Remote(ref client) => Box::new(
+                client.load(&token, &job, &program, &args, &schemas, &vertices, &num_vertices).then(flatten)
+            )
+        }
+    }
+    fn superstep(&self, job: u64, superstep: u64) -> Box<Future<Item = StepReport, Error = String>> {
+        match self {
+            &Worker::Local(ref service) => service.superstep(job, superstep),
+            &Worker::Remote(ref client) => Box::new(client.superstep(&job, &superstep).then(flatten))
+        }
+    }
+    fn finish(&self, job: u64) -> Box<Future<Item = Vec<(Id, Vec<u8>)>, Error = String>> {
+        match self {
+            &Worker::Local(ref service) => service.finish(job),
+            &Worker::Remote(ref client) => Box::new(client.finish(&job).then(flatten))
+        }
+    }
+}
+
+impl Service for PregelService {
+    fn load(&self, token: String, job: u64, program: String, args: Vec<u8>, schemas: Vec<u32>, vertices: Vec<(Id, Vec<Id>)>, num_vertices: u64)
+        -> Box<Future<Item = u64, Error = String>>
+    {
+        for schema in schemas {
+            let resource = Resource { namespace: self.graph.namespace().cloned(), schema: Some(schema) };
+            if let Err(e) = self.auth.check(&token, Permission::Read, &resource) {
+                return Box::new(future::err(format!("{:?}", e)));
+            }
+        }
+        let factory = match self.programs.read().get(&program) {
+            Some(factory) => factory.clone(),
+            None => return Box::new(future::err(format!("Pregel program {} is not registered", program)))
+        };
+        let jobs = self.jobs.clone();
+        let (ids, adjacency): (Vec<Id>, Vec<Vec<Id>>) = vertices.into_iter().unzip();
+        Box::new(self.graph.vertices_by(ids)
+            .map_err(|e| format!("{:?}", e))
+            .and_then(move |read| -> Result<u64, String> {
+                // vertices removed since the coordinator scanned them are left out
+                let vertices: Vec<_> = read.into_iter().zip(adjacency.into_iter())
+                    .filter_map(|(vertex, neighbours)| vertex.map(|vertex| (vertex, neighbours)))
+                    .collect();
+                let shard = factory(&args, vertices, num_vertices as usize)?;
+                let loaded = shard.len() as u64;
+                jobs.insert(job, Arc::new(RunningJob { shard: Mutex::new(shard), incoming: Mutex::new(HashMap::new()) }));
+                Ok(loaded)
+            }))
+    }
+
+    fn superstep(&self, job: u64, superstep: u64) -> Box<Future<Item = StepReport, Error = String>> {
+        let running = match self.job(job) {
+            Ok(running) => running,
+            Err(e) => return Box::new(future::err(e))
+        };
+        let router = self.router.clone();
+        let computing = running.clone();
+        Box::new(self.pool.spawn_fn(move || {
+                let messages = computing.incoming.lock().remove(&superstep).unwrap_or_default();
+                let mut shard = computing.shard.lock();
+                shard.deliver(messages)?;
+                shard.superstep(superstep as usize)
+            })
+            .and_then(move |(outbox, active)| {
+                let sent = outbox.len() as u64;
+                route(&router, &running, job, superstep + 1, outbox).map(move |_| StepReport { active, sent })
+            }))
+    }
+
+    fn deliver(&self, job: u64, superstep: u64, messages: Vec<(Id, Vec<u8>)>) -> Box<Future<Item = (), Error = String>> {
+        Box::new(future::result(self.job(job).map(|running| running.accept(superstep, messages))))
+    }
+
+    fn finish(&self, job: u64) -> Box<Future<Item = Vec<(Id, Vec<u8>)>, Error = String>> {
+        Box::new(future::result(match self.jobs.remove(&job) {
+            Some(running) => {
+                let states = running.shard.lock().states();
+                states
+            },
+            None => Err(format!("No pregel job {}", job))
+        }))
+    }
+}
+dispatch_rpc_service_functions!(PregelService);
@@ -0,0 +1,145 @@
+// Token buckets in front of writes and expensive traversals, so a runaway importer or query loop
+// slows down instead of taking the cluster with it. Every client has a bucket per class: the user
+// of the token for admin and GraphQL requests, the origin cluster for replicated writes and the
+// graph's namespace for traversal requests from other servers, which carry no user. Requests that
+// touch many items take as many tokens, capped at the burst so large requests are delayed, not
+// refused forever. Buckets live on each server, a client spread over n servers gets n times the rate.
+
+use parking_lot::Mutex;
+
+use std::collections::HashMap;
+use std::time::Instant;
+
+// the client of requests without a user, while authentication is off
+pub static ANONYMOUS_CLIENT: &'static str = "anonymous";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateClass {
+    Write,
+    Traversal
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RateLimit {
+    // tokens added every second
+    pub per_second: f64,
+    // tokens a bucket holds at most, what an idle client may spend at once
+    pub burst: u64
+}
+
+// limits for one client or namespace in place of the defaults
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClientRateLimits {
+    pub client: String,
+    #[serde(default)]
+    pub writes: Option<RateLimit>,
+    #[serde(default)]
+    pub traversals: Option<RateLimit>
+}
+
+// classes without a limit are not limited
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RateLimitOptions {
+    #[serde(default)]
+    pub writes: Option<RateLimit>,
+    #[serde(default)]
+    pub traversals: Option<RateLimit>,
+    #[serde(default)]
+    pub clients: Vec<ClientRateLimits>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RateLimited {
+    pub client: String,
+    pub class: RateClass,
+    // when the request would pass if nothing else is taken meanwhile
+    pub retry_after_ms: u64
+}
+
+struct TokenBucket {
+    tokens: f64,
+    refilled: Instant
+}
+
+pub struct RateLimiter {
+    options: RateLimitOptions,
+    buckets: Mutex<HashMap<(RateClass, String), TokenBucket>>
+}
+
+impl RateLimit {
+    pub fn new(per_second: f64, burst: u64) -> RateLimit {
+        RateLimit { per_second, burst }
+    }
+}
+
+impl RateLimitOptions {
+    pub fn with_writes(mut self, limit: RateLimit) -> RateLimitOptions {
+        self.writes = Some(limit);
+        self
+    }
+    pub fn with_traversals(mut self, limit: RateLimit) -> RateLimitOptions {
+        self.traversals = Some(limit);
+        self
+    }
+    pub fn with_client(mut self, client: &str, writes: Option<RateLimit>, traversals: Option<RateLimit>) -> RateLimitOptions {
+        self.clients.retain(|limits| limits.client != client);
+        self.clients.push(ClientRateLimits { client: client.to_string(), writes, traversals });
+        self
+    }
+
+    // a client listed in clients takes its own limits, a class it leaves out is not limited for it
+    pub fn limit(&self, class: RateClass, client: &str) -> Option<&RateLimit> {
+        let (writes, traversals) = match self.clients.iter().find(|limits| limits.client == client) {
+            Some(limits) => (&limits.writes, &limits.traversals),
+            None => (&self.writes, &self.traversals)
+        };
+        match class {
+            RateClass::Write => writes.as_ref(),
+            RateClass::Traversal => traversals.as_ref()
+        }
+    }
+}
+
+impl RateLimiter {
+    pub fn new(options: RateLimitOptions) -> RateLimiter {
+        RateLimiter { options, buckets: Mutex::new(HashMap::new()) }
+    }
+
+    // limits nothing
+    pub fn unlimited() -> RateLimiter {
+        RateLimiter::new(RateLimitOptions::default())
+    }
+
+    pub fn options(&self) -> &RateLimitOptions {
+        &self.options
+    }
+
+    // Takes cost tokens from the client's bucket of the class, or nothing when there are not enough
+    pub fn acquire(&self, class: RateClass, client: &str, cost: u64) -> Result<(), RateLimited> {
+        let limit = match self.options.limit(class, client) {
+            Some(limit) => limit,
+            None => return Ok(())
+        };
+        let capacity = limit.burst.max(1) as f64;
+        let cost = (cost as f64).min(capacity);
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock();
+        let bucket = buckets.entry((class, client.to_string()))
+            .or_insert_with(|| TokenBucket { tokens: capacity, refilled: now });
+        let elapsed = now.duration_since(bucket.refilled);
+        let elapsed_secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1_000_000_000.0;
+        bucket.tokens = (bucket.tokens + elapsed_secs * limit.per_second).min(capacity);
+        bucket.refilled = now;
+        if bucket.tokens >= cost {
+            bucket.tokens -= cost;
+            return Ok(());
+        }
+        let retry_after_ms = if limit.per_second > 0.0 {
+            ((cost - bucket.tokens) / limit.per_second * 1000.0).ceil() as u64
+        } else {
+            u64::max_value()
+        };
+        debug!("Rate limited {:?} of {}, retry after {}ms", class, client, retry_after_ms);
+        Err(RateLimited { client: client.to_string(), class, retry_after_ms })
+    }
+}
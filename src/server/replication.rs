@@ -0,0 +1,426 @@
+// Asynchronous replication to another Morpheus cluster, for disaster recovery and geo-distribution.
+// An agent on every source server tails the change log of graph::changes and sends the writes to
+// the replication service of the target cluster, which applies every batch in one transaction.
+// Vertices keep their ids, so edges and keyed lookups resolve the same on both sides, and schemas
+// are matched by name. Hidden fields, those starting with `_`, stay the target's own. Edges are
+// matched by schema and ends: a link is applied only when the ends are not linked by the schema yet
+// and an unlink removes every such edge, so parallel edges collapse into one on the target. Links
+// skipped and unlinks that removed more than one edge are reported back and logged by the agent.
+// Conflicts between a replicated write and a write made on the target are settled by the policy.
+// Source wins applies every replicated write. Last writer wins compares the commit time of the write
+// with the last one the target knows for the vertex or the pair of ends, taken from its own change
+// log and from the batches it applied, and drops replicated writes that are older. That clock is in
+// memory and bounded, entries it forgot or lost to a restart count as older than any write, and
+// clocks of different clusters are only as close as their servers' clocks.
+// Offsets are kept in raft like those of the CDC sinks and delivery is at least once. Writes that
+// came from the target cluster are not sent back to it, so two clusters can replicate into each
+// other, but not through a third one. Batches carry the token of a user of the target with write
+// permission on the schemas written, events of other schemas are rejected. Targets with write rate
+// limits take a token per event from the bucket of that user, batches refused for the rate are sent
+// again after the retry interval.
+
+use bifrost::rpc::*;
+use neb::ram::types::{Id, key_hash};
+use neb::dovahkiin::types::{Map, Value};
+use neb::client::transaction::TxnError;
+use bifrost_hasher::hash_str;
+use parking_lot::Mutex;
+use futures::prelude::*;
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use graph::{Graph, GraphTransaction, UpdateOptions};
+use graph::changes::{ChangeEvent, ChangeKind, ChangeLog};
+use graph::gc::pause;
+use graph::placement::Placement;
+use server::cdc::{OffsetStore, offset_key};
+use server::auth::{AuthContainer, Permission, Resource};
+use server::metrics;
+use server::rate_limit::{RateLimiter, RateClass};
+
+pub static REPLICATION_SERVICE_ID: u64 = hash_ident!(MORPHEUS_REPLICATION_RPC_SERVICE) as u64;
+
+fn default_name() -> String { "default".to_string() }
+fn default_policy() -> ConflictPolicy { ConflictPolicy::LastWriterWins }
+fn default_batch_size() -> usize { 512 }
+fn default_log_capacity() -> usize { 100000 }
+fn default_retry_interval_ms() -> u64 { 1000 }
+fn default_clock_capacity() -> usize { 1000000 }
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    LastWriterWins,
+    SourceWins
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReplicationOptions {
+    // offsets are kept by name, like those of the CDC sinks
+    #[serde(default = "default_name")]
+    pub name: String,
+    // group name of the target cluster, writes replicated from it are not sent back
+    pub group: String,
+    // of a user of the target cluster allowed to write the replicated schemas
+    #[serde(default)]
+    pub token: String,
+    // host:port of target servers with the replication service on, tried in order
+    pub servers: Vec<String>,
+    #[serde(default = "default_policy")]
+    pub policy: ConflictPolicy,
+    // events per transaction on the target
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    // events the change log keeps for the agent to catch up on
+    #[serde(default = "default_log_capacity")]
+    pub log_capacity: usize,
+    // pause after a failed send or offset lookup
+    #[serde(default = "default_retry_interval_ms")]
+    pub retry_interval_ms: u64
+}
+
+// for servers taking writes replicated from other clusters
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReplicaOptions {
+    // vertices and pairs of ends whose last write time is remembered for last writer wins
+    #[serde(default = "default_clock_capacity")]
+    pub clock_capacity: usize,
+    // local writes the change log keeps until the clock takes them in
+    #[serde(default = "default_log_capacity")]
+    pub log_capacity: usize
+}
+
+impl Default for ReplicaOptions {
+    fn default() -> ReplicaOptions {
+        ReplicaOptions { clock_capacity: default_clock_capacity(), log_capacity: default_log_capacity() }
+    }
+}
+
+// a change event with the name of its schema, schema ids differ between clusters
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReplicatedEvent {
+    pub schema: String,
+    pub event: ChangeEvent
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ApplyReport {
+    pub applied: usize,
+    // older than the write the target has, under last writer wins
+    pub superseded: usize,
+    // rejected by the target graph or of schemas it does not have, with why
+    pub rejected: Vec<(u64, String)>,
+    // links not applied as their ends were linked already and unlinks that removed more than one
+    // edge, the target no longer has the parallel edges of the origin
+    #[serde(default)]
+    pub collapsed: Vec<u64>
+}
+
+#[derive(Debug)]
+pub enum ReplicationError {
+    RPCError(RPCError),
+    Remote(String)
+}
+
+service! {
+    rpc apply(token: String, origin: String, policy: ConflictPolicy, events: Vec<ReplicatedEvent>) -> ApplyReport | String;
+}
+
+// what last writer wins compares against, vertices by id and edges by their schema and ends
+fn clock_key(schema: u32, event: &ChangeEvent) -> Id {
+    match event.ends {
+        Some((from, to)) => Id::new(
+            hash_str(&format!("{}:{}:{}", schema, from.higher, from.lower)),
+            hash_str(&format!("{}:{}", to.higher, to.lower))
+        ),
+        None => event.id
+    }
+}
+
+struct Clock {
+    times: HashMap<Id, u64>,
+    // keys in the order they were first written, the oldest are forgotten first
+    order: VecDeque<Id>,
+    capacity: usize,
+    // the last event of the local change log taken in
+    seq: u64
+}
+
+impl Clock {
+    fn written(&mut self, key: Id, time: u64) {
+        {
+            let known = self.times.entry(key).or_insert(0);
+            if *known == 0 { self.order.push_back(key); }
+            if time > *known { *known = time; }
+        }
+        while self.times.len() > self.capacity {
+            match self.order.pop_front() {
+                Some(oldest) => { self.times.remove(&oldest); },
+                None => break
+            }
+        }
+    }
+
+    // local writes since the last batch, the replicated ones were taken in when applied
+    fn catch_up(&mut self, log: &ChangeLog) {
+        loop {
+            let events = log.read_after(self.seq, 4096, Duration::from_millis(0));
+            let last = match events.last() { Some(event) => event.seq, None => return };
+            for event in events.iter().filter(|event| event.origin.is_none()) {
+                self.written(clock_key(event.schema, event), event.time);
+            }
+            self.seq = last;
+        }
+    }
+
+    fn is_superseded(&self, key: &Id, time: u64) -> bool {
+        self.times.get(key).map_or(false, |&known| known > time)
+    }
+}
+
+// the user fields of a replicated vertex or edge body, hidden ones are the target's own
+fn user_fields(data: &Option<Value>) -> Map {
+    let mut fields = Map::new();
+    if let &Some(Value::Map(ref map)) = data {
+        for name in map.fields.iter().filter(|name| !name.starts_with('_')) {
+            fields.insert(name, map.get_by_key_id(key_hash(name)).clone());
+        }
+    }
+    fields
+}
+
+fn error<E: Debug>(e: E) -> String {
+    format!("{:?}", e)
+}
+
+// whether the event collapsed parallel edges of the origin when applied
+fn apply_event(txn: &GraphTransaction, schema: u32, event: &ChangeEvent) -> Result<Result<bool, String>, TxnError> {
+    Ok(match (event.kind, event.ends) {
+        (ChangeKind::VertexInserted, _) | (ChangeKind::VertexUpdated, _) => {
+            let data = user_fields(&event.data);
+            if txn.vertex_exists(event.id)? {
+                txn.update_vertex_with(event.id, UpdateOptions::default(), |mut vertex| {
+                    if let Value::Map(ref mut map) = vertex.cell.data {
+                        for name in &data.fields {
+                            map.insert(name, data.get_by_key_id(key_hash(name)).clone());
+                        }
+                    }
+                    Some(vertex)
+                })?.map(|_| false).map_err(error)
+            } else {
+                txn.new_vertex_with_placement(schema, data, Placement::Exact(event.id))?.map(|_| false).map_err(error)
+            }
+        },
+        (ChangeKind::VertexRemoved, _) => {
+            if txn.vertex_exists(event.id)? { txn.remove_vertex(event.id)?.map(|_| false).map_err(error) } else { Ok(false) }
+        },
+        (ChangeKind::Linked, Some((from, to))) => match txn.has_edge(from, schema, to)? {
+            Ok(true) => Ok(true),
+            Ok(false) => {
+                let body = match event.data { Some(_) => Some(user_fields(&event.data)), None => None };
+                txn.link(from, schema, to, body)?.map(|_| false).map_err(error)
+            },
+            Err(e) => Err(error(e))
+        },
+        (ChangeKind::Unlinked, Some((from, to))) => txn.unlink(from, schema, to)?.map(|removed| removed > 1).map_err(error),
+        (_, None) => Err("edge event without ends".to_string())
+    })
+}
+
+pub struct ReplicationService {
+    graph: Arc<Graph>,
+    log: Arc<ChangeLog>,
+    clock: Arc<Mutex<Clock>>,
+    auth: Arc<AuthContainer>,
+    rate_limiter: Arc<RateLimiter>
+}
+
+impl ReplicationService {
+    // turns the change log of the graph on for the clock
+    pub fn new(
+        graph: &Arc<Graph>, options: &ReplicaOptions, auth: &Arc<AuthContainer>, rate_limiter: &Arc<RateLimiter>
+    ) -> Arc<ReplicationService> {
+        let log = graph.enable_change_log(options.log_capacity);
+        let seq = log.first_seq().map(|first| first - 1).unwrap_or(0);
+        Arc::new(ReplicationService {
+            graph: graph.clone(),
+            log,
+            clock: Arc::new(Mutex::new(Clock {
+                times: HashMap::new(), order: VecDeque::new(), capacity: ::std::cmp::max(options.clock_capacity, 1), seq
+            })),
+            auth: auth.clone(),
+            rate_limiter: rate_limiter.clone()
+        })
+    }
+}
+
+impl Service for ReplicationService {
+    fn apply(&self, token: String, origin: String, policy: ConflictPolicy, events: Vec<ReplicatedEvent>)
+        -> Box<Future<Item = ApplyReport, Error = String>>
+    {
+        if self.auth.is_enabled() {
+            if let Err(e) = self.auth.authenticate(&token) {
+                return Box::new(::futures::future::err(format!("{:?}", e)));
+            }
+        }
+        if let Err(limited) = self.rate_limiter.acquire(RateClass::Write, &self.auth.client_of(&token), events.len() as u64) {
+            return Box::new(::futures::future::err(format!("{:?}", limited)));
+        }
+        let mut report = ApplyReport::default();
+        let mut batch = Vec::with_capacity(events.len());
+        {
+            let mut clock = self.clock.lock();
+            clock.catch_up(&self.log);
+            for replicated in events {
+                let schema = match self.graph.schema_by_name(&replicated.schema) {
+                    Some((schema, _)) => schema,
+                    None => {
+                        report.rejected.push((replicated.event.seq, format!("unknown schema {}", replicated.schema)));
+                        continue;
+                    }
+                };
+                let resource = Resource { namespace: self.graph.namespace().cloned(), schema: Some(schema) };
+                if let Err(e) = self.auth.check(&token, Permission::Write, &resource) {
+                    report.rejected.push((replicated.event.seq, format!("{:?} on schema {}", e, replicated.schema)));
+                    continue;
+                }
+                let key = clock_key(schema, &replicated.event);
+                if policy == ConflictPolicy::LastWriterWins && clock.is_superseded(&key, replicated.event.time) {
+                    report.superseded += 1;
+                    continue;
+                }
+                batch.push((schema, key, replicated.event));
+            }
+        }
+        let batch = Arc::new(batch);
+        let txn_batch = batch.clone();
+        let clock = self.clock.clone();
+        Box::new(self.graph.graph_transaction(move |txn| {
+            let mut rejected = Vec::new();
+            let mut collapsed = Vec::new();
+            for (pos, &(schema, _, ref event)) in txn_batch.iter().enumerate() {
+                match apply_event(txn, schema, event)? {
+                    Ok(true) => collapsed.push(event.seq),
+                    Ok(false) => {},
+                    Err(reason) => rejected.push((pos, reason))
+                }
+            }
+            txn.mark_change_origin(&origin);
+            Ok((rejected, collapsed))
+        }).map_err(|e| format!("{:?}", e)).map(move |(rejected, collapsed)| {
+            report.collapsed = collapsed;
+            let mut clock = clock.lock();
+            for (pos, &(_, key, ref event)) in batch.iter().enumerate() {
+                match rejected.iter().find(|&&(rejected_pos, _)| rejected_pos == pos) {
+                    Some(&(_, ref reason)) => report.rejected.push((event.seq, reason.clone())),
+                    None => {
+                        clock.written(key, event.time);
+                        report.applied += 1;
+                    }
+                }
+            }
+            report
+        }))
+    }
+}
+dispatch_rpc_service_functions!(ReplicationService);
+
+// sends the batch to the first target server that takes it
+pub fn send(servers: &[String], token: &str, origin: &str, policy: ConflictPolicy, events: &Vec<ReplicatedEvent>)
+    -> Result<ApplyReport, ReplicationError>
+{
+    let mut last_error = ReplicationError::Remote("no target servers".to_string());
+    for server in servers {
+        let rpc_client = match DEFAULT_CLIENT_POOL.get(server) {
+            Ok(rpc_client) => rpc_client,
+            Err(e) => { last_error = ReplicationError::Remote(format!("cannot connect to {}: {:?}", server, e)); continue; }
+        };
+        let service = AsyncServiceClient::new(REPLICATION_SERVICE_ID, &rpc_client);
+        match service.apply(&token.to_string(), &origin.to_string(), &policy, events).wait() {
+            Ok(Ok(report)) => return Ok(report),
+            Ok(Err(e)) => last_error = ReplicationError::Remote(e),
+            Err(e) => last_error = ReplicationError::RPCError(e)
+        }
+    }
+    Err(last_error)
+}
+
+// Turns the change log of the graph on and replicates it to the target until running is cleared
+pub fn start_agent(
+    graph: Arc<Graph>, offsets: Arc<OffsetStore>, options: ReplicationOptions,
+    group: &str, server_addr: &str, running: Arc<AtomicBool>
+) -> thread::JoinHandle<()> {
+    let log = graph.enable_change_log(options.log_capacity);
+    let key = offset_key(&format!("replication-{}", options.name), server_addr);
+    let origin = group.to_string();
+    thread::Builder::new()
+        .name("morpheus-replication".to_string())
+        .spawn(move || {
+            let retry_interval = Duration::from_millis(options.retry_interval_ms);
+            let poll = Duration::from_millis(100);
+            let mut offset: Option<u64> = None;
+            // the events read and the sequence number of the last one, those from the target are left out
+            let mut batch: Option<(Vec<ReplicatedEvent>, u64)> = None;
+            while running.load(Ordering::Relaxed) {
+                let committed = match offset {
+                    Some(committed) => committed,
+                    None => match offsets.get(&key) {
+                        Ok(committed) => { offset = Some(committed); committed },
+                        Err(e) => {
+                            warn!("Replication {} cannot read its offset: {:?}", key, e);
+                            if !pause(&running, retry_interval) { break; }
+                            continue;
+                        }
+                    }
+                };
+                if batch.is_none() {
+                    let events = log.read_after(committed, ::std::cmp::max(options.batch_size, 1), poll);
+                    let last = match (events.first(), events.last()) {
+                        (Some(first), Some(last)) => {
+                            if committed > 0 && first.seq > committed + 1 {
+                                warn!("Replication {} lost the events after {} up to {}", key, committed, first.seq);
+                            }
+                            last.seq
+                        },
+                        _ => continue
+                    };
+                    let replicated = events.into_iter()
+                        .filter(|event| event.origin.as_ref() != Some(&options.group))
+                        .filter_map(|event| graph.schema_name(event.schema).map(|schema| ReplicatedEvent { schema, event }))
+                        .collect();
+                    batch = Some((replicated, last));
+                }
+                let sent = match batch {
+                    Some((ref events, _)) if events.is_empty() => Ok(ApplyReport::default()),
+                    Some((ref events, _)) => send(&options.servers, &options.token, &origin, options.policy, events),
+                    None => continue
+                };
+                match sent {
+                    Ok(report) => {
+                        let last = batch.take().map(|(_, last)| last).unwrap_or(committed);
+                        let _ = metrics::REPLICATED_EVENTS.inc_by(report.applied as f64);
+                        for &(seq, ref reason) in &report.rejected {
+                            warn!("Replication {} event {} was rejected by the target: {}", key, seq, reason);
+                        }
+                        for seq in &report.collapsed {
+                            warn!("Replication {} event {} collapsed parallel edges on the target", key, seq);
+                        }
+                        offset = Some(last);
+                        if let Err(e) = offsets.commit(&key, last) {
+                            warn!("Replication {} cannot save offset {}, a restart sends again: {:?}", key, last, e);
+                        }
+                    },
+                    Err(e) => {
+                        warn!("Replication {} failed to send, retrying: {:?}", key, e);
+                        if !pause(&running, retry_interval) { break; }
+                    }
+                }
+            }
+            debug!("Replication {} stopped", key);
+        })
+        .unwrap()
+}
@@ -5,13 +5,26 @@ use bifrost_hasher::hash_str;
 use graph::edge::{EdgeAttributes, EdgeType};
 use graph::edge;
 use chashmap::CHashMap;
+use parking_lot::RwLock;
 use std::sync::Arc;
 use neb::ram::schema::{Field, Schema};
+use neb::ram::types::TypeId;
+use neb::dovahkiin::types::Value;
 use neb::client::{AsyncClient as NebClient};
 use neb::server::{ServerMeta as NebServerMeta};
 use server::schema::sm::schema_types::client::SMClient;
+use server::schema::sm::{StoredSchemaType, SchemaRecord};
+use graph::placement::PlacementPolicy;
+use graph::ids::IdStrategy;
+use graph::endpoints::EdgeEndpoints;
+use graph::encryption::FieldCipher;
+use graph::window_counter::WindowCounter;
 use graph::fields::VERTEX_TEMPLATE;
+use graph::validation;
+use graph::computed::{ComputedField, ComputedMode};
+use query::Expr;
 use futures::{Future, future};
+use server::metrics;
 
 mod sm;
 
@@ -28,6 +41,17 @@ pub enum SchemaError {
     NewMorpheusSchemaExecError(ExecError),
     SimpleEdgeShouldNotHaveSchema,
     SchemaTypeUnspecified,
+    // the field the default is given for is not declared, or the value does not fit it
+    InvalidDefault(String),
+    // materialized fields must be declared, virtual ones must not, and the expression must parse
+    InvalidComputedField(String),
+    // endpoint restrictions are for edge schemas only
+    EndpointsOnVertexSchema,
+    // encrypted fields must be strings, or undeclared in dynamic schemas, and cannot be keys
+    InvalidEncryptedField(String),
+    // counters are for edge schemas, with distinct names, a window and at least one bucket
+    InvalidWindowCounter(String),
+    ReadOnly,
 }
 
 pub struct SchemaContainer {
@@ -35,6 +59,11 @@ pub struct SchemaContainer {
     map: Arc<CHashMap<u32, SchemaType>>,
     sm_client: Arc<SMClient>,
     neb_mata: Arc<NebServerMeta>,
+    namespace: Option<String>,
+    // everything else kept for each schema, see sm::SchemaRecord
+    records: Arc<CHashMap<u32, SchemaRecord>>,
+    // seals and opens the encrypted fields, see graph::encryption
+    cipher: RwLock<Option<Arc<FieldCipher>>>,
 }
 
 #[derive(Clone)]
@@ -44,7 +73,21 @@ pub struct MorpheusSchema {
     pub schema_type: SchemaType,
     pub key_field: Option<Vec<String>>,
     pub fields: Vec<Field>,
-    pub is_dynamic: bool
+    pub is_dynamic: bool,
+    // where new vertices of this schema go by default, ignored by edge schemas
+    pub placement: PlacementPolicy,
+    // values written for fields the data leaves null, for vertices and edge bodies
+    pub defaults: Vec<(String, Value)>,
+    // vertex fields derived from the others, see graph::computed
+    pub computed: Vec<ComputedField>,
+    // how vertices get their ids when the schema has no key, see graph::ids
+    pub id_strategy: IdStrategy,
+    // the vertex schemas edges of this schema may connect, see graph::endpoints
+    pub endpoints: EdgeEndpoints,
+    // fields stored encrypted with the name of their key, see graph::encryption
+    pub encrypted: Vec<(String, String)>,
+    // rolling counts of links kept on their ends, see graph::window_counter
+    pub window_counters: Vec<WindowCounter>
 }
 
 lazy_static! {
@@ -59,9 +102,111 @@ impl MorpheusSchema {
             key_field: key_field.cloned(),
             fields: fields.clone(),
             schema_type: SchemaType::Unspecified,
-            is_dynamic
+            is_dynamic,
+            placement: PlacementPolicy::Random,
+            defaults: Vec::new(),
+            computed: Vec::new(),
+            id_strategy: IdStrategy::Hash,
+            endpoints: EdgeEndpoints::default(),
+            encrypted: Vec::new(),
+            window_counters: Vec::new()
         }
     }
+    pub fn with_id_strategy(mut self, id_strategy: IdStrategy) -> MorpheusSchema {
+        self.id_strategy = id_strategy;
+        self
+    }
+    // only vertices of these schemas at the ends of its edges, empty lists allow any
+    pub fn with_endpoints(mut self, from: &[&str], to: &[&str]) -> MorpheusSchema {
+        self.endpoints = EdgeEndpoints::new(from, to);
+        self
+    }
+    pub fn with_placement(mut self, placement: PlacementPolicy) -> MorpheusSchema {
+        self.placement = placement;
+        self
+    }
+    pub fn with_encrypted(mut self, field: &str, key: &str) -> MorpheusSchema {
+        self.encrypted.retain(|&(ref name, _)| name != field);
+        self.encrypted.push((field.to_string(), key.to_string()));
+        self
+    }
+    pub fn with_window_counter(mut self, counter: WindowCounter) -> MorpheusSchema {
+        self.window_counters.retain(|c| c.name != counter.name);
+        self.window_counters.push(counter);
+        self
+    }
+    pub fn with_default(mut self, field: &str, value: Value) -> MorpheusSchema {
+        self.defaults.retain(|&(ref name, _)| name != field);
+        self.defaults.push((field.to_string(), value));
+        self
+    }
+    pub fn with_computed(mut self, field: ComputedField) -> MorpheusSchema {
+        self.computed.retain(|f| f.name != field.name);
+        self.computed.push(field);
+        self
+    }
+    // required fields reject null writes that no default fills in
+    pub fn required(self, field: &str) -> MorpheusSchema {
+        self.with_nullable(field, false)
+    }
+    pub fn optional(self, field: &str) -> MorpheusSchema {
+        self.with_nullable(field, true)
+    }
+    fn with_nullable(mut self, field: &str, nullable: bool) -> MorpheusSchema {
+        for f in self.fields.iter_mut().filter(|f| f.name == field) {
+            f.nullable = nullable;
+        }
+        self
+    }
+    fn check_computed(&self) -> Result<(), SchemaError> {
+        for computed in &self.computed {
+            let declared = self.fields.iter().any(|f| f.name == computed.name);
+            if declared != (computed.mode == ComputedMode::Materialized) || computed.expr.to_sexpr().is_err() {
+                return Err(SchemaError::InvalidComputedField(computed.name.clone()));
+            }
+        }
+        Ok(())
+    }
+    fn check_endpoints(&self) -> Result<(), SchemaError> {
+        match self.schema_type {
+            SchemaType::Edge(_) => Ok(()),
+            _ if self.endpoints.is_empty() => Ok(()),
+            _ => Err(SchemaError::EndpointsOnVertexSchema)
+        }
+    }
+    fn check_encrypted(&self) -> Result<(), SchemaError> {
+        for &(ref name, _) in &self.encrypted {
+            let is_key = self.key_field.as_ref().map_or(false, |key| key.contains(name));
+            let fits = match self.fields.iter().find(|f| &f.name == name) {
+                Some(field) => field.type_id == TypeId::String as u32 && !field.is_array && field.sub_fields.is_none(),
+                None => self.is_dynamic
+            };
+            if is_key || !fits {
+                return Err(SchemaError::InvalidEncryptedField(name.clone()));
+            }
+        }
+        Ok(())
+    }
+    fn check_window_counters(&self) -> Result<(), SchemaError> {
+        for counter in &self.window_counters {
+            let is_edge = match self.schema_type { SchemaType::Edge(_) => true, _ => false };
+            if !is_edge || counter.window_secs == 0 || counter.buckets == 0 || counter.name.is_empty() {
+                return Err(SchemaError::InvalidWindowCounter(counter.name.clone()));
+            }
+        }
+        Ok(())
+    }
+    fn check_defaults(&self) -> Result<(), SchemaError> {
+        for &(ref name, ref value) in &self.defaults {
+            match self.fields.iter().find(|f| &f.name == name) {
+                Some(field) => if let Err(e) = validation::validate_field_value(field, value) {
+                    return Err(SchemaError::InvalidDefault(format!("{:?}", e)));
+                },
+                None => return Err(SchemaError::InvalidDefault(name.clone()))
+            }
+        }
+        Ok(())
+    }
     pub fn into_ref(self) -> Arc<MorpheusSchema> {
         Arc::new(self)
     }
@@ -89,6 +234,45 @@ pub fn generate_sm_id<'a>(group: &'a str) -> u64 {
     hash_str(&format!("{}-{}", sm::DEFAULT_RAFT_PREFIX, group))
 }
 
+// schemas stored before the record came in have the defaults for everything but their type
+fn from_stored(stored: StoredSchemaType) -> SchemaRecord {
+    let schema_type = match stored {
+        StoredSchemaType::Record(record) => return record,
+        StoredSchemaType::Unspecified => SchemaType::Unspecified,
+        StoredSchemaType::Vertex => SchemaType::Vertex,
+        StoredSchemaType::Edge(attrs) => SchemaType::Edge(EdgeAttributes::new(attrs.edge_type, attrs.has_body))
+    };
+    SchemaRecord { schema_type, ..SchemaRecord::default() }
+}
+
+fn load_record(
+    map: &CHashMap<u32, SchemaType>, records: &CHashMap<u32, SchemaRecord>, schema_id: u32, stored: StoredSchemaType
+) {
+    let record = from_stored(stored);
+    map.insert(schema_id, record.schema_type);
+    records.insert(schema_id, record);
+}
+
+// each named graph keeps its schema types under its own raft state machine
+pub fn namespaced_group<'a>(group: &'a str, namespace: &'a str) -> String {
+    format!("{}/{}", group, namespace)
+}
+
+// neb schema names are cluster wide, so schemas of a named graph carry its name as a prefix
+fn neb_schema_name(namespace: &Option<String>, name: &str) -> String {
+    match namespace {
+        &Some(ref ns) => format!("{}:{}", ns, name),
+        &None => name.to_string()
+    }
+}
+
+fn local_schema_name(namespace: &Option<String>, name: &str) -> String {
+    match namespace {
+        &Some(ref ns) if name.starts_with(&format!("{}:", ns)) => name[ns.len() + 1..].to_string(),
+        _ => name.to_string()
+    }
+}
+
 impl SchemaContainer {
 
     pub fn new_meta_service<'a>(group: &'a str, raft_service: &Arc<RaftService>) {
@@ -103,38 +287,78 @@ impl SchemaContainer {
         neb_client: &Arc<NebClient>,
         neb_meta: &Arc<NebServerMeta>
     ) -> Result<Arc<SchemaContainer>, ExecError> {
-        let sm_client = Arc::new(SMClient::new(generate_sm_id(group), &raft_client));
+        Self::new_namespaced_client(group, None, raft_client, neb_client, neb_meta)
+    }
+
+    pub fn new_namespaced_client<'a>(
+        group: &'a str,
+        namespace: Option<&'a str>,
+        raft_client: &Arc<RaftClient>,
+        neb_client: &Arc<NebClient>,
+        neb_meta: &Arc<NebServerMeta>
+    ) -> Result<Arc<SchemaContainer>, ExecError> {
+        let sm_group = match namespace {
+            Some(ns) => namespaced_group(group, ns),
+            None => group.to_string()
+        };
+        let sm_client = Arc::new(SMClient::new(generate_sm_id(&sm_group), &raft_client));
         let sm_entries = sm_client.entries()?.unwrap();
         let container = SchemaContainer {
             map: Arc::new(CHashMap::new()),
             sm_client: sm_client.clone(),
             neb_client: neb_client.clone(),
-            neb_mata: neb_meta.clone()
+            neb_mata: neb_meta.clone(),
+            namespace: namespace.map(|ns| ns.to_string()),
+            records: Arc::new(CHashMap::new()),
+            cipher: RwLock::new(None)
         };
         let container_ref = Arc::new(container);
         let container_ref1 = container_ref.clone();
         let container_ref2 = container_ref.clone();
-        for (schema_id, schema_type) in sm_entries {
-            container_ref.map.insert(schema_id, schema_type);
+        for (schema_id, stored) in sm_entries {
+            load_record(&container_ref.map, &container_ref.records, schema_id, stored);
         }
         sm_client.on_inserted(move |res| {
-            if let Ok((id, schema_type)) = res {
-                container_ref1.map.insert(id, schema_type);
+            if let Ok((id, stored)) = res {
+                load_record(&container_ref1.map, &container_ref1.records, id, stored);
             }
         })?;
         sm_client.on_removed(move |res| {
-            if let Ok((id, schema_type)) = res {
+            if let Ok((id, _)) = res {
                 container_ref2.map.remove(&id);
+                container_ref2.records.remove(&id);
             }
         })?;
         return Ok(container_ref);
     }
 
-    pub fn new_schema(&self, schema: MorpheusSchema) -> impl Future<Item = u32, Error = SchemaError> {
-        let schema_type = schema.schema_type;
+    pub fn namespace(&self) -> Option<&String> {
+        self.namespace.as_ref()
+    }
+
+    pub fn new_schema(&self, mut schema: MorpheusSchema) -> impl Future<Item = u32, Error = SchemaError> {
+        schema.name = neb_schema_name(&self.namespace, &schema.name);
         let sm_client = self.sm_client.clone();
+        let map = self.map.clone();
+        let records = self.records.clone();
         let neb_client = self.neb_client.clone();
-        future::result(cell_fields(schema_type, schema.fields.clone()))
+        let checked = schema.check_defaults()
+            .and_then(|_| schema.check_computed())
+            .and_then(|_| schema.check_endpoints())
+            .and_then(|_| schema.check_encrypted())
+            .and_then(|_| schema.check_window_counters())
+            .and_then(|_| cell_fields(schema.schema_type, schema.fields.clone()));
+        let record = SchemaRecord {
+            schema_type: schema.schema_type,
+            placement: schema.placement.clone(),
+            defaults: schema.defaults.clone(),
+            computed: schema.computed.clone(),
+            id_strategy: schema.id_strategy,
+            endpoints: schema.endpoints.clone(),
+            encrypted: schema.encrypted.clone(),
+            window_counters: schema.window_counters.clone()
+        };
+        future::result(checked)
             .and_then(move |schema_fields| {
                 let mut neb_schema = Schema::new(
                     &schema.name,
@@ -146,13 +370,55 @@ impl SchemaContainer {
                     .map_err(|e| SchemaError::NewNebSchemaExecError(e))
             })
             .and_then(move |(schema_id, _)| {
-                match sm_client.insert(&schema_id, &schema_type) {
-                    Ok(_) => Ok(schema_id),
+                // the type and all metadata go in one entry, a failed insert leaves none of it behind
+                match sm_client.insert(&schema_id, &StoredSchemaType::Record(record.clone())) {
+                    Ok(_) => {
+                        map.insert(schema_id, record.schema_type);
+                        records.insert(schema_id, record);
+                        metrics::SCHEMA_CHANGES.inc();
+                        Ok(schema_id)
+                    },
                     Err(e) => Err(SchemaError::NewMorpheusSchemaExecError(e))
                 }
             })
     }
 
+    pub fn placement(&self, schema_id: u32) -> PlacementPolicy {
+        self.records.get(&schema_id).map(|r| r.placement.clone()).unwrap_or_default()
+    }
+
+    pub fn defaults(&self, schema_id: u32) -> Vec<(String, Value)> {
+        self.records.get(&schema_id).map(|r| r.defaults.clone()).unwrap_or_default()
+    }
+
+    pub fn computed(&self, schema_id: u32) -> Vec<ComputedField> {
+        self.records.get(&schema_id).map(|r| r.computed.clone()).unwrap_or_default()
+    }
+
+    pub fn id_strategy(&self, schema_id: u32) -> IdStrategy {
+        self.records.get(&schema_id).map(|r| r.id_strategy).unwrap_or_default()
+    }
+
+    pub fn endpoints(&self, schema_id: u32) -> EdgeEndpoints {
+        self.records.get(&schema_id).map(|r| r.endpoints.clone()).unwrap_or_default()
+    }
+
+    pub fn encrypted(&self, schema_id: u32) -> Vec<(String, String)> {
+        self.records.get(&schema_id).map(|r| r.encrypted.clone()).unwrap_or_default()
+    }
+
+    pub fn window_counters(&self, schema_id: u32) -> Vec<WindowCounter> {
+        self.records.get(&schema_id).map(|r| r.window_counters.clone()).unwrap_or_default()
+    }
+
+    pub fn field_cipher(&self) -> Option<Arc<FieldCipher>> {
+        self.cipher.read().clone()
+    }
+
+    pub fn set_field_cipher(&self, cipher: Arc<FieldCipher>) {
+        *self.cipher.write() = Some(cipher);
+    }
+
     pub fn schema_type(&self, schema_id: u32) -> Option<SchemaType> {
         Self::schema_type_(&self.map, schema_id)
     }
@@ -164,8 +430,20 @@ impl SchemaContainer {
         }
     }
 
+    pub fn all_schema_types(&self) -> Vec<(u32, SchemaType)> {
+        (*self.map).clone().into_iter().collect()
+    }
+
+    pub fn vertex_schema_ids(&self) -> Vec<u32> {
+        self.all_schema_types()
+            .into_iter()
+            .filter(|&(_, schema_type)| schema_type == SchemaType::Vertex)
+            .map(|(id, _)| id)
+            .collect()
+    }
+
     pub fn id_from_name<'a>(&self, name : &'a str) -> Option<u32> {
-        self.neb_mata.schemas.name_to_id(name)
+        self.neb_mata.schemas.name_to_id(&neb_schema_name(&self.namespace, name))
     }
 
     pub fn from_name<'a>(&self, name: &'a str) -> Option<MorpheusSchema> {
@@ -179,29 +457,42 @@ impl SchemaContainer {
         self.neb_mata.schemas.get(&schema_id)
     }
     pub fn neb_to_morpheus_schema(&self, schema: &Arc<Schema>) -> Option<MorpheusSchema> {
-        Self::neb_to_morpheus_schema_(&self.map, schema)
+        Self::neb_to_morpheus_schema_(&self.map, &self.records, &self.namespace, schema)
     }
-    fn neb_to_morpheus_schema_(schema_map: &Arc<CHashMap<u32, SchemaType>>, schema: &Arc<Schema>) -> Option<MorpheusSchema> {
+    fn neb_to_morpheus_schema_(
+        schema_map: &Arc<CHashMap<u32, SchemaType>>, records: &Arc<CHashMap<u32, SchemaRecord>>,
+        namespace: &Option<String>, schema: &Arc<Schema>
+    ) -> Option<MorpheusSchema> {
         if let Some(schema_type) = Self::schema_type_(schema_map, schema.id) {
             if let Some(ref fields) = schema.fields.sub_fields {
+                let record = records.get(&schema.id).map(|r| r.clone()).unwrap_or_default();
                 Some(MorpheusSchema {
                     id: schema.id,
-                    name: schema.name.clone(),
+                    name: local_schema_name(namespace, &schema.name),
                     schema_type,
                     key_field: schema.str_key_field.clone(),
                     fields: fields.clone(),
-                    is_dynamic: schema.is_dynamic
+                    is_dynamic: schema.is_dynamic,
+                    placement: record.placement,
+                    defaults: record.defaults,
+                    computed: record.computed,
+                    id_strategy: record.id_strategy,
+                    endpoints: record.endpoints,
+                    encrypted: record.encrypted,
+                    window_counters: record.window_counters
                 })
             } else { None }
         } else { None }
     }
     pub fn all_morpheus_schemas(&self) -> impl Future<Item = Vec<MorpheusSchema>, Error = ExecError> {
         let schema_map = self.map.clone();
+        let namespace = self.namespace.clone();
+        let records = self.records.clone();
         self.neb_client.get_all_schema()
             .map(move |neb_schemas| {
                 neb_schemas
                     .into_iter()
-                    .map(|schema| Self::neb_to_morpheus_schema_(&schema_map, &Arc::new(schema)))
+                    .map(|schema| Self::neb_to_morpheus_schema_(&schema_map, &records, &namespace, &Arc::new(schema)))
                     .filter_map(|ms| ms)
                     .collect()
             })
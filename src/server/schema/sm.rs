@@ -1,6 +1,59 @@
 use std::collections::HashMap;
 use super::SchemaType;
+use graph::edge::EdgeType;
+use graph::placement::PlacementPolicy;
+use neb::dovahkiin::types::Value;
+use graph::computed::ComputedField;
+use graph::ids::IdStrategy;
+use graph::endpoints::EdgeEndpoints;
+use graph::window_counter::WindowCounter;
 
 pub static DEFAULT_RAFT_PREFIX: &'static str = "MORPHEUS_SCHEMA_RAFT_SM";
 
-def_store_hash_map!(schema_types <u32, SchemaType>);
+// Schema types in the layout of their first entries are still read. The raft log and snapshots hold
+// them encoded without field names, so the layout cannot grow: new schemas are written as a Record,
+// which carries everything morpheus keeps for a schema, and variants may only be appended.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum StoredSchemaType {
+    Unspecified,
+    Vertex,
+    Edge(StoredEdgeAttributes),
+    Record(SchemaRecord)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub struct StoredEdgeAttributes {
+    pub edge_type: EdgeType,
+    pub has_body: bool
+}
+
+// per-schema metadata, written in one insert with the schema type
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SchemaRecord {
+    pub schema_type: SchemaType,
+    pub placement: PlacementPolicy,
+    pub defaults: Vec<(String, Value)>,
+    pub computed: Vec<ComputedField>,
+    pub id_strategy: IdStrategy,
+    pub endpoints: EdgeEndpoints,
+    pub encrypted: Vec<(String, String)>,
+    pub window_counters: Vec<WindowCounter>
+}
+
+impl Default for SchemaRecord {
+    fn default() -> SchemaRecord {
+        SchemaRecord {
+            schema_type: SchemaType::Unspecified,
+            placement: PlacementPolicy::default(),
+            defaults: Vec::new(),
+            computed: Vec::new(),
+            id_strategy: IdStrategy::default(),
+            endpoints: EdgeEndpoints::default(),
+            encrypted: Vec::new(),
+            window_counters: Vec::new()
+        }
+    }
+}
+
+def_store_hash_map!(schema_types <u32, StoredSchemaType>);
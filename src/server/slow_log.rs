@@ -0,0 +1,50 @@
+use neb::dovahkiin::expr::SExpr;
+use parking_lot::RwLock;
+use std::time::{Duration, Instant};
+
+pub static SLOW_QUERY_TARGET: &'static str = "morpheus::slow_query";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SlowQueryOptions {
+    pub latency_ms: u64,
+    pub result_size: usize
+}
+
+impl Default for SlowQueryOptions {
+    fn default() -> SlowQueryOptions {
+        SlowQueryOptions {
+            latency_ms: 1000,
+            result_size: 10000
+        }
+    }
+}
+
+lazy_static! {
+    static ref OPTIONS: RwLock<SlowQueryOptions> = RwLock::new(SlowQueryOptions::default());
+}
+
+pub fn configure(options: SlowQueryOptions) {
+    *OPTIONS.write() = options;
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + (duration.subsec_nanos() / 1_000_000) as u64
+}
+
+// logs the operation when it took too long or returned too many results, tells whether it did
+pub fn check(op: &'static str, started: Instant, result_size: usize, schemas: &[u32], filter: &Option<Vec<SExpr>>) -> bool {
+    let elapsed_ms = millis(started.elapsed());
+    let (latency_ms, size_limit) = {
+        let options = OPTIONS.read();
+        (options.latency_ms, options.result_size)
+    };
+    let slow = elapsed_ms >= latency_ms || result_size >= size_limit;
+    if slow {
+        warn!(
+            target: SLOW_QUERY_TARGET,
+            "op={} elapsed_ms={} results={} schemas={:?} filter={:?}",
+            op, elapsed_ms, result_size, schemas, filter
+        );
+    }
+    slow
+}
@@ -0,0 +1,336 @@
+// Statistics of the graph kept in a raft state machine so every server sees the same. A pass counts the
+// vertices and edges of every schema and reads the degree of every vertex for every edge schema, which
+// makes it a full scan, so only the raft leader runs it and never more often than interval_secs.
+// Counts feed CountMode::Approximate of every server, the rest is for operators through the admin
+// service. There is no query planner yet to use the degrees.
+
+use bifrost::raft::RaftService;
+use bifrost::raft::client::RaftClient;
+use bifrost::raft::state_machine::master::ExecError;
+use bifrost_hasher::hash_str;
+use chashmap::CHashMap;
+use neb::ram::types::Id;
+use neb::client::transaction::TxnError;
+use futures::prelude::*;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use graph::{Graph, CountMode, EdgeDirection, ScanVerticesError, ScanEdgesError};
+use graph::edge::{EdgeType, EdgeError};
+use graph::quota::value_size;
+use server::schema::{SchemaContainer, SchemaType};
+use server::stats::sm::graph_stats::client::SMClient;
+
+mod sm;
+
+pub static HOT_VERTICES: usize = 16;
+pub static DEGREE_BATCH: usize = 512;
+pub static COLLECT_INTERVAL_SECS: u64 = 300;
+
+fn default_interval_secs() -> u64 { COLLECT_INTERVAL_SECS }
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct StatisticsOptions {
+    // seconds between passes of the background collector, 0 turns it off
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64
+}
+
+impl Default for StatisticsOptions {
+    fn default() -> StatisticsOptions {
+        StatisticsOptions { interval_secs: default_interval_secs() }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SchemaStatistics {
+    pub schema_id: u32,
+    pub count: u64,
+    // edge schemas only, bucket i holds vertices whose degree takes i bits
+    pub degree_histogram: Vec<u64>,
+    pub hot_vertices: Vec<(Id, u64)>,
+    pub collected_at: u64
+}
+
+// Degrees of one direction over every vertex, bucket i of the histogram holds vertices whose degree
+// takes i bits, vertices without edges of the schema are in bucket 0 and in the mean
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DegreeDistribution {
+    pub histogram: Vec<u64>,
+    pub vertices: u64,
+    pub mean: f64,
+    pub max: u64,
+    // the vertices of the highest degrees, highest first
+    pub hubs: Vec<(Id, u64)>
+}
+
+// Made on demand by degree_report, to find supernodes before they hurt and to plan capacity
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DegreeReport {
+    pub schema_id: u32,
+    pub edges: u64,
+    // degrees of undirected schemas are all here
+    pub out_degree: DegreeDistribution,
+    // directed schemas only
+    pub in_degree: Option<DegreeDistribution>,
+    // schemas with bodies only, the estimated bytes of a body cell like quotas count them
+    pub avg_body_bytes: Option<f64>,
+    pub collected_at: u64
+}
+
+#[derive(Debug)]
+pub enum CollectError {
+    ScanVerticesError(ScanVerticesError),
+    ScanEdgesError(ScanEdgesError),
+    EdgeError(EdgeError),
+    TxnError(TxnError),
+    // the server shut down in the middle of the pass
+    Stopped
+}
+
+pub struct StatisticsContainer {
+    map: Arc<CHashMap<u32, SchemaStatistics>>,
+    sm_client: Arc<SMClient>
+}
+
+pub fn generate_sm_id<'a>(group: &'a str) -> u64 {
+    hash_str(&format!("{}-{}", sm::DEFAULT_RAFT_PREFIX, group))
+}
+
+impl StatisticsContainer {
+
+    pub fn new_meta_service<'a>(group: &'a str, raft_service: &Arc<RaftService>) {
+        let mut stats_sm = sm::graph_stats::Map::new(generate_sm_id(group));
+        stats_sm.init_callback(raft_service);
+        raft_service.register_state_machine(Box::new(stats_sm));
+    }
+
+    pub fn new_client<'a>(
+        group: &'a str,
+        raft_client: &Arc<RaftClient>,
+        graph: &Arc<Graph>
+    ) -> Result<Arc<StatisticsContainer>, ExecError> {
+        let sm_client = Arc::new(SMClient::new(generate_sm_id(group), &raft_client));
+        let sm_entries = sm_client.entries()?.unwrap();
+        let container = Arc::new(StatisticsContainer {
+            map: Arc::new(CHashMap::new()),
+            sm_client: sm_client.clone()
+        });
+        for (schema_id, stats) in sm_entries {
+            graph.record_count(schema_id, stats.count as usize, stats.collected_at);
+            container.map.insert(schema_id, stats);
+        }
+        let map = container.map.clone();
+        let graph = graph.clone();
+        sm_client.on_inserted(move |res| {
+            if let Ok((schema_id, stats)) = res {
+                graph.record_count(schema_id, stats.count as usize, stats.collected_at);
+                map.insert(schema_id, stats);
+            }
+        })?;
+        Ok(container)
+    }
+
+    pub fn get(&self, schema_id: u32) -> Option<SchemaStatistics> {
+        self.map.get(&schema_id).map(|stats| stats.clone())
+    }
+
+    pub fn all(&self) -> Vec<SchemaStatistics> {
+        (*self.map).clone().into_iter().map(|(_, stats)| stats).collect()
+    }
+
+    pub fn save(&self, stats: &SchemaStatistics) -> Result<(), ExecError> {
+        self.sm_client.insert(&stats.schema_id, stats).map(|_| ())
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// one distribution per direction, read in the same pass over the vertices
+fn degree_distributions(
+    graph: &Arc<Graph>, vertex_schemas: &Vec<u32>, edge_schema: u32, directions: Vec<EdgeDirection>, hubs: usize
+) -> Result<Vec<DegreeDistribution>, CollectError> {
+    let mut distributions: Vec<_> = directions.iter().map(|_| DegreeDistribution {
+        histogram: vec![0u64; 65],
+        ..DegreeDistribution::default()
+    }).collect();
+    let mut totals = vec![0u64; directions.len()];
+    for vertex_schema in vertex_schemas {
+        let batches = graph.scan_vertices::<_, String>(*vertex_schema, &None, Some(Vec::new()))
+            .map_err(CollectError::ScanVerticesError)
+            .chunks(DEGREE_BATCH)
+            .wait();
+        for batch in batches {
+            let ids: Vec<Id> = batch?.iter().map(|v| v.cell.id()).collect();
+            let directions = directions.clone();
+            let degrees = graph.graph_transaction(move |txn| {
+                let mut degrees = Vec::with_capacity(ids.len() * directions.len());
+                for id in &ids {
+                    for (index, direction) in directions.iter().enumerate() {
+                        match txn.degree(id, edge_schema, *direction)? {
+                            Ok(degree) => degrees.push((index, *id, degree as u64)),
+                            Err(e) => return Ok(Err(e))
+                        }
+                    }
+                }
+                Ok(Ok(degrees))
+            }).wait().map_err(CollectError::TxnError)?.map_err(CollectError::EdgeError)?;
+            for (index, id, degree) in degrees {
+                let distribution = &mut distributions[index];
+                distribution.histogram[(64 - degree.leading_zeros()) as usize] += 1;
+                distribution.vertices += 1;
+                distribution.max = ::std::cmp::max(distribution.max, degree);
+                totals[index] += degree;
+                if degree > 0 { distribution.hubs.push((id, degree)); }
+            }
+            for distribution in &mut distributions {
+                distribution.hubs.sort_by(|a, b| b.1.cmp(&a.1));
+                distribution.hubs.truncate(hubs);
+            }
+        }
+    }
+    for (distribution, total) in distributions.iter_mut().zip(totals) {
+        while distribution.histogram.last() == Some(&0) { distribution.histogram.pop(); }
+        if distribution.vertices > 0 {
+            distribution.mean = total as f64 / distribution.vertices as f64;
+        }
+    }
+    Ok(distributions)
+}
+
+fn degree_distribution(
+    graph: &Arc<Graph>, vertex_schemas: &Vec<u32>, edge_schema: u32, direction: EdgeDirection
+) -> Result<(Vec<u64>, Vec<(Id, u64)>), CollectError> {
+    let distribution = degree_distributions(graph, vertex_schemas, edge_schema, vec![direction], HOT_VERTICES)?
+        .pop().unwrap();
+    Ok((distribution.histogram, distribution.hubs))
+}
+
+fn avg_body_bytes(graph: &Arc<Graph>, edge_schema: u32) -> Result<Option<f64>, CollectError> {
+    let (mut bodies, mut bytes) = (0u64, 0u64);
+    for edge in graph.scan_edges::<_, String>(edge_schema, &None).wait() {
+        if let &Some(ref body) = edge.map_err(CollectError::ScanEdgesError)?.get_data() {
+            bodies += 1;
+            bytes += value_size(&body.data);
+        }
+    }
+    Ok(if bodies > 0 { Some(bytes as f64 / bodies as f64) } else { None })
+}
+
+// Full pass over every edge schema, blocking like collect. Degrees are read for the vertices of every
+// vertex schema, hubs keeps the top vertices of each direction and bodies are all read for their sizes.
+pub fn degree_report(graph: &Arc<Graph>, schemas: &Arc<SchemaContainer>, hubs: usize)
+    -> Result<Vec<DegreeReport>, CollectError>
+{
+    let vertex_schemas = schemas.vertex_schema_ids();
+    let mut reports = Vec::new();
+    for (schema_id, schema_type) in schemas.all_schema_types() {
+        let edge_attrs = match schema_type {
+            SchemaType::Edge(edge_attrs) => edge_attrs,
+            _ => continue
+        };
+        let edges = graph.count_edges(schema_id, CountMode::Exact)
+            .wait().map_err(CollectError::ScanEdgesError)?.count as u64;
+        let directions = match edge_attrs.edge_type {
+            EdgeType::Directed => vec![EdgeDirection::Outbound, EdgeDirection::Inbound],
+            EdgeType::Undirected => vec![EdgeDirection::Undirected]
+        };
+        let mut distributions = degree_distributions(graph, &vertex_schemas, schema_id, directions, hubs)?;
+        let in_degree = if distributions.len() > 1 { distributions.pop() } else { None };
+        reports.push(DegreeReport {
+            schema_id,
+            edges,
+            out_degree: distributions.pop().unwrap(),
+            in_degree,
+            avg_body_bytes: if edge_attrs.has_body { avg_body_bytes(graph, schema_id)? } else { None },
+            collected_at: now_secs()
+        });
+    }
+    Ok(reports)
+}
+
+// full pass over every schema, blocking, meant for the background collector thread
+pub fn collect(graph: &Arc<Graph>, schemas: &Arc<SchemaContainer>) -> Result<Vec<SchemaStatistics>, CollectError> {
+    collect_while(graph, schemas, &AtomicBool::new(true))
+}
+
+// gives up with CollectError::Stopped before the next schema once running is cleared
+fn collect_while(graph: &Arc<Graph>, schemas: &Arc<SchemaContainer>, running: &AtomicBool)
+    -> Result<Vec<SchemaStatistics>, CollectError>
+{
+    let vertex_schemas = schemas.vertex_schema_ids();
+    let mut result = Vec::new();
+    for (schema_id, schema_type) in schemas.all_schema_types() {
+        if !running.load(Ordering::Relaxed) { return Err(CollectError::Stopped); }
+        let stats = match schema_type {
+            SchemaType::Vertex => {
+                let estimate = graph.count_vertices::<_, String>(schema_id, &None, CountMode::Exact)
+                    .wait().map_err(CollectError::ScanVerticesError)?;
+                SchemaStatistics {
+                    schema_id,
+                    count: estimate.count as u64,
+                    degree_histogram: Vec::new(),
+                    hot_vertices: Vec::new(),
+                    collected_at: now_secs()
+                }
+            },
+            SchemaType::Edge(edge_attrs) => {
+                let estimate = graph.count_edges(schema_id, CountMode::Exact)
+                    .wait().map_err(CollectError::ScanEdgesError)?;
+                let direction = match edge_attrs.edge_type {
+                    EdgeType::Directed => EdgeDirection::Outbound,
+                    EdgeType::Undirected => EdgeDirection::Undirected
+                };
+                let (degree_histogram, hot_vertices) =
+                    degree_distribution(graph, &vertex_schemas, schema_id, direction)?;
+                SchemaStatistics {
+                    schema_id,
+                    count: estimate.count as u64,
+                    degree_histogram,
+                    hot_vertices,
+                    collected_at: now_secs()
+                }
+            },
+            SchemaType::Unspecified => continue
+        };
+        result.push(stats);
+    }
+    Ok(result)
+}
+
+// Returns once `running` is cleared, checking it every second while waiting for the next pass and
+// before each schema of a pass. Passes are skipped while this server is not the raft leader.
+pub fn start_collector(
+    graph: Arc<Graph>, schemas: Arc<SchemaContainer>, container: Arc<StatisticsContainer>,
+    raft_service: Arc<RaftService>, options: StatisticsOptions, running: Arc<AtomicBool>
+) -> thread::JoinHandle<()> {
+    thread::Builder::new()
+        .name("morpheus-stats".to_string())
+        .spawn(move || {
+            'collecting: loop {
+                for _ in 0..options.interval_secs {
+                    if !running.load(Ordering::Relaxed) { break 'collecting; }
+                    thread::sleep(Duration::from_secs(1));
+                }
+                if !raft_service.is_leader() { continue; }
+                match collect_while(&graph, &schemas, &running) {
+                    Ok(all_stats) => {
+                        for stats in &all_stats {
+                            if let Err(e) = container.save(stats) {
+                                warn!("Cannot save statistics for schema {}: {:?}", stats.schema_id, e);
+                            }
+                        }
+                        debug!("Collected statistics for {} schemas", all_stats.len());
+                    },
+                    Err(CollectError::Stopped) => break 'collecting,
+                    Err(e) => warn!("Statistics collection failed: {:?}", e)
+                }
+            }
+            debug!("Statistics collector stopped");
+        })
+        .unwrap()
+}
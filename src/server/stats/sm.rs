@@ -0,0 +1,6 @@
+use std::collections::HashMap;
+use super::SchemaStatistics;
+
+pub static DEFAULT_RAFT_PREFIX: &'static str = "MORPHEUS_STATS_RAFT_SM";
+
+def_store_hash_map!(graph_stats <u32, SchemaStatistics>);
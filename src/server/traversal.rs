@@ -0,0 +1,291 @@
+use bifrost::rpc::*;
+use neb::ram::types::{Id, key_hash};
+use neb::dovahkiin::types::Value;
+use neb::client::{AsyncClient as NebClient};
+use futures::prelude::*;
+use futures::future;
+use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+
+use graph::{Graph, EdgeDirection};
+use graph::multi_schema::EdgeSchemas;
+use graph::traverse::{TraverseOptions, Neighbour};
+use server::auth::{AuthContainer, AuthError, Permission, Resource};
+use server::rate_limit::{RateLimiter, RateClass};
+
+pub static TRAVERSAL_SERVICE_ID: u64 = hash_ident!(MORPHEUS_TRAVERSAL_RPC_SERVICE) as u64;
+
+// one matching neighbour, edge is the id of the edge cell for edges with a body
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RoutedNeighbour {
+    pub vertex: Id,
+    pub edge: Option<Id>,
+    // the number in the weight field of the hop, None when the edge has none
+    #[serde(default)]
+    pub weight: Option<f64>
+}
+
+#[derive(Debug)]
+pub enum RoutingError {
+    OwnerNotFound(Id),
+    RPCError(RPCError),
+    RemoteError(String),
+    LocalError(String),
+    AuthError(AuthError)
+}
+
+// Runs on the node owning the vertex, edge lists are walked and filtered there
+// so only the matching ids travel back to the caller. The router passes on the token of the user
+// it traverses for, who needs read permission on the edge schema on every server reached.
+service! {
+    rpc neighbours(token: String, vertex: Id, schema: u32, direction: EdgeDirection, filter: Option<String>) -> Vec<RoutedNeighbour> | String;
+    rpc expand(token: String, frontier: Vec<Id>, hop: Hop) -> Vec<(Id, Vec<RoutedNeighbour>)> | String;
+}
+
+// one step of a multi-hop traversal
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Hop {
+    pub schema: u32,
+    pub direction: EdgeDirection,
+    pub filter: Option<String>,
+    // edge body field read into RoutedNeighbour::weight
+    #[serde(default)]
+    pub weight_field: Option<String>
+}
+
+// frontiers[0] holds the start vertices, frontiers[i] the vertices first reached at hop i
+#[derive(Debug, Clone)]
+pub struct TraversalResult {
+    pub frontiers: Vec<Vec<Id>>
+}
+
+pub struct TraversalService {
+    graph: Arc<Graph>,
+    auth: Arc<AuthContainer>,
+    rate_limiter: Arc<RateLimiter>
+}
+
+impl TraversalService {
+    pub fn new(graph: &Arc<Graph>, auth: &Arc<AuthContainer>, rate_limiter: &Arc<RateLimiter>) -> Arc<TraversalService> {
+        Arc::new(TraversalService { graph: graph.clone(), auth: auth.clone(), rate_limiter: rate_limiter.clone() })
+    }
+
+    // checks the token, then takes a token per vertex from the bucket of its user
+    fn acquire(&self, token: &String, schema: u32, vertices: usize) -> Result<(), String> {
+        check_read(&self.auth, &self.graph, token, schema).map_err(|e| format!("{:?}", e))?;
+        self.rate_limiter.acquire(RateClass::Traversal, &self.auth.client_of(token), vertices as u64)
+            .map_err(|limited| format!("{:?}", limited))
+    }
+}
+
+fn check_read<'a>(auth: &AuthContainer, graph: &Graph, token: &'a str, schema: u32) -> Result<(), AuthError> {
+    auth.check(token, Permission::Read, &Resource { namespace: graph.namespace().cloned(), schema: Some(schema) })
+}
+
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        &Value::I8(n) => Some(n as f64), &Value::I16(n) => Some(n as f64),
+        &Value::I32(n) => Some(n as f64), &Value::I64(n) => Some(n as f64),
+        &Value::U8(n) => Some(n as f64), &Value::U16(n) => Some(n as f64),
+        &Value::U32(n) => Some(n as f64), &Value::U64(n) => Some(n as f64),
+        &Value::F32(n) => Some(n as f64), &Value::F64(n) => Some(n),
+        _ => None
+    }
+}
+
+// Only ids travel back, so the opposite vertices are read only for filters that refer to them
+fn local_neighbours(
+    graph: &Arc<Graph>, vertex: Id, schema: u32, direction: EdgeDirection, filter: Option<String>, weight_field: Option<String>
+) -> impl Future<Item = Vec<RoutedNeighbour>, Error = String> {
+    let weight_key = weight_field.map(|field| key_hash(&field));
+    let options = TraverseOptions {
+        direction,
+        schemas: EdgeSchemas::Of(vec![schema]),
+        filter,
+        include_vertices: false,
+        ..TraverseOptions::default()
+    };
+    graph.traverse_neighbours(vertex, options)
+        .map_err(|e| format!("{:?}", e))
+        .and_then(|result| result.map_err(|e| format!("{:?}", e)))
+        .map(move |neighbours| neighbours.into_iter().map(|Neighbour { id, edge, .. }| {
+            let weight = match (weight_key, edge.get_data()) {
+                (Some(key), &Some(ref body)) => match body.data {
+                    Value::Map(ref map) => number(map.get_by_key_id(key)),
+                    _ => None
+                },
+                _ => None
+            };
+            RoutedNeighbour {
+                vertex: id,
+                edge: edge.get_data().as_ref().map(|cell| cell.id()),
+                weight
+            }
+        }).collect())
+}
+
+fn local_expand(graph: &Arc<Graph>, frontier: Vec<Id>, hop: Hop)
+    -> impl Future<Item = Vec<(Id, Vec<RoutedNeighbour>)>, Error = String>
+{
+    let expansions: Vec<_> = frontier.into_iter().map(|vertex| {
+        local_neighbours(graph, vertex, hop.schema, hop.direction, hop.filter.clone(), hop.weight_field.clone())
+            .map(move |neighbours| (vertex, neighbours))
+    }).collect();
+    future::join_all(expansions)
+}
+
+impl Service for TraversalService {
+    fn neighbours(&self, token: String, vertex: Id, schema: u32, direction: EdgeDirection, filter: Option<String>)
+        -> Box<Future<Item = Vec<RoutedNeighbour>, Error = String>>
+    {
+        if let Err(e) = self.acquire(&token, schema, 1) { return Box::new(future::err(e)); }
+        Box::new(local_neighbours(&self.graph, vertex, schema, direction, filter, None))
+    }
+
+    fn expand(&self, token: String, frontier: Vec<Id>, hop: Hop)
+        -> Box<Future<Item = Vec<(Id, Vec<RoutedNeighbour>)>, Error = String>>
+    {
+        if let Err(e) = self.acquire(&token, hop.schema, frontier.len()) { return Box::new(future::err(e)); }
+        Box::new(local_expand(&self.graph, frontier, hop))
+    }
+}
+dispatch_rpc_service_functions!(TraversalService);
+
+// Sends neighbourhood queries to the server owning the vertex cell.
+// Queries for cells owned by this server never leave the process, their token is checked here.
+pub struct TraversalRouter {
+    graph: Arc<Graph>,
+    neb_client: Arc<NebClient>,
+    auth: Arc<AuthContainer>,
+    local_address: String
+}
+
+impl TraversalRouter {
+    pub fn new<'a>(
+        graph: &Arc<Graph>, neb_client: &Arc<NebClient>, auth: &Arc<AuthContainer>, local_address: &'a str
+    ) -> Arc<TraversalRouter> {
+        Arc::new(TraversalRouter {
+            graph: graph.clone(),
+            neb_client: neb_client.clone(),
+            auth: auth.clone(),
+            local_address: local_address.to_string()
+        })
+    }
+
+    pub fn owner_of(&self, id: &Id) -> Option<String> {
+        self.neb_client.conshash.get_server(id.higher)
+    }
+
+    pub fn is_local(&self, owner: &str) -> bool {
+        owner == self.local_address
+    }
+
+    pub fn neighbours<'a>(&self, token: &'a str, vertex: Id, schema: u32, direction: EdgeDirection, filter: Option<String>)
+        -> impl Future<Item = Vec<RoutedNeighbour>, Error = RoutingError>
+    {
+        let owner = match self.owner_of(&vertex) {
+            Some(owner) => owner,
+            None => return future::Either::A(future::err(RoutingError::OwnerNotFound(vertex)))
+        };
+        if owner == self.local_address {
+            if let Err(e) = check_read(&self.auth, &self.graph, token, schema) {
+                return future::Either::A(future::err(RoutingError::AuthError(e)));
+            }
+            return future::Either::B(future::Either::A(
+                local_neighbours(&self.graph, vertex, schema, direction, filter, None)
+                    .map_err(RoutingError::LocalError)
+            ));
+        }
+        let rpc_client = match DEFAULT_CLIENT_POOL.get(&owner) {
+            Ok(client) => client,
+            Err(e) => return future::Either::A(future::err(RoutingError::RemoteError(format!("{:?}", e))))
+        };
+        let service = AsyncServiceClient::new(TRAVERSAL_SERVICE_ID, &rpc_client);
+        future::Either::B(future::Either::B(
+            service.neighbours(&token.to_string(), &vertex, &schema, &direction, &filter)
+                .then(|result| match result {
+                    Ok(Ok(neighbours)) => Ok(neighbours),
+                    Ok(Err(e)) => Err(RoutingError::RemoteError(e)),
+                    Err(e) => Err(RoutingError::RPCError(e))
+                })
+        ))
+    }
+
+    // Expands a whole frontier, one request per owning server, all servers in parallel
+    pub fn expand<'a>(&self, token: &'a str, frontier: Vec<Id>, hop: &Hop)
+        -> impl Future<Item = Vec<(Id, Vec<RoutedNeighbour>)>, Error = RoutingError>
+    {
+        self.expand_partitions(token, frontier, hop)
+            .map(|parts| parts.into_iter().flat_map(|part| part).collect())
+    }
+
+    // what each owning server answered for its part of the frontier
+    fn expand_partitions<'a>(&self, token: &'a str, frontier: Vec<Id>, hop: &Hop)
+        -> impl Future<Item = Vec<Vec<(Id, Vec<RoutedNeighbour>)>>, Error = RoutingError>
+    {
+        let mut partitions: HashMap<String, Vec<Id>> = HashMap::new();
+        for id in frontier {
+            match self.owner_of(&id) {
+                Some(owner) => partitions.entry(owner).or_insert_with(Vec::new).push(id),
+                None => return future::Either::A(future::err(RoutingError::OwnerNotFound(id)))
+            }
+        }
+        let mut requests: Vec<Box<Future<Item = Vec<(Id, Vec<RoutedNeighbour>)>, Error = RoutingError>>> = Vec::new();
+        for (owner, ids) in partitions {
+            if owner == self.local_address {
+                if let Err(e) = check_read(&self.auth, &self.graph, token, hop.schema) {
+                    return future::Either::A(future::err(RoutingError::AuthError(e)));
+                }
+                requests.push(Box::new(local_expand(&self.graph, ids, hop.clone())
+                    .map_err(RoutingError::LocalError)));
+                continue;
+            }
+            let rpc_client = match DEFAULT_CLIENT_POOL.get(&owner) {
+                Ok(client) => client,
+                Err(e) => return future::Either::A(future::err(RoutingError::RemoteError(format!("{:?}", e))))
+            };
+            let service = AsyncServiceClient::new(TRAVERSAL_SERVICE_ID, &rpc_client);
+            requests.push(Box::new(service.expand(&token.to_string(), &ids, hop)
+                .then(|result| match result {
+                    Ok(Ok(expanded)) => Ok(expanded),
+                    Ok(Err(e)) => Err(RoutingError::RemoteError(e)),
+                    Err(e) => Err(RoutingError::RPCError(e))
+                })));
+        }
+        future::Either::B(future::join_all(requests))
+    }
+
+    // Scatter-gather multi-hop traversal. Every hop partitions the frontier by cell owner,
+    // expands the partitions on their owners in parallel and merges the results here.
+    // Vertices already reached are not expanded again.
+    pub fn traverse(this: Arc<Self>, token: String, start: Vec<Id>, hops: Vec<Hop>)
+        -> impl Future<Item = TraversalResult, Error = RoutingError>
+    {
+        let visited: HashSet<Id> = start.iter().cloned().collect();
+        let initial = TraversalResult { frontiers: vec![start] };
+        ::futures::stream::iter_ok(hops)
+            .fold((initial, visited), move |(mut result, mut visited), hop| {
+                let frontier = result.frontiers.last().cloned().unwrap_or(Vec::new());
+                this.expand_partitions(&token, frontier, &hop).map(move |parts| {
+                    let next = merge_partitions(parts, &mut visited);
+                    result.frontiers.push(next);
+                    (result, visited)
+                })
+            })
+            .map(|(result, _)| result)
+    }
+}
+
+// The next frontier out of the answers of every partition, in the order they came. A vertex reached
+// from several partitions, or reached by an earlier hop, is taken once.
+pub fn merge_partitions(parts: Vec<Vec<(Id, Vec<RoutedNeighbour>)>>, visited: &mut HashSet<Id>) -> Vec<Id> {
+    let mut next = Vec::new();
+    for (_, neighbours) in parts.into_iter().flat_map(|part| part) {
+        for neighbour in neighbours {
+            if visited.insert(neighbour.vertex) {
+                next.push(neighbour.vertex);
+            }
+        }
+    }
+    next
+}
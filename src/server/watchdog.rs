@@ -0,0 +1,166 @@
+// Watches graph transactions that run too long. Every graph transaction registers here and
+// records each graph operation it runs, the watchdog thread logs the ones running past the limit
+// with the last schema and vertex they touched and can mark them for abort. A marked transaction
+// fails at its next graph operation, an operation already running is not interrupted.
+
+use neb::ram::types::Id;
+use neb::client::transaction::TxnError;
+use parking_lot::{Mutex, RwLock};
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use server::metrics;
+
+pub static WATCHDOG_TARGET: &'static str = "morpheus::watchdog";
+
+fn default_max_duration_ms() -> u64 { 30000 }
+fn default_check_interval_ms() -> u64 { 1000 }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WatchdogOptions {
+    #[serde(default = "default_max_duration_ms")]
+    pub max_duration_ms: u64,
+    #[serde(default = "default_check_interval_ms")]
+    pub check_interval_ms: u64,
+    // abort transactions over the limit instead of only logging them
+    #[serde(default)]
+    pub abort: bool
+}
+
+impl Default for WatchdogOptions {
+    fn default() -> WatchdogOptions {
+        WatchdogOptions {
+            max_duration_ms: default_max_duration_ms(),
+            check_interval_ms: default_check_interval_ms(),
+            abort: false
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Operation {
+    name: &'static str,
+    schema: Option<u32>,
+    vertex: Id
+}
+
+pub struct TxnWatch {
+    id: usize,
+    started: Instant,
+    last_op: Mutex<Option<Operation>>,
+    reported: AtomicBool,
+    abort: AtomicBool
+}
+
+impl TxnWatch {
+    // called at the start of every graph operation, fails once the watchdog aborted the transaction
+    pub fn touch(&self, name: &'static str, schema: Option<u32>, vertex: Id) -> Result<(), TxnError> {
+        *self.last_op.lock() = Some(Operation { name, schema, vertex });
+        if self.is_aborted() { Err(TxnError::Aborted(None)) } else { Ok(()) }
+    }
+    pub fn is_aborted(&self) -> bool {
+        self.abort.load(Ordering::Relaxed)
+    }
+    // the txn= of the watchdog log lines
+    pub fn id(&self) -> usize {
+        self.id
+    }
+}
+
+// unregisters the transaction when dropped
+pub struct WatchGuard {
+    watch: Arc<TxnWatch>
+}
+
+impl WatchGuard {
+    pub fn watch(&self) -> &Arc<TxnWatch> {
+        &self.watch
+    }
+}
+
+impl Drop for WatchGuard {
+    fn drop(&mut self) {
+        ACTIVE.lock().remove(&self.watch.id);
+    }
+}
+
+static NEXT_TXN_ID: AtomicUsize = ATOMIC_USIZE_INIT;
+
+lazy_static! {
+    static ref OPTIONS: RwLock<WatchdogOptions> = RwLock::new(WatchdogOptions::default());
+    static ref ACTIVE: Mutex<HashMap<usize, Arc<TxnWatch>>> = Mutex::new(HashMap::new());
+}
+
+pub fn configure(options: WatchdogOptions) {
+    *OPTIONS.write() = options;
+}
+
+pub fn register() -> WatchGuard {
+    let watch = Arc::new(TxnWatch {
+        id: NEXT_TXN_ID.fetch_add(1, Ordering::Relaxed),
+        started: Instant::now(),
+        last_op: Mutex::new(None),
+        reported: AtomicBool::new(false),
+        abort: AtomicBool::new(false)
+    });
+    ACTIVE.lock().insert(watch.id, watch.clone());
+    WatchGuard { watch }
+}
+
+pub fn active_count() -> usize {
+    ACTIVE.lock().len()
+}
+
+// one pass over running transactions, returns how many were over the limit
+pub fn check() -> usize {
+    let options = OPTIONS.read().clone();
+    let active: Vec<Arc<TxnWatch>> = ACTIVE.lock().values().cloned().collect();
+    check_watches(&active, &options)
+}
+
+// the pass of check over the given transactions only
+pub fn check_watches(watches: &[Arc<TxnWatch>], options: &WatchdogOptions) -> usize {
+    let limit = Duration::from_millis(options.max_duration_ms);
+    let overdue: Vec<&Arc<TxnWatch>> = watches.iter()
+        .filter(|watch| watch.started.elapsed() >= limit)
+        .collect();
+    for watch in &overdue {
+        if !watch.reported.swap(true, Ordering::Relaxed) {
+            let elapsed = watch.started.elapsed();
+            match *watch.last_op.lock() {
+                Some(op) => warn!(
+                    target: WATCHDOG_TARGET,
+                    "txn={} running for {}s, last op={} schema={:?} vertex={},{}",
+                    watch.id, elapsed.as_secs(), op.name, op.schema, op.vertex.higher, op.vertex.lower
+                ),
+                None => warn!(
+                    target: WATCHDOG_TARGET,
+                    "txn={} running for {}s before its first graph operation", watch.id, elapsed.as_secs()
+                )
+            }
+        }
+        if options.abort && !watch.abort.swap(true, Ordering::Relaxed) {
+            metrics::WATCHDOG_ABORTS.inc();
+            warn!(target: WATCHDOG_TARGET, "txn={} will be aborted at its next operation", watch.id);
+        }
+    }
+    overdue.len()
+}
+
+pub fn start(running: Arc<AtomicBool>) -> thread::JoinHandle<()> {
+    thread::Builder::new()
+        .name("morpheus-watchdog".to_string())
+        .spawn(move || {
+            while running.load(Ordering::Relaxed) {
+                let interval = OPTIONS.read().check_interval_ms;
+                thread::sleep(Duration::from_millis(interval));
+                check();
+            }
+            debug!("Transaction watchdog stopped");
+        })
+        .unwrap()
+}
@@ -4,11 +4,34 @@ use graph::*;
 use graph::edge::*;
 use graph::vertex::*;
 use server::schema::{MorpheusSchema, SchemaError, EMPTY_FIELDS};
+use server::traversal::{TraversalRouter, Hop};
+use graph::placement::{Placement, PlacementPolicy};
+use graph::retry::RetryPolicy;
+use graph::session::Session;
+use graph::similarity::SimilarityMetric;
+use graph::mem::MemGraph;
+use graph::multi_schema::EdgeSchemas;
+use graph::traverse::{TraverseOptions, TraverseSort, SortOrder};
+use graph::geo::{self, GeoPoint};
+use graph::vector::{self, HnswOptions, VectorMetric, VectorError};
+use graph::computed::{ComputedField, ComputedMode};
+use graph::fsck::{FsckOptions, Inconsistency};
+use graph::columnar::ColumnData;
+use graph::changes::ChangeKind;
+use analytics::centrality::{self, CentralityOptions};
+use analytics::community::{self, CommunityAlgorithm, CommunityOptions};
+use analytics::AnalyticsError;
+use analytics::pregel::{self, PregelOptions};
+use analytics::programs::{PageRank, ConnectedComponents, ShortestPaths};
+use query::symbols::udf;
+use neb::client::transaction::{Transaction, TxnError};
+use std::time::Duration;
 use neb::ram::schema::Field;
-use neb::ram::types::{TypeId, Value, Map};
+use neb::ram::types::{TypeId, Value, Map, Id};
 use neb::ram::cell::Cell;
 use env_logger;
-use futures::Future;
+use petgraph;
+use futures::{Future, Stream};
 
 #[test]
 pub fn schemas() {
@@ -237,5 +260,2820 @@ pub fn relationship() {
         graph.neighbourhoods::<_, _, String>
         (&jeanette, "spouse", EdgeDirection::Undirected, &None)
             .wait().unwrap().unwrap());
+    assert!(graph.vertex_exists(&jeanette).wait().unwrap());
+    assert!(graph.has_edge(&morgan_freeman, "spouse", &jeanette).wait().unwrap().unwrap());
+    assert!(graph.has_edge(&jeanette, "spouse", &morgan_freeman).wait().unwrap().unwrap());
+    assert!(graph.has_edge(&morgan_freeman, "acted-in", &batman_begins).wait().unwrap().unwrap());
+    assert!(!graph.has_edge(&batman_begins, "acted-in", &morgan_freeman).wait().unwrap().unwrap());
+    let fetched = graph.vertices_by(vec![
+        morgan_freeman.cell.id(), Id::unit_id(), jeanette.cell.id()
+    ]).wait().unwrap();
+    assert_eq!(fetched.len(), 3);
+    assert_eq!(fetched[0].as_ref().unwrap()["name"].String().unwrap(), morgan_freeman_name);
+    assert!(fetched[1].is_none());
+    assert_eq!(fetched[2].as_ref().unwrap()["name"].String().unwrap(), jeanette_name);
+    let movies = graph.scan_vertices::<_, String>("movie", &None, Some(vec!["name".to_string()]))
+        .collect().wait().unwrap();
+    assert_eq!(movies.len(), 4);
 }
 
+// the movies and people of relationship, linked the same way
+struct Movies {
+    morgan_freeman: Vertex,
+    batman_begins: Vertex,
+    oblivion: Vertex,
+    jeanette: Vertex,
+    acted_in: u32
+}
+
+fn movies(graph: &Graph) -> Movies {
+    graph.new_vertex_group(MorpheusSchema::new("people", Some(&vec!["name".to_string()]), &vec! [
+        Field::new("name", TypeId::String as u32, false, false, None)
+    ], true)).wait().unwrap();
+    graph.new_vertex_group(MorpheusSchema::new("movie", Some(&vec!["name".to_string()]), &vec! [
+        Field::new("name", TypeId::String as u32, false, false, None),
+        Field::new("year", TypeId::U32 as u32, false, false, None)
+    ], true)).wait().unwrap();
+    let acted_in = graph.new_edge_group(MorpheusSchema::new("acted-in", None, &vec! [
+        Field::new("role", TypeId::String as u32, false, false, None)
+    ], true), EdgeAttributes::new(EdgeType::Directed, true)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("spouse", None, &EMPTY_FIELDS, false),
+        EdgeAttributes::new(EdgeType::Undirected, false)
+    ).wait().unwrap();
+    let morgan_freeman = graph.new_vertex("people", data_map!{ name: "Morgan Freeman" }).wait().unwrap();
+    let jeanette = graph.new_vertex("people", data_map!{ name: "Jeanette Adair Bradshaw" }).wait().unwrap();
+    let new_movie = |name: &str, year: u32| graph.new_vertex("movie", data_map!{ name: name, year: year }).wait().unwrap();
+    let batman_begins = new_movie("Batman Begins", 2005);
+    let the_dark_knight = new_movie("The Dark Knight", 2008);
+    let the_dark_knight_rises = new_movie("The Dark Knight Rises", 2012);
+    let oblivion = new_movie("Oblivion", 2010);
+    for &(movie, role) in &[(&batman_begins, "Lucius Fox"), (&the_dark_knight, "Lucius Fox"),
+                            (&the_dark_knight_rises, "Lucius Fox"), (&oblivion, "Beech")] {
+        graph.link(&morgan_freeman, "acted-in", movie, Some(data_map!{ role: role })).wait().unwrap().unwrap();
+    }
+    graph.link(&morgan_freeman, "spouse", &jeanette, None).wait().unwrap().unwrap();
+    Movies { morgan_freeman, batman_begins, oblivion, jeanette, acted_in }
+}
+
+fn review_schema(graph: &Graph) {
+    graph.new_vertex_group(MorpheusSchema::new("review", None, &vec! [
+        Field::new("movie", TypeId::String as u32, false, false, None)
+    ], true).with_placement(PlacementPolicy::PartitionByField("movie".to_string()))).wait().unwrap();
+}
+
+#[test]
+pub fn read_only_replica() {
+    let server = start_server(4068, "read_only_replica");
+    let graph = &server.graph;
+    let Movies { morgan_freeman, batman_begins, jeanette, .. } = movies(graph);
+    graph.set_read_only(true);
+    assert!(graph.vertex_exists(&jeanette).wait().unwrap());
+    match graph.link(&morgan_freeman, "acted-in", &batman_begins, None).wait().unwrap() {
+        Err(LinkVerticesError::ReadOnly) => {},
+        other => panic!("{:?}", other)
+    }
+    match graph.update_vertex_with(&jeanette, UpdateOptions::default(), |vertex| Some(vertex)).wait().unwrap() {
+        Err(UpdateError::ReadOnly) => {},
+        other => panic!("{:?}", other)
+    }
+    match graph.compact_adjacency(&jeanette).wait().unwrap() {
+        Err(id_list::IdListError::ReadOnly) => {},
+        other => panic!("{:?}", other)
+    }
+    let (jeanette_id, morgan_freeman_id) = (jeanette.cell.id(), morgan_freeman.cell.id());
+    match graph.graph_transaction(move |txn| txn.unlink(&jeanette_id, "spouse", &morgan_freeman_id)).wait().unwrap() {
+        Err(EdgeError::ReadOnly) => {},
+        other => panic!("{:?}", other)
+    }
+    graph.set_read_only(false);
+    assert!(graph.has_edge(&morgan_freeman, "spouse", &jeanette).wait().unwrap().unwrap());
+}
+
+#[test]
+pub fn traversal_routing() {
+    let server = start_server(4069, "traversal_routing");
+    let Movies { morgan_freeman, jeanette, .. } = movies(&server.graph);
+    let routed = server.router.neighbours(
+        "", morgan_freeman.cell.id(), server.schema_container.id_from_name("spouse").unwrap(),
+        EdgeDirection::Undirected, None
+    ).wait().unwrap();
+    assert_eq!(routed.len(), 1);
+    assert_eq!(routed[0].vertex, jeanette.cell.id());
+}
+
+#[test]
+pub fn traversal_rpc_neighbours() {
+    use bifrost::rpc::DEFAULT_CLIENT_POOL;
+    use server::traversal::{AsyncServiceClient, TRAVERSAL_SERVICE_ID};
+    let server = start_server(4100, "traversal_rpc_neighbours");
+    let Movies { morgan_freeman, batman_begins, acted_in, .. } = movies(&server.graph);
+    udf::register("test-lucius", |args| match args.get(0) {
+        Some(&Value::Map(_)) => Ok(Value::Bool(args[0]["role"] == Value::String("Lucius Fox".to_string()))),
+        _ => Err("test-lucius takes the edge".to_string())
+    }).unwrap();
+    udf::register("test-before-2009", |args| match args.get(0) {
+        Some(&Value::Map(_)) => Ok(Value::Bool(match args[0]["year"] { Value::U32(year) => year < 2009, _ => false })),
+        _ => Err("test-before-2009 takes the vertex".to_string())
+    }).unwrap();
+    // the service is reached the way routers of other servers reach it
+    let client = DEFAULT_CLIENT_POOL.get(&"127.0.0.1:4100".to_string()).unwrap();
+    let service = AsyncServiceClient::new(TRAVERSAL_SERVICE_ID, &client);
+    let morgan_id = morgan_freeman.cell.id();
+    let neighbours = |filter: Option<&str>| service.neighbours(
+        &String::new(), &morgan_id, &acted_in, &EdgeDirection::Outbound, &filter.map(|f| f.to_string())
+    ).wait().unwrap().unwrap();
+    let all = neighbours(None);
+    assert_eq!(all.len(), 4);
+    // acted-in has bodies, their ids come back with the vertices
+    assert!(all.iter().all(|neighbour| neighbour.edge.is_some()));
+    assert!(all.iter().any(|neighbour| neighbour.vertex == batman_begins.cell.id()));
+    assert_eq!(neighbours(Some("(test-lucius edge)")).len(), 3);
+    // the owner reads the opposite vertices for filters on them
+    let early = neighbours(Some("(test-before-2009 vertex)"));
+    assert_eq!(early.len(), 2);
+    assert!(early.iter().any(|neighbour| neighbour.vertex == batman_begins.cell.id()));
+    match service.neighbours(&String::new(), &morgan_id, &acted_in, &EdgeDirection::Outbound, &Some("(".to_string())).wait() {
+        Ok(Err(_)) => {},
+        other => panic!("{:?}", other)
+    }
+}
+
+#[test]
+pub fn distributed_traversal() {
+    let server = start_server(4070, "distributed_traversal");
+    let Movies { morgan_freeman, acted_in, .. } = movies(&server.graph);
+    let traversal = TraversalRouter::traverse(server.router.clone(), String::new(), vec![morgan_freeman.cell.id()], vec![
+        Hop { schema: acted_in, direction: EdgeDirection::Outbound, filter: None, weight_field: None },
+        Hop { schema: acted_in, direction: EdgeDirection::Inbound, filter: None, weight_field: None }
+    ]).wait().unwrap();
+    assert_eq!(traversal.frontiers.len(), 3);
+    assert_eq!(traversal.frontiers[1].len(), 4);
+    assert!(!traversal.frontiers[2].contains(&morgan_freeman.cell.id()));
+}
+
+#[test]
+pub fn scatter_gather_expand() {
+    use bifrost::rpc::DEFAULT_CLIENT_POOL;
+    use server::traversal::{AsyncServiceClient, TRAVERSAL_SERVICE_ID, merge_partitions};
+    use std::collections::HashSet;
+    let server = start_server(4101, "scatter_gather_expand");
+    let Movies { morgan_freeman, batman_begins, oblivion, acted_in, .. } = movies(&server.graph);
+    let client = DEFAULT_CLIENT_POOL.get(&"127.0.0.1:4101".to_string()).unwrap();
+    let service = AsyncServiceClient::new(TRAVERSAL_SERVICE_ID, &client);
+    let cast = Hop { schema: acted_in, direction: EdgeDirection::Inbound, filter: None, weight_field: None };
+    let (batman_id, oblivion_id, morgan_id) = (batman_begins.cell.id(), oblivion.cell.id(), morgan_freeman.cell.id());
+    // each owner expands its part of the frontier, one entry per frontier vertex
+    let expand = |frontier: Vec<Id>| service.expand(&String::new(), &frontier, &cast).wait().unwrap().unwrap();
+    let batman_part = expand(vec![batman_id]);
+    let oblivion_part = expand(vec![oblivion_id]);
+    assert_eq!(batman_part.len(), 1);
+    assert_eq!(batman_part[0].0, batman_id);
+    assert_eq!(batman_part[0].1.iter().map(|n| n.vertex).collect::<Vec<_>>(), vec![morgan_id]);
+    assert_eq!(oblivion_part[0].1.iter().map(|n| n.vertex).collect::<Vec<_>>(), vec![morgan_id]);
+    // both partitions reach the same actor, the merged frontier has it once
+    let mut visited: HashSet<Id> = vec![batman_id, oblivion_id].into_iter().collect();
+    let merged = merge_partitions(vec![batman_part.clone(), oblivion_part.clone()], &mut visited);
+    assert_eq!(merged, vec![morgan_id]);
+    // vertices reached before are left out
+    assert!(merge_partitions(vec![batman_part, oblivion_part], &mut visited).is_empty());
+    // the router gathers the same entries from the owners of the whole frontier
+    let gathered = server.router.expand("", vec![batman_id, oblivion_id], &cast).wait().unwrap();
+    assert_eq!(gathered.len(), 2);
+    assert!(gathered.iter().all(|&(_, ref neighbours)| neighbours.iter().map(|n| n.vertex).collect::<Vec<_>>() == vec![morgan_id]));
+}
+
+#[test]
+pub fn placement_hints() {
+    let server = start_server(4071, "placement_hints");
+    let graph = &server.graph;
+    let Movies { morgan_freeman, .. } = movies(graph);
+    review_schema(graph);
+    let review_a = graph.new_vertex("review", data_map!{ movie: "Oblivion" }).wait().unwrap();
+    let review_b = graph.new_vertex("review", data_map!{ movie: "Oblivion" }).wait().unwrap();
+    assert_eq!(review_a.cell.id().higher, review_b.cell.id().higher);
+    assert_ne!(review_a.cell.id(), review_b.cell.id());
+    let colocated = graph.new_vertex_with_placement(
+        "review", data_map!{ movie: "Batman Begins" }, Placement::ColocateWith(morgan_freeman.cell.id())
+    ).wait().unwrap();
+    assert_eq!(colocated.cell.id().higher, morgan_freeman.cell.id().higher);
+}
+
+#[test]
+pub fn read_your_writes() {
+    let server = start_server(4072, "read_your_writes");
+    let graph = &server.graph;
+    review_schema(graph);
+    let session = Session::new(graph);
+    let written = session.new_vertex("review", data_map!{ movie: "Oblivion" }).wait().unwrap();
+    session.update_vertex(&written, |mut review| {
+        review["movie"] = Value::String(String::from("Batman Begins"));
+        Some(review)
+    }).wait().unwrap();
+    assert_eq!(
+        session.vertex_by(&written).wait().unwrap().unwrap()["movie"].String().unwrap(),
+        "Batman Begins");
+    session.remove_vertex(&written).wait().unwrap().unwrap();
+    assert!(session.vertex_by(&written).wait().unwrap().is_none());
+}
+
+#[test]
+pub fn snapshot_reads() {
+    let server = start_server(4073, "snapshot_reads");
+    let graph = &server.graph;
+    let Movies { morgan_freeman, jeanette, .. } = movies(graph);
+    let morgan_freeman_id = morgan_freeman.cell.id();
+    let (movies_acted, spouses) = graph.read_transaction(move |txn| {
+        let movies_acted = txn.edges(&morgan_freeman_id, "acted-in", EdgeDirection::Outbound, &None)?.unwrap().len();
+        let spouses = txn.neighbourhoods(&morgan_freeman_id, "spouse", EdgeDirection::Undirected, &None)?.unwrap();
+        Ok((movies_acted, spouses))
+    }).wait().unwrap();
+    assert_eq!(movies_acted, 4);
+    assert_eq!(spouses.len(), 1);
+    assert_eq!(spouses[0].0.cell.id(), jeanette.cell.id());
+}
+
+#[test]
+pub fn savepoints() {
+    let server = start_server(4074, "savepoints");
+    let graph = &server.graph;
+    review_schema(graph);
+    let (kept, rolled_back) = graph.graph_transaction(move |txn| {
+        let kept = txn.new_vertex("review", data_map!{ movie: "Oblivion" })?.unwrap();
+        let savepoint = txn.savepoint();
+        let rolled_back = txn.new_vertex("review", data_map!{ movie: "Oblivion" })?.unwrap();
+        txn.rollback_to(&savepoint)?.unwrap();
+        Ok((kept.cell.id(), rolled_back.cell.id()))
+    }).wait().unwrap();
+    assert!(graph.vertex_exists(kept).wait().unwrap());
+    assert!(!graph.vertex_exists(rolled_back).wait().unwrap());
+}
+
+#[test]
+pub fn optimistic_locking() {
+    let server = start_server(4075, "optimistic_locking");
+    let graph = &server.graph;
+    review_schema(graph);
+    let review = graph.new_vertex("review", data_map!{ movie: "Oblivion" }).wait().unwrap();
+    assert_eq!(review.version(), 0);
+    let check_version = UpdateOptions { check_version: Some(0) };
+    graph.update_vertex_with(&review, check_version, |review| Some(review)).wait().unwrap().unwrap();
+    assert_eq!(graph.vertex_by(&review).wait().unwrap().unwrap().version(), 1);
+    match graph.update_vertex_with(&review, check_version, |review| Some(review)).wait().unwrap() {
+        Err(UpdateError::VersionMismatch { expected: 0, actual: 1 }) => {},
+        other => panic!("expected a version mismatch, got {:?}", other)
+    }
+}
+
+#[test]
+pub fn neighbour_sets() {
+    let server = start_server(4076, "neighbour_sets");
+    let graph = &server.graph;
+    let Movies { morgan_freeman, jeanette, .. } = movies(graph);
+    let (morgan_id, jeanette_id) = (morgan_freeman.cell.id(), jeanette.cell.id());
+    assert!(graph.common_neighbours(&morgan_id, &jeanette_id, "spouse", EdgeDirection::Undirected)
+        .wait().unwrap().unwrap().is_empty());
+    assert_eq!(graph.neighbours_union(&morgan_id, &jeanette_id, "spouse", EdgeDirection::Undirected)
+        .wait().unwrap().unwrap(), vec![jeanette_id, morgan_id]);
+    assert_eq!(graph.neighbours_difference(&morgan_id, &jeanette_id, "spouse", EdgeDirection::Undirected)
+        .wait().unwrap().unwrap(), vec![jeanette_id]);
+}
+
+#[test]
+pub fn jaccard_similarity() {
+    let server = start_server(4077, "jaccard_similarity");
+    let graph = &server.graph;
+    let Movies { batman_begins, .. } = movies(graph);
+    let similar = graph.similar_vertices(&batman_begins, "acted-in", EdgeDirection::Inbound, SimilarityMetric::Jaccard, 2)
+        .wait().unwrap().unwrap();
+    assert_eq!(similar.len(), 2);
+    assert!(similar.iter().all(|&(id, score)| id != batman_begins.cell.id() && score == 1.0));
+}
+
+#[test]
+pub fn subgraph_extraction() {
+    let server = start_server(4078, "subgraph_extraction");
+    let graph = &server.graph;
+    let Movies { morgan_freeman, .. } = movies(graph);
+    let around_morgan = graph.subgraph::<_, _, String>(vec![&morgan_freeman], vec!["acted-in", "spouse"], 1, &None)
+        .wait().unwrap().unwrap();
+    assert_eq!(around_morgan.index_of(&morgan_freeman.cell.id()), Some(0));
+    assert_eq!(around_morgan.vertices.len(), 6);
+    assert_eq!(around_morgan.edges.len(), 5);
+    assert_eq!(around_morgan.incident_edges(0).count(), 5);
+}
+
+#[test]
+pub fn mem_graph_petgraph() {
+    let server = start_server(4079, "mem_graph_petgraph");
+    let graph = &server.graph;
+    let Movies { morgan_freeman, .. } = movies(graph);
+    let around_morgan = graph.subgraph::<_, _, String>(vec![&morgan_freeman], vec!["acted-in", "spouse"], 1, &None)
+        .wait().unwrap().unwrap();
+    let petgraph: petgraph::Graph<Vertex, Edge> = MemGraph::from(around_morgan).into();
+    assert_eq!(petgraph.node_count(), 6);
+    assert_eq!(petgraph.edge_count(), 5);
+    assert_eq!(petgraph::algo::connected_components(&petgraph), 1);
+    let movies = MemGraph::from_scans(graph, vec!["movie"], vec!["acted-in"]).wait().unwrap();
+    assert_eq!(movies.vertices().len(), 4);
+    // morgan was not loaded, so none of the acted-in edges are kept
+    assert!(movies.edges().is_empty());
+}
+
+// morgan at the centre with his movies and his spouse around him
+fn star(graph: &Graph, morgan_freeman: &Vertex) -> MemGraph {
+    MemGraph::from(graph.subgraph::<_, _, String>(vec![morgan_freeman], vec!["acted-in", "spouse"], 1, &None)
+        .wait().unwrap().unwrap())
+}
+
+#[test]
+pub fn centrality_jobs() {
+    let server = start_server(4080, "centrality_jobs");
+    let graph = &server.graph;
+    let Movies { morgan_freeman, .. } = movies(graph);
+    let star = star(graph, &morgan_freeman);
+    let undirected = CentralityOptions { directed: false, ..CentralityOptions::default() };
+    let betweenness = centrality::betweenness(&star, &undirected);
+    assert_eq!(betweenness[0], 1.0);
+    assert!(betweenness[1..].iter().all(|score| *score == 0.0));
+    assert_eq!(centrality::closeness(&star, &undirected)[0], 1.0);
+}
+
+#[test]
+pub fn community_detection() {
+    let server = start_server(4081, "community_detection");
+    let graph = &server.graph;
+    let Movies { morgan_freeman, .. } = movies(graph);
+    let star = star(graph, &morgan_freeman);
+    let louvain = CommunityOptions { algorithm: CommunityAlgorithm::Louvain, ..CommunityOptions::default() };
+    assert!(community::detect(&star, &louvain).iter().all(|id| *id == morgan_freeman.cell.id()));
+}
+
+#[test]
+pub fn pregel_programs() {
+    let server = start_server(4082, "pregel_programs");
+    let graph = &server.graph;
+    let Movies { morgan_freeman, jeanette, .. } = movies(graph);
+    let star = star(graph, &morgan_freeman);
+    let undirected_steps = PregelOptions { directed: false, ..PregelOptions::default() };
+    let hops = pregel::run_program(&star, ShortestPaths { source: jeanette.cell.id() }, &undirected_steps);
+    assert!(hops.converged);
+    assert_eq!(hops.states[0], Some(1));
+    assert!(hops.states.iter().all(|d| d.map(|d| d <= 2).unwrap_or(false)));
+    let components = pregel::run_program(&star, ConnectedComponents, &undirected_steps);
+    assert!(components.states.iter().all(|c| *c == components.states[0]));
+    let ranks = pregel::run_program(&star, PageRank::default(), &undirected_steps);
+    assert!(ranks.states[1..].iter().all(|rank| *rank < ranks.states[0]));
+}
+
+#[test]
+pub fn distributed_pregel() {
+    use analytics::pregel::{Context, DistributedProgram, VertexProgram};
+    use server::pregel::PregelService;
+    use std::collections::HashMap;
+    #[derive(Serialize, Deserialize)]
+    struct Unregistered;
+    impl VertexProgram for Unregistered {
+        type State = ();
+        type Message = ();
+        fn init(&self, _vertex: &Vertex) {}
+        fn compute(&self, ctx: &mut Context<()>, _state: &mut (), _messages: Vec<()>) { ctx.vote_to_halt(); }
+    }
+    impl DistributedProgram for Unregistered {
+        const NAME: &'static str = "unregistered";
+    }
+    let server = start_server(4097, "distributed_pregel");
+    let graph = &server.graph;
+    let Movies { morgan_freeman, .. } = movies(graph);
+    let undirected_steps = PregelOptions { directed: false, ..PregelOptions::default() };
+    let vertex_schemas = || vec!["people", "movie"];
+    let edge_schemas = || vec!["acted-in", "spouse"];
+    // the ranks computed over shards match the ones computed in this process
+    let local: HashMap<Id, f64> = pregel::run(graph, vertex_schemas(), edge_schemas(), PageRank::default(), undirected_steps.clone())
+        .collect().wait().unwrap().into_iter().collect();
+    let distributed: HashMap<Id, f64> = PregelService::run(
+        server.pregel.clone(), "", vertex_schemas(), edge_schemas(), PageRank::default(), undirected_steps.clone()
+    ).collect().wait().unwrap().into_iter().collect();
+    assert_eq!(distributed.len(), 6);
+    assert_eq!(distributed.len(), local.len());
+    for (id, rank) in &local {
+        assert!((distributed[id] - rank).abs() < 1e-9);
+    }
+    let components = PregelService::run(
+        server.pregel.clone(), "", vertex_schemas(), edge_schemas(), ConnectedComponents, undirected_steps.clone()
+    ).collect().wait().unwrap();
+    assert!(components.iter().all(|&(_, component)| component == components[0].1));
+    let hops: HashMap<Id, Option<u64>> = PregelService::run(
+        server.pregel.clone(), "", vertex_schemas(), edge_schemas(),
+        ShortestPaths { source: morgan_freeman.cell.id() }, undirected_steps.clone()
+    ).collect().wait().unwrap().into_iter().collect();
+    assert_eq!(hops[&morgan_freeman.cell.id()], Some(0));
+    assert!(hops.values().all(|hops| *hops == Some(0) || *hops == Some(1)));
+    // programs run on servers knowing them by name only
+    match PregelService::run(server.pregel.clone(), "", vertex_schemas(), edge_schemas(), Unregistered, undirected_steps)
+        .collect().wait() {
+        Err(AnalyticsError::DistributedError(_)) => {},
+        other => panic!("{:?}", other.map(|states| states.len()))
+    }
+}
+
+#[test]
+pub fn both_directions() {
+    let server = start_server(4083, "both_directions");
+    let graph = &server.graph;
+    let Movies { morgan_freeman, batman_begins, jeanette, .. } = movies(graph);
+    assert_eq!(graph.degree(&batman_begins, "acted-in", EdgeDirection::Both).wait().unwrap().unwrap(), 1);
+    assert_eq!(
+        graph.neighbourhoods::<_, _, String>(&morgan_freeman, "acted-in", EdgeDirection::Both, &None).wait().unwrap().unwrap().len(),
+        graph.degree(&morgan_freeman, "acted-in", EdgeDirection::Outbound).wait().unwrap().unwrap()
+    );
+    // on an undirected schema Both reads the undirected list
+    assert_eq!(EdgeDirection::Both.as_field(), None);
+    assert_eq!(graph.degree(&jeanette, "spouse", EdgeDirection::Both).wait().unwrap().unwrap(), 1);
+    let spouses = graph.neighbourhoods::<_, _, String>(&jeanette, "spouse", EdgeDirection::Both, &None).wait().unwrap().unwrap();
+    assert_eq!(spouses.len(), 1);
+    assert_eq!(spouses[0].0.cell.id(), morgan_freeman.cell.id());
+}
+
+#[test]
+pub fn multiple_edge_schemas() {
+    let server = start_server(4084, "multiple_edge_schemas");
+    let graph = &server.graph;
+    let Movies { morgan_freeman, .. } = movies(graph);
+    let related = graph.degree(&morgan_freeman, "acted-in", EdgeDirection::Outbound).wait().unwrap().unwrap() +
+        graph.degree(&morgan_freeman, "spouse", EdgeDirection::Undirected).wait().unwrap().unwrap();
+    assert_eq!(
+        graph.edges_of_any_schema::<_, String>(&morgan_freeman, EdgeDirection::Both, &None).wait().unwrap().unwrap().len(),
+        related
+    );
+    assert_eq!(
+        graph.neighbourhoods_of_schemas::<_, _, String>(&morgan_freeman, vec!["acted-in", "spouse"], EdgeDirection::Both, &None)
+            .wait().unwrap().unwrap().len(),
+        related
+    );
+    assert!(graph.edges_of_schemas::<_, _, String>(&morgan_freeman, vec!["spouse"], EdgeDirection::Outbound, &None)
+        .wait().unwrap().unwrap().is_empty());
+}
+
+#[test]
+pub fn incident_schemas() {
+    let server = start_server(4085, "incident_schemas");
+    let graph = &server.graph;
+    let Movies { morgan_freeman, acted_in, .. } = movies(graph);
+    let incident = graph.incident_edge_schemas(&morgan_freeman).wait().unwrap().unwrap();
+    assert!(incident.iter().any(|s| s.schema == acted_in && s.direction == EdgeDirection::Outbound && s.count > 0));
+}
+
+#[test]
+pub fn traverse_with_projection() {
+    use query;
+    let server = start_server(4086, "traverse_with_projection");
+    let graph = &server.graph;
+    let Movies { morgan_freeman, acted_in, .. } = movies(graph);
+    let related = graph.degree(&morgan_freeman, "acted-in", EdgeDirection::Outbound).wait().unwrap().unwrap() +
+        graph.degree(&morgan_freeman, "spouse", EdgeDirection::Undirected).wait().unwrap().unwrap();
+    let latest_year = graph.neighbourhoods::<_, _, String>(&morgan_freeman, "acted-in", EdgeDirection::Outbound, &None)
+        .wait().unwrap().unwrap().iter().filter_map(|&(ref movie, _)| match movie["year"] { Value::U32(year) => Some(year), _ => None }).max();
+    let latest = graph.traverse_neighbours(&morgan_freeman, TraverseOptions {
+        direction: EdgeDirection::Outbound,
+        schemas: EdgeSchemas::Of(vec![acted_in]),
+        projection: Some(vec!["year".to_string()]),
+        sort: Some(TraverseSort::VertexField("year".to_string(), SortOrder::Descending)),
+        limit: Some(1),
+        ..TraverseOptions::default()
+    }).wait().unwrap().unwrap();
+    assert_eq!(latest.len(), 1);
+    let latest_movie = latest[0].vertex.as_ref().unwrap();
+    assert_eq!(Some(latest_movie["year"].clone()), latest_year.map(Value::U32));
+    assert_eq!(latest_movie["name"], Value::Null);
+    let edges_only = graph.traverse_neighbours(&morgan_freeman, TraverseOptions {
+        include_vertices: false, ..TraverseOptions::default()
+    }).wait().unwrap().unwrap();
+    assert_eq!(edges_only.len(), related);
+    assert!(edges_only.iter().all(|n| n.vertex.is_none()));
+    // a filter on the vertex has it read even when it is not returned
+    udf::register("test-since-2010", |args| match args.get(0) {
+        Some(&Value::Map(_)) => Ok(Value::Bool(match args[0]["year"] { Value::U32(year) => year >= 2010, _ => false })),
+        _ => Err("test-since-2010 takes the vertex".to_string())
+    }).unwrap();
+    let parsed = |filter: &str| query::parse_optional_expr(&Some(filter)).unwrap();
+    assert!(query::refers_to_vertex(&parsed("(test-since-2010 vertex)")));
+    assert!(!query::refers_to_vertex(&parsed("(test-is-id vertex_id)")));
+    let recent = graph.traverse_neighbours(&morgan_freeman, TraverseOptions {
+        direction: EdgeDirection::Outbound,
+        schemas: EdgeSchemas::Of(vec![acted_in]),
+        filter: Some("(test-since-2010 vertex)".to_string()),
+        include_vertices: false,
+        ..TraverseOptions::default()
+    }).wait().unwrap().unwrap();
+    assert_eq!(recent.len(), 2);
+    assert!(recent.iter().all(|n| n.vertex.is_none()));
+}
+
+#[test]
+pub fn schema_validation() {
+    let server = start_server(4087, "schema_validation");
+    let graph = &server.graph;
+    let Movies { morgan_freeman, oblivion, .. } = movies(graph);
+    match graph.new_vertex("movie", data_map!{ name: "Memento", year: "2000" }).wait() {
+        Err(NewVertexError::ValidationError(validation::ValidationError::TypeMismatch { field, expected, actual })) =>
+            assert_eq!((field.as_str(), expected.as_str(), actual.as_str()), ("year", "u32", "string")),
+        other => panic!("{:?}", other)
+    }
+    match graph.new_vertex("movie", data_map!{ name: "Memento" }).wait() {
+        Err(NewVertexError::ValidationError(validation::ValidationError::MissingField(field))) => assert_eq!(field, "year"),
+        other => panic!("{:?}", other)
+    }
+    match graph.link(&morgan_freeman, "acted-in", &oblivion, Some(data_map!{ role: 1 as u32 })).wait().unwrap() {
+        Err(LinkVerticesError::ValidationError(validation::ValidationError::TypeMismatch { field, .. })) => assert_eq!(field, "role"),
+        other => panic!("{:?}", other)
+    }
+}
+
+#[test]
+pub fn retry_policy() {
+    let policy = RetryPolicy { jitter: false, ..RetryPolicy::default() };
+    assert_eq!(policy.backoff(1), Duration::from_millis(10));
+    assert_eq!(policy.backoff(2), Duration::from_millis(20));
+    assert_eq!(policy.backoff(20), Duration::from_millis(1000));
+    assert!(policy.should_retry(1, &TxnError::Aborted(None)));
+    assert!(!policy.should_retry(3, &TxnError::Aborted(None)));
+    assert!(!RetryPolicy::no_retry().should_retry(1, &TxnError::Aborted(None)));
+}
+
+#[test]
+pub fn explicit_aborts_not_retried() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    let server = start_server(4053, "explicit_aborts");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("item", None, &Vec::new(), true)).wait().unwrap();
+    let id = graph.new_vertex("item", Map::new()).wait().unwrap().cell.id();
+    let policy = RetryPolicy { max_attempts: 3, ..RetryPolicy::default() };
+    let runs = Arc::new(AtomicUsize::new(0));
+    let counter = runs.clone();
+    let result = graph.graph_transaction_with_retry(policy.clone(), move |txn| {
+        counter.fetch_add(1, Ordering::Relaxed);
+        txn.update_vertex(id, |_| None)
+    }).wait();
+    assert!(result.is_err());
+    assert_eq!(runs.load(Ordering::Relaxed), 1);
+    let counter = runs.clone();
+    let result = graph.graph_transaction_with_retry(policy, move |txn| {
+        counter.fetch_add(1, Ordering::Relaxed);
+        txn.abort()
+    }).wait();
+    assert!(result.is_err());
+    assert_eq!(runs.load(Ordering::Relaxed), 2);
+    // a failed removal is an error of its own, not an abort
+    match graph.remove_vertex(Id::new(1, 1)).wait().unwrap() {
+        Err(RemoveError::NotFound) => {},
+        other => panic!("{:?}", other)
+    }
+}
+
+#[test]
+pub fn udf_registry() {
+    udf::register("test-double", |args| match args.get(0) {
+        Some(&Value::U64(n)) => Ok(Value::U64(n * 2)),
+        _ => Err("test-double takes an u64".to_string())
+    }).unwrap();
+    assert!(udf::registered().contains(&"test-double".to_string()));
+    match udf::register("test-double", |_| Ok(Value::Null)) {
+        Err(udf::UdfError::NameTaken(_)) => {},
+        other => panic!("{:?}", other)
+    }
+}
+
+#[test]
+pub fn geo_radius() {
+    let server = start_server(4006, "geo_radius");
+    let graph = &server.graph;
+    let place_schema = MorpheusSchema::new("place", Some(&vec!["name".to_string()]), &vec! [
+        Field::new("name", TypeId::String as u32, false, false, None),
+        geo::point_field("location", false)
+    ], false);
+    graph.new_vertex_group(place_schema).wait().unwrap();
+    let places = vec![
+        ("Eiffel Tower", 48.8584, 2.2945),
+        ("Louvre", 48.8606, 2.3376),
+        ("Versailles", 48.8049, 2.1204),
+        ("Big Ben", 51.5007, -0.1246)
+    ];
+    for &(name, lat, lon) in &places {
+        let mut place = Map::new();
+        place.insert("name", Value::String(name.to_string()));
+        place.insert("location", GeoPoint::new(lat, lon).to_value());
+        graph.new_vertex("place", place).wait().unwrap();
+    }
+    let near = graph.vertices_near("place", "location", 48.8584, 2.2945, 5000.0).wait().unwrap().unwrap();
+    let names: Vec<String> = near.iter().map(|&(ref v, _)| v["name"].String().unwrap().clone()).collect();
+    assert_eq!(names, vec!["Eiffel Tower".to_string(), "Louvre".to_string()]);
+    assert!(near[1].1 > 3000.0 && near[1].1 < 3500.0);
+    assert_eq!(graph.vertices_near("place", "location", 48.8584, 2.2945, 400000.0).wait().unwrap().unwrap().len(), 4);
+    // moving a place takes it out of its old buckets
+    graph.update_vertex_by_key("place", "Louvre", |mut v| {
+        v["location"] = GeoPoint::new(51.5, -0.12).to_value();
+        Some(v)
+    }).wait().unwrap();
+    assert_eq!(graph.vertices_near("place", "location", 48.8584, 2.2945, 5000.0).wait().unwrap().unwrap().len(), 1);
+    assert!(graph.vertices_near("place", "name", 48.8584, 2.2945, 5000.0).wait().unwrap().is_err());
+}
+
+#[test]
+pub fn vector_knn() {
+    let server = start_server(4007, "vector_knn");
+    let graph = &server.graph;
+    let item_schema = MorpheusSchema::new("item", Some(&vec!["name".to_string()]), &vec! [
+        Field::new("name", TypeId::String as u32, false, false, None),
+        vector::vector_field("embedding", true)
+    ], false);
+    graph.new_vertex_group(item_schema).wait().unwrap();
+    let new_item = |name: &str, embedding: &[f32]| {
+        let mut item = Map::new();
+        item.insert("name", Value::String(name.to_string()));
+        item.insert("embedding", vector::vector_to_value(embedding));
+        graph.new_vertex("item", item).wait().unwrap();
+    };
+    new_item("a", &[1.0, 0.0, 0.0]);
+    new_item("b", &[0.9, 0.1, 0.0]);
+    new_item("c", &[0.0, 1.0, 0.0]);
+    assert!(match graph.knn("item", "embedding", vec![1.0, 0.0, 0.0], 1).wait().unwrap() {
+        Err(VectorError::IndexNotFound) => true, _ => false
+    });
+    let options = HnswOptions { metric: VectorMetric::Euclidean, ..HnswOptions::default() };
+    assert_eq!(graph.build_vector_index("item", "embedding", options).wait().unwrap(), 3);
+    let names = |k: usize| -> Vec<String> {
+        graph.knn("item", "embedding", vec![1.0, 0.0, 0.0], k).wait().unwrap().unwrap()
+            .iter().map(|&(ref v, _)| v["name"].String().unwrap().clone()).collect()
+    };
+    assert_eq!(names(2), vec!["a".to_string(), "b".to_string()]);
+    // writes after the build reach the index once committed
+    new_item("d", &[1.0, 0.0, 0.01]);
+    assert_eq!(names(2), vec!["a".to_string(), "d".to_string()]);
+    graph.remove_vertex_by_key("item", "a").wait().unwrap().unwrap();
+    graph.update_vertex_by_key("item", "c", |mut v| {
+        v["embedding"] = vector::vector_to_value(&[1.0, 0.0, 0.0]);
+        Some(v)
+    }).wait().unwrap();
+    assert_eq!(names(3), vec!["c".to_string(), "d".to_string(), "b".to_string()]);
+    assert!(match graph.knn("item", "embedding", vec![1.0, 0.0], 1).wait().unwrap() {
+        Err(VectorError::DimensionMismatch { expected: 3, actual: 2 }) => true, _ => false
+    });
+    assert!(graph.drop_vector_index("item", "embedding"));
+}
+
+#[test]
+pub fn field_defaults() {
+    let server = start_server(4008, "field_defaults");
+    let graph = &server.graph;
+    let task_schema = MorpheusSchema::new("task", Some(&vec!["title".to_string()]), &vec! [
+        Field::new("title", TypeId::String as u32, false, false, None),
+        Field::new("status", TypeId::String as u32, false, false, None),
+        Field::new("owner", TypeId::String as u32, false, false, None)
+    ], false)
+        .with_default("status", Value::String("open".to_string()))
+        .optional("owner");
+    graph.new_vertex_group(task_schema).wait().unwrap();
+    let task = graph.new_vertex("task", data_map!{ title: "write docs" }).wait().unwrap();
+    assert_eq!(task["status"].String().unwrap(), "open");
+    let done = graph.new_vertex("task", data_map!{ title: "ship", status: "done" }).wait().unwrap();
+    assert_eq!(done["status"].String().unwrap(), "done");
+    let stored = server.schema_container.from_name("task").unwrap();
+    assert_eq!(stored.defaults, vec![("status".to_string(), Value::String("open".to_string()))]);
+    let bad_default = MorpheusSchema::new("bad-task", None, &vec! [
+        Field::new("status", TypeId::String as u32, false, false, None)
+    ], false).with_default("status", Value::U32(0));
+    match graph.new_vertex_group(bad_default).wait() {
+        Err(SchemaError::InvalidDefault(_)) => {},
+        other => panic!("{:?}", other)
+    }
+    let required_title = MorpheusSchema::new("note", None, &vec! [
+        Field::new("title", TypeId::String as u32, true, false, None)
+    ], false).required("title");
+    graph.new_vertex_group(required_title).wait().unwrap();
+    match graph.new_vertex("note", Map::new()).wait() {
+        Err(NewVertexError::ValidationError(validation::ValidationError::MissingField(field))) => assert_eq!(field, "title"),
+        other => panic!("{:?}", other)
+    }
+}
+
+#[test]
+pub fn computed_fields() {
+    let server = start_server(4009, "computed_fields");
+    let graph = &server.graph;
+    udf::register("test-full-name", |args| match args.get(0) {
+        Some(&Value::Map(_)) => match (&args[0]["first"], &args[0]["last"]) {
+            (&Value::String(ref first), &Value::String(ref last)) => Ok(Value::String(format!("{} {}", first, last))),
+            _ => Ok(Value::Null)
+        },
+        _ => Err("test-full-name takes the vertex".to_string())
+    }).unwrap();
+    udf::register("test-rank", |args| match args.get(0) {
+        Some(&Value::U64(degree)) => Ok(Value::U64(degree * 10)),
+        _ => Ok(Value::Null)
+    }).unwrap();
+    let person_schema = MorpheusSchema::new("person", None, &vec! [
+        Field::new("first", TypeId::String as u32, false, false, None),
+        Field::new("last", TypeId::String as u32, false, false, None),
+        Field::new("full_name", TypeId::String as u32, true, false, None)
+    ], false)
+        .with_computed(ComputedField::new("full_name", "(test-full-name vertex)", ComputedMode::Materialized))
+        .with_computed(ComputedField::new("rank", "(test-rank out_degree)", ComputedMode::Virtual));
+    graph.new_vertex_group(person_schema).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("knows", None, &EMPTY_FIELDS, false),
+        EdgeAttributes::new(EdgeType::Directed, false)
+    ).wait().unwrap();
+    let ada = graph.new_vertex("person", data_map!{ first: "Ada", last: "Lovelace" }).wait().unwrap();
+    let charles = graph.new_vertex("person", data_map!{ first: "Charles", last: "Babbage" }).wait().unwrap();
+    assert_eq!(ada["full_name"].String().unwrap(), "Ada Lovelace");
+    graph.link(&ada, "knows", &charles, None).wait().unwrap().unwrap();
+    let ada_id = ada.cell.id();
+    let read = graph.graph_transaction(move |txn| Ok(txn.read_vertex(ada_id)?.unwrap())).wait().unwrap();
+    assert!(match read["rank"] { Value::U64(10) => true, _ => false });
+    // outside a transaction there are no degrees
+    let read = graph.vertex_by(ada_id).wait().unwrap().unwrap();
+    assert!(match read["rank"] { Value::Null => true, _ => false });
+    graph.update_vertex(ada_id, |mut v| {
+        v["last"] = Value::String("King".to_string());
+        Some(v)
+    }).wait().unwrap();
+    let read = graph.vertex_by(ada_id).wait().unwrap().unwrap();
+    assert_eq!(read["full_name"].String().unwrap(), "Ada King");
+    let undeclared = MorpheusSchema::new("bad-person", None, &vec! [
+        Field::new("first", TypeId::String as u32, false, false, None)
+    ], false).with_computed(ComputedField::new("initial", "(test-full-name vertex)", ComputedMode::Materialized));
+    match graph.new_vertex_group(undeclared).wait() {
+        Err(SchemaError::InvalidComputedField(field)) => assert_eq!(field, "initial"),
+        other => panic!("{:?}", other)
+    }
+}
+
+#[test]
+pub fn consistency_check() {
+    let server = start_server(4010, "consistency_check");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("user", Some(&vec!["name".to_string()]), &vec! [
+        Field::new("name", TypeId::String as u32, false, false, None)
+    ], false)).wait().unwrap();
+    let knows = graph.new_edge_group(
+        MorpheusSchema::new("knows", None, &EMPTY_FIELDS, false),
+        EdgeAttributes::new(EdgeType::Directed, false)
+    ).wait().unwrap();
+    let a = graph.new_vertex("user", data_map!{ name: "a" }).wait().unwrap().cell.id();
+    let b = graph.new_vertex("user", data_map!{ name: "b" }).wait().unwrap().cell.id();
+    let c = graph.new_vertex("user", data_map!{ name: "c" }).wait().unwrap().cell.id();
+    graph.link(a, "knows", b, None).wait().unwrap().unwrap();
+    graph.link(a, "knows", c, None).wait().unwrap().unwrap();
+    assert!(graph.check_consistency(FsckOptions::default()).wait().unwrap().inconsistencies.is_empty());
+    // remove b behind the graph's back, a keeps listing it
+    graph.graph_transaction(move |txn| txn.neb_txn.remove(&b)).wait().unwrap();
+    let report = graph.check_consistency(FsckOptions::default()).wait().unwrap();
+    assert_eq!(report.vertices_checked, 2);
+    assert_eq!(report.inconsistencies, vec![Inconsistency::DanglingEntry {
+        vertex: a, schema: knows, direction: EdgeDirection::Outbound, entry: b
+    }]);
+    assert_eq!(report.repaired, 0);
+    let repaired = graph.check_consistency(FsckOptions { repair: true, ..FsckOptions::default() }).wait().unwrap();
+    assert_eq!(repaired.repaired, 1);
+    assert!(graph.check_consistency(FsckOptions::default()).wait().unwrap().inconsistencies.is_empty());
+    assert_eq!(graph.degree(a, "knows", EdgeDirection::Outbound).wait().unwrap().unwrap(), 1);
+}
+
+#[test]
+pub fn columnar_scan() {
+    let server = start_server(4013, "columnar_scan");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("city", None, &vec! [
+        Field::new("name", TypeId::String as u32, false, false, None),
+        Field::new("population", TypeId::U32 as u32, true, false, None),
+        Field::new("tags", TypeId::String as u32, true, true, None)
+    ], false)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("road", None, &vec! [Field::new("km", TypeId::F32 as u32, false, false, None)], false),
+        EdgeAttributes::new(EdgeType::Undirected, true)
+    ).wait().unwrap();
+    let paris = graph.new_vertex("city", data_map!{ name: "Paris", population: 2148000 as u32 }).wait().unwrap().cell.id();
+    let lyon = graph.new_vertex("city", data_map!{ name: "Lyon" }).wait().unwrap().cell.id();
+    graph.link(paris, "road", lyon, Some(data_map!{ km: 465.0 as f32 })).wait().unwrap().unwrap();
+    let batches: Vec<_> = graph.scan_vertex_batches("city", &None::<String>, 1).collect().wait().unwrap();
+    assert_eq!(batches.len(), 2);
+    let names: Vec<&str> = vec!["id.higher", "id.lower", "name", "population"];
+    assert_eq!(batches[0].columns.iter().map(|c| c.name.as_str()).collect::<Vec<_>>(), names);
+    let populations: Vec<bool> = batches.iter().map(|b| b.column("population").unwrap().valid[0]).collect();
+    assert_eq!(populations.iter().filter(|valid| **valid).count(), 1);
+    let edges: Vec<_> = graph.scan_edge_batches("road", &None::<String>, 64).collect().wait().unwrap();
+    assert_eq!(edges.len(), 1);
+    assert_eq!(edges[0].rows, 1);
+    assert_eq!(edges[0].column("km").unwrap().data, ColumnData::Float64(vec![465.0]));
+}
+
+#[test]
+pub fn change_log() {
+    let server = start_server(4014, "change_log");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("user", None, &Vec::new(), true)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("follows", None, &Vec::new(), false),
+        EdgeAttributes::new(EdgeType::Directed, false)
+    ).wait().unwrap();
+    graph.new_vertex("user", data_map!{ name: "before" }).wait().unwrap();
+    let log = graph.enable_change_log(16);
+    let alice = graph.new_vertex("user", data_map!{ name: "alice" }).wait().unwrap().cell.id();
+    let bob = graph.new_vertex("user", data_map!{ name: "bob" }).wait().unwrap().cell.id();
+    graph.graph_transaction(move |txn| {
+        txn.link(alice, "follows", bob, None)?.unwrap();
+        let savepoint = txn.savepoint();
+        txn.link(bob, "follows", alice, None)?.unwrap();
+        txn.rollback_to(&savepoint)?.unwrap();
+        Ok(())
+    }).wait().unwrap();
+    graph.remove_vertex(bob).wait().unwrap().unwrap();
+    let events = log.read_after(0, 64, Duration::from_millis(10));
+    let kinds: Vec<ChangeKind> = events.iter().map(|e| e.kind).collect();
+    assert_eq!(kinds, vec![
+        ChangeKind::VertexInserted, ChangeKind::VertexInserted, ChangeKind::Linked,
+        ChangeKind::Linked, ChangeKind::Unlinked, ChangeKind::VertexRemoved
+    ]);
+    assert_eq!(events[2].ends, Some((alice, bob)));
+    assert_eq!(events[5].id, bob);
+    assert!(events.windows(2).all(|pair| pair[0].seq < pair[1].seq));
+    assert!(log.read_after(events[5].seq, 64, Duration::from_millis(10)).is_empty());
+}
+
+#[test]
+pub fn query_cache() {
+    use query;
+    let server = start_server(4017, "query_cache");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("user", None, &Vec::new(), true)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("follows", None, &Vec::new(), false),
+        EdgeAttributes::new(EdgeType::Directed, false)
+    ).wait().unwrap();
+    let cache = graph.enable_query_cache(cache::QueryCacheOptions::default());
+    let alice = graph.new_vertex("user", data_map!{ name: "alice" }).wait().unwrap().cell.id();
+    let bob = graph.new_vertex("user", data_map!{ name: "bob" }).wait().unwrap().cell.id();
+    let carol = graph.new_vertex("user", data_map!{ name: "carol" }).wait().unwrap().cell.id();
+    graph.link(alice, "follows", bob, None).wait().unwrap().unwrap();
+    let names = || -> Vec<String> {
+        graph.neighbourhoods(alice, "follows", EdgeDirection::Outbound, &None::<String>).wait().unwrap().unwrap()
+            .iter().map(|&(ref v, _)| v["name"].String().unwrap().clone()).collect()
+    };
+    assert_eq!(names(), vec!["bob"]);
+    assert_eq!(cache.len(), 1);
+    assert_eq!(names(), vec!["bob"]);
+    // linking from alice drops her entry
+    graph.link(alice, "follows", carol, None).wait().unwrap().unwrap();
+    assert_eq!(cache.len(), 0);
+    assert_eq!(names().len(), 2);
+    // so does updating a vertex listed in it
+    graph.update_vertex(bob, |mut vertex| {
+        vertex["name"] = Value::String("robert".to_string());
+        Some(vertex)
+    }).wait().unwrap();
+    assert_eq!(cache.len(), 0);
+    let mut after_update = names();
+    after_update.sort();
+    assert_eq!(after_update, vec!["carol", "robert"]);
+    // other vertices and directions are entries of their own
+    graph.neighbourhoods(carol, "follows", EdgeDirection::Inbound, &None::<String>).wait().unwrap().unwrap();
+    assert_eq!(cache.len(), 2);
+    // so are filters, keyed by the whole parsed expression
+    let key = |filter: Option<&str>| cache::CacheKey::new(
+        alice, 1, EdgeDirection::Outbound, &query::parse_optional_expr(&filter).unwrap()
+    );
+    assert_eq!(key(Some("(test-cache-filter 1)")), key(Some("(test-cache-filter 1)")));
+    assert!(key(Some("(test-cache-filter 1)")) != key(Some("(test-cache-filter 2)")));
+    assert!(key(None) != key(Some("(test-cache-filter 1)")));
+}
+
+#[test]
+pub fn vertex_cache() {
+    let server = start_server(4018, "vertex_cache");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("user", None, &Vec::new(), true)).wait().unwrap();
+    let cache = graph.enable_vertex_cache(vertex_cache::VertexCacheOptions {
+        capacity: 2, ..Default::default()
+    });
+    let ids: Vec<Id> = (0..3).map(|i| {
+        graph.new_vertex("user", data_map!{ rank: i as u32 }).wait().unwrap().cell.id()
+    }).collect();
+    for id in &ids { graph.vertex_by(*id).wait().unwrap().unwrap(); }
+    // the least recently read vertex went first
+    assert_eq!(cache.len(), 2);
+    assert!(cache.get(&ids[0]).is_none());
+    assert!(cache.get(&ids[2]).is_some());
+    graph.update_vertex(ids[2], |mut vertex| {
+        vertex["rank"] = Value::U32(7);
+        Some(vertex)
+    }).wait().unwrap();
+    assert!(cache.get(&ids[2]).is_none());
+    assert_eq!(graph.vertex_by(ids[2]).wait().unwrap().unwrap()["rank"], Value::U32(7));
+    // what peers report is dropped as well
+    cache.invalidate(&[ids[2]]);
+    assert!(cache.get(&ids[2]).is_none());
+}
+
+#[test]
+pub fn missing_vertex_cache() {
+    let server = start_server(4019, "missing_vertex_cache");
+    let graph = &server.graph;
+    let member = graph.new_vertex_group(MorpheusSchema::new("member", Some(&vec!["name".to_string()]), &vec![
+        Field::new("name", TypeId::String as u32, false, false, None)
+    ], false)).wait().unwrap();
+    let cache = graph.enable_vertex_cache(vertex_cache::VertexCacheOptions {
+        missing_max_age_ms: 60000, ..Default::default()
+    });
+    let id = Cell::encode_cell_key(member, &Value::String("nobody".to_string()));
+    assert!(graph.vertex_by_key("member", "nobody").wait().unwrap().is_none());
+    assert!(cache.is_missing(&id));
+    assert!(graph.vertex_by(id).wait().unwrap().is_none());
+    // the insert drops the remembered miss
+    graph.new_vertex("member", data_map!{ name: "nobody" }).wait().unwrap();
+    assert!(!cache.is_missing(&id));
+    assert!(graph.vertex_by_key("member", "nobody").wait().unwrap().is_some());
+}
+
+#[test]
+pub fn bulk_transaction() {
+    let server = start_server(4020, "bulk_transaction");
+    let graph = &server.graph;
+    let member = graph.new_vertex_group(MorpheusSchema::new("member", Some(&vec!["name".to_string()]), &vec![
+        Field::new("name", TypeId::String as u32, false, false, None),
+        Field::new("age", TypeId::U32 as u32, true, false, None)
+    ], false)).wait().unwrap();
+    let knows = graph.new_edge_group(
+        MorpheusSchema::new("knows", None, &Vec::new(), false),
+        EdgeAttributes::new(EdgeType::Undirected, false)
+    ).wait().unwrap();
+    let names = ["ann", "ben", "cid", "dot", "eve"];
+    let id_of = |name: &str| Cell::encode_cell_key(member, &Value::String(name.to_string()));
+    let mut ops: Vec<bulk::BulkOp> = names.iter()
+        .map(|name| bulk::BulkOp::InsertVertex { schema: member, data: data_map!{ name: *name } })
+        .collect();
+    for pair in names.windows(2) {
+        ops.push(bulk::BulkOp::Link { from: id_of(pair[0]), schema: knows, to: id_of(pair[1]), body: None });
+    }
+    ops.push(bulk::BulkOp::UpdateVertex { id: id_of("eve"), fields: vec![("age".to_string(), Value::U32(30))] });
+    // links to a vertex that was never written are rejected, the rest goes on
+    ops.push(bulk::BulkOp::Link { from: id_of("ann"), schema: knows, to: id_of("zed"), body: None });
+    let options = bulk::BulkOptions { max_ops: 3, ..Default::default() };
+    assert_eq!(bulk::chunks(&ops, &options).len(), 4);
+    let report = graph.bulk_transaction(ops, options).wait().unwrap();
+    assert_eq!(report.chunks, 4);
+    assert_eq!(report.applied, 10);
+    assert_eq!(report.rejected.iter().map(|r| r.0).collect::<Vec<_>>(), vec![10]);
+    assert!(report.failed.is_empty());
+    assert_eq!(graph.count_edges("knows", CountMode::Exact).wait().unwrap().count, 4);
+    assert_eq!(graph.vertex_by(id_of("eve")).wait().unwrap().unwrap()["age"], Value::U32(30));
+    // one oversized operation still gets a chunk
+    let tiny = bulk::BulkOptions { max_ops: 100, max_bytes: 1 };
+    let big = vec![bulk::BulkOp::RemoveVertex { id: id_of("ann") }, bulk::BulkOp::RemoveVertex { id: id_of("ben") }];
+    assert_eq!(bulk::chunks(&big, &tiny), vec![0..1, 1..2]);
+}
+
+#[test]
+pub fn idempotency_keys() {
+    let server = start_server(4021, "idempotency_keys");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("user", None, &Vec::new(), true)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("follows", None, &Vec::new(), false),
+        EdgeAttributes::new(EdgeType::Directed, false)
+    ).wait().unwrap();
+    let first = graph.new_vertex_idempotent("req-1", "user", data_map!{ name: "alice" }).wait().unwrap().unwrap();
+    let alice = match first { idempotency::Outcome::Applied(ref v) => v.cell.id(), _ => panic!("first call replayed") };
+    // a retry of the same request gets the vertex of the first call back
+    match graph.new_vertex_idempotent("req-1", "user", data_map!{ name: "alice" }).wait().unwrap().unwrap() {
+        idempotency::Outcome::Replayed(Some(ref v)) => assert_eq!(v.cell.id(), alice),
+        _ => panic!("retry was applied again")
+    }
+    assert_eq!(graph.count_vertices("user", &None::<String>, CountMode::Exact).wait().unwrap().count, 1);
+    let bob = graph.new_vertex("user", data_map!{ name: "bob" }).wait().unwrap().cell.id();
+    assert!(!graph.link_idempotent("req-2", alice, "follows", bob, None).wait().unwrap().unwrap().is_replayed());
+    assert!(graph.link_idempotent("req-2", alice, "follows", bob, None).wait().unwrap().unwrap().is_replayed());
+    assert_eq!(graph.count_edges("follows", CountMode::Exact).wait().unwrap().count, 1);
+    // rolled back writes leave no record behind
+    graph.graph_transaction(move |txn| {
+        let savepoint = txn.savepoint();
+        txn.link_idempotent("req-3", bob, "follows", alice, None)?.unwrap();
+        txn.rollback_to(&savepoint)?.unwrap();
+        Ok(())
+    }).wait().unwrap();
+    assert!(!graph.link_idempotent("req-3", bob, "follows", alice, None).wait().unwrap().unwrap().is_replayed());
+    graph.forget_idempotency_key("req-1").wait().unwrap();
+    assert!(!graph.new_vertex_idempotent("req-1", "user", data_map!{ name: "carol" }).wait().unwrap().unwrap().is_replayed());
+}
+
+#[test]
+pub fn stored_procedures() {
+    use graph::procedure::{Procedure, ProcedureError, Script, Step};
+    let server = start_server(4022, "stored_procedures");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("user", None, &vec! [
+        Field::new("name", TypeId::String as u32, false, false, None)
+    ], false)).wait().unwrap();
+    graph.new_vertex_group(MorpheusSchema::new("org", None, &vec! [
+        Field::new("name", TypeId::String as u32, false, false, None)
+    ], false)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("member_of", None, &EMPTY_FIELDS, false),
+        EdgeAttributes::new(EdgeType::Directed, false)
+    ).wait().unwrap();
+    graph.deploy_procedure("join_org", Procedure::Script(Script {
+        params: vec!["name".to_string(), "org".to_string()],
+        steps: vec![
+            Step::NewVertex {
+                name: Some("user".to_string()), schema: "user".to_string(),
+                fields: vec![("name".to_string(), "name".to_string())]
+            },
+            Step::Link { from: "user".to_string(), schema: "member_of".to_string(), to: "org".to_string(), body: None }
+        ],
+        result: Some("user".to_string())
+    })).unwrap();
+    graph.deploy_procedure("org_count", Procedure::native(|txn, args| {
+        let org = match args.get(0) { Some(&Value::Id(id)) => id, _ => return Ok(Err("takes an org".to_string())) };
+        Ok(txn.degree(org, "member_of", EdgeDirection::Inbound)?
+            .map(|degree| Value::U64(degree as u64))
+            .map_err(|e| format!("{:?}", e)))
+    })).unwrap();
+    assert_eq!(graph.procedures(), vec!["join_org".to_string(), "org_count".to_string()]);
+    let org = graph.new_vertex("org", data_map!{ name: "shisoft" }).wait().unwrap().cell.id();
+    let user = match graph.call_procedure("join_org", vec![Value::String("alice".to_string()), Value::Id(org)]).wait().unwrap() {
+        Value::Id(id) => id,
+        other => panic!("{:?}", other)
+    };
+    assert_eq!(graph.vertex_by(user).wait().unwrap().unwrap()["name"].String().unwrap(), "alice");
+    assert!(match graph.call_procedure("org_count", vec![Value::Id(org)]).wait().unwrap() { Value::U64(1) => true, _ => false });
+    // the link to a missing org fails, the user created before it is rolled back
+    match graph.call_procedure("join_org", vec![Value::String("bob".to_string()), Value::Id(Id::new(1, 1))]).wait() {
+        Err(ProcedureError::StepFailed(1, _)) => {},
+        other => panic!("{:?}", other)
+    }
+    assert_eq!(graph.count_vertices("user", &None::<String>, CountMode::Exact).wait().unwrap().count, 1);
+    match graph.call_procedure("join_org", vec![]).wait() {
+        Err(ProcedureError::WrongArity { expected: 2, given: 0 }) => {},
+        other => panic!("{:?}", other)
+    }
+    assert!(graph.remove_procedure("org_count"));
+    match graph.call_procedure("org_count", vec![Value::Id(org)]).wait() {
+        Err(ProcedureError::NotFound(_)) => {},
+        other => panic!("{:?}", other)
+    }
+}
+
+#[test]
+pub fn tree_queries() {
+    use graph::tree::SubtreeAggregate;
+    let server = start_server(4023, "tree_queries");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("category", None, &vec! [
+        Field::new("name", TypeId::String as u32, false, false, None),
+        Field::new("items", TypeId::U64 as u32, false, false, None)
+    ], false)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("subcategory", None, &EMPTY_FIELDS, false),
+        EdgeAttributes::new(EdgeType::Directed, false)
+    ).wait().unwrap();
+    let category = |name: &str, items: u64| graph.new_vertex("category", data_map!{ name: name, items: items }).wait().unwrap().cell.id();
+    let (root, books, music, novels) = (category("root", 0), category("books", 5), category("music", 3), category("novels", 7));
+    graph.link(root, "subcategory", books, None).wait().unwrap().unwrap();
+    graph.link(root, "subcategory", music, None).wait().unwrap().unwrap();
+    graph.link(books, "subcategory", novels, None).wait().unwrap().unwrap();
+    // a broken tree pointing back at its root
+    graph.link(novels, "subcategory", root, None).wait().unwrap().unwrap();
+    let tree = graph.descendants(root, "subcategory", 10).wait().unwrap().unwrap();
+    assert_eq!(tree.ids().len(), 4);
+    assert_eq!(tree.ids()[0], root);
+    let novels_node = tree.ids().iter().position(|id| *id == novels).unwrap();
+    assert_eq!(tree.nodes[novels_node].depth, 2);
+    assert_eq!(tree.cycles(), vec![(novels_node, 0)]);
+    assert_eq!(graph.descendants(root, "subcategory", 1).wait().unwrap().unwrap().ids().len(), 3);
+    let ancestors = graph.ancestors(novels, "subcategory", 2).wait().unwrap().unwrap();
+    assert_eq!(ancestors.ids(), vec![novels, books, root]);
+    let totals = graph.aggregate_subtree(root, "subcategory", 10, SubtreeAggregate::Sum("items".to_string())).wait().unwrap().unwrap();
+    assert_eq!(totals[0], (root, Some(15.0)));
+    assert!(totals.contains(&(books, Some(12.0))));
+    let counts = graph.aggregate_subtree(root, "subcategory", 10, SubtreeAggregate::Count).wait().unwrap().unwrap();
+    assert_eq!(counts[0].1, Some(4.0));
+}
+
+#[test]
+pub fn variable_length_paths() {
+    use server::path_pattern::PathPattern;
+    let server = start_server(4024, "variable_length_paths");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("account", None, &vec! [
+        Field::new("name", TypeId::String as u32, false, false, None)
+    ], false)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("pays", None, &EMPTY_FIELDS, false),
+        EdgeAttributes::new(EdgeType::Directed, false)
+    ).wait().unwrap();
+    let account = |name: &str| graph.new_vertex("account", data_map!{ name: name }).wait().unwrap().cell.id();
+    let (a, b, c, d) = (account("a"), account("b"), account("c"), account("d"));
+    graph.link(a, "pays", b, None).wait().unwrap().unwrap();
+    graph.link(b, "pays", c, None).wait().unwrap().unwrap();
+    graph.link(c, "pays", a, None).wait().unwrap().unwrap();
+    graph.link(c, "pays", d, None).wait().unwrap().unwrap();
+    let pattern = PathPattern::parse(graph, "(a)-[:pays*1..3]->(b)").unwrap();
+    let paths = TraversalRouter::match_paths(server.router.clone(), String::new(), vec![a], pattern).wait().unwrap();
+    let vertices: Vec<Vec<Id>> = paths.iter().map(|p| p.vertices.clone()).collect();
+    assert_eq!(vertices, vec![vec![a, b], vec![a, b, c], vec![a, b, c, a], vec![a, b, c, d]]);
+    // the ring back to a is the only path of three hops ending at the start
+    let pattern = PathPattern::parse(graph, "(a)-[:pays*3]->(a)").unwrap();
+    let rings: Vec<_> = TraversalRouter::match_paths(server.router.clone(), String::new(), vec![a], pattern).wait().unwrap()
+        .into_iter().filter(|p| p.vertices.last() == Some(&a)).collect();
+    assert_eq!(rings.len(), 1);
+    let pattern = PathPattern::parse(graph, "(c)<-[:pays]-()-[:pays*..2]-()").unwrap().with_max_paths(1);
+    assert_eq!(TraversalRouter::match_paths(server.router.clone(), String::new(), vec![c], pattern).wait().unwrap().len(), 1);
+    assert!(PathPattern::parse(graph, "(a)-[:pays*]->(b)").is_err());
+    assert!(PathPattern::parse(graph, "(a)-[:unknown]->(b)").is_err());
+}
+
+#[test]
+pub fn reachability() {
+    let server = start_server(4025, "reachability");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("station", None, &vec! [
+        Field::new("name", TypeId::String as u32, false, false, None)
+    ], false)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("line", None, &EMPTY_FIELDS, false),
+        EdgeAttributes::new(EdgeType::Directed, false)
+    ).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("walk", None, &EMPTY_FIELDS, false),
+        EdgeAttributes::new(EdgeType::Undirected, false)
+    ).wait().unwrap();
+    let station = |name: &str| graph.new_vertex("station", data_map!{ name: name }).wait().unwrap().cell.id();
+    let stations: Vec<Id> = (0..6).map(|i| station(&format!("s{}", i))).collect();
+    for pair in stations[..4].windows(2) {
+        graph.link(pair[0], "line", pair[1], None).wait().unwrap().unwrap();
+    }
+    graph.link(stations[3], "walk", stations[4], None).wait().unwrap().unwrap();
+    assert_eq!(
+        graph.reachable_path(stations[0], stations[3], vec!["line"], 3).wait().unwrap().unwrap(),
+        Some(stations[..4].to_vec())
+    );
+    assert!(!graph.is_reachable(stations[0], stations[3], vec!["line"], 2).wait().unwrap().unwrap());
+    // lines run one way only
+    assert!(!graph.is_reachable(stations[3], stations[0], vec!["line"], 10).wait().unwrap().unwrap());
+    assert!(!graph.is_reachable(stations[0], stations[4], vec!["line"], 10).wait().unwrap().unwrap());
+    assert!(graph.is_reachable(stations[0], stations[4], vec!["line", "walk"], 10).wait().unwrap().unwrap());
+    assert!(graph.is_reachable(stations[4], stations[3], vec!["walk"], 1).wait().unwrap().unwrap());
+    assert!(!graph.is_reachable(stations[0], stations[5], vec!["line", "walk"], 10).wait().unwrap().unwrap());
+}
+
+#[test]
+pub fn weighted_paths() {
+    use server::path_pattern::{PathPattern, PathWeight, WeightAggregate};
+    let server = start_server(4026, "weighted_paths");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("host", None, &vec! [
+        Field::new("name", TypeId::String as u32, false, false, None)
+    ], false)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("route", None, &vec! [
+            Field::new("latency", TypeId::U32 as u32, false, false, None)
+        ], false),
+        EdgeAttributes::new(EdgeType::Directed, true)
+    ).wait().unwrap();
+    let host = |name: &str| graph.new_vertex("host", data_map!{ name: name }).wait().unwrap().cell.id();
+    let (a, b, c, d) = (host("a"), host("b"), host("c"), host("d"));
+    let route = |from: Id, to: Id, latency: u32| {
+        graph.link(from, "route", to, Some(data_map!{ latency: latency })).wait().unwrap().unwrap();
+    };
+    route(a, b, 10);
+    route(b, d, 10);
+    route(a, c, 5);
+    route(c, d, 50);
+    let weight = |aggregate, at_least, at_most| PathWeight { field: "latency".to_string(), aggregate, at_least, at_most };
+    let pattern = PathPattern::parse(graph, "(a)-[:route*2]->(d)").unwrap()
+        .with_weight(weight(WeightAggregate::Sum, None, Some(30.0)));
+    let paths = TraversalRouter::match_paths(server.router.clone(), String::new(), vec![a], pattern).wait().unwrap();
+    assert_eq!(paths.len(), 1);
+    assert_eq!(paths[0].vertices, vec![a, b, d]);
+    assert_eq!(paths[0].weight, Some(20.0));
+    let pattern = PathPattern::parse(graph, "(a)-[:route*1..2]->(d)").unwrap()
+        .with_weight(weight(WeightAggregate::Max, Some(40.0), None));
+    let paths = TraversalRouter::match_paths(server.router.clone(), String::new(), vec![a], pattern).wait().unwrap();
+    assert_eq!(paths.len(), 1);
+    assert_eq!(paths[0].vertices, vec![a, c, d]);
+    let pattern = PathPattern::parse(graph, "(a)-[:route*1..2]->(d)").unwrap()
+        .with_weight(weight(WeightAggregate::Min, Some(6.0), None));
+    assert_eq!(TraversalRouter::match_paths(server.router.clone(), String::new(), vec![a], pattern).wait().unwrap().len(), 2);
+}
+
+#[test]
+pub fn k_shortest_paths() {
+    let server = start_server(4027, "k_shortest_paths");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("city", None, &vec! [
+        Field::new("name", TypeId::String as u32, false, false, None)
+    ], false)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("road", None, &vec! [
+            Field::new("km", TypeId::F64 as u32, false, false, None)
+        ], false),
+        EdgeAttributes::new(EdgeType::Directed, true)
+    ).wait().unwrap();
+    let city = |name: &str| graph.new_vertex("city", data_map!{ name: name }).wait().unwrap().cell.id();
+    let (a, b, c, d) = (city("a"), city("b"), city("c"), city("d"));
+    let road = |from: Id, to: Id, km: f64| {
+        graph.link(from, "road", to, Some(data_map!{ km: km })).wait().unwrap().unwrap();
+    };
+    road(a, b, 1.0);
+    road(b, d, 1.0);
+    road(a, c, 1.0);
+    road(c, d, 2.0);
+    road(a, d, 5.0);
+    road(b, c, 0.5);
+    let paths = graph.k_shortest_paths(a, d, "road", 4, Some("km")).wait().unwrap().unwrap();
+    let routes: Vec<(Vec<Id>, Option<f64>)> = paths.into_iter().map(|p| (p.vertices, p.weight)).collect();
+    assert_eq!(routes, vec![
+        (vec![a, b, d], Some(2.0)),
+        (vec![a, c, d], Some(3.0)),
+        (vec![a, b, c, d], Some(3.5)),
+        (vec![a, d], Some(5.0))
+    ]);
+    // by hops the direct road comes first
+    let paths = graph.k_shortest_paths(a, d, "road", 1, None).wait().unwrap().unwrap();
+    assert_eq!(paths[0].vertices, vec![a, d]);
+    assert!(graph.k_shortest_paths(d, a, "road", 3, Some("km")).wait().unwrap().unwrap().is_empty());
+}
+
+#[test]
+pub fn startup_schema_checks() {
+    use graph::fields::VERTEX_TEMPLATE;
+    use graph::startup::{compare_fields, conclude, StartupError};
+    let body = Field::new("name", TypeId::String as u32, false, false, None);
+    let mut stored: Vec<Field> = VERTEX_TEMPLATE.clone();
+    stored.push(body.clone());
+    let (mut problems, mut notes) = (Vec::new(), Vec::new());
+    compare_fields("user", &VERTEX_TEMPLATE, &stored, true, &mut problems, &mut notes);
+    assert!(problems.is_empty() && notes.is_empty());
+    // schemas created before the nullable version and key fields need a migration
+    let mut old: Vec<Field> = VERTEX_TEMPLATE[..3].to_vec();
+    old.push(body.clone());
+    compare_fields("user", &VERTEX_TEMPLATE, &old, true, &mut problems, &mut notes);
+    assert!(problems.is_empty());
+    assert_eq!(notes.len(), 2);
+    match conclude(problems.clone(), notes.clone()) {
+        Err(StartupError::NeedsMigration(fields)) => assert_eq!(fields.len(), 2),
+        other => panic!("{:?}", other)
+    }
+    let mut changed = stored.clone();
+    changed[0] = Field::new(&changed[0].name.clone(), TypeId::U64 as u32, false, false, None);
+    compare_fields("user", &VERTEX_TEMPLATE, &changed, true, &mut problems, &mut notes);
+    assert_eq!(problems.len(), 1);
+    match conclude(problems, notes) {
+        Err(StartupError::Incompatible(found)) => assert_eq!(found.len(), 3),
+        other => panic!("{:?}", other)
+    }
+    let server = start_server(4028, "startup_schema_checks");
+    // the base schemas created by the first graph fit the next one
+    Graph::new(&server.schema_container, &server.neb_client).wait().unwrap();
+}
+
+#[test]
+pub fn id_strategies() {
+    use graph::ids::IdStrategy;
+    let server = start_server(4029, "id_strategies");
+    let graph = &server.graph;
+    let fields = vec![Field::new("name", TypeId::String as u32, false, false, None)];
+    graph.new_vertex_group(MorpheusSchema::new("ticket", None, &fields, false)
+        .with_id_strategy(IdStrategy::Sequential)).wait().unwrap();
+    graph.new_vertex_group(MorpheusSchema::new("event", None, &fields, false)
+        .with_id_strategy(IdStrategy::Snowflake)).wait().unwrap();
+    let tickets: Vec<Id> = (0..3).map(|n| graph.new_vertex("ticket", data_map!{
+        name: format!("ticket {}", n)
+    }).wait().unwrap().cell.id()).collect();
+    assert_eq!(tickets.iter().map(|id| id.higher).collect::<Vec<_>>(), vec![1, 2, 3]);
+    let events: Vec<Id> = (0..3).map(|n| graph.new_vertex("event", data_map!{
+        name: format!("event {}", n)
+    }).wait().unwrap().cell.id()).collect();
+    assert!(events.windows(2).all(|pair| pair[0].higher < pair[1].higher));
+    // the strategy is stored with the schema
+    let ticket_schema = graph.schema_by_name("ticket").unwrap().0;
+    assert_eq!(server.schema_container.id_strategy(ticket_schema), IdStrategy::Sequential);
+}
+
+#[test]
+pub fn key_registry() {
+    use graph::keys;
+    let server = start_server(4030, "key_registry");
+    let graph = &server.graph;
+    let fields = vec![Field::new("email", TypeId::String as u32, false, false, None)];
+    graph.new_vertex_group(MorpheusSchema::new("account", Some(&vec!["email".to_string()]), &fields, false)).wait().unwrap();
+    graph.new_vertex_group(MorpheusSchema::new("note", None, &fields, false)).wait().unwrap();
+    let account = graph.new_vertex("account", data_map!{ email: "ada@example.com" }).wait().unwrap();
+    let key = Value::String("ada@example.com".to_string());
+    assert_eq!(graph.key_of(&account).wait().unwrap(), Some(key.clone()));
+    let note = graph.new_vertex("note", data_map!{ email: "ada@example.com" }).wait().unwrap();
+    assert_eq!(graph.key_of(&note).wait().unwrap(), None);
+    // the same key again is a plain duplicate
+    match graph.new_vertex("account", data_map!{ email: "ada@example.com" }).wait() {
+        Err(NewVertexError::WriteError(_)) => {},
+        other => panic!("{:?}", other)
+    }
+    // a different key on the id of the first one, as if its hash collided
+    let id = account.cell.id();
+    graph.graph_transaction(move |txn| {
+        let mut cell = txn.neb_txn.read(&id)?.unwrap();
+        if let Value::Map(ref mut map) = cell.data {
+            map.insert_key_id(*fields::KEY_KEY_ID, keys::encode(&Value::String("bob@example.com".to_string())).unwrap());
+        }
+        txn.neb_txn.update(&cell)
+    }).wait().unwrap();
+    match graph.new_vertex("account", data_map!{ email: "ada@example.com" }).wait() {
+        Err(NewVertexError::KeyCollision(existing)) => assert_eq!(existing, Value::String("bob@example.com".to_string())),
+        other => panic!("{:?}", other)
+    }
+}
+
+#[test]
+pub fn all_edges() {
+    let server = start_server(4031, "all_edges");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("city", None, &vec! [
+        Field::new("name", TypeId::String as u32, false, false, None)
+    ], false)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("road", None, &vec! [
+            Field::new("km", TypeId::F64 as u32, false, false, None)
+        ], false),
+        EdgeAttributes::new(EdgeType::Directed, true)
+    ).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("twinned", None, &EMPTY_FIELDS, false),
+        EdgeAttributes::new(EdgeType::Undirected, false)
+    ).wait().unwrap();
+    let city = |name: &str| graph.new_vertex("city", data_map!{ name: name }).wait().unwrap().cell.id();
+    let (a, b, c) = (city("a"), city("b"), city("c"));
+    graph.link(a, "road", b, Some(data_map!{ km: 1.5 })).wait().unwrap().unwrap();
+    graph.link(c, "road", a, Some(data_map!{ km: 4.0 })).wait().unwrap().unwrap();
+    graph.link(a, "twinned", c, None).wait().unwrap().unwrap();
+    let mut roads: Vec<(Id, Id, Value)> = graph.all_edges("road").collect().wait().unwrap().into_iter()
+        .map(|(from, to, body)| (from, to, body.unwrap().data["km"].clone()))
+        .collect();
+    roads.sort_by_key(|&(from, to, _)| (from.higher, from.lower, to.higher, to.lower));
+    let mut expected = vec![(a, b, Value::F64(1.5)), (c, a, Value::F64(4.0))];
+    expected.sort_by_key(|&(from, to, _)| (from.higher, from.lower, to.higher, to.lower));
+    assert_eq!(roads, expected);
+    // simple undirected edges come once, without a body
+    let twins = graph.all_edges("twinned").collect().wait().unwrap();
+    assert_eq!(twins.len(), 1);
+    let (x, y, ref body) = twins[0];
+    assert!(body.is_none());
+    assert!((x, y) == (a, c) || (x, y) == (c, a));
+}
+
+#[test]
+pub fn merge_vertices() {
+    use graph::merge::{MergeStrategy, FieldMerge};
+    let server = start_server(4032, "merge_vertices");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("person", None, &vec! [
+        Field::new("name", TypeId::String as u32, false, false, None),
+        Field::new("email", TypeId::String as u32, true, false, None),
+        Field::new("phone", TypeId::String as u32, true, false, None)
+    ], false)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("knows", None, &EMPTY_FIELDS, false),
+        EdgeAttributes::new(EdgeType::Directed, false).with_unique_pairs(true).with_self_loops(false)
+    ).wait().unwrap();
+    let person = |data: Map| graph.new_vertex("person", data).wait().unwrap().cell.id();
+    let ada = person(data_map!{ name: "Ada", phone: "555-0100" });
+    let ada_copy = person(data_map!{ name: "Ada L.", email: "ada@example.com", phone: "555-0199" });
+    let (bob, eve) = (person(data_map!{ name: "Bob" }), person(data_map!{ name: "Eve" }));
+    for &(from, to) in &[(ada, bob), (ada_copy, bob), (ada_copy, eve), (eve, ada_copy), (ada, ada_copy)] {
+        graph.link(from, "knows", to, None).wait().unwrap().unwrap();
+    }
+    let strategy = MergeStrategy::default().with_field("phone", FieldMerge::Duplicate).with_chunk_size(2);
+    let report = graph.merge_vertices(ada, ada_copy, strategy).wait().unwrap().unwrap();
+    assert_eq!((report.moved, report.deduplicated, report.dropped_loops), (2, 1, 1));
+    assert_eq!(report.chunks, 3);
+    assert!(!graph.vertex_exists(ada_copy).wait().unwrap());
+    let merged = graph.vertex_by(ada).wait().unwrap().unwrap();
+    assert_eq!(merged["name"], Value::String("Ada".to_string()));
+    assert_eq!(merged["email"], Value::String("ada@example.com".to_string()));
+    assert_eq!(merged["phone"], Value::String("555-0199".to_string()));
+    let mut outbound: Vec<Id> = graph.neighbourhoods(ada, "knows", EdgeDirection::Outbound, &None::<String>)
+        .wait().unwrap().unwrap().into_iter().map(|(vertex, _)| vertex.cell.id()).collect();
+    outbound.sort_by_key(|id| (id.higher, id.lower));
+    let mut expected = vec![bob, eve];
+    expected.sort_by_key(|id| (id.higher, id.lower));
+    assert_eq!(outbound, expected);
+    assert!(graph.has_edge(eve, "knows", ada).wait().unwrap().unwrap());
+    match graph.merge_vertices(ada, ada, MergeStrategy::default()).wait().unwrap() {
+        Err(merge::MergeError::SameVertex) => {},
+        other => panic!("{:?}", other)
+    }
+}
+
+#[test]
+pub fn rekey_and_split_vertices() {
+    let server = start_server(4033, "rekey_and_split_vertices");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("user", Some(&vec!["login".to_string()]), &vec! [
+        Field::new("login", TypeId::String as u32, false, false, None),
+        Field::new("city", TypeId::String as u32, true, false, None)
+    ], false)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("follows", None, &EMPTY_FIELDS, false),
+        EdgeAttributes::new(EdgeType::Directed, false)
+    ).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("rated", None, &vec! [
+            Field::new("stars", TypeId::U8 as u32, false, false, None)
+        ], false),
+        EdgeAttributes::new(EdgeType::Directed, true)
+    ).wait().unwrap();
+    let user = |login: &str| graph.new_vertex("user", data_map!{ login: login, city: "Paris" }).wait().unwrap().cell.id();
+    let (typo, bob) = (user("aad"), user("bob"));
+    graph.link(bob, "follows", typo, None).wait().unwrap().unwrap();
+    graph.link(typo, "rated", bob, Some(data_map!{ stars: 4 as u8 })).wait().unwrap().unwrap();
+    let ada = graph.rekey_vertex(typo, "ada").wait().unwrap().unwrap();
+    assert!(!graph.vertex_exists(typo).wait().unwrap());
+    let moved = graph.vertex_by_key("user", "ada").wait().unwrap().unwrap();
+    assert_eq!(moved.cell.id(), ada);
+    assert_eq!(moved["city"], Value::String("Paris".to_string()));
+    assert_eq!(graph.key_of(ada).wait().unwrap(), Some(Value::String("ada".to_string())));
+    assert!(graph.has_edge(bob, "follows", ada).wait().unwrap().unwrap());
+    let rated = graph.edges(ada, "rated", EdgeDirection::Outbound, &None::<String>).wait().unwrap().unwrap();
+    assert_eq!(rated.len(), 1);
+    assert_eq!(rated[0].get_data().as_ref().unwrap().data["stars"], Value::U8(4));
+    // the ratings move to a second account, the follows stay
+    let (bot, report) = graph.split_vertex(ada, "user", data_map!{ login: "ada-bot" }, vec!["rated"]).wait().unwrap().unwrap();
+    assert_eq!(report.moved, 1);
+    assert!(graph.has_edge(bot, "rated", bob).wait().unwrap().unwrap());
+    assert!(!graph.has_edge(ada, "rated", bob).wait().unwrap().unwrap());
+    assert!(graph.has_edge(bob, "follows", ada).wait().unwrap().unwrap());
+    match graph.rekey_vertex(ada, "bob").wait().unwrap() {
+        Err(rekey::RekeyError::NewVertexError(_)) => {},
+        other => panic!("{:?}", other)
+    }
+}
+
+#[test]
+pub fn rewire_edges() {
+    let server = start_server(4034, "rewire_edges");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("host", None, &vec! [
+        Field::new("name", TypeId::String as u32, false, false, None)
+    ], false)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("route", None, &vec! [
+            Field::new("metric", TypeId::U32 as u32, false, false, None)
+        ], false),
+        EdgeAttributes::new(EdgeType::Directed, true)
+    ).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("peer", None, &EMPTY_FIELDS, false),
+        EdgeAttributes::new(EdgeType::Undirected, false)
+    ).wait().unwrap();
+    let host = |name: &str| graph.new_vertex("host", data_map!{ name: name }).wait().unwrap().cell.id();
+    let (a, b, c) = (host("a"), host("b"), host("c"));
+    let route = graph.link(a, "route", b, Some(data_map!{ metric: 10 as u32 })).wait().unwrap().unwrap();
+    graph.link(a, "peer", b, None).wait().unwrap().unwrap();
+    let body_id = route.get_data().as_ref().unwrap().id();
+    let rewired = graph.graph_transaction(move |txn| {
+        let route = txn.edges(a, "route", EdgeDirection::Outbound, &None)?.unwrap().pop().unwrap();
+        let peer = txn.edges(a, "peer", EdgeDirection::Undirected, &None)?.unwrap().pop().unwrap();
+        txn.rewire_edge(&peer, c)?.unwrap();
+        Ok(txn.rewire_edge(&route, c)?.unwrap())
+    }).wait().unwrap();
+    assert_eq!(rewired.ends(), (&a, &c));
+    // the body cell stays, with its new end
+    assert_eq!(rewired.get_data().as_ref().unwrap().id(), body_id);
+    assert_eq!(graph.degree(b, "route", EdgeDirection::Inbound).wait().unwrap().unwrap(), 0);
+    let inbound = graph.edges(c, "route", EdgeDirection::Inbound, &None::<String>).wait().unwrap().unwrap();
+    assert_eq!(inbound.len(), 1);
+    assert_eq!(inbound[0].get_data().as_ref().unwrap().data["metric"], Value::U32(10));
+    assert!(graph.has_edge(a, "peer", c).wait().unwrap().unwrap());
+    assert!(!graph.has_edge(a, "peer", b).wait().unwrap().unwrap());
+    assert_eq!(graph.degree(b, "peer", EdgeDirection::Undirected).wait().unwrap().unwrap(), 0);
+}
+
+#[test]
+pub fn repository() {
+    use graph::model::{self, VertexModel, EdgeModel, ModelError};
+    use graph::repository::RepositoryError;
+    // what the derives in morpheus_derive write, they cannot be used from inside this crate
+    #[derive(Debug, Clone, PartialEq)]
+    struct Actor { name: String, born: u32 }
+    impl VertexModel for Actor {
+        fn schema_name() -> &'static str { "actor" }
+        fn key_field() -> Option<&'static str> { Some("name") }
+        fn fields() -> Vec<Field> { vec![model::model_field::<String>("name"), model::model_field::<u32>("born")] }
+        fn to_map(&self) -> Map {
+            let mut map = Map::new();
+            model::write_field(&mut map, "name", &self.name);
+            model::write_field(&mut map, "born", &self.born);
+            map
+        }
+        fn from_map(map: &Map) -> Result<Actor, ModelError> {
+            Ok(Actor { name: model::read_field(map, "name")?, born: model::read_field(map, "born")? })
+        }
+    }
+    #[derive(Debug, Clone, PartialEq)]
+    struct Film { title: String }
+    impl VertexModel for Film {
+        fn schema_name() -> &'static str { "film" }
+        fn key_field() -> Option<&'static str> { None }
+        fn fields() -> Vec<Field> { vec![model::model_field::<String>("title")] }
+        fn to_map(&self) -> Map {
+            let mut map = Map::new();
+            model::write_field(&mut map, "title", &self.title);
+            map
+        }
+        fn from_map(map: &Map) -> Result<Film, ModelError> {
+            Ok(Film { title: model::read_field(map, "title")? })
+        }
+    }
+    #[derive(Debug, Clone, PartialEq)]
+    struct Starred { role: String }
+    impl EdgeModel for Starred {
+        fn schema_name() -> &'static str { "starred" }
+        fn edge_type() -> EdgeType { EdgeType::Directed }
+        fn fields() -> Vec<Field> { vec![model::model_field::<String>("role")] }
+        fn to_map(&self) -> Map {
+            let mut map = Map::new();
+            model::write_field(&mut map, "role", &self.role);
+            map
+        }
+        fn from_map(map: &Map) -> Result<Starred, ModelError> {
+            Ok(Starred { role: model::read_field(map, "role")? })
+        }
+    }
+    let server = start_server(4035, "repository");
+    let graph = &server.graph;
+    graph.new_vertex_group_of::<Actor>().wait().unwrap();
+    graph.new_vertex_group_of::<Film>().wait().unwrap();
+    graph.new_edge_group_of::<Starred>().wait().unwrap();
+    let actors = graph.repository::<Actor>();
+    let films = graph.repository::<Film>();
+    let mut keanu = actors.create(Actor { name: "Keanu Reeves".to_string(), born: 1946 }).wait().unwrap();
+    let matrix = films.create(Film { title: "The Matrix".to_string() }).wait().unwrap();
+    actors.link_to(&keanu, &matrix, &Starred { role: "Neo".to_string() }).wait().unwrap();
+    keanu.model.born = 1964;
+    actors.update(&keanu).wait().unwrap();
+    let found = actors.find_by_key("Keanu Reeves").wait().unwrap().unwrap();
+    assert_eq!(found, keanu);
+    let starred = actors.linked::<Film, Starred>(&keanu, EdgeDirection::Outbound).wait().unwrap();
+    assert_eq!(starred, vec![(matrix.clone(), Starred { role: "Neo".to_string() })]);
+    // a film is no actor
+    match actors.find(matrix.id).wait() {
+        Err(RepositoryError::WrongSchema(id)) => assert_eq!(id, matrix.id),
+        other => panic!("{:?}", other)
+    }
+    actors.remove(&keanu).wait().unwrap();
+    assert!(actors.find(keanu.id).wait().unwrap().is_none());
+}
+
+#[test]
+pub fn blocking_graph() {
+    use error::ErrorKind;
+    let server = start_server(4036, "blocking_graph");
+    let graph = server.graph.blocking();
+    let person = MorpheusSchema::new("person", Some(&vec!["name".to_string()]), &vec![
+        Field::new("name", TypeId::String as u32, false, false, None)
+    ], false);
+    let knows = MorpheusSchema::new("knows", None, &EMPTY_FIELDS, false);
+    graph.new_vertex_group(person).unwrap();
+    graph.new_edge_group(knows, EdgeAttributes::new(EdgeType::Directed, false).with_self_loops(false)).unwrap();
+    let mut people = Vec::new();
+    for name in &["alice", "bob"] {
+        let mut data = Map::new();
+        data.insert("name", Value::String(name.to_string()));
+        people.push(graph.new_vertex("person", data).unwrap().cell.id());
+    }
+    let (alice, bob) = (people[0], people[1]);
+    graph.link(alice, "knows", bob, None).unwrap();
+    assert!(graph.has_edge(alice, "knows", bob).unwrap());
+    assert_eq!(graph.degree(alice, "knows", EdgeDirection::Outbound).unwrap(), 1);
+    let neighbours = graph.neighbourhoods(alice, "knows", EdgeDirection::Outbound, &None::<String>).unwrap();
+    assert_eq!(neighbours.len(), 1);
+    assert_eq!(neighbours[0].0.cell.id(), bob);
+    assert_eq!(graph.scan_vertices("person", &None::<String>, None).unwrap().len(), 2);
+    // errors carry the operation and vertex
+    let e = graph.link(alice, "knows", alice, None).unwrap_err();
+    match e.kind {
+        ErrorKind::LinkVertices(LinkVerticesError::SelfLoopNotAllowed) => {},
+        ref other => panic!("{:?}", other)
+    }
+    assert_eq!(e.operation, Some("link vertices"));
+    assert_eq!(e.vertex, Some(alice));
+    graph.remove_vertex(bob).unwrap();
+    assert!(graph.vertex_by(bob).unwrap().is_none());
+    assert!(graph.vertex_by_key("person", "alice").unwrap().is_some());
+}
+
+#[test]
+pub fn edge_endpoints() {
+    let server = start_server(4037, "edge_endpoints");
+    let graph = &server.graph;
+    let name_field = vec![Field::new("name", TypeId::String as u32, false, false, None)];
+    graph.new_vertex_group(MorpheusSchema::new("person", Some(&vec!["name".to_string()]), &name_field, false)).wait().unwrap();
+    graph.new_vertex_group(MorpheusSchema::new("company", Some(&vec!["name".to_string()]), &name_field, false)).wait().unwrap();
+    let works_at = MorpheusSchema::new("works_at", None, &EMPTY_FIELDS, false).with_endpoints(&["person"], &["company"]);
+    graph.new_edge_group(works_at, EdgeAttributes::new(EdgeType::Directed, false)).wait().unwrap();
+    let partner = MorpheusSchema::new("partner", None, &EMPTY_FIELDS, false).with_endpoints(&["person"], &["company"]);
+    graph.new_edge_group(partner, EdgeAttributes::new(EdgeType::Undirected, false)).wait().unwrap();
+    // vertex schemas have no endpoints
+    let restricted = MorpheusSchema::new("place", None, &name_field, false).with_endpoints(&["person"], &[]);
+    match graph.new_vertex_group(restricted).wait() {
+        Err(SchemaError::EndpointsOnVertexSchema) => {},
+        other => panic!("{:?}", other)
+    }
+    let vertex = |schema: &str, name: &str| {
+        let mut data = Map::new();
+        data.insert("name", Value::String(name.to_string()));
+        graph.new_vertex(schema, data).wait().unwrap().cell.id()
+    };
+    let (alice, bob, acme) = (vertex("person", "alice"), vertex("person", "bob"), vertex("company", "acme"));
+    graph.link(alice, "works_at", acme, None).wait().unwrap().unwrap();
+    match graph.link(acme, "works_at", alice, None).wait().unwrap() {
+        Err(LinkVerticesError::EndpointNotAllowed(id)) => assert_eq!(id, acme),
+        other => panic!("{:?}", other)
+    }
+    match graph.link(alice, "works_at", bob, None).wait().unwrap() {
+        Err(LinkVerticesError::EndpointNotAllowed(id)) => assert_eq!(id, bob),
+        other => panic!("{:?}", other)
+    }
+    // undirected edges fit in either order
+    graph.link(acme, "partner", bob, None).wait().unwrap().unwrap();
+    assert!(graph.link(alice, "partner", bob, None).wait().unwrap().is_err());
+    let schema = server.schema_container.from_name("works_at").unwrap();
+    assert_eq!(schema.endpoints.to, vec!["company".to_string()]);
+}
+
+#[test]
+pub fn export_dot() {
+    use graph::dot::DotStyle;
+    let server = start_server(4038, "export_dot");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("person", Some(&vec!["name".to_string()]), &vec![
+        Field::new("name", TypeId::String as u32, false, false, None)
+    ], false)).wait().unwrap();
+    graph.new_edge_group(MorpheusSchema::new("knows", None, &vec![
+        Field::new("since", TypeId::U32 as u32, false, false, None)
+    ], false), EdgeAttributes::new(EdgeType::Directed, true)).wait().unwrap();
+    let mut ids = Vec::new();
+    for name in &["alice", "bob \"the builder\""] {
+        let mut data = Map::new();
+        data.insert("name", Value::String(name.to_string()));
+        ids.push(graph.new_vertex("person", data).wait().unwrap().cell.id());
+    }
+    let mut since = Map::new();
+    since.insert("since", Value::U32(2010));
+    graph.link(ids[0], "knows", ids[1], Some(since)).wait().unwrap().unwrap();
+    let style = DotStyle::default().with_vertex_field("name").with_edge_field("since").with_rank_dir("LR");
+    let dot = graph.export_dot(vec![ids[0]], vec!["knows"], 1, &None::<String>, style).wait().unwrap().unwrap();
+    assert!(dot.starts_with("digraph \"morpheus\" {\n  rankdir=LR;\n"));
+    assert!(dot.contains("v0 [label=\"alice\""));
+    assert!(dot.contains("v1 [label=\"bob \\\"the builder\\\"\""));
+    assert!(dot.contains("v0 -> v1 [label=\"2010\"];"));
+    assert!(dot.ends_with("}\n"));
+}
+
+#[test]
+pub fn storage_quotas() {
+    use graph::quota::{QuotaOptions, QuotaLimits, QuotaKind};
+    let server = start_server(4039, "storage_quotas");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("tenant_item", None, &vec![
+        Field::new("name", TypeId::String as u32, false, false, None)
+    ], false)).wait().unwrap();
+    fn item(name: &str) -> Map {
+        let mut data = Map::new();
+        data.insert("name", Value::String(name.to_string()));
+        data
+    }
+    // written before the quota, found by the recount
+    let first = graph.new_vertex("tenant_item", item("first")).wait().unwrap().cell.id();
+    graph.set_quotas(QuotaOptions::default()
+        .with_schema_limits("tenant_item", QuotaLimits { max_vertices: Some(2), max_bytes: None }));
+    graph.recount_quota_usage().wait().unwrap();
+    graph.new_vertex("tenant_item", item("second")).wait().unwrap();
+    match graph.new_vertex("tenant_item", item("third")).wait() {
+        Err(NewVertexError::QuotaExceeded(e)) => {
+            assert_eq!(e.kind, QuotaKind::Vertices);
+            assert_eq!(e.limit, 2);
+        },
+        other => panic!("{:?}", other.map(|v| v.cell.id()))
+    }
+    // transactions are held to the same limits
+    let refused = graph.graph_transaction(move |txn| txn.new_vertex("tenant_item", item("fourth")))
+        .wait().unwrap();
+    assert!(refused.is_err());
+    let reports = graph.quota_usage().wait().unwrap();
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].schema, Some("tenant_item".to_string()));
+    assert_eq!(reports[0].usage.vertices, 2);
+    assert!(reports[0].usage.bytes > 0);
+    // removing frees room
+    graph.remove_vertex(first).wait().unwrap().unwrap();
+    graph.new_vertex("tenant_item", item("third")).wait().unwrap();
+}
+
+#[test]
+pub fn result_limits() {
+    use graph::result_limit::{ResultLimits, OverflowKind};
+    let server = start_server(4041, "result_limits");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("hub_node", None, &EMPTY_FIELDS, true)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("hub_link", None, &EMPTY_FIELDS, false),
+        EdgeAttributes::new(EdgeType::Directed, false)
+    ).wait().unwrap();
+    let hub = graph.new_vertex("hub_node", Map::new()).wait().unwrap();
+    for _ in 0..5 {
+        let spoke = graph.new_vertex("hub_node", Map::new()).wait().unwrap();
+        graph.link(&hub, "hub_link", &spoke, None).wait().unwrap().unwrap();
+    }
+    graph.set_result_limits(ResultLimits::new(Some(3), None));
+    match graph.neighbourhoods(&hub, "hub_link", EdgeDirection::Outbound, &None::<String>).wait().unwrap() {
+        Err(NeighbourhoodError::Overflow(overflow)) => {
+            assert_eq!(overflow.kind, OverflowKind::Count);
+            assert_eq!(overflow.limit, 3);
+        },
+        other => panic!("{:?}", other.map(|n| n.len()))
+    }
+    match graph.edges(&hub, "hub_link", EdgeDirection::Outbound, &None::<String>).wait().unwrap() {
+        Err(EdgeError::Overflow(_)) => {},
+        other => panic!("{:?}", other.map(|e| e.len()))
+    }
+    // a page below the cap passes
+    let page = graph.traverse_neighbours(&hub, TraverseOptions {
+        direction: EdgeDirection::Outbound,
+        limit: Some(3),
+        ..TraverseOptions::default()
+    }).wait().unwrap().unwrap();
+    assert_eq!(page.len(), 3);
+    graph.set_result_limits(ResultLimits::new(None, Some(64)));
+    match graph.edges(&hub, "hub_link", EdgeDirection::Outbound, &None::<String>).wait().unwrap() {
+        Err(EdgeError::Overflow(overflow)) => assert_eq!(overflow.kind, OverflowKind::Bytes),
+        other => panic!("{:?}", other.map(|e| e.len()))
+    }
+    graph.set_result_limits(ResultLimits::default());
+    assert_eq!(graph.neighbourhoods(&hub, "hub_link", EdgeDirection::Outbound, &None::<String>)
+        .wait().unwrap().unwrap().len(), 5);
+}
+
+#[test]
+pub fn memory_budget() {
+    use graph::memory_budget::{MemoryBudget, MemoryBudgetOptions};
+    let server = start_server(4042, "memory_budget");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("budget_node", None, &EMPTY_FIELDS, true)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("budget_link", None, &EMPTY_FIELDS, false),
+        EdgeAttributes::new(EdgeType::Directed, false)
+    ).wait().unwrap();
+    let hub = graph.new_vertex("budget_node", Map::new()).wait().unwrap();
+    for _ in 0..3 {
+        let spoke = graph.new_vertex("budget_node", Map::new()).wait().unwrap();
+        graph.link(&hub, "budget_link", &spoke, None).wait().unwrap().unwrap();
+    }
+    let budget = MemoryBudget::new(MemoryBudgetOptions::new(1024).with_queue_timeout_ms(0));
+    graph.set_memory_budget(budget.clone());
+    // alone on the server, a traversal runs whatever its size
+    assert_eq!(graph.neighbourhoods(&hub, "budget_link", EdgeDirection::Outbound, &None::<String>)
+        .wait().unwrap().unwrap().len(), 3);
+    assert_eq!(budget.used(), 0);
+    let held = MemoryBudget::reserve(&budget, 1000).unwrap();
+    match graph.neighbourhoods(&hub, "budget_link", EdgeDirection::Outbound, &None::<String>).wait().unwrap() {
+        Err(NeighbourhoodError::BudgetExceeded(e)) => {
+            assert_eq!(e.used, 1000);
+            assert_eq!(e.max_bytes, 1024);
+        },
+        other => panic!("{:?}", other.map(|n| n.len()))
+    }
+    drop(held);
+    assert_eq!(budget.used(), 0);
+    assert_eq!(graph.neighbourhoods(&hub, "budget_link", EdgeDirection::Outbound, &None::<String>)
+        .wait().unwrap().unwrap().len(), 3);
+}
+
+#[test]
+pub fn prefetch() {
+    use graph::cache::QueryCacheOptions;
+    use graph::vertex_cache::VertexCacheOptions;
+    let server = start_server(4043, "prefetch");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("account", None, &EMPTY_FIELDS, true)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("follows_account", None, &EMPTY_FIELDS, false),
+        EdgeAttributes::new(EdgeType::Directed, false)
+    ).wait().unwrap();
+    let star = graph.new_vertex("account", Map::new()).wait().unwrap();
+    for _ in 0..4 {
+        let fan = graph.new_vertex("account", Map::new()).wait().unwrap();
+        graph.link(&fan, "follows_account", &star, None).wait().unwrap().unwrap();
+    }
+    // nowhere to keep anything yet
+    let report = graph.prefetch(vec![&star], vec!["follows_account"], EdgeDirection::Inbound).wait().unwrap();
+    assert_eq!(report.neighbourhoods, 0);
+    let query_cache = graph.enable_query_cache(QueryCacheOptions::default());
+    let vertex_cache = graph.enable_vertex_cache(VertexCacheOptions::default());
+    let report = graph.prefetch(vec![&star], vec!["follows_account"], EdgeDirection::Inbound).wait().unwrap();
+    assert_eq!(report.vertices, 1);
+    assert_eq!(report.neighbourhoods, 1);
+    assert_eq!(report.neighbours, 4);
+    assert!(report.failed.is_empty());
+    assert_eq!(query_cache.len(), 1);
+    assert_eq!(vertex_cache.len(), 5);
+    assert_eq!(graph.neighbourhoods(&star, "follows_account", EdgeDirection::Inbound, &None::<String>)
+        .wait().unwrap().unwrap().len(), 4);
+}
+
+#[test]
+pub fn sampling() {
+    use graph::sample::{SampleSize, Reservoir};
+    use std::collections::HashSet;
+    let server = start_server(4049, "sampling");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("member", None, &EMPTY_FIELDS, true)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("member_of", None, &EMPTY_FIELDS, false),
+        EdgeAttributes::new(EdgeType::Directed, false)
+    ).wait().unwrap();
+    let club = graph.new_vertex("member", Map::new()).wait().unwrap();
+    let mut members = HashSet::new();
+    for _ in 0..20 {
+        let member = graph.new_vertex("member", Map::new()).wait().unwrap();
+        graph.link(&member, "member_of", &club, None).wait().unwrap().unwrap();
+        members.insert(member.cell.id());
+    }
+    let sample = graph.sample_vertices("member", SampleSize::Count(5)).wait().unwrap();
+    assert_eq!(sample.len(), 5);
+    assert_eq!(sample.iter().map(|v| v.cell.id()).collect::<HashSet<_>>().len(), 5);
+    assert_eq!(graph.sample_vertices("member", SampleSize::Count(100)).wait().unwrap().len(), 21);
+    assert_eq!(graph.sample_vertices("member", SampleSize::Fraction(1.0)).wait().unwrap().len(), 21);
+    assert!(graph.sample_vertices("member", SampleSize::Fraction(0.0)).wait().unwrap().is_empty());
+    let neighbours = graph.sample_neighbours(&club, "member_of", EdgeDirection::Inbound, 3).wait().unwrap().unwrap();
+    assert_eq!(neighbours.len(), 3);
+    assert!(neighbours.iter().all(|&(ref vertex, _)| members.contains(&vertex.cell.id())));
+    assert!(graph.sample_neighbours(&club, "member_of", EdgeDirection::Outbound, 3).wait().unwrap().unwrap().is_empty());
+    // every item has the same chance to be kept
+    let mut kept = vec![0; 4];
+    for _ in 0..4000 {
+        let mut reservoir = Reservoir::new(1);
+        for i in 0..4 { reservoir.offer(i); }
+        kept[reservoir.into_vec()[0]] += 1;
+    }
+    assert!(kept.iter().all(|&n| n > 800 && n < 1200), "{:?}", kept);
+}
+
+#[test]
+pub fn window_counters() {
+    use graph::window_counter::{WindowCounter, CounterEnds, CounterBuckets};
+    let server = start_server(4050, "window_counters");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("post", None, &EMPTY_FIELDS, true)).wait().unwrap();
+    let likes_24h = WindowCounter::new("likes_24h", 86400, 24, CounterEnds::To);
+    graph.new_edge_group(
+        MorpheusSchema::new("likes", None, &EMPTY_FIELDS, false).with_window_counter(likes_24h.clone()),
+        EdgeAttributes::new(EdgeType::Directed, false)
+    ).wait().unwrap();
+    let invalid = MorpheusSchema::new("viewed", None, &EMPTY_FIELDS, false)
+        .with_window_counter(WindowCounter::new("views", 0, 24, CounterEnds::To));
+    match graph.new_edge_group(invalid, EdgeAttributes::new(EdgeType::Directed, false)).wait() {
+        Err(SchemaError::InvalidWindowCounter(name)) => assert_eq!(name, "views"),
+        other => panic!("{:?}", other)
+    }
+    let post = graph.new_vertex("post", Map::new()).wait().unwrap();
+    let fans: Vec<_> = (0..3).map(|_| graph.new_vertex("post", Map::new()).wait().unwrap()).collect();
+    for fan in &fans {
+        graph.link(fan, "likes", &post, None).wait().unwrap().unwrap();
+    }
+    assert_eq!(graph.window_count(&post, "likes_24h").wait().unwrap(), 3);
+    assert_eq!(graph.window_count(&fans[0], "likes_24h").wait().unwrap(), 0);
+    // unlinking does not take the like back, a rolled back link does
+    let post_id = post.cell.id();
+    let (unliked_id, fan_id) = (fans[0].cell.id(), fans[1].cell.id());
+    graph.graph_transaction(move |txn| {
+        txn.unlink(unliked_id, "likes", post_id)?.unwrap();
+        let savepoint = txn.savepoint();
+        txn.link(fan_id, "likes", post_id, None)?.unwrap();
+        txn.rollback_to(&savepoint)?.unwrap();
+        Ok(())
+    }).wait().unwrap();
+    graph.bump_counter(&post, likes_24h.clone(), 2).wait().unwrap();
+    assert_eq!(graph.window_counts(vec![post_id, fan_id], "likes_24h").wait().unwrap(), vec![5, 0]);
+    // buckets age out one hour at a time
+    let mut buckets = CounterBuckets::new(&likes_24h);
+    buckets.add(3600 * 100, 4);
+    buckets.add(3600 * 110, 1);
+    assert_eq!(buckets.total_at(3600 * 123), 5);
+    assert_eq!(buckets.total_at(3600 * 124), 1);
+    assert_eq!(buckets.total_at(3600 * 200), 0);
+    // a clock running behind adds to the latest bucket
+    buckets.add(3600 * 105, 1);
+    assert_eq!(buckets.total_at(3600 * 124), 2);
+}
+
+#[test]
+pub fn schema_migration() {
+    use graph::id_list::{self, ID_LINKED_LIST};
+    use graph::migrate::{self, Migration, SchemaMigrationOptions};
+    use neb::ram::schema::Schema;
+    use std::sync::Arc;
+    // id lists stored before packed blocks, segment counts and chain tails migrate to the layout of this binary
+    let old_layout = ID_LINKED_LIST.sub_fields.as_ref().unwrap()[..2].to_vec();
+    let old_id_list = Field::new("*", TypeId::Map as u32, false, false, Some(old_layout));
+    let stored = Arc::new(Schema::new_with_id(id_list::ID_LIST_SCHEMA_ID, "_NEB_ID_LIST", None, old_id_list, false));
+    let needed = migrate::base_migration(&stored, &ID_LINKED_LIST).unwrap();
+    assert_eq!(needed.fields.sub_fields.as_ref().unwrap().len(), 7);
+    assert!(migrate::base_migration(&Arc::new(Schema::new_with_id(
+        id_list::ID_LIST_SCHEMA_ID, "_NEB_ID_LIST", None, ID_LINKED_LIST.clone(), false
+    )), &ID_LINKED_LIST).is_none());
+    // cells keep their values across the rewrite and take the new field
+    let server = start_server(4051, "schema_migration");
+    let neb_client = &server.neb_client;
+    let schema_id = 9001;
+    let count = Field::new("count", TypeId::U64 as u32, false, false, None);
+    let label = Field::new("label", TypeId::String as u32, true, false, None);
+    let legacy = || Schema::new_with_id(schema_id, "legacy", None,
+        Field::new("*", TypeId::Map as u32, false, false, Some(vec![count.clone()])), false);
+    neb_client.new_schema_with_id(legacy()).wait().unwrap();
+    let ids: Vec<Id> = (1..4).map(|n| Id::new(1, n)).collect();
+    for (n, id) in ids.iter().enumerate() {
+        let mut data = Map::new();
+        data.insert("count", Value::U64(n as u64));
+        neb_client.write_cell(Cell::new_with_id(schema_id, id, Value::Map(data))).wait().unwrap().unwrap();
+    }
+    let spill_dir = ::std::env::temp_dir().join("morpheus-schema-migration");
+    let options = SchemaMigrationOptions::new(spill_dir.to_str().unwrap());
+    let migration = Migration {
+        stored: Arc::new(legacy()),
+        fields: Field::new("*", TypeId::Map as u32, false, false, Some(vec![count.clone(), label]))
+    };
+    assert_eq!(migration.run(neb_client, &options).unwrap(), 3);
+    assert!(!migration.is_spilled(&options));
+    for (n, &id) in ids.iter().enumerate() {
+        let cell = server.graph.graph_transaction(move |txn| {
+            let mut cell = txn.neb_txn.read(&id)?.unwrap();
+            cell.data["label"] = Value::String("migrated".to_string());
+            txn.neb_txn.update(&cell)?;
+            Ok(cell)
+        }).wait().unwrap();
+        assert_eq!(cell.data["count"], Value::U64(n as u64));
+        let cell = server.graph.graph_transaction(move |txn| Ok(txn.neb_txn.read(&id)?.unwrap())).wait().unwrap();
+        assert_eq!(cell.data["label"], Value::String("migrated".to_string()));
+    }
+}
+
+#[test]
+pub fn neighbourhood_reads() {
+    let server = start_server(4054, "neighbourhood_reads");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("person", None, &Vec::new(), true)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("knows", None, &Vec::new(), false),
+        EdgeAttributes::new(EdgeType::Directed, false)
+    ).wait().unwrap();
+    let hub = graph.new_vertex("person", Map::new()).wait().unwrap().cell.id();
+    let friends: Vec<Id> = (0..40).map(|_| graph.new_vertex("person", Map::new()).wait().unwrap().cell.id()).collect();
+    for friend in &friends {
+        graph.link(hub, "knows", *friend, None).wait().unwrap().unwrap();
+    }
+    // a parallel edge, its vertex is read once and handed out for both edges
+    graph.link(hub, "knows", friends[0], None).wait().unwrap().unwrap();
+    let mut expected: Vec<Id> = friends.clone();
+    expected.push(friends[0]);
+    expected.sort_by_key(|id| (id.higher, id.lower));
+    let in_txn = graph.graph_transaction(move |txn| {
+        Ok(txn.neighbourhoods(hub, "knows", EdgeDirection::Outbound, &None)?.unwrap())
+    }).wait().unwrap();
+    let mut read: Vec<Id> = in_txn.iter().map(|&(ref vertex, _)| vertex.cell.id()).collect();
+    read.sort_by_key(|id| (id.higher, id.lower));
+    assert_eq!(read, expected);
+    let outside = graph.neighbourhoods(hub, "knows", EdgeDirection::Outbound, &None::<String>).wait().unwrap().unwrap();
+    let mut read: Vec<Id> = outside.iter().map(|&(ref vertex, _)| vertex.cell.id()).collect();
+    read.sort_by_key(|id| (id.higher, id.lower));
+    assert_eq!(read, expected);
+    // a vertex not committed yet is read through the transaction that created it
+    let (fresh, read_fresh) = graph.graph_transaction(move |txn| {
+        let fresh = txn.new_vertex("person", Map::new())?.unwrap().cell.id();
+        txn.link(hub, "knows", fresh, None)?.unwrap();
+        let neighbours = txn.neighbourhoods(hub, "knows", EdgeDirection::Outbound, &None)?.unwrap();
+        Ok((fresh, neighbours.iter().any(|&(ref vertex, _)| vertex.cell.id() == fresh)))
+    }).wait().unwrap();
+    assert!(read_fresh);
+    graph.remove_vertex(fresh).wait().unwrap().unwrap();
+    // an edge to a vertex gone without unlinking is reported, not skipped
+    let gone = friends[1];
+    graph.graph_transaction(move |txn| txn.neb_txn.remove(&gone)).wait().unwrap();
+    match graph.neighbourhoods(hub, "knows", EdgeDirection::Outbound, &None::<String>).wait().unwrap() {
+        Err(NeighbourhoodError::VertexNotFound(id)) => assert_eq!(id, gone),
+        other => panic!("{:?}", other.map(|n| n.len()))
+    }
+}
+
+#[test]
+pub fn supernode_shards() {
+    use graph::id_list::{IdList, ShardOptions};
+    use graph::fields::OUTBOUND_KEY_ID;
+    let server = start_server(4056, "supernode_shards");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("hub", None, &Vec::new(), true)).wait().unwrap();
+    let hub = graph.new_vertex("hub", Map::new()).wait().unwrap().cell.id();
+    let shards = ShardOptions { threshold: 8, count: 4 };
+    let add = move |from: u64, to: u64| graph.graph_transaction(move |txn| {
+        let mut list = IdList::from_txn_and_container(txn.neb_txn, &hub, *OUTBOUND_KEY_ID, 1).with_shards(shards);
+        for n in from..to {
+            list.add(&Id::new(1, n))?.unwrap();
+        }
+        list.is_sharded()
+    }).wait().unwrap();
+    assert!(!add(0, 5));
+    // the list splits once it holds the threshold, later ids go to the shards
+    assert!(add(5, 30));
+    let (count, contained, missing) = graph.graph_transaction(move |txn| {
+        let mut list = IdList::from_txn_and_container(txn.neb_txn, &hub, *OUTBOUND_KEY_ID, 1);
+        let contained = (0..30).all(|n| list.contains(&Id::new(1, n)).unwrap().unwrap());
+        let missing = list.contains(&Id::new(1, 30))?.unwrap();
+        Ok((list.count()?.unwrap(), contained, missing))
+    }).wait().unwrap();
+    assert_eq!(count, 30);
+    assert!(contained);
+    assert!(!missing);
+    // one id from the original chain, one from a shard
+    let remaining = graph.graph_transaction(move |txn| {
+        let mut list = IdList::from_txn_and_container(txn.neb_txn, &hub, *OUTBOUND_KEY_ID, 1);
+        list.remove(&Id::new(1, 2), false)?.unwrap();
+        list.remove(&Id::new(1, 20), false)?.unwrap();
+        let count = list.count()?.unwrap();
+        let iterated: Vec<Id> = list.iter()?.unwrap().collect();
+        Ok((count, iterated, list.all()?.unwrap()))
+    }).wait().unwrap();
+    let (count, mut iterated, mut all) = remaining;
+    let expected: Vec<Id> = (0..30).filter(|&n| n != 2 && n != 20).map(|n| Id::new(1, n)).collect();
+    assert_eq!(count, 28);
+    iterated.sort_by_key(|id| id.lower);
+    all.sort_by_key(|id| id.lower);
+    assert_eq!(iterated, expected);
+    assert_eq!(all, expected);
+}
+
+#[test]
+pub fn unified_errors() {
+    use error::ErrorKind;
+    let server = start_server(4057, "unified_errors");
+    let graph = server.graph.unified();
+    graph.new_vertex_group(MorpheusSchema::new("person", None, &EMPTY_FIELDS, false)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("knows", None, &EMPTY_FIELDS, false),
+        EdgeAttributes::new(EdgeType::Directed, false).with_self_loops(false)
+    ).wait().unwrap();
+    let alice = graph.new_vertex("person", Map::new()).wait().unwrap().cell.id();
+    // the operation, the vertex and the schema name come with the error
+    let e = graph.link(alice, "knows", alice, None).wait().unwrap_err();
+    match e.kind {
+        ErrorKind::LinkVertices(LinkVerticesError::SelfLoopNotAllowed) => {},
+        ref other => panic!("{:?}", other)
+    }
+    assert_eq!(e.operation, Some("link vertices"));
+    assert_eq!(e.vertex, Some(alice));
+    assert_eq!(e.schema, Some("knows".to_string()));
+    let e = graph.new_vertex("knows", Map::new()).wait().unwrap_err();
+    match e.kind {
+        ErrorKind::NewVertex(NewVertexError::SchemaNotVertex(_)) => {},
+        ref other => panic!("{:?}", other)
+    }
+    assert_eq!(e.schema, Some("knows".to_string()));
+    assert!(format!("{}", e).starts_with("new vertex: cannot create vertex"));
+    // schemas that cannot be created fail with their name
+    let e = graph.new_edge_group(
+        MorpheusSchema::new("likes", None, &vec![Field::new("weight", TypeId::U32 as u32, false, false, None)], false),
+        EdgeAttributes::new(EdgeType::Directed, false)
+    ).wait().unwrap_err();
+    assert_eq!(e.operation, Some("new edge group"));
+    assert_eq!(e.schema, Some("likes".to_string()));
+}
+
+#[cfg(feature = "std-future")]
+#[test]
+pub fn std_futures() {
+    use futures_executor::block_on;
+    let server = start_server(4058, "std_futures");
+    let graph = server.graph.std_futures();
+    block_on(graph.new_vertex_group(MorpheusSchema::new("person", None, &EMPTY_FIELDS, false))).unwrap();
+    block_on(graph.new_edge_group(
+        MorpheusSchema::new("knows", None, &EMPTY_FIELDS, false),
+        EdgeAttributes::new(EdgeType::Directed, false).with_self_loops(false)
+    )).unwrap();
+    let alice = block_on(graph.new_vertex("person", Map::new())).unwrap().cell.id();
+    let bob = block_on(graph.new_vertex("person", Map::new())).unwrap().cell.id();
+    block_on(graph.link(alice, "knows", bob, None)).unwrap();
+    assert_eq!(block_on(graph.degree(alice, "knows", EdgeDirection::Outbound)).unwrap(), 1);
+    let e = block_on(graph.link(alice, "knows", alice, None)).unwrap_err();
+    assert_eq!(e.schema, Some("knows".to_string()));
+}
+
+#[test]
+pub fn trace_spans() {
+    use utils::trace::Span;
+    let mut span = Span::enter("test_span");
+    span.record("txn", 7);
+    span.fail(&"broken");
+    let line = span.line();
+    assert!(line.starts_with(&format!("span=test_span id={} elapsed_us=", span.id())));
+    assert!(line.ends_with(" txn=7 error=\"broken\""));
+}
+
+#[test]
+pub fn existence_checks() {
+    let server = start_server(4059, "existence_checks");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("person", None, &EMPTY_FIELDS, false)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("friend", None, &vec![Field::new("since", TypeId::U32 as u32, false, false, None)], false),
+        EdgeAttributes::new(EdgeType::Undirected, true)
+    ).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("follows", None, &EMPTY_FIELDS, false),
+        EdgeAttributes::new(EdgeType::Directed, false)
+    ).wait().unwrap();
+    let a = graph.new_vertex("person", Map::new()).wait().unwrap().cell.id();
+    let b = graph.new_vertex("person", Map::new()).wait().unwrap().cell.id();
+    let mut since = Map::new();
+    since.insert("since", Value::U32(2001));
+    let friendship = graph.link(a, "friend", b, Some(since)).wait().unwrap().unwrap();
+    graph.link(a, "follows", b, None).wait().unwrap().unwrap();
+    // a bodied undirected edge is found from either end, but from is not its own opposite
+    assert!(graph.has_edge(a, "friend", b).wait().unwrap().unwrap());
+    assert!(graph.has_edge(b, "friend", a).wait().unwrap().unwrap());
+    assert!(!graph.has_edge(a, "friend", a).wait().unwrap().unwrap());
+    assert!(!graph.has_edge(b, "friend", b).wait().unwrap().unwrap());
+    assert!(graph.has_edge(a, "follows", b).wait().unwrap().unwrap());
+    assert!(!graph.has_edge(b, "follows", a).wait().unwrap().unwrap());
+    // only vertices exist as vertices, the cell of an edge does not
+    assert!(graph.vertex_exists(a).wait().unwrap());
+    assert!(!graph.vertex_exists(Id::new(a.higher, a.lower + 1)).wait().unwrap());
+    let edge_cell = match friendship {
+        edge::Edge::Undirected(ref edge) => edge.get_data().as_ref().unwrap().id(),
+        ref other => panic!("{:?}", other)
+    };
+    assert!(!graph.vertex_exists(edge_cell).wait().unwrap());
+}
+
+#[test]
+pub fn corrupt_adjacency() {
+    use graph::id_list::{IdList, IdListError, PACKED_KEY_ID};
+    use graph::fields::OUTBOUND_KEY_ID;
+    use utils::transaction::set_map_by_key_id;
+    use error::{self, ErrorKind};
+    let server = start_server(4060, "corrupt_adjacency");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("person", None, &EMPTY_FIELDS, false)).wait().unwrap();
+    let follows = graph.new_edge_group(
+        MorpheusSchema::new("follows", None, &EMPTY_FIELDS, false),
+        EdgeAttributes::new(EdgeType::Directed, false)
+    ).wait().unwrap();
+    let a = graph.new_vertex("person", Map::new()).wait().unwrap().cell.id();
+    let b = graph.new_vertex("person", Map::new()).wait().unwrap().cell.id();
+    graph.link(a, "follows", b, None).wait().unwrap().unwrap();
+    // a packed block announcing five ids and holding none
+    graph.graph_transaction(move |txn| {
+        let head = adjacency_head(txn.neb_txn, a, *OUTBOUND_KEY_ID, follows)?;
+        set_map_by_key_id(txn.neb_txn, &head, *PACKED_KEY_ID, Value::Array(vec![Value::U8(5)]))?;
+        Ok(())
+    }).wait().unwrap();
+    let all = graph.graph_transaction(move |txn| {
+        IdList::from_txn_and_container(txn.neb_txn, &a, *OUTBOUND_KEY_ID, follows).all()
+    }).wait().unwrap();
+    match all {
+        Err(IdListError::FormatError) => {},
+        other => panic!("{:?}", other)
+    }
+    match graph.edges(a, "follows", EdgeDirection::Outbound, &None::<String>).wait().unwrap() {
+        Err(EdgeError::IdListError(IdListError::FormatError)) => {},
+        other => panic!("{:?}", other)
+    }
+    let compacted = error::flatten(graph.compact_adjacency(a).wait());
+    match compacted.unwrap_err().kind {
+        ErrorKind::IdList(IdListError::FormatError) => {},
+        other => panic!("{:?}", other)
+    }
+}
+
+// first segment of the adjacency list a vertex keeps for an edge schema
+fn adjacency_head(txn: &Transaction, vertex: Id, field: u64, schema_id: u32) -> Result<Id, TxnError> {
+    use graph::id_list::{ID_TYPES_MAP_ID, ID_TYPES_LIST_ID, ID_TYPES_SCHEMA_ID_ID};
+    let type_list_id = match txn.read(&vertex)?.unwrap().data[field] {
+        Value::Id(id) => id, ref other => panic!("{:?}", other)
+    };
+    match txn.read(&type_list_id)?.unwrap().data[*ID_TYPES_MAP_ID] {
+        Value::Array(ref types) => {
+            let pair = types.iter().find(|pair| pair[*ID_TYPES_SCHEMA_ID_ID] == Value::U32(schema_id)).unwrap();
+            match pair[*ID_TYPES_LIST_ID] {
+                Value::Id(id) => Ok(id), ref other => panic!("{:?}", other)
+            }
+        },
+        ref other => panic!("{:?}", other)
+    }
+}
+
+#[test]
+pub fn packed_segment_lookup() {
+    use graph::id_list::{IdList, ShardOptions, SKIP_KEY_ID};
+    use graph::fields::OUTBOUND_KEY_ID;
+    use utils::transaction::set_map_by_key_id;
+    let server = start_server(4061, "packed_segment_lookup");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("hub", None, &Vec::new(), true)).wait().unwrap();
+    let hub = graph.new_vertex("hub", Map::new()).wait().unwrap().cell.id();
+    let unsharded = ShardOptions { threshold: usize::max_value(), count: 0 };
+    let added: Vec<Id> = (0..300).map(|n| Id::new(1 + n % 3, 1000 - n)).collect();
+    let to_add = added.clone();
+    let skip_len = graph.graph_transaction(move |txn| {
+        let mut list = IdList::from_txn_and_container(txn.neb_txn, &hub, *OUTBOUND_KEY_ID, 1).with_shards(unsharded);
+        for id in &to_add {
+            list.add(id)?.unwrap();
+        }
+        let head = adjacency_head(txn.neb_txn, hub, *OUTBOUND_KEY_ID, 1)?;
+        Ok(match txn.neb_txn.read(&head)?.unwrap().data[*SKIP_KEY_ID] {
+            Value::Array(ref skip) => skip.len(), ref other => panic!("{:?}", other)
+        })
+    }).wait().unwrap();
+    // 256 ids were packed, one restart point every 64 of them
+    assert_eq!(skip_len, 3 * 4);
+    let lookups = move |txn: &GraphTransaction| {
+        let mut list = IdList::from_txn_and_container(txn.neb_txn, &hub, *OUTBOUND_KEY_ID, 1);
+        let mut found = Vec::new();
+        for n in 0..302 {
+            found.push(list.contains(&Id::new(1 + n % 3, 1000 - n))?.unwrap());
+        }
+        for &absent in &[Id::new(1, 1001), Id::new(2, 500), Id::new(4, 1000), Id::new(0, 1)] {
+            found.push(list.contains(&absent)?.unwrap());
+        }
+        Ok(found)
+    };
+    let expected: Vec<bool> = (0..306).map(|n| n < 300).collect();
+    assert_eq!(graph.graph_transaction(lookups).wait().unwrap(), expected);
+    // packing sorts a segment, only the raw tail keeps the order ids were added in
+    let iterated = graph.graph_transaction(move |txn| {
+        IdList::from_txn_and_container(txn.neb_txn, &hub, *OUTBOUND_KEY_ID, 1).all()
+    }).wait().unwrap().unwrap();
+    let mut packed = added[..256].to_vec();
+    packed.sort_by_key(|id| (id.higher, id.lower));
+    assert_eq!(&iterated[..256], &packed[..]);
+    assert_eq!(&iterated[256..], &added[256..]);
+    // blocks packed before restart points were recorded are searched whole, compaction indexes them
+    graph.graph_transaction(move |txn| {
+        let head = adjacency_head(txn.neb_txn, hub, *OUTBOUND_KEY_ID, 1)?;
+        set_map_by_key_id(txn.neb_txn, &head, *SKIP_KEY_ID, Value::Null)
+    }).wait().unwrap();
+    assert_eq!(graph.graph_transaction(lookups).wait().unwrap(), expected);
+    let skip = graph.graph_transaction(move |txn| {
+        IdList::from_txn_and_container(txn.neb_txn, &hub, *OUTBOUND_KEY_ID, 1).compact()?.unwrap();
+        let head = adjacency_head(txn.neb_txn, hub, *OUTBOUND_KEY_ID, 1)?;
+        Ok(txn.neb_txn.read(&head)?.unwrap().data[*SKIP_KEY_ID].clone())
+    }).wait().unwrap();
+    match skip {
+        Value::Array(ref skip) => assert_eq!(skip.len(), 3 * 5),
+        other => panic!("{:?}", other)
+    }
+    assert_eq!(graph.graph_transaction(lookups).wait().unwrap(), expected);
+}
+
+#[test]
+pub fn approximate_counts() {
+    use graph::window_counter::now_secs;
+    let server = start_server(4062, "approximate_counts");
+    let graph = &server.graph;
+    let counted = graph.new_vertex_group(MorpheusSchema::new("counted", None, &EMPTY_FIELDS, false)).wait().unwrap();
+    let stale = graph.new_vertex_group(MorpheusSchema::new("stale", None, &EMPTY_FIELDS, false)).wait().unwrap();
+    let add = |schema: u32, n: usize| for _ in 0..n {
+        graph.new_vertex(schema, Map::new()).wait().unwrap();
+    };
+    let count = |schema: u32, mode: CountMode| {
+        let estimate = graph.count_vertices::<_, String>(schema, &None, mode).wait().unwrap();
+        (estimate.count, estimate.exact)
+    };
+    add(counted, 3);
+    // nothing recorded yet, the scan records what it counted
+    assert_eq!(count(counted, CountMode::Approximate), (3, true));
+    add(counted, 2);
+    assert_eq!(count(counted, CountMode::Approximate), (3, false));
+    assert_eq!(count(counted, CountMode::Exact), (5, true));
+    assert_eq!(count(counted, CountMode::Approximate), (5, false));
+    // collected statistics replace older counts only
+    graph.record_count(counted, 42, now_secs());
+    graph.record_count(counted, 1, now_secs() - 100);
+    assert_eq!(count(counted, CountMode::Approximate), (42, false));
+    // past the age bound a recorded count is dropped and the schema is counted again
+    graph.set_count_max_age(Duration::from_secs(60));
+    add(stale, 2);
+    graph.record_count(stale, 9, now_secs() - 3600);
+    assert_eq!(count(stale, CountMode::Approximate), (2, true));
+    assert_eq!(count(stale, CountMode::Approximate), (2, false));
+}
+
+#[test]
+pub fn lazy_filter_degrees() {
+    use query::{self, parse_optional_expr};
+    let server = start_server(4065, "lazy_filter_degrees");
+    let graph = &server.graph;
+    udf::register("test-popular", |args| match args.get(0) {
+        Some(&Value::U64(degree)) => Ok(Value::Bool(degree >= 2)),
+        _ => Err("test-popular takes a degree".to_string())
+    }).unwrap();
+    udf::register("test-is-id", |args| Ok(Value::Bool(match args.get(0) { Some(&Value::Id(_)) => true, _ => false }))).unwrap();
+    let parsed = |filter: &str| parse_optional_expr(&Some(filter)).unwrap();
+    assert!(query::refers_to_degrees(&parsed("(test-popular in_degree)")));
+    assert!(query::refers_to_degrees(&parsed("(test-popular out_degree)")));
+    assert!(!query::refers_to_degrees(&parsed("(test-is-id vertex_id)")));
+    assert!(!query::refers_to_degrees(&None));
+    graph.new_vertex_group(MorpheusSchema::new("person", None, &EMPTY_FIELDS, false)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("follows", None, &EMPTY_FIELDS, false),
+        EdgeAttributes::new(EdgeType::Directed, false)
+    ).wait().unwrap();
+    let people: Vec<Id> = (0..3).map(|_| graph.new_vertex("person", Map::new()).wait().unwrap().cell.id()).collect();
+    for &(from, to) in &[(0, 1), (0, 2), (2, 1)] {
+        graph.link(people[from], "follows", people[to], None).wait().unwrap().unwrap();
+    }
+    let opposite = |filter: &str| -> Vec<Id> {
+        let mut ids: Vec<Id> = graph.edges(people[0], "follows", EdgeDirection::Outbound, &Some(filter.to_string()))
+            .wait().unwrap().unwrap().iter().map(|e| *e.one_opposite_id_vertex_id(&people[0]).unwrap()).collect();
+        ids.sort_by_key(|id| (id.higher, id.lower));
+        ids
+    };
+    // degrees are counted for filters that refer to them
+    assert_eq!(opposite("(test-popular in_degree)"), vec![people[1]]);
+    let mut both = vec![people[1], people[2]];
+    both.sort_by_key(|id| (id.higher, id.lower));
+    assert_eq!(opposite("(test-is-id vertex_id)"), both);
+    let popular = graph.neighbourhoods(people[0], "follows", EdgeDirection::Outbound, &Some("(test-popular in_degree)"))
+        .wait().unwrap().unwrap();
+    assert_eq!(popular.iter().map(|&(ref v, _)| v.cell.id()).collect::<Vec<_>>(), vec![people[1]]);
+}
+
+#[test]
+pub fn session_versions() {
+    use graph::session::SessionOptions;
+    let server = start_server(4066, "session_versions");
+    let graph = &server.graph;
+    let noted = graph.new_vertex_group(MorpheusSchema::new("noted", None, &vec![
+        Field::new("note", TypeId::String as u32, false, false, None)
+    ], false)).wait().unwrap();
+    let options = SessionOptions { max_tracked: 2, ..SessionOptions::default() };
+    let session = Session::with_options(graph, options);
+    let first = session.new_vertex(noted, data_map!{ note: "first" }).wait().unwrap();
+    assert_eq!(first.version(), 0);
+    session.update_vertex(&first, |mut vertex| {
+        vertex["note"] = Value::String(String::from("updated"));
+        Some(vertex)
+    }).wait().unwrap();
+    let read = session.vertex_by(&first).wait().unwrap().unwrap();
+    assert_eq!(read.version(), 1);
+    assert_eq!(read["note"].String().unwrap(), "updated");
+    // past max_tracked the first vertex is forgotten and still reads
+    session.new_vertex(noted, data_map!{ note: "second" }).wait().unwrap();
+    session.new_vertex(noted, data_map!{ note: "third" }).wait().unwrap();
+    assert_eq!(session.tracked(), 2);
+    assert_eq!(session.vertex_by(&first).wait().unwrap().unwrap().version(), 1);
+    assert_eq!(session.tracked(), 2);
+    session.clear();
+    assert_eq!(session.tracked(), 0);
+}
+
+#[test]
+pub fn read_transaction_batches() {
+    use std::cell::Cell as Counter;
+    let server = start_server(4067, "read_transaction_batches");
+    let graph = &server.graph;
+    let item = graph.new_vertex_group(MorpheusSchema::new("item", None, &vec![
+        Field::new("name", TypeId::String as u32, false, false, None)
+    ], false)).wait().unwrap();
+    let first = graph.new_vertex(item, data_map!{ name: "first" }).wait().unwrap().cell.id();
+    let second = graph.new_vertex(item, data_map!{ name: "second" }).wait().unwrap().cell.id();
+    let absent = Id::new(first.higher, first.lower + 1000);
+    let runs = Counter::new(0);
+    let names = graph.read_transaction(|txn| {
+        runs.set(runs.get() + 1);
+        let read = txn.read_vertices(vec![second, absent, first])?;
+        // a write to a cell read by the first run makes it run again
+        if runs.get() == 1 {
+            graph.update_vertex(first, |mut vertex| {
+                vertex["name"] = Value::String(String::from("renamed"));
+                Some(vertex)
+            }).wait().unwrap();
+        }
+        Ok(read.into_iter().map(|v| v.map(|v| v["name"].String().unwrap().clone())).collect::<Vec<_>>())
+    }).wait().unwrap();
+    assert_eq!(runs.get(), 2);
+    assert_eq!(names, vec![Some(String::from("second")), None, Some(String::from("renamed"))]);
+}
+
+#[test]
+pub fn remove_vertices_where() {
+    let server = start_server(4090, "remove_vertices_where");
+    let graph = &server.graph;
+    let isolated = graph.new_vertex_group(MorpheusSchema::new("isolated", None, &EMPTY_FIELDS, false)).wait().unwrap();
+    let linked = graph.new_vertex_group(MorpheusSchema::new("linked", None, &EMPTY_FIELDS, false)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("linked_to", None, &EMPTY_FIELDS, false),
+        EdgeAttributes::new(EdgeType::Directed, true)
+    ).wait().unwrap();
+    for _ in 0..3 { graph.new_vertex(isolated, Map::new()).wait().unwrap(); }
+    let progress: Vec<_> = graph.remove_vertices_where::<_, String>(isolated, &None, 2)
+        .collect().wait().unwrap().into_iter().map(|p| (p.scanned, p.removed)).collect();
+    assert_eq!(progress, vec![(2, 2), (3, 3)]);
+    assert_eq!(graph.scan_vertices::<_, String>(isolated, &None, None).collect().wait().unwrap().len(), 0);
+    // an edge whose body went missing cannot be removed with its vertex, the error is reported as is
+    let a = graph.new_vertex(linked, Map::new()).wait().unwrap();
+    let b = graph.new_vertex(linked, Map::new()).wait().unwrap();
+    let edge = graph.link(&a, "linked_to", &b, Some(Map::new())).wait().unwrap().unwrap();
+    let body_id = edge.get_data().as_ref().unwrap().id();
+    let _ = server.neb_client.remove_cell(body_id).wait().unwrap();
+    match graph.remove_vertices_where::<_, String>(linked, &None, 2).collect().wait() {
+        Err(RemoveVerticesError::RemoveError(RemoveError::EdgeError(EdgeError::CellNotFound))) => {},
+        other => panic!("{:?}", other)
+    }
+    // read only graphs refuse before scanning
+    graph.set_read_only(true);
+    match graph.remove_vertices_where::<_, String>(linked, &None, 2).collect().wait() {
+        Err(RemoveVerticesError::ReadOnly) => {},
+        other => panic!("{:?}", other)
+    }
+}
+
+#[test]
+pub fn id_list_counts() {
+    use graph::id_list::{IdList, ShardOptions};
+    use graph::fields::OUTBOUND_KEY_ID;
+    let server = start_server(4091, "id_list_counts");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("hub", None, &Vec::new(), true)).wait().unwrap();
+    let hub = graph.new_vertex("hub", Map::new()).wait().unwrap().cell.id();
+    let (a, b) = (Id::new(1, 1), Id::new(1, 2));
+    // removal takes out the given id only, one occurrence or all of them
+    let remaining = graph.graph_transaction(move |txn| {
+        let mut list = IdList::from_txn_and_container(txn.neb_txn, &hub, *OUTBOUND_KEY_ID, 1);
+        let occurrences = |list: &mut IdList| -> Result<_, TxnError> {
+            let all = list.all()?.unwrap();
+            let count = list.count()?.unwrap();
+            Ok((count, all.iter().filter(|&id| *id == a).count(), all.iter().filter(|&id| *id == b).count()))
+        };
+        for id in &[a, b, a, b, a] { list.add(id)?.unwrap(); }
+        list.remove(&a, false)?.unwrap();
+        let one_removed = occurrences(&mut list)?;
+        list.remove(&a, true)?.unwrap();
+        Ok((one_removed, occurrences(&mut list)?))
+    }).wait().unwrap();
+    assert_eq!(remaining, ((4, 2, 2), (2, 0, 2)));
+    // clearing resets the cached count and forgets the shards
+    let shards = ShardOptions { threshold: 4, count: 2 };
+    let cleared = graph.graph_transaction(move |txn| {
+        let mut list = IdList::from_txn_and_container(txn.neb_txn, &hub, *OUTBOUND_KEY_ID, 1).with_shards(shards);
+        for n in 10..20 { list.add(&Id::new(1, n))?.unwrap(); }
+        let before = (list.count()?.unwrap(), list.is_sharded()?);
+        list.clear_segments()?.unwrap();
+        Ok((before, (list.count()?.unwrap(), list.is_sharded()?)))
+    }).wait().unwrap();
+    assert_eq!(cleared, ((12, true), (0, false)));
+}
+
+#[test]
+pub fn self_loops() {
+    let server = start_server(4092, "self_loops");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("node", None, &EMPTY_FIELDS, false)).wait().unwrap();
+    let edge_schema = |name: &str, attrs: EdgeAttributes| {
+        graph.new_edge_group(MorpheusSchema::new(name, None, &EMPTY_FIELDS, false), attrs).wait().unwrap()
+    };
+    let cases = vec![
+        // schema, body, direction the degree is read from, degree with the loop
+        (edge_schema("directed_twice", EdgeAttributes::new(EdgeType::Directed, false)),
+            None, EdgeDirection::Outbound, 1),
+        (edge_schema("directed_once", EdgeAttributes::new(EdgeType::Directed, false)
+            .with_self_loops_count_twice(false)), None, EdgeDirection::Inbound, 1),
+        (edge_schema("undirected_twice", EdgeAttributes::new(EdgeType::Undirected, false)),
+            None, EdgeDirection::Undirected, 2),
+        (edge_schema("undirected_once", EdgeAttributes::new(EdgeType::Undirected, false)
+            .with_self_loops_count_twice(false)), None, EdgeDirection::Undirected, 1),
+        (edge_schema("undirected_body_twice", EdgeAttributes::new(EdgeType::Undirected, true)),
+            Some(Map::new()), EdgeDirection::Undirected, 2),
+        (edge_schema("undirected_body_once", EdgeAttributes::new(EdgeType::Undirected, true)
+            .with_self_loops_count_twice(false)), Some(Map::new()), EdgeDirection::Undirected, 1)
+    ];
+    for (schema, body, direction, degree) in cases {
+        let node = graph.new_vertex("node", Map::new()).wait().unwrap().cell.id();
+        graph.link(node, schema, node, body).wait().unwrap().unwrap();
+        assert_eq!(graph.degree(node, schema, direction).wait().unwrap().unwrap(), degree, "schema {}", schema);
+        // the loop is listed once whatever it counts for
+        let edges = graph.edges::<_, _, String>(node, schema, direction, &None).wait().unwrap().unwrap();
+        assert_eq!(edges.len(), 1, "schema {}", schema);
+        let removed = graph.graph_transaction(move |txn| txn.unlink(node, schema, node)).wait().unwrap().unwrap();
+        assert_eq!(removed, 1, "schema {}", schema);
+        assert_eq!(graph.degree(node, schema, direction).wait().unwrap().unwrap(), 0, "schema {}", schema);
+    }
+}
+
+#[test]
+pub fn scan_edges() {
+    use std::collections::HashSet;
+    let server = start_server(4093, "scan_edges");
+    let graph = &server.graph;
+    let node = graph.new_vertex_group(MorpheusSchema::new("node", None, &EMPTY_FIELDS, false)).wait().unwrap();
+    let edge_schema = |name: &str, attrs: EdgeAttributes| {
+        graph.new_edge_group(MorpheusSchema::new(name, None, &EMPTY_FIELDS, false), attrs).wait().unwrap()
+    };
+    let follows = edge_schema("follows", EdgeAttributes::new(EdgeType::Directed, false));
+    let knows = edge_schema("knows", EdgeAttributes::new(EdgeType::Undirected, false));
+    let rated = edge_schema("rated", EdgeAttributes::new(EdgeType::Directed, true));
+    let nodes: Vec<Id> = (0..4).map(|_| graph.new_vertex(node, Map::new()).wait().unwrap().cell.id()).collect();
+    let pairs = vec![(0, 1), (0, 2), (2, 3), (3, 3)];
+    for &(from, to) in &pairs {
+        for &schema in &[follows, knows] {
+            graph.link(nodes[from], schema, nodes[to], None).wait().unwrap().unwrap();
+        }
+        graph.link(nodes[from], rated, nodes[to], Some(Map::new())).wait().unwrap().unwrap();
+    }
+    let scanned = |schema: u32| -> Vec<(Id, Id, bool)> {
+        graph.scan_edges::<_, String>(schema, &None).collect().wait().unwrap().iter().map(|edge| {
+            assert_eq!(edge.schema_id(), schema);
+            let (from, to) = edge.ends();
+            (*from, *to, edge.get_data().is_some())
+        }).collect()
+    };
+    let expected: HashSet<_> = pairs.iter().map(|&(from, to)| (nodes[from], nodes[to])).collect();
+    // every edge comes once, simple edges from the lists of their vertices and bodied ones from their cells
+    for &(schema, bodied) in &[(follows, false), (rated, true)] {
+        let edges = scanned(schema);
+        assert_eq!(edges.len(), pairs.len());
+        assert_eq!(edges.iter().map(|&(from, to, _)| (from, to)).collect::<HashSet<_>>(), expected);
+        assert!(edges.iter().all(|&(_, _, has_body)| has_body == bodied));
+    }
+    // undirected edges sit in both lists but are emitted once, either way round
+    let undirected = scanned(knows);
+    assert_eq!(undirected.len(), pairs.len());
+    assert!(undirected.iter().all(|&(a, b, _)| expected.contains(&(a, b)) || expected.contains(&(b, a))));
+    match graph.scan_edges::<_, String>(node, &None).collect().wait() {
+        Err(ScanEdgesError::SchemaNotEdge) => {},
+        other => panic!("{:?}", other.map(|edges| edges.len()))
+    }
+    match graph.scan_edges::<_, String>(9999, &None).collect().wait() {
+        Err(ScanEdgesError::SchemaNotFound) => {},
+        other => panic!("{:?}", other.map(|edges| edges.len()))
+    }
+}
+
+#[test]
+pub fn orphan_gc() {
+    use graph::gc::OrphanGcOptions;
+    use std::sync::atomic::AtomicBool;
+    let server = start_server(4095, "orphan_gc");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("user", None, &EMPTY_FIELDS, false)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("knows", None, &EMPTY_FIELDS, false),
+        EdgeAttributes::new(EdgeType::Directed, false)
+    ).wait().unwrap();
+    let users: Vec<Id> = (0..3).map(|_| graph.new_vertex("user", Map::new()).wait().unwrap().cell.id()).collect();
+    let (a, b, c) = (users[0], users[1], users[2]);
+    graph.link(a, "knows", b, None).wait().unwrap().unwrap();
+    graph.link(a, "knows", c, None).wait().unwrap().unwrap();
+    let options = OrphanGcOptions { cells_per_second: 10000, ..OrphanGcOptions::default() };
+    let running = AtomicBool::new(true);
+    let round = graph.collect_orphans(&options, &running).unwrap();
+    assert_eq!((round.vertices_checked, round.repaired), (3, 0));
+    // remove b behind the graph's back, the round drops it from the list of a
+    graph.graph_transaction(move |txn| txn.neb_txn.remove(&b)).wait().unwrap();
+    let round = graph.collect_orphans(&options, &running).unwrap();
+    assert_eq!((round.vertices_checked, round.repaired), (2, 1));
+    assert_eq!(graph.degree(a, "knows", EdgeDirection::Outbound).wait().unwrap().unwrap(), 1);
+    // read only graphs are left alone
+    graph.graph_transaction(move |txn| txn.neb_txn.remove(&c)).wait().unwrap();
+    graph.set_read_only(true);
+    let round = graph.collect_orphans(&options, &running).unwrap();
+    assert_eq!((round.vertices_checked, round.repaired), (0, 0));
+    graph.set_read_only(false);
+    // a stopped collector gives up after its first page
+    running.store(false, ::std::sync::atomic::Ordering::Relaxed);
+    assert!(graph.collect_orphans(&options, &running).is_none());
+}
+
+// A module with the exports graph::wasm expects, `alloc` always hands out offset 1024. Every function
+// given takes (ptr, len) and returns the packed result, the data is placed at offset 0 and
+// `morpheus.call` is function 0.
+#[cfg(feature = "wasm")]
+fn wasm_module(memory_pages: u32, data: &[u8], funcs: Vec<(&str, Vec<::parity_wasm::elements::Instruction>)>) -> Vec<u8> {
+    use parity_wasm;
+    use parity_wasm::elements::{
+        Module, Section, Type, FunctionType, ValueType, TypeSection, ImportSection, ImportEntry, External,
+        FunctionSection, Func, MemorySection, MemoryType, ExportSection, ExportEntry, Internal, CodeSection,
+        FuncBody, Instructions, Instruction, DataSection, DataSegment, InitExpr
+    };
+    let call_type = Type::Function(FunctionType::new(vec![ValueType::I32, ValueType::I32], Some(ValueType::I64)));
+    let alloc_type = Type::Function(FunctionType::new(vec![ValueType::I32], Some(ValueType::I32)));
+    let mut entries = vec![Func::new(1)];
+    let mut bodies = vec![FuncBody::new(vec![], Instructions::new(vec![Instruction::I32Const(1024), Instruction::End]))];
+    let mut exports = vec![
+        ExportEntry::new("memory".to_string(), Internal::Memory(0)),
+        ExportEntry::new("alloc".to_string(), Internal::Function(1))
+    ];
+    for (index, (name, instructions)) in funcs.into_iter().enumerate() {
+        entries.push(Func::new(0));
+        bodies.push(FuncBody::new(vec![], Instructions::new(instructions)));
+        exports.push(ExportEntry::new(name.to_string(), Internal::Function(index as u32 + 2)));
+    }
+    let module = Module::new(vec![
+        Section::Type(TypeSection::with_types(vec![call_type, alloc_type])),
+        Section::Import(ImportSection::with_entries(vec![
+            ImportEntry::new("morpheus".to_string(), "call".to_string(), External::Function(0))
+        ])),
+        Section::Function(FunctionSection::with_entries(entries)),
+        Section::Memory(MemorySection::with_entries(vec![MemoryType::new(memory_pages, None)])),
+        Section::Export(ExportSection::with_entries(exports)),
+        Section::Code(CodeSection::with_bodies(bodies)),
+        Section::Data(DataSection::with_entries(vec![
+            DataSegment::new(0, InitExpr::new(vec![Instruction::I32Const(0), Instruction::End]), data.to_vec())
+        ]))
+    ]);
+    parity_wasm::serialize(module).unwrap()
+}
+
+#[cfg(feature = "wasm")]
+#[test]
+pub fn wasm_plugins() {
+    use graph::wasm::{self, WasmModule, WasmLimits, WasmError};
+    use graph::procedure::ProcedureError;
+    use parity_wasm::elements::{Instruction, BlockType};
+    use std::sync::Arc;
+    let server = start_server(4096, "wasm_plugins");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("plugin_user", None, &EMPTY_FIELDS, true)).wait().unwrap();
+    let request = br#"{"op":"new_vertex","schema":"plugin_user"}"#;
+    let bytes = wasm_module(1, request, vec![
+        // hands its argument array back
+        ("echo", vec![
+            Instruction::GetLocal(0), Instruction::I64ExtendUI32, Instruction::I64Const(32), Instruction::I64Shl,
+            Instruction::GetLocal(1), Instruction::I64ExtendUI32, Instruction::I64Or, Instruction::End
+        ]),
+        // sends the request in the data segment to the host and returns the response
+        ("create", vec![
+            Instruction::I32Const(0), Instruction::I32Const(request.len() as i32), Instruction::Call(0), Instruction::End
+        ]),
+        ("spin", vec![
+            Instruction::Loop(BlockType::NoResult), Instruction::Br(0), Instruction::End,
+            Instruction::Unreachable, Instruction::End
+        ])
+    ]);
+    let module = Arc::new(WasmModule::compile(&bytes, WasmLimits::default()).unwrap());
+    graph.deploy_procedure("wasm_echo", wasm::procedure(module.clone(), "echo")).unwrap();
+    match graph.call_procedure("wasm_echo", vec![Value::String("hello".to_string()), Value::Bool(true)]).wait().unwrap() {
+        Value::Array(ref items) if items.len() == 2 => match (&items[0], &items[1]) {
+            (&Value::String(ref hello), &Value::Bool(true)) if hello == "hello" => {},
+            other => panic!("{:?}", other)
+        },
+        other => panic!("{:?}", other)
+    }
+    // procedures reach the graph in their own transaction
+    graph.deploy_procedure("wasm_create", wasm::procedure(module.clone(), "create")).unwrap();
+    match graph.call_procedure("wasm_create", vec![]).wait().unwrap() {
+        Value::Map(_) => {},
+        other => panic!("{:?}", other)
+    }
+    assert_eq!(graph.count_vertices("plugin_user", &None::<String>, CountMode::Exact).wait().unwrap().count, 1);
+    // gas runs out long before the loop ends
+    let metered = Arc::new(WasmModule::compile(&bytes, WasmLimits { max_gas: 10000, ..WasmLimits::default() }).unwrap());
+    graph.deploy_procedure("wasm_spin", wasm::procedure(metered, "spin")).unwrap();
+    match graph.call_procedure("wasm_spin", vec![]).wait() {
+        Err(ProcedureError::Failed(ref reason)) if reason == "OutOfGas" => {},
+        other => panic!("{:?}", other)
+    }
+    wasm::register_function("test-wasm-echo", module, "echo").unwrap();
+    assert!(udf::registered().contains(&"test-wasm-echo".to_string()));
+    match WasmModule::compile(b"not a module", WasmLimits::default()).err() {
+        Some(WasmError::InvalidModule(_)) => {},
+        other => panic!("{:?}", other)
+    }
+    match WasmModule::compile(&wasm_module(32, b"", vec![]), WasmLimits::default()).err() {
+        Some(WasmError::InvalidModule(_)) => {},
+        other => panic!("{:?}", other)
+    }
+}
+
+#[test]
+pub fn bodied_has_edge_index() {
+    use graph::id_list::IdList;
+    use graph::fields::{OUTBOUND_KEY_ID, INBOUND_KEY_ID, UNDIRECTED_KEY_ID};
+    let server = start_server(4098, "bodied_has_edge_index");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("station", None, &vec! [
+        Field::new("name", TypeId::String as u32, false, false, None)
+    ], false)).wait().unwrap();
+    let rail = graph.new_edge_group(
+        MorpheusSchema::new("rail", None, &vec! [
+            Field::new("km", TypeId::U32 as u32, false, false, None)
+        ], false),
+        EdgeAttributes::new(EdgeType::Directed, true)
+    ).wait().unwrap();
+    let ferry = graph.new_edge_group(
+        MorpheusSchema::new("ferry", None, &vec! [
+            Field::new("km", TypeId::U32 as u32, false, false, None)
+        ], false),
+        EdgeAttributes::new(EdgeType::Undirected, true)
+    ).wait().unwrap();
+    let station = |name: &str| graph.new_vertex("station", data_map!{ name: name }).wait().unwrap().cell.id();
+    let (a, b, c) = (station("a"), station("b"), station("c"));
+    let index = move |vertex: Id, field: u64, schema: u32| graph.graph_transaction(move |txn| {
+        let mut index = IdList::opposite_index(txn.neb_txn, &vertex, field, schema);
+        Ok(if index.is_assigned()?.unwrap() { Some(index.all()?.unwrap()) } else { None })
+    }).wait().unwrap();
+    graph.link(a, "rail", b, Some(data_map!{ km: 5 as u32 })).wait().unwrap().unwrap();
+    assert!(graph.has_edge(a, "rail", b).wait().unwrap().unwrap());
+    assert!(!graph.has_edge(b, "rail", a).wait().unwrap().unwrap());
+    assert!(!graph.has_edge(a, "rail", c).wait().unwrap().unwrap());
+    assert_eq!(index(a, *OUTBOUND_KEY_ID, rail), Some(vec![b]));
+    assert_eq!(index(b, *INBOUND_KEY_ID, rail), Some(vec![a]));
+    // a list written before the index existed is still answered, and indexed on its next change
+    graph.graph_transaction(move |txn| {
+        IdList::opposite_index(txn.neb_txn, &a, *OUTBOUND_KEY_ID, rail).discard()
+    }).wait().unwrap().unwrap();
+    assert_eq!(index(a, *OUTBOUND_KEY_ID, rail), None);
+    assert!(graph.has_edge(a, "rail", b).wait().unwrap().unwrap());
+    graph.link(a, "rail", c, Some(data_map!{ km: 7 as u32 })).wait().unwrap().unwrap();
+    assert_eq!(index(a, *OUTBOUND_KEY_ID, rail), Some(vec![b, c]));
+    assert!(graph.has_edge(a, "rail", c).wait().unwrap().unwrap());
+    // rewiring moves the entries of both ends
+    graph.graph_transaction(move |txn| {
+        let to_b = txn.edges(a, "rail", EdgeDirection::Outbound, &None)?.unwrap().into_iter()
+            .find(|edge| edge.ends().1 == &b).unwrap();
+        txn.rewire_edge(&to_b, c)?.unwrap();
+        Ok(())
+    }).wait().unwrap();
+    assert!(!graph.has_edge(a, "rail", b).wait().unwrap().unwrap());
+    assert_eq!(index(a, *OUTBOUND_KEY_ID, rail), Some(vec![c, c]));
+    assert_eq!(index(b, *INBOUND_KEY_ID, rail), Some(vec![]));
+    assert_eq!(index(c, *INBOUND_KEY_ID, rail), Some(vec![a, a]));
+    assert_eq!(graph.graph_transaction(move |txn| txn.unlink(a, "rail", c)).wait().unwrap().unwrap(), 2);
+    assert!(!graph.has_edge(a, "rail", c).wait().unwrap().unwrap());
+    assert_eq!(index(a, *OUTBOUND_KEY_ID, rail), Some(vec![]));
+    assert_eq!(index(c, *INBOUND_KEY_ID, rail), Some(vec![]));
+    // undirected edges are indexed on both ends, a self-loop once
+    graph.link(a, "ferry", b, Some(data_map!{ km: 3 as u32 })).wait().unwrap().unwrap();
+    graph.link(c, "ferry", c, Some(data_map!{ km: 0 as u32 })).wait().unwrap().unwrap();
+    assert!(graph.has_edge(b, "ferry", a).wait().unwrap().unwrap());
+    assert!(graph.has_edge(c, "ferry", c).wait().unwrap().unwrap());
+    assert_eq!(index(c, *UNDIRECTED_KEY_ID, ferry), Some(vec![c]));
+    // the index goes with the vertex
+    graph.remove_vertex(b).wait().unwrap().unwrap();
+    assert!(!graph.has_edge(a, "ferry", b).wait().unwrap().unwrap());
+    assert_eq!(index(a, *UNDIRECTED_KEY_ID, ferry), Some(vec![]));
+}
+
+#[test]
+pub fn unique_pairs() {
+    let server = start_server(4099, "unique_pairs");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("person", None, &vec! [
+        Field::new("name", TypeId::String as u32, false, false, None)
+    ], false)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("married", None, &EMPTY_FIELDS, false),
+        EdgeAttributes::new(EdgeType::Undirected, false).with_unique_pairs(true)
+    ).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("reviewed", None, &vec! [
+            Field::new("stars", TypeId::U8 as u32, false, false, None)
+        ], false),
+        EdgeAttributes::new(EdgeType::Directed, true).with_unique_pairs(true)
+    ).wait().unwrap();
+    let person = |name: &str| graph.new_vertex("person", data_map!{ name: name }).wait().unwrap().cell.id();
+    let (ada, bob, eve) = (person("ada"), person("bob"), person("eve"));
+    let duplicate = |result: Result<edge::Edge, LinkVerticesError>| match result {
+        Err(LinkVerticesError::EdgeError(EdgeError::DuplicateEdge)) => {},
+        other => panic!("{:?}", other)
+    };
+    graph.link(ada, "married", bob, None).wait().unwrap().unwrap();
+    // an undirected pair is the same pair from either end
+    duplicate(graph.link(bob, "married", ada, None).wait().unwrap());
+    assert_eq!(graph.degree(bob, "married", EdgeDirection::Undirected).wait().unwrap().unwrap(), 1);
+    graph.link(ada, "reviewed", bob, Some(data_map!{ stars: 4 as u8 })).wait().unwrap().unwrap();
+    duplicate(graph.link(ada, "reviewed", bob, Some(data_map!{ stars: 5 as u8 })).wait().unwrap());
+    // a directed pair has a direction
+    graph.link(bob, "reviewed", ada, Some(data_map!{ stars: 3 as u8 })).wait().unwrap().unwrap();
+    graph.link(ada, "reviewed", eve, Some(data_map!{ stars: 2 as u8 })).wait().unwrap().unwrap();
+    assert_eq!(graph.degree(ada, "reviewed", EdgeDirection::Outbound).wait().unwrap().unwrap(), 2);
+    // rewiring onto a linked pair is a duplicate too
+    let rewired = graph.graph_transaction(move |txn| {
+        let to_eve = txn.edges(ada, "reviewed", EdgeDirection::Outbound, &None)?.unwrap().into_iter()
+            .find(|edge| edge.ends().1 == &eve).unwrap();
+        txn.rewire_edge(&to_eve, bob)
+    }).wait().unwrap();
+    duplicate(rewired);
+    // the pair can be linked again once unlinked
+    graph.graph_transaction(move |txn| txn.unlink(bob, "married", ada)).wait().unwrap().unwrap();
+    graph.link(bob, "married", ada, None).wait().unwrap().unwrap();
+    assert_eq!(graph.degree(ada, "married", EdgeDirection::Undirected).wait().unwrap().unwrap(), 1);
+}
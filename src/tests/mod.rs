@@ -1,21 +1,807 @@
 use neb::server::ServerOptions;
-use server::MorpheusServer;
+use server::{MorpheusServer, MorpheusServerOptions, EmbeddedOptions};
+use server::namespace::{GraphOptions, OpenGraphError};
+use server::schema::{MorpheusSchema, SchemaContainer, SchemaError, SchemaType};
+use server::admin::{AdminService, AdminError, Service as AdminRpc};
+use server::audit::{AuditAction, AuditQuery};
+use server::stats;
+use neb::ram::types::{Map, TypeId, Value};
+use neb::ram::schema::Field;
+use server::auth::{AuthOptions, AuthError, Role, Grant, Permission, Scope, Resource};
+use graph::{EdgeDirection, CountMode};
+use graph::batch::LinkBatchOptions;
+use graph::edge::{EdgeAttributes, EdgeType};
+use graph::encryption::KeyProviderOptions;
+use graph::ids::IdStrategy;
+use import::ImportError;
+use import::checkpoint::Checkpoint;
+use import::neo4j::{self, Neo4jCsvOptions};
+use import::rdf::{self, RdfOptions};
+use import::scrub::{ScrubOptions, ScrubRule};
+use import::stream;
+use server::graphql;
+use server::rate_limit::{RateLimitOptions, RateLimit, RateClass};
+use server::traversal::RoutingError;
+use server::replication::{ReplicationService, ReplicaOptions, ReplicatedEvent, ConflictPolicy, Service as ReplicationRpc};
+use graph::changes::{ChangeEvent, ChangeKind};
+use neb::ram::types::Id;
 use config;
 use std::sync::Arc;
-use futures::Future;
+use std::{env, fs};
+use futures::{Future, future};
 
 mod graph;
 
 pub fn start_server<'a>(port: u32, group: &'a str) -> Arc<MorpheusServer> {
+    start_server_with_options(port, group, MorpheusServerOptions::default())
+}
+
+pub fn start_server_with_options<'a>(port: u32, group: &'a str, options: MorpheusServerOptions) -> Arc<MorpheusServer> {
     let replacement_address: String = format!("127.0.0.1:{}", port);
-    let mut neb_config: ServerOptions = config::neb::options_from_file("config/neb.yaml");
+    let mut neb_config: ServerOptions = config::morpheus::options_from_file("config/morpheus.yaml").unwrap().neb;
     neb_config.meta_members = vec![replacement_address.clone()];
     neb_config.address = replacement_address.clone();
     neb_config.group_name = format!("{}-{}", group, "test");
-    MorpheusServer::new(neb_config).wait().unwrap()
+    MorpheusServer::new_with_options(neb_config, options).wait().unwrap()
 }
 
 #[test]
 pub fn server_startup() {
     start_server(4000, "bootstrap");
-}
\ No newline at end of file
+}
+
+#[test]
+pub fn embedded_startup() {
+    let server = MorpheusServer::embedded(EmbeddedOptions::default()).unwrap();
+    assert!(server.is_running());
+    server.shutdown();
+    assert!(!server.is_running());
+}
+
+#[test]
+pub fn named_graphs() {
+    let server = start_server_with_options(4003, "named_graphs", MorpheusServerOptions {
+        graphs: vec![GraphOptions {
+            name: "analytics".to_string(),
+            users: Some(vec!["alice".to_string()]),
+            quotas: None
+        }],
+        ..Default::default()
+    });
+    match server.open_graph("analytics", "bob").wait() {
+        Err(OpenGraphError::AccessDenied) => {},
+        other => panic!("{:?}", other.map(|_| ()))
+    }
+    match server.open_graph("missing", "alice").wait() {
+        Err(OpenGraphError::GraphNotFound) => {},
+        other => panic!("{:?}", other.map(|_| ()))
+    }
+    let analytics = server.open_graph("analytics", "alice").wait().unwrap();
+    assert_eq!(analytics.namespace(), Some(&"analytics".to_string()));
+    analytics.new_vertex_group(MorpheusSchema::new("people", None, &Vec::new(), true)).wait().unwrap();
+    assert!(server.schema_container.id_from_name("people").is_none());
+}
+
+#[test]
+pub fn access_control() {
+    let root_token = "root-token-for-tests";
+    let server = start_server_with_options(4004, "access_control", MorpheusServerOptions {
+        auth: AuthOptions { enabled: true, root_token: Some(root_token.to_string()) },
+        ..Default::default()
+    });
+    let auth = &server.auth;
+    let everything = Resource::default();
+    assert!(auth.check(root_token, Permission::SchemaAdmin, &everything).is_ok());
+    match auth.check("forged", Permission::Read, &everything) {
+        Err(AuthError::InvalidToken) => {},
+        other => panic!("{:?}", other)
+    }
+    auth.define_role(Role {
+        name: "analyst".to_string(),
+        grants: vec![Grant { permission: Permission::Write, scope: Scope::Namespace("analytics".to_string()) }]
+    }).unwrap();
+    let token = auth.create_user("alice".to_string(), vec!["analyst".to_string()]).unwrap();
+    let analytics = Resource { namespace: Some("analytics".to_string()), schema: None };
+    assert!(auth.check(&token, Permission::Read, &analytics).is_ok());
+    assert!(auth.check(&token, Permission::Write, &analytics).is_ok());
+    assert!(auth.check(&token, Permission::SchemaAdmin, &analytics).is_err());
+    assert!(auth.check(&token, Permission::Read, &everything).is_err());
+    // admin operations on a schema pass with a grant on it alone
+    let graph = &server.graph;
+    let person = graph.new_vertex_group(MorpheusSchema::new("person", None, &Vec::new(), true)).wait().unwrap();
+    graph.new_vertex_group(MorpheusSchema::new("robot", None, &Vec::new(), true)).wait().unwrap();
+    let knows = graph.new_edge_group(
+        MorpheusSchema::new("knows", None, &Vec::new(), false),
+        EdgeAttributes::new(EdgeType::Directed, false)
+    ).wait().unwrap();
+    auth.define_role(Role {
+        name: "person-editor".to_string(),
+        grants: vec![
+            Grant { permission: Permission::Write, scope: Scope::Schema(person) },
+            Grant { permission: Permission::Read, scope: Scope::Schema(knows) }
+        ]
+    }).unwrap();
+    let editor = auth.create_user("bob".to_string(), vec!["person-editor".to_string()]).unwrap();
+    let admin = AdminService::new(
+        "access_control-test", graph, &server.schema_container, &server.statistics, auth,
+        &server.rate_limiter, &None, &server.neb_client.raft_client(), &server.audit
+    );
+    graph.new_vertex("person", Map::new()).wait().unwrap();
+    assert_eq!(admin.remove_vertices(editor.clone(), "person".to_string(), None).wait().unwrap(), 1);
+    assert!(admin.remove_vertices(editor.clone(), "robot".to_string(), None).wait().is_err());
+    assert!(admin.schemas(editor.clone()).wait().is_err());
+    // traversals check the token of the user they run for on the edge schema
+    let vertex = graph.new_vertex("robot", Map::new()).wait().unwrap().cell.id();
+    assert!(server.router.neighbours(&editor, vertex, knows, EdgeDirection::Outbound, None).wait().unwrap().is_empty());
+    match server.router.neighbours(&token, vertex, knows, EdgeDirection::Outbound, None).wait() {
+        Err(RoutingError::AuthError(AuthError::PermissionDenied)) => {},
+        other => panic!("{:?}", other)
+    }
+    server.shutdown();
+}
+
+#[test]
+pub fn link_batching() {
+    let server = start_server_with_options(4005, "link_batching", MorpheusServerOptions {
+        link_batch: Some(LinkBatchOptions::default()),
+        ..Default::default()
+    });
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("node", None, &Vec::new(), true)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("follows", None, &Vec::new(), false),
+        EdgeAttributes::new(EdgeType::Directed, false)
+    ).wait().unwrap();
+    let hub = graph.new_vertex("node", Map::new()).wait().unwrap();
+    let followers: Vec<_> = (0..64).map(|_| graph.new_vertex("node", Map::new()).wait().unwrap()).collect();
+    let links: Vec<_> = followers.iter().map(|follower| graph.link(follower, "follows", &hub, None)).collect();
+    for result in future::join_all(links).wait().unwrap() {
+        result.unwrap();
+    }
+    assert_eq!(graph.degree(&hub, "follows", EdgeDirection::Inbound).wait().unwrap().unwrap(), 64);
+    server.shutdown();
+}
+
+#[test]
+pub fn neo4j_import() {
+    let server = start_server(4011, "neo4j_import");
+    let dump = env::temp_dir().join("morpheus-neo4j-import.csv");
+    fs::write(&dump, "\"_id\",\"_labels\",\"name\",\"age\",\"_start\",\"_end\",\"_type\",\"since\"\n\
+                      \"0\",\":Person\",\"Ada\",\"36\",,,,\n\
+                      \"1\",\":Person:Admin\",\"Grace\",\"85\",,,,\n\
+                      \"2\",\"\",\"orphan\",,,,,\n\
+                      ,,,,\"0\",\"1\",\"KNOWS\",\"1843\"\n\
+                      ,,,,\"0\",\"9\",\"KNOWS\",\n").unwrap();
+    let report = neo4j::import_csv(&server.graph, &[dump.to_str().unwrap()], &Neo4jCsvOptions::default()).unwrap();
+    assert_eq!(report.vertices, 3);
+    assert_eq!(report.edges, 1);
+    assert_eq!(report.schemas_created, vec!["Person".to_string(), "Node".to_string(), "KNOWS".to_string()]);
+    // the second relationship points to a node the dump does not have
+    assert_eq!(report.skipped.len(), 1);
+    assert_eq!(report.skipped[0].line, 6);
+    let graph = &server.graph;
+    assert_eq!(graph.count_vertices("Person", &None::<String>, CountMode::Exact).wait().unwrap().count, 2);
+    assert_eq!(graph.count_edges("KNOWS", CountMode::Exact).wait().unwrap().count, 1);
+}
+
+#[test]
+pub fn rdf_round_trip() {
+    let server = start_server(4012, "rdf_round_trip");
+    let turtle = "@prefix foaf: <http://xmlns.com/foaf/0.1/> .\n\
+                  @prefix ex: <http://example.org/> .\n\
+                  ex:ada a foaf:Person ; foaf:name \"Ada\" ; foaf:nick \"ada\", \"countess\" ;\n\
+                         foaf:knows ex:charles , [ foaf:name \"anonymous\" ] .\n\
+                  <http://example.org/charles> <http://xmlns.com/foaf/0.1/age> \"79\"^^<http://www.w3.org/2001/XMLSchema#integer> .\n";
+    let report = rdf::import_rdf(&server.graph, "people.ttl", turtle.as_bytes(), &RdfOptions::default()).unwrap();
+    // ada, foaf:Person, charles and the blank node
+    assert_eq!(report.vertices, 4);
+    assert_eq!(report.edges, 3);
+    assert!(report.skipped.is_empty());
+    let mut exported = Vec::new();
+    rdf::export_ntriples(&server.graph, &mut exported, &RdfOptions::default()).unwrap();
+    let exported = String::from_utf8(exported).unwrap();
+    assert!(exported.contains(
+        "<http://example.org/charles> <http://xmlns.com/foaf/0.1/age> \"79\"^^<http://www.w3.org/2001/XMLSchema#integer> ."
+    ));
+    assert!(exported.contains("<http://example.org/ada> <http://xmlns.com/foaf/0.1/knows> <http://example.org/charles> ."));
+    assert!(exported.contains("<http://example.org/ada> <http://xmlns.com/foaf/0.1/nick> \"countess\" ."));
+    match rdf::import_rdf(&server.graph, "broken.nt", "<http://example.org/a> <http://example.org/b> .\n".as_bytes(),
+                          &RdfOptions::default()) {
+        Err(ImportError::SyntaxError(source, line, _)) => { assert_eq!(source, "broken.nt"); assert_eq!(line, 1); },
+        other => panic!("{:?}", other)
+    }
+}
+
+#[test]
+pub fn stream_commands() {
+    let server = start_server(4015, "stream_commands");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("person", Some(&vec!["name".to_string()]), &vec![
+        Field::new("name", TypeId::String as u32, false, false, None),
+        Field::new("age", TypeId::U8 as u32, true, false, None)
+    ], false)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("knows", None, &Vec::new(), false),
+        EdgeAttributes::new(EdgeType::Directed, false)
+    ).wait().unwrap();
+    let messages: Vec<&[u8]> = vec![
+        br#"{"op": "insert_vertex", "schema": "person", "data": {"name": "ada", "age": 36}}"#,
+        br#"{"op": "insert_vertex", "schema": "person", "data": {"name": "grace"}}"#,
+        br#"{"op": "update_vertex", "schema": "person", "key": "grace", "data": {"age": 85}}"#,
+        br#"{"op": "link", "schema": "knows", "from": {"schema": "person", "key": "ada"},
+             "to": {"schema": "person", "key": "grace"}}"#
+    ];
+    let commands: Vec<_> = messages.iter().map(|m| stream::parse_command(graph, m).unwrap()).collect();
+    assert!(stream::apply_commands(graph, commands).wait().unwrap().is_empty());
+    assert!(stream::parse_command(graph, b"{\"op\": \"insert_vertex\", \"schema\": \"robot\"}").is_err());
+    assert!(stream::parse_command(graph, br#"{"op": "insert_vertex", "schema": "person", "data": {"age": 300}}"#).is_err());
+    let grace = graph.vertex_by_key("person", "grace").wait().unwrap().unwrap();
+    assert_eq!(grace["age"], Value::U8(85));
+    assert_eq!(graph.count_edges("knows", CountMode::Exact).wait().unwrap().count, 1);
+    let unlink = stream::parse_command(graph, br#"{"op": "unlink", "schema": "knows",
+        "from": {"schema": "person", "key": "ada"}, "to": {"schema": "person", "key": "grace"}}"#).unwrap();
+    // removing a vertex twice is rejected the second time, the rest of the batch goes on
+    let remove = stream::parse_command(graph, br#"{"op": "remove_vertex", "schema": "person", "key": "ada"}"#).unwrap();
+    let rejected = stream::apply_commands(graph, vec![unlink, remove.clone(), remove]).wait().unwrap();
+    assert_eq!(rejected.iter().map(|r| r.0).collect::<Vec<_>>(), vec![2]);
+    assert_eq!(graph.count_edges("knows", CountMode::Exact).wait().unwrap().count, 0);
+}
+
+#[test]
+pub fn graphql_queries() {
+    let server = start_server(4016, "graphql_queries");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("person", Some(&vec!["name".to_string()]), &vec![
+        Field::new("name", TypeId::String as u32, false, false, None),
+        Field::new("age", TypeId::U8 as u32, true, false, None)
+    ], false)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("knows", None, &Vec::new(), false),
+        EdgeAttributes::new(EdgeType::Directed, false)
+    ).wait().unwrap();
+    let messages: Vec<&[u8]> = vec![
+        br#"{"op": "insert_vertex", "schema": "person", "data": {"name": "ada", "age": 36}}"#,
+        br#"{"op": "insert_vertex", "schema": "person", "data": {"name": "grace", "age": 85}}"#,
+        br#"{"op": "insert_vertex", "schema": "person", "data": {"name": "alan", "age": 41}}"#,
+        br#"{"op": "link", "schema": "knows", "from": {"schema": "person", "key": "ada"},
+             "to": {"schema": "person", "key": "grace"}}"#,
+        br#"{"op": "link", "schema": "knows", "from": {"schema": "person", "key": "ada"},
+             "to": {"schema": "person", "key": "alan"}}"#
+    ];
+    let commands: Vec<_> = messages.iter().map(|m| stream::parse_command(graph, m).unwrap()).collect();
+    assert!(stream::apply_commands(graph, commands).wait().unwrap().is_empty());
+    let sdl = graphql::GraphqlSchema::from_graph(graph).sdl();
+    assert!(sdl.contains("type person implements Vertex"));
+    assert!(sdl.contains("knowsOut(first: Int, offset: Int): [Vertex!]!"));
+    let limits = graphql::Limits { max_page_size: 10, max_depth: 2 };
+    let query = r#"
+        query Friends($who: JSON) {
+            person(key: $who) {
+                name
+                years: age
+                knowsOut(first: 5) { ...names }
+                knowsIn @skip(if: true) { id }
+            }
+        }
+        fragment names on person { name }"#;
+    let mut variables = ::serde_json::Map::new();
+    variables.insert("who".to_string(), ::serde_json::Value::String("ada".to_string()));
+    let response = graphql::run_query(graph, query, variables, None, limits);
+    let ada = &response["data"]["person"];
+    assert_eq!(ada["name"], "ada");
+    assert_eq!(ada["years"], 36);
+    assert!(ada.get("knowsIn").is_none());
+    let mut friends: Vec<_> = ada["knowsOut"].as_array().unwrap().iter()
+        .map(|f| f["name"].as_str().unwrap().to_string()).collect();
+    friends.sort();
+    assert_eq!(friends, vec!["alan", "grace"]);
+    let page = graphql::run_query(graph, "{ personList(first: 2) { name } }", ::serde_json::Map::new(), None, limits);
+    assert_eq!(page["data"]["personList"].as_array().unwrap().len(), 2);
+    let too_deep = graphql::run_query(graph, r#"{ person(key: "ada") { knowsOut { ... on person {
+        knowsIn { ... on person { knowsOut { id } } } } } } }"#, ::serde_json::Map::new(), None, limits);
+    assert!(too_deep["errors"][0]["message"].as_str().unwrap().contains("deeper"));
+    let mutation = graphql::run_query(graph, "mutation { person(key: \"ada\") { id } }", ::serde_json::Map::new(), None, limits);
+    assert!(mutation.get("data").is_none());
+}
+
+#[test]
+pub fn rate_limiting() {
+    let server = start_server_with_options(4040, "rate_limiting", MorpheusServerOptions {
+        rate_limit: Some(RateLimitOptions::default()
+            .with_writes(RateLimit::new(0.001, 2))
+            .with_client("importer", None, Some(RateLimit::new(1000.0, 10)))),
+        ..Default::default()
+    });
+    let limiter = &server.rate_limiter;
+    assert!(limiter.acquire(RateClass::Write, "alice", 1).is_ok());
+    assert!(limiter.acquire(RateClass::Write, "alice", 1).is_ok());
+    let limited = limiter.acquire(RateClass::Write, "alice", 1).unwrap_err();
+    assert_eq!(limited.class, RateClass::Write);
+    assert!(limited.retry_after_ms > 0);
+    // a bucket per client, a request above the burst takes a full bucket
+    assert!(limiter.acquire(RateClass::Write, "bob", 100).is_ok());
+    assert!(limiter.acquire(RateClass::Write, "bob", 1).is_err());
+    // listed clients only have their own limits
+    for _ in 0..10 {
+        assert!(limiter.acquire(RateClass::Write, "importer", 1000).is_ok());
+    }
+    assert!(limiter.acquire(RateClass::Traversal, "alice", 1000).is_ok());
+    server.shutdown();
+}
+
+#[test]
+pub fn import_checkpoints() {
+    let server = start_server(4044, "import_checkpoints");
+    let dump = env::temp_dir().join("morpheus-import-checkpoints.csv");
+    let contents = "\"_id\",\"_labels\",\"name\",\"_start\",\"_end\",\"_type\"\n\
+                    \"0\",\":Person\",\"Ada\",,,\n\
+                    \"1\",\":Person\",\"Grace\",,,\n\
+                    \"2\",\":Person\",\"Alan\",,,\n\
+                    ,,,\"0\",\"1\",\"KNOWS\"\n\
+                    ,,,\"0\",\"9\",\"KNOWS\"\n";
+    fs::write(&dump, contents).unwrap();
+    let files = [dump.to_str().unwrap()];
+    let options = Neo4jCsvOptions { batch_size: 2, ..Neo4jCsvOptions::default() };
+    let checkpoints = &server.import_checkpoints;
+    let graph = &server.graph;
+    let report = neo4j::import_csv_checkpointed(graph, &files, &options, checkpoints, "people").unwrap();
+    assert!(report.resumed_from.is_none());
+    assert_eq!((report.vertices, report.edges, report.skipped.len()), (3, 1, 1));
+    assert!(checkpoints.get("people").unwrap().unwrap().finished);
+    // as if the import was cut off after the first two rows with the rest already written
+    let offset = contents.find("\"2\"").unwrap() as u64;
+    let cut = Checkpoint { file: files[0].to_string(), offset, line: 4, record: 3, batch: 1, ..Checkpoint::default() };
+    checkpoints.commit("people", &cut).unwrap();
+    let report = neo4j::import_csv_checkpointed(graph, &files, &options, checkpoints, "people").unwrap();
+    assert_eq!(report.resumed_from, Some(cut));
+    // the replayed rows are there already, the edge ends written before the cut are found
+    assert_eq!((report.vertices, report.edges), (0, 0));
+    assert_eq!(report.skipped.len(), 1);
+    assert_eq!(report.skipped[0].line, 6);
+    assert_eq!(graph.count_vertices("Person", &None::<String>, CountMode::Exact).wait().unwrap().count, 3);
+    assert_eq!(graph.count_edges("KNOWS", CountMode::Exact).wait().unwrap().count, 1);
+    // finished imports do nothing until reset
+    let report = neo4j::import_csv_checkpointed(graph, &files, &options, checkpoints, "people").unwrap();
+    assert_eq!((report.vertices, report.edges, report.skipped.len()), (0, 0, 0));
+    checkpoints.reset("people").unwrap();
+    let report = neo4j::import_csv_checkpointed(graph, &files, &options, checkpoints, "people").unwrap();
+    assert_eq!((report.vertices, report.edges), (0, 0));
+    assert_eq!(graph.count_vertices("Person", &None::<String>, CountMode::Exact).wait().unwrap().count, 3);
+    server.shutdown();
+}
+
+#[test]
+pub fn scrubbed_export() {
+    let server = start_server(4045, "scrubbed_export");
+    let turtle = "<http://example.org/ada> <urn:morpheus:name> \"Ada\" ; <urn:morpheus:born> \"1815\"^^<http://www.w3.org/2001/XMLSchema#integer> ;\n\
+                  <urn:morpheus:nick> \"ada\", \"countess\" ; <urn:morpheus:city> \"London\" .\n";
+    rdf::import_rdf(&server.graph, "people.ttl", turtle.as_bytes(), &RdfOptions::default()).unwrap();
+    let scrub = ScrubOptions { salt: "pepper".to_string(), ..ScrubOptions::default() }
+        .with_rule("Resource", "name", ScrubRule::Hash)
+        .with_rule("Resource", "born", ScrubRule::Generalize(10))
+        .with_rule("Resource", "nick", ScrubRule::Redact)
+        .with_rule("Resource", "city", ScrubRule::Generalize(3))
+        .with_rule("Resource", "iri", ScrubRule::Hash);
+    let options = RdfOptions { scrub: scrub.clone(), ..RdfOptions::default() };
+    let mut exported = Vec::new();
+    rdf::export_ntriples(&server.graph, &mut exported, &options).unwrap();
+    let exported = String::from_utf8(exported).unwrap();
+    let subject = format!("<urn:morpheus:{}>", scrub.digest("http://example.org/ada"));
+    assert!(exported.contains(&format!("{} <urn:morpheus:name> \"{}\" .", subject, scrub.digest("Ada"))));
+    assert!(exported.contains(&format!("{} <urn:morpheus:born> \"1810\"^^<http://www.w3.org/2001/XMLSchema#integer> .", subject)));
+    assert!(exported.contains(&format!("{} <urn:morpheus:city> \"Lon\" .", subject)));
+    assert!(!exported.contains("nick"));
+    assert!(!exported.contains("example.org"));
+    // the graph keeps its values
+    let mut plain = Vec::new();
+    rdf::export_ntriples(&server.graph, &mut plain, &RdfOptions::default()).unwrap();
+    assert!(String::from_utf8(plain).unwrap().contains("<http://example.org/ada> <urn:morpheus:name> \"Ada\" ."));
+    server.shutdown();
+}
+
+#[test]
+pub fn field_encryption() {
+    let keys = vec![("pii".to_string(), "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff".to_string())];
+    let server = start_server_with_options(4046, "field_encryption", MorpheusServerOptions {
+        encryption: Some(KeyProviderOptions::Static(keys.into_iter().collect())),
+        ..Default::default()
+    });
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("patient", None, &vec![
+        Field::new("name", TypeId::String as u32, false, false, None),
+        Field::new("ssn", TypeId::String as u32, true, false, None)
+    ], false).with_encrypted("ssn", "pii")).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("referral", None, &Vec::new(), true).with_encrypted("note", "pii"),
+        EdgeAttributes::new(EdgeType::Directed, false)
+    ).wait().unwrap();
+    let mut data = Map::new();
+    data.insert("name", Value::String("Ada".to_string()));
+    data.insert("ssn", Value::String("078-05-1120".to_string()));
+    let ada = graph.new_vertex("patient", data).wait().unwrap();
+    assert_eq!(ada["ssn"].String().unwrap(), "078-05-1120");
+    let id = ada.cell.id();
+    assert_eq!(graph.vertex_by(id).wait().unwrap().unwrap()["ssn"].String().unwrap(), "078-05-1120");
+    // neb holds the ciphertext, the other fields stay as they are
+    let stored = server.neb_client.read_cell(id).wait().unwrap().unwrap();
+    assert!(stored.data["ssn"].String().unwrap().starts_with("enc1:"));
+    assert_eq!(stored.data["name"].String().unwrap(), "Ada");
+    graph.update_vertex(id, |mut vertex| {
+        let ssn = format!("{}!", vertex["ssn"].String().unwrap());
+        vertex["ssn"] = Value::String(ssn);
+        Some(vertex)
+    }).wait().unwrap();
+    assert_eq!(graph.vertex_by(id).wait().unwrap().unwrap()["ssn"].String().unwrap(), "078-05-1120!");
+    let mut body = Map::new();
+    body.insert("note", Value::String("cardiology".to_string()));
+    graph.link(id, "referral", id, Some(body)).wait().unwrap().unwrap();
+    let edges = graph.edges(id, "referral", EdgeDirection::Outbound, &None::<String>).wait().unwrap().unwrap();
+    assert_eq!(edges[0].get_data().as_ref().unwrap().data["note"].String().unwrap(), "cardiology");
+    // sealed values are strings
+    match graph.new_vertex_group(MorpheusSchema::new("account", None, &vec![
+        Field::new("balance", TypeId::U64 as u32, false, false, None)
+    ], false).with_encrypted("balance", "pii")).wait() {
+        Err(SchemaError::InvalidEncryptedField(field)) => assert_eq!(field, "balance"),
+        other => panic!("{:?}", other)
+    }
+    server.shutdown();
+}
+
+#[test]
+pub fn audit_log() {
+    let root_token = "audit-root-token";
+    let server = start_server_with_options(4047, "audit_log", MorpheusServerOptions {
+        auth: AuthOptions { enabled: true, root_token: Some(root_token.to_string()) },
+        ..Default::default()
+    });
+    let admin = AdminService::new(
+        "audit_log-test", &server.graph, &server.schema_container, &server.statistics, &server.auth,
+        &server.rate_limiter, &None, &server.neb_client.raft_client(), &server.audit
+    );
+    let root = root_token.to_string();
+    let stranger = "not-a-token".to_string();
+    assert!(admin.new_schema(stranger.clone(), "person".to_string(), SchemaType::Vertex, None, Vec::new(), true).wait().is_err());
+    admin.new_schema(root.clone(), "person".to_string(), SchemaType::Vertex, None, Vec::new(), true).wait().unwrap();
+    for name in &["ada", "grace"] {
+        let mut data = Map::new();
+        data.insert("name", Value::String(name.to_string()));
+        server.graph.new_vertex("person", data).wait().unwrap();
+    }
+    assert_eq!(admin.remove_vertices(root.clone(), "person".to_string(), None).wait().unwrap(), 2);
+    match admin.remove_vertices(root.clone(), "robot".to_string(), None).wait() {
+        Err(AdminError::SchemaNotFound(schema)) => assert_eq!(schema, "robot"),
+        other => panic!("{:?}", other)
+    }
+    let entries = admin.audit_log(root.clone(), AuditQuery::default()).wait().unwrap();
+    let summary: Vec<_> = entries.iter().map(|e| (e.user.as_str(), e.action, e.target.as_str(), e.error.is_some())).collect();
+    assert_eq!(summary, vec![
+        ("anonymous", AuditAction::NewSchema, "person", true),
+        ("root", AuditAction::NewSchema, "person", false),
+        ("root", AuditAction::RemoveVertices, "person", false),
+        ("root", AuditAction::RemoveVertices, "robot", true)
+    ]);
+    // reading the log is not recorded itself
+    let query = AuditQuery { user: Some("root".to_string()), limit: Some(2), ..AuditQuery::default() };
+    let latest = admin.audit_log(root.clone(), query).wait().unwrap();
+    assert_eq!(latest, entries[2..].to_vec());
+    assert!(admin.audit_log(stranger, AuditQuery::default()).wait().is_err());
+    server.shutdown();
+}
+
+#[test]
+pub fn degree_report() {
+    let server = start_server(4048, "degree_report");
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("person", None, &Vec::new(), true)).wait().unwrap();
+    let follows = graph.new_edge_group(
+        MorpheusSchema::new("follows", None, &Vec::new(), true),
+        EdgeAttributes::new(EdgeType::Directed, true)
+    ).wait().unwrap();
+    let people: Vec<_> = (0..3).map(|_| graph.new_vertex("person", Map::new()).wait().unwrap().cell.id()).collect();
+    for &(from, to) in &[(0, 1), (0, 2), (1, 2)] {
+        let mut body = Map::new();
+        body.insert("since", Value::U32(2018));
+        graph.link(people[from], "follows", people[to], Some(body)).wait().unwrap().unwrap();
+    }
+    let reports = stats::degree_report(graph, &server.schema_container, 1).unwrap();
+    let report = reports.iter().find(|r| r.schema_id == follows).unwrap();
+    assert_eq!(report.edges, 3);
+    assert_eq!(report.out_degree.histogram, vec![1, 1, 1]);
+    assert_eq!((report.out_degree.vertices, report.out_degree.max), (3, 2));
+    assert_eq!(report.out_degree.mean, 1.0);
+    assert_eq!(report.out_degree.hubs, vec![(people[0], 2)]);
+    let in_degree = report.in_degree.as_ref().unwrap();
+    assert_eq!(in_degree.hubs, vec![(people[2], 2)]);
+    assert!(report.avg_body_bytes.unwrap() > 0.0);
+    server.shutdown();
+}
+
+#[test]
+pub fn replication_apply() {
+    let root_token = "replication-root-token";
+    let server = start_server_with_options(4052, "replication_apply", MorpheusServerOptions {
+        auth: AuthOptions { enabled: true, root_token: Some(root_token.to_string()) },
+        ..Default::default()
+    });
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("person", None, &Vec::new(), true)).wait().unwrap();
+    graph.new_edge_group(
+        MorpheusSchema::new("follows", None, &Vec::new(), false),
+        EdgeAttributes::new(EdgeType::Directed, false)
+    ).wait().unwrap();
+    let follows = graph.schema_by_name("follows").unwrap().0;
+    let a = graph.new_vertex("person", Map::new()).wait().unwrap().cell.id();
+    let b = graph.new_vertex("person", Map::new()).wait().unwrap().cell.id();
+    for _ in 0..2 {
+        graph.link(a, "follows", b, None).wait().unwrap().unwrap();
+    }
+    let service = ReplicationService::new(graph, &ReplicaOptions::default(), &server.auth, &server.rate_limiter);
+    let edge_event = |seq, kind| ReplicatedEvent {
+        schema: "follows".to_string(),
+        event: ChangeEvent {
+            seq, kind, schema: follows, id: Id::unit_id(), ends: Some((a, b)), data: None, time: 0, origin: None
+        }
+    };
+    let apply = |token: &str, events| service.apply(
+        token.to_string(), "origin-test".to_string(), ConflictPolicy::SourceWins, events
+    ).wait();
+    // batches need a token of the target
+    assert!(apply("forged", vec![edge_event(1, ChangeKind::Linked)]).is_err());
+    server.auth.define_role(Role {
+        name: "person-writer".to_string(),
+        grants: vec![Grant { permission: Permission::Write, scope: Scope::Schema(graph.schema_by_name("person").unwrap().0) }]
+    }).unwrap();
+    let token = server.auth.create_user("replicator".to_string(), vec!["person-writer".to_string()]).unwrap();
+    let report = apply(&token, vec![edge_event(2, ChangeKind::Linked)]).unwrap();
+    assert_eq!(report.applied, 0);
+    assert_eq!(report.rejected.iter().map(|&(seq, _)| seq).collect::<Vec<_>>(), vec![2]);
+    // the ends are linked twice on the target, the link is skipped and the unlink removes both
+    let report = apply(root_token, vec![edge_event(3, ChangeKind::Linked), edge_event(4, ChangeKind::Unlinked)]).unwrap();
+    assert_eq!(report.applied, 2);
+    assert!(report.rejected.is_empty());
+    assert_eq!(report.collapsed, vec![3, 4]);
+    assert_eq!(graph.degree(a, "follows", EdgeDirection::Outbound).wait().unwrap().unwrap(), 0);
+    server.shutdown();
+}
+
+#[test]
+pub fn edge_options_reload() {
+    let server = start_server(4055, "edge_options_reload");
+    let graph = &server.graph;
+    let strict = EdgeAttributes::new(EdgeType::Undirected, false)
+        .with_unique_pairs(true)
+        .with_self_loops(false)
+        .with_self_loops_count_twice(false);
+    let plain = EdgeAttributes::new(EdgeType::Directed, true);
+    let strict_id = graph.new_edge_group(MorpheusSchema::new("strict", None, &Vec::new(), false), strict).wait().unwrap();
+    let plain_id = graph.new_edge_group(MorpheusSchema::new("plain", None, &Vec::new(), false), plain).wait().unwrap();
+    let user_id = graph.new_vertex_group(
+        MorpheusSchema::new("user", None, &vec![
+            Field::new("name", TypeId::String as u32, true, false, None)
+        ], false)
+            .with_id_strategy(IdStrategy::Sequential)
+            .with_default("name", Value::String("anonymous".to_string()))
+    ).wait().unwrap();
+    // a fresh client rebuilds the edge attributes and the rest of the metadata from the schema records
+    let reloaded = SchemaContainer::new_client(
+        "edge_options_reload-test", &server.neb_client.raft_client(), &server.neb_client, &server.neb_server.meta
+    ).unwrap();
+    assert_eq!(reloaded.schema_type(strict_id), Some(SchemaType::Edge(strict)));
+    assert_eq!(reloaded.schema_type(plain_id), Some(SchemaType::Edge(plain)));
+    assert_eq!(reloaded.schema_type(user_id), Some(SchemaType::Vertex));
+    assert_eq!(reloaded.id_strategy(user_id), IdStrategy::Sequential);
+    assert_eq!(reloaded.defaults(user_id), vec![("name".to_string(), Value::String("anonymous".to_string()))]);
+    assert!(reloaded.window_counters(strict_id).is_empty());
+    server.shutdown();
+}
+
+#[test]
+pub fn admin_jobs() {
+    use graph::vector::{self, HnswOptions, VectorMetric};
+    let server = start_server(4063, "admin_jobs");
+    let graph = &server.graph;
+    let admin = AdminService::new(
+        "admin_jobs-test", &server.graph, &server.schema_container, &server.statistics, &server.auth,
+        &server.rate_limiter, &None, &server.neb_client.raft_client(), &server.audit
+    );
+    let token = String::new();
+    graph.new_vertex_group(MorpheusSchema::new("item", None, &vec![
+        vector::vector_field("embedding", false)
+    ], false)).wait().unwrap();
+    let new_item = |embedding: &[f32]| {
+        let mut item = Map::new();
+        item.insert("embedding", vector::vector_to_value(embedding));
+        graph.new_vertex("item", item).wait().unwrap();
+    };
+    new_item(&[1.0, 0.0]);
+    new_item(&[0.0, 1.0]);
+    let options = HnswOptions { metric: VectorMetric::Euclidean, ..HnswOptions::default() };
+    assert_eq!(graph.build_vector_index("item", "embedding", options).wait().unwrap(), 2);
+    new_item(&[0.5, 0.5]);
+    assert_eq!(admin.rebuild_index(token.clone(), "item".to_string(), "embedding".to_string()).wait().unwrap(), 3);
+    match admin.rebuild_index(token.clone(), "item".to_string(), "name".to_string()).wait() {
+        Err(AdminError::IndexNotFound(index)) => assert_eq!(index, "item.name"),
+        other => panic!("{:?}", other)
+    }
+    let path = env::temp_dir().join("morpheus-admin-snapshot.nt");
+    let triples = admin.snapshot(token.clone(), path.to_str().unwrap().to_string()).wait().unwrap();
+    assert!(triples > 0);
+    assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), triples);
+    assert!(admin.collect_statistics(token.clone()).wait().unwrap() >= 1);
+    let actions: Vec<_> = admin.audit_log(token.clone(), AuditQuery::default()).wait().unwrap()
+        .into_iter().map(|e| (e.action, e.error.is_some())).collect();
+    assert_eq!(actions, vec![
+        (AuditAction::RebuildIndex, false),
+        (AuditAction::RebuildIndex, true),
+        (AuditAction::Snapshot, false),
+        (AuditAction::CollectStatistics, false)
+    ]);
+    server.shutdown();
+}
+
+#[test]
+pub fn raft_membership() {
+    let server = start_server(4088, "raft_membership");
+    let token = String::new();
+    let new_admin = |raft_service| AdminService::new(
+        "raft_membership-test", &server.graph, &server.schema_container, &server.statistics, &server.auth,
+        &server.rate_limiter, raft_service, &server.neb_client.raft_client(), &server.audit
+    );
+    let admin = new_admin(&server.neb_server.raft_service);
+    let health = admin.group_health().wait().unwrap();
+    assert!(health.is_leader);
+    assert_eq!(health.members, 1);
+    assert_eq!(health.leader_id, health.server_id);
+    // members are removed by address, never the last one
+    match admin.remove_member(token.clone(), "127.0.0.1:4999".to_string()).wait() {
+        Err(AdminError::NotMember(address)) => assert_eq!(address, "127.0.0.1:4999"),
+        other => panic!("{:?}", other)
+    }
+    match admin.remove_member(token.clone(), "127.0.0.1:4088".to_string()).wait() {
+        Err(AdminError::Internal(_)) => {},
+        other => panic!("{:?}", other)
+    }
+    assert_eq!(admin.group_health().wait().unwrap().members, 1);
+    let not_meta = new_admin(&None);
+    match not_meta.leave_group(token.clone()).wait() {
+        Err(AdminError::NotMetaServer) => {},
+        other => panic!("{:?}", other)
+    }
+    match not_meta.group_health().wait() {
+        Err(AdminError::NotMetaServer) => {},
+        other => panic!("{:?}", other)
+    }
+    let actions: Vec<_> = admin.audit_log(token.clone(), AuditQuery::default()).wait().unwrap()
+        .into_iter().map(|e| (e.action, e.error.is_some())).collect();
+    assert_eq!(actions, vec![
+        (AuditAction::RemoveMember, true),
+        (AuditAction::RemoveMember, true),
+        (AuditAction::LeaveGroup, true)
+    ]);
+    server.shutdown();
+}
+
+#[test]
+pub fn shutdown_releases_ports() {
+    use std::net::{TcpListener, TcpStream};
+    let (graphql_port, metrics_port) = (5064, 5065);
+    let server = start_server_with_options(4064, "shutdown_releases_ports", MorpheusServerOptions {
+        graphql: Some(graphql::GraphqlOptions { port: graphql_port, max_page_size: 100, max_depth: 4 }),
+        metrics_port: Some(metrics_port),
+        ..Default::default()
+    });
+    assert!(TcpStream::connect(("127.0.0.1", metrics_port)).is_ok());
+    assert!(TcpListener::bind(("0.0.0.0", graphql_port)).is_err());
+    server.shutdown();
+    assert!(!server.is_running());
+    TcpListener::bind(("0.0.0.0", graphql_port)).unwrap();
+    TcpListener::bind(("0.0.0.0", metrics_port)).unwrap();
+    // a second call finds nothing left to stop
+    server.shutdown();
+}
+
+#[test]
+pub fn statistics_collection() {
+    use std::thread;
+    use std::time::Duration;
+    let server = start_server_with_options(4089, "statistics_collection", MorpheusServerOptions {
+        statistics: stats::StatisticsOptions { interval_secs: 0 },
+        ..Default::default()
+    });
+    let graph = &server.graph;
+    let person = graph.new_vertex_group(MorpheusSchema::new("person", None, &Vec::new(), false)).wait().unwrap();
+    let follows = graph.new_edge_group(
+        MorpheusSchema::new("follows", None, &Vec::new(), false),
+        EdgeAttributes::new(EdgeType::Directed, false)
+    ).wait().unwrap();
+    let people: Vec<_> = (0..3).map(|_| graph.new_vertex("person", Map::new()).wait().unwrap().cell.id()).collect();
+    for &(from, to) in &[(0, 1), (0, 2)] {
+        graph.link(people[from], "follows", people[to], None).wait().unwrap().unwrap();
+    }
+    let collected = stats::collect(graph, &server.schema_container).unwrap();
+    for schema_stats in &collected {
+        server.statistics.save(schema_stats).unwrap();
+    }
+    // saved statistics come back through the state machine subscription
+    let mut attempts = 0;
+    while server.statistics.get(follows).is_none() && attempts < 50 {
+        thread::sleep(Duration::from_millis(100));
+        attempts += 1;
+    }
+    assert_eq!(server.statistics.get(person).unwrap().count, 3);
+    let follows_stats = server.statistics.get(follows).unwrap();
+    assert_eq!(follows_stats.count, 2);
+    assert_eq!(follows_stats.hot_vertices.first(), Some(&(people[0], 2)));
+    // and replace the approximate counts of the graph
+    graph.new_vertex("person", Map::new()).wait().unwrap();
+    let estimate = graph.count_vertices::<_, String>(person, &None, CountMode::Approximate).wait().unwrap();
+    assert_eq!((estimate.count, estimate.exact), (3, false));
+    server.shutdown();
+}
+
+#[test]
+pub fn metrics_endpoint() {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use server::metrics;
+    let metrics_port = 5094;
+    let server = start_server_with_options(4094, "metrics_endpoint", MorpheusServerOptions {
+        metrics_port: Some(metrics_port),
+        ..Default::default()
+    });
+    let request = |path: &str| {
+        let mut stream = TcpStream::connect(("127.0.0.1", metrics_port)).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+    let graph = &server.graph;
+    graph.new_vertex_group(MorpheusSchema::new("person", None, &Vec::new(), false)).wait().unwrap();
+    // counters are shared by every server of the process, so only growth is checked
+    let writes_before = metrics::VERTEX_WRITES.get();
+    graph.new_vertex("person", Map::new()).wait().unwrap();
+    assert!(metrics::VERTEX_WRITES.get() >= writes_before + 1.0);
+    let response = request("/metrics");
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.contains("morpheus_vertex_writes_total"));
+    assert!(request("/").starts_with("HTTP/1.1 404 Not Found"));
+    server.shutdown();
+}
+
+#[test]
+pub fn slow_query_log() {
+    use server::slow_log::{self, SlowQueryOptions};
+    use std::time::{Duration, Instant};
+    slow_log::configure(SlowQueryOptions { latency_ms: 50, result_size: 10 });
+    let filter = None;
+    // either threshold is enough
+    assert!(!slow_log::check("edges", Instant::now(), 9, &[1], &filter));
+    assert!(slow_log::check("edges", Instant::now(), 10, &[1], &filter));
+    let long_ago = Instant::now() - Duration::from_millis(60);
+    assert!(slow_log::check("neighbourhoods", long_ago, 0, &[1], &filter));
+    slow_log::configure(SlowQueryOptions::default());
+}
+
+#[test]
+pub fn transaction_watchdog() {
+    use server::watchdog::{self, WatchdogOptions};
+    use neb::client::transaction::TxnError;
+    let guard = watchdog::register();
+    let watch = guard.watch().clone();
+    let watched = vec![watch.clone()];
+    let vertex = Id::new(1, 1);
+    assert!(watch.touch("read_vertex", None, vertex).is_ok());
+    // under the limit nothing happens, past it the transaction is only logged unless abort is on
+    let options = |max_duration_ms: u64, abort: bool| WatchdogOptions { max_duration_ms, abort, ..WatchdogOptions::default() };
+    assert_eq!(watchdog::check_watches(&watched, &options(60000, true)), 0);
+    assert_eq!(watchdog::check_watches(&watched, &options(0, false)), 1);
+    assert!(watch.touch("update_vertex", Some(1), vertex).is_ok());
+    assert_eq!(watchdog::check_watches(&watched, &options(0, true)), 1);
+    assert!(watch.is_aborted());
+    match watch.touch("link", Some(2), vertex) {
+        Err(TxnError::Aborted(None)) => {},
+        other => panic!("{:?}", other)
+    }
+    drop(guard);
+}
@@ -1,2 +1,4 @@
 pub mod transaction;
-pub mod file;
\ No newline at end of file
+pub mod file;
+pub mod trace;
+pub mod timer;
@@ -0,0 +1,32 @@
+// Waits that do not hold the thread polling them, for backoffs and poll intervals inside futures.
+// One wheel serves the whole process from its own thread, it ticks every millisecond and takes
+// waits of up to a minute, longer ones are cut to that.
+
+use tokio_timer::{self, Timer};
+use futures::prelude::*;
+
+use std::cmp;
+use std::time::Duration;
+
+const TICK_MS: u64 = 1;
+const SLOTS: usize = 65536;
+
+lazy_static! {
+    static ref TIMER: Timer = tokio_timer::wheel()
+        .tick_duration(Duration::from_millis(TICK_MS))
+        .num_slots(SLOTS)
+        .thread_name("morpheus-timer")
+        .build();
+}
+
+pub fn max_delay() -> Duration {
+    Duration::from_millis(TICK_MS * (SLOTS as u64 - 1))
+}
+
+// resolves once the duration passed, a timer at capacity resolves at once
+pub fn delay(duration: Duration) -> impl Future<Item = (), Error = ()> {
+    TIMER.sleep(cmp::min(duration, max_delay())).then(|result| {
+        if let Err(e) = result { warn!("Timer cannot wait: {:?}", e); }
+        Ok(())
+    })
+}
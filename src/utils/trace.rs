@@ -0,0 +1,54 @@
+use std::fmt::{Debug, Display};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::time::Instant;
+
+// route this target in log4rs to collect spans separately from the application log
+pub static TRACE_TARGET: &'static str = "morpheus::trace";
+
+static NEXT_SPAN_ID: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// A timed unit of work, logged with its recorded fields when dropped
+pub struct Span {
+    id: usize,
+    name: &'static str,
+    started: Instant,
+    fields: Vec<(&'static str, String)>
+}
+
+impl Span {
+    pub fn enter(name: &'static str) -> Span {
+        let id = NEXT_SPAN_ID.fetch_add(1, Ordering::Relaxed);
+        trace!(target: TRACE_TARGET, "span={} id={} started", name, id);
+        Span {
+            id,
+            name,
+            started: Instant::now(),
+            fields: Vec::new()
+        }
+    }
+    pub fn id(&self) -> usize {
+        self.id
+    }
+    pub fn record<V>(&mut self, key: &'static str, value: V) where V: Display {
+        self.fields.push((key, format!("{}", value)));
+    }
+    pub fn fail<E>(&mut self, error: &E) where E: Debug {
+        self.fields.push(("error", format!("{:?}", error)));
+    }
+    // what the span logs when dropped
+    pub fn line(&self) -> String {
+        let elapsed = self.started.elapsed();
+        let elapsed_us = elapsed.as_secs() * 1_000_000 + (elapsed.subsec_nanos() / 1_000) as u64;
+        let fields: Vec<String> = self.fields.iter().map(|&(k, ref v)| format!("{}={}", k, v)).collect();
+        format!("span={} id={} elapsed_us={} {}", self.name, self.id, elapsed_us, fields.join(" "))
+    }
+}
+
+// every cell read and transaction ends a span, so they are logged at debug and stay off at info
+impl Drop for Span {
+    fn drop(&mut self) {
+        if log_enabled!(target: TRACE_TARGET, ::log::LogLevel::Debug) {
+            debug!(target: TRACE_TARGET, "{}", self.line());
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use morpheus::Error;
+use morpheus::error::{self, ErrorKind, ResultExt};
+use morpheus::graph::LinkVerticesError;
+use morpheus::graph::model::ModelError;
+
+fn link() -> Result<(), LinkVerticesError> {
+    Err(LinkVerticesError::SelfLoopNotAllowed)
+}
+
+fn read_model() -> error::Result<()> {
+    Err(ModelError::Missing("name".to_string())).schema("person")?;
+    Ok(())
+}
+
+fn both() -> error::Result<()> {
+    read_model().operation("read person")?;
+    link()?;
+    Ok(())
+}
+
+#[test]
+fn context() {
+    let e: Error = both().unwrap_err();
+    match e.kind {
+        ErrorKind::Model(ModelError::Missing(ref field)) => assert_eq!(field, "name"),
+        ref other => panic!("{:?}", other)
+    }
+    assert_eq!(e.operation, Some("read person"));
+    assert_eq!(e.schema, Some("person".to_string()));
+    // context set first stays
+    let e = e.with_schema("other");
+    assert_eq!(e.schema, Some("person".to_string()));
+    assert_eq!(e.to_string(), "read person: model error: Missing(\"name\"), schema person");
+}
+
+#[test]
+fn flatten() {
+    let linked: Result<Result<(), LinkVerticesError>, _> = Ok(link());
+    match error::flatten(linked) {
+        Err(Error { kind: ErrorKind::LinkVertices(LinkVerticesError::SelfLoopNotAllowed), .. }) => {},
+        other => panic!("{:?}", other)
+    }
+}
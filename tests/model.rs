@@ -0,0 +1,46 @@
+use morpheus::graph::model::{VertexModel, EdgeModel, ModelError, Map, Value};
+use morpheus::graph::edge::EdgeType;
+
+#[derive(MorpheusVertex, Debug, PartialEq)]
+#[morpheus(name = "person")]
+struct Person {
+    #[morpheus(key)]
+    name: String,
+    age: u8,
+    nickname: Option<String>,
+    tags: Vec<String>
+}
+
+#[derive(MorpheusEdge, Debug, PartialEq)]
+#[morpheus(directed)]
+struct Follows {
+    since: u32
+}
+
+#[test]
+fn vertex_models() {
+    let schema = Person::schema();
+    assert_eq!(schema.name, "person");
+    assert_eq!(schema.key_field, Some(vec!["name".to_string()]));
+    let fields = Person::fields();
+    assert_eq!(fields.iter().map(|f| f.name.clone()).collect::<Vec<_>>(), vec!["name", "age", "nickname", "tags"]);
+    assert!(fields[2].nullable && !fields[1].nullable);
+    assert!(fields[3].is_array);
+    let ada = Person { name: "Ada".to_string(), age: 36, nickname: None, tags: vec!["math".to_string()] };
+    let map = ada.to_map();
+    assert_eq!(Person::from_map(&map), Ok(ada));
+    let mut missing = Map::new();
+    missing.insert(&"name".to_string(), Value::String("Bob".to_string()));
+    assert_eq!(Person::from_map(&missing), Err(ModelError::Missing("age".to_string())));
+    missing.insert(&"age".to_string(), Value::String("old".to_string()));
+    assert_eq!(Person::from_map(&missing), Err(ModelError::WrongType("age".to_string())));
+}
+
+#[test]
+fn edge_models() {
+    assert_eq!(Follows::schema_name(), "Follows");
+    assert_eq!(Follows::edge_type(), EdgeType::Directed);
+    assert!(Follows::attributes().has_body);
+    let follows = Follows { since: 2018 };
+    assert_eq!(Follows::from_map(&follows.to_map()), Ok(follows));
+}
@@ -1 +1,7 @@
+extern crate morpheus;
+#[macro_use]
+extern crate morpheus_derive;
+
 mod server;
+mod model;
+mod error;